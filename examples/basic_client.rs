@@ -36,11 +36,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             software_version: "0.1.0".to_string(),
         },
         player_support: Some(PlayerSupport {
-            support_codecs: vec!["pcm".to_string()],
-            support_channels: vec![2], // Stereo
-            support_sample_rates: vec![48000, 96000, 192000],
-            support_bit_depth: vec![16, 24],
-            support_formats: vec![AudioFormatSpec {
+            supported_formats: vec![AudioFormatSpec {
                 codec: "pcm".to_string(),
                 channels: 2,
                 sample_rate: 48000,
@@ -48,8 +44,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             }],
             buffer_capacity: 100,
             supported_commands: vec!["play".to_string(), "pause".to_string()],
+            fec: None,
         }),
         metadata_support: None,
+        artwork_support: None,
+        source_format: None,
     };
 
     println!("Connecting to {}...", args.server);
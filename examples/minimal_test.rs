@@ -3,9 +3,7 @@
 
 use clap::Parser;
 use sendspin::protocol::client::ProtocolClient;
-use sendspin::protocol::messages::{
-    AudioFormatSpec, ClientHello, DeviceInfo, Message, PlayerSupport, PlayerUpdate,
-};
+use sendspin::protocol::messages::{AudioFormatSpec, ClientHello, DeviceInfo, PlayerSupport};
 
 /// Minimal Sendspin test client
 #[derive(Parser, Debug)]
@@ -33,11 +31,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             software_version: "0.1.0".to_string(),
         },
         player_support: Some(PlayerSupport {
-            support_codecs: vec!["pcm".to_string()],
-            support_channels: vec![2],
-            support_sample_rates: vec![48000],
-            support_bit_depth: vec![24],
-            support_formats: vec![AudioFormatSpec {
+            supported_formats: vec![AudioFormatSpec {
                 codec: "pcm".to_string(),
                 channels: 2,
                 sample_rate: 48000,
@@ -45,8 +39,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             }],
             buffer_capacity: 100,
             supported_commands: vec!["play".to_string()],
+            fec: None,
         }),
         metadata_support: None,
+        artwork_support: None,
+        source_format: None,
     };
 
     println!("Connecting to {}...", args.server);
@@ -56,14 +53,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Split client
     let (mut message_rx, mut audio_rx, _clock_sync, ws_tx) = client.split();
 
-    // Send player/update (handshake step 3)
-    let player_update = Message::PlayerUpdate(PlayerUpdate {
-        state: "idle".to_string(),
-        volume: 100,
-        muted: false,
-    });
-    ws_tx.send_message(player_update).await?;
-    println!("Sent player/update");
+    // Send client/state (handshake step 3)
+    ws_tx
+        .send_player_state("synchronized", Some(100), Some(false))
+        .await?;
+    println!("Sent client/state");
 
     println!("\nListening for ALL messages from server...\n");
 
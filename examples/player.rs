@@ -2,12 +2,13 @@
 // ABOUTME: Connects to server, receives audio, and plays it back
 
 use clap::Parser;
-use sendspin::audio::decode::{Decoder, PcmDecoder, PcmEndian};
-use sendspin::audio::{AudioBuffer, AudioFormat, AudioOutput, Codec, CpalOutput, Sample};
+use sendspin::audio::decode::{Concealer, Decoder, PcmDecoder, PcmEndian};
+use sendspin::audio::{AudioBuffer, AudioFormat, AudioOutput, Codec, CpalOutput};
 use sendspin::protocol::client::ProtocolClient;
 use sendspin::protocol::messages::{
-    AudioFormatSpec, ClientHello, ClientTime, DeviceInfo, Message, PlayerSupport, PlayerUpdate,
+    AudioFormatSpec, ClientHello, ClientTime, DeviceInfo, Message, PlayerSupport,
 };
+use sendspin::protocol::stats::DEFAULT_GAP_TOLERANCE_MICROS;
 use sendspin::scheduler::AudioScheduler;
 use std::sync::Arc;
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
@@ -59,11 +60,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             software_version: "0.1.0".to_string(),
         },
         player_support: Some(PlayerSupport {
-            support_codecs: vec!["pcm".to_string()],
-            support_channels: vec![2], // Stereo
-            support_sample_rates: vec![48000, 96000, 192000],
-            support_bit_depth: vec![16, 24],
-            support_formats: vec![AudioFormatSpec {
+            supported_formats: vec![AudioFormatSpec {
                 codec: "pcm".to_string(),
                 channels: 2,
                 sample_rate: 48000,
@@ -71,25 +68,27 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             }],
             buffer_capacity: 100,
             supported_commands: vec!["play".to_string(), "pause".to_string()],
+            fec: None,
         }),
         metadata_support: None,
+        artwork_support: None,
+        source_format: None,
     };
 
     println!("Connecting to {}...", args.server);
     let client = ProtocolClient::connect(&args.server, hello).await?;
     println!("Connected!");
 
+    let stats = client.stats();
+
     // Split client into separate receivers for concurrent processing
     let (mut message_rx, mut audio_rx, clock_sync, ws_tx) = client.split();
 
-    // Send initial player/update message (handshake step 3)
-    let player_update = Message::PlayerUpdate(PlayerUpdate {
-        state: "idle".to_string(),
-        volume: 100,
-        muted: false,
-    });
-    ws_tx.send_message(player_update).await?;
-    println!("Sent initial player/update");
+    // Send initial client/state (handshake step 3)
+    ws_tx
+        .send_player_state("synchronized", Some(100), Some(false))
+        .await?;
+    println!("Sent initial client/state");
 
     // Send immediate initial clock sync
     let client_transmitted = SystemTime::now()
@@ -102,11 +101,33 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     println!("Waiting for stream to start...");
 
-    // Spawn clock sync task that sends client/time every 5 seconds
+    // Create shared scheduler
+    let scheduler = Arc::new(AudioScheduler::new());
+    let scheduler_clone = Arc::clone(&scheduler);
+    let report_scheduler = Arc::clone(&scheduler);
+
+    // Spawn a task that reports the buffer level every second and sends
+    // client/time every 5 seconds
     tokio::spawn(async move {
-        let mut interval = interval(Duration::from_secs(5));
+        let mut interval = interval(Duration::from_secs(1));
+        let mut ticks = 0u64;
         loop {
             interval.tick().await;
+            ticks += 1;
+
+            let report = {
+                let mut stats = stats.lock();
+                stats.buffered_ms = Some(report_scheduler.buffered().as_millis() as u32);
+                stats.clone()
+            };
+            if let Err(e) = ws_tx.send_stats(&report).await {
+                eprintln!("Failed to send stats: {}", e);
+                break;
+            }
+
+            if !ticks.is_multiple_of(5) {
+                continue;
+            }
 
             // Get current Unix epoch microseconds
             let client_transmitted = SystemTime::now()
@@ -124,10 +145,6 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     });
 
-    // Create shared scheduler
-    let scheduler = Arc::new(AudioScheduler::new());
-    let scheduler_clone = Arc::clone(&scheduler);
-
     // Spawn playback thread (not tokio task, since CpalOutput is !Send)
     let playback_handle = std::thread::spawn(move || {
         let mut output: Option<CpalOutput> = None;
@@ -177,6 +194,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut playback_started = false; // Track if we've started playback
     let mut next_play_time: Option<Instant> = None; // Track when next chunk should play
     let mut first_chunk_logged = false; // Track if we've logged the first chunk
+    let mut concealer: Option<Concealer> = None; // Fills gaps left by lost chunks
+    let mut expected_next_ts: Option<i64> = None; // Server timestamp the next chunk should start at
 
     loop {
         // Process messages and audio chunks concurrently
@@ -219,6 +238,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                         playback_started = false;
                         next_play_time = None;
                         first_chunk_logged = false; // Reset for new stream
+                        concealer = Some(Concealer::new(
+                            stream_start.player.sample_rate,
+                            stream_start.player.channels,
+                        ));
+                        expected_next_ts = None;
                         println!("Waiting for first audio chunk to auto-detect endianness...");
                     }
                     Message::ServerTime(server_time) => {
@@ -298,14 +322,40 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     }
                 }
 
-                if let (Some(ref dec), Some(ref fmt)) = (&decoder, &audio_format) {
+                if let (Some(ref dec), Some(ref fmt), Some(plc)) =
+                    (&decoder, &audio_format, concealer.as_mut())
+                {
+                    // Conceal lost chunks instead of leaving silence in the gap
+                    if let Some(expected) = expected_next_ts {
+                        let gap = chunk.timestamp - expected;
+                        // Longer outages are a stream discontinuity, not packet loss
+                        if gap > DEFAULT_GAP_TOLERANCE_MICROS && gap < 1_000_000 {
+                            let sync = clock_sync.lock().await;
+                            if let Some(play_at) = sync.server_to_local_instant(expected) {
+                                let frames = (gap * fmt.sample_rate as i64 / 1_000_000) as usize;
+                                let samples = plc.conceal(frames);
+                                if log_lead {
+                                    println!("Concealed {}µs gap before ts={}", gap, chunk.timestamp);
+                                }
+                                scheduler.schedule(AudioBuffer {
+                                    timestamp: expected,
+                                    play_at,
+                                    samples,
+                                    format: fmt.clone(),
+                                });
+                            }
+                        }
+                    }
+
                     match dec.decode(&chunk.data) {
                         Ok(samples) => {
+                            plc.remember(&samples);
                             // Calculate chunk duration in microseconds
                             // samples.len() includes all channels
                             let frames = samples.len() / fmt.channels as usize;
                             let duration_micros = (frames as u64 * 1_000_000) / fmt.sample_rate as u64;
                             let duration = Duration::from_micros(duration_micros);
+                            expected_next_ts = Some(chunk.timestamp + duration_micros as i64);
 
                             // Try to use clock sync to determine play_at time
                             let sync = clock_sync.lock().await;
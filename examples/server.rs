@@ -67,7 +67,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     // Create and run server
     let server = SendspinServer::with_config(config).with_source(source);
 
-    tracing::info!("Server ready. Connect with a Sendspin client to ws://{}/sendspin", args.bind);
+    tracing::info!(
+        "Server ready. Connect with a Sendspin client to ws://{}/sendspin",
+        args.bind
+    );
 
     server.run().await
 }
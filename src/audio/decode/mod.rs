@@ -3,8 +3,11 @@
 
 /// PCM decoder implementation
 pub mod pcm;
+/// Packet-loss concealment for lost chunks
+pub mod plc;
 
 pub use pcm::{PcmDecoder, PcmEndian};
+pub use plc::Concealer;
 
 use crate::audio::Sample;
 use crate::error::Error;
@@ -43,38 +43,40 @@ impl Decoder for PcmDecoder {
             (16, PcmEndian::Little) => {
                 // Convert 16-bit little-endian PCM to Sample
                 let samples: Vec<Sample> = data
-                    .chunks_exact(2)
-                    .map(|c| {
-                        let i16_val = i16::from_le_bytes([c[0], c[1]]);
-                        Sample::from_i16(i16_val)
-                    })
+                    .as_chunks::<2>()
+                    .0
+                    .iter()
+                    .map(|c| Sample::from_i16(i16::from_le_bytes(*c)))
                     .collect();
                 Ok(Arc::from(samples.into_boxed_slice()))
             }
             (16, PcmEndian::Big) => {
                 // Convert 16-bit big-endian PCM to Sample
                 let samples: Vec<Sample> = data
-                    .chunks_exact(2)
-                    .map(|c| {
-                        let i16_val = i16::from_be_bytes([c[0], c[1]]);
-                        Sample::from_i16(i16_val)
-                    })
+                    .as_chunks::<2>()
+                    .0
+                    .iter()
+                    .map(|c| Sample::from_i16(i16::from_be_bytes(*c)))
                     .collect();
                 Ok(Arc::from(samples.into_boxed_slice()))
             }
             (24, PcmEndian::Little) => {
                 // Convert 24-bit little-endian PCM to Sample
                 let samples: Vec<Sample> = data
-                    .chunks_exact(3)
-                    .map(|c| Sample::from_i24_le([c[0], c[1], c[2]]))
+                    .as_chunks::<3>()
+                    .0
+                    .iter()
+                    .map(|c| Sample::from_i24_le(*c))
                     .collect();
                 Ok(Arc::from(samples.into_boxed_slice()))
             }
             (24, PcmEndian::Big) => {
                 // Convert 24-bit big-endian PCM to Sample
                 let samples: Vec<Sample> = data
-                    .chunks_exact(3)
-                    .map(|c| Sample::from_i24_be([c[0], c[1], c[2]]))
+                    .as_chunks::<3>()
+                    .0
+                    .iter()
+                    .map(|c| Sample::from_i24_be(*c))
                     .collect();
                 Ok(Arc::from(samples.into_boxed_slice()))
             }
@@ -0,0 +1,80 @@
+// ABOUTME: Packet-loss concealment for audio chunks lost in transit
+// ABOUTME: Repeats recent decoded audio with a fade-out, whatever the codec
+
+use crate::audio::Sample;
+use std::sync::Arc;
+
+/// Audio kept for waveform repetition (10ms)
+const HISTORY_MICROS: u64 = 10_000;
+
+/// Length of the fade from full level to silence across a loss (100ms)
+const FADE_MICROS: u64 = 100_000;
+
+/// Fills gaps left by lost chunks instead of playing hard silence
+///
+/// The last few milliseconds of decoded audio are repeated, fading to silence
+/// over 100ms so a long outage does not turn into a buzz. It works on decoded
+/// samples, so it covers every codec the same way.
+#[derive(Debug, Clone)]
+pub struct Concealer {
+    channels: usize,
+    history_frames: usize,
+    fade_frames: usize,
+    history: Vec<Sample>,
+    /// Frames concealed since the last real audio
+    concealed: usize,
+}
+
+impl Concealer {
+    /// Create a concealer for the stream's format
+    pub fn new(sample_rate: u32, channels: u8) -> Self {
+        let frames = |micros: u64| (sample_rate as u64 * micros / 1_000_000) as usize;
+        Self {
+            channels: channels.max(1) as usize,
+            history_frames: frames(HISTORY_MICROS).max(1),
+            fade_frames: frames(FADE_MICROS).max(1),
+            history: Vec::new(),
+            concealed: 0,
+        }
+    }
+
+    /// Record decoded audio that is about to be played
+    pub fn remember(&mut self, samples: &[Sample]) {
+        let keep = self.history_frames * self.channels;
+        if samples.len() >= keep {
+            self.history.clear();
+            self.history
+                .extend_from_slice(&samples[samples.len() - keep..]);
+        } else {
+            self.history.extend_from_slice(samples);
+            let excess = self.history.len().saturating_sub(keep);
+            self.history.drain(..excess);
+        }
+        self.concealed = 0;
+    }
+
+    /// Produce `frames` frames of audio to play in place of lost chunks
+    pub fn conceal(&mut self, frames: usize) -> Arc<[Sample]> {
+        let history_frames = self.history.len() / self.channels;
+        let mut out = Vec::with_capacity(frames * self.channels);
+        for i in 0..frames {
+            let faded = (self.concealed + i).min(self.fade_frames);
+            let gain = (self.fade_frames - faded) as f32 / self.fade_frames as f32;
+            for ch in 0..self.channels {
+                let sample = match history_frames {
+                    0 => Sample::ZERO,
+                    n => self.history[(i % n) * self.channels + ch],
+                };
+                out.push(Sample(sample.0 * gain));
+            }
+        }
+        self.concealed += frames;
+        Arc::from(out.into_boxed_slice())
+    }
+
+    /// Forget recent audio (after `stream/clear` or a new stream)
+    pub fn reset(&mut self) {
+        self.history.clear();
+        self.concealed = 0;
+    }
+}
@@ -0,0 +1,112 @@
+// ABOUTME: TPDF dither and noise shaping for quantizing float samples
+// ABOUTME: Keeps quiet passages free of quantization distortion at 16-bit and below
+
+use crate::audio::types::Sample;
+use serde::{Deserialize, Serialize};
+
+/// Bit depth from which samples are quantized without dither
+///
+/// Float samples decoded from 24-bit sources land exactly on its steps.
+const UNDITHERED_BITS: u8 = 24;
+
+/// Largest quantization error fed back into the noise shaper, in steps
+///
+/// Dither and rounding stay within 1.5 steps; only clipping goes further, and
+/// feeding that back would ring long after the peak.
+const MAX_SHAPED_ERROR: f32 = 2.0;
+
+/// How samples are dithered when quantized below 24 bits
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize, clap::ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum DitherMode {
+    /// Round to the nearest step
+    Off,
+    /// Triangular (TPDF) dither, trading distortion for a steady noise floor
+    #[default]
+    Tpdf,
+    /// TPDF dither with the noise pushed toward high frequencies
+    Shaped,
+}
+
+/// Quantizes interleaved float samples to a bit depth, with dither
+///
+/// Keeps a noise generator and, when shaping, each channel's recent
+/// quantization error, so one ditherer serves one stream.
+#[derive(Debug, Clone)]
+pub struct Ditherer {
+    mode: DitherMode,
+    bits: u8,
+    /// Quantization error of each channel's last two samples, newest first
+    errors: Vec<[f32; 2]>,
+    /// xorshift32 state
+    noise: u32,
+}
+
+impl Ditherer {
+    /// Create a ditherer quantizing `channels` interleaved channels to `bits` bits
+    pub fn new(mode: DitherMode, bits: u8, channels: u8) -> Self {
+        Self {
+            mode,
+            bits: bits.clamp(1, 32),
+            errors: vec![[0.0; 2]; channels.max(1) as usize],
+            noise: 0x9E37_79B9,
+        }
+    }
+
+    /// The dither applied
+    pub fn mode(&self) -> DitherMode {
+        self.mode
+    }
+
+    /// Bits per quantized sample
+    pub fn bits(&self) -> u8 {
+        self.bits
+    }
+
+    /// Whether samples are dithered at this bit depth
+    pub fn is_active(&self) -> bool {
+        self.mode != DitherMode::Off && self.bits < UNDITHERED_BITS
+    }
+
+    /// Quantize interleaved samples, clamping to the bit depth's range
+    pub fn quantize(&mut self, samples: &[Sample]) -> Vec<i32> {
+        if !self.is_active() {
+            return samples.iter().map(|s| s.quantize(self.bits)).collect();
+        }
+        let scale = (1u32 << (self.bits - 1)) as f32;
+        let channels = self.errors.len();
+        let mut output = Vec::with_capacity(samples.len());
+        for (i, sample) in samples.iter().enumerate() {
+            let mut value = sample.0 * scale;
+            let shaping = self.mode == DitherMode::Shaped;
+            if shaping {
+                // Second-order error feedback: the noise is filtered by (1 - z^-1)^2
+                let [last, before] = self.errors[i % channels];
+                value -= 2.0 * last - before;
+            }
+            let dithered = (value + self.tpdf()).round().clamp(-scale, scale - 1.0);
+            if shaping {
+                let error = (dithered - value).clamp(-MAX_SHAPED_ERROR, MAX_SHAPED_ERROR);
+                let errors = &mut self.errors[i % channels];
+                *errors = [error, errors[0]];
+            }
+            output.push(dithered as i32);
+        }
+        output
+    }
+
+    /// Triangular noise of up to one step either way
+    fn tpdf(&mut self) -> f32 {
+        self.uniform() + self.uniform()
+    }
+
+    /// Uniform noise in -0.5..0.5 steps
+    fn uniform(&mut self) -> f32 {
+        self.noise ^= self.noise << 13;
+        self.noise ^= self.noise >> 17;
+        self.noise ^= self.noise << 5;
+        (self.noise >> 8) as f32 / (1u32 << 24) as f32 - 0.5
+    }
+}
@@ -0,0 +1,374 @@
+// ABOUTME: Channel downmixing for multichannel sources
+// ABOUTME: Folds surround layouts to smaller layouts, stereo or mono with configurable levels
+
+use crate::audio::types::Sample;
+use serde::{Deserialize, Serialize};
+
+/// -3 dB as a linear gain, the usual level for folding center and surround channels
+pub const MINUS_3DB: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+/// Speaker position of one input channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Speaker {
+    /// Front left
+    FrontLeft,
+    /// Front right
+    FrontRight,
+    /// Front center
+    FrontCenter,
+    /// Low-frequency effects
+    Lfe,
+    /// Rear (back) left
+    RearLeft,
+    /// Rear (back) right
+    RearRight,
+    /// Rear (back) center
+    RearCenter,
+    /// Side left
+    SideLeft,
+    /// Side right
+    SideRight,
+    /// Any position without a stereo mapping (dropped)
+    Other,
+}
+
+impl Speaker {
+    /// Conventional WAV/FLAC channel order for a channel count
+    ///
+    /// Used when a source does not describe its layout.
+    pub fn default_layout(channels: usize) -> Vec<Speaker> {
+        use Speaker::*;
+        let layout: &[Speaker] = match channels {
+            1 => &[FrontCenter],
+            2 => &[FrontLeft, FrontRight],
+            3 => &[FrontLeft, FrontRight, FrontCenter],
+            4 => &[FrontLeft, FrontRight, RearLeft, RearRight],
+            5 => &[FrontLeft, FrontRight, FrontCenter, RearLeft, RearRight],
+            6 => &[FrontLeft, FrontRight, FrontCenter, Lfe, RearLeft, RearRight],
+            7 => &[
+                FrontLeft,
+                FrontRight,
+                FrontCenter,
+                Lfe,
+                RearCenter,
+                SideLeft,
+                SideRight,
+            ],
+            _ => &[
+                FrontLeft,
+                FrontRight,
+                FrontCenter,
+                Lfe,
+                RearLeft,
+                RearRight,
+                SideLeft,
+                SideRight,
+            ],
+        };
+        let mut layout = layout.to_vec();
+        layout.resize(channels, Other);
+        layout.truncate(channels);
+        layout
+    }
+}
+
+/// Most channels the pipeline carries (7.1)
+pub const MAX_CHANNELS: u8 = 8;
+
+/// Linear gains applied when folding channels down, and how far sources are folded
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DownmixLevels {
+    /// Gain of the center channel into both left and right
+    pub center: f32,
+    /// Gain of the LFE channel into both left and right (0 drops it)
+    pub lfe: f32,
+    /// Gain of rear and side channels into their own side
+    pub surround: f32,
+    /// Gain of each of left and right when summing stereo to mono
+    pub mono: f32,
+    /// Most channels a multichannel source keeps (2 folds everything to stereo)
+    ///
+    /// Larger layouts are folded down to this many channels; clients that
+    /// negotiate fewer get their own fold-down.
+    pub max_channels: u8,
+}
+
+impl Default for DownmixLevels {
+    /// ITU-R BS.775 style fold-down: center and surrounds at -3 dB, LFE dropped,
+    /// stereo summed to mono at -6 dB per side, and everything folded to stereo
+    fn default() -> Self {
+        Self {
+            center: MINUS_3DB,
+            lfe: 0.0,
+            surround: MINUS_3DB,
+            mono: 0.5,
+            max_channels: 2,
+        }
+    }
+}
+
+impl DownmixLevels {
+    /// Convert a level in dB to a linear gain
+    pub fn db_to_gain(db: f32) -> f32 {
+        10f32.powf(db / 20.0)
+    }
+
+    /// Channels a source with `channels` channels is played with
+    ///
+    /// Mono and stereo sources play as stereo; larger layouts keep up to
+    /// [`max_channels`](Self::max_channels).
+    pub fn source_channels(&self, channels: usize) -> usize {
+        if channels > 2 {
+            channels
+                .min(self.max_channels.min(MAX_CHANNELS) as usize)
+                .max(2)
+        } else {
+            2
+        }
+    }
+
+    /// Sum one stereo frame to mono
+    pub fn to_mono(&self, left: Sample, right: Sample) -> Sample {
+        Sample((left.0 + right.0) * self.mono)
+    }
+}
+
+/// Fold interleaved stereo to mono on both channels
+///
+/// Each frame becomes `(L + R) * -3dB` on left and right, so a single speaker
+/// fed from either channel plays the whole mix.
+pub fn fold_to_mono(samples: &[Sample]) -> Vec<Sample> {
+    let mut out = Vec::with_capacity(samples.len());
+    for [left, right] in samples.as_chunks::<2>().0 {
+        let mono = Sample((left.0 + right.0) * MINUS_3DB).clamp();
+        out.extend([mono, mono]);
+    }
+    out
+}
+
+/// What a client's left and right channels carry
+///
+/// Two single-speaker players in one room can split a stereo pair, one
+/// taking [`ChannelMap::Left`] and the other [`ChannelMap::Right`].
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize, clap::ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum ChannelMap {
+    /// Left and right as they are
+    #[default]
+    Stereo,
+    /// The [`fold_to_mono`] sum on both channels
+    Mono,
+    /// The left channel on both channels
+    Left,
+    /// The right channel on both channels
+    Right,
+    /// Left and right exchanged
+    Swap,
+}
+
+impl ChannelMap {
+    /// Name used in config files and the control API
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChannelMap::Stereo => "stereo",
+            ChannelMap::Mono => "mono",
+            ChannelMap::Left => "left",
+            ChannelMap::Right => "right",
+            ChannelMap::Swap => "swap",
+        }
+    }
+
+    /// Remap interleaved stereo
+    pub fn apply(&self, samples: &[Sample]) -> Vec<Sample> {
+        let frames = samples.as_chunks::<2>().0.iter();
+        match self {
+            ChannelMap::Stereo => samples.to_vec(),
+            ChannelMap::Mono => fold_to_mono(samples),
+            ChannelMap::Left => frames.flat_map(|&[left, _]| [left, left]).collect(),
+            ChannelMap::Right => frames.flat_map(|&[_, right]| [right, right]).collect(),
+            ChannelMap::Swap => frames.flat_map(|&[left, right]| [right, left]).collect(),
+        }
+    }
+}
+
+/// Per-channel gains from one input layout to an output layout
+#[derive(Debug, Clone, PartialEq)]
+pub struct Downmix {
+    /// Gain from each input channel to each output channel, input by input
+    gains: Vec<f32>,
+    inputs: usize,
+    outputs: usize,
+}
+
+impl Downmix {
+    /// Build the mix from one layout to another
+    ///
+    /// Speakers present in both layouts pass through, side and rear pairs
+    /// stand in for each other, a missing rear center is split across the
+    /// rear pair, and anything else is folded into the front pair using
+    /// `levels`. Mono plays on the front pair. The output layout should
+    /// have a front pair.
+    pub fn new(input: &[Speaker], output: &[Speaker], levels: DownmixLevels) -> Self {
+        use Speaker::*;
+        let position = |speaker| output.iter().position(|&s| s == speaker);
+        let pair = |left, right| position(left).zip(position(right));
+        let front = pair(FrontLeft, FrontRight);
+        let mut gains = vec![0.0; input.len() * output.len()];
+        for (channel, &speaker) in input.iter().enumerate() {
+            let row = &mut gains[channel * output.len()..][..output.len()];
+            if input.len() == 1 {
+                if !row.is_empty() {
+                    let (left, right) = front.unwrap_or((0, 0));
+                    row[left] = 1.0;
+                    row[right] = 1.0;
+                }
+                continue;
+            }
+            let moved = match speaker {
+                Other => continue,
+                SideLeft => position(SideLeft).or(position(RearLeft)),
+                SideRight => position(SideRight).or(position(RearRight)),
+                RearLeft => position(RearLeft).or(position(SideLeft)),
+                RearRight => position(RearRight).or(position(SideRight)),
+                speaker => position(speaker),
+            };
+            if let Some(to) = moved {
+                row[to] = 1.0;
+                continue;
+            }
+            if speaker == RearCenter {
+                if let Some((left, right)) = pair(RearLeft, RearRight).or(pair(SideLeft, SideRight))
+                {
+                    row[left] = MINUS_3DB;
+                    row[right] = MINUS_3DB;
+                    continue;
+                }
+            }
+            let (left_gain, right_gain) = match speaker {
+                FrontLeft => (1.0, 0.0),
+                FrontRight => (0.0, 1.0),
+                FrontCenter => (levels.center, levels.center),
+                Lfe => (levels.lfe, levels.lfe),
+                RearLeft | SideLeft => (levels.surround, 0.0),
+                RearRight | SideRight => (0.0, levels.surround),
+                RearCenter => (levels.surround * MINUS_3DB, levels.surround * MINUS_3DB),
+                Other => (0.0, 0.0),
+            };
+            if let Some((left, right)) = front {
+                row[left] += left_gain;
+                row[right] += right_gain;
+            }
+        }
+        Self {
+            gains,
+            inputs: input.len(),
+            outputs: output.len(),
+        }
+    }
+
+    /// Build the stereo fold-down for a layout
+    ///
+    /// Mono plays on both sides and stereo passes through unchanged; other
+    /// layouts mix each speaker into left and right using `levels`.
+    pub fn stereo(layout: &[Speaker], levels: DownmixLevels) -> Self {
+        Self::new(layout, &Speaker::default_layout(2), levels)
+    }
+
+    /// Build the mix a source with `layout` is played with
+    ///
+    /// The output is stereo, or the conventional layout of
+    /// [`DownmixLevels::source_channels`] channels for larger layouts.
+    pub fn for_source(layout: &[Speaker], levels: DownmixLevels) -> Self {
+        let channels = levels.source_channels(layout.len());
+        Self::new(layout, &Speaker::default_layout(channels), levels)
+    }
+
+    /// Build the mix between the conventional layouts of two channel counts
+    pub fn between(from: u8, to: u8, levels: DownmixLevels) -> Self {
+        Self::new(
+            &Speaker::default_layout(from as usize),
+            &Speaker::default_layout(to as usize),
+            levels,
+        )
+    }
+
+    /// Number of input channels per frame
+    pub fn channels(&self) -> usize {
+        self.inputs
+    }
+
+    /// Number of output channels per frame
+    pub fn output_channels(&self) -> usize {
+        self.outputs
+    }
+
+    /// Whether every channel passes through unchanged
+    fn is_passthrough(&self) -> bool {
+        self.inputs == self.outputs
+            && self.gains.iter().enumerate().all(|(i, &gain)| {
+                let expected = if i / self.outputs == i % self.outputs {
+                    1.0
+                } else {
+                    0.0
+                };
+                gain == expected
+            })
+    }
+
+    /// Mix one interleaved input frame onto `output`
+    fn mix_into(&self, frame: &[f32], output: &mut Vec<Sample>) {
+        for out in 0..self.outputs {
+            let sum = frame
+                .iter()
+                .zip(self.gains[out..].iter().step_by(self.outputs))
+                .fold(0.0f64, |sum, (&s, &gain)| sum + s as f64 * gain as f64);
+            output.push(Sample(sum as f32));
+        }
+    }
+
+    /// Fold one interleaved input frame to a stereo pair
+    ///
+    /// Gives the first two output channels. A mix past full scale is kept
+    /// for a later stage to clamp.
+    pub fn frame(&self, frame: &[f32]) -> (Sample, Sample) {
+        let mut mixed = Vec::with_capacity(self.outputs);
+        self.mix_into(frame, &mut mixed);
+        let channel = |i: usize| mixed.get(i).copied().unwrap_or(Sample::ZERO);
+        (channel(0), channel(1))
+    }
+
+    /// Fold whole interleaved frames from `input` onto `output`
+    ///
+    /// Returns the number of input samples consumed; a trailing partial frame
+    /// is left for the next call.
+    pub fn extend(&self, input: &[f32], max_frames: usize, output: &mut Vec<Sample>) -> usize {
+        let channels = self.channels().max(1);
+        let frames = (input.len() / channels).min(max_frames);
+        if self.is_passthrough() {
+            output.extend(input[..frames * channels].iter().map(|&s| Sample(s)));
+        } else {
+            for frame in input[..frames * channels].chunks_exact(channels) {
+                self.mix_into(frame, output);
+            }
+        }
+        frames * channels
+    }
+
+    /// Mix interleaved samples, dropping any trailing partial frame
+    pub fn apply(&self, samples: &[Sample]) -> Vec<Sample> {
+        let channels = self.channels().max(1);
+        if self.is_passthrough() {
+            return samples[..samples.len() / channels * channels].to_vec();
+        }
+        let mut output = Vec::with_capacity(samples.len() / channels * self.outputs);
+        let mut values = Vec::with_capacity(channels);
+        for frame in samples.chunks_exact(channels) {
+            values.clear();
+            values.extend(frame.iter().map(|s| s.0));
+            self.mix_into(&values, &mut output);
+        }
+        output
+    }
+}
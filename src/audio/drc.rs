@@ -0,0 +1,135 @@
+// ABOUTME: Night-mode dynamic range compression
+// ABOUTME: Stereo-linked compressor with optional bass attenuation for late-night listening
+
+use crate::audio::types::Sample;
+use serde::{Deserialize, Serialize};
+
+/// Settings for night-mode processing
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct NightMode {
+    /// Level above which gain is reduced (dBFS)
+    pub threshold_db: f32,
+    /// Input dB above the threshold per output dB
+    pub ratio: f32,
+    /// Gain applied after compression to restore quiet passages (dB)
+    pub makeup_db: f32,
+    /// Time to react to a louder signal (ms)
+    pub attack_ms: f32,
+    /// Time to recover once the signal gets quieter (ms)
+    pub release_ms: f32,
+    /// Attenuation of low frequencies, for LFE-heavy material (dB; None leaves bass alone)
+    pub bass_cut_db: Option<f32>,
+    /// Corner frequency of the bass attenuation (Hz)
+    pub bass_cutoff_hz: f32,
+}
+
+impl Default for NightMode {
+    fn default() -> Self {
+        Self {
+            threshold_db: -24.0,
+            ratio: 4.0,
+            makeup_db: 6.0,
+            attack_ms: 5.0,
+            release_ms: 200.0,
+            bass_cut_db: Some(-6.0),
+            bass_cutoff_hz: 120.0,
+        }
+    }
+}
+
+/// Smoothing coefficient for a one-pole filter with the given time constant
+fn time_coefficient(ms: f32, sample_rate: u32) -> f32 {
+    if ms <= 0.0 {
+        return 0.0;
+    }
+    (-1.0 / (ms / 1000.0 * sample_rate as f32)).exp()
+}
+
+/// Stateful night-mode processor for one interleaved stream
+///
+/// All channels share one gain so the image does not shift.
+#[derive(Debug, Clone)]
+pub struct Compressor {
+    profile: NightMode,
+    attack: f32,
+    release: f32,
+    bass_coefficient: f32,
+    bass_gain: f32,
+    makeup: f32,
+    /// Envelope of the input level (dBFS)
+    envelope_db: f32,
+    /// Low-pass state per channel, used to split off the bass
+    lows: Vec<f32>,
+}
+
+impl Compressor {
+    /// Create a processor for stereo audio at `sample_rate`
+    pub fn new(profile: NightMode, sample_rate: u32) -> Self {
+        let rc = 1.0 / (2.0 * std::f32::consts::PI * profile.bass_cutoff_hz.max(1.0));
+        let dt = 1.0 / sample_rate.max(1) as f32;
+        Self {
+            attack: time_coefficient(profile.attack_ms, sample_rate),
+            release: time_coefficient(profile.release_ms, sample_rate),
+            bass_coefficient: dt / (rc + dt),
+            bass_gain: profile.bass_cut_db.map_or(1.0, db_to_gain),
+            makeup: db_to_gain(profile.makeup_db),
+            profile,
+            envelope_db: -120.0,
+            lows: vec![0.0; 2],
+        }
+    }
+
+    /// Process `channels` channels instead of stereo
+    pub fn with_channels(mut self, channels: u8) -> Self {
+        self.lows = vec![0.0; channels.max(1) as usize];
+        self
+    }
+
+    /// The settings this processor was built with
+    pub fn profile(&self) -> NightMode {
+        self.profile
+    }
+
+    /// Process one chunk of interleaved samples
+    pub fn process(&mut self, samples: &[Sample]) -> Vec<Sample> {
+        let mut output = Vec::with_capacity(samples.len());
+        let mut values = vec![0.0f32; self.lows.len()];
+
+        for frame in samples.chunks(self.lows.len()) {
+            for (channel, sample) in frame.iter().enumerate() {
+                let x = sample.0;
+                let low = &mut self.lows[channel];
+                *low += self.bass_coefficient * (x - *low);
+                values[channel] = x - *low + *low * self.bass_gain;
+            }
+
+            let peak = values[..frame.len()]
+                .iter()
+                .fold(0.0f32, |m, v| m.max(v.abs()));
+            let level_db = 20.0 * peak.max(1e-6).log10();
+            let coefficient = if level_db > self.envelope_db {
+                self.attack
+            } else {
+                self.release
+            };
+            self.envelope_db = coefficient * self.envelope_db + (1.0 - coefficient) * level_db;
+
+            let over = self.envelope_db - self.profile.threshold_db;
+            let reduction_db = if over > 0.0 {
+                over - over / self.profile.ratio.max(1.0)
+            } else {
+                0.0
+            };
+            let gain = db_to_gain(-reduction_db) * self.makeup;
+
+            for value in &values[..frame.len()] {
+                output.push(Sample(value * gain).clamp());
+            }
+        }
+        output
+    }
+}
+
+fn db_to_gain(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
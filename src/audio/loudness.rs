@@ -0,0 +1,373 @@
+// ABOUTME: EBU R128 loudness measurement and normalization toward a target LUFS
+// ABOUTME: Keeps quiet and loud sources at a similar level without clipping boosted audio
+
+use crate::audio::downmix::Speaker;
+use crate::audio::replay_gain::REFERENCE_LUFS;
+use crate::audio::types::Sample;
+
+/// EBU R128 programme loudness target
+pub const DEFAULT_TARGET_LUFS: f64 = -23.0;
+
+/// Length of a gating block
+const BLOCK_MS: u64 = 400;
+
+/// Gating blocks start every 100ms, overlapping by 75%
+const HOP_MS: u64 = 100;
+
+/// Blocks quieter than this are never counted
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+
+/// Blocks this far below the ungated loudness are not counted
+const RELATIVE_GATE_LU: f64 = -10.0;
+
+/// Width of the loudness histogram's bins
+const BIN_LU: f64 = 0.1;
+
+/// Loudest block level the histogram tells apart
+const MAX_BLOCK_LUFS: f64 = 10.0;
+
+/// Seconds for the applied gain to cover most of a change
+const GAIN_SMOOTHING_SECS: f64 = 0.5;
+
+/// Loudness normalization settings
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LoudnessNormalization {
+    /// Integrated loudness sources are brought to (LUFS)
+    pub target_lufs: f64,
+    /// Most gain applied to a quiet source (dB)
+    pub max_gain_db: f64,
+}
+
+impl Default for LoudnessNormalization {
+    fn default() -> Self {
+        Self {
+            target_lufs: DEFAULT_TARGET_LUFS,
+            max_gain_db: 12.0,
+        }
+    }
+}
+
+impl LoudnessNormalization {
+    /// Check the settings are usable
+    pub fn validate(&self) -> Result<(), String> {
+        if !(-70.0..=0.0).contains(&self.target_lufs) {
+            return Err(format!(
+                "loudness target {} LUFS out of range (-70 to 0)",
+                self.target_lufs
+            ));
+        }
+        if !(0.0..=40.0).contains(&self.max_gain_db) {
+            return Err(format!(
+                "maximum loudness gain {} dB out of range (0-40)",
+                self.max_gain_db
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Biquad filter in direct form I
+#[derive(Debug, Clone, Copy)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    fn new(b: [f64; 3], a: [f64; 2]) -> Self {
+        Self {
+            b,
+            a,
+            x: [0.0; 2],
+            y: [0.0; 2],
+        }
+    }
+
+    fn process(&mut self, input: f64) -> f64 {
+        let output = self.b[0] * input + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+        self.x = [input, self.x[0]];
+        self.y = [output, self.y[0]];
+        output
+    }
+}
+
+/// The two-stage K-weighting filter of ITU-R BS.1770, designed for `sample_rate`
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let rate = sample_rate.max(1) as f64;
+
+    // High shelf modelling the head's acoustic effect
+    let (f0, gain_db, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (std::f64::consts::PI * f0 / rate).tan();
+    let vh = 10f64.powf(gain_db / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad::new(
+        [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    );
+
+    // High pass dropping what barely registers as loudness
+    let (f0, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (std::f64::consts::PI * f0 / rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad::new(
+        [1.0, -2.0, 1.0],
+        [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    );
+    [shelf, high_pass]
+}
+
+/// BS.1770 weight of each channel of a conventional layout
+///
+/// Surround channels count 1.41 times (+1.5dB) and the LFE channel not at all.
+fn channel_weights(channels: usize) -> Vec<f64> {
+    Speaker::default_layout(channels)
+        .into_iter()
+        .map(|speaker| match speaker {
+            Speaker::Lfe => 0.0,
+            Speaker::RearLeft
+            | Speaker::RearRight
+            | Speaker::RearCenter
+            | Speaker::SideLeft
+            | Speaker::SideRight => 1.41,
+            _ => 1.0,
+        })
+        .collect()
+}
+
+/// Loudness of a mean square summed over channels
+fn lufs(power: f64) -> f64 {
+    -0.691 + 10.0 * power.max(f64::MIN_POSITIVE).log10()
+}
+
+/// Integrated loudness meter for one interleaved stream (EBU R128 / BS.1770)
+///
+/// Gated blocks are kept in a histogram rather than one by one, so an
+/// endless radio stream measures in constant memory.
+#[derive(Debug, Clone)]
+pub struct LoudnessMeter {
+    sample_rate: u32,
+    channels: usize,
+    filters: Vec<[Biquad; 2]>,
+    weights: Vec<f64>,
+    /// Frames in one 100ms hop
+    hop_frames: usize,
+    /// Summed squared K-weighted samples of the last four hops, oldest first
+    hops: Vec<f64>,
+    /// Squares summed so far in the current hop
+    current: f64,
+    /// Frames counted so far in the current hop
+    current_frames: usize,
+    /// Blocks counted in each bin
+    counts: Vec<u64>,
+    /// Summed power of the blocks in each bin
+    powers: Vec<f64>,
+}
+
+impl LoudnessMeter {
+    /// Create a meter for audio at `sample_rate` with `channels` channels
+    pub fn new(sample_rate: u32, channels: u8) -> Self {
+        let channels = channels.max(1) as usize;
+        let bins = ((MAX_BLOCK_LUFS - ABSOLUTE_GATE_LUFS) / BIN_LU).ceil() as usize + 1;
+        Self {
+            sample_rate,
+            channels,
+            filters: vec![k_weighting(sample_rate); channels],
+            weights: channel_weights(channels),
+            hop_frames: (sample_rate as u64 * HOP_MS / 1000).max(1) as usize,
+            hops: Vec::new(),
+            current: 0.0,
+            current_frames: 0,
+            counts: vec![0; bins],
+            powers: vec![0.0; bins],
+        }
+    }
+
+    /// Forget everything measured
+    pub fn reset(&mut self) {
+        *self = Self::new(self.sample_rate, self.channels as u8);
+    }
+
+    /// Measure one chunk of interleaved samples
+    pub fn add(&mut self, samples: &[Sample]) {
+        for frame in samples.chunks_exact(self.channels) {
+            let channels = frame.iter().zip(&mut self.filters).zip(&self.weights);
+            for ((sample, [shelf, high_pass]), weight) in channels {
+                let weighted = high_pass.process(shelf.process(sample.0 as f64));
+                self.current += weighted * weighted * weight;
+            }
+            self.current_frames += 1;
+            if self.current_frames == self.hop_frames {
+                self.end_hop();
+            }
+        }
+    }
+
+    /// Close the current hop, and with it a 400ms block once four are in
+    fn end_hop(&mut self) {
+        let hops_per_block = (BLOCK_MS / HOP_MS) as usize;
+        self.hops.push(std::mem::take(&mut self.current));
+        self.current_frames = 0;
+        if self.hops.len() > hops_per_block {
+            self.hops.remove(0);
+        }
+        if self.hops.len() < hops_per_block {
+            return;
+        }
+        let power = self.hops.iter().sum::<f64>() / (self.hop_frames * hops_per_block) as f64;
+        let loudness = lufs(power);
+        if loudness < ABSOLUTE_GATE_LUFS {
+            return;
+        }
+        let bin = (((loudness - ABSOLUTE_GATE_LUFS) / BIN_LU) as usize).min(self.counts.len() - 1);
+        self.counts[bin] += 1;
+        self.powers[bin] += power;
+    }
+
+    /// Integrated loudness in LUFS, once a block above the absolute gate is in
+    pub fn integrated(&self) -> Option<f64> {
+        let (count, power) = self.gated_from(0);
+        if count == 0 {
+            return None;
+        }
+        let relative_gate = lufs(power / count as f64) + RELATIVE_GATE_LU;
+        let first = ((relative_gate - ABSOLUTE_GATE_LUFS) / BIN_LU).max(0.0) as usize;
+        let (count, power) = self.gated_from(first);
+        Some(lufs(power / count.max(1) as f64))
+    }
+
+    /// Blocks and their summed power from histogram bin `first` up
+    fn gated_from(&self, first: usize) -> (u64, f64) {
+        let first = first.min(self.counts.len());
+        let count = self.counts[first..].iter().sum();
+        let power = self.powers[first..].iter().sum();
+        (count, power)
+    }
+}
+
+/// Brings a stream's integrated loudness toward a target
+///
+/// The gain follows the loudness measured since the last [`reset`](Self::reset)
+/// and moves smoothly, so a new source settles within a second or two. It is
+/// never more than the settings' maximum, nor enough to push the loudest
+/// sample measured past full scale. Until the first 400ms of a source are
+/// measured, audio passes at unity gain. A source already leveled by its
+/// ReplayGain tags is not measured; it is moved from the ReplayGain reference
+/// to the target by a fixed gain instead.
+#[derive(Debug, Clone)]
+pub struct LoudnessNormalizer {
+    settings: LoudnessNormalization,
+    meter: LoudnessMeter,
+    sample_rate: u32,
+    channels: u8,
+    /// Gain applied at the end of the last chunk (linear)
+    gain: f64,
+    /// Loudest sample since the last reset, as a fraction of full scale
+    peak: f64,
+    /// Whether the source plays at the ReplayGain reference loudness
+    replay_gain: bool,
+}
+
+impl LoudnessNormalizer {
+    /// Create a normalizer for stereo audio at `sample_rate`
+    pub fn new(settings: LoudnessNormalization, sample_rate: u32) -> Self {
+        Self {
+            settings,
+            meter: LoudnessMeter::new(sample_rate, 2),
+            sample_rate,
+            channels: 2,
+            gain: 1.0,
+            peak: 0.0,
+            replay_gain: false,
+        }
+    }
+
+    /// The settings this normalizer was built with
+    pub fn settings(&self) -> LoudnessNormalization {
+        self.settings
+    }
+
+    /// Integrated loudness of the current source in LUFS, once measured
+    pub fn loudness(&self) -> Option<f64> {
+        self.meter.integrated()
+    }
+
+    /// Gain currently applied, in dB
+    pub fn gain_db(&self) -> f64 {
+        20.0 * self.gain.log10()
+    }
+
+    /// Start measuring a new source at `sample_rate` with `channels`
+    /// channels, back at unity gain
+    pub fn reset(&mut self, sample_rate: u32, channels: u8) {
+        self.meter = LoudnessMeter::new(sample_rate, channels);
+        self.sample_rate = sample_rate;
+        self.channels = channels.max(1);
+        self.gain = 1.0;
+        self.peak = 0.0;
+        self.replay_gain = false;
+    }
+
+    /// Say whether the source is leveled by its ReplayGain tags
+    ///
+    /// A change starts the source over, as after [`reset`](Self::reset).
+    pub fn set_replay_gain(&mut self, replay_gain: bool) {
+        if replay_gain != self.replay_gain {
+            self.reset(self.sample_rate, self.channels);
+            self.replay_gain = replay_gain;
+        }
+    }
+
+    /// Measure and normalize one chunk of interleaved samples
+    pub fn process(&mut self, samples: &[Sample]) -> Vec<Sample> {
+        if !self.replay_gain {
+            self.meter.add(samples);
+        }
+        let chunk_peak = samples.iter().fold(0.0f32, |peak, s| peak.max(s.0.abs()));
+        self.peak = self.peak.max(chunk_peak as f64);
+
+        let loudness = if self.replay_gain {
+            Some(REFERENCE_LUFS)
+        } else {
+            self.meter.integrated()
+        };
+        let target = match loudness {
+            Some(loudness) => {
+                let gain_db = (self.settings.target_lufs - loudness).min(self.settings.max_gain_db);
+                let gain = 10f64.powf(gain_db / 20.0);
+                if self.peak > 0.0 {
+                    gain.min(1.0 / self.peak)
+                } else {
+                    gain
+                }
+            }
+            None => 1.0,
+        };
+
+        // Ramp across the chunk toward the smoothed gain
+        let channels = self.channels as usize;
+        let frames = samples.len() / channels;
+        let chunk_secs = frames as f64 / self.sample_rate.max(1) as f64;
+        let step = 1.0 - (-chunk_secs / (GAIN_SMOOTHING_SECS / 3.0)).exp();
+        let start = self.gain;
+        let end = start + (target - start) * step;
+        self.gain = end;
+        samples
+            .iter()
+            .enumerate()
+            .map(|(i, sample)| {
+                let position = (i / channels) as f64 / frames.max(1) as f64;
+                let gain = start + (end - start) * position;
+                Sample((sample.0 as f64 * gain) as f32).clamp()
+            })
+            .collect()
+    }
+}
@@ -3,13 +3,34 @@
 
 /// Audio decoder implementations (PCM, Opus, FLAC)
 pub mod decode;
+/// Dither for quantizing to 16 bits and below
+pub mod dither;
+/// Multichannel to stereo and stereo to mono downmixing
+pub mod downmix;
+/// Night-mode dynamic range compression
+pub mod drc;
+/// EBU R128 loudness measurement and normalization
+pub mod loudness;
 /// Audio output trait and implementations
 pub mod output;
 /// Buffer pool for reusing audio sample buffers
 pub mod pool;
+/// ReplayGain and R128 gain tags
+pub mod replay_gain;
+/// Streaming sample-rate conversion
+pub mod resample;
+/// Mid/side stereo width control
+pub mod spatial;
 /// Core audio type definitions (Sample, Codec, AudioFormat, AudioBuffer)
 pub mod types;
 
+pub use dither::{DitherMode, Ditherer};
+pub use downmix::{ChannelMap, Downmix, DownmixLevels, Speaker};
+pub use drc::{Compressor, NightMode};
+pub use loudness::{LoudnessMeter, LoudnessNormalization, LoudnessNormalizer};
 pub use output::{AudioOutput, CpalOutput};
 pub use pool::BufferPool;
+pub use replay_gain::{GainTags, ReplayGain, ReplayGainMode};
+pub use resample::Resampler;
+pub use spatial::{StereoWidth, Widener};
 pub use types::{AudioBuffer, AudioFormat, Codec, Sample};
@@ -5,10 +5,80 @@ use crate::audio::output::AudioOutput;
 use crate::audio::{AudioFormat, Sample};
 use crate::error::Error;
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::{Device, Stream, StreamConfig};
+use cpal::{Device, SampleFormat, SizedSample, Stream, StreamConfig, SupportedStreamConfigRange};
 use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
 use std::sync::{Arc, Mutex};
 
+/// Device sample formats we can convert to, most preferred first
+const SAMPLE_FORMATS: [SampleFormat; 4] = [
+    SampleFormat::F32,
+    SampleFormat::I32,
+    SampleFormat::I16,
+    SampleFormat::U16,
+];
+
+/// Pick the device sample format to stream `format` in
+///
+/// Prefers the formats in [`SAMPLE_FORMATS`] order among the ranges that
+/// support the stream's channel count and sample rate, then any range we can
+/// convert to. Returns None if the device offers nothing we can convert to.
+pub fn negotiate_sample_format(
+    supported: &[SupportedStreamConfigRange],
+    format: &AudioFormat,
+) -> Option<SampleFormat> {
+    let rate = cpal::SampleRate(format.sample_rate);
+    let matches = |range: &&SupportedStreamConfigRange| {
+        range.channels() == format.channels as u16
+            && range.min_sample_rate() <= rate
+            && rate <= range.max_sample_rate()
+    };
+    let pick = |ranges: Vec<&SupportedStreamConfigRange>| {
+        SAMPLE_FORMATS
+            .into_iter()
+            .find(|f| ranges.iter().any(|r| r.sample_format() == *f))
+    };
+    pick(supported.iter().filter(matches).collect()).or_else(|| pick(supported.iter().collect()))
+}
+
+/// Device sample types the stream callback can write
+trait OutputSample: SizedSample + Send + 'static {
+    const SILENCE: Self;
+
+    fn convert(sample: Sample) -> Self;
+}
+
+impl OutputSample for f32 {
+    const SILENCE: Self = 0.0;
+
+    fn convert(sample: Sample) -> Self {
+        sample.to_f32()
+    }
+}
+
+impl OutputSample for i32 {
+    const SILENCE: Self = 0;
+
+    fn convert(sample: Sample) -> Self {
+        sample.to_i32()
+    }
+}
+
+impl OutputSample for i16 {
+    const SILENCE: Self = 0;
+
+    fn convert(sample: Sample) -> Self {
+        sample.to_i16()
+    }
+}
+
+impl OutputSample for u16 {
+    const SILENCE: Self = 32_768;
+
+    fn convert(sample: Sample) -> Self {
+        sample.to_u16()
+    }
+}
+
 /// cpal-based audio output
 pub struct CpalOutput {
     format: AudioFormat,
@@ -56,7 +126,40 @@ impl CpalOutput {
         let latency_micros = Arc::new(Mutex::new(0u64));
         let latency_clone = Arc::clone(&latency_micros);
 
-        let stream = Self::build_stream(&device, &config, sample_rx, latency_clone)?;
+        let supported: Vec<SupportedStreamConfigRange> = device
+            .supported_output_configs()
+            .map(|configs| configs.collect())
+            .unwrap_or_default();
+        let sample_format = negotiate_sample_format(&supported, &format)
+            .or_else(|| {
+                device
+                    .default_output_config()
+                    .ok()
+                    .map(|c| c.sample_format())
+            })
+            .unwrap_or(SampleFormat::F32);
+        log::debug!("Opening output stream as {:?}", sample_format);
+
+        let stream = match sample_format {
+            SampleFormat::I32 => {
+                Self::build_stream::<i32>(&device, &config, sample_rx, latency_clone)
+            }
+            SampleFormat::I16 => {
+                Self::build_stream::<i16>(&device, &config, sample_rx, latency_clone)
+            }
+            SampleFormat::U16 => {
+                Self::build_stream::<u16>(&device, &config, sample_rx, latency_clone)
+            }
+            SampleFormat::F32 => {
+                Self::build_stream::<f32>(&device, &config, sample_rx, latency_clone)
+            }
+            other => {
+                return Err(Error::Output(format!(
+                    "device sample format {:?} is not supported",
+                    other
+                )))
+            }
+        }?;
         stream.play().map_err(|e| Error::Output(e.to_string()))?;
 
         Ok(Self {
@@ -67,7 +170,7 @@ impl CpalOutput {
         })
     }
 
-    fn build_stream(
+    fn build_stream<T: OutputSample>(
         device: &Device,
         config: &StreamConfig,
         sample_rx: Receiver<Arc<[Sample]>>,
@@ -80,7 +183,7 @@ impl CpalOutput {
         let stream = device
             .build_output_stream(
                 config,
-                move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
                     for sample_out in data.iter_mut() {
                         // Get next sample from current buffer or receive new buffer
                         if current_buffer.is_none()
@@ -98,15 +201,13 @@ impl CpalOutput {
                         // Output sample or silence
                         if let Some(ref buf) = current_buffer {
                             if buffer_pos < buf.len() {
-                                let sample = buf[buffer_pos];
-                                // Convert 24-bit sample to f32 (-1.0 to 1.0)
-                                *sample_out = sample.0 as f32 / 8388607.0;
+                                *sample_out = T::convert(buf[buffer_pos]);
                                 buffer_pos += 1;
                             } else {
-                                *sample_out = 0.0; // Silence
+                                *sample_out = T::SILENCE;
                             }
                         } else {
-                            *sample_out = 0.0; // Silence
+                            *sample_out = T::SILENCE;
                         }
                     }
                 },
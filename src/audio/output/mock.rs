@@ -0,0 +1,130 @@
+// ABOUTME: Audio output that records what it is given instead of playing it
+// ABOUTME: Stamps each write with virtual device time from a Clock, for hardware-free tests
+
+use crate::audio::output::AudioOutput;
+use crate::audio::{AudioFormat, Sample};
+use crate::error::Error;
+use crate::sync::time::Clock;
+use parking_lot::Mutex;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// One write to a [`MockOutput`]
+#[derive(Debug, Clone)]
+pub struct RecordedWrite {
+    /// Clock reading when the samples were written
+    pub written_at: Instant,
+    /// When the device starts sounding the first sample
+    ///
+    /// A write plays after the output latency, or after the audio written
+    /// before it when that ends later.
+    pub starts_at: Instant,
+    /// The samples written, interleaved
+    pub samples: Arc<[Sample]>,
+}
+
+impl RecordedWrite {
+    /// When the device finishes sounding the write at `format`
+    pub fn ends_at(&self, format: &AudioFormat) -> Instant {
+        self.starts_at + samples_duration(self.samples.len(), format)
+    }
+}
+
+/// Playback time of `samples` interleaved samples at `format`
+fn samples_duration(samples: usize, format: &AudioFormat) -> Duration {
+    let per_sec = format.sample_rate as u64 * format.channels.max(1) as u64;
+    if per_sec == 0 {
+        return Duration::ZERO;
+    }
+    Duration::from_micros(samples as u64 * 1_000_000 / per_sec)
+}
+
+/// Shared view of the writes made to a [`MockOutput`]
+///
+/// Stays readable after the output itself has moved to a playback thread.
+#[derive(Debug, Clone, Default)]
+pub struct MockRecording {
+    writes: Arc<Mutex<Vec<RecordedWrite>>>,
+}
+
+impl MockRecording {
+    /// Every write so far, oldest first
+    pub fn writes(&self) -> Vec<RecordedWrite> {
+        self.writes.lock().clone()
+    }
+
+    /// Every sample written so far, in order
+    pub fn samples(&self) -> Vec<Sample> {
+        self.writes
+            .lock()
+            .iter()
+            .flat_map(|write| write.samples.iter().copied())
+            .collect()
+    }
+
+    /// Forget the writes so far
+    pub fn clear(&self) {
+        self.writes.lock().clear();
+    }
+}
+
+/// An [`AudioOutput`] that records writes against a clock
+///
+/// The device is modelled as playing each write back to back after a fixed
+/// latency, so tests driving an [`crate::scheduler::AudioScheduler`] with a
+/// [`crate::sync::ManualClock`] can check exactly when audio would be heard.
+#[derive(Debug)]
+pub struct MockOutput {
+    format: AudioFormat,
+    clock: Arc<dyn Clock>,
+    latency: Duration,
+    recording: MockRecording,
+}
+
+impl MockOutput {
+    /// Output for `format` reading time from `clock`, with no latency
+    pub fn new(format: AudioFormat, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            format,
+            clock,
+            latency: Duration::ZERO,
+            recording: MockRecording::default(),
+        }
+    }
+
+    /// Delay between a write and the device sounding it
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Handle for inspecting the writes
+    pub fn recording(&self) -> MockRecording {
+        self.recording.clone()
+    }
+}
+
+impl AudioOutput for MockOutput {
+    fn write(&mut self, samples: &Arc<[Sample]>) -> Result<(), Error> {
+        let written_at = self.clock.now();
+        let mut writes = self.recording.writes.lock();
+        let queued_until = writes.last().map(|last| last.ends_at(&self.format));
+        let starts_at = queued_until
+            .filter(|&end| end > written_at + self.latency)
+            .unwrap_or(written_at + self.latency);
+        writes.push(RecordedWrite {
+            written_at,
+            starts_at,
+            samples: Arc::clone(samples),
+        });
+        Ok(())
+    }
+
+    fn latency_micros(&self) -> u64 {
+        self.latency.as_micros() as u64
+    }
+
+    fn format(&self) -> &AudioFormat {
+        &self.format
+    }
+}
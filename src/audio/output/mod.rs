@@ -3,8 +3,11 @@
 
 /// cpal-based audio output implementation
 pub mod cpal_output;
+/// Recording output with virtual timing, for tests
+pub mod mock;
 
-pub use cpal_output::CpalOutput;
+pub use cpal_output::{negotiate_sample_format, CpalOutput};
+pub use mock::{MockOutput, MockRecording, RecordedWrite};
 
 use crate::audio::{AudioFormat, Sample};
 use crate::error::Error;
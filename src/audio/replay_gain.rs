@@ -0,0 +1,136 @@
+// ABOUTME: ReplayGain and R128 gain tags, and the gain they call for
+// ABOUTME: Lets tagged files play at a consistent level without measuring them first
+
+use serde::{Deserialize, Serialize};
+
+/// Loudness ReplayGain 2.0 gains bring a track to (LUFS)
+pub const REFERENCE_LUFS: f64 = -18.0;
+
+/// Loudness R128 gain tags bring a track to (LUFS)
+const R128_REFERENCE_LUFS: f64 = -23.0;
+
+/// Which of a file's gains to apply
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplayGainMode {
+    /// Each track at the reference loudness
+    #[default]
+    Track,
+    /// Each album at the reference loudness, keeping its tracks' relative levels
+    Album,
+}
+
+/// ReplayGain settings
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReplayGain {
+    /// Which gain to prefer; the other is used when it is missing
+    pub mode: ReplayGainMode,
+    /// Gain added to the tagged gain (dB)
+    pub preamp_db: f64,
+    /// Lower the gain where the tagged peak would otherwise clip
+    pub prevent_clipping: bool,
+}
+
+impl Default for ReplayGain {
+    fn default() -> Self {
+        Self {
+            mode: ReplayGainMode::Track,
+            preamp_db: 0.0,
+            prevent_clipping: true,
+        }
+    }
+}
+
+impl ReplayGain {
+    /// Check the settings are usable
+    pub fn validate(&self) -> Result<(), String> {
+        if !(-15.0..=15.0).contains(&self.preamp_db) {
+            return Err(format!(
+                "ReplayGain preamp {} dB out of range (-15 to 15)",
+                self.preamp_db
+            ));
+        }
+        Ok(())
+    }
+
+    /// Linear gain for a file with `tags`, or None when it has no gain tags
+    pub fn gain(&self, tags: &GainTags) -> Option<f64> {
+        let track = tags.track_gain_db.map(|gain| (gain, tags.track_peak));
+        let album = tags.album_gain_db.map(|gain| (gain, tags.album_peak));
+        let (gain_db, peak) = match self.mode {
+            ReplayGainMode::Track => track.or(album)?,
+            ReplayGainMode::Album => album.or(track)?,
+        };
+        let gain = 10f64.powf((gain_db + self.preamp_db) / 20.0);
+        match peak {
+            Some(peak) if self.prevent_clipping && peak > 0.0 => Some(gain.min(1.0 / peak)),
+            _ => Some(gain),
+        }
+    }
+}
+
+/// Gain and peak tags read from a file
+///
+/// Gains are relative to [`REFERENCE_LUFS`]; R128 tags are converted.
+/// Peaks are fractions of full scale.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct GainTags {
+    /// Gain bringing the track to the reference loudness (dB)
+    pub track_gain_db: Option<f64>,
+    /// Loudest sample of the track
+    pub track_peak: Option<f64>,
+    /// Gain bringing the album to the reference loudness (dB)
+    pub album_gain_db: Option<f64>,
+    /// Loudest sample of the album
+    pub album_peak: Option<f64>,
+}
+
+impl GainTags {
+    /// Whether any gain was found
+    pub fn is_empty(&self) -> bool {
+        self.track_gain_db.is_none() && self.album_gain_db.is_none()
+    }
+
+    /// Take in one tag, returning whether it was a gain or peak tag
+    ///
+    /// Keys match case-insensitively, after any `TXXX:` or iTunes `----:`
+    /// prefix. Unparseable values are ignored.
+    pub fn add(&mut self, key: &str, value: &str) -> bool {
+        let name = key.rsplit(':').next().unwrap_or(key).to_ascii_uppercase();
+        let (slot, value) = match name.as_str() {
+            "REPLAYGAIN_TRACK_GAIN" => (&mut self.track_gain_db, parse_db(value)),
+            "REPLAYGAIN_TRACK_PEAK" => (&mut self.track_peak, parse_number(value)),
+            "REPLAYGAIN_ALBUM_GAIN" => (&mut self.album_gain_db, parse_db(value)),
+            "REPLAYGAIN_ALBUM_PEAK" => (&mut self.album_peak, parse_number(value)),
+            "R128_TRACK_GAIN" => (&mut self.track_gain_db, parse_r128(value)),
+            "R128_ALBUM_GAIN" => (&mut self.album_gain_db, parse_r128(value)),
+            _ => return false,
+        };
+        if value.is_some() {
+            *slot = value;
+        }
+        true
+    }
+}
+
+/// A number, ignoring surrounding whitespace
+fn parse_number(value: &str) -> Option<f64> {
+    value.trim().parse().ok().filter(|n: &f64| n.is_finite())
+}
+
+/// A gain such as `-6.54 dB`
+fn parse_db(value: &str) -> Option<f64> {
+    let value = value.trim();
+    let number = value
+        .strip_suffix("dB")
+        .or_else(|| value.strip_suffix("db"))
+        .or_else(|| value.strip_suffix("DB"))
+        .unwrap_or(value);
+    parse_number(number)
+}
+
+/// An R128 gain in 1/256 dB toward -23 LUFS, as a ReplayGain gain
+fn parse_r128(value: &str) -> Option<f64> {
+    let steps: i16 = value.trim().parse().ok()?;
+    Some(steps as f64 / 256.0 + REFERENCE_LUFS - R128_REFERENCE_LUFS)
+}
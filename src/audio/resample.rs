@@ -0,0 +1,174 @@
+// ABOUTME: Streaming sample-rate conversion with a windowed-sinc filter
+// ABOUTME: Lets a source at one rate feed clients negotiated at another without a pitch change
+
+use crate::audio::types::Sample;
+
+/// Filter half-width in input frames when upsampling
+const HALF_TAPS: usize = 16;
+
+/// Fractional positions the filter is tabulated at (interpolated in between)
+const PHASES: usize = 256;
+
+/// Cutoff as a fraction of the lower of the two Nyquist frequencies
+const CUTOFF: f64 = 0.95;
+
+/// Stateful sample-rate converter for one interleaved stream
+///
+/// Chunks are converted back to back: input not yet needed for an output
+/// frame is kept for the next call, so output chunk lengths vary by a frame
+/// around the exact ratio but add up to it. An output chunk therefore does
+/// not start exactly where its input chunk did; output frame `n` always
+/// carries the audio of input time `n / to` seconds, and
+/// [`Resampler::output_offset_micros`] gives where the next output chunk
+/// starts relative to the next input chunk, for timestamping. Output trails
+/// input by up to [`Resampler::delay_frames`] input frames (a third of a
+/// millisecond at 48kHz) while the filter waits for the input it looks ahead to.
+#[derive(Debug, Clone)]
+pub struct Resampler {
+    from: u32,
+    to: u32,
+    channels: usize,
+    /// Half-width of the filter in input frames
+    half: usize,
+    /// `PHASES + 1` rows of `2 * half` filter taps
+    table: Vec<f32>,
+    /// Interleaved input frames still needed
+    pending: Vec<f32>,
+    /// Position of the next output frame in `pending`, in 1/`to` input frames
+    position: u64,
+}
+
+impl Resampler {
+    /// Create a converter from `from` Hz to `to` Hz for `channels` channels
+    pub fn new(from: u32, to: u32, channels: u8) -> Self {
+        let from = from.max(1);
+        let to = to.max(1);
+        let cutoff = CUTOFF * (to as f64 / from as f64).min(1.0);
+        // Keep the same number of zero crossings when the cutoff drops
+        let half = (HALF_TAPS as f64 * CUTOFF / cutoff).round() as usize;
+        let taps = 2 * half;
+        let mut table = Vec::with_capacity((PHASES + 1) * taps);
+        for phase in 0..=PHASES {
+            let fraction = phase as f64 / PHASES as f64;
+            let row: Vec<f64> = (0..taps)
+                .map(|tap| {
+                    let x = tap as f64 + 1.0 - half as f64 - fraction;
+                    sinc(cutoff * x) * blackman(x / half as f64)
+                })
+                .collect();
+            // Unity gain at DC whatever the phase
+            let sum: f64 = row.iter().sum();
+            table.extend(row.iter().map(|tap| (tap / sum) as f32));
+        }
+        let channels = channels.max(1) as usize;
+        let mut resampler = Self {
+            from,
+            to,
+            channels,
+            half,
+            table,
+            pending: Vec::new(),
+            position: 0,
+        };
+        resampler.reset();
+        resampler
+    }
+
+    /// Input sample rate in Hz
+    pub fn input_rate(&self) -> u32 {
+        self.from
+    }
+
+    /// Output sample rate in Hz
+    pub fn output_rate(&self) -> u32 {
+        self.to
+    }
+
+    /// Input frames the output lags behind the input
+    pub fn delay_frames(&self) -> usize {
+        self.half
+    }
+
+    /// Start of the next output chunk relative to the next input chunk, in microseconds
+    ///
+    /// Zero or negative: the output held back by the filter comes first. Add
+    /// it to the input chunk's timestamp before calling [`Resampler::process`]
+    /// to get the output chunk's.
+    pub fn output_offset_micros(&self) -> i64 {
+        let buffered = (self.pending.len() / self.channels) as i64 * self.to as i64;
+        let units = self.position as i64 - buffered;
+        units * 1_000_000 / (self.to as i64 * self.from as i64)
+    }
+
+    /// Forget buffered input, as before the first chunk
+    pub fn reset(&mut self) {
+        // Silence before the stream stands in for the filter's left half
+        self.pending = vec![0.0; (self.half - 1) * self.channels];
+        self.position = (self.half as u64 - 1) * self.to as u64;
+    }
+
+    /// Convert one chunk of interleaved samples
+    ///
+    /// Passes the samples through unchanged when both rates are the same.
+    pub fn process(&mut self, samples: &[Sample]) -> Vec<Sample> {
+        if self.from == self.to {
+            return samples.to_vec();
+        }
+        let channels = self.channels;
+        self.pending.extend(samples.iter().map(|s| s.0));
+        let frames = self.pending.len() / channels;
+        let taps = 2 * self.half;
+        let to = self.to as u64;
+
+        let expected = samples.len() as u64 * to / self.from as u64 + channels as u64;
+        let mut output = Vec::with_capacity(expected as usize);
+        loop {
+            let base = (self.position / to) as usize;
+            if base + self.half >= frames {
+                break;
+            }
+            // Blend the two tabulated phases around the exact position
+            let phase = (self.position % to) as f64 * PHASES as f64 / to as f64;
+            let row = phase as usize;
+            let blend = (phase - row as f64) as f32;
+            let low = &self.table[row * taps..(row + 1) * taps];
+            let high = &self.table[(row + 1) * taps..(row + 2) * taps];
+            let first = base + 1 - self.half;
+            for channel in 0..channels {
+                let mut sum = 0.0f32;
+                for tap in 0..taps {
+                    let weight = low[tap] + blend * (high[tap] - low[tap]);
+                    sum += self.pending[(first + tap) * channels + channel] * weight;
+                }
+                output.push(Sample(sum).clamp());
+            }
+            self.position += self.from as u64;
+        }
+
+        // Drop input no later output frame reaches back to
+        let keep_from = ((self.position / to) as usize + 1)
+            .saturating_sub(self.half)
+            .min(frames);
+        self.pending.drain(..keep_from * channels);
+        self.position -= keep_from as u64 * to;
+        output
+    }
+}
+
+/// Normalised sinc, sin(πx)/(πx)
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-9 {
+        return 1.0;
+    }
+    let pi_x = std::f64::consts::PI * x;
+    pi_x.sin() / pi_x
+}
+
+/// Blackman window over -1..1, zero outside
+fn blackman(x: f64) -> f64 {
+    if x.abs() >= 1.0 {
+        return 0.0;
+    }
+    let angle = std::f64::consts::PI * x;
+    0.42 + 0.5 * angle.cos() + 0.08 * (2.0 * angle).cos()
+}
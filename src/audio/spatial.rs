@@ -0,0 +1,131 @@
+// ABOUTME: Stereo width control using mid/side processing
+// ABOUTME: Widens or narrows the stereo image, optionally keeping the bass centered
+
+use crate::audio::types::Sample;
+use serde::{Deserialize, Serialize};
+
+/// Widest image allowed; beyond this the side signal swamps the mid
+pub const MAX_WIDTH: f32 = 2.0;
+
+/// Settings for stereo width processing
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct StereoWidth {
+    /// Side-signal gain: 0 is mono, 1 leaves the image unchanged, up to
+    /// [`MAX_WIDTH`] widens it
+    pub width: f32,
+    /// Frequency below which the side signal is removed, keeping bass in the
+    /// center when widening (Hz; None processes all frequencies alike)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bass_mono_hz: Option<f32>,
+}
+
+impl Default for StereoWidth {
+    fn default() -> Self {
+        Self {
+            width: 1.0,
+            bass_mono_hz: None,
+        }
+    }
+}
+
+impl StereoWidth {
+    /// Settings with the given width and no bass handling
+    pub fn new(width: f32) -> Self {
+        Self {
+            width,
+            ..Default::default()
+        }
+    }
+
+    /// Whether processing would leave the audio unchanged
+    pub fn is_bypass(&self) -> bool {
+        self.width == 1.0 && self.bass_mono_hz.is_none()
+    }
+
+    /// Check the settings are in range
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0.0..=MAX_WIDTH).contains(&self.width) {
+            return Err(format!(
+                "width {} out of range (0-{})",
+                self.width, MAX_WIDTH
+            ));
+        }
+        if let Some(hz) = self.bass_mono_hz {
+            if !(20.0..=500.0).contains(&hz) {
+                return Err(format!(
+                    "bass mono frequency {} Hz out of range (20-500)",
+                    hz
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Stateful stereo width processor for one interleaved stream
+///
+/// Only the front left and right channels of a surround stream are reshaped.
+#[derive(Debug, Clone)]
+pub struct Widener {
+    profile: StereoWidth,
+    channels: usize,
+    /// One-pole low-pass coefficient for the side signal's bass
+    bass_coefficient: Option<f32>,
+    /// Low-pass state of the side signal
+    side_low: f32,
+}
+
+impl Widener {
+    /// Create a processor for stereo audio at `sample_rate`
+    pub fn new(profile: StereoWidth, sample_rate: u32) -> Self {
+        let bass_coefficient = profile.bass_mono_hz.map(|hz| {
+            let rc = 1.0 / (2.0 * std::f32::consts::PI * hz.max(1.0));
+            let dt = 1.0 / sample_rate.max(1) as f32;
+            dt / (rc + dt)
+        });
+        Self {
+            profile,
+            channels: 2,
+            bass_coefficient,
+            side_low: 0.0,
+        }
+    }
+
+    /// The settings this processor was built with
+    pub fn profile(&self) -> StereoWidth {
+        self.profile
+    }
+
+    /// Process `channels` channels instead of stereo
+    pub fn with_channels(mut self, channels: u8) -> Self {
+        self.channels = channels.max(1) as usize;
+        self
+    }
+
+    /// Process one chunk of interleaved samples
+    pub fn process(&mut self, samples: &[Sample]) -> Vec<Sample> {
+        if self.profile.is_bypass() {
+            // Splitting into mid and side and back is not exact in float
+            return samples.to_vec();
+        }
+        let mut output = Vec::with_capacity(samples.len());
+        for frame in samples.chunks(self.channels) {
+            let [left, right, rest @ ..] = frame else {
+                output.extend_from_slice(frame);
+                continue;
+            };
+            let (left, right) = (left.0, right.0);
+            let mid = (left + right) / 2.0;
+            let mut side = (left - right) / 2.0;
+            if let Some(coefficient) = self.bass_coefficient {
+                self.side_low += coefficient * (side - self.side_low);
+                side -= self.side_low;
+            }
+            side *= self.profile.width;
+            output.push(Sample(mid + side).clamp());
+            output.push(Sample(mid - side).clamp());
+            output.extend_from_slice(rest);
+        }
+        output
+    }
+}
@@ -1,64 +1,96 @@
 // ABOUTME: Core audio type definitions
-// ABOUTME: Sample (24-bit), AudioFormat, AudioBuffer for zero-copy audio data
+// ABOUTME: Sample (32-bit float), AudioFormat, AudioBuffer for zero-copy audio data
 
 use std::sync::Arc;
 use std::time::Instant;
 
-/// 24-bit audio sample stored in i32
-/// Range: -8388608 to 8388607 (±2^23)
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+/// Audio sample as a 32-bit float, full scale at ±1.0
+///
+/// Gains, mixes and filters work on floats so they do not pile up rounding
+/// error; samples are quantized only when an encoder or output writes them at
+/// its bit depth. A value may pass full scale between stages until
+/// [`clamp`](Self::clamp)ed.
+#[derive(Copy, Clone, Debug, Default, PartialEq, PartialOrd)]
 #[repr(transparent)]
-pub struct Sample(pub i32);
+pub struct Sample(pub f32);
 
 impl Sample {
-    /// Maximum valid 24-bit sample value (2^23 - 1)
-    pub const MAX: Self = Self(8_388_607);
-    /// Minimum valid 24-bit sample value (-2^23)
-    pub const MIN: Self = Self(-8_388_608);
+    /// Positive full scale
+    pub const MAX: Self = Self(1.0);
+    /// Negative full scale
+    pub const MIN: Self = Self(-1.0);
     /// Zero sample value
-    pub const ZERO: Self = Self(0);
+    pub const ZERO: Self = Self(0.0);
 
-    /// Convert from 16-bit sample (shift left 8 bits)
+    /// Convert from a 16-bit sample
     #[inline]
     pub fn from_i16(s: i16) -> Self {
-        Self((s as i32) << 8)
+        Self(s as f32 / 32_768.0)
+    }
+
+    /// Convert from a 24-bit sample held in an i32
+    #[inline]
+    pub fn from_i24(s: i32) -> Self {
+        Self(s as f32 / 8_388_608.0)
+    }
+
+    /// Convert from a full-scale 32-bit sample
+    #[inline]
+    pub fn from_i32(s: i32) -> Self {
+        Self((s as f64 / 2_147_483_648.0) as f32)
     }
 
     /// Convert from 24-bit little-endian bytes
     #[inline]
     pub fn from_i24_le(bytes: [u8; 3]) -> Self {
-        // Build 24-bit signed integer in i32
-        let val = (bytes[0] as i32) | ((bytes[1] as i32) << 8) | ((bytes[2] as i32) << 16);
-        // Sign-extend from 24-bit to 32-bit
-        let extended = if val & 0x00800000 != 0 {
-            val | 0xFF000000u32 as i32 // Negative: fill upper 8 bits with 1
-        } else {
-            val // Positive: upper 8 bits already 0
-        };
-        Self(extended)
+        // Sign-extend by placing the bytes at the top of an i32
+        Self::from_i24(i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8)
     }
 
     /// Convert from 24-bit big-endian bytes
     #[inline]
     pub fn from_i24_be(bytes: [u8; 3]) -> Self {
-        // Build 24-bit signed integer in i32 (big-endian order)
-        let val = ((bytes[0] as i32) << 16) | ((bytes[1] as i32) << 8) | (bytes[2] as i32);
-        // Sign-extend from 24-bit to 32-bit
-        let extended = if val & 0x00800000 != 0 {
-            val | 0xFF000000u32 as i32 // Negative: fill upper 8 bits with 1
-        } else {
-            val // Positive: upper 8 bits already 0
-        };
-        Self(extended)
+        Self::from_i24(i32::from_be_bytes([bytes[0], bytes[1], bytes[2], 0]) >> 8)
     }
 
-    /// Convert to 16-bit sample (shift right 8 bits)
+    /// Quantize to a 16-bit sample, rounding and clamping
     #[inline]
     pub fn to_i16(self) -> i16 {
-        (self.0 >> 8) as i16
+        (self.0 * 32_768.0).round().clamp(-32_768.0, 32_767.0) as i16
+    }
+
+    /// Quantize to a 24-bit sample held in an i32, rounding and clamping
+    #[inline]
+    pub fn to_i24(self) -> i32 {
+        self.quantize(24)
+    }
+
+    /// Quantize to a `bits`-bit sample (1 to 32) held in an i32, rounding and clamping
+    #[inline]
+    pub fn quantize(self, bits: u8) -> i32 {
+        let scale = (1u64 << (bits.clamp(1, 32) - 1)) as f64;
+        (self.0 as f64 * scale).round().clamp(-scale, scale - 1.0) as i32
+    }
+
+    /// Convert to unsigned 16-bit (silence at 32768)
+    #[inline]
+    pub fn to_u16(self) -> u16 {
+        (self.to_i16() as i32 + 32_768) as u16
+    }
+
+    /// Quantize to a full-scale 32-bit sample, clamping
+    #[inline]
+    pub fn to_i32(self) -> i32 {
+        self.quantize(32)
+    }
+
+    /// Convert to floating point (-1.0 to 1.0)
+    #[inline]
+    pub fn to_f32(self) -> f32 {
+        self.0
     }
 
-    /// Clamp to valid 24-bit range
+    /// Clamp to full scale
     #[inline]
     pub fn clamp(self) -> Self {
         Self(self.0.clamp(Self::MIN.0, Self::MAX.0))
@@ -66,7 +98,7 @@ impl Sample {
 }
 
 /// Audio codec type
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Codec {
     /// Uncompressed PCM audio
     Pcm,
@@ -78,6 +110,37 @@ pub enum Codec {
     Mp3,
 }
 
+impl Codec {
+    /// Protocol name of the codec (as used in `stream/start`)
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Pcm => "pcm",
+            Self::Opus => "opus",
+            Self::Flac => "flac",
+            Self::Mp3 => "mp3",
+        }
+    }
+
+    /// Most channels the codec's stream can carry
+    pub fn max_channels(self) -> u8 {
+        match self {
+            Self::Pcm | Self::Flac => 8,
+            Self::Opus | Self::Mp3 => 2,
+        }
+    }
+
+    /// Look up a codec by its protocol name
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "pcm" => Some(Self::Pcm),
+            "opus" => Some(Self::Opus),
+            "flac" => Some(Self::Flac),
+            "mp3" => Some(Self::Mp3),
+            _ => None,
+        }
+    }
+}
+
 /// Audio format specification
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct AudioFormat {
@@ -0,0 +1,63 @@
+// ABOUTME: Sendspin protocol conformance test suite binary
+// ABOUTME: Tests a third-party server (as a client) or client (as a server) and prints a pass/fail report
+
+use clap::{Parser, Subcommand};
+use sendspin::conformance::{check_client, check_server};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+#[derive(Parser, Debug)]
+#[command(name = "sendspin-conformance")]
+#[command(author, version, about = "Sendspin protocol conformance test suite", long_about = None)]
+struct Args {
+    /// Seconds to wait for each response from the implementation under test
+    #[arg(long, default_value_t = 5)]
+    timeout: u64,
+
+    /// Print the report as JSON
+    #[arg(long)]
+    json: bool,
+
+    #[command(subcommand)]
+    mode: Mode,
+}
+
+#[derive(Subcommand, Debug)]
+enum Mode {
+    /// Connect to a server and check its behavior
+    Server {
+        /// WebSocket URL of the server (e.g., ws://localhost:8927/sendspin)
+        url: String,
+    },
+    /// Accept one client connection and check its behavior
+    Client {
+        /// Address to listen on for the client
+        #[arg(long, default_value = "0.0.0.0:8927")]
+        bind: SocketAddr,
+    },
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+    let timeout = Duration::from_secs(args.timeout);
+
+    let report = match args.mode {
+        Mode::Server { url } => check_server(&url, timeout).await,
+        Mode::Client { bind } => {
+            eprintln!("Waiting for a client on ws://{}/ ...", bind);
+            check_client(bind, timeout).await
+        }
+    };
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print!("{}", report.render());
+    }
+
+    if !report.is_success() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
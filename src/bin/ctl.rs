@@ -0,0 +1,1370 @@
+// ABOUTME: Command-line control client for a running Sendspin server
+// ABOUTME: Lists clients, groups, and encoder load, changes volume, groups, and sources, queries client diagnostics, and analyses chunk audit logs
+
+use clap::{Parser, Subcommand};
+use sendspin::audio::{ChannelMap, DitherMode};
+use sendspin::protocol::messages::ClientDiagnostics;
+use sendspin::server::{
+    BatchVolumeRequest, ChannelMapRequest, ClientInfo, ClientRtt, CodecInfo, CodecRequest,
+    EncoderSettings, EncoderSettingsInfo, EncoderStats, GroupStats, MonoRequest, MoveRequest,
+    NightModeRequest, NowPlayingInfo, PipelineGraph, PipelineStage, PlayRecord, PlayerVolume,
+    SourceRequest, StereoWidthRequest, StreamRequest, VolumeRequest,
+};
+use sendspin::sync::audit::{chunk_timelines, read_audit_log, ChunkTimeline, IntervalStats};
+use sendspin::sync::AuditStage;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::json;
+use std::fmt::{Display, Write};
+use std::path::PathBuf;
+
+/// Rows in the audit headroom plot
+const AUDIT_PLOT_ROWS: usize = 40;
+
+/// Width of the longest bar in the audit headroom plot
+const AUDIT_PLOT_WIDTH: usize = 50;
+
+#[derive(Parser, Debug)]
+#[command(name = "sendspin-ctl")]
+#[command(author, version, about = "Control a running Sendspin server", long_about = None)]
+struct Args {
+    /// Base URL of the server, including any reverse-proxy prefix
+    #[arg(short, long, default_value = "http://127.0.0.1:8927")]
+    server: String,
+
+    /// Control API key (defaults to $SENDSPIN_API_KEY)
+    #[arg(long)]
+    api_key: Option<String>,
+
+    /// Print JSON instead of tables
+    #[arg(long)]
+    json: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// List connected clients
+    Clients,
+    /// List groups with playback state and throughput
+    Groups,
+    /// Set a player's volume
+    Volume {
+        /// Client ID
+        client: String,
+        /// Volume in percent (0-100)
+        #[arg(value_parser = clap::value_parser!(u8).range(0..=100))]
+        percent: u8,
+    },
+    /// Set a group's volume, scaling its players proportionally
+    GroupVolume {
+        /// Group ID
+        group: String,
+        /// Group volume in percent (0-100)
+        #[arg(value_parser = clap::value_parser!(u8).range(0..=100))]
+        percent: u8,
+    },
+    /// Change every player's volume at once (or one group's with --group)
+    VolumeAll {
+        /// Only change players in this group
+        #[arg(long)]
+        group: Option<String>,
+        /// Set every player to this volume (0-100)
+        #[arg(long, value_name = "PERCENT", value_parser = clap::value_parser!(u8).range(0..=100))]
+        set: Option<u8>,
+        /// Raise every player by this many points (negative lowers, e.g. --step=-5)
+        #[arg(long, value_name = "POINTS", allow_hyphen_values = true)]
+        step: Option<i16>,
+        /// Mute every player
+        #[arg(long, conflicts_with = "unmute")]
+        mute: bool,
+        /// Unmute every player
+        #[arg(long)]
+        unmute: bool,
+    },
+    /// Switch a player between stereo and mono (both channels summed)
+    Mono {
+        /// Client ID
+        client: String,
+        /// Desired state
+        #[arg(value_parser = ["on", "off"])]
+        state: String,
+    },
+    /// Set what a player's channels carry; left and right split a stereo pair
+    ChannelMap {
+        /// Client ID
+        client: String,
+        /// Channel map
+        #[arg(value_enum)]
+        map: ChannelMap,
+    },
+    /// Show or pin the codec a player is streamed in (no options shows the current codec)
+    Codec {
+        /// Client ID
+        client: String,
+        /// Codec to pin the player to
+        #[arg(long, value_parser = ["flac", "pcm"])]
+        pin: Option<String>,
+        /// Go back to the server's codec policy
+        #[arg(long, conflicts_with = "pin")]
+        reset: bool,
+    },
+    /// Move a client to another group
+    Move {
+        /// Client ID
+        client: String,
+        /// Destination group ID
+        group: String,
+    },
+    /// Change the source played by a group (file path or HTTP URL)
+    Source {
+        /// Group ID
+        group: String,
+        /// File path, file:// URI, or http(s):// URL
+        uri: String,
+    },
+    /// Turn a group's night-mode compression on or off
+    Night {
+        /// Group ID
+        group: String,
+        /// Desired state
+        #[arg(value_parser = ["on", "off"])]
+        state: String,
+        /// Leave bass untouched
+        #[arg(long)]
+        keep_bass: bool,
+    },
+    /// Subscribe a group to a stream ("default" for the server's main stream)
+    Stream {
+        /// Group ID
+        group: String,
+        /// Stream ID
+        stream: String,
+    },
+    /// Set a group's stereo width (0 mono, 1 unchanged, 2 widest)
+    Width {
+        /// Group ID
+        group: String,
+        /// Width, or "off" to bypass width processing
+        width: String,
+        /// Keep bass below this frequency centered (Hz)
+        #[arg(long)]
+        bass_mono_hz: Option<f32>,
+    },
+    /// Show or tune a group's encoder (no options shows the current settings)
+    Encoder {
+        /// Group ID
+        group: String,
+        /// FLAC compression level (0-8)
+        #[arg(long, value_name = "N")]
+        flac_level: Option<u8>,
+        /// Dither below 24 bits
+        #[arg(long, value_enum)]
+        dither: Option<DitherMode>,
+        /// Go back to the server defaults
+        #[arg(long, conflicts_with_all = ["flac_level", "dither"])]
+        reset: bool,
+    },
+    /// Show what is playing
+    NowPlaying,
+    /// Show recently played tracks, newest first
+    History {
+        /// Only tracks played on this group
+        #[arg(long)]
+        group: Option<String>,
+        /// How many tracks to show
+        #[arg(long, default_value = "20")]
+        limit: usize,
+    },
+    /// Show each group's audio pipeline, from source through processing to encoders and clients
+    Pipeline {
+        /// Only show this group
+        group: Option<String>,
+    },
+    /// Show time spent encoding audio, per codec
+    Encoders,
+    /// Show time-sync round-trip percentiles, per client
+    Rtt,
+    /// Ask a client for a diagnostics snapshot (buffer, sync, underruns, device)
+    Diagnostics {
+        /// Client ID
+        client: String,
+    },
+    /// Analyse chunk audit logs (from --chunk-audit) offline and plot end-to-end timing
+    Audit {
+        /// Audit logs to merge, e.g. the server's and a player's
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+}
+
+struct Api {
+    base: String,
+    api_key: Option<String>,
+}
+
+impl Api {
+    /// Send a request and return the response body
+    fn send(&self, method: &str, path: &str, body: Option<String>) -> Result<String, String> {
+        let url = format!("{}/api{}", self.base.trim_end_matches('/'), path);
+        let mut request = ureq::request(method, &url);
+        if let Some(key) = &self.api_key {
+            request = request.set("Authorization", &format!("Bearer {}", key));
+        }
+
+        let result = match body {
+            Some(body) => request
+                .set("Content-Type", "application/json")
+                .send_string(&body),
+            None => request.call(),
+        };
+
+        let response = match result {
+            Ok(response) => response,
+            Err(ureq::Error::Status(status, response)) => {
+                let detail = response.into_string().unwrap_or_default();
+                return Err(match status {
+                    401 => "unauthorized: pass --api-key or set SENDSPIN_API_KEY".to_string(),
+                    403 => "forbidden: the API key lacks control permission".to_string(),
+                    404 => format!("not found: {}", path),
+                    _ if detail.is_empty() => format!("server returned {}", status),
+                    _ => format!("server returned {}: {}", status, detail),
+                });
+            }
+            Err(e) => return Err(format!("cannot reach {}: {}", url, e)),
+        };
+
+        response.into_string().map_err(|e| e.to_string())
+    }
+
+    fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T, String> {
+        parse_response(&self.send("GET", path, None)?)
+    }
+
+    /// Send a JSON body and parse the JSON answer
+    fn call<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        path: &str,
+        body: &impl Serialize,
+    ) -> Result<T, String> {
+        parse_response(&self.send(method, path, Some(to_body(body)?))?)
+    }
+
+    /// Send a JSON body to an endpoint that answers with no content
+    fn put(&self, path: &str, body: &impl Serialize) -> Result<(), String> {
+        self.send("PUT", path, Some(to_body(body)?)).map(drop)
+    }
+}
+
+fn to_body(body: &impl Serialize) -> Result<String, String> {
+    serde_json::to_string(body).map_err(|e| e.to_string())
+}
+
+fn parse_response<T: DeserializeOwned>(text: &str) -> Result<T, String> {
+    serde_json::from_str(text).map_err(|e| format!("invalid response: {}", e))
+}
+
+/// Lay rows out as left-aligned columns under a header
+fn render_table(header: &[&str], rows: &[Vec<String>]) -> String {
+    let mut widths: Vec<usize> = header.iter().map(|h| h.len()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let mut out = String::new();
+    let mut line = |cells: Vec<&str>| {
+        let padded: Vec<String> = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        let _ = writeln!(out, "{}", padded.join("  ").trim_end());
+    };
+    line(header.to_vec());
+    for row in rows {
+        line(row.iter().map(String::as_str).collect());
+    }
+    out
+}
+
+/// A value, or "-" when it is not set
+fn or_dash<T: Display>(value: Option<T>) -> String {
+    value.map_or("-".to_string(), |v| v.to_string())
+}
+
+/// How the control API names an enum value, e.g. "tpdf"
+fn api_name(value: &impl Serialize) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(name)) => name,
+        _ => "-".to_string(),
+    }
+}
+
+fn render_clients(clients: &[ClientInfo]) -> String {
+    let rows: Vec<Vec<String>> = clients
+        .iter()
+        .map(|c| {
+            let mut volume = if c.muted {
+                "muted".to_string()
+            } else {
+                format!("{}%", c.volume)
+            };
+            if c.channel_map != ChannelMap::Stereo {
+                volume = format!("{} {}", volume, c.channel_map.as_str());
+            }
+            vec![
+                c.client_id.clone(),
+                c.name.clone(),
+                or_dash(c.group_id.as_deref()),
+                volume,
+                c.roles.join(","),
+            ]
+        })
+        .collect();
+    render_table(&["CLIENT", "NAME", "GROUP", "VOLUME", "ROLES"], &rows)
+}
+
+fn render_volumes(volumes: &[PlayerVolume]) -> String {
+    let rows: Vec<Vec<String>> = volumes
+        .iter()
+        .map(|v| {
+            vec![
+                v.client_id.clone(),
+                format!("{}%", v.volume),
+                if v.muted { "muted" } else { "" }.to_string(),
+            ]
+        })
+        .collect();
+    render_table(&["CLIENT", "VOLUME", ""], &rows)
+}
+
+fn render_groups(groups: &[GroupStats]) -> String {
+    let rows: Vec<Vec<String>> = groups
+        .iter()
+        .map(|g| {
+            vec![
+                g.group_id.clone(),
+                g.name.clone(),
+                g.playback_state.clone(),
+                g.player_count.to_string(),
+                g.client_count.to_string(),
+                format!("{:.0}", g.bytes_per_second * 8.0 / 1000.0),
+                or_dash(
+                    g.sync_deviation_micros
+                        .map(|us| format!("{:.1}", us as f64 / 1000.0)),
+                ),
+                if g.night_mode { "on" } else { "off" }.to_string(),
+            ]
+        })
+        .collect();
+    render_table(
+        &[
+            "GROUP",
+            "NAME",
+            "STATE",
+            "PLAYERS",
+            "CLIENTS",
+            "KBIT/S",
+            "SPREAD MS",
+            "NIGHT",
+        ],
+        &rows,
+    )
+}
+
+fn render_encoders(encoders: &[EncoderStats]) -> String {
+    let rows: Vec<Vec<String>> = encoders
+        .iter()
+        .map(|e| {
+            vec![
+                e.codec.clone(),
+                e.chunks.to_string(),
+                format!("{:.0}", e.mean_micros),
+                e.max_micros.to_string(),
+                format!("{:.1}%", e.load * 100.0),
+            ]
+        })
+        .collect();
+    render_table(&["CODEC", "CHUNKS", "MEAN US", "MAX US", "LOAD"], &rows)
+}
+
+fn render_rtt(clients: &[ClientRtt]) -> String {
+    let ms = |micros: i64| format!("{:.1}", micros as f64 / 1000.0);
+    let rows: Vec<Vec<String>> = clients
+        .iter()
+        .map(|c| {
+            vec![
+                c.client_id.clone(),
+                c.name.clone(),
+                c.rtt.samples.to_string(),
+                ms(c.rtt.p50_micros),
+                ms(c.rtt.p90_micros),
+                ms(c.rtt.p99_micros),
+                ms(c.rtt.max_micros),
+            ]
+        })
+        .collect();
+    render_table(
+        &[
+            "CLIENT", "NAME", "SAMPLES", "P50 MS", "P90 MS", "P99 MS", "MAX MS",
+        ],
+        &rows,
+    )
+}
+
+/// A pipeline stage's name and settings, as one line of the pipeline view
+fn describe_stage(stage: &PipelineStage) -> (&'static str, String) {
+    match stage {
+        PipelineStage::SilenceTrim {
+            threshold_db,
+            max_ms,
+        } => (
+            "silence_trim",
+            format!("below {} dBFS, up to {} ms", threshold_db, max_ms),
+        ),
+        PipelineStage::Fallback {
+            fallback,
+            fail_after_ms,
+        } => (
+            "fallback",
+            format!("to {} after {} ms", fallback, fail_after_ms),
+        ),
+        PipelineStage::ReplayGain {
+            mode,
+            preamp_db,
+            prevent_clipping,
+        } => (
+            "replay_gain",
+            format!(
+                "{} gain {:+} dB, clipping {}",
+                api_name(mode),
+                preamp_db,
+                if *prevent_clipping {
+                    "prevented"
+                } else {
+                    "allowed"
+                }
+            ),
+        ),
+        PipelineStage::Loudness {
+            target_lufs,
+            max_gain_db,
+        } => (
+            "loudness",
+            format!("to {} LUFS, up to +{} dB", target_lufs, max_gain_db),
+        ),
+        PipelineStage::NightMode {
+            threshold_db,
+            ratio,
+            bass_cut_db,
+        } => (
+            "night_mode",
+            format!(
+                "above {} dB at {}:1, bass cut {}",
+                threshold_db,
+                ratio,
+                or_dash(*bass_cut_db)
+            ),
+        ),
+        PipelineStage::StereoWidth {
+            width,
+            bass_mono_hz,
+        } => (
+            "stereo_width",
+            format!(
+                "{}, bass centered below {} Hz",
+                width,
+                or_dash(*bass_mono_hz)
+            ),
+        ),
+    }
+}
+
+fn render_pipelines(pipelines: &[PipelineGraph]) -> String {
+    let mut out = String::new();
+    for (i, graph) in pipelines.iter().enumerate() {
+        if i > 0 {
+            out.push('\n');
+        }
+        let _ = writeln!(
+            out,
+            "Group {} ({}, {} ms ahead)",
+            graph.group_id,
+            graph.playback_state.as_str(),
+            graph.buffer_ahead_ms
+        );
+        let source = &graph.source;
+        let _ = writeln!(
+            out,
+            "  source    {} ({} Hz, {}ch)",
+            or_dash(source.description.as_deref()),
+            source.sample_rate,
+            source.channels
+        );
+        for stage in &graph.stages {
+            let (kind, detail) = describe_stage(stage);
+            let _ = writeln!(out, "  -> {:<12} {}", kind, detail);
+        }
+        if graph.branches.is_empty() {
+            out.push_str("  (no clients)\n");
+        }
+        for branch in &graph.branches {
+            let mut processing = Vec::new();
+            if branch.gain < 100 {
+                processing.push(format!("gain {}%", branch.gain));
+            }
+            if branch.channel_map != ChannelMap::Stereo {
+                processing.push(branch.channel_map.as_str().to_string());
+            }
+            let _ = writeln!(
+                out,
+                "  => {} {} Hz {}ch {}bit{} -> {}",
+                branch.codec,
+                branch.sample_rate,
+                branch.channels,
+                branch.bit_depth,
+                if processing.is_empty() {
+                    String::new()
+                } else {
+                    format!(" [{}]", processing.join(", "))
+                },
+                branch.clients.join(", ")
+            );
+        }
+    }
+    out
+}
+
+fn render_encoder_settings(info: &EncoderSettingsInfo) -> String {
+    let settings = &info.settings;
+    format!(
+        "Group:      {}{}\nFLAC level: {}\nDither:     {}\n",
+        info.group_id,
+        if info.overridden { " (override)" } else { "" },
+        or_dash(settings.flac_compression_level),
+        or_dash(settings.dither.map(|d| api_name(&d)))
+    )
+}
+
+fn render_codec(info: &CodecInfo) -> String {
+    format!(
+        "Client:    {}\nStreaming: {}\nPinned:    {}\n",
+        info.client_id,
+        or_dash(info.streaming.as_deref()),
+        info.pinned.as_deref().unwrap_or("no (codec policy)")
+    )
+}
+
+fn render_now_playing(info: &NowPlayingInfo) -> String {
+    let now = &info.now_playing;
+    let mut out = format!("Source:   {}\n", or_dash(now.source.as_deref()));
+    if let Some(title) = &now.track.title {
+        let _ = match &now.track.artist {
+            Some(artist) => writeln!(out, "Track:    {} - {}", artist, title),
+            None => writeln!(out, "Track:    {}", title),
+        };
+    }
+    let _ = writeln!(
+        out,
+        "Format:   {} Hz, {} channels",
+        now.sample_rate, now.channels
+    );
+    if info.playing_groups.is_empty() {
+        out.push_str("Playing:  (no groups)\n");
+    } else {
+        let _ = writeln!(out, "Playing:  {}", info.playing_groups.join(", "));
+    }
+    out
+}
+
+/// Milliseconds since the Unix epoch
+fn unix_now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+/// Time between two Unix timestamps in milliseconds, e.g. "5m ago"
+fn ago(then_ms: u64, now_ms: u64) -> String {
+    let secs = now_ms.saturating_sub(then_ms) / 1000;
+    match secs {
+        0..=59 => format!("{}s ago", secs),
+        60..=3599 => format!("{}m ago", secs / 60),
+        3600..=86399 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400),
+    }
+}
+
+fn render_history(records: &[PlayRecord]) -> String {
+    let now = unix_now_ms();
+    let rows: Vec<Vec<String>> = records
+        .iter()
+        .map(|r| {
+            let title = match (&r.track.artist, &r.track.title) {
+                (Some(artist), Some(title)) => format!("{} - {}", artist, title),
+                (None, Some(title)) => title.clone(),
+                _ => "-".to_string(),
+            };
+            let played = match r.ended_at_ms {
+                Some(end) => format!("{}s", end.saturating_sub(r.started_at_ms) / 1000),
+                None => "playing".to_string(),
+            };
+            vec![
+                ago(r.started_at_ms, now),
+                title,
+                or_dash(r.source.as_deref()),
+                played,
+                r.groups.join(","),
+            ]
+        })
+        .collect();
+    render_table(&["STARTED", "TRACK", "SOURCE", "PLAYED", "GROUPS"], &rows)
+}
+
+fn render_diagnostics(info: &ClientDiagnostics) -> String {
+    let stats = &info.stats;
+    let mut out = String::new();
+    if let Some(device) = &info.device_info {
+        let _ = writeln!(
+            out,
+            "Device:      {} ({}) {}",
+            device.product_name, device.manufacturer, device.software_version
+        );
+    }
+    let _ = writeln!(
+        out,
+        "Clock sync:  {}",
+        or_dash(info.sync_quality.map(|q| api_name(&q)))
+    );
+    let _ = writeln!(out, "RTT:         {} us", or_dash(stats.rtt_micros));
+    let _ = writeln!(out, "Buffered:    {} ms", or_dash(stats.buffered_ms));
+    let _ = writeln!(out, "Sync error:  {} us", or_dash(stats.sync_error_micros));
+    let _ = writeln!(out, "Underruns:   {}", stats.underruns);
+    let _ = writeln!(
+        out,
+        "Chunks:      {} received, {} gaps, {} stale, {} recovered",
+        stats.chunks_received, stats.gaps, stats.stale_dropped, stats.fec_recovered
+    );
+    out
+}
+
+/// Distribution of one audit interval, in microseconds
+#[derive(Debug, Serialize)]
+struct AuditStats {
+    count: usize,
+    min_micros: i64,
+    median_micros: i64,
+    p99_micros: i64,
+    max_micros: i64,
+}
+
+impl AuditStats {
+    fn from_values(values: Vec<i64>) -> Option<Self> {
+        IntervalStats::from_values(values).map(|s| Self {
+            count: s.count,
+            min_micros: s.min,
+            median_micros: s.median,
+            p99_micros: s.p99,
+            max_micros: s.max,
+        })
+    }
+}
+
+/// Time chunks took between two stages
+#[derive(Debug, Serialize)]
+struct AuditInterval {
+    from: &'static str,
+    to: &'static str,
+    stats: Option<AuditStats>,
+}
+
+/// How far ahead of their play-at time chunks reached a stage
+#[derive(Debug, Serialize)]
+struct AuditLead {
+    stage: &'static str,
+    stats: Option<AuditStats>,
+}
+
+/// Worst headroom among a run of consecutive chunks
+#[derive(Debug, Serialize)]
+struct HeadroomBucket {
+    offset_micros: i64,
+    chunks: usize,
+    min_headroom_micros: i64,
+}
+
+/// Summary of merged chunk audit logs
+#[derive(Debug, Serialize)]
+struct AuditReport {
+    records: usize,
+    chunks: usize,
+    intervals: Vec<AuditInterval>,
+    lead: Vec<AuditLead>,
+    timeline: Vec<HeadroomBucket>,
+}
+
+/// Summarize merged audit logs: per-interval statistics and headroom over time
+fn audit_report(files: &[PathBuf]) -> Result<AuditReport, String> {
+    let mut records = Vec::new();
+    for file in files {
+        records.extend(read_audit_log(file).map_err(|e| format!("{}: {}", file.display(), e))?);
+    }
+    let chunks = chunk_timelines(&records);
+
+    let intervals = [
+        (AuditStage::Generated, AuditStage::Sent),
+        (AuditStage::Sent, AuditStage::Received),
+        (AuditStage::Received, AuditStage::PlayedOut),
+        (AuditStage::Generated, AuditStage::PlayedOut),
+    ]
+    .into_iter()
+    .map(|(from, to)| {
+        let values = chunks.iter().filter_map(|c| c.between(from, to)).collect();
+        AuditInterval {
+            from: from.name(),
+            to: to.name(),
+            stats: AuditStats::from_values(values),
+        }
+    })
+    .collect();
+    let lead = AuditStage::ALL
+        .into_iter()
+        .map(|stage| {
+            let values = chunks.iter().filter_map(|c| c.lead(stage)).collect();
+            AuditLead {
+                stage: stage.name(),
+                stats: AuditStats::from_values(values),
+            }
+        })
+        .collect();
+
+    // Worst headroom per time bucket, at the furthest stage each chunk reached
+    let headroom = |c: &ChunkTimeline| AuditStage::ALL.iter().rev().find_map(|&s| c.lead(s));
+    let first = chunks.first().map_or(0, |c| c.play_at);
+    let bucket_len = chunks.len().div_ceil(AUDIT_PLOT_ROWS).max(1);
+    let timeline = chunks
+        .chunks(bucket_len)
+        .filter_map(|bucket| {
+            let worst = bucket.iter().filter_map(headroom).min()?;
+            Some(HeadroomBucket {
+                offset_micros: bucket[0].play_at - first,
+                chunks: bucket.len(),
+                min_headroom_micros: worst,
+            })
+        })
+        .collect();
+
+    Ok(AuditReport {
+        records: records.len(),
+        chunks: chunks.len(),
+        intervals,
+        lead,
+        timeline,
+    })
+}
+
+fn render_audit(report: &AuditReport) -> String {
+    let ms = |micros: i64| format!("{:.2}", micros as f64 / 1000.0);
+    let stat_row = |label: String, stats: &AuditStats| {
+        vec![
+            label,
+            stats.count.to_string(),
+            ms(stats.min_micros),
+            ms(stats.median_micros),
+            ms(stats.p99_micros),
+            ms(stats.max_micros),
+        ]
+    };
+    let header = ["", "CHUNKS", "MIN MS", "MEDIAN MS", "P99 MS", "MAX MS"];
+
+    let mut out = format!("{} records, {} chunks\n\n", report.records, report.chunks);
+    let rows: Vec<Vec<String>> = report
+        .intervals
+        .iter()
+        .filter_map(|i| {
+            let label = format!("{} -> {}", i.from, i.to);
+            Some(stat_row(label, i.stats.as_ref()?))
+        })
+        .collect();
+    out.push_str(&render_table(&header, &rows));
+    out.push('\n');
+    let rows: Vec<Vec<String>> = report
+        .lead
+        .iter()
+        .filter_map(|l| {
+            Some(stat_row(
+                format!("lead when {}", l.stage),
+                l.stats.as_ref()?,
+            ))
+        })
+        .collect();
+    out.push_str(&render_table(&header, &rows));
+
+    let Some(scale) = report
+        .timeline
+        .iter()
+        .map(|r| r.min_headroom_micros.abs())
+        .max()
+    else {
+        return out;
+    };
+    out.push_str("\nWorst headroom over time (# ahead of play-at, ! late):\n");
+    for row in &report.timeline {
+        let micros = row.min_headroom_micros;
+        let len = (micros.unsigned_abs() as usize * AUDIT_PLOT_WIDTH)
+            .checked_div(scale as usize)
+            .unwrap_or(0);
+        let bar = if micros < 0 { "!" } else { "#" }.repeat(len);
+        let _ = writeln!(
+            out,
+            "{:>8.1}s |{:<width$} {} ms",
+            row.offset_micros as f64 / 1_000_000.0,
+            bar,
+            ms(micros),
+            width = AUDIT_PLOT_WIDTH
+        );
+    }
+    out
+}
+
+/// Body of a volume-all command, refusing one that changes nothing
+fn batch_volume_request(
+    group: Option<String>,
+    set: Option<u8>,
+    step: Option<i16>,
+    mute: bool,
+    unmute: bool,
+) -> Result<BatchVolumeRequest, String> {
+    let muted = (mute || unmute).then_some(mute);
+    if set.is_none() && step.is_none() && muted.is_none() {
+        return Err("pass --set, --step, --mute, or --unmute".to_string());
+    }
+    Ok(BatchVolumeRequest {
+        group_id: group,
+        volume: set,
+        step,
+        muted,
+    })
+}
+
+/// A stereo width argument; "off" bypasses width processing
+fn parse_width(width: &str) -> Result<Option<f32>, String> {
+    match width {
+        "off" => Ok(None),
+        value => value
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("invalid width: {}", value)),
+    }
+}
+
+/// Print a result as JSON, or rendered for reading
+fn emit<T: Serialize + ?Sized>(
+    json: bool,
+    value: &T,
+    render: fn(&T) -> String,
+) -> Result<(), String> {
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(value).map_err(|e| e.to_string())?
+        );
+    } else {
+        print!("{}", render(value));
+    }
+    Ok(())
+}
+
+/// Render nothing: the command's effect is its output
+fn quiet<T: ?Sized>(_: &T) -> String {
+    String::new()
+}
+
+fn run(args: Args) -> Result<(), String> {
+    let api = Api {
+        base: args.server,
+        api_key: args
+            .api_key
+            .or_else(|| std::env::var("SENDSPIN_API_KEY").ok()),
+    };
+    let json = args.json;
+
+    match args.command {
+        Command::Clients => {
+            let clients: Vec<ClientInfo> = api.get("/clients")?;
+            emit(json, clients.as_slice(), render_clients)
+        }
+        Command::Groups => {
+            let groups: Vec<GroupStats> = api.get("/groups")?;
+            emit(json, groups.as_slice(), render_groups)
+        }
+        Command::NowPlaying => {
+            let info: NowPlayingInfo = api.get("/now-playing")?;
+            emit(json, &info, render_now_playing)
+        }
+        Command::History { group, limit } => {
+            let mut path = format!("/history?limit={}", limit);
+            if let Some(group) = group {
+                path.push_str(&format!("&group={}", group));
+            }
+            let records: Vec<PlayRecord> = api.get(&path)?;
+            emit(json, records.as_slice(), render_history)
+        }
+        Command::Pipeline { group } => {
+            let pipelines: Vec<PipelineGraph> = match group {
+                Some(group) => vec![api.get(&format!("/groups/{}/pipeline", group))?],
+                None => api.get("/pipeline")?,
+            };
+            emit(json, pipelines.as_slice(), render_pipelines)
+        }
+        Command::Encoders => {
+            let encoders: Vec<EncoderStats> = api.get("/metrics/encoders")?;
+            emit(json, encoders.as_slice(), render_encoders)
+        }
+        Command::Rtt => {
+            let clients: Vec<ClientRtt> = api.get("/metrics/rtt")?;
+            emit(json, clients.as_slice(), render_rtt)
+        }
+        Command::Audit { files } => emit(json, &audit_report(&files)?, render_audit),
+        Command::Diagnostics { client } => {
+            let path = format!("/clients/{}/diagnostics", client);
+            let diagnostics: ClientDiagnostics = api.get(&path)?;
+            emit(json, &diagnostics, render_diagnostics)
+        }
+        Command::Volume { client, percent } => {
+            let path = format!("/clients/{}/volume", client);
+            let body = VolumeRequest {
+                volume: Some(percent),
+                muted: None,
+            };
+            api.put(&path, &body)?;
+            emit(
+                json,
+                &json!({ "client_id": client, "volume": percent }),
+                quiet,
+            )
+        }
+        Command::GroupVolume { group, percent } => {
+            let path = format!("/groups/{}/volume", group);
+            let body = VolumeRequest {
+                volume: Some(percent),
+                muted: None,
+            };
+            let volumes: Vec<PlayerVolume> = api.call("PUT", &path, &body)?;
+            emit(json, volumes.as_slice(), render_volumes)
+        }
+        Command::VolumeAll {
+            group,
+            set,
+            step,
+            mute,
+            unmute,
+        } => {
+            let body = batch_volume_request(group, set, step, mute, unmute)?;
+            let volumes: Vec<PlayerVolume> = api.call("POST", "/volume", &body)?;
+            emit(json, volumes.as_slice(), render_volumes)
+        }
+        Command::Mono { client, state } => {
+            let path = format!("/clients/{}/mono", client);
+            let enabled = state == "on";
+            api.put(&path, &MonoRequest { enabled })?;
+            emit(
+                json,
+                &json!({ "client_id": client, "mono": enabled }),
+                quiet,
+            )
+        }
+        Command::ChannelMap { client, map } => {
+            let path = format!("/clients/{}/channel-map", client);
+            api.put(&path, &ChannelMapRequest { channel_map: map })?;
+            emit(
+                json,
+                &json!({ "client_id": client, "channel_map": map }),
+                quiet,
+            )
+        }
+        Command::Codec { client, pin, reset } => {
+            let path = format!("/clients/{}/codec", client);
+            if reset || pin.is_some() {
+                let body = CodecRequest {
+                    codec: pin,
+                    ..Default::default()
+                };
+                api.put(&path, &body)?;
+            }
+            let info: CodecInfo = api.get(&path)?;
+            emit(json, &info, render_codec)
+        }
+        Command::Move { client, group } => {
+            let path = format!("/clients/{}/group", client);
+            let body = MoveRequest {
+                group_id: group.clone(),
+            };
+            api.put(&path, &body)?;
+            emit(
+                json,
+                &json!({ "client_id": client, "group_id": group }),
+                quiet,
+            )
+        }
+        Command::Night {
+            group,
+            state,
+            keep_bass,
+        } => {
+            let path = format!("/groups/{}/night-mode", group);
+            let enabled = state == "on";
+            let body = NightModeRequest {
+                enabled,
+                bass_cut: Some(!keep_bass),
+            };
+            api.put(&path, &body)?;
+            emit(
+                json,
+                &json!({ "group_id": group, "night_mode": enabled }),
+                quiet,
+            )
+        }
+        Command::Stream { group, stream } => {
+            let path = format!("/groups/{}/stream", group);
+            let body = StreamRequest {
+                stream_id: Some(stream.clone()),
+            };
+            api.put(&path, &body)?;
+            emit(
+                json,
+                &json!({ "group_id": group, "stream_id": stream }),
+                quiet,
+            )
+        }
+        Command::Width {
+            group,
+            width,
+            bass_mono_hz,
+        } => {
+            let width = parse_width(&width)?;
+            let path = format!("/groups/{}/stereo-width", group);
+            let body = StereoWidthRequest {
+                width,
+                bass_mono_hz,
+            };
+            api.put(&path, &body)?;
+            emit(
+                json,
+                &json!({ "group_id": group, "stereo_width": width }),
+                quiet,
+            )
+        }
+        Command::Encoder {
+            group,
+            flac_level,
+            dither,
+            reset,
+        } => {
+            let path = format!("/groups/{}/encoder", group);
+            if reset || flac_level.is_some() || dither.is_some() {
+                let body = EncoderSettings {
+                    flac_compression_level: flac_level,
+                    dither,
+                };
+                api.put(&path, &body)?;
+            }
+            let info: EncoderSettingsInfo = api.get(&path)?;
+            emit(json, &info, render_encoder_settings)
+        }
+        Command::Source { group, uri } => {
+            let path = format!("/groups/{}/source", group);
+            api.put(&path, &SourceRequest { uri: uri.clone() })?;
+            emit(json, &json!({ "group_id": group, "uri": uri }), quiet)
+        }
+    }
+}
+
+fn main() {
+    if let Err(e) = run(Args::parse()) {
+        eprintln!("sendspin-ctl: {}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sendspin::server::{
+        BufferHealth, EncoderBranch, PipelineSource, PlaybackState, TrackInfo, TransportSnapshot,
+    };
+
+    fn parse(args: &[&str]) -> Result<Args, clap::Error> {
+        Args::try_parse_from(std::iter::once("sendspin-ctl").chain(args.iter().copied()))
+    }
+
+    fn client(client_id: &str, volume: u8, muted: bool, channel_map: ChannelMap) -> ClientInfo {
+        ClientInfo {
+            client_id: client_id.to_string(),
+            name: "Kitchen".to_string(),
+            roles: vec!["player".to_string(), "metadata".to_string()],
+            group_id: Some("downstairs".to_string()),
+            volume,
+            muted,
+            max_volume: 100,
+            mono: false,
+            channel_map,
+            link_tier: None,
+            buffered_ms: None,
+            buffer_health: BufferHealth::Healthy,
+            rtt: None,
+            control_latency_ms: None,
+            transport: TransportSnapshot::default(),
+        }
+    }
+
+    #[test]
+    fn test_defaults_to_local_server() {
+        let args = parse(&["clients"]).unwrap();
+        assert_eq!(args.server, "http://127.0.0.1:8927");
+        assert!(!args.json);
+        assert!(matches!(args.command, Command::Clients));
+    }
+
+    #[test]
+    fn test_volume_must_be_a_percentage() {
+        assert!(parse(&["volume", "kitchen", "100"]).is_ok());
+        assert!(parse(&["volume", "kitchen", "101"]).is_err());
+        assert!(parse(&["group-volume", "downstairs", "101"]).is_err());
+    }
+
+    #[test]
+    fn test_volume_all_takes_a_negative_step() {
+        let args = parse(&["volume-all", "--step=-5", "--group", "downstairs"]).unwrap();
+        let Command::VolumeAll { group, step, .. } = args.command else {
+            panic!("expected volume-all");
+        };
+        assert_eq!(group.as_deref(), Some("downstairs"));
+        assert_eq!(step, Some(-5));
+
+        assert!(parse(&["volume-all", "--mute", "--unmute"]).is_err());
+    }
+
+    #[test]
+    fn test_enum_arguments_use_api_types() {
+        let args = parse(&["channel-map", "kitchen", "left"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Command::ChannelMap {
+                map: ChannelMap::Left,
+                ..
+            }
+        ));
+        assert!(parse(&["channel-map", "kitchen", "sideways"]).is_err());
+
+        let args = parse(&["encoder", "downstairs", "--dither", "shaped"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Command::Encoder {
+                dither: Some(DitherMode::Shaped),
+                ..
+            }
+        ));
+        assert!(parse(&["encoder", "downstairs", "--reset", "--flac-level", "5"]).is_err());
+    }
+
+    #[test]
+    fn test_conflicting_and_missing_arguments() {
+        assert!(parse(&["codec", "kitchen", "--pin", "flac", "--reset"]).is_err());
+        assert!(parse(&["codec", "kitchen", "--pin", "pcm"]).is_ok());
+        assert!(parse(&["codec", "kitchen", "--pin", "opus"]).is_err());
+        assert!(parse(&["mono", "kitchen", "maybe"]).is_err());
+        assert!(parse(&["audit"]).is_err());
+    }
+
+    #[test]
+    fn test_batch_volume_request() {
+        assert!(batch_volume_request(None, None, None, false, false).is_err());
+
+        let body = batch_volume_request(None, None, None, false, true).unwrap();
+        assert_eq!(body.muted, Some(false));
+
+        let body = batch_volume_request(Some("den".to_string()), Some(40), None, false, false);
+        assert_eq!(
+            serde_json::to_value(body.unwrap()).unwrap(),
+            json!({ "group_id": "den", "volume": 40 })
+        );
+    }
+
+    #[test]
+    fn test_parse_width() {
+        assert_eq!(parse_width("off"), Ok(None));
+        assert_eq!(parse_width("1.5"), Ok(Some(1.5)));
+        assert!(parse_width("wide").is_err());
+    }
+
+    #[test]
+    fn test_render_table_aligns_columns() {
+        let rows = vec![vec!["kitchen".to_string(), "40%".to_string()]];
+        assert_eq!(
+            render_table(&["CLIENT", "VOLUME"], &rows),
+            "CLIENT   VOLUME\nkitchen  40%\n"
+        );
+    }
+
+    #[test]
+    fn test_render_clients() {
+        let clients = [
+            client("kitchen", 40, false, ChannelMap::Left),
+            client("den", 70, true, ChannelMap::Stereo),
+        ];
+        let out = render_clients(&clients);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].starts_with("kitchen"));
+        assert!(lines[1].contains("40% left"));
+        assert!(lines[1].ends_with("player,metadata"));
+        assert!(lines[2].contains("muted"));
+    }
+
+    #[test]
+    fn test_client_info_round_trips_through_json() {
+        let json = serde_json::to_string(&[client("kitchen", 40, false, ChannelMap::Left)]);
+        let clients: Vec<ClientInfo> = parse_response(&json.unwrap()).unwrap();
+        assert_eq!(clients[0].client_id, "kitchen");
+        assert_eq!(clients[0].channel_map, ChannelMap::Left);
+        assert!(parse_response::<Vec<ClientInfo>>("{}").is_err());
+    }
+
+    #[test]
+    fn test_render_groups() {
+        let groups = [GroupStats {
+            group_id: "downstairs".to_string(),
+            name: "Downstairs".to_string(),
+            playback_state: "playing".to_string(),
+            bytes_per_second: 176_400.0,
+            sync_deviation_micros: Some(2_500),
+            night_mode: true,
+            ..Default::default()
+        }];
+        let out = render_groups(&groups);
+        let row = out.lines().nth(1).unwrap();
+        assert!(row.contains("1411"));
+        assert!(row.contains("2.5"));
+        assert!(row.ends_with("on"));
+    }
+
+    #[test]
+    fn test_render_pipelines() {
+        let graph = PipelineGraph {
+            group_id: "downstairs".to_string(),
+            playback_state: PlaybackState::Playing,
+            buffer_ahead_ms: 500,
+            stream_id: "default".to_string(),
+            source: PipelineSource {
+                description: Some("song.flac".to_string()),
+                sample_rate: 48_000,
+                channels: 2,
+            },
+            stages: vec![PipelineStage::NightMode {
+                threshold_db: -24.0,
+                ratio: 4.0,
+                bass_cut_db: None,
+            }],
+            branches: vec![EncoderBranch {
+                codec: "flac".to_string(),
+                sample_rate: 48_000,
+                channels: 2,
+                bit_depth: 16,
+                settings: EncoderSettings::default(),
+                gain: 50,
+                channel_map: ChannelMap::Left,
+                clients: vec!["kitchen".to_string()],
+            }],
+        };
+        let idle = PipelineGraph {
+            group_id: "upstairs".to_string(),
+            playback_state: PlaybackState::Stopped,
+            stages: Vec::new(),
+            branches: Vec::new(),
+            ..graph.clone()
+        };
+
+        let out = render_pipelines(&[graph, idle]);
+        assert!(out.starts_with("Group downstairs (playing, 500 ms ahead)\n"));
+        assert!(out.contains("  source    song.flac (48000 Hz, 2ch)\n"));
+        assert!(out.contains("  -> night_mode   above -24 dB at 4:1, bass cut -\n"));
+        assert!(out.contains("  => flac 48000 Hz 2ch 16bit [gain 50%, left] -> kitchen\n"));
+        assert!(out.ends_with("Group upstairs (stopped, 500 ms ahead)\n  source    song.flac (48000 Hz, 2ch)\n  (no clients)\n"));
+    }
+
+    #[test]
+    fn test_render_codec_and_encoder_settings() {
+        let codec = CodecInfo {
+            client_id: "kitchen".to_string(),
+            pinned: None,
+            settings: EncoderSettings::default(),
+            streaming: Some("flac".to_string()),
+        };
+        assert_eq!(
+            render_codec(&codec),
+            "Client:    kitchen\nStreaming: flac\nPinned:    no (codec policy)\n"
+        );
+
+        let info = EncoderSettingsInfo {
+            group_id: "downstairs".to_string(),
+            overridden: true,
+            settings: EncoderSettings {
+                flac_compression_level: Some(5),
+                dither: Some(DitherMode::Tpdf),
+            },
+        };
+        assert_eq!(
+            render_encoder_settings(&info),
+            "Group:      downstairs (override)\nFLAC level: 5\nDither:     tpdf\n"
+        );
+    }
+
+    #[test]
+    fn test_render_diagnostics_from_a_minimal_reply() {
+        let reply = json!({
+            "request_id": "1",
+            "stats": {
+                "chunks_received": 10,
+                "gaps": 1,
+                "overlaps": 0,
+                "max_gap_micros": 0,
+                "max_overlap_micros": 0
+            }
+        });
+        let diagnostics: ClientDiagnostics = parse_response(&reply.to_string()).unwrap();
+        let out = render_diagnostics(&diagnostics);
+        assert!(!out.contains("Device:"));
+        assert!(out.contains("Clock sync:  -\n"));
+        assert!(out.contains("RTT:         - us\n"));
+        assert!(out.contains("Chunks:      10 received, 1 gaps, 0 stale, 0 recovered\n"));
+    }
+
+    #[test]
+    fn test_render_history() {
+        let record = PlayRecord {
+            id: 1,
+            source: Some("song.flac".to_string()),
+            track: TrackInfo {
+                title: Some("Title".to_string()),
+                artist: Some("Artist".to_string()),
+                ..Default::default()
+            },
+            started_at_ms: unix_now_ms(),
+            ended_at_ms: None,
+            groups: vec!["downstairs".to_string()],
+        };
+        let out = render_history(&[record]);
+        let row = out.lines().nth(1).unwrap();
+        assert!(row.contains("Artist - Title"));
+        assert!(row.contains("playing"));
+
+        assert_eq!(ago(0, 59_000), "59s ago");
+        assert_eq!(ago(0, 7_200_000), "2h ago");
+    }
+}
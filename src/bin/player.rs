@@ -0,0 +1,504 @@
+// ABOUTME: Headless Sendspin player binary
+// ABOUTME: Connects to a server, keeps its clock in sync, and plays the stream on the default output device
+
+use clap::Parser;
+use sendspin::audio::decode::{Concealer, Decoder, PcmDecoder};
+use sendspin::audio::{AudioBuffer, AudioFormat, AudioOutput, Codec, CpalOutput, Sample};
+use sendspin::protocol::client::{connect_discovered, AudioChunk, ProtocolClient};
+use sendspin::protocol::messages::{
+    AudioFormatSpec, ClientHello, ClientTime, DeviceInfo, Message, MessageTrace, PlayerCommand,
+    PlayerSupport, StreamPlayerConfig,
+};
+use sendspin::protocol::stats::DEFAULT_GAP_TOLERANCE_MICROS;
+use sendspin::protocol::WsSender;
+use sendspin::scheduler::AudioScheduler;
+use sendspin::sync::{AuditLog, AuditStage, Clock, ClockSync, SystemClock};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Delay before reconnecting after the server goes away
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// How long `--discover` browses mDNS for a server
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Quick `client/time` exchanges sent right after connecting
+const INITIAL_SYNC_ROUNDS: u32 = 5;
+
+/// Spacing of the initial `client/time` burst
+const INITIAL_SYNC_SPACING: Duration = Duration::from_millis(100);
+
+/// Spacing of `client/time` once the clock is synced
+const SYNC_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How often buffer statistics are reported to the server
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Playback thread poll interval (1ms keeps enqueue jitter low)
+const PLAYBACK_POLL: Duration = Duration::from_millis(1);
+
+/// Gaps longer than this are a stream discontinuity, not packet loss
+const MAX_CONCEALED_GAP_MICROS: i64 = 1_000_000;
+
+/// Headless Sendspin player
+#[derive(Parser, Debug)]
+#[command(name = "sendspin-player")]
+#[command(author, version, about = "Play a Sendspin stream on the default audio device", long_about = None)]
+struct Args {
+    /// WebSocket URL of the server
+    #[arg(
+        short,
+        long,
+        default_value = "ws://localhost:8927/sendspin",
+        conflicts_with = "discover"
+    )]
+    server: String,
+
+    /// Find the server on the local network via mDNS instead of --server
+    #[arg(short, long)]
+    discover: bool,
+
+    /// Player name shown by the server
+    #[arg(short, long, default_value = "Sendspin Player")]
+    name: String,
+
+    /// Stable client ID so the server recognises this player across restarts
+    #[arg(long)]
+    client_id: Option<String>,
+
+    /// Initial volume (0-100)
+    #[arg(long, default_value = "100", value_parser = clap::value_parser!(u8).range(0..=100))]
+    volume: u8,
+
+    /// Buffer capacity advertised to the server in bytes
+    #[arg(long, value_name = "BYTES", default_value = "1000000")]
+    buffer_capacity: u32,
+
+    /// Record when each chunk is received and played to this file (analyse with sendspin-ctl audit)
+    #[arg(long, value_name = "FILE")]
+    chunk_audit: Option<PathBuf>,
+
+    /// Enable verbose logging
+    #[arg(short, long)]
+    verbose: bool,
+}
+
+/// Volume and mute, set by server commands and applied on the playback thread
+struct Controls {
+    volume: AtomicU8,
+    muted: AtomicBool,
+}
+
+impl Controls {
+    fn apply(&self, command: &PlayerCommand) {
+        match command.command.as_str() {
+            "volume" => {
+                if let Some(volume) = command.volume {
+                    self.volume.store(volume.min(100), Ordering::Relaxed);
+                }
+            }
+            "mute" => {
+                if let Some(mute) = command.mute {
+                    self.muted.store(mute, Ordering::Relaxed);
+                }
+            }
+            other => tracing::debug!("Ignoring player command '{}'", other),
+        }
+    }
+
+    fn volume(&self) -> u8 {
+        self.volume.load(Ordering::Relaxed)
+    }
+
+    fn muted(&self) -> bool {
+        self.muted.load(Ordering::Relaxed)
+    }
+
+    /// Scale samples by the current volume (silence while muted)
+    fn scale(&self, samples: &Arc<[Sample]>) -> Arc<[Sample]> {
+        let percent = if self.muted() { 0 } else { self.volume() };
+        if percent == 100 {
+            return Arc::clone(samples);
+        }
+        samples
+            .iter()
+            .map(|s| Sample(s.0 * percent as f32 / 100.0))
+            .collect()
+    }
+}
+
+/// Decoding state for the current stream
+struct Stream {
+    format: AudioFormat,
+    decoder: PcmDecoder,
+    concealer: Concealer,
+    /// Server timestamp the next chunk should start at
+    expected_next: Option<i64>,
+}
+
+impl Stream {
+    fn start(config: &StreamPlayerConfig) -> Result<Self, String> {
+        if config.codec != "pcm" {
+            return Err(format!("unsupported codec '{}'", config.codec));
+        }
+        if config.bit_depth != 16 && config.bit_depth != 24 {
+            return Err(format!("unsupported bit depth {}", config.bit_depth));
+        }
+        Ok(Self {
+            format: AudioFormat {
+                codec: Codec::Pcm,
+                sample_rate: config.sample_rate,
+                channels: config.channels,
+                bit_depth: config.bit_depth,
+                codec_header: None,
+            },
+            decoder: PcmDecoder::new(config.bit_depth),
+            concealer: Concealer::new(config.sample_rate, config.channels),
+            expected_next: None,
+        })
+    }
+
+    fn micros(&self, samples: usize) -> i64 {
+        let frames = samples / self.format.channels.max(1) as usize;
+        (frames as u64 * 1_000_000 / self.format.sample_rate as u64) as i64
+    }
+
+    /// Decode a chunk and schedule it (and any concealed gap before it)
+    fn schedule(&mut self, chunk: &AudioChunk, sync: &ClockSync, scheduler: &AudioScheduler) {
+        // Chunks can't be placed on the timeline until the clock is synced
+        let Some(play_at) = sync.server_to_local_instant(chunk.timestamp) else {
+            return;
+        };
+
+        if let Some(expected) = self.expected_next {
+            let gap = chunk.timestamp - expected;
+            if gap > DEFAULT_GAP_TOLERANCE_MICROS && gap < MAX_CONCEALED_GAP_MICROS {
+                if let Some(gap_at) = sync.server_to_local_instant(expected) {
+                    let frames = (gap * self.format.sample_rate as i64 / 1_000_000) as usize;
+                    let samples = self.concealer.conceal(frames);
+                    tracing::debug!("Concealed {}µs gap before ts={}", gap, chunk.timestamp);
+                    scheduler.schedule(AudioBuffer {
+                        timestamp: expected,
+                        play_at: gap_at,
+                        samples,
+                        format: self.format.clone(),
+                    });
+                }
+            }
+        }
+
+        let samples = match self.decoder.decode(&chunk.data) {
+            Ok(samples) => samples,
+            Err(e) => {
+                tracing::warn!("Dropping undecodable chunk: {}", e);
+                return;
+            }
+        };
+        let duration = self.micros(samples.len());
+        self.expected_next = Some(chunk.timestamp + duration);
+
+        // Too late to play any of it
+        if play_at + Duration::from_micros(duration as u64) < Instant::now() {
+            tracing::debug!("Dropping late chunk ts={}", chunk.timestamp);
+            return;
+        }
+        self.concealer.remember(&samples);
+        scheduler.schedule(AudioBuffer {
+            timestamp: chunk.timestamp,
+            play_at,
+            samples,
+            format: self.format.clone(),
+        });
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+    init_tracing(args.verbose);
+
+    let hello = build_hello(&args);
+    let controls = Arc::new(Controls {
+        volume: AtomicU8::new(args.volume),
+        muted: AtomicBool::new(false),
+    });
+    let audit = args
+        .chunk_audit
+        .as_ref()
+        .map(AuditLog::create)
+        .transpose()?;
+    let scheduler = Arc::new(AudioScheduler::new());
+    spawn_playback(Arc::clone(&scheduler), Arc::clone(&controls), audit.clone());
+
+    loop {
+        let connected = if args.discover {
+            connect_discovered(hello.clone(), DISCOVERY_TIMEOUT).await
+        } else {
+            ProtocolClient::connect(&args.server, hello.clone()).await
+        };
+        match connected {
+            Ok(client) => {
+                let server = client.server_hello().name.clone();
+                tracing::info!("Connected to {}", server);
+                if let Err(e) = run_session(client, &scheduler, &controls, audit.as_ref()).await {
+                    tracing::warn!("Session ended: {}", e);
+                }
+                scheduler.clear();
+                tracing::info!("Disconnected from {}, reconnecting...", server);
+            }
+            Err(e) => tracing::warn!("Connection failed: {}", e),
+        }
+        tokio::time::sleep(RETRY_DELAY).await;
+    }
+}
+
+fn init_tracing(verbose: bool) {
+    use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+    let filter = if verbose {
+        "sendspin=debug,sendspin_player=debug"
+    } else {
+        "sendspin=info,sendspin_player=info"
+    };
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| filter.into()),
+        )
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+}
+
+fn build_hello(args: &Args) -> ClientHello {
+    let pcm = |sample_rate, bit_depth| AudioFormatSpec {
+        codec: "pcm".to_string(),
+        channels: 2,
+        sample_rate,
+        bit_depth,
+    };
+    ClientHello {
+        client_id: args
+            .client_id
+            .clone()
+            .unwrap_or_else(|| uuid::Uuid::new_v4().to_string()),
+        name: args.name.clone(),
+        version: 1,
+        supported_roles: vec!["player@v1".to_string()],
+        device_info: DeviceInfo {
+            product_name: "sendspin-player".to_string(),
+            manufacturer: "Sendspin".to_string(),
+            software_version: env!("CARGO_PKG_VERSION").to_string(),
+        },
+        player_support: Some(PlayerSupport {
+            supported_formats: vec![
+                pcm(48_000, 24),
+                pcm(48_000, 16),
+                pcm(44_100, 24),
+                pcm(44_100, 16),
+            ],
+            buffer_capacity: args.buffer_capacity,
+            supported_commands: vec!["volume".to_string(), "mute".to_string()],
+            fec: None,
+        }),
+        metadata_support: None,
+        artwork_support: None,
+        source_format: None,
+    }
+}
+
+/// Play scheduled buffers on the default device
+///
+/// Runs on its own thread because `CpalOutput` is not `Send`. The output is
+/// reopened whenever the stream format changes.
+fn spawn_playback(
+    scheduler: Arc<AudioScheduler>,
+    controls: Arc<Controls>,
+    audit: Option<AuditLog>,
+) {
+    std::thread::spawn(move || {
+        let mut output: Option<CpalOutput> = None;
+        loop {
+            let Some(buffer) = scheduler.next_ready() else {
+                std::thread::sleep(PLAYBACK_POLL);
+                continue;
+            };
+            if output.as_ref().map(|o| o.format()) != Some(&buffer.format) {
+                output = match CpalOutput::new(buffer.format.clone()) {
+                    Ok(out) => {
+                        tracing::info!(
+                            "Audio output opened: {}Hz {}ch",
+                            buffer.format.sample_rate,
+                            buffer.format.channels
+                        );
+                        Some(out)
+                    }
+                    Err(e) => {
+                        tracing::error!("Failed to open audio output: {}", e);
+                        None
+                    }
+                };
+            }
+            if let Some(out) = output.as_mut() {
+                if let Err(e) = out.write(&controls.scale(&buffer.samples)) {
+                    tracing::warn!("Output error: {}", e);
+                }
+            }
+            if let Some(audit) = &audit {
+                // Express the playout time on the server clock via the chunk's own timestamp
+                let now = Instant::now();
+                let offset = if now >= buffer.play_at {
+                    (now - buffer.play_at).as_micros() as i64
+                } else {
+                    -((buffer.play_at - now).as_micros() as i64)
+                };
+                audit.record(
+                    AuditStage::PlayedOut,
+                    buffer.timestamp,
+                    buffer.timestamp + offset,
+                );
+            }
+        }
+    });
+}
+
+/// Handle one connection until the server goes away
+async fn run_session(
+    client: ProtocolClient,
+    scheduler: &Arc<AudioScheduler>,
+    controls: &Controls,
+    audit: Option<&AuditLog>,
+) -> Result<(), sendspin::error::Error> {
+    let stats = client.stats();
+    let (mut message_rx, mut audio_rx, clock_sync, ws_tx) = client.split();
+    let ws_tx = Arc::new(ws_tx);
+
+    report_state(&ws_tx, controls, None).await?;
+
+    // Keep the clock in sync and report how much audio is buffered
+    let sync_task = {
+        let ws_tx = Arc::clone(&ws_tx);
+        let scheduler = Arc::clone(scheduler);
+        tokio::spawn(async move {
+            let mut sync_ticks = tokio::time::interval(INITIAL_SYNC_SPACING);
+            let mut stats_ticks = tokio::time::interval(STATS_INTERVAL);
+            let mut rounds = 0u32;
+            loop {
+                tokio::select! {
+                    _ = sync_ticks.tick() => {
+                        let client_transmitted = SystemClock.unix_micros();
+                        let time = Message::ClientTime(ClientTime { client_transmitted });
+                        if ws_tx.send_message(time).await.is_err() {
+                            break;
+                        }
+                        rounds += 1;
+                        if rounds == INITIAL_SYNC_ROUNDS {
+                            let next = tokio::time::Instant::now() + SYNC_INTERVAL;
+                            sync_ticks = tokio::time::interval_at(next, SYNC_INTERVAL);
+                        }
+                    }
+                    _ = stats_ticks.tick() => {
+                        let report = {
+                            let mut stats = stats.lock();
+                            stats.buffered_ms = Some(scheduler.buffered().as_millis() as u32);
+                            stats.clone()
+                        };
+                        if ws_tx.send_stats(&report).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        })
+    };
+
+    let mut stream: Option<Stream> = None;
+    let result = loop {
+        tokio::select! {
+            Some(msg) = message_rx.recv() => match msg {
+                Message::StreamStart(start) => {
+                    scheduler.clear();
+                    match Stream::start(&start.player) {
+                        Ok(started) => {
+                            tracing::info!(
+                                "Stream started: {} {}Hz {}ch {}bit",
+                                start.player.codec,
+                                start.player.sample_rate,
+                                start.player.channels,
+                                start.player.bit_depth
+                            );
+                            stream = Some(started);
+                        }
+                        Err(e) => {
+                            tracing::error!("Cannot play stream: {}", e);
+                            stream = None;
+                            ws_tx.send_player_state("error", None, None).await?;
+                        }
+                    }
+                }
+                Message::StreamClear(_) => {
+                    scheduler.clear();
+                    if let Some(stream) = stream.as_mut() {
+                        stream.concealer.reset();
+                        stream.expected_next = None;
+                    }
+                }
+                Message::StreamEnd(_) => {
+                    scheduler.clear();
+                    stream = None;
+                }
+                Message::ServerTime(time) => {
+                    let t4 = SystemClock.unix_micros();
+                    let mut sync = clock_sync.lock().await;
+                    sync.update(
+                        time.client_transmitted,
+                        time.server_received,
+                        time.server_transmitted,
+                        t4,
+                    );
+                }
+                Message::ServerCommand(command) => {
+                    if let Some(player) = command.player {
+                        controls.apply(&player);
+                        // Echo the command's trace, stamped with when it took effect
+                        let handled_at = clock_sync.lock().await.server_now_micros();
+                        let trace = command.trace.map(|trace| MessageTrace { handled_at, ..trace });
+                        report_state(&ws_tx, controls, trace).await?;
+                    }
+                }
+                other => tracing::debug!("Unhandled message: {:?}", other),
+            },
+            Some(chunk) = audio_rx.recv() => {
+                if let Some(stream) = stream.as_mut() {
+                    let sync = clock_sync.lock().await;
+                    if let (Some(audit), Some(now)) = (audit, sync.server_now_micros()) {
+                        audit.record(AuditStage::Received, chunk.timestamp, now);
+                    }
+                    stream.schedule(&chunk, &sync, scheduler);
+                }
+            }
+            else => break Ok(()),
+        }
+    };
+
+    sync_task.abort();
+    if let Some(audit) = audit {
+        audit.flush();
+    }
+    result
+}
+
+async fn report_state(
+    ws_tx: &WsSender,
+    controls: &Controls,
+    trace: Option<MessageTrace>,
+) -> Result<(), sendspin::error::Error> {
+    ws_tx
+        .send_traced_player_state(
+            "synchronized",
+            Some(controls.volume()),
+            Some(controls.muted()),
+            trace,
+        )
+        .await
+}
@@ -10,12 +10,22 @@ use sendspin::server::{SendspinServer, ServerArgs};
 struct Args {
     #[command(flatten)]
     server: ServerArgs,
+
+    /// Check the configuration and probe its sources, then exit without serving
+    #[arg(long)]
+    check_config: bool,
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let args = Args::parse();
 
+    if args.check_config {
+        let report = args.server.check_config();
+        println!("{}", report);
+        std::process::exit(if report.is_ok() { 0 } else { 1 });
+    }
+
     // Initialize tracing
     args.server.init_tracing();
 
@@ -26,7 +36,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let source = args.server.create_audio_source()?;
 
     // Create server configuration
-    let config = args.server.build_config();
+    let config = args.server.load_config()?;
 
     // Create and run server
     let server = SendspinServer::with_config(config).with_source(source);
@@ -30,13 +30,14 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     args.server.log_startup_info();
 
     // Create server configuration
-    let config = args.server.build_config();
+    let config = args.server.load_config()?;
 
     // Create server (takes ownership of config)
     let server = SendspinServer::with_config(config.clone()).with_source(source);
 
     let config = Arc::new(config);
     let client_manager = server.client_manager();
+    let group_stats = server.stats();
 
     // Create stats tracker (use actual sample rate from audio source)
     let stats = Arc::new(parking_lot::Mutex::new(ServerStats::new(
@@ -60,7 +61,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let mut terminal = sendspin::server::tui::setup_terminal()?;
 
     // Create TUI app
-    let mut tui_app = TuiApp::new(Arc::clone(&config), client_manager, Arc::clone(&stats));
+    let mut tui_app = TuiApp::new(Arc::clone(&config), client_manager, Arc::clone(&stats))
+        .with_group_stats(group_stats)
+        .with_group_manager(server.group_manager())
+        .with_streams(server.streams());
 
     // Spawn server in background
     let server_handle = tokio::spawn(async move { server.run().await });
@@ -0,0 +1,363 @@
+// ABOUTME: Conformance checks for Sendspin clients
+// ABOUTME: Accepts one client connection as a minimal server and exercises handshake, time sync, commands, and error paths
+
+use super::{message_type, Connection, Report};
+use crate::protocol::binary::BinaryFrame;
+use crate::protocol::messages::{
+    ClientHello, Message, PlayerCommand, ServerCommand, ServerHello, ServerTime, StreamEnd,
+    StreamPlayerConfig, StreamStart,
+};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tokio::net::{TcpListener, TcpStream};
+
+type ClientConnection = Connection<TcpStream>;
+
+/// Codecs defined by the spec for the player role
+const KNOWN_CODECS: &[&str] = &["opus", "flac", "pcm"];
+
+/// PCM chunks sent to the client after stream/start
+const CHUNKS_TO_SEND: usize = 10;
+
+/// Accept one client on `bind` and run the client conformance suite against it
+///
+/// Each check uses `timeout` as its deadline for the client's response. The
+/// listener accepts a connection on any WebSocket path.
+pub async fn check_client(bind: SocketAddr, timeout: Duration) -> Report {
+    let mut report = Report::new(format!("client on {}", bind));
+
+    let mut conn = match accept(bind).await {
+        Ok((conn, peer)) => {
+            report.target = format!("client at {}", peer);
+            Connection::new(conn, timeout)
+        }
+        Err(e) => {
+            report.fail("accept", e);
+            return report;
+        }
+    };
+    report.pass("accept");
+
+    let Some(hello) = check_hello(&mut conn, &mut report).await else {
+        return report;
+    };
+    let is_player = hello
+        .supported_roles
+        .iter()
+        .any(|r| r.starts_with("player@"));
+
+    let active_roles = negotiate_roles(&hello.supported_roles);
+    let server_hello = Message::ServerHello(ServerHello {
+        server_id: "sendspin-conformance".to_string(),
+        name: "Sendspin Conformance".to_string(),
+        version: 1,
+        active_roles,
+        connection_reason: Some("discovery".to_string()),
+    });
+    if let Err(e) = conn.send(&server_hello).await {
+        report.fail("handshake/server-hello", e);
+        return report;
+    }
+
+    if is_player {
+        check_initial_state(&mut conn, &mut report).await;
+    } else {
+        report.skip(
+            "state/initial-player-state",
+            "client does not support player@v1",
+        );
+    }
+    check_time_sync(&mut conn, &mut report).await;
+
+    if is_player {
+        check_stream(&mut conn, &hello, &mut report).await;
+        check_commands(&mut conn, &hello, &mut report).await;
+    } else {
+        report.skip("stream/accepted", "client does not support player@v1");
+        report.skip("command/volume", "client does not support player@v1");
+        report.skip("command/mute", "client does not support player@v1");
+    }
+    check_unknown_type(&mut conn, &mut report).await;
+
+    report
+}
+
+async fn accept(
+    bind: SocketAddr,
+) -> Result<(tokio_tungstenite::WebSocketStream<TcpStream>, SocketAddr), String> {
+    let listener = TcpListener::bind(bind)
+        .await
+        .map_err(|e| format!("failed to bind {}: {}", bind, e))?;
+    let (stream, peer) = listener.accept().await.map_err(|e| e.to_string())?;
+    let ws = tokio_tungstenite::accept_async(stream)
+        .await
+        .map_err(|e| format!("WebSocket handshake failed: {}", e))?;
+    Ok((ws, peer))
+}
+
+/// Activate the first supported version of each role family
+fn negotiate_roles(supported_roles: &[String]) -> Vec<String> {
+    let mut active: Vec<String> = Vec::new();
+    for role in supported_roles {
+        let family = role.split('@').next().unwrap_or(role);
+        if role.contains('@')
+            && !active
+                .iter()
+                .any(|r| r.starts_with(&format!("{}@", family)))
+        {
+            active.push(role.clone());
+        }
+    }
+    active
+}
+
+async fn check_hello(conn: &mut ClientConnection, report: &mut Report) -> Option<ClientHello> {
+    let first = match conn.recv_any().await {
+        Ok(text) => text,
+        Err(e) => {
+            report.fail("hello/first-message", e);
+            return None;
+        }
+    };
+
+    let hello = match serde_json::from_str::<Message>(&first) {
+        Ok(Message::ClientHello(hello)) => hello,
+        _ => {
+            report.fail(
+                "hello/first-message",
+                format!(
+                    "expected client/hello, got {}",
+                    message_type(&first).unwrap_or_else(|| "invalid JSON".to_string())
+                ),
+            );
+            return None;
+        }
+    };
+    report.pass("hello/first-message");
+    report.target = format!("{} ({})", hello.name, hello.client_id);
+
+    report.check(
+        "hello/version",
+        hello.version == 1,
+        format!("expected version 1, got {}", hello.version),
+    );
+    report.check(
+        "hello/client-id",
+        !hello.client_id.is_empty(),
+        "client_id is empty",
+    );
+
+    let unversioned: Vec<&String> = hello
+        .supported_roles
+        .iter()
+        .filter(|r| !r.starts_with('_') && !r.contains('@'))
+        .collect();
+    report.check(
+        "hello/versioned-roles",
+        unversioned.is_empty(),
+        format!("roles without a version: {:?}", unversioned),
+    );
+
+    let is_player = hello
+        .supported_roles
+        .iter()
+        .any(|r| r.starts_with("player@"));
+    match (&hello.player_support, is_player) {
+        (Some(support), true) => {
+            let unknown: Vec<&str> = support
+                .supported_formats
+                .iter()
+                .map(|f| f.codec.as_str())
+                .filter(|c| !KNOWN_CODECS.contains(c))
+                .collect();
+            let ok = !support.supported_formats.is_empty() && unknown.is_empty();
+            report.check(
+                "hello/player-support",
+                ok,
+                if support.supported_formats.is_empty() {
+                    "supported_formats is empty".to_string()
+                } else {
+                    format!("unknown codecs: {:?}", unknown)
+                },
+            );
+        }
+        (None, true) => report.fail(
+            "hello/player-support",
+            "player@v1 listed without player@v1_support",
+        ),
+        (_, false) => report.skip("hello/player-support", "client does not support player@v1"),
+    }
+
+    Some(hello)
+}
+
+async fn check_initial_state(conn: &mut ClientConnection, report: &mut Report) {
+    let name = "state/initial-player-state";
+    match conn.recv_type("client/state").await {
+        Ok(Message::ClientState(state)) => match state.player {
+            Some(player) => report.check(
+                name,
+                player.state == "synchronized" || player.state == "error",
+                format!("invalid player state '{}'", player.state),
+            ),
+            None => report.fail(name, "client/state has no player object"),
+        },
+        Ok(_) => unreachable!("recv_type returns the requested type"),
+        Err(e) => report.fail(name, format!("no client/state after server/hello: {}", e)),
+    }
+}
+
+async fn check_time_sync(conn: &mut ClientConnection, report: &mut Report) {
+    // Answer client/time manually the first time, then automatically afterwards
+    let start = Instant::now();
+    let result = conn.recv_type("client/time").await;
+    conn.server_clock = Some(start);
+
+    match result {
+        Ok(Message::ClientTime(time)) => {
+            let now = start.elapsed().as_micros() as i64;
+            let reply = Message::ServerTime(ServerTime {
+                client_transmitted: time.client_transmitted,
+                server_received: now,
+                server_transmitted: now,
+            });
+            let _ = conn.send(&reply).await;
+            report.pass("time-sync/client-time");
+        }
+        Ok(_) => unreachable!("recv_type returns the requested type"),
+        Err(e) => report.fail("time-sync/client-time", e),
+    }
+}
+
+async fn check_stream(conn: &mut ClientConnection, hello: &ClientHello, report: &mut Report) {
+    let name = "stream/accepted";
+    let Some(format) = hello
+        .player_support
+        .as_ref()
+        .and_then(|s| s.supported_formats.iter().find(|f| f.codec == "pcm"))
+    else {
+        report.skip(name, "client offers no PCM format");
+        return;
+    };
+
+    let start = Message::StreamStart(StreamStart {
+        player: StreamPlayerConfig {
+            codec: "pcm".to_string(),
+            sample_rate: format.sample_rate,
+            channels: format.channels,
+            bit_depth: format.bit_depth,
+            codec_header: None,
+            fec: None,
+            chunk_interval_ms: None,
+            buffer_ahead_ms: None,
+        },
+    });
+    if let Err(e) = conn.send(&start).await {
+        report.fail(name, e);
+        return;
+    }
+
+    // 20ms chunks of silence, starting 500ms in the future on the server clock
+    let frame_bytes = (format.bit_depth as usize / 8) * format.channels as usize;
+    let frames = format.sample_rate as usize / 50;
+    let clock = conn.server_clock.unwrap_or_else(Instant::now);
+    let first = clock.elapsed().as_micros() as i64 + 500_000;
+    let silence = vec![0u8; frames * frame_bytes];
+    for i in 0..CHUNKS_TO_SEND {
+        let frame = BinaryFrame::AudioChunk {
+            timestamp: first + i as i64 * 20_000,
+            payload: &silence,
+        };
+        if let Err(e) = conn.send_binary(frame.encode()).await {
+            report.fail(name, e);
+            return;
+        }
+    }
+
+    let end = Message::StreamEnd(StreamEnd { roles: None });
+    if let Err(e) = conn.send(&end).await {
+        report.fail(name, e);
+        return;
+    }
+
+    // Give the client a moment to reject the stream by disconnecting
+    report.check(
+        name,
+        !conn.closes_within(Duration::from_millis(500)).await,
+        "client disconnected after receiving audio",
+    );
+}
+
+async fn check_commands(conn: &mut ClientConnection, hello: &ClientHello, report: &mut Report) {
+    let commands = hello
+        .player_support
+        .as_ref()
+        .map(|s| s.supported_commands.clone())
+        .unwrap_or_default();
+
+    for (name, command, volume, mute) in [
+        ("command/volume", "volume", Some(42u8), None),
+        ("command/mute", "mute", None, Some(true)),
+    ] {
+        if !commands.iter().any(|c| c == command) {
+            report.skip(name, format!("'{}' not in supported_commands", command));
+            continue;
+        }
+
+        let msg = Message::ServerCommand(ServerCommand {
+            player: Some(PlayerCommand {
+                command: command.to_string(),
+                volume,
+                mute,
+            }),
+            trace: None,
+        });
+        if let Err(e) = conn.send(&msg).await {
+            report.fail(name, e);
+            continue;
+        }
+
+        let deadline = Instant::now() + conn.timeout;
+        let mut result = Err("no client/state reflecting the command".to_string());
+        while Instant::now() < deadline {
+            match conn.recv_type("client/state").await {
+                Ok(Message::ClientState(state)) => {
+                    let Some(player) = state.player else { continue };
+                    if volume.is_some_and(|v| player.volume == Some(v))
+                        || mute.is_some_and(|m| player.muted == Some(m))
+                    {
+                        result = Ok(());
+                        break;
+                    }
+                }
+                Ok(_) => unreachable!("recv_type returns the requested type"),
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+
+        match result {
+            Ok(()) => report.pass(name),
+            Err(e) => report.fail(name, e),
+        }
+    }
+}
+
+async fn check_unknown_type(conn: &mut ClientConnection, report: &mut Report) {
+    let name = "error/unknown-type-ignored";
+    if let Err(e) = conn
+        .send_raw(r#"{"type":"_conformance/unknown","payload":{}}"#)
+        .await
+    {
+        report.fail(name, e);
+        return;
+    }
+    let timeout = conn.timeout;
+    report.check(
+        name,
+        !conn.closes_within(timeout).await,
+        "client disconnected after an unknown message type",
+    );
+}
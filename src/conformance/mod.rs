@@ -0,0 +1,348 @@
+// ABOUTME: Protocol conformance test suite for Sendspin implementations
+// ABOUTME: Scripted checks run against third-party servers and clients with a pass/fail report
+
+mod client_checks;
+mod server_checks;
+
+pub use client_checks::check_client;
+pub use server_checks::check_server;
+
+use crate::protocol::messages::Message;
+use futures_util::{SinkExt, StreamExt};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use tokio_tungstenite::WebSocketStream;
+
+/// Outcome of a single conformance check
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "outcome", content = "detail", rename_all = "lowercase")]
+pub enum Outcome {
+    /// The implementation behaved as the spec requires
+    Pass,
+    /// The implementation violated the spec (with reason)
+    Fail(String),
+    /// The check did not apply or could not run (with reason)
+    Skip(String),
+}
+
+/// Result of a named conformance check
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckResult {
+    /// Check name (e.g., "handshake/server-hello")
+    pub name: String,
+    /// Check outcome
+    pub outcome: Outcome,
+}
+
+/// Conformance report for one implementation under test
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    /// Description of what was tested (URL or client name)
+    pub target: String,
+    /// Results in the order the checks ran
+    pub checks: Vec<CheckResult>,
+}
+
+impl Report {
+    /// Create an empty report for the given target
+    pub fn new(target: impl Into<String>) -> Self {
+        Self {
+            target: target.into(),
+            checks: Vec::new(),
+        }
+    }
+
+    /// Record a check outcome
+    pub fn record(&mut self, name: &str, outcome: Outcome) {
+        self.checks.push(CheckResult {
+            name: name.to_string(),
+            outcome,
+        });
+    }
+
+    /// Record a passing check
+    pub fn pass(&mut self, name: &str) {
+        self.record(name, Outcome::Pass);
+    }
+
+    /// Record a failing check
+    pub fn fail(&mut self, name: &str, reason: impl Into<String>) {
+        self.record(name, Outcome::Fail(reason.into()));
+    }
+
+    /// Record a skipped check
+    pub fn skip(&mut self, name: &str, reason: impl Into<String>) {
+        self.record(name, Outcome::Skip(reason.into()));
+    }
+
+    /// Record pass or fail depending on `ok`
+    pub fn check(&mut self, name: &str, ok: bool, reason: impl Into<String>) {
+        if ok {
+            self.pass(name);
+        } else {
+            self.fail(name, reason);
+        }
+    }
+
+    /// Number of passing checks
+    pub fn passed(&self) -> usize {
+        self.count(|o| matches!(o, Outcome::Pass))
+    }
+
+    /// Number of failing checks
+    pub fn failed(&self) -> usize {
+        self.count(|o| matches!(o, Outcome::Fail(_)))
+    }
+
+    /// Number of skipped checks
+    pub fn skipped(&self) -> usize {
+        self.count(|o| matches!(o, Outcome::Skip(_)))
+    }
+
+    /// True if no check failed
+    pub fn is_success(&self) -> bool {
+        self.failed() == 0
+    }
+
+    fn count(&self, f: impl Fn(&Outcome) -> bool) -> usize {
+        self.checks.iter().filter(|c| f(&c.outcome)).count()
+    }
+
+    /// Render the report as a human-readable table
+    pub fn render(&self) -> String {
+        let width = self.checks.iter().map(|c| c.name.len()).max().unwrap_or(0);
+
+        let mut out = format!("Conformance report for {}\n\n", self.target);
+        for check in &self.checks {
+            let (status, detail) = match &check.outcome {
+                Outcome::Pass => ("PASS", ""),
+                Outcome::Fail(reason) => ("FAIL", reason.as_str()),
+                Outcome::Skip(reason) => ("SKIP", reason.as_str()),
+            };
+            out.push_str(&format!("  {:<width$}  {}", check.name, status));
+            if !detail.is_empty() {
+                out.push_str(&format!("  ({})", detail));
+            }
+            out.push('\n');
+        }
+        out.push_str(&format!(
+            "\n{} passed, {} failed, {} skipped\n",
+            self.passed(),
+            self.failed(),
+            self.skipped()
+        ));
+        out
+    }
+}
+
+/// Event received from the peer while waiting for a message
+enum Received {
+    Text(String),
+    Binary(Vec<u8>),
+    Closed,
+}
+
+/// WebSocket connection wrapper with deadline-based receive helpers
+struct Connection<S> {
+    ws: WebSocketStream<S>,
+    timeout: Duration,
+    /// Binary frames received while waiting for text messages
+    binary: Vec<Vec<u8>>,
+    /// Text messages skipped while waiting for a different type
+    pending: VecDeque<String>,
+    /// When set, answer client/time automatically (we are acting as the server)
+    server_clock: Option<Instant>,
+    closed: bool,
+}
+
+impl<S> Connection<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn new(ws: WebSocketStream<S>, timeout: Duration) -> Self {
+        Self {
+            ws,
+            timeout,
+            binary: Vec::new(),
+            pending: VecDeque::new(),
+            server_clock: None,
+            closed: false,
+        }
+    }
+
+    async fn send(&mut self, msg: &Message) -> Result<(), String> {
+        let json = serde_json::to_string(msg).map_err(|e| e.to_string())?;
+        self.send_raw(&json).await
+    }
+
+    async fn send_raw(&mut self, text: &str) -> Result<(), String> {
+        self.ws
+            .send(WsMessage::Text(text.to_string()))
+            .await
+            .map_err(|e| format!("send failed: {}", e))
+    }
+
+    async fn send_binary(&mut self, data: Vec<u8>) -> Result<(), String> {
+        self.ws
+            .send(WsMessage::Binary(data))
+            .await
+            .map_err(|e| format!("send failed: {}", e))
+    }
+
+    /// Receive the next text/binary/close event before `deadline`
+    async fn next_event(&mut self, deadline: Instant) -> Result<Received, String> {
+        if self.closed {
+            return Ok(Received::Closed);
+        }
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let next = match tokio::time::timeout(remaining, self.ws.next()).await {
+                Ok(next) => next,
+                Err(_) => return Err("timed out".to_string()),
+            };
+            match next {
+                Some(Ok(WsMessage::Text(text))) => {
+                    if self.answer_time(&text).await {
+                        continue;
+                    }
+                    return Ok(Received::Text(text));
+                }
+                Some(Ok(WsMessage::Binary(data))) => return Ok(Received::Binary(data)),
+                Some(Ok(WsMessage::Close(_))) | None | Some(Err(_)) => {
+                    self.closed = true;
+                    return Ok(Received::Closed);
+                }
+                Some(Ok(_)) => continue,
+            }
+        }
+    }
+
+    /// Reply to client/time when acting as the server; returns true if handled
+    async fn answer_time(&mut self, text: &str) -> bool {
+        use crate::protocol::messages::ServerTime;
+
+        let Some(start) = self.server_clock else {
+            return false;
+        };
+        let Ok(Message::ClientTime(time)) = serde_json::from_str::<Message>(text) else {
+            return false;
+        };
+        let now = start.elapsed().as_micros() as i64;
+        let reply = Message::ServerTime(ServerTime {
+            client_transmitted: time.client_transmitted,
+            server_received: now,
+            server_transmitted: start.elapsed().as_micros() as i64,
+        });
+        let _ = self.send(&reply).await;
+        true
+    }
+
+    /// Wait for a text message with the given `type`, skipping others
+    async fn recv_type(&mut self, msg_type: &str) -> Result<Message, String> {
+        let parse = |text: &str| {
+            serde_json::from_str::<Message>(text)
+                .map_err(|e| format!("invalid {}: {}", msg_type, e))
+        };
+
+        let is_match = |text: &String| message_type(text).as_deref() == Some(msg_type);
+        if let Some(pos) = self.pending.iter().position(is_match) {
+            let text = self.pending.remove(pos).unwrap_or_default();
+            return parse(&text);
+        }
+
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            match self.next_event(deadline).await? {
+                Received::Text(text) => {
+                    if is_match(&text) {
+                        return parse(&text);
+                    }
+                    self.pending.push_back(text);
+                }
+                Received::Binary(data) => self.binary.push(data),
+                Received::Closed => return Err("connection closed".to_string()),
+            }
+        }
+    }
+
+    /// Wait for the next text message of any type
+    async fn recv_any(&mut self) -> Result<String, String> {
+        if let Some(text) = self.pending.pop_front() {
+            return Ok(text);
+        }
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            match self.next_event(deadline).await? {
+                Received::Text(text) => return Ok(text),
+                Received::Binary(data) => self.binary.push(data),
+                Received::Closed => return Err("connection closed".to_string()),
+            }
+        }
+    }
+
+    /// Wait until `count` binary frames have been received
+    async fn recv_binary(&mut self, count: usize) -> Result<Vec<Vec<u8>>, String> {
+        let deadline = Instant::now() + self.timeout;
+        while self.binary.len() < count {
+            match self.next_event(deadline).await? {
+                Received::Binary(data) => self.binary.push(data),
+                Received::Text(text) => self.pending.push_back(text),
+                Received::Closed => return Err("connection closed".to_string()),
+            }
+        }
+        Ok(self.binary.drain(..count).collect())
+    }
+
+    /// Returns true if the peer closes the connection within `wait`
+    async fn closes_within(&mut self, wait: Duration) -> bool {
+        let deadline = Instant::now() + wait;
+        loop {
+            match self.next_event(deadline).await {
+                Ok(Received::Closed) => return true,
+                Ok(_) => continue,
+                Err(_) => return false,
+            }
+        }
+    }
+}
+
+/// Extract the `type` field of a JSON message without parsing the payload
+fn message_type(text: &str) -> Option<String> {
+    let value: serde_json::Value = serde_json::from_str(text).ok()?;
+    value.get("type")?.as_str().map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_counts() {
+        let mut report = Report::new("ws://example");
+        report.pass("a");
+        report.fail("b", "broken");
+        report.skip("c", "n/a");
+        report.check("d", true, "unused");
+
+        assert_eq!(report.passed(), 2);
+        assert_eq!(report.failed(), 1);
+        assert_eq!(report.skipped(), 1);
+        assert!(!report.is_success());
+
+        let rendered = report.render();
+        assert!(rendered.contains("FAIL  (broken)"));
+        assert!(rendered.contains("2 passed, 1 failed, 1 skipped"));
+    }
+
+    #[test]
+    fn test_message_type() {
+        assert_eq!(
+            message_type(r#"{"type":"server/hello","payload":{}}"#).as_deref(),
+            Some("server/hello")
+        );
+        assert_eq!(message_type("not json"), None);
+    }
+}
@@ -0,0 +1,395 @@
+// ABOUTME: Conformance checks for Sendspin servers
+// ABOUTME: Connects as a player client and exercises handshake, time sync, streaming, and error paths
+
+use super::{Connection, Report};
+use crate::protocol::binary::BinaryFrame;
+use crate::protocol::messages::{
+    AudioFormatSpec, ClientGoodbye, ClientHello, ClientTime, DeviceInfo, Message,
+    PlayerFormatRequest, PlayerSupport, StreamPlayerConfig, StreamRequestFormat,
+};
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use tokio_tungstenite::MaybeTlsStream;
+
+type ServerConnection = Connection<MaybeTlsStream<TcpStream>>;
+
+/// Audio chunks to inspect for the streaming checks
+const CHUNKS_TO_INSPECT: usize = 10;
+
+/// Time sync round trips for the monotonicity check
+const TIME_SYNC_ROUNDS: usize = 5;
+
+/// Run the server conformance suite against the server at `url`
+///
+/// Each check uses `timeout` as its deadline for the server's response.
+pub async fn check_server(url: &str, timeout: Duration) -> Report {
+    let mut report = Report::new(url);
+
+    let mut conn = match connect(url, timeout).await {
+        Ok(conn) => conn,
+        Err(e) => {
+            report.fail("connect", e);
+            return report;
+        }
+    };
+    report.pass("connect");
+
+    if !check_handshake(&mut conn, &mut report).await {
+        return report;
+    }
+    check_time_sync(&mut conn, &mut report).await;
+    let stream = check_stream_start(&mut conn, &mut report).await;
+    check_audio_chunks(&mut conn, stream.as_ref(), &mut report).await;
+    check_request_format(&mut conn, &mut report).await;
+    check_malformed_input(&mut conn, &mut report).await;
+    check_goodbye(&mut conn, &mut report).await;
+
+    check_hello_required(url, timeout, &mut report).await;
+
+    report
+}
+
+async fn connect(url: &str, timeout: Duration) -> Result<ServerConnection, String> {
+    let (ws, _) = tokio::time::timeout(timeout, tokio_tungstenite::connect_async(url))
+        .await
+        .map_err(|_| "timed out".to_string())?
+        .map_err(|e| e.to_string())?;
+    Ok(Connection::new(ws, timeout))
+}
+
+/// Formats offered by the conformance client, in preference order
+fn offered_formats() -> Vec<AudioFormatSpec> {
+    vec![
+        AudioFormatSpec {
+            codec: "pcm".to_string(),
+            channels: 2,
+            sample_rate: 48000,
+            bit_depth: 24,
+        },
+        AudioFormatSpec {
+            codec: "pcm".to_string(),
+            channels: 2,
+            sample_rate: 48000,
+            bit_depth: 16,
+        },
+    ]
+}
+
+fn conformance_hello() -> ClientHello {
+    ClientHello {
+        client_id: format!("conformance-{}", uuid::Uuid::new_v4()),
+        name: "Sendspin Conformance".to_string(),
+        version: 1,
+        supported_roles: vec!["player@v1".to_string()],
+        device_info: DeviceInfo {
+            product_name: "sendspin-conformance".to_string(),
+            manufacturer: "Sendspin".to_string(),
+            software_version: env!("CARGO_PKG_VERSION").to_string(),
+        },
+        player_support: Some(PlayerSupport {
+            supported_formats: offered_formats(),
+            buffer_capacity: 1_048_576,
+            supported_commands: vec!["volume".to_string(), "mute".to_string()],
+            fec: None,
+        }),
+        metadata_support: None,
+        artwork_support: None,
+        source_format: None,
+    }
+}
+
+/// Returns false if the handshake failed and the remaining checks cannot run
+async fn check_handshake(conn: &mut ServerConnection, report: &mut Report) -> bool {
+    if let Err(e) = conn.send(&Message::ClientHello(conformance_hello())).await {
+        report.fail("handshake/server-hello", e);
+        return false;
+    }
+
+    let hello = match conn.recv_type("server/hello").await {
+        Ok(Message::ServerHello(hello)) => hello,
+        Ok(_) => unreachable!("recv_type returns the requested type"),
+        Err(e) => {
+            report.fail("handshake/server-hello", e);
+            return false;
+        }
+    };
+    report.pass("handshake/server-hello");
+
+    report.check(
+        "handshake/version",
+        hello.version == 1,
+        format!("expected version 1, got {}", hello.version),
+    );
+    report.check(
+        "handshake/server-id",
+        !hello.server_id.is_empty(),
+        "server_id is empty",
+    );
+    report.check(
+        "handshake/active-roles",
+        hello.active_roles.iter().any(|r| r == "player@v1"),
+        format!("player@v1 not activated: {:?}", hello.active_roles),
+    );
+
+    // Handshake step 3: report initial player state
+    let state = serde_json::json!({
+        "type": "client/state",
+        "payload": { "player": { "state": "synchronized", "volume": 100, "muted": false } }
+    });
+    if let Err(e) = conn.send_raw(&state.to_string()).await {
+        report.fail("handshake/client-state", e);
+        return false;
+    }
+
+    true
+}
+
+fn now_micros(start: Instant) -> i64 {
+    start.elapsed().as_micros() as i64
+}
+
+async fn time_round_trip(
+    conn: &mut ServerConnection,
+    client_transmitted: i64,
+) -> Result<(i64, i64, i64), String> {
+    conn.send(&Message::ClientTime(ClientTime { client_transmitted }))
+        .await?;
+    match conn.recv_type("server/time").await? {
+        Message::ServerTime(t) => Ok((
+            t.client_transmitted,
+            t.server_received,
+            t.server_transmitted,
+        )),
+        _ => unreachable!("recv_type returns the requested type"),
+    }
+}
+
+async fn check_time_sync(conn: &mut ServerConnection, report: &mut Report) {
+    let start = Instant::now();
+    let mut samples = Vec::new();
+    for _ in 0..TIME_SYNC_ROUNDS {
+        let sent = now_micros(start) + 1;
+        match time_round_trip(conn, sent).await {
+            Ok((echo, received, transmitted)) => samples.push((sent, echo, received, transmitted)),
+            Err(e) => {
+                report.fail("time-sync/response", e);
+                return;
+            }
+        }
+    }
+    report.pass("time-sync/response");
+
+    let bad_echo = samples.iter().find(|(sent, echo, _, _)| sent != echo);
+    report.check(
+        "time-sync/echo",
+        bad_echo.is_none(),
+        format!(
+            "client_transmitted not echoed: {:?}",
+            bad_echo.map(|(sent, echo, _, _)| (sent, echo))
+        ),
+    );
+
+    let bad_order = samples.iter().find(|(_, _, rx, tx)| tx < rx);
+    report.check(
+        "time-sync/ordering",
+        bad_order.is_none(),
+        "server_transmitted earlier than server_received",
+    );
+
+    let monotonic = samples.windows(2).all(|w| w[1].2 >= w[0].2);
+    report.check(
+        "time-sync/monotonic",
+        monotonic,
+        "server clock went backwards between round trips",
+    );
+}
+
+async fn check_stream_start(
+    conn: &mut ServerConnection,
+    report: &mut Report,
+) -> Option<StreamPlayerConfig> {
+    let start = match conn.recv_type("stream/start").await {
+        Ok(Message::StreamStart(start)) => start,
+        Ok(_) => unreachable!("recv_type returns the requested type"),
+        Err(e) => {
+            report.fail("negotiation/stream-start", e);
+            return None;
+        }
+    };
+    report.pass("negotiation/stream-start");
+
+    let player = start.player;
+    let offered = offered_formats().iter().any(|f| {
+        f.codec == player.codec
+            && f.channels == player.channels
+            && f.sample_rate == player.sample_rate
+            && f.bit_depth == player.bit_depth
+    });
+    report.check(
+        "negotiation/offered-format",
+        offered,
+        format!(
+            "server chose a format the client did not offer: {} {}Hz {}ch {}bit",
+            player.codec, player.sample_rate, player.channels, player.bit_depth
+        ),
+    );
+
+    Some(player)
+}
+
+async fn check_audio_chunks(
+    conn: &mut ServerConnection,
+    stream: Option<&StreamPlayerConfig>,
+    report: &mut Report,
+) {
+    let Some(stream) = stream else {
+        report.skip("stream/audio-chunks", "no stream/start received");
+        return;
+    };
+
+    let chunks = match conn.recv_binary(CHUNKS_TO_INSPECT).await {
+        Ok(chunks) => chunks,
+        Err(e) => {
+            report.fail("stream/audio-chunks", e);
+            return;
+        }
+    };
+    report.pass("stream/audio-chunks");
+
+    let frames: Vec<BinaryFrame> = chunks
+        .iter()
+        .filter_map(|c| BinaryFrame::decode(c).ok())
+        .filter(|f| matches!(f, BinaryFrame::AudioChunk { .. }))
+        .collect();
+    let bad_header = frames.len() != chunks.len();
+    report.check(
+        "stream/chunk-header",
+        !bad_header,
+        "binary frame is not a player audio chunk (type 4 + 8-byte timestamp)",
+    );
+    if bad_header {
+        return;
+    }
+
+    if stream.codec == "pcm" {
+        let frame_bytes = (stream.bit_depth as usize / 8) * stream.channels as usize;
+        let misaligned = frames
+            .iter()
+            .find(|f| frame_bytes == 0 || f.payload().len() % frame_bytes != 0);
+        report.check(
+            "stream/pcm-frame-alignment",
+            misaligned.is_none(),
+            format!("PCM payload is not a multiple of {} bytes", frame_bytes),
+        );
+    } else {
+        report.skip("stream/pcm-frame-alignment", "stream is not PCM");
+    }
+
+    let timestamps: Vec<i64> = frames.iter().map(BinaryFrame::timestamp).collect();
+    report.check(
+        "stream/timestamps-increasing",
+        timestamps.windows(2).all(|w| w[1] > w[0]),
+        format!("chunk timestamps not increasing: {:?}", timestamps),
+    );
+}
+
+async fn check_request_format(conn: &mut ServerConnection, report: &mut Report) {
+    let request = Message::StreamRequestFormat(StreamRequestFormat {
+        player: Some(PlayerFormatRequest {
+            codec: Some("pcm".to_string()),
+            channels: Some(2),
+            sample_rate: Some(48000),
+            bit_depth: Some(16),
+        }),
+        artwork: None,
+    });
+    if let Err(e) = conn.send(&request).await {
+        report.fail("negotiation/request-format", e);
+        return;
+    }
+
+    match conn.recv_type("stream/start").await {
+        Ok(Message::StreamStart(start)) => report.check(
+            "negotiation/request-format",
+            start.player.bit_depth == 16,
+            format!(
+                "stream/start after request has bit_depth {}",
+                start.player.bit_depth
+            ),
+        ),
+        Ok(_) => unreachable!("recv_type returns the requested type"),
+        Err(e) => report.fail(
+            "negotiation/request-format",
+            format!("no stream/start after stream/request-format: {}", e),
+        ),
+    }
+}
+
+async fn check_malformed_input(conn: &mut ServerConnection, report: &mut Report) {
+    let start = Instant::now();
+
+    let cases = [
+        ("error/invalid-json-ignored", "{not json".to_string()),
+        (
+            "error/unknown-type-ignored",
+            r#"{"type":"_conformance/unknown","payload":{}}"#.to_string(),
+        ),
+    ];
+
+    for (name, payload) in cases {
+        if let Err(e) = conn.send_raw(&payload).await {
+            report.fail(name, e);
+            continue;
+        }
+        // The server should ignore the message and keep answering time sync
+        let sent = now_micros(start) + 1;
+        match time_round_trip(conn, sent).await {
+            Ok(_) => report.pass(name),
+            Err(e) => report.fail(name, format!("server stopped responding: {}", e)),
+        }
+    }
+}
+
+async fn check_goodbye(conn: &mut ServerConnection, report: &mut Report) {
+    let goodbye = Message::ClientGoodbye(ClientGoodbye {
+        reason: "user_request".to_string(),
+    });
+    if let Err(e) = conn.send(&goodbye).await {
+        report.fail("goodbye/server-disconnects", e);
+        return;
+    }
+    let timeout = conn.timeout;
+    report.check(
+        "goodbye/server-disconnects",
+        conn.closes_within(timeout).await,
+        "server did not close the connection after client/goodbye",
+    );
+}
+
+/// Messages before client/hello must not be processed
+async fn check_hello_required(url: &str, timeout: Duration, report: &mut Report) {
+    let name = "error/hello-required";
+    let mut conn = match connect(url, timeout).await {
+        Ok(conn) => conn,
+        Err(e) => {
+            report.skip(name, format!("could not open second connection: {}", e));
+            return;
+        }
+    };
+
+    if let Err(e) = conn
+        .send(&Message::ClientTime(ClientTime {
+            client_transmitted: 1,
+        }))
+        .await
+    {
+        report.skip(name, e);
+        return;
+    }
+
+    match conn.recv_type("server/time").await {
+        Ok(_) => report.fail(name, "server answered client/time before client/hello"),
+        // Closing the connection or ignoring the message are both acceptable
+        Err(_) => report.pass(name),
+    }
+}
@@ -35,6 +35,8 @@
 
 /// Audio types and processing
 pub mod audio;
+/// Protocol conformance test suite for third-party implementations
+pub mod conformance;
 /// Protocol implementation for WebSocket communication
 pub mod protocol;
 /// Audio scheduler for timed playback
@@ -1,19 +1,30 @@
-use sendspin::protocol::client::ProtocolClient;
-use sendspin::protocol::messages::{AudioFormatSpec, ClientHello, DeviceInfo, PlayerSupport};
+use sendspin::protocol::client::discover;
+use sendspin::protocol::failover::ServerList;
+use sendspin::protocol::messages::{
+    AudioFormatSpec, ClientHello, DeviceInfo, Message, PlayerSupport,
+};
+use std::time::Duration;
 
 const DEFAULT_SERVER: &str = "ws://localhost:8927/sendspin";
 const DEFAULT_NAME: &str = "Sendspin-RS Client";
 
-fn parse_args() -> (String, String) {
-    let mut server = DEFAULT_SERVER.to_string();
+/// Delay before walking the server list again when no server is reachable
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// How long to browse mDNS for servers with `--discover`
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(3);
+
+fn parse_args() -> (Vec<String>, String, bool) {
+    let mut servers = Vec::new();
     let mut name = DEFAULT_NAME.to_string();
+    let mut discovery = false;
 
     let mut args = std::env::args().skip(1);
     while let Some(arg) = args.next() {
         match arg.as_str() {
             "--server" | "-s" => {
                 if let Some(value) = args.next() {
-                    server = value;
+                    servers.push(value);
                 }
             }
             "--name" | "-n" => {
@@ -21,6 +32,7 @@ fn parse_args() -> (String, String) {
                     name = value;
                 }
             }
+            "--discover" | "-d" => discovery = true,
             "--help" | "-h" => {
                 print_usage();
                 std::process::exit(0);
@@ -29,23 +41,43 @@ fn parse_args() -> (String, String) {
         }
     }
 
-    (server, name)
+    if servers.is_empty() && !discovery {
+        servers.push(DEFAULT_SERVER.to_string());
+    }
+
+    (servers, name, discovery)
+}
+
+/// Replace the list's discovered servers with the ones answering now
+async fn refresh_discovered(servers: &mut ServerList) {
+    match discover(DISCOVERY_TIMEOUT).await {
+        Ok(found) => {
+            servers.clear_discovered();
+            for server in &found {
+                println!("Discovered {} ({})", server.name, server.urls().join(", "));
+            }
+            servers.add_discovered(found.iter().flat_map(|server| server.urls()));
+        }
+        Err(e) => eprintln!("mDNS discovery failed: {e}"),
+    }
 }
 
 fn print_usage() {
     println!(
-        "Usage: sendspin [--server <url>] [--name <client name>]\n\
+        "Usage: sendspin [--server <url>]... [--discover] [--name <client name>]\n\
         \n\
-        Connect to a Sendspin server and perform the initial handshake.\n\
+        Connect to a Sendspin server and stay connected. Repeat --server to\n\
+        give a prioritized failover list; on disconnect the next server is tried.\n\
+        --discover finds servers on the local network via mDNS and tries them\n\
+        after any given with --server.\n\
+        Handoff requests from the server are followed.\n\
         Defaults: server={DEFAULT_SERVER}, name=\"{DEFAULT_NAME}\"."
     );
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let (server, name) = parse_args();
-
-    println!("Connecting to {server} as {name}...");
+    let (servers, name, discovery) = parse_args();
 
     let hello = ClientHello {
         client_id: uuid::Uuid::new_v4().to_string(),
@@ -75,13 +107,65 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             // Buffer capacity in bytes (per spec) - 200KB buffer
             buffer_capacity: 200_000,
             supported_commands: vec!["volume".to_string(), "mute".to_string()],
+            fec: None,
         }),
         metadata_support: None,
+        artwork_support: None,
+        source_format: None,
     };
 
-    let _client = ProtocolClient::connect(&server, hello).await?;
+    let mut servers = ServerList::new(servers);
+    if discovery {
+        refresh_discovered(&mut servers).await;
+    }
+    if servers.is_empty() {
+        return Err("no Sendspin server found via mDNS; pass --server <url>".into());
+    }
 
-    println!("Connected! Waiting for server hello...");
+    println!(
+        "Connecting as {name} (servers: {})...",
+        servers.failover_order().join(", ")
+    );
+    let mut client = servers.connect(&hello).await?;
+
+    loop {
+        let url = servers.current().map(|e| e.url.clone()).unwrap_or_default();
+        println!("Connected to {url}");
 
-    Ok(())
+        // Stay connected until the server goes away or hands us off
+        let (mut message_rx, mut audio_rx, _clock_sync, ws_tx) = client.split();
+        let handed_off = loop {
+            tokio::select! {
+                Some(msg) = message_rx.recv() => {
+                    if let Message::ServerHandoff(handoff) = msg {
+                        println!("Server requested handoff to {}", handoff.url);
+                        match servers.follow_handoff(&handoff, Some(&ws_tx), &hello).await {
+                            Ok(client) => break Some(client),
+                            Err(e) => eprintln!("Handoff failed ({e}), staying on {url}"),
+                        }
+                    }
+                }
+                Some(_) = audio_rx.recv() => {}
+                else => break None,
+            }
+        };
+        if let Some(next) = handed_off {
+            client = next;
+            continue;
+        }
+
+        println!("Disconnected from {url}, trying next server...");
+        client = loop {
+            if discovery {
+                refresh_discovered(&mut servers).await;
+            }
+            match servers.failover(Some(&ws_tx), &hello).await {
+                Ok(client) => break client,
+                Err(e) => {
+                    eprintln!("No server reachable ({e}), retrying...");
+                    tokio::time::sleep(RETRY_DELAY).await;
+                }
+            }
+        };
+    }
 }
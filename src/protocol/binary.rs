@@ -0,0 +1,164 @@
+// ABOUTME: Typed parsing and encoding of Sendspin binary frames
+// ABOUTME: Frames are [type: u8][timestamp: i64 BE][payload], with the type selecting the role
+
+use crate::error::Error;
+
+/// Length of the type byte plus timestamp that precede every payload
+pub const HEADER_LEN: usize = 9;
+
+/// Binary message type for player audio chunks
+pub const AUDIO_CHUNK: u8 = 4;
+
+/// First binary message type for artwork (one type per channel)
+pub const ARTWORK_BASE: u8 = 8;
+
+/// Number of artwork channels
+pub const ARTWORK_CHANNELS: u8 = 4;
+
+/// Binary message type for visualizer data
+pub const VISUALIZER: u8 = 16;
+
+/// Application-specific binary message type for FEC parity frames
+pub const FEC_PARITY: u8 = 192;
+
+/// Application-specific binary message type for segments of a large frame
+pub const TRANSFER_SEGMENT: u8 = 193;
+
+/// Application-specific binary message type for audio a `_source@v1` client
+/// pushes to the server
+pub const SOURCE_AUDIO: u8 = 196;
+
+/// A binary WebSocket frame, borrowing its payload from the frame bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryFrame<'a> {
+    /// Encoded audio for the player role
+    AudioChunk {
+        /// Server time in microseconds at which to play the first sample
+        timestamp: i64,
+        /// Encoded audio
+        payload: &'a [u8],
+    },
+    /// Image for one artwork channel
+    Artwork {
+        /// Artwork channel (0-3)
+        channel: u8,
+        /// Server time in microseconds at which to display the image
+        timestamp: i64,
+        /// Encoded image (empty clears the channel)
+        payload: &'a [u8],
+    },
+    /// Visualizer data
+    Visualizer {
+        /// Server time in microseconds the data applies to
+        timestamp: i64,
+        /// Visualizer payload
+        payload: &'a [u8],
+    },
+    /// XOR parity over a group of audio chunks (see [`crate::protocol::fec`])
+    Parity {
+        /// Timestamp of the first chunk in the group
+        timestamp: i64,
+        /// Encoded parity
+        payload: &'a [u8],
+    },
+    /// Part of a frame too large to send whole (see [`crate::protocol::transfer`])
+    Segment {
+        /// Timestamp of the frame being transferred
+        timestamp: i64,
+        /// Segment header and data
+        payload: &'a [u8],
+    },
+    /// Audio from a client with the `_source` role, in its `_source_format`
+    SourceAudio {
+        /// Timestamp in microseconds, as chosen by the client
+        timestamp: i64,
+        /// Encoded audio
+        payload: &'a [u8],
+    },
+}
+
+impl<'a> BinaryFrame<'a> {
+    /// Parse a frame, rejecting short frames and unknown message types
+    pub fn decode(frame: &'a [u8]) -> Result<Self, Error> {
+        if frame.len() < HEADER_LEN {
+            return Err(Error::Protocol(format!(
+                "Binary frame too short: {} bytes, header is {}",
+                frame.len(),
+                HEADER_LEN
+            )));
+        }
+
+        let kind = frame[0];
+        let mut timestamp = [0u8; 8];
+        timestamp.copy_from_slice(&frame[1..HEADER_LEN]);
+        let timestamp = i64::from_be_bytes(timestamp);
+        let payload = &frame[HEADER_LEN..];
+
+        match kind {
+            AUDIO_CHUNK => Ok(Self::AudioChunk { timestamp, payload }),
+            k if (ARTWORK_BASE..ARTWORK_BASE + ARTWORK_CHANNELS).contains(&k) => {
+                Ok(Self::Artwork {
+                    channel: k - ARTWORK_BASE,
+                    timestamp,
+                    payload,
+                })
+            }
+            VISUALIZER => Ok(Self::Visualizer { timestamp, payload }),
+            FEC_PARITY => Ok(Self::Parity { timestamp, payload }),
+            TRANSFER_SEGMENT => Ok(Self::Segment { timestamp, payload }),
+            SOURCE_AUDIO => Ok(Self::SourceAudio { timestamp, payload }),
+            k => Err(Error::Protocol(format!(
+                "Unknown binary message type {}",
+                k
+            ))),
+        }
+    }
+
+    /// Binary message type byte
+    ///
+    /// Artwork channels above 3 are not representable and encode as channel 3.
+    pub fn message_type(&self) -> u8 {
+        match self {
+            Self::AudioChunk { .. } => AUDIO_CHUNK,
+            Self::Artwork { channel, .. } => ARTWORK_BASE + (*channel).min(ARTWORK_CHANNELS - 1),
+            Self::Visualizer { .. } => VISUALIZER,
+            Self::Parity { .. } => FEC_PARITY,
+            Self::Segment { .. } => TRANSFER_SEGMENT,
+            Self::SourceAudio { .. } => SOURCE_AUDIO,
+        }
+    }
+
+    /// Timestamp in server microseconds
+    pub fn timestamp(&self) -> i64 {
+        match self {
+            Self::AudioChunk { timestamp, .. }
+            | Self::Artwork { timestamp, .. }
+            | Self::Visualizer { timestamp, .. }
+            | Self::Parity { timestamp, .. }
+            | Self::Segment { timestamp, .. }
+            | Self::SourceAudio { timestamp, .. } => *timestamp,
+        }
+    }
+
+    /// Payload following the header
+    pub fn payload(&self) -> &'a [u8] {
+        match self {
+            Self::AudioChunk { payload, .. }
+            | Self::Artwork { payload, .. }
+            | Self::Visualizer { payload, .. }
+            | Self::Parity { payload, .. }
+            | Self::Segment { payload, .. }
+            | Self::SourceAudio { payload, .. } => payload,
+        }
+    }
+
+    /// Serialize the frame for sending
+    pub fn encode(&self) -> Vec<u8> {
+        let payload = self.payload();
+        let mut frame = Vec::with_capacity(HEADER_LEN + payload.len());
+        frame.push(self.message_type());
+        frame.extend_from_slice(&self.timestamp().to_be_bytes());
+        frame.extend_from_slice(payload);
+        frame
+    }
+}
@@ -2,13 +2,23 @@
 // ABOUTME: Handles connection, message routing, and protocol state machine
 
 use crate::error::Error;
-use crate::protocol::messages::{ClientHello, Message};
+use crate::protocol::binary::BinaryFrame;
+use crate::protocol::fec::ParityDecoder;
+use crate::protocol::messages::{
+    ClientDiagnostics, ClientHello, DeviceInfo, Message, MessageTrace, ServerHello,
+};
+use crate::protocol::reorder::ReorderBuffer;
+use crate::protocol::stats::{ChunkTracker, ClientStats, StaleChunkFilter};
+use crate::protocol::transfer::Reassembler;
 use crate::sync::ClockSync;
 use futures_util::{
     stream::{SplitSink, SplitStream},
     SinkExt, StreamExt,
 };
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use std::net::IpAddr;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::net::TcpStream;
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 use tokio_tungstenite::{connect_async, tungstenite::Message as WsMessage};
@@ -41,6 +51,14 @@ impl WsSender {
         self.send_message(msg).await
     }
 
+    /// Close the WebSocket connection
+    pub async fn close(&self) -> Result<(), Error> {
+        let mut tx = self.tx.lock().await;
+        tx.close()
+            .await
+            .map_err(|e| Error::WebSocket(e.to_string()))
+    }
+
     /// Send stream/request-format to request a different audio format
     /// Per spec: used for adaptive streaming based on network conditions
     pub async fn request_player_format(
@@ -70,6 +88,18 @@ impl WsSender {
         state: &str,
         volume: Option<u8>,
         muted: Option<bool>,
+    ) -> Result<(), Error> {
+        self.send_traced_player_state(state, volume, muted, None)
+            .await
+    }
+
+    /// Send client/state echoing the `_trace` of the server/command it reflects
+    pub async fn send_traced_player_state(
+        &self,
+        state: &str,
+        volume: Option<u8>,
+        muted: Option<bool>,
+        trace: Option<MessageTrace>,
     ) -> Result<(), Error> {
         use crate::protocol::messages::{ClientState, PlayerState};
         let msg = Message::ClientState(ClientState {
@@ -78,9 +108,35 @@ impl WsSender {
                 volume,
                 muted,
             }),
+            stats: None,
+            trace,
         });
         self.send_message(msg).await
     }
+
+    /// Report client stream statistics to the server
+    /// Sent as the application-specific `_stats` object in client/state
+    pub async fn send_stats(&self, stats: &ClientStats) -> Result<(), Error> {
+        use crate::protocol::messages::ClientState;
+        let msg = Message::ClientState(ClientState {
+            player: None,
+            stats: Some(stats.clone()),
+            trace: None,
+        });
+        self.send_message(msg).await
+    }
+
+    /// Push a chunk of audio to the server, for clients with the `_source` role
+    ///
+    /// `payload` is in the `_source_format` declared in client/hello and is
+    /// sent as binary message type [`SOURCE_AUDIO`](crate::protocol::binary::SOURCE_AUDIO).
+    pub async fn send_audio(&self, timestamp: i64, payload: &[u8]) -> Result<(), Error> {
+        let frame = BinaryFrame::SourceAudio { timestamp, payload }.encode();
+        let mut tx = self.tx.lock().await;
+        tx.send(WsMessage::Binary(frame))
+            .await
+            .map_err(|e| Error::WebSocket(e.to_string()))
+    }
 }
 
 /// Audio chunk from server (binary frame)
@@ -95,22 +151,16 @@ pub struct AudioChunk {
 impl AudioChunk {
     /// Parse from WebSocket binary frame
     pub fn from_bytes(frame: &[u8]) -> Result<Self, Error> {
-        if frame.len() < 9 {
-            return Err(Error::Protocol("Audio chunk too short".to_string()));
-        }
-
-        // Per spec: Binary message type 4 for player role audio chunks
-        if frame[0] != 0x04 {
-            return Err(Error::Protocol("Invalid audio chunk type".to_string()));
+        match BinaryFrame::decode(frame)? {
+            BinaryFrame::AudioChunk { timestamp, payload } => Ok(Self {
+                timestamp,
+                data: Arc::from(payload),
+            }),
+            other => Err(Error::Protocol(format!(
+                "Expected audio chunk, got binary message type {}",
+                other.message_type()
+            ))),
         }
-
-        let timestamp = i64::from_be_bytes([
-            frame[1], frame[2], frame[3], frame[4], frame[5], frame[6], frame[7], frame[8],
-        ]);
-
-        let data = Arc::from(&frame[9..]);
-
-        Ok(Self { timestamp, data })
     }
 }
 
@@ -119,13 +169,28 @@ pub struct ProtocolClient {
     ws_tx:
         Arc<tokio::sync::Mutex<SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, WsMessage>>>,
     audio_rx: UnboundedReceiver<AudioChunk>,
+    frame_rx: Option<UnboundedReceiver<Vec<u8>>>,
     message_rx: UnboundedReceiver<Message>,
     clock_sync: Arc<tokio::sync::Mutex<ClockSync>>,
+    stats: Arc<parking_lot::Mutex<ClientStats>>,
+    server_hello: ServerHello,
 }
 
 impl ProtocolClient {
     /// Connect to Sendspin server
     pub async fn connect(url: &str, hello: ClientHello) -> Result<Self, Error> {
+        Self::connect_with_reorder_window(url, hello, 0).await
+    }
+
+    /// Connect to Sendspin server, reordering audio chunks within a window
+    ///
+    /// Up to `reorder_window` chunks are held back and released in timestamp
+    /// order before reaching the scheduler. Use 0 for ordered transports.
+    pub async fn connect_with_reorder_window(
+        url: &str,
+        hello: ClientHello,
+        reorder_window: usize,
+    ) -> Result<Self, Error> {
         // Connect WebSocket
         let (ws_stream, _) = connect_async(url)
             .await
@@ -134,6 +199,7 @@ impl ProtocolClient {
         let (mut write, read) = ws_stream.split();
 
         // Send client hello
+        let device_info = hello.device_info.clone();
         let hello_msg = Message::ClientHello(hello);
         let hello_json =
             serde_json::to_string(&hello_msg).map_err(|e| Error::Protocol(e.to_string()))?;
@@ -149,7 +215,7 @@ impl ProtocolClient {
         let mut read_temp = read;
         log::debug!("Waiting for server/hello...");
 
-        loop {
+        let server_hello = loop {
             if let Some(result) = read_temp.next().await {
                 match result {
                     Ok(WsMessage::Text(text)) => {
@@ -162,11 +228,23 @@ impl ProtocolClient {
                         match msg {
                             Message::ServerHello(server_hello) => {
                                 log::info!(
-                                    "Connected to server: {} ({})",
+                                    "Connected to server: {} ({}), reason: {:?}",
                                     server_hello.name,
-                                    server_hello.server_id
+                                    server_hello.server_id,
+                                    server_hello.connection_reason
+                                );
+                                break server_hello; // Exit loop, we got the server/hello
+                            }
+                            Message::ServerGoodbye(goodbye) => {
+                                log::error!(
+                                    "Server refused connection ({}): {}",
+                                    goodbye.reason,
+                                    goodbye.message
                                 );
-                                break; // Exit loop, we got the server/hello
+                                return Err(Error::Connection(format!(
+                                    "server refused connection ({}): {}",
+                                    goodbye.reason, goodbye.message
+                                )));
                             }
                             _ => {
                                 log::error!("Expected server/hello, got: {:?}", msg);
@@ -199,50 +277,156 @@ impl ProtocolClient {
                 log::error!("Connection closed before receiving server/hello");
                 return Err(Error::Connection("No server hello received".to_string()));
             }
-        }
+        };
 
         // Create channels for message routing
         let (audio_tx, audio_rx) = unbounded_channel();
+        let (frame_tx, frame_rx) = unbounded_channel();
         let (message_tx, message_rx) = unbounded_channel();
 
         let clock_sync = Arc::new(tokio::sync::Mutex::new(ClockSync::new()));
+        let stats = Arc::new(parking_lot::Mutex::new(ClientStats::default()));
 
         // Spawn message router task
+        let ws_tx = Arc::new(tokio::sync::Mutex::new(write));
+        let diagnostics = WsSender {
+            tx: Arc::clone(&ws_tx),
+        };
         let clock_sync_clone = Arc::clone(&clock_sync);
+        let stats_clone = Arc::clone(&stats);
         tokio::spawn(async move {
-            Self::message_router(read_temp, audio_tx, message_tx, clock_sync_clone).await;
+            Self::message_router(
+                read_temp,
+                (audio_tx, frame_tx),
+                message_tx,
+                clock_sync_clone,
+                stats_clone,
+                ReorderBuffer::new(reorder_window),
+                (diagnostics, device_info),
+            )
+            .await;
         });
 
         Ok(Self {
-            ws_tx: Arc::new(tokio::sync::Mutex::new(write)),
+            ws_tx,
             audio_rx,
+            frame_rx: Some(frame_rx),
             message_rx,
             clock_sync,
+            stats,
+            server_hello,
         })
     }
 
     async fn message_router(
         mut read: SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>,
-        audio_tx: UnboundedSender<AudioChunk>,
+        (audio_tx, frame_tx): (UnboundedSender<AudioChunk>, UnboundedSender<Vec<u8>>),
         message_tx: UnboundedSender<Message>,
-        _clock_sync: Arc<tokio::sync::Mutex<ClockSync>>,
+        clock_sync: Arc<tokio::sync::Mutex<ClockSync>>,
+        stats: Arc<parking_lot::Mutex<ClientStats>>,
+        mut reorder: ReorderBuffer,
+        (diagnostics, device_info): (WsSender, DeviceInfo),
     ) {
+        let mut tracker = ChunkTracker::default();
+        let mut stale_filter = StaleChunkFilter::default();
+        let mut fec: Option<ParityDecoder> = None;
+        let mut transfers = Reassembler::default();
+
         while let Some(msg) = read.next().await {
             match msg {
                 Ok(WsMessage::Binary(data)) => {
                     log::debug!("Received binary frame ({} bytes)", data.len());
-                    match AudioChunk::from_bytes(&data) {
-                        Ok(chunk) => {
-                            log::debug!(
-                                "Parsed audio chunk: timestamp={}, data_len={}",
-                                chunk.timestamp,
-                                chunk.data.len()
-                            );
-                            let _ = audio_tx.send(chunk);
+                    // Large frames arrive in segments; carry on once the last one is in
+                    let data = match BinaryFrame::decode(&data) {
+                        Ok(BinaryFrame::Segment { timestamp, payload }) => {
+                            match transfers.push(timestamp, payload, Instant::now()) {
+                                Ok(Some(frame)) => frame,
+                                Ok(None) => continue,
+                                Err(e) => {
+                                    log::warn!("Invalid transfer segment: {}", e);
+                                    continue;
+                                }
+                            }
+                        }
+                        _ => data,
+                    };
+                    let (chunk, recovered) = match BinaryFrame::decode(&data) {
+                        Ok(BinaryFrame::AudioChunk { timestamp, payload }) => (
+                            AudioChunk {
+                                timestamp,
+                                data: Arc::from(payload),
+                            },
+                            false,
+                        ),
+                        Ok(BinaryFrame::Parity { payload, .. }) => {
+                            let Some(decoder) = fec.as_mut() else {
+                                continue;
+                            };
+                            match decoder.recover(payload) {
+                                Ok(Some(chunk)) => {
+                                    log::debug!(
+                                        "Recovered lost chunk {} from parity",
+                                        chunk.timestamp
+                                    );
+                                    stats.lock().fec_recovered += 1;
+                                    (chunk, true)
+                                }
+                                Ok(None) => continue,
+                                Err(e) => {
+                                    log::warn!("Invalid parity frame: {}", e);
+                                    continue;
+                                }
+                            }
+                        }
+                        Ok(other) => {
+                            if frame_tx.send(data.clone()).is_err() {
+                                log::debug!(
+                                    "Ignoring binary message type {}",
+                                    other.message_type()
+                                );
+                            }
+                            continue;
                         }
                         Err(e) => {
                             log::warn!("Failed to parse audio chunk: {}", e);
+                            continue;
+                        }
+                    };
+                    log::debug!(
+                        "Parsed audio chunk: timestamp={}, data_len={}",
+                        chunk.timestamp,
+                        chunk.data.len()
+                    );
+                    if let (Some(decoder), false) = (fec.as_mut(), recovered) {
+                        decoder.observe(&chunk);
+                    }
+
+                    let ready = reorder.push(chunk, &mut stats.lock());
+                    for chunk in ready {
+                        if let Some(delta) = tracker.observe(&chunk, &mut stats.lock()) {
+                            log::warn!(
+                                "Chunk discontinuity at timestamp {}: {}µs",
+                                chunk.timestamp,
+                                delta
+                            );
                         }
+
+                        // Drop chunks whose play time has passed before they reach the scheduler
+                        let server_now = clock_sync.lock().await.server_now_micros();
+                        let was_dropping = stale_filter.is_dropping();
+                        if let Some(late_by) =
+                            stale_filter.check(&chunk, server_now, &mut stats.lock())
+                        {
+                            if !was_dropping {
+                                log::warn!(
+                                    "Dropping stale audio chunks ({}µs late at timestamp {})",
+                                    late_by,
+                                    chunk.timestamp
+                                );
+                            }
+                            continue;
+                        }
+                        let _ = audio_tx.send(chunk);
                     }
                 }
                 Ok(WsMessage::Text(text)) => {
@@ -250,6 +434,34 @@ impl ProtocolClient {
                     match serde_json::from_str::<Message>(&text) {
                         Ok(msg) => {
                             log::debug!("Parsed message: {:?}", msg);
+                            match &msg {
+                                Message::StreamStart(start) => {
+                                    tracker.set_format(&start.player);
+                                    fec = start.player.fec.map(ParityDecoder::new);
+                                }
+                                Message::StreamClear(_) | Message::StreamEnd(_) => {
+                                    tracker.reset();
+                                    reorder.reset();
+                                    if let Some(decoder) = fec.as_mut() {
+                                        decoder.reset();
+                                    }
+                                }
+                                Message::DiagnosticsRequest(request) => {
+                                    // Answered here so the server gets a snapshot
+                                    // even if the application ignores the request
+                                    let sync_quality = clock_sync.lock().await.quality();
+                                    let reply = Message::ClientDiagnostics(ClientDiagnostics {
+                                        request_id: request.request_id.clone(),
+                                        stats: stats.lock().clone(),
+                                        sync_quality: Some(sync_quality),
+                                        device_info: Some(device_info.clone()),
+                                    });
+                                    if let Err(e) = diagnostics.send_message(reply).await {
+                                        log::warn!("Failed to send diagnostics: {}", e);
+                                    }
+                                }
+                                _ => {}
+                            }
                             let _ = message_tx.send(msg);
                         }
                         Err(e) => {
@@ -278,6 +490,14 @@ impl ProtocolClient {
         self.audio_rx.recv().await
     }
 
+    /// Take the receiver for binary frames other than audio (artwork, visualizer)
+    ///
+    /// Frames sent in segments arrive reassembled. Take this before
+    /// `split()`; once the receiver is dropped such frames are ignored.
+    pub fn take_frames(&mut self) -> Option<UnboundedReceiver<Vec<u8>>> {
+        self.frame_rx.take()
+    }
+
     /// Receive next protocol message
     pub async fn recv_message(&mut self) -> Option<Message> {
         self.message_rx.recv().await
@@ -333,6 +553,18 @@ impl ProtocolClient {
         state: &str,
         volume: Option<u8>,
         muted: Option<bool>,
+    ) -> Result<(), Error> {
+        self.send_traced_player_state(state, volume, muted, None)
+            .await
+    }
+
+    /// Send client/state echoing the `_trace` of the server/command it reflects
+    pub async fn send_traced_player_state(
+        &self,
+        state: &str,
+        volume: Option<u8>,
+        muted: Option<bool>,
+        trace: Option<MessageTrace>,
     ) -> Result<(), Error> {
         use crate::protocol::messages::{ClientState, PlayerState};
         let msg = Message::ClientState(ClientState {
@@ -341,10 +573,38 @@ impl ProtocolClient {
                 volume,
                 muted,
             }),
+            stats: None,
+            trace,
         });
         self.send_message(&msg).await
     }
 
+    /// Report client stream statistics to the server
+    /// Sent as the application-specific `_stats` object in client/state
+    pub async fn send_stats(&self, stats: &ClientStats) -> Result<(), Error> {
+        use crate::protocol::messages::ClientState;
+        let msg = Message::ClientState(ClientState {
+            player: None,
+            stats: Some(stats.clone()),
+            trace: None,
+        });
+        self.send_message(&msg).await
+    }
+
+    /// Get reference to the client stream statistics
+    ///
+    /// Updated by the message router as chunks arrive; grab this before `split()`.
+    pub fn stats(&self) -> Arc<parking_lot::Mutex<ClientStats>> {
+        Arc::clone(&self.stats)
+    }
+
+    /// The server/hello received during the handshake
+    ///
+    /// Includes the server's `connection_reason`; clone it before `split()`.
+    pub fn server_hello(&self) -> &ServerHello {
+        &self.server_hello
+    }
+
     /// Get reference to clock sync
     pub fn clock_sync(&self) -> Arc<tokio::sync::Mutex<ClockSync>> {
         Arc::clone(&self.clock_sync)
@@ -370,3 +630,144 @@ impl ProtocolClient {
         )
     }
 }
+
+/// Service type Sendspin servers advertise for client-initiated connections
+pub const SERVER_SERVICE_TYPE: &str = "_sendspin-server._tcp.local.";
+
+/// WebSocket path assumed when a server does not advertise one
+const DEFAULT_PATH: &str = "/sendspin";
+
+/// A Sendspin server found via mDNS
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveredServer {
+    /// Server name (the `name` TXT record, or the service instance name)
+    pub name: String,
+    /// Server ID, when the server advertises one
+    pub server_id: Option<String>,
+    /// Addresses the server answered from, IPv4 first
+    pub addresses: Vec<IpAddr>,
+    /// Port the server listens on
+    pub port: u16,
+    /// WebSocket endpoint path
+    pub path: String,
+}
+
+impl DiscoveredServer {
+    fn from_service(info: &ServiceInfo) -> Self {
+        let instance = info
+            .get_fullname()
+            .strip_suffix(SERVER_SERVICE_TYPE)
+            .unwrap_or(info.get_fullname())
+            .trim_end_matches('.');
+        let mut addresses: Vec<IpAddr> = info.get_addresses().iter().copied().collect();
+        addresses.sort_by_key(|ip| (ip.is_ipv6(), *ip));
+        let path = info.get_property_val_str("path").unwrap_or(DEFAULT_PATH);
+        Self {
+            name: info
+                .get_property_val_str("name")
+                .unwrap_or(instance)
+                .to_string(),
+            server_id: info.get_property_val_str("server_id").map(str::to_string),
+            addresses,
+            port: info.get_port(),
+            path: if path.starts_with('/') {
+                path.to_string()
+            } else {
+                format!("/{}", path)
+            },
+        }
+    }
+
+    /// WebSocket URLs for each address, in the order to try them
+    pub fn urls(&self) -> Vec<String> {
+        self.addresses
+            .iter()
+            .map(|ip| {
+                let addr = std::net::SocketAddr::new(*ip, self.port);
+                format!("ws://{}{}", addr, self.path)
+            })
+            .collect()
+    }
+}
+
+/// Browse the local network for Sendspin servers for `timeout`
+///
+/// Returns every server that resolved, in the order they answered.
+pub async fn discover(timeout: Duration) -> Result<Vec<DiscoveredServer>, Error> {
+    let mut browser = Browser::start(timeout)?;
+    let mut servers: Vec<DiscoveredServer> = Vec::new();
+    while let Some(server) = browser.next().await {
+        if !servers.contains(&server) {
+            servers.push(server);
+        }
+    }
+    Ok(servers)
+}
+
+/// Connect to the first Sendspin server that answers on the local network
+///
+/// Servers are tried as they resolve; gives up after `timeout` without a
+/// successful connection.
+pub async fn connect_discovered(
+    hello: ClientHello,
+    timeout: Duration,
+) -> Result<ProtocolClient, Error> {
+    let mut browser = Browser::start(timeout)?;
+    while let Some(server) = browser.next().await {
+        for url in server.urls() {
+            match ProtocolClient::connect(&url, hello.clone()).await {
+                Ok(client) => {
+                    log::info!("Connected to discovered server {} at {}", server.name, url);
+                    return Ok(client);
+                }
+                Err(e) => log::debug!("Discovered server {} unreachable: {}", url, e),
+            }
+        }
+    }
+    Err(Error::Connection(
+        "no Sendspin server found via mDNS".to_string(),
+    ))
+}
+
+/// An mDNS browse for servers that ends at a deadline
+struct Browser {
+    daemon: ServiceDaemon,
+    events: mdns_sd::Receiver<ServiceEvent>,
+    deadline: tokio::time::Instant,
+}
+
+impl Browser {
+    fn start(timeout: Duration) -> Result<Self, Error> {
+        let daemon = ServiceDaemon::new().map_err(|e| Error::Connection(e.to_string()))?;
+        let events = daemon
+            .browse(SERVER_SERVICE_TYPE)
+            .map_err(|e| Error::Connection(e.to_string()))?;
+        Ok(Self {
+            daemon,
+            events,
+            deadline: tokio::time::Instant::now() + timeout,
+        })
+    }
+
+    /// Next server to resolve, or None once the deadline passes
+    async fn next(&mut self) -> Option<DiscoveredServer> {
+        loop {
+            let event = tokio::time::timeout_at(self.deadline, self.events.recv_async())
+                .await
+                .ok()?
+                .ok()?;
+            if let ServiceEvent::ServiceResolved(info) = event {
+                let server = DiscoveredServer::from_service(&info);
+                log::debug!("Discovered server {:?}", server);
+                return Some(server);
+            }
+        }
+    }
+}
+
+impl Drop for Browser {
+    fn drop(&mut self) {
+        let _ = self.daemon.stop_browse(SERVER_SERVICE_TYPE);
+        let _ = self.daemon.shutdown();
+    }
+}
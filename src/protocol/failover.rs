@@ -0,0 +1,273 @@
+// ABOUTME: Multi-server failover for the client
+// ABOUTME: Prioritized server URL list that is walked on disconnect, with optional discovered entries
+
+use crate::error::Error;
+use crate::protocol::client::{ProtocolClient, WsSender};
+use crate::protocol::messages::{ClientHello, ServerHandoff, ServerHello};
+use std::time::Duration;
+
+/// Default time allowed for each connection attempt
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where a server list entry came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServerOrigin {
+    /// Explicitly configured by the user
+    Configured,
+    /// Found via discovery (e.g., mDNS); always ranked after configured entries
+    Discovered,
+    /// Added by a server-initiated handoff
+    Handoff,
+}
+
+/// A server the client may connect to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerEntry {
+    /// WebSocket URL of the server
+    pub url: String,
+    /// Where the entry came from
+    pub origin: ServerOrigin,
+}
+
+/// Prioritized list of servers for client failover
+///
+/// `connect` tries servers in priority order. When the active connection drops,
+/// `failover` walks the list starting after the current server (wrapping around,
+/// with the current server tried last) and tells the old server the client moved
+/// on with `client/goodbye` reason `another_server`.
+#[derive(Debug, Clone)]
+pub struct ServerList {
+    entries: Vec<ServerEntry>,
+    current: Option<usize>,
+    connect_timeout: Duration,
+}
+
+impl ServerList {
+    /// Create a list from configured URLs in priority order (duplicates are dropped)
+    pub fn new<I, S>(urls: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let mut list = Self {
+            entries: Vec::new(),
+            current: None,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+        };
+        for url in urls {
+            list.push(url.into(), ServerOrigin::Configured);
+        }
+        list
+    }
+
+    /// Set the time allowed for each connection attempt
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    fn push(&mut self, url: String, origin: ServerOrigin) {
+        if !self.entries.iter().any(|e| e.url == url) {
+            self.entries.push(ServerEntry { url, origin });
+        }
+    }
+
+    /// Append discovered servers after the configured ones
+    ///
+    /// URLs already in the list are ignored.
+    pub fn add_discovered<I, S>(&mut self, urls: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        for url in urls {
+            self.push(url.into(), ServerOrigin::Discovered);
+        }
+    }
+
+    /// Remove all discovered entries
+    pub fn clear_discovered(&mut self) {
+        let current_url = self.current().map(|e| e.url.clone());
+        self.entries
+            .retain(|e| e.origin != ServerOrigin::Discovered);
+        self.current = current_url.and_then(|url| self.entries.iter().position(|e| e.url == url));
+    }
+
+    /// All entries in priority order
+    pub fn entries(&self) -> &[ServerEntry] {
+        &self.entries
+    }
+
+    /// The server of the active connection, if any
+    pub fn current(&self) -> Option<&ServerEntry> {
+        self.current.map(|i| &self.entries[i])
+    }
+
+    /// Number of servers in the list
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// True if the list has no servers
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// URLs in the order `failover` will try them
+    pub fn failover_order(&self) -> Vec<&str> {
+        self.failover_indices()
+            .into_iter()
+            .map(|i| self.entries[i].url.as_str())
+            .collect()
+    }
+
+    fn failover_indices(&self) -> Vec<usize> {
+        let len = self.entries.len();
+        let start = self.current.map(|i| i + 1).unwrap_or(0);
+        (0..len).map(|offset| (start + offset) % len).collect()
+    }
+
+    /// Connect to the highest-priority reachable server
+    pub async fn connect(&mut self, hello: &ClientHello) -> Result<ProtocolClient, Error> {
+        let order: Vec<usize> = (0..self.entries.len()).collect();
+        self.connect_in_order(&order, hello).await
+    }
+
+    /// Move to the next reachable server after the current one
+    ///
+    /// If `old` is given and the client ends up on a different server, the old
+    /// server receives `client/goodbye` with reason `another_server` (best effort,
+    /// since the old connection may already be gone).
+    pub async fn failover(
+        &mut self,
+        old: Option<&WsSender>,
+        hello: &ClientHello,
+    ) -> Result<ProtocolClient, Error> {
+        let previous = self.current;
+        let order = self.failover_indices();
+        let client = self.connect_in_order(&order, hello).await?;
+
+        if let Some(old) = old {
+            if previous != self.current {
+                leave(old).await;
+            }
+        }
+
+        Ok(client)
+    }
+
+    /// Follow a server-initiated handoff to another server
+    ///
+    /// The target is added to the list if it is not already known. On success
+    /// the old server (if given) receives `client/goodbye` with reason
+    /// `another_server` and the connection is closed. On failure the current
+    /// server is unchanged, so the client can stay on the old connection.
+    pub async fn follow_handoff(
+        &mut self,
+        handoff: &ServerHandoff,
+        old: Option<&WsSender>,
+        hello: &ClientHello,
+    ) -> Result<ProtocolClient, Error> {
+        let previous = self.current;
+        self.push(handoff.url.clone(), ServerOrigin::Handoff);
+        let index = self
+            .entries
+            .iter()
+            .position(|e| e.url == handoff.url)
+            .unwrap_or_default();
+
+        let client = match self.connect_in_order(&[index], hello).await {
+            Ok(client) => client,
+            Err(e) => {
+                self.current = previous;
+                return Err(e);
+            }
+        };
+
+        if let Some(expected) = &handoff.server_id {
+            let actual = &client.server_hello().server_id;
+            if actual != expected {
+                self.current = previous;
+                return Err(Error::Protocol(format!(
+                    "Handoff target is {} but expected {}",
+                    actual, expected
+                )));
+            }
+        }
+
+        log::info!("Followed handoff to {}", handoff.url);
+        if let Some(old) = old {
+            leave(old).await;
+        }
+        Ok(client)
+    }
+
+    async fn connect_in_order(
+        &mut self,
+        order: &[usize],
+        hello: &ClientHello,
+    ) -> Result<ProtocolClient, Error> {
+        if order.is_empty() {
+            return Err(Error::Connection("No servers configured".to_string()));
+        }
+
+        let mut last_error = None;
+        for &index in order {
+            let url = &self.entries[index].url;
+            log::info!("Connecting to {}", url);
+            let attempt = tokio::time::timeout(
+                self.connect_timeout,
+                ProtocolClient::connect(url, hello.clone()),
+            )
+            .await
+            .unwrap_or_else(|_| Err(Error::Connection("Connection timed out".to_string())));
+
+            match attempt {
+                Ok(client) => {
+                    self.current = Some(index);
+                    return Ok(client);
+                }
+                Err(e) => {
+                    log::warn!("Failed to connect to {}: {}", url, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        self.current = None;
+        Err(last_error.unwrap_or_else(|| Error::Connection("No servers reachable".to_string())))
+    }
+}
+
+/// Say goodbye to a server we are switching away from (best effort)
+async fn leave(old: &WsSender) {
+    if let Err(e) = old.send_goodbye("another_server").await {
+        log::debug!("Could not send goodbye to previous server: {}", e);
+    }
+    let _ = old.close().await;
+}
+
+/// Decide whether to switch to a newly connected server
+///
+/// Implements the spec's rules for a second server connecting: a `playback`
+/// connection always wins; a `discovery` connection never displaces a `playback`
+/// one; between two `discovery` connections the stored last played server is
+/// preferred, and otherwise the existing server is kept.
+pub fn prefer_new_server(
+    existing: &ServerHello,
+    new: &ServerHello,
+    last_played_server_id: Option<&str>,
+) -> bool {
+    let is_playback = |hello: &ServerHello| hello.connection_reason.as_deref() == Some("playback");
+
+    if is_playback(new) {
+        return true;
+    }
+    if is_playback(existing) {
+        return false;
+    }
+    match last_played_server_id {
+        Some(id) => new.server_id == id && existing.server_id != id,
+        None => false,
+    }
+}
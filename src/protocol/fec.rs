@@ -0,0 +1,222 @@
+// ABOUTME: XOR parity forward error correction for audio chunks
+// ABOUTME: Server emits a parity frame every N chunks; a client can rebuild one lost chunk per group
+
+use crate::error::Error;
+use crate::protocol::binary::BinaryFrame;
+use crate::protocol::client::AudioChunk;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+/// Smallest parity group (one parity frame per two chunks)
+pub const MIN_GROUP_SIZE: u8 = 2;
+
+/// Largest parity group
+pub const MAX_GROUP_SIZE: u8 = 32;
+
+/// FEC parameters, requested in `player@v1_support` and confirmed in `stream/start`
+///
+/// Sent as the application-specific `_fec` object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FecConfig {
+    /// Audio chunks protected by each parity frame
+    pub group_size: u8,
+}
+
+impl FecConfig {
+    /// Agree on FEC for a client
+    ///
+    /// Returns None if the client did not ask for FEC or the server has it
+    /// disabled (`max_group_size` None). Otherwise the client's group size is
+    /// clamped to what the server allows.
+    pub fn negotiate(requested: Option<FecConfig>, max_group_size: Option<u8>) -> Option<Self> {
+        let max = max_group_size?.clamp(MIN_GROUP_SIZE, MAX_GROUP_SIZE);
+        requested.map(|r| Self {
+            group_size: r.group_size.clamp(MIN_GROUP_SIZE, max),
+        })
+    }
+}
+
+/// Decoded parity payload
+///
+/// Layout: `[count: u8][timestamps: count × i64 BE][length xor: u32 BE][payload xor]`,
+/// where the payload XOR covers every chunk zero-padded to the longest one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Parity {
+    timestamps: Vec<i64>,
+    length_xor: u32,
+    data: Vec<u8>,
+}
+
+impl Parity {
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(1 + self.timestamps.len() * 8 + 4 + self.data.len());
+        out.push(self.timestamps.len() as u8);
+        for ts in &self.timestamps {
+            out.extend_from_slice(&ts.to_be_bytes());
+        }
+        out.extend_from_slice(&self.length_xor.to_be_bytes());
+        out.extend_from_slice(&self.data);
+        out
+    }
+
+    fn decode(payload: &[u8]) -> Result<Self, Error> {
+        let short = || Error::Protocol("Parity frame too short".to_string());
+        let (&count, rest) = payload.split_first().ok_or_else(short)?;
+        let header = count as usize * 8 + 4;
+        if rest.len() < header {
+            return Err(short());
+        }
+
+        let timestamps = rest[..count as usize * 8]
+            .as_chunks::<8>()
+            .0
+            .iter()
+            .map(|b| i64::from_be_bytes(*b))
+            .collect();
+        let length = &rest[count as usize * 8..header];
+        let length_xor = u32::from_be_bytes(length.try_into().unwrap_or_default());
+        Ok(Self {
+            timestamps,
+            length_xor,
+            data: rest[header..].to_vec(),
+        })
+    }
+}
+
+fn xor_into(acc: &mut Vec<u8>, data: &[u8]) {
+    if acc.len() < data.len() {
+        acc.resize(data.len(), 0);
+    }
+    for (a, b) in acc.iter_mut().zip(data) {
+        *a ^= b;
+    }
+}
+
+/// Builds parity frames over the chunks sent to one client
+#[derive(Debug, Clone)]
+pub struct ParityEncoder {
+    config: FecConfig,
+    parity: Parity,
+}
+
+impl ParityEncoder {
+    /// Create an encoder for the negotiated parameters
+    pub fn new(config: FecConfig) -> Self {
+        Self {
+            config,
+            parity: Parity {
+                timestamps: Vec::new(),
+                length_xor: 0,
+                data: Vec::new(),
+            },
+        }
+    }
+
+    /// Negotiated parameters
+    pub fn config(&self) -> FecConfig {
+        self.config
+    }
+
+    /// Add a sent chunk, returning an encoded parity frame when a group completes
+    pub fn push(&mut self, timestamp: i64, payload: &[u8]) -> Option<Vec<u8>> {
+        self.parity.timestamps.push(timestamp);
+        self.parity.length_xor ^= payload.len() as u32;
+        xor_into(&mut self.parity.data, payload);
+
+        if self.parity.timestamps.len() < self.config.group_size as usize {
+            return None;
+        }
+        let parity = std::mem::replace(
+            &mut self.parity,
+            Parity {
+                timestamps: Vec::new(),
+                length_xor: 0,
+                data: Vec::new(),
+            },
+        );
+        let payload = parity.encode();
+        Some(
+            BinaryFrame::Parity {
+                timestamp: parity.timestamps[0],
+                payload: &payload,
+            }
+            .encode(),
+        )
+    }
+
+    /// Start a new group (after `stream/clear` or a new stream)
+    pub fn reset(&mut self) {
+        self.parity = Parity {
+            timestamps: Vec::new(),
+            length_xor: 0,
+            data: Vec::new(),
+        };
+    }
+}
+
+/// Rebuilds a single lost chunk per parity group on the client
+#[derive(Debug, Clone)]
+pub struct ParityDecoder {
+    config: FecConfig,
+    recent: VecDeque<AudioChunk>,
+}
+
+impl ParityDecoder {
+    /// Create a decoder for the parameters confirmed in `stream/start`
+    pub fn new(config: FecConfig) -> Self {
+        Self {
+            config,
+            recent: VecDeque::new(),
+        }
+    }
+
+    /// Remember a received chunk for later recovery
+    pub fn observe(&mut self, chunk: &AudioChunk) {
+        self.recent.push_back(chunk.clone());
+        // Keep two groups so a parity frame delayed past the next group still works
+        while self.recent.len() > self.config.group_size as usize * 2 {
+            self.recent.pop_front();
+        }
+    }
+
+    /// Apply a parity frame payload, returning the recovered chunk if exactly
+    /// one chunk of its group is missing
+    pub fn recover(&mut self, payload: &[u8]) -> Result<Option<AudioChunk>, Error> {
+        let parity = Parity::decode(payload)?;
+
+        let mut missing = None;
+        let mut length = parity.length_xor;
+        let mut data = parity.data;
+        for &ts in &parity.timestamps {
+            match self.recent.iter().find(|c| c.timestamp == ts) {
+                Some(chunk) => {
+                    length ^= chunk.data.len() as u32;
+                    xor_into(&mut data, &chunk.data);
+                }
+                None if missing.is_none() => missing = Some(ts),
+                // Two or more lost: XOR parity cannot rebuild them
+                None => return Ok(None),
+            }
+        }
+
+        let Some(timestamp) = missing else {
+            return Ok(None);
+        };
+        if length as usize > data.len() {
+            return Err(Error::Protocol("Parity frame length mismatch".to_string()));
+        }
+        data.truncate(length as usize);
+        let chunk = AudioChunk {
+            timestamp,
+            data: Arc::from(data),
+        };
+        self.observe(&chunk);
+        Ok(Some(chunk))
+    }
+
+    /// Forget received chunks (after `stream/clear` or `stream/end`)
+    pub fn reset(&mut self) {
+        self.recent.clear();
+    }
+}
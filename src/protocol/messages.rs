@@ -1,6 +1,9 @@
 // ABOUTME: Protocol message type definitions and serialization
 // ABOUTME: Supports client/hello, server/hello, stream/start, etc.
 
+use crate::protocol::fec::FecConfig;
+use crate::protocol::stats::ClientStats;
+use crate::sync::SyncQuality;
 use serde::{Deserialize, Serialize};
 
 /// Top-level protocol message envelope
@@ -55,9 +58,29 @@ pub enum Message {
     #[serde(rename = "client/goodbye")]
     ClientGoodbye(ClientGoodbye),
 
+    /// Client command message (controller role)
+    #[serde(rename = "client/command")]
+    ClientCommand(ClientCommand),
+
     /// Client request for format change (adaptive streaming)
     #[serde(rename = "stream/request-format")]
     StreamRequestFormat(StreamRequestFormat),
+
+    /// Server asks a client for a diagnostics snapshot (application-specific)
+    #[serde(rename = "_server/diagnostics")]
+    DiagnosticsRequest(DiagnosticsRequest),
+
+    /// Client diagnostics snapshot (application-specific)
+    #[serde(rename = "_client/diagnostics")]
+    ClientDiagnostics(ClientDiagnostics),
+
+    /// Server instructs the client to move to another server (application-specific)
+    #[serde(rename = "_server/handoff")]
+    ServerHandoff(ServerHandoff),
+
+    /// Server explains why it is closing the connection (application-specific)
+    #[serde(rename = "_server/goodbye")]
+    ServerGoodbye(ServerGoodbye),
 }
 
 /// Client hello message
@@ -77,8 +100,25 @@ pub struct ClientHello {
     #[serde(rename = "player@v1_support", skip_serializing_if = "Option::is_none")]
     pub player_support: Option<PlayerSupport>,
     /// Metadata@v1 capabilities (if client supports metadata@v1 role)
-    #[serde(rename = "metadata@v1_support", skip_serializing_if = "Option::is_none")]
+    #[serde(
+        rename = "metadata@v1_support",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub metadata_support: Option<MetadataSupport>,
+    /// Artwork@v1 capabilities (if client supports artwork@v1 role)
+    #[serde(
+        rename = "artwork@v1_support",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub artwork_support: Option<ArtworkSupport>,
+    /// Format of the audio a client with the `_source` role pushes (server extension)
+    #[serde(
+        rename = "_source_format",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub source_format: Option<AudioFormatSpec>,
 }
 
 /// Device information
@@ -101,10 +141,13 @@ pub struct PlayerSupport {
     pub buffer_capacity: u32,
     /// List of supported playback commands (subset of: 'volume', 'mute')
     pub supported_commands: Vec<String>,
+    /// Requested forward error correction (application-specific `_fec`)
+    #[serde(rename = "_fec", default, skip_serializing_if = "Option::is_none")]
+    pub fec: Option<FecConfig>,
 }
 
 /// Audio format specification
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct AudioFormatSpec {
     /// Codec name (e.g., "pcm", "opus")
     pub codec: String,
@@ -127,6 +170,26 @@ pub struct MetadataSupport {
     pub media_height: u32,
 }
 
+/// Artwork display capabilities (artwork@v1 support object)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArtworkSupport {
+    /// Image channels the client displays; the index is the channel number (0-3)
+    pub channels: Vec<ArtworkChannelSpec>,
+}
+
+/// One artwork channel of a client
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArtworkChannelSpec {
+    /// Artwork source: 'album', 'artist', or 'none'
+    pub source: String,
+    /// Image format: 'jpeg', 'png', or 'bmp'
+    pub format: String,
+    /// Maximum width in pixels
+    pub media_width: u32,
+    /// Maximum height in pixels
+    pub media_height: u32,
+}
+
 /// Server hello message
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServerHello {
@@ -183,6 +246,24 @@ pub struct StreamPlayerConfig {
     /// Optional codec-specific header (base64 encoded)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub codec_header: Option<String>,
+    /// Forward error correction in use for this stream (application-specific `_fec`)
+    #[serde(rename = "_fec", default, skip_serializing_if = "Option::is_none")]
+    pub fec: Option<FecConfig>,
+    /// Duration of each audio chunk in milliseconds (application-specific)
+    #[serde(
+        rename = "_chunk_interval_ms",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub chunk_interval_ms: Option<u64>,
+    /// How far ahead of its play time each chunk is sent, in milliseconds
+    /// (application-specific)
+    #[serde(
+        rename = "_buffer_ahead_ms",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub buffer_ahead_ms: Option<u64>,
 }
 
 /// Server command message (server -> client)
@@ -192,6 +273,9 @@ pub struct ServerCommand {
     /// Player command (if client has player role)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub player: Option<PlayerCommand>,
+    /// Correlation of this command with the client/state it causes (application-specific)
+    #[serde(rename = "_trace", default, skip_serializing_if = "Option::is_none")]
+    pub trace: Option<MessageTrace>,
 }
 
 /// Player command in server/command message
@@ -228,6 +312,55 @@ pub struct ClientState {
     /// Player state (if client has player role)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub player: Option<PlayerState>,
+    /// Client stream statistics (application-specific extension)
+    #[serde(rename = "_stats", default, skip_serializing_if = "Option::is_none")]
+    pub stats: Option<ClientStats>,
+    /// Trace of the server/command this state reflects (application-specific)
+    #[serde(rename = "_trace", default, skip_serializing_if = "Option::is_none")]
+    pub trace: Option<MessageTrace>,
+}
+
+/// Client command message (client -> server)
+/// Per spec: client/command contains role-specific command objects
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientCommand {
+    /// Controller command (if client has controller role)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub controller: Option<ControllerCommand>,
+    /// Correlation of this command with the server/state it causes (application-specific)
+    #[serde(rename = "_trace", default, skip_serializing_if = "Option::is_none")]
+    pub trace: Option<MessageTrace>,
+}
+
+/// Correlates a control message with the reply that reflects it (application-specific)
+///
+/// Sent as `_trace` in server/command and client/command. The receiver echoes
+/// it in the client/state or server/state sent once the command has been
+/// applied, so the sender can measure how long the change took.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MessageTrace {
+    /// Correlation ID chosen by the sender
+    pub id: String,
+    /// When the command was sent, in microseconds of the sender's clock
+    pub sent_at: i64,
+    /// When the command was applied, in microseconds of the server's clock
+    ///
+    /// Set in the echo; players leave it out until their clock is synchronized.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub handled_at: Option<i64>,
+}
+
+/// Controller command in client/command message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ControllerCommand {
+    /// Command to execute: 'play', 'pause', 'stop', 'next', 'previous', 'volume' or 'mute'
+    pub command: String,
+    /// Group volume (0-100) - only set if command is 'volume'
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub volume: Option<u8>,
+    /// Group mute state - only set if command is 'mute'
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mute: Option<bool>,
 }
 
 /// Player state in client/state message
@@ -267,6 +400,56 @@ pub struct ClientGoodbye {
     pub reason: String,
 }
 
+/// Server goodbye message (server -> client, application-specific)
+///
+/// Sent just before the server closes a connection it refuses, e.g. when the
+/// handshake times out or no role or audio format could be agreed on.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerGoodbye {
+    /// Machine-readable reason: 'handshake_timeout', 'invalid_hello',
+    /// 'no_supported_roles', or 'no_supported_format'
+    pub reason: String,
+    /// Human-readable explanation
+    pub message: String,
+}
+
+/// Server handoff message (server -> client, application-specific)
+///
+/// Tells the client to connect to another server, e.g. when a controller
+/// migrates players between a primary and a backup server. The client completes
+/// the handshake with the new server, then sends `client/goodbye` with reason
+/// `another_server` to the old one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerHandoff {
+    /// WebSocket URL of the server to connect to
+    pub url: String,
+    /// Expected `server_id` of the new server; the handoff is refused on mismatch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server_id: Option<String>,
+}
+
+/// Diagnostics request message (server -> client, application-specific)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiagnosticsRequest {
+    /// Identifier echoed in the client's reply
+    pub request_id: String,
+}
+
+/// Diagnostics snapshot message (client -> server, application-specific)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientDiagnostics {
+    /// Identifier from the request being answered
+    pub request_id: String,
+    /// Stream statistics (buffer level, sync offset, underruns, ...)
+    pub stats: ClientStats,
+    /// Clock synchronization quality
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sync_quality: Option<SyncQuality>,
+    /// Device the client runs on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_info: Option<DeviceInfo>,
+}
+
 /// Stream request format message (client -> server)
 /// Per spec: client requests a different stream format (adaptive streaming)
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -324,6 +507,9 @@ pub struct ServerState {
     /// Controller state (if client has controller role)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub controller: Option<ControllerState>,
+    /// Trace of the client/command this state reflects (application-specific)
+    #[serde(rename = "_trace", default, skip_serializing_if = "Option::is_none")]
+    pub trace: Option<MessageTrace>,
 }
 
 /// Metadata state in server/state message
@@ -352,3 +538,118 @@ pub struct ControllerState {
     /// Group mute state
     pub muted: bool,
 }
+
+/// Status request message (controller -> server, application-specific)
+///
+/// Sent with type `_client/status`. The server answers with a full
+/// `_server/status` snapshot and, when `subscribe` is set, follows it with a
+/// `_server/status` diff whenever something changes. A request with
+/// `subscribe` unset cancels an earlier subscription.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StatusRequest {
+    /// Keep sending diffs after the snapshot
+    #[serde(default)]
+    pub subscribe: bool,
+}
+
+/// Server status message (server -> controller, application-specific)
+///
+/// Sent with type `_server/status`. A full snapshot lists every group and
+/// client; a diff lists only groups and clients that were added or changed
+/// since the previous revision, the IDs of removed ones, and `now_playing` if
+/// it changed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServerStatus {
+    /// Increases by one with every change
+    pub revision: u64,
+    /// Whether this is a full snapshot rather than a diff
+    pub full: bool,
+    /// Groups added or changed (all groups in a snapshot)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<GroupStatus>,
+    /// IDs of groups removed since the previous revision
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub removed_groups: Vec<String>,
+    /// Clients added or changed (all clients in a snapshot)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub clients: Vec<ClientStatus>,
+    /// IDs of clients removed since the previous revision
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub removed_clients: Vec<String>,
+    /// What is playing (always set in a snapshot)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub now_playing: Option<NowPlayingStatus>,
+}
+
+/// One group in a server/status message
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GroupStatus {
+    /// Group identifier
+    pub group_id: String,
+    /// Human-readable name
+    pub name: String,
+    /// 'playing', 'paused' or 'stopped'
+    pub playback_state: String,
+    /// Group volume (0-100)
+    pub volume: u8,
+    /// Group mute state
+    pub muted: bool,
+    /// Member client IDs, sorted
+    pub members: Vec<String>,
+    /// Whether night-mode compression is on
+    pub night_mode: bool,
+}
+
+/// One client in a server/status message
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientStatus {
+    /// Client identifier
+    pub client_id: String,
+    /// Human-readable name
+    pub name: String,
+    /// Active roles
+    pub roles: Vec<String>,
+    /// Group the client belongs to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group_id: Option<String>,
+    /// Client volume (0-100)
+    pub volume: u8,
+    /// Client mute state
+    pub muted: bool,
+    /// Whether the server sums the client's audio to mono
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub mono: bool,
+    /// Audio stream sent to the client (players only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream: Option<StreamStatus>,
+}
+
+/// Format of the audio stream sent to a client
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StreamStatus {
+    /// Codec name: 'opus', 'flac', or 'pcm'
+    pub codec: String,
+    /// Sample rate in Hz
+    pub sample_rate: u32,
+    /// Number of channels
+    pub channels: u8,
+    /// Bit depth
+    pub bit_depth: u8,
+}
+
+/// What the server is playing, in a server/status message
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NowPlayingStatus {
+    /// Source description (file path, URL, ...)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// Track title
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// Artist name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artist: Option<String>,
+    /// Album name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub album: Option<String>,
+}
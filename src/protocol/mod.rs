@@ -1,10 +1,27 @@
 // ABOUTME: Protocol implementation for Sendspin WebSocket protocol
 // ABOUTME: Message types, serialization, and WebSocket client
 
+/// Typed binary frame parsing and encoding
+pub mod binary;
 /// WebSocket client implementation
 pub mod client;
+/// Prioritized server list for client failover
+pub mod failover;
+/// XOR parity forward error correction
+pub mod fec;
 /// Protocol message type definitions and serialization
 pub mod messages;
+/// Reordering window for out-of-order chunk delivery
+pub mod reorder;
+/// Client-side stream statistics and chunk continuity tracking
+pub mod stats;
+/// Chunked transfer of frames too large for one WebSocket message
+pub mod transfer;
 
-pub use client::WsSender;
+pub use binary::BinaryFrame;
+pub use client::{connect_discovered, discover, DiscoveredServer, WsSender};
+pub use failover::ServerList;
 pub use messages::Message;
+pub use reorder::ReorderBuffer;
+pub use stats::{ChunkTracker, ClientStats, StaleChunkFilter};
+pub use transfer::{Reassembler, Segmenter};
@@ -0,0 +1,97 @@
+// ABOUTME: Reordering window for audio chunks delivered out of order
+// ABOUTME: Holds a few chunks and releases them in timestamp order (for datagram transports)
+
+use crate::protocol::client::AudioChunk;
+use crate::protocol::stats::ClientStats;
+use std::collections::BTreeMap;
+
+/// Small reordering window between the transport and the scheduler
+///
+/// Up to `window` chunks are held back; once the window is full the earliest
+/// chunk is released. Chunks arriving behind the last released timestamp
+/// cannot be put back in order and are passed straight through. A window of 0
+/// releases every chunk immediately (for ordered transports like WebSocket).
+#[derive(Debug, Clone)]
+pub struct ReorderBuffer {
+    window: usize,
+    pending: BTreeMap<i64, AudioChunk>,
+    last_released: Option<i64>,
+    highest_seen: Option<i64>,
+}
+
+impl ReorderBuffer {
+    /// Create a buffer holding back up to `window` chunks
+    pub fn new(window: usize) -> Self {
+        Self {
+            window,
+            pending: BTreeMap::new(),
+            last_released: None,
+            highest_seen: None,
+        }
+    }
+
+    /// Add a received chunk, returning the chunks now ready in timestamp order
+    ///
+    /// Chunks that arrive after a later-timestamped chunk are counted in
+    /// `stats.reordered`.
+    pub fn push(&mut self, chunk: AudioChunk, stats: &mut ClientStats) -> Vec<AudioChunk> {
+        if self
+            .highest_seen
+            .is_some_and(|highest| chunk.timestamp < highest)
+        {
+            stats.reordered += 1;
+        }
+        self.highest_seen = Some(
+            self.highest_seen
+                .map_or(chunk.timestamp, |h| h.max(chunk.timestamp)),
+        );
+
+        if self
+            .last_released
+            .is_some_and(|last| chunk.timestamp <= last)
+        {
+            return vec![chunk];
+        }
+
+        self.pending.insert(chunk.timestamp, chunk);
+        let mut ready = Vec::new();
+        while self.pending.len() > self.window {
+            if let Some((timestamp, chunk)) = self.pending.pop_first() {
+                self.last_released = Some(timestamp);
+                ready.push(chunk);
+            }
+        }
+        ready
+    }
+
+    /// Release everything held, in timestamp order
+    pub fn flush(&mut self) -> Vec<AudioChunk> {
+        if let Some((&last, _)) = self.pending.last_key_value() {
+            self.last_released = Some(last);
+        }
+        std::mem::take(&mut self.pending).into_values().collect()
+    }
+
+    /// Discard held chunks and forget ordering (after `stream/clear` or `stream/end`)
+    pub fn reset(&mut self) {
+        self.pending.clear();
+        self.last_released = None;
+        self.highest_seen = None;
+    }
+
+    /// Number of chunks currently held back
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Whether no chunks are held back
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+impl Default for ReorderBuffer {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
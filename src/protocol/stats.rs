@@ -0,0 +1,208 @@
+// ABOUTME: Client-side stream statistics
+// ABOUTME: Detects timestamp gaps and overlaps between consecutive audio chunks and drops stale ones
+
+use crate::protocol::client::AudioChunk;
+use crate::protocol::messages::StreamPlayerConfig;
+use serde::{Deserialize, Serialize};
+
+/// Default tolerance before a timestamp mismatch counts as a discontinuity (2ms)
+pub const DEFAULT_GAP_TOLERANCE_MICROS: i64 = 2_000;
+
+/// Default lateness after which a chunk is too old to play (50ms)
+pub const DEFAULT_STALE_THRESHOLD_MICROS: i64 = 50_000;
+
+/// Client-side stream statistics
+///
+/// Serialized as the application-specific `_stats` object in `client/state`
+/// when a client chooses to report it to the server.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientStats {
+    /// Total audio chunks received
+    pub chunks_received: u64,
+    /// Chunks that started later than the previous chunk ended
+    pub gaps: u64,
+    /// Chunks that started before the previous chunk ended
+    pub overlaps: u64,
+    /// Largest gap seen in microseconds
+    pub max_gap_micros: i64,
+    /// Largest overlap seen in microseconds
+    pub max_overlap_micros: i64,
+    /// Latest time-sync round-trip time in microseconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rtt_micros: Option<i64>,
+    /// Audio currently buffered ahead of playback in milliseconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub buffered_ms: Option<u32>,
+    /// Current playback offset from the scheduled time in microseconds
+    /// (positive when playing late)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sync_error_micros: Option<i64>,
+    /// Chunks dropped on arrival because their play time had already passed
+    #[serde(default)]
+    pub stale_dropped: u64,
+    /// Chunks that arrived after a chunk with a later timestamp
+    #[serde(default)]
+    pub reordered: u64,
+    /// Lost chunks rebuilt from FEC parity
+    #[serde(default)]
+    pub fec_recovered: u64,
+    /// Times playback ran out of buffered audio (counted by the output)
+    #[serde(default)]
+    pub underruns: u64,
+}
+
+/// Tracks chunk continuity for the active stream
+///
+/// The expected timestamp of the next chunk is derived from the current chunk's
+/// duration when it can be computed from the stream format (PCM), otherwise from
+/// the spacing between the previous two chunks.
+#[derive(Debug, Clone)]
+pub struct ChunkTracker {
+    tolerance_micros: i64,
+    /// Bytes per PCM frame, if the stream is PCM
+    frame_bytes: Option<usize>,
+    sample_rate: u32,
+    last_timestamp: Option<i64>,
+    last_delta: Option<i64>,
+    expected_next: Option<i64>,
+}
+
+impl ChunkTracker {
+    /// Create a new tracker with the given discontinuity tolerance
+    pub fn new(tolerance_micros: i64) -> Self {
+        Self {
+            tolerance_micros,
+            frame_bytes: None,
+            sample_rate: 0,
+            last_timestamp: None,
+            last_delta: None,
+            expected_next: None,
+        }
+    }
+
+    /// Configure the tracker from a `stream/start` player object
+    ///
+    /// Starting a stream also resets continuity tracking.
+    pub fn set_format(&mut self, config: &StreamPlayerConfig) {
+        self.frame_bytes = if config.codec == "pcm" && config.bit_depth.is_multiple_of(8) {
+            Some((config.bit_depth / 8) as usize * config.channels as usize)
+        } else {
+            None
+        };
+        self.sample_rate = config.sample_rate;
+        self.reset();
+    }
+
+    /// Forget the previous chunk (after `stream/clear` or `stream/end`)
+    pub fn reset(&mut self) {
+        self.last_timestamp = None;
+        self.last_delta = None;
+        self.expected_next = None;
+    }
+
+    /// Record a received chunk, updating `stats` with any discontinuity
+    ///
+    /// Returns the signed discontinuity in microseconds (positive for a gap,
+    /// negative for an overlap) if one was detected.
+    pub fn observe(&mut self, chunk: &AudioChunk, stats: &mut ClientStats) -> Option<i64> {
+        stats.chunks_received += 1;
+
+        let mut discontinuity = None;
+        if let Some(expected) = self.expected_next {
+            let delta = chunk.timestamp - expected;
+            if delta > self.tolerance_micros {
+                stats.gaps += 1;
+                stats.max_gap_micros = stats.max_gap_micros.max(delta);
+                discontinuity = Some(delta);
+            } else if delta < -self.tolerance_micros {
+                stats.overlaps += 1;
+                stats.max_overlap_micros = stats.max_overlap_micros.max(-delta);
+                discontinuity = Some(delta);
+            }
+        }
+
+        // Only learn the chunk spacing from continuous chunks
+        if let Some(last) = self.last_timestamp {
+            if discontinuity.is_none() {
+                self.last_delta = Some(chunk.timestamp - last);
+            }
+        }
+
+        self.expected_next = self
+            .chunk_duration_micros(chunk.data.len())
+            .or(self.last_delta)
+            .map(|duration| chunk.timestamp + duration);
+        self.last_timestamp = Some(chunk.timestamp);
+
+        discontinuity
+    }
+
+    fn chunk_duration_micros(&self, data_len: usize) -> Option<i64> {
+        let frame_bytes = self.frame_bytes?;
+        if frame_bytes == 0 || self.sample_rate == 0 {
+            return None;
+        }
+        let frames = (data_len / frame_bytes) as i64;
+        Some(frames * 1_000_000 / self.sample_rate as i64)
+    }
+}
+
+impl Default for ChunkTracker {
+    fn default() -> Self {
+        Self::new(DEFAULT_GAP_TOLERANCE_MICROS)
+    }
+}
+
+/// Drops chunks that arrive after their play time has passed
+///
+/// After a network stall the server's backlog arrives in a burst. Scheduling
+/// it would play seconds of audio late and garble the catch-up, so chunks more
+/// than the threshold behind the synchronized clock are dropped instead.
+#[derive(Debug, Clone)]
+pub struct StaleChunkFilter {
+    threshold_micros: i64,
+    dropping: bool,
+}
+
+impl StaleChunkFilter {
+    /// Create a filter dropping chunks later than `threshold_micros`
+    pub fn new(threshold_micros: i64) -> Self {
+        Self {
+            threshold_micros,
+            dropping: false,
+        }
+    }
+
+    /// Check a chunk against the current server time
+    ///
+    /// Returns how late the chunk is (in microseconds) if it was dropped, and
+    /// counts it in `stats`. Without a synchronized clock (`server_now_micros`
+    /// is None) every chunk is accepted.
+    pub fn check(
+        &mut self,
+        chunk: &AudioChunk,
+        server_now_micros: Option<i64>,
+        stats: &mut ClientStats,
+    ) -> Option<i64> {
+        let late_by = server_now_micros? - chunk.timestamp;
+        if late_by > self.threshold_micros {
+            self.dropping = true;
+            stats.stale_dropped += 1;
+            Some(late_by)
+        } else {
+            self.dropping = false;
+            None
+        }
+    }
+
+    /// Whether the previous chunk was dropped (a stale burst is in progress)
+    pub fn is_dropping(&self) -> bool {
+        self.dropping
+    }
+}
+
+impl Default for StaleChunkFilter {
+    fn default() -> Self {
+        Self::new(DEFAULT_STALE_THRESHOLD_MICROS)
+    }
+}
@@ -0,0 +1,303 @@
+// ABOUTME: Chunked transfer of binary frames too large to send as one WebSocket message
+// ABOUTME: Splits frames into sequenced segments and reassembles them per channel with a timeout
+
+use crate::error::Error;
+use crate::protocol::binary::{BinaryFrame, HEADER_LEN};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Largest frame sent whole; bigger ones are split into segments
+pub const DEFAULT_MAX_FRAME: usize = 64 * 1024;
+
+/// How long a partly received transfer is kept before it is abandoned
+pub const DEFAULT_TRANSFER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Largest payload reassembled by default
+pub const DEFAULT_MAX_PAYLOAD: usize = 16 * 1024 * 1024;
+
+/// Length of the header at the start of every segment payload
+pub const SEGMENT_HEADER_LEN: usize = 9;
+
+/// Header at the start of a segment's payload
+///
+/// Layout: `[message type: u8][transfer id: u32 BE][index: u16 BE][count: u16 BE]`.
+/// The segment frame's timestamp is the original frame's, and the message
+/// type is the channel transfers are sequenced on: a newer transfer on a
+/// channel replaces an unfinished older one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentHeader {
+    /// Binary message type of the frame being transferred
+    pub message_type: u8,
+    /// Transfer sequence number, increasing per message type
+    pub transfer_id: u32,
+    /// Position of this segment
+    pub index: u16,
+    /// Number of segments in the transfer
+    pub count: u16,
+}
+
+impl SegmentHeader {
+    /// Serialize the header
+    pub fn encode(&self) -> [u8; SEGMENT_HEADER_LEN] {
+        let mut bytes = [0u8; SEGMENT_HEADER_LEN];
+        bytes[0] = self.message_type;
+        bytes[1..5].copy_from_slice(&self.transfer_id.to_be_bytes());
+        bytes[5..7].copy_from_slice(&self.index.to_be_bytes());
+        bytes[7..9].copy_from_slice(&self.count.to_be_bytes());
+        bytes
+    }
+
+    /// Split a segment payload into its header and data
+    pub fn decode(payload: &[u8]) -> Result<(Self, &[u8]), Error> {
+        if payload.len() < SEGMENT_HEADER_LEN {
+            return Err(Error::Protocol(format!(
+                "Segment too short: {} bytes, header is {}",
+                payload.len(),
+                SEGMENT_HEADER_LEN
+            )));
+        }
+        let (header, data) = payload.split_at(SEGMENT_HEADER_LEN);
+        let header = Self {
+            message_type: header[0],
+            transfer_id: u32::from_be_bytes([header[1], header[2], header[3], header[4]]),
+            index: u16::from_be_bytes([header[5], header[6]]),
+            count: u16::from_be_bytes([header[7], header[8]]),
+        };
+        if header.count == 0 || header.index >= header.count {
+            return Err(Error::Protocol(format!(
+                "Segment {} of {} is out of range",
+                header.index, header.count
+            )));
+        }
+        Ok((header, data))
+    }
+}
+
+/// Splits large frames into segments, numbering transfers per message type
+#[derive(Debug)]
+pub struct Segmenter {
+    max_frame: usize,
+    next_id: HashMap<u8, u32>,
+}
+
+impl Default for Segmenter {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_FRAME)
+    }
+}
+
+impl Segmenter {
+    /// Segment frames longer than `max_frame` bytes
+    pub fn new(max_frame: usize) -> Self {
+        Self {
+            // Every segment must carry at least one byte of data
+            max_frame: max_frame.max(HEADER_LEN + SEGMENT_HEADER_LEN + 1),
+            next_id: HashMap::new(),
+        }
+    }
+
+    /// Frames to send for `frame`: the frame itself if it fits, else its segments
+    ///
+    /// Returns an error if the frame needs more than `u16::MAX` segments.
+    pub fn segment(&mut self, frame: &BinaryFrame) -> Result<Vec<Vec<u8>>, Error> {
+        let payload = frame.payload();
+        if HEADER_LEN + payload.len() <= self.max_frame {
+            return Ok(vec![frame.encode()]);
+        }
+
+        let per_segment = self.max_frame - HEADER_LEN - SEGMENT_HEADER_LEN;
+        let count = u16::try_from(payload.len().div_ceil(per_segment)).map_err(|_| {
+            Error::Protocol(format!(
+                "Frame of {} bytes needs too many segments",
+                payload.len()
+            ))
+        })?;
+        let message_type = frame.message_type();
+        let id = self.next_id.entry(message_type).or_insert(0);
+        let transfer_id = *id;
+        *id = id.wrapping_add(1);
+
+        let segments = payload
+            .chunks(per_segment)
+            .enumerate()
+            .map(|(index, data)| {
+                let header = SegmentHeader {
+                    message_type,
+                    transfer_id,
+                    index: index as u16,
+                    count,
+                };
+                let mut segment = Vec::with_capacity(SEGMENT_HEADER_LEN + data.len());
+                segment.extend_from_slice(&header.encode());
+                segment.extend_from_slice(data);
+                BinaryFrame::Segment {
+                    timestamp: frame.timestamp(),
+                    payload: &segment,
+                }
+                .encode()
+            })
+            .collect();
+        Ok(segments)
+    }
+}
+
+/// A transfer still missing segments
+#[derive(Debug)]
+struct Partial {
+    transfer_id: u32,
+    timestamp: i64,
+    segments: Vec<Option<Vec<u8>>>,
+    received: usize,
+    bytes: usize,
+    started: Instant,
+}
+
+/// Reassembles segmented frames, one transfer in flight per message type
+#[derive(Debug)]
+pub struct Reassembler {
+    timeout: Duration,
+    max_payload: usize,
+    partial: HashMap<u8, Partial>,
+    /// Last transfer completed or started on each channel
+    latest: HashMap<u8, u32>,
+}
+
+impl Default for Reassembler {
+    fn default() -> Self {
+        Self::new(DEFAULT_TRANSFER_TIMEOUT, DEFAULT_MAX_PAYLOAD)
+    }
+}
+
+impl Reassembler {
+    /// Abandon transfers after `timeout` and refuse payloads over `max_payload` bytes
+    pub fn new(timeout: Duration, max_payload: usize) -> Self {
+        Self {
+            timeout,
+            max_payload,
+            partial: HashMap::new(),
+            latest: HashMap::new(),
+        }
+    }
+
+    /// Add a received segment
+    ///
+    /// Returns the original frame once its last segment arrives. Segments of
+    /// a transfer older than the channel's latest are dropped; a newer
+    /// transfer abandons the unfinished one.
+    pub fn push(
+        &mut self,
+        timestamp: i64,
+        payload: &[u8],
+        now: Instant,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        self.expire(now);
+        let (header, data) = SegmentHeader::decode(payload)?;
+        let channel = header.message_type;
+
+        if let Some(&latest) = self.latest.get(&channel) {
+            if is_newer(latest, header.transfer_id) {
+                log::debug!(
+                    "Dropping segment of stale transfer {} on type {}",
+                    header.transfer_id,
+                    channel
+                );
+                return Ok(None);
+            }
+        }
+        match self.partial.get(&channel) {
+            Some(partial) if partial.transfer_id == header.transfer_id => {}
+            existing => {
+                if let Some(old) = existing {
+                    log::debug!(
+                        "Transfer {} on type {} superseded after {} of {} segments",
+                        old.transfer_id,
+                        channel,
+                        old.received,
+                        old.segments.len()
+                    );
+                } else if self.latest.get(&channel) == Some(&header.transfer_id) {
+                    // A late duplicate of a transfer already delivered
+                    return Ok(None);
+                }
+                self.latest.insert(channel, header.transfer_id);
+                self.partial.insert(
+                    channel,
+                    Partial {
+                        transfer_id: header.transfer_id,
+                        timestamp,
+                        segments: vec![None; header.count as usize],
+                        received: 0,
+                        bytes: 0,
+                        started: now,
+                    },
+                );
+            }
+        }
+
+        let partial = self.partial.get_mut(&channel).expect("inserted above");
+        if partial.segments.len() != header.count as usize {
+            self.partial.remove(&channel);
+            return Err(Error::Protocol(format!(
+                "Transfer {} changed segment count to {}",
+                header.transfer_id, header.count
+            )));
+        }
+        let slot = &mut partial.segments[header.index as usize];
+        if slot.is_some() {
+            return Ok(None);
+        }
+        partial.bytes += data.len();
+        if partial.bytes > self.max_payload {
+            self.partial.remove(&channel);
+            return Err(Error::Protocol(format!(
+                "Transfer {} exceeds {} bytes",
+                header.transfer_id, self.max_payload
+            )));
+        }
+        *slot = Some(data.to_vec());
+        partial.received += 1;
+        if partial.received < partial.segments.len() {
+            return Ok(None);
+        }
+
+        let partial = self.partial.remove(&channel).expect("present above");
+        let mut frame = Vec::with_capacity(HEADER_LEN + partial.bytes);
+        frame.push(channel);
+        frame.extend_from_slice(&partial.timestamp.to_be_bytes());
+        for segment in partial.segments.into_iter().flatten() {
+            frame.extend_from_slice(&segment);
+        }
+        Ok(Some(frame))
+    }
+
+    /// Abandon transfers older than the timeout, returning how many were dropped
+    pub fn expire(&mut self, now: Instant) -> usize {
+        let before = self.partial.len();
+        let timeout = self.timeout;
+        self.partial.retain(|channel, partial| {
+            let alive = now.saturating_duration_since(partial.started) < timeout;
+            if !alive {
+                log::warn!(
+                    "Transfer {} on type {} timed out with {} of {} segments",
+                    partial.transfer_id,
+                    channel,
+                    partial.received,
+                    partial.segments.len()
+                );
+            }
+            alive
+        });
+        before - self.partial.len()
+    }
+
+    /// Drop all partial transfers and sequencing, e.g. after reconnecting
+    pub fn reset(&mut self) {
+        self.partial.clear();
+        self.latest.clear();
+    }
+}
+
+/// Whether transfer `a` comes after `b`, allowing for the IDs wrapping
+fn is_newer(a: u32, b: u32) -> bool {
+    a != b && a.wrapping_sub(b) < u32::MAX / 2
+}
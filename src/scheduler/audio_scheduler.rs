@@ -1,10 +1,32 @@
 // ABOUTME: Lock-free audio scheduler implementation
 // ABOUTME: Uses crossbeam queues for thread-safe scheduling without locks
 
-use crate::audio::AudioBuffer;
+use crate::audio::{AudioBuffer, Sample};
+use crate::sync::time::{Clock, SystemClock};
 use crossbeam::queue::SegQueue;
+use std::sync::atomic::{AtomicI64, Ordering};
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::Duration;
+
+/// How long output fades in when playback starts or restarts after a clear
+pub const FADE_IN: Duration = Duration::from_millis(100);
+
+/// How far past its play time the first buffer may be and still start playback
+///
+/// Older buffers are dropped so a late joiner starts on a chunk boundary in
+/// step with the rest of its group instead of bursting stale audio.
+pub const START_TOLERANCE: Duration = Duration::from_millis(10);
+
+/// Where playback is in starting up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Startup {
+    /// Nothing played since creation or the last clear
+    Waiting,
+    /// Fading in; counts the frames played so far
+    FadingIn(u64),
+    /// Past the fade-in
+    Playing,
+}
 
 /// Lock-free audio scheduler
 pub struct AudioScheduler {
@@ -13,28 +35,78 @@ pub struct AudioScheduler {
 
     /// Sorted buffers ready for playback
     sorted: Arc<parking_lot::Mutex<Vec<AudioBuffer>>>,
+
+    /// Total duration of scheduled, not yet played audio
+    buffered_micros: AtomicI64,
+
+    /// Source of the current time for readiness checks
+    clock: Arc<dyn Clock>,
+
+    /// Start-of-playback gating and fade-in
+    startup: parking_lot::Mutex<Startup>,
+}
+
+/// Playback duration of a buffer in microseconds
+fn buffer_micros(buffer: &AudioBuffer) -> i64 {
+    let frames_per_sec = buffer.format.sample_rate as i64 * buffer.format.channels.max(1) as i64;
+    if frames_per_sec == 0 {
+        return 0;
+    }
+    buffer.samples.len() as i64 * 1_000_000 / frames_per_sec
 }
 
 impl AudioScheduler {
     /// Create a new audio scheduler
     pub fn new() -> Self {
+        Self::with_clock(SystemClock::shared())
+    }
+
+    /// Create a scheduler that decides readiness using `clock`
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
         Self {
             incoming: Arc::new(SegQueue::new()),
             sorted: Arc::new(parking_lot::Mutex::new(Vec::new())),
+            buffered_micros: AtomicI64::new(0),
+            clock,
+            startup: parking_lot::Mutex::new(Startup::Waiting),
         }
     }
 
     /// Schedule an audio buffer for future playback
     pub fn schedule(&self, buffer: AudioBuffer) {
+        self.buffered_micros
+            .fetch_add(buffer_micros(&buffer), Ordering::Relaxed);
         self.incoming.push(buffer);
     }
 
+    /// Duration of audio scheduled but not yet handed to playback
+    ///
+    /// Clients report this to the server so it can pace delivery.
+    pub fn buffered(&self) -> Duration {
+        Duration::from_micros(self.buffered_micros.load(Ordering::Relaxed).max(0) as u64)
+    }
+
     /// Check if scheduler is empty
     pub fn is_empty(&self) -> bool {
         self.incoming.is_empty() && self.sorted.lock().is_empty()
     }
 
-    /// Get next buffer that's ready to play (within 50ms window)
+    /// Drop every scheduled buffer (after `stream/clear` or `stream/end`)
+    ///
+    /// Playback then starts afresh, fading in from the next buffer on time.
+    pub fn clear(&self) {
+        let mut sorted = self.sorted.lock();
+        while self.incoming.pop().is_some() {}
+        sorted.clear();
+        self.buffered_micros.store(0, Ordering::Relaxed);
+        *self.startup.lock() = Startup::Waiting;
+    }
+
+    /// Get next buffer that's ready to play (within 1ms early window)
+    ///
+    /// Playback starts at the first buffer that is not more than
+    /// [`START_TOLERANCE`] late, and fades in over [`FADE_IN`]. Once playing,
+    /// late buffers are handed out as they come.
     pub fn next_ready(&self) -> Option<AudioBuffer> {
         // Take the lock once and do all operations under it
         let mut sorted = self.sorted.lock();
@@ -47,7 +119,20 @@ impl AudioScheduler {
             sorted.insert(pos, buf);
         }
 
-        let now = Instant::now();
+        let now = self.clock.now();
+        let mut startup = self.startup.lock();
+
+        // Not started yet: skip whatever a late joiner got too late to play
+        if *startup == Startup::Waiting {
+            while sorted
+                .first()
+                .is_some_and(|buf| buf.play_at + START_TOLERANCE < now)
+            {
+                let buf = sorted.remove(0);
+                self.buffered_micros
+                    .fetch_sub(buffer_micros(&buf), Ordering::Relaxed);
+            }
+        }
 
         // Per spec: 1ms early window to tolerate micro jitter
         let early_ok = Duration::from_micros(1000);
@@ -57,7 +142,10 @@ impl AudioScheduler {
             // Check if play_at time has passed or is within early window
             if buf.play_at <= now + early_ok {
                 // Ready to play, late, or within 1ms early (tolerate jitter)
-                return Some(sorted.remove(0));
+                let buf = sorted.remove(0);
+                self.buffered_micros
+                    .fetch_sub(buffer_micros(&buf), Ordering::Relaxed);
+                return Some(fade_in(buf, &mut startup));
             }
         }
 
@@ -65,6 +153,38 @@ impl AudioScheduler {
     }
 }
 
+/// Apply the start-of-playback fade to a buffer, advancing the fade
+fn fade_in(mut buffer: AudioBuffer, startup: &mut Startup) -> AudioBuffer {
+    let played = match *startup {
+        Startup::Waiting => 0,
+        Startup::FadingIn(played) => played,
+        Startup::Playing => return buffer,
+    };
+    let channels = buffer.format.channels.max(1) as usize;
+    let fade_frames = buffer.format.sample_rate as u64 * FADE_IN.as_millis() as u64 / 1000;
+    let frames = (buffer.samples.len() / channels) as u64;
+    if played >= fade_frames {
+        *startup = Startup::Playing;
+        return buffer;
+    }
+
+    buffer.samples = buffer
+        .samples
+        .chunks(channels)
+        .enumerate()
+        .flat_map(|(i, frame)| {
+            let gain = ((played + i as u64) as f64 / fade_frames as f64).min(1.0);
+            frame.iter().map(move |s| Sample(s.0 * gain as f32))
+        })
+        .collect();
+    *startup = if played + frames >= fade_frames {
+        Startup::Playing
+    } else {
+        Startup::FadingIn(played + frames)
+    };
+    buffer
+}
+
 impl Default for AudioScheduler {
     fn default() -> Self {
         Self::new()
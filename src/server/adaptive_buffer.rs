@@ -0,0 +1,199 @@
+// ABOUTME: Dynamic buffer-ahead adaptation
+// ABOUTME: Raises or lowers each group's buffer-ahead from client-reported RTT and buffer levels
+
+use crate::protocol::stats::ClientStats;
+use crate::server::buffer_health::BufferHealth;
+use crate::server::client_manager::ClientManager;
+use crate::server::group::GroupManager;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Bounds and tuning for automatic buffer-ahead adaptation
+#[derive(Debug, Clone)]
+pub struct AdaptiveBufferConfig {
+    /// Lowest buffer-ahead the adapter will choose (ms)
+    pub min_ms: u64,
+    /// Highest buffer-ahead the adapter will choose (ms)
+    pub max_ms: u64,
+    /// Buffer-ahead as a multiple of the worst client RTT
+    pub rtt_multiplier: f64,
+    /// Fixed safety margin added on top of the RTT term (ms)
+    pub margin_ms: u64,
+    /// A client buffer level below this forces the buffer to grow (ms)
+    pub low_buffer_ms: u32,
+    /// Largest change applied per adjustment (ms)
+    pub max_step_ms: u64,
+    /// How often groups are re-evaluated
+    pub interval: Duration,
+}
+
+impl Default for AdaptiveBufferConfig {
+    fn default() -> Self {
+        Self {
+            min_ms: 100,
+            max_ms: 2000,
+            rtt_multiplier: 4.0,
+            margin_ms: 100,
+            low_buffer_ms: 100,
+            max_step_ms: 50,
+            interval: Duration::from_secs(5),
+        }
+    }
+}
+
+impl AdaptiveBufferConfig {
+    /// Create a config with the given bounds and default tuning
+    pub fn new(min_ms: u64, max_ms: u64) -> Self {
+        Self {
+            min_ms,
+            max_ms: max_ms.max(min_ms),
+            ..Default::default()
+        }
+    }
+
+    /// Compute the next buffer-ahead for a group
+    ///
+    /// `stats` holds the latest report from each player in the group. Returns
+    /// `current_ms` unchanged when no member has reported an RTT yet.
+    pub fn next_buffer_ahead(&self, current_ms: u64, stats: &[ClientStats]) -> u64 {
+        self.next_buffer_ahead_with_trend(current_ms, stats, false)
+    }
+
+    /// Compute the next buffer-ahead, growing it while any member is draining
+    ///
+    /// `draining` is set when a member's reported buffer is trending toward
+    /// empty, so the group gets more headroom before that client underruns.
+    pub fn next_buffer_ahead_with_trend(
+        &self,
+        current_ms: u64,
+        stats: &[ClientStats],
+        draining: bool,
+    ) -> u64 {
+        let worst_rtt_micros = stats.iter().filter_map(|s| s.rtt_micros).max();
+        let starving = draining
+            || stats
+                .iter()
+                .filter_map(|s| s.buffered_ms)
+                .any(|ms| ms < self.low_buffer_ms);
+
+        let mut target = match worst_rtt_micros {
+            Some(rtt) => {
+                let rtt_ms = rtt.max(0) as f64 / 1000.0;
+                (rtt_ms * self.rtt_multiplier) as u64 + self.margin_ms
+            }
+            None if starving => current_ms,
+            None => return current_ms,
+        };
+        if starving {
+            target = target.max(current_ms + self.max_step_ms);
+        }
+        let target = target.clamp(self.min_ms, self.max_ms);
+
+        // Move gradually: each step is a small gap or overlap on the clients
+        if target > current_ms {
+            current_ms + (target - current_ms).min(self.max_step_ms)
+        } else {
+            current_ms - (current_ms - target).min(self.max_step_ms)
+        }
+    }
+}
+
+/// Spawn a task that periodically adapts every group's buffer-ahead
+///
+/// Groups without an override start from `default_buffer_ahead_ms`.
+pub fn spawn_buffer_adapter(
+    config: AdaptiveBufferConfig,
+    client_manager: Arc<ClientManager>,
+    group_manager: Arc<GroupManager>,
+    default_buffer_ahead_ms: u64,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(config.interval);
+        loop {
+            ticker.tick().await;
+            for group_id in group_manager.group_ids() {
+                let players: Vec<String> = group_manager
+                    .get_group_members(&group_id)
+                    .into_iter()
+                    .filter(|id| client_manager.is_player(id))
+                    .collect();
+                let stats: Vec<ClientStats> = players
+                    .iter()
+                    .filter_map(|id| client_manager.get_stats(id))
+                    .collect();
+                let draining = players
+                    .iter()
+                    .any(|id| client_manager.buffer_health(id) == Some(BufferHealth::Draining));
+                if stats.is_empty() {
+                    continue;
+                }
+
+                let current = group_manager
+                    .get_buffer_ahead(&group_id)
+                    .or(group_manager.default_buffer_ahead())
+                    .unwrap_or(default_buffer_ahead_ms);
+                let next = config.next_buffer_ahead_with_trend(current, &stats, draining);
+                if next != current {
+                    log::info!(
+                        "Group {} buffer-ahead adapted: {}ms -> {}ms",
+                        group_id,
+                        current,
+                        next
+                    );
+                    group_manager.set_buffer_ahead(&group_id, Some(next));
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(rtt_ms: Option<i64>, buffered_ms: Option<u32>) -> ClientStats {
+        ClientStats {
+            rtt_micros: rtt_ms.map(|ms| ms * 1000),
+            buffered_ms,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_good_network_lowers_buffer_gradually() {
+        let config = AdaptiveBufferConfig::new(100, 2000);
+        // 5ms RTT -> target 4 * 5 + 100 = 120ms, clamped step of 50ms
+        let stats = [report(Some(5), Some(400))];
+        assert_eq!(config.next_buffer_ahead(500, &stats), 450);
+        assert_eq!(config.next_buffer_ahead(140, &stats), 120);
+    }
+
+    #[test]
+    fn test_worst_client_and_bounds() {
+        let config = AdaptiveBufferConfig::new(100, 600);
+        let stats = [report(Some(5), None), report(Some(300), None)];
+        // Worst RTT 300ms -> 1300ms target, capped at 600
+        assert_eq!(config.next_buffer_ahead(580, &stats), 600);
+        assert_eq!(config.next_buffer_ahead(600, &stats), 600);
+    }
+
+    #[test]
+    fn test_starving_client_grows_buffer() {
+        let config = AdaptiveBufferConfig::new(100, 2000);
+        let stats = [report(None, Some(20))];
+        assert_eq!(config.next_buffer_ahead(300, &stats), 350);
+    }
+
+    #[test]
+    fn test_draining_client_grows_buffer() {
+        let config = AdaptiveBufferConfig::new(100, 2000);
+        let stats = [report(Some(5), Some(400))];
+        assert_eq!(config.next_buffer_ahead_with_trend(300, &stats, true), 350);
+    }
+
+    #[test]
+    fn test_no_reports_keeps_current() {
+        let config = AdaptiveBufferConfig::default();
+        assert_eq!(config.next_buffer_ahead(500, &[report(None, None)]), 500);
+    }
+}
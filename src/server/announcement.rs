@@ -0,0 +1,246 @@
+// ABOUTME: Announcement stream class with priority mixing
+// ABOUTME: Short-latency announcements that override or duck the music for selected groups
+
+use crate::audio::resample::Resampler;
+use crate::audio::types::Sample;
+use crate::server::audio_source::AudioSource;
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+/// Default buffer-ahead for announcements in milliseconds
+pub const DEFAULT_ANNOUNCEMENT_BUFFER_MS: u64 = 100;
+
+/// Default music gain while an announcement is ducking it
+pub const DEFAULT_DUCK_GAIN: f32 = 0.2;
+
+/// How an announcement is combined with the music stream
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AnnouncementMix {
+    /// Replace the music entirely
+    Override,
+    /// Keep the music underneath at the given linear gain (0.0-1.0)
+    Duck(f32),
+}
+
+/// A finite audio clip played over the music for some groups
+///
+/// Announcements use their own (short) buffer-ahead so they play promptly.
+/// When one starts, targeted players receive `stream/clear` to drop the music
+/// already queued at the normal offset; when it ends there is a short gap
+/// while the music catches up to the group's regular buffer-ahead.
+pub struct Announcement {
+    pub(crate) source: Box<dyn AudioSource>,
+    pub(crate) groups: Option<Vec<String>>,
+    pub(crate) mix: AnnouncementMix,
+    pub(crate) buffer_ahead_ms: u64,
+}
+
+impl Announcement {
+    /// Create an announcement that ducks the music in all groups
+    ///
+    /// The source must end (return `None`), so use a non-looping source.
+    pub fn new(source: Box<dyn AudioSource>) -> Self {
+        Self {
+            source,
+            groups: None,
+            mix: AnnouncementMix::Duck(DEFAULT_DUCK_GAIN),
+            buffer_ahead_ms: DEFAULT_ANNOUNCEMENT_BUFFER_MS,
+        }
+    }
+
+    /// Limit the announcement to the given groups
+    pub fn groups<I, S>(mut self, groups: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.groups = Some(groups.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Set how the announcement is mixed with the music
+    pub fn mix(mut self, mix: AnnouncementMix) -> Self {
+        self.mix = mix;
+        self
+    }
+
+    /// Set the announcement's buffer-ahead in milliseconds
+    pub fn buffer_ahead_ms(mut self, ms: u64) -> Self {
+        self.buffer_ahead_ms = ms;
+        self
+    }
+
+    /// Check whether the announcement applies to a group
+    pub fn targets(&self, group_id: &str) -> bool {
+        self.groups
+            .as_ref()
+            .is_none_or(|groups| groups.iter().any(|g| g == group_id))
+    }
+}
+
+/// Queue of pending announcements shared with the audio engine
+///
+/// Announcements play one at a time in the order they were queued.
+#[derive(Clone, Default)]
+pub struct AnnouncementQueue {
+    pending: Arc<Mutex<VecDeque<Announcement>>>,
+}
+
+impl AnnouncementQueue {
+    /// Create an empty queue
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue an announcement
+    pub fn play(&self, announcement: Announcement) {
+        self.pending.lock().push_back(announcement);
+    }
+
+    /// Drop all announcements that have not started yet
+    pub fn clear(&self) {
+        self.pending.lock().clear();
+    }
+
+    /// Number of announcements waiting to start
+    pub fn len(&self) -> usize {
+        self.pending.lock().len()
+    }
+
+    /// True if no announcements are waiting
+    pub fn is_empty(&self) -> bool {
+        self.pending.lock().is_empty()
+    }
+
+    /// Take the next announcement to play
+    pub(crate) fn next(&self) -> Option<Announcement> {
+        self.pending.lock().pop_front()
+    }
+}
+
+/// Mix an announcement chunk over a music chunk
+///
+/// A short announcement chunk (end of clip) is padded with silence.
+pub fn mix_announcement(
+    music: &[Sample],
+    announcement: &[Sample],
+    mix: AnnouncementMix,
+) -> Vec<Sample> {
+    let music_gain = match mix {
+        AnnouncementMix::Override => 0.0,
+        AnnouncementMix::Duck(gain) => gain.clamp(0.0, 1.0),
+    };
+    music
+        .iter()
+        .enumerate()
+        .map(|(i, m)| {
+            let a = announcement.get(i).copied().unwrap_or(Sample::ZERO);
+            Sample(m.0 * music_gain + a.0).clamp()
+        })
+        .collect()
+}
+
+/// An announcement converted to the stream's sample rate
+///
+/// Once the clip ends, silence is fed through to push out the audio the
+/// resampler's filter held back, so the clip plays to its last frame.
+pub(crate) struct ResampledAnnouncement {
+    source: Box<dyn AudioSource>,
+    resampler: Resampler,
+    /// Converted samples not yet read
+    pending: VecDeque<Sample>,
+    /// Whether the clip has ended and its tail has been flushed
+    ended: bool,
+}
+
+impl ResampledAnnouncement {
+    /// Convert `source` to `sample_rate` Hz
+    pub(crate) fn new(source: Box<dyn AudioSource>, sample_rate: u32) -> Self {
+        let resampler = Resampler::new(source.sample_rate(), sample_rate, source.channels());
+        Self {
+            source,
+            resampler,
+            pending: VecDeque::new(),
+            ended: false,
+        }
+    }
+}
+
+impl AudioSource for ResampledAnnouncement {
+    fn read_chunk(&mut self, samples_per_channel: usize) -> Option<Vec<Sample>> {
+        let channels = self.source.channels() as usize;
+        let wanted = samples_per_channel * channels;
+        let (from, to) = (self.resampler.input_rate(), self.resampler.output_rate());
+        let frames = (samples_per_channel * from as usize).div_ceil(to as usize);
+        while self.pending.len() < wanted && !self.ended {
+            match self.source.read_chunk(frames) {
+                Some(chunk) => self.pending.extend(self.resampler.process(&chunk)),
+                None => {
+                    let tail = vec![Sample::ZERO; 2 * self.resampler.delay_frames() * channels];
+                    self.pending.extend(self.resampler.process(&tail));
+                    self.ended = true;
+                }
+            }
+        }
+        if self.pending.is_empty() {
+            return None;
+        }
+        let available = self.pending.len().min(wanted);
+        let mut chunk: Vec<Sample> = self.pending.drain(..available).collect();
+        chunk.resize(wanted, Sample::ZERO);
+        Some(chunk)
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.resampler.output_rate()
+    }
+
+    fn channels(&self) -> u8 {
+        self.source.channels()
+    }
+
+    fn is_exhausted(&self) -> bool {
+        self.ended && self.pending.is_empty()
+    }
+
+    fn description(&self) -> Option<String> {
+        self.source.description()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mix_modes() {
+        let music = vec![Sample(0.25); 4];
+        let announcement = vec![Sample(0.125); 2];
+
+        let ducked = mix_announcement(&music, &announcement, AnnouncementMix::Duck(0.5));
+        assert_eq!(ducked, [0.25, 0.25, 0.125, 0.125].map(Sample));
+
+        let overridden = mix_announcement(&music, &announcement, AnnouncementMix::Override);
+        assert_eq!(overridden, [0.125, 0.125, 0.0, 0.0].map(Sample));
+    }
+
+    #[test]
+    fn test_mix_clamps() {
+        let music = vec![Sample::MAX];
+        let mixed = mix_announcement(&music, &[Sample::MAX], AnnouncementMix::Duck(1.0));
+        assert_eq!(mixed, [Sample::MAX]);
+    }
+
+    #[test]
+    fn test_targets() {
+        use crate::server::audio_source::SilenceSource;
+
+        let all = Announcement::new(Box::new(SilenceSource::new(48000)));
+        assert!(all.targets("anything"));
+
+        let kitchen = Announcement::new(Box::new(SilenceSource::new(48000))).groups(["kitchen"]);
+        assert!(kitchen.targets("kitchen"));
+        assert!(!kitchen.targets("bedroom"));
+    }
+}
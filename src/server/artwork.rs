@@ -0,0 +1,424 @@
+// ABOUTME: Artwork role: per-client image channels and cover art delivery
+// ABOUTME: Scales and converts a track's pictures to each channel's format and sends them as binary frames
+
+use crate::protocol::binary::BinaryFrame;
+use crate::protocol::messages::{ArtworkChannelSpec, ArtworkFormatRequest, ArtworkSupport};
+use crate::server::client_manager::ClientManager;
+use crate::server::source_events::{Artwork, TrackInfo};
+use std::collections::HashSet;
+use std::io::Cursor;
+
+/// Channels a client can have (binary message types 4-7)
+pub const MAX_ARTWORK_CHANNELS: usize = 4;
+
+/// Largest image sent on a channel whose client did not give a size
+pub const DEFAULT_ARTWORK_SIZE: u32 = 500;
+
+/// Which picture of the track a channel shows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArtworkSource {
+    /// Album cover
+    Album,
+    /// Picture of the artist
+    Artist,
+    /// Nothing; the channel is unused
+    None,
+}
+
+impl ArtworkSource {
+    /// Parse a protocol source name (`album`, `artist`, `none`)
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "album" => Some(Self::Album),
+            "artist" => Some(Self::Artist),
+            "none" => Some(Self::None),
+            _ => None,
+        }
+    }
+}
+
+/// Image encoding of a channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArtworkFormat {
+    /// JPEG
+    Jpeg,
+    /// PNG
+    Png,
+    /// Uncompressed BMP
+    Bmp,
+}
+
+impl ArtworkFormat {
+    /// Parse a protocol format name (`jpeg`, `png`, `bmp`)
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "jpeg" => Some(Self::Jpeg),
+            "png" => Some(Self::Png),
+            "bmp" => Some(Self::Bmp),
+            _ => None,
+        }
+    }
+
+    fn image_format(self) -> image::ImageFormat {
+        match self {
+            Self::Jpeg => image::ImageFormat::Jpeg,
+            Self::Png => image::ImageFormat::Png,
+            Self::Bmp => image::ImageFormat::Bmp,
+        }
+    }
+}
+
+/// What one artwork channel of a client shows, and how
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArtworkChannel {
+    /// Picture shown
+    pub source: ArtworkSource,
+    /// Encoding sent
+    pub format: ArtworkFormat,
+    /// Largest width in pixels
+    pub max_width: u32,
+    /// Largest height in pixels
+    pub max_height: u32,
+}
+
+impl Default for ArtworkChannel {
+    fn default() -> Self {
+        Self {
+            source: ArtworkSource::Album,
+            format: ArtworkFormat::Jpeg,
+            max_width: DEFAULT_ARTWORK_SIZE,
+            max_height: DEFAULT_ARTWORK_SIZE,
+        }
+    }
+}
+
+impl ArtworkChannel {
+    /// A channel from the client's hello, with defaults for unknown names
+    pub fn from_spec(spec: &ArtworkChannelSpec) -> Self {
+        let default = Self::default();
+        Self {
+            source: ArtworkSource::parse(&spec.source).unwrap_or(default.source),
+            format: ArtworkFormat::parse(&spec.format).unwrap_or(default.format),
+            max_width: spec.media_width,
+            max_height: spec.media_height,
+        }
+    }
+
+    /// Apply the fields a `stream/request-format` sets, ignoring unknown names
+    pub fn apply(&mut self, request: &ArtworkFormatRequest) {
+        if let Some(source) = request.source.as_deref().and_then(ArtworkSource::parse) {
+            self.source = source;
+        }
+        if let Some(format) = request.format.as_deref().and_then(ArtworkFormat::parse) {
+            self.format = format;
+        }
+        if let Some(width) = request.media_width {
+            self.max_width = width;
+        }
+        if let Some(height) = request.media_height {
+            self.max_height = height;
+        }
+    }
+
+    /// The picture of `track` this channel shows, if the track has one
+    pub fn picture<'a>(&self, track: &'a TrackInfo) -> Option<&'a Artwork> {
+        match self.source {
+            ArtworkSource::Album => track.album_art.as_ref(),
+            ArtworkSource::Artist => track.artist_art.as_ref(),
+            ArtworkSource::None => None,
+        }
+    }
+}
+
+/// Artwork channels of a client and the pictures it was last sent
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ArtworkState {
+    /// Channels by number
+    pub channels: Vec<ArtworkChannel>,
+    /// Track whose pictures the channels show
+    pub shown: TrackInfo,
+}
+
+impl ArtworkState {
+    /// Channels from the client's `artwork@v1_support`, or one default channel
+    pub fn from_support(support: Option<&ArtworkSupport>) -> Self {
+        let channels = match support {
+            Some(support) => support
+                .channels
+                .iter()
+                .take(MAX_ARTWORK_CHANNELS)
+                .map(ArtworkChannel::from_spec)
+                .collect(),
+            None => vec![ArtworkChannel::default()],
+        };
+        Self {
+            channels,
+            shown: TrackInfo::default(),
+        }
+    }
+
+    /// Change a channel as the client requested, adding it if new
+    ///
+    /// Returns false for channel numbers beyond the protocol's four.
+    pub fn apply(&mut self, request: &ArtworkFormatRequest) -> bool {
+        let index = request.channel as usize;
+        if index >= MAX_ARTWORK_CHANNELS {
+            return false;
+        }
+        if self.channels.len() <= index {
+            let unused = ArtworkChannel {
+                source: ArtworkSource::None,
+                ..Default::default()
+            };
+            self.channels.resize(index + 1, unused);
+        }
+        self.channels[index].apply(request);
+        true
+    }
+}
+
+/// Scale `artwork` to fit within a channel's size and encode it in its format
+///
+/// Images already in the right format and size are sent as they are.
+pub fn render_artwork(
+    artwork: &Artwork,
+    channel: &ArtworkChannel,
+) -> Result<Vec<u8>, image::ImageError> {
+    let target = channel.format.image_format();
+    let image = image::load_from_memory(&artwork.data)?;
+    let fits = image.width() <= channel.max_width && image.height() <= channel.max_height;
+    if fits && image::guess_format(&artwork.data).ok() == Some(target) {
+        return Ok(artwork.data.to_vec());
+    }
+    let image = if fits {
+        image
+    } else {
+        image.resize(
+            channel.max_width.max(1),
+            channel.max_height.max(1),
+            image::imageops::FilterType::Triangle,
+        )
+    };
+    // JPEG has no alpha channel
+    let image = match channel.format {
+        ArtworkFormat::Jpeg => image::DynamicImage::ImageRgb8(image.to_rgb8()),
+        _ => image,
+    };
+    let mut encoded = Cursor::new(Vec::new());
+    image.write_to(&mut encoded, target)?;
+    Ok(encoded.into_inner())
+}
+
+/// Send the pictures of `track` to a client with the artwork role
+///
+/// Nothing is sent when the client already shows them. Channels whose
+/// picture the track lacks are cleared.
+pub fn send_artwork(
+    client_manager: &ClientManager,
+    client_id: &str,
+    track: &TrackInfo,
+    timestamp: i64,
+) -> bool {
+    let Some(state) = client_manager.artwork_state(client_id) else {
+        return false;
+    };
+    if state.shown.album_art == track.album_art && state.shown.artist_art == track.artist_art {
+        return false;
+    }
+    let shown = TrackInfo {
+        album_art: track.album_art.clone(),
+        artist_art: track.artist_art.clone(),
+        ..Default::default()
+    };
+    client_manager.update_artwork_state(client_id, |state| state.shown = shown.clone());
+    send_channels(
+        client_manager,
+        client_id,
+        &state.channels,
+        &shown,
+        timestamp,
+    )
+}
+
+/// Send a client the pictures it shows again, after its channels changed
+pub fn resend_artwork(client_manager: &ClientManager, client_id: &str, timestamp: i64) -> bool {
+    let Some(state) = client_manager.artwork_state(client_id) else {
+        return false;
+    };
+    send_channels(
+        client_manager,
+        client_id,
+        &state.channels,
+        &state.shown,
+        timestamp,
+    )
+}
+
+fn send_channels(
+    client_manager: &ClientManager,
+    client_id: &str,
+    channels: &[ArtworkChannel],
+    track: &TrackInfo,
+    timestamp: i64,
+) -> bool {
+    let recipient = HashSet::from([client_id.to_string()]);
+    let mut sent = false;
+    for (channel, config) in channels.iter().enumerate() {
+        if config.source == ArtworkSource::None {
+            continue;
+        }
+        let payload = match config.picture(track).map(|art| render_artwork(art, config)) {
+            Some(Ok(image)) => image,
+            Some(Err(e)) => {
+                log::warn!("Cannot convert artwork for {}: {}", client_id, e);
+                Vec::new()
+            }
+            None => Vec::new(),
+        };
+        let frame = BinaryFrame::Artwork {
+            channel: channel as u8,
+            timestamp,
+            payload: &payload,
+        };
+        sent |= client_manager.send_frame_to(&recipient, &frame) > 0;
+    }
+    sent
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::client_manager::{ConnectedClient, ServerMessage};
+    use tokio::sync::mpsc;
+
+    /// A `width` x `height` PNG
+    fn png(width: u32, height: u32) -> Artwork {
+        let image = image::RgbaImage::from_pixel(width, height, image::Rgba([200, 10, 10, 255]));
+        let mut data = Cursor::new(Vec::new());
+        image.write_to(&mut data, image::ImageFormat::Png).unwrap();
+        Artwork {
+            media_type: "image/png".to_string(),
+            data: data.into_inner().into(),
+        }
+    }
+
+    fn frames(rx: &mut mpsc::UnboundedReceiver<ServerMessage>) -> Vec<(u8, Vec<u8>)> {
+        let mut frames = Vec::new();
+        while let Ok(ServerMessage::Binary(data)) = rx.try_recv() {
+            match BinaryFrame::decode(&data).unwrap() {
+                BinaryFrame::Artwork {
+                    channel, payload, ..
+                } => frames.push((channel, payload.to_vec())),
+                other => panic!("unexpected frame {:?}", other.message_type()),
+            }
+        }
+        frames
+    }
+
+    #[test]
+    fn test_render_scales_and_converts() {
+        let channel = ArtworkChannel {
+            max_width: 100,
+            max_height: 50,
+            ..Default::default()
+        };
+        let jpeg = render_artwork(&png(400, 400), &channel).unwrap();
+        assert_eq!(
+            image::guess_format(&jpeg).unwrap(),
+            image::ImageFormat::Jpeg
+        );
+        let decoded = image::load_from_memory(&jpeg).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (50, 50));
+
+        // Small enough and already PNG: passed through
+        let small = png(20, 20);
+        let channel = ArtworkChannel {
+            format: ArtworkFormat::Png,
+            ..channel
+        };
+        assert_eq!(
+            render_artwork(&small, &channel).unwrap(),
+            small.data.to_vec()
+        );
+    }
+
+    #[test]
+    fn test_state_follows_support_and_requests() {
+        let support = ArtworkSupport {
+            channels: vec![ArtworkChannelSpec {
+                source: "artist".to_string(),
+                format: "png".to_string(),
+                media_width: 64,
+                media_height: 64,
+            }],
+        };
+        let mut state = ArtworkState::from_support(Some(&support));
+        assert_eq!(state.channels[0].source, ArtworkSource::Artist);
+        assert_eq!(state.channels[0].format, ArtworkFormat::Png);
+        assert_eq!(
+            ArtworkState::from_support(None).channels,
+            [ArtworkChannel::default()]
+        );
+
+        let request = ArtworkFormatRequest {
+            channel: 2,
+            source: Some("album".to_string()),
+            format: Some("bmp".to_string()),
+            media_width: Some(32),
+            media_height: None,
+        };
+        assert!(state.apply(&request));
+        assert_eq!(state.channels.len(), 3);
+        assert_eq!(state.channels[1].source, ArtworkSource::None);
+        assert_eq!(state.channels[2].format, ArtworkFormat::Bmp);
+        assert_eq!(state.channels[2].max_width, 32);
+        assert!(!state.apply(&ArtworkFormatRequest {
+            channel: 4,
+            ..request
+        }));
+    }
+
+    #[test]
+    fn test_send_artwork_per_channel() {
+        let client_manager = ClientManager::new();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut client = ConnectedClient::new("frame".to_string(), "Frame".to_string(), tx);
+        client.active_roles = vec!["artwork@v1".to_string()];
+        client.artwork = ArtworkState::from_support(Some(&ArtworkSupport {
+            channels: vec![
+                ArtworkChannelSpec {
+                    source: "album".to_string(),
+                    format: "png".to_string(),
+                    media_width: 10,
+                    media_height: 10,
+                },
+                ArtworkChannelSpec {
+                    source: "artist".to_string(),
+                    format: "jpeg".to_string(),
+                    media_width: 10,
+                    media_height: 10,
+                },
+            ],
+        }));
+        client_manager.add_client(client);
+
+        let track = TrackInfo {
+            album_art: Some(png(40, 20)),
+            ..Default::default()
+        };
+        assert!(send_artwork(&client_manager, "frame", &track, 1000));
+        let sent = frames(&mut rx);
+        assert_eq!(sent.len(), 2);
+        let cover = image::load_from_memory(&sent[0].1).unwrap();
+        assert_eq!((sent[0].0, cover.width(), cover.height()), (0, 10, 5));
+        // No artist picture clears channel 1
+        assert_eq!(sent[1], (1, Vec::new()));
+
+        // The same pictures are not sent twice, unless the channels change
+        assert!(!send_artwork(&client_manager, "frame", &track, 2000));
+        assert!(frames(&mut rx).is_empty());
+        assert!(resend_artwork(&client_manager, "frame", 3000));
+        assert_eq!(frames(&mut rx).len(), 2);
+        assert!(!send_artwork(&client_manager, "missing", &track, 0));
+    }
+}
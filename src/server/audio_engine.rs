@@ -1,23 +1,46 @@
 // ABOUTME: Audio engine for generating and broadcasting audio chunks
 // ABOUTME: Runs a 20ms interval loop to generate synchronized audio
 
-use crate::audio::types::Sample;
-use crate::server::audio_source::AudioSource;
-use crate::server::client_manager::ClientManager;
+use crate::audio::downmix::{ChannelMap, Downmix, DownmixLevels};
+use crate::audio::drc::Compressor;
+use crate::audio::loudness::{LoudnessNormalization, LoudnessNormalizer};
+use crate::audio::resample::Resampler;
+use crate::audio::spatial::Widener;
+use crate::audio::types::{AudioFormat, Sample};
+use crate::protocol::binary::BinaryFrame;
+use crate::protocol::messages::{Message, StreamClear};
+use crate::server::announcement::{
+    mix_announcement, Announcement, AnnouncementQueue, ResampledAnnouncement,
+};
+use crate::server::audio_source::{open_track, AudioSource, SilenceSource};
+use crate::server::client_handler::create_stream_start;
+use crate::server::client_manager::{ClientId, ClientManager, OutputProcessing};
 use crate::server::clock::ServerClock;
-use crate::server::encoder::PcmEncoder;
-use crate::server::encoder::AudioEncoder;
+use crate::server::encoder::{
+    AudioEncoder, EncoderParams, EncoderRegistry, EncoderSettings, PcmEncoder, StreamFormat,
+};
+use crate::server::encoder_metrics::EncoderMetrics;
+use crate::server::group::{GroupManager, StreamTiming};
+use crate::server::profiling;
+use crate::server::source_control::SourceControl;
+use crate::server::source_fallback::{
+    spawn_open, FallbackConfig, FallbackSource, Opening, SourceOpener,
+};
+use crate::server::stream_manager::DEFAULT_STREAM;
+use crate::server::track_start::{ChunkTimeline, SilenceTrim, TrimSilence};
+use crate::sync::audit::{AuditLog, AuditStage};
+use crossbeam::channel::TryRecvError;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
-use std::time::Duration;
-use tokio::sync::watch;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, watch};
 use tokio::time::{interval, MissedTickBehavior};
 
-/// Audio chunk type byte for player role (per Sendspin Protocol spec)
-/// Spec: Binary message type 4 for player role audio chunks
-const AUDIO_CHUNK_TYPE: u8 = 0x04;
-
 /// Audio engine state
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum EngineState {
     /// Engine is stopped
     Stopped,
@@ -25,6 +48,87 @@ pub enum EngineState {
     Running,
     /// Engine is paused (maintains timing but sends silence)
     Paused,
+    /// Engine is idle because no players are connected (no decoding or sending)
+    Standby,
+}
+
+/// A change to a running engine, sent through its [`EngineHandle`]
+pub enum EngineCommand {
+    /// Stream the source again after a pause or stop
+    Play,
+    /// Keep the timeline running but stream silence
+    Pause,
+    /// Stop generating chunks
+    Stop,
+    /// Move on to the next track in the stream's play queue
+    Next,
+    /// Switch to another source live
+    SetSource(Box<dyn AudioSource>),
+}
+
+/// Controls an engine started with [`spawn_audio_engine`]
+///
+/// Commands take effect before the engine's next chunk, or when it leaves
+/// standby. Clones control the same engine.
+#[derive(Clone)]
+pub struct EngineHandle {
+    commands: mpsc::UnboundedSender<EngineCommand>,
+    state: watch::Receiver<EngineState>,
+    shutdown: Arc<watch::Sender<bool>>,
+}
+
+impl EngineHandle {
+    /// Send a command; false if the engine has exited
+    pub fn send(&self, command: EngineCommand) -> bool {
+        self.commands.send(command).is_ok()
+    }
+
+    /// Stream the source again after a pause or stop
+    pub fn play(&self) -> bool {
+        self.send(EngineCommand::Play)
+    }
+
+    /// Stream silence, keeping clients' timing
+    pub fn pause(&self) -> bool {
+        self.send(EngineCommand::Pause)
+    }
+
+    /// Stop generating chunks
+    pub fn stop(&self) -> bool {
+        self.send(EngineCommand::Stop)
+    }
+
+    /// Skip to the next queued track (the current one plays on if none is queued)
+    pub fn next(&self) -> bool {
+        self.send(EngineCommand::Next)
+    }
+
+    /// Switch the engine to another source live
+    ///
+    /// Players drop the old source's buffered audio.
+    pub fn set_source(&self, source: Box<dyn AudioSource>) -> bool {
+        self.send(EngineCommand::SetSource(source))
+    }
+
+    /// The engine's state as of its last change
+    pub fn state(&self) -> EngineState {
+        *self.state.borrow()
+    }
+
+    /// Ask the engine to exit
+    pub fn shutdown(&self) {
+        let _ = self.shutdown.send(true);
+    }
+}
+
+/// What one encoded copy of a chunk is made for: announcement mix, output
+/// processing, per-group processing and encoder tuning
+type EncodedKey = (bool, OutputProcessing, Option<String>, EncoderSettings);
+
+/// The copy a key is made from before gain, which volume changes leave alone
+fn full_gain(key: &EncodedKey) -> EncodedKey {
+    let output = OutputProcessing { gain: 100, ..key.1 };
+    (key.0, output, key.2.clone(), key.3)
 }
 
 /// Audio engine for generating and broadcasting audio chunks
@@ -33,6 +137,10 @@ pub struct AudioEngine {
     source: Box<dyn AudioSource>,
     /// Client manager for broadcasting
     client_manager: Arc<ClientManager>,
+    /// Group manager for playback state
+    group_manager: Arc<GroupManager>,
+    /// Stream whose subscribed groups this engine plays to
+    stream_id: String,
     /// Server clock for timestamps
     clock: Arc<ServerClock>,
     /// Chunk interval
@@ -43,8 +151,49 @@ pub struct AudioEngine {
     buffer_ahead_micros: i64,
     /// Current engine state
     state: EngineState,
-    /// Encoder for PCM
-    encoder: PcmEncoder,
+    /// Publishes state changes to [`EngineHandle`]s
+    state_tx: watch::Sender<EngineState>,
+    /// One encoder per encoded copy, so encoder state such as dither noise
+    /// and FLAC frame numbers follows a single stream, with the clients it
+    /// encoded for last
+    stream_encoders: HashMap<EncodedKey, (Box<dyn AudioEncoder>, HashSet<ClientId>)>,
+    /// Where the encoders come from
+    encoders: EncoderRegistry,
+    /// Tuning for groups without their own
+    encoder_settings: EncoderSettings,
+    /// Enter standby while no players are connected
+    idle_standby: bool,
+    /// Pending announcements
+    announcements: AnnouncementQueue,
+    /// Announcement currently being mixed in
+    announcement: Option<Announcement>,
+    /// Runtime source replacement and now-playing reporting
+    source_control: SourceControl,
+    /// Time spent encoding, per codec
+    encoder_metrics: EncoderMetrics,
+    /// Compressor state for each group in night mode
+    night_modes: HashMap<String, Compressor>,
+    /// Stereo width state for each group with width processing
+    wideners: HashMap<String, Widener>,
+    /// Rate conversion state for each copy sent at another rate than the
+    /// source's, keyed at full gain since gain is applied after it
+    resamplers: HashMap<EncodedKey, Resampler>,
+    /// Failover applied to every source the engine plays
+    source_fallback: Option<(FallbackConfig, SourceOpener)>,
+    /// Per-chunk generation and send times, for debugging scheduling
+    chunk_audit: Option<AuditLog>,
+    /// Back-to-back play-at times, aligned at each track start
+    timeline: ChunkTimeline,
+    /// Leading silence trimmed from each new source
+    silence_trim: Option<SilenceTrim>,
+    /// Brings each source toward a target loudness
+    loudness: Option<LoudnessNormalizer>,
+    /// Levels for clients negotiated with fewer channels than the source
+    downmix: DownmixLevels,
+    /// Opens tracks from the play queue
+    queue_opener: SourceOpener,
+    /// Queued track being opened, with its URI
+    opening: Option<(String, Opening)>,
 }
 
 impl AudioEngine {
@@ -52,6 +201,7 @@ impl AudioEngine {
     pub fn new(
         source: Box<dyn AudioSource>,
         client_manager: Arc<ClientManager>,
+        group_manager: Arc<GroupManager>,
         clock: Arc<ServerClock>,
         chunk_interval_ms: u64,
         buffer_ahead_ms: u64,
@@ -62,15 +212,180 @@ impl AudioEngine {
         Self {
             source,
             client_manager,
+            group_manager,
+            stream_id: DEFAULT_STREAM.to_string(),
             clock,
             chunk_interval: Duration::from_millis(chunk_interval_ms),
             samples_per_chunk,
             buffer_ahead_micros: (buffer_ahead_ms * 1000) as i64,
             state: EngineState::Stopped,
-            encoder: PcmEncoder::new(sample_rate, 2),
+            state_tx: watch::Sender::new(EngineState::Stopped),
+            stream_encoders: HashMap::new(),
+            encoders: EncoderRegistry::default(),
+            encoder_settings: EncoderSettings::default(),
+            idle_standby: false,
+            announcements: AnnouncementQueue::new(),
+            announcement: None,
+            source_control: SourceControl::new(),
+            encoder_metrics: EncoderMetrics::new(),
+            night_modes: HashMap::new(),
+            wideners: HashMap::new(),
+            resamplers: HashMap::new(),
+            source_fallback: None,
+            chunk_audit: None,
+            timeline: ChunkTimeline::new(Duration::from_millis(chunk_interval_ms)),
+            silence_trim: None,
+            loudness: None,
+            downmix: DownmixLevels::default(),
+            queue_opener: Arc::new(|uri: &str| {
+                open_track(uri, DownmixLevels::default(), None, None).map_err(|e| e.to_string())
+            }),
+            opening: None,
         }
     }
 
+    /// Only play to groups subscribed to the given stream
+    pub fn set_stream(&mut self, stream_id: impl Into<String>) {
+        self.stream_id = stream_id.into();
+    }
+
+    /// Take announcements from the given queue
+    pub fn set_announcements(&mut self, announcements: AnnouncementQueue) {
+        self.announcements = announcements;
+    }
+
+    /// Take replacement sources from the given control and report the current one to it
+    pub fn set_source_control(&mut self, source_control: SourceControl) {
+        source_control.started(self.source.as_mut());
+        self.source_control = source_control;
+    }
+
+    /// Fail over to a fallback whenever a source keeps failing
+    ///
+    /// Applies to the current source and every replacement; `opener` reopens
+    /// failed sources by their description and opens backup URIs.
+    pub fn set_source_fallback(&mut self, config: FallbackConfig, opener: SourceOpener) {
+        self.source_fallback = Some((config, opener));
+        let source = std::mem::replace(&mut self.source, Box::new(SilenceSource::new(0)));
+        self.source = self.with_fallback(source);
+        self.source.set_events(self.source_control.events());
+    }
+
+    /// Trim leading encoder/decoder silence from the current source and every replacement
+    ///
+    /// Call before [`set_source_fallback`](Self::set_source_fallback) so the
+    /// trim applies to the primary source rather than the fallback.
+    pub fn set_silence_trim(&mut self, trim: SilenceTrim) {
+        self.silence_trim = Some(trim);
+        let source = std::mem::replace(&mut self.source, Box::new(SilenceSource::new(0)));
+        self.source = self.with_silence_trim(source);
+        self.source.set_events(self.source_control.events());
+    }
+
+    /// Normalize the loudness of every source toward `settings`' target
+    ///
+    /// Each new source or queued track is measured afresh.
+    pub fn set_loudness_normalization(&mut self, settings: LoudnessNormalization) {
+        let mut normalizer = LoudnessNormalizer::new(settings, self.source.sample_rate());
+        normalizer.reset(self.source.sample_rate(), self.source.channels());
+        self.loudness = Some(normalizer);
+    }
+
+    /// Fold surround sources down with `levels` for clients negotiated with
+    /// fewer channels
+    pub fn set_downmix(&mut self, levels: DownmixLevels) {
+        self.downmix = levels;
+    }
+
+    /// Open tracks from the play queue with `opener` (which should not loop files)
+    pub fn set_queue_opener(&mut self, opener: SourceOpener) {
+        self.queue_opener = opener;
+    }
+
+    fn with_fallback(&self, source: Box<dyn AudioSource>) -> Box<dyn AudioSource> {
+        match &self.source_fallback {
+            Some((config, opener)) => {
+                Box::new(FallbackSource::new(source, config.clone(), opener.clone()))
+            }
+            None => source,
+        }
+    }
+
+    fn with_silence_trim(&self, source: Box<dyn AudioSource>) -> Box<dyn AudioSource> {
+        match self.silence_trim {
+            Some(trim) => Box::new(TrimSilence::new(source, trim)),
+            None => source,
+        }
+    }
+
+    /// Create stream encoders from the given registry
+    ///
+    /// Each client is streamed in the codec it negotiated, using the encoder
+    /// registered under that codec's name.
+    pub fn set_encoders(&mut self, encoders: EncoderRegistry) {
+        self.encoders = encoders;
+        self.stream_encoders.clear();
+    }
+
+    /// Tune encoders for groups without their own settings (bitrate,
+    /// complexity, compression level)
+    pub fn set_encoder_settings(&mut self, settings: EncoderSettings) {
+        self.encoder_settings = settings;
+        self.stream_encoders.clear();
+    }
+
+    /// Create an encoder for a negotiated format at its sample rate
+    ///
+    /// Negotiation only picks codecs in the registry, so this falls back to
+    /// 24-bit PCM only when an encoder rejects these parameters or the
+    /// registry was swapped after the client connected.
+    fn create_encoder(
+        &self,
+        format: StreamFormat,
+        settings: EncoderSettings,
+    ) -> Box<dyn AudioEncoder> {
+        let sample_rate = format.sample_rate;
+        let params = EncoderParams {
+            sample_rate,
+            channels: format.channels,
+            bit_depth: format.bit_depth,
+            chunk_frames: (sample_rate as u64 * self.chunk_interval.as_millis() as u64 / 1000)
+                as usize,
+            settings,
+        };
+        self.encoders
+            .create(format.codec.name(), params)
+            .unwrap_or_else(|e| {
+                log::warn!(
+                    "Streaming PCM instead of {} {}Hz {}ch {}bit: {}",
+                    format.codec.name(),
+                    format.sample_rate,
+                    format.channels,
+                    format.bit_depth,
+                    e
+                );
+                Box::new(PcmEncoder::new(sample_rate, format.channels))
+            })
+    }
+
+    /// Record when each chunk is generated and sent to the given audit log
+    pub fn set_chunk_audit(&mut self, audit: AuditLog) {
+        self.chunk_audit = Some(audit);
+    }
+
+    /// Record encoding time into the given metrics
+    pub fn set_encoder_metrics(&mut self, metrics: EncoderMetrics) {
+        self.encoder_metrics = metrics;
+    }
+
+    /// Enable or disable idle standby
+    ///
+    /// While no player clients are connected the engine stops reading the
+    /// source, so file playback resumes where it left off on the next connect.
+    pub fn set_idle_standby(&mut self, enabled: bool) {
+        self.idle_standby = enabled;
+    }
+
     /// Get the current state
     pub fn state(&self) -> EngineState {
         self.state
@@ -78,36 +393,90 @@ impl AudioEngine {
 
     /// Start the engine
     pub fn start(&mut self) {
-        self.state = EngineState::Running;
+        self.set_state(EngineState::Running);
     }
 
     /// Pause the engine
     pub fn pause(&mut self) {
-        self.state = EngineState::Paused;
+        self.set_state(EngineState::Paused);
     }
 
     /// Stop the engine
     pub fn stop(&mut self) {
-        self.state = EngineState::Stopped;
+        self.set_state(EngineState::Stopped);
     }
 
-    /// Run the audio engine loop
+    fn set_state(&mut self, state: EngineState) {
+        self.state = state;
+        self.state_tx.send_replace(state);
+    }
+
+    /// Apply a command from an [`EngineHandle`]
+    fn apply(&mut self, command: EngineCommand) {
+        match command {
+            EngineCommand::Play => self.start(),
+            EngineCommand::Pause => self.pause(),
+            EngineCommand::Stop => {
+                self.stop();
+                self.timeline.stop();
+            }
+            EngineCommand::Next => {
+                if self.source_control.queue().is_empty() {
+                    log::debug!("Nothing queued on stream {} to skip to", self.stream_id);
+                    return;
+                }
+                self.open_next_queued();
+            }
+            EngineCommand::SetSource(source) => {
+                self.switch_source(source);
+                return;
+            }
+        }
+        log::info!(
+            "Audio engine for stream {} is {:?}",
+            self.stream_id,
+            self.state
+        );
+    }
+
+    /// Run the audio engine loop until `shutdown`, applying `commands` between chunks
     ///
     /// This should be spawned as a separate task
-    pub async fn run(&mut self, mut shutdown: watch::Receiver<bool>) {
+    pub async fn run(
+        &mut self,
+        mut shutdown: watch::Receiver<bool>,
+        mut commands: mpsc::UnboundedReceiver<EngineCommand>,
+    ) {
         let mut ticker = interval(self.chunk_interval);
         ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
 
         log::info!(
-            "Audio engine started: {}ms chunks, {} samples/chunk, {} buffer ahead",
+            "Audio engine for stream {} started: {}ms chunks, {} samples/chunk, {} buffer ahead",
+            self.stream_id,
             self.chunk_interval.as_millis(),
             self.samples_per_chunk,
             self.buffer_ahead_micros / 1000
         );
 
-        self.state = EngineState::Running;
+        self.set_state(EngineState::Running);
+        let mut players = self.client_manager.subscribe_player_count();
 
         loop {
+            if self.idle_standby && *players.borrow() == 0 {
+                let resume_state = self.state;
+                self.set_state(EngineState::Standby);
+                log::info!("No players connected, audio engine entering standby");
+
+                if !wait_for_players(&mut players, &mut shutdown).await {
+                    log::info!("Audio engine shutting down");
+                    break;
+                }
+
+                log::info!("Player connected, audio engine resuming");
+                self.set_state(resume_state);
+                ticker.reset();
+            }
+
             tokio::select! {
                 _ = ticker.tick() => {
                     if self.state == EngineState::Stopped {
@@ -116,6 +485,7 @@ impl AudioEngine {
 
                     self.generate_and_broadcast_chunk();
                 }
+                Some(command) = commands.recv() => self.apply(command),
                 _ = shutdown.changed() => {
                     if *shutdown.borrow() {
                         log::info!("Audio engine shutting down");
@@ -125,80 +495,537 @@ impl AudioEngine {
             }
         }
 
-        self.state = EngineState::Stopped;
+        self.set_state(EngineState::Stopped);
+        if let Some(audit) = &self.chunk_audit {
+            audit.flush();
+        }
     }
 
-    /// Generate a single audio chunk and broadcast it
+    /// Generate a single audio chunk and broadcast it to playing groups
     fn generate_and_broadcast_chunk(&mut self) {
-        // Get current time and calculate playback timestamp
-        let now = self.clock.now_micros();
-        let play_at = now + self.buffer_ahead_micros;
+        let _tick = profiling::scope("engine_tick");
+        if let Some(source) = self.source_control.take() {
+            self.switch_source(source);
+        }
+        if self.source_control.take_skip() {
+            self.open_next_queued();
+        }
+        self.poll_queued();
+
+        // Don't decode anything while no group on this stream is playing
+        let groups = self.group_manager.playing_groups_on(&self.stream_id);
+        if groups.is_empty() {
+            self.timeline.stop();
+            return;
+        }
+
+        let sample_rate = self.source.sample_rate();
+        let channels = self.source.channels();
+        let Some(now) =
+            self.timeline
+                .next(self.clock.now_micros(), self.samples_per_chunk, sample_rate)
+        else {
+            // Far enough ahead already; let the clock catch up
+            return;
+        };
 
         // Generate audio samples
         let samples = if self.state == EngineState::Paused {
             // Send silence when paused
-            vec![Sample::ZERO; self.samples_per_chunk * 2]
+            vec![Sample::ZERO; self.samples_per_chunk * channels as usize]
         } else {
             // Get samples from source
+            let _read = profiling::scope("source_read");
             match self.source.read_chunk(self.samples_per_chunk) {
-                Some(samples) => samples,
+                Some(samples) => match &mut self.loudness {
+                    Some(normalizer) => {
+                        let _normalize = profiling::scope("loudness");
+                        normalizer.set_replay_gain(self.source.replay_gain_db().is_some());
+                        normalizer.process(&samples)
+                    }
+                    None => samples,
+                },
                 None => {
-                    // Source exhausted, send silence
-                    vec![Sample::ZERO; self.samples_per_chunk * 2]
+                    // Source exhausted, send silence until the next queued track opens
+                    if self.opening.is_none() {
+                        self.open_next_queued();
+                    }
+                    vec![Sample::ZERO; self.samples_per_chunk * channels as usize]
                 }
             }
         };
 
-        // Encode to PCM
-        let encoded = self.encoder.encode(&samples);
+        let generated = self.clock.now_micros();
+        let announcement = self.announcement_chunk(&samples, &groups);
+
+        // Encode each (mix, output processing, per-group processing, tuning)
+        // combination at most once per chunk
+        // Encoded chunks with their play-at offset, which resampling can shift
+        let mut encoded: HashMap<EncodedKey, (Vec<u8>, i64)> = HashMap::new();
+        // Processed and resampled audio before gain, shared by copies that
+        // differ only in volume
+        let mut prepared: HashMap<EncodedKey, (Vec<Sample>, i64)> = HashMap::new();
+        let mut night_groups = HashSet::new();
+        let mut width_groups = HashSet::new();
+        let mut audited = HashSet::new();
+
+        // Each group plays the chunk at its own buffer-ahead offset
+        for (group_id, members, buffer_ahead_ms) in groups {
+            let (mix, buffer_ahead_micros) = match (&announcement, &self.announcement) {
+                (Some((mixed, micros)), Some(active)) if active.targets(&group_id) => {
+                    (Some(mixed), *micros)
+                }
+                _ => (
+                    None,
+                    buffer_ahead_ms
+                        .map(|ms| (ms * 1000) as i64)
+                        .unwrap_or(self.buffer_ahead_micros),
+                ),
+            };
+            let play_at = now + buffer_ahead_micros;
+            let timing = StreamTiming {
+                chunk_interval_ms: self.chunk_interval.as_millis() as u64,
+                buffer_ahead_ms: buffer_ahead_ms.unwrap_or(self.buffer_ahead_micros as u64 / 1000),
+            };
+
+            // Night mode compresses this group's copy of the audio
+            let night = self.group_manager.get_night_mode(&group_id).map(|profile| {
+                let sample_rate = self.source.sample_rate();
+                let create = || Compressor::new(profile, sample_rate).with_channels(channels);
+                let compressor = self
+                    .night_modes
+                    .entry(group_id.clone())
+                    .or_insert_with(create);
+                if compressor.profile() != profile {
+                    *compressor = create();
+                }
+                compressor.process(mix.unwrap_or(&samples))
+            });
+            if night.is_some() {
+                night_groups.insert(group_id.clone());
+            }
+
+            // Stereo width reshapes the image after any compression
+            let widened = self
+                .group_manager
+                .get_stereo_width(&group_id)
+                .filter(|profile| !profile.is_bypass())
+                .map(|profile| {
+                    let sample_rate = self.source.sample_rate();
+                    let create = || Widener::new(profile, sample_rate).with_channels(channels);
+                    let widener = self.wideners.entry(group_id.clone()).or_insert_with(create);
+                    if widener.profile() != profile {
+                        *widener = create();
+                    }
+                    widener.process(night.as_deref().unwrap_or(mix.unwrap_or(&samples)))
+                });
+            if widened.is_some() {
+                width_groups.insert(group_id.clone());
+            }
+            let group_audio = widened.or(night);
+            let group_key = group_audio.as_ref().map(|_| group_id.clone());
+
+            let settings = self
+                .group_manager
+                .get_encoder_settings(&group_id)
+                .unwrap_or_default()
+                .or(self.encoder_settings);
+
+            // Clients get their negotiated format and any pinned encoder
+            // tuning; those without volume command support get their volume
+            // applied to the audio, and clients get their channel map
+            for (output, clients) in self.client_manager.group_by_output(&members) {
+                let settings = output.encoder.or(settings);
+                let key = (mix.is_some(), output, group_key.clone(), settings);
+                let stream_key = full_gain(&key);
+                if !self.stream_encoders.contains_key(&key) {
+                    // The same clients at a new volume keep their encoder
+                    let previous = self.stream_encoders.iter().find_map(|(old, (_, used))| {
+                        let moved = *used == clients
+                            && !encoded.contains_key(old)
+                            && full_gain(old) == stream_key;
+                        moved.then(|| old.clone())
+                    });
+                    let entry = match previous.and_then(|old| self.stream_encoders.remove(&old)) {
+                        Some(entry) => entry,
+                        None => (self.create_encoder(output.format, settings), HashSet::new()),
+                    };
+                    self.stream_encoders.insert(key.clone(), entry);
+                }
+                let (encoder, used) = self
+                    .stream_encoders
+                    .get_mut(&key)
+                    .expect("encoder created above");
+                if !encoded.contains_key(&key) {
+                    used.clear();
+                }
+                used.extend(clients.iter().cloned());
+
+                // Players on a slower link tier play further behind the
+                // rest of the group; announcements keep their own offset
+                let tiers: Vec<(i64, StreamTiming, HashSet<ClientId>)> = match mix {
+                    Some(_) => vec![(play_at, timing, clients)],
+                    None => self
+                        .client_manager
+                        .split_by_buffer_ahead(&clients, timing.buffer_ahead_ms)
+                        .into_iter()
+                        .map(|(ms, ids)| {
+                            let tier_timing = StreamTiming {
+                                buffer_ahead_ms: ms,
+                                ..timing
+                            };
+                            (now + (ms * 1000) as i64, tier_timing, ids)
+                        })
+                        .collect(),
+                };
+                for (_, tier_timing, ids) in &tiers {
+                    announce_format(&self.client_manager, ids, encoder.as_ref(), *tier_timing);
+                }
+
+                let (data, offset) = encoded.entry(key.clone()).or_insert_with(|| {
+                    let (audio, offset) = prepared.entry(stream_key).or_insert_with_key(|key| {
+                        let source = match &group_audio {
+                            Some(group_samples) => group_samples,
+                            None => mix.unwrap_or(&samples),
+                        };
+                        let processed = {
+                            let _process = profiling::scope("process");
+                            let counts = (channels, encoder.channels());
+                            process_for_output(source, output, counts, self.downmix)
+                        };
+                        // Clients negotiated at another rate get the audio
+                        // converted to it, timestamped by where its first
+                        // frame falls
+                        if encoder.sample_rate() == sample_rate {
+                            return (processed.into_owned(), 0);
+                        }
+                        let _resample = profiling::scope("resample");
+                        let resampler = self.resamplers.entry(key.clone()).or_insert_with(|| {
+                            Resampler::new(sample_rate, encoder.sample_rate(), encoder.channels())
+                        });
+                        let offset = resampler.output_offset_micros();
+                        (resampler.process(&processed), offset)
+                    });
+                    let processed = match output.gain {
+                        100 => Cow::Borrowed(audio.as_slice()),
+                        gain => Cow::Owned(apply_gain(audio, gain)),
+                    };
+                    let _encode = profiling::scope("encode");
+                    let started = Instant::now();
+                    let data = encoder.encode(&processed);
+                    self.encoder_metrics.record(
+                        encoder.codec(),
+                        started.elapsed(),
+                        self.chunk_interval,
+                    );
+                    (data, *offset)
+                });
+
+                for (play_at, _, ids) in tiers {
+                    let timestamp = play_at + *offset;
+                    let message = BinaryFrame::AudioChunk {
+                        timestamp,
+                        payload: data,
+                    }
+                    .encode();
+                    self.client_manager.broadcast_audio_to(&ids, message);
+
+                    // Groups and tiers sharing a buffer-ahead share the chunk's
+                    // play-at time
+                    if let Some(audit) = &self.chunk_audit {
+                        if audited.insert(timestamp) {
+                            audit.record(AuditStage::Generated, timestamp, generated);
+                            audit.record(AuditStage::Sent, timestamp, self.clock.now_micros());
+                        }
+                    }
+                }
+            }
+        }
+
+        // Groups that left night mode, dropped width processing or stopped
+        // playing start fresh next time
+        self.night_modes
+            .retain(|group_id, _| night_groups.contains(group_id));
+        self.wideners
+            .retain(|group_id, _| width_groups.contains(group_id));
+        self.resamplers.retain(|key, _| prepared.contains_key(key));
+        self.stream_encoders
+            .retain(|key, _| encoded.contains_key(key));
+    }
+
+    /// Mix the active announcement (starting the next queued one if needed)
+    ///
+    /// Returns the mixed samples and their buffer-ahead in microseconds, or None
+    /// if no announcement is playing this chunk.
+    fn announcement_chunk(
+        &mut self,
+        music: &[Sample],
+        groups: &[(String, HashSet<String>, Option<u64>)],
+    ) -> Option<(Vec<Sample>, i64)> {
+        if self.announcement.is_none() {
+            let mut next = self.announcements.next()?;
+            let (from, to) = (next.source.sample_rate(), self.source.sample_rate());
+            if from != to {
+                log::debug!("Resampling announcement from {}Hz to {}Hz", from, to);
+                next.source = Box::new(ResampledAnnouncement::new(next.source, to));
+            }
+            self.start_announcement(&next, groups);
+            self.announcement = Some(next);
+        }
+
+        let active = self.announcement.as_mut()?;
+        let Some(chunk) = active.source.read_chunk(self.samples_per_chunk) else {
+            log::info!("Announcement finished");
+            self.announcement = None;
+            return None;
+        };
+        // Announcements are stereo; a surround stream carries them on its front pair
+        let (from, to) = (active.source.channels(), self.source.channels());
+        let chunk = if from == to {
+            chunk
+        } else {
+            Downmix::between(from, to, self.downmix).apply(&chunk)
+        };
+        let mixed = mix_announcement(music, &chunk, active.mix);
+        let buffer_ahead_micros = (active.buffer_ahead_ms * 1000) as i64;
+        Some((mixed, buffer_ahead_micros))
+    }
+
+    /// Drop queued music on the targeted players so the announcement plays promptly
+    fn start_announcement(
+        &self,
+        announcement: &Announcement,
+        groups: &[(String, HashSet<String>, Option<u64>)],
+    ) {
+        log::info!(
+            "Announcement started ({:?}, {}ms buffer ahead)",
+            announcement.mix,
+            announcement.buffer_ahead_ms
+        );
+        let Ok(clear) = serde_json::to_string(&Message::StreamClear(StreamClear { roles: None }))
+        else {
+            return;
+        };
+        for (group_id, members, _) in groups {
+            if !announcement.targets(group_id) {
+                continue;
+            }
+            for member in members {
+                if self.client_manager.is_player(member) {
+                    self.client_manager.send_to_client(member, &clear);
+                }
+            }
+        }
+    }
+
+    /// Start opening the next track in the play queue, abandoning any being opened
+    fn open_next_queued(&mut self) {
+        self.opening = self.source_control.queue().pop().map(|entry| {
+            (
+                entry.uri.clone(),
+                spawn_open(self.queue_opener.clone(), entry.uri),
+            )
+        });
+    }
 
-        // Build binary message: [type=0x04][timestamp: i64 BE][audio data]
-        let mut message = Vec::with_capacity(9 + encoded.len());
-        message.push(AUDIO_CHUNK_TYPE);
-        message.extend_from_slice(&play_at.to_be_bytes());
-        message.extend_from_slice(&encoded);
+    /// Switch to a queued track that finished opening, or move past one that failed
+    fn poll_queued(&mut self) {
+        let Some((uri, rx)) = &self.opening else {
+            return;
+        };
+        match rx.try_recv() {
+            Ok(Ok(source)) => {
+                log::info!("Playing queued track {}", uri);
+                self.opening = None;
+                self.set_source(source);
+            }
+            Ok(Err(e)) => {
+                log::warn!("Skipping queued track {}: {}", uri, e);
+                self.open_next_queued();
+            }
+            Err(TryRecvError::Empty) => {}
+            Err(TryRecvError::Disconnected) => self.opening = None,
+        }
+    }
 
-        // Broadcast to all clients
-        self.client_manager.broadcast_audio(&message);
+    /// Switch to `source` live, dropping what players buffered of the old one
+    ///
+    /// Players on the stream get `stream/clear` and hear the new source one
+    /// buffer-ahead later; the new source is resampled to their negotiated
+    /// rate, so their stream format stays the same.
+    fn switch_source(&mut self, source: Box<dyn AudioSource>) {
+        log::info!(
+            "Switching source to {}",
+            source
+                .description()
+                .unwrap_or_else(|| "(unnamed)".to_string())
+        );
+        self.set_source(source);
+        self.timeline.stop();
+        self.clear_players();
+    }
+
+    /// Send `stream/clear` to the players of every group on this stream
+    fn clear_players(&self) {
+        let Ok(clear) = serde_json::to_string(&Message::StreamClear(StreamClear { roles: None }))
+        else {
+            return;
+        };
+        for (_, members, _) in self.group_manager.playing_groups_on(&self.stream_id) {
+            for member in members {
+                if self.client_manager.is_player(&member) {
+                    self.client_manager.send_to_client(&member, &clear);
+                }
+            }
+        }
     }
 
     /// Change the audio source
     pub fn set_source(&mut self, source: Box<dyn AudioSource>) {
-        self.source = source;
+        self.source = self.with_fallback(self.with_silence_trim(source));
+        self.timeline.restart();
         let sample_rate = self.source.sample_rate();
-        self.samples_per_chunk = (sample_rate as u64 * self.chunk_interval.as_millis() as u64 / 1000) as usize;
-        self.encoder = PcmEncoder::new(sample_rate, 2);
+        self.samples_per_chunk =
+            (sample_rate as u64 * self.chunk_interval.as_millis() as u64 / 1000) as usize;
+        self.stream_encoders.clear();
+        self.night_modes.clear();
+        self.wideners.clear();
+        self.resamplers.clear();
+        if let Some(normalizer) = &mut self.loudness {
+            normalizer.reset(sample_rate, self.source.channels());
+        }
+        self.source_control.started(self.source.as_mut());
     }
 }
 
-/// Spawn an audio engine task
-pub fn spawn_audio_engine(
-    source: Box<dyn AudioSource>,
-    client_manager: Arc<ClientManager>,
-    clock: Arc<ServerClock>,
-    chunk_interval_ms: u64,
-    buffer_ahead_ms: u64,
-) -> (tokio::task::JoinHandle<()>, watch::Sender<bool>) {
+/// Send `stream/start` to clients not yet told the format they are streamed in
+///
+/// The negotiated format can differ from the encoded one: some encoders add
+/// a codec header, and a codec without a usable encoder falls back to PCM.
+/// The announcement carries the group's chunk timing, ignoring any
+/// announcement's temporary buffer-ahead.
+fn announce_format(
+    client_manager: &ClientManager,
+    clients: &HashSet<ClientId>,
+    encoder: &dyn AudioEncoder,
+    timing: StreamTiming,
+) {
+    let format = AudioFormat {
+        codec: encoder.codec(),
+        sample_rate: encoder.sample_rate(),
+        channels: encoder.channels(),
+        bit_depth: encoder.bit_depth(),
+        codec_header: encoder.codec_header(),
+    };
+    for client_id in client_manager.update_stream_format(clients, &format) {
+        log::info!(
+            "Streaming {} {}Hz {}ch {}bit to {}",
+            format.codec.name(),
+            format.sample_rate,
+            format.channels,
+            format.bit_depth,
+            client_id
+        );
+        let fec = client_manager.get_fec(&client_id);
+        let start = create_stream_start(&format, fec, Some(timing));
+        if let Ok(json) = serde_json::to_string(&start) {
+            client_manager.send_to_client(&client_id, &json);
+        }
+    }
+}
+
+/// Apply a client cohort's channel map, then match the encoder's channel count
+///
+/// Gain is left to the caller, after any resampling.
+///
+/// `(source, channels)` are the source's and encoder's channel counts. A
+/// surround source is folded down with `levels` for an encoder with fewer
+/// channels, and a smaller layout spread over the front of one with more.
+/// Channel maps apply to stereo only. A one-channel encoder gets the mono
+/// sum, or the one side a client is mapped to.
+fn process_for_output(
+    samples: &[Sample],
+    output: OutputProcessing,
+    (source, channels): (u8, u8),
+    levels: DownmixLevels,
+) -> Cow<'_, [Sample]> {
+    let mut processed = Cow::Borrowed(samples);
+    let mut carried = source;
+    if source > 2 && channels < source {
+        carried = channels.max(2);
+        processed = Cow::Owned(Downmix::between(source, carried, levels).apply(&processed));
+    }
+    if carried == 2 {
+        let map = match output.channel_map {
+            ChannelMap::Stereo | ChannelMap::Swap if channels == 1 => ChannelMap::Mono,
+            map => map,
+        };
+        if map != ChannelMap::Stereo {
+            processed = Cow::Owned(map.apply(&processed));
+        }
+        if channels == 1 {
+            processed = Cow::Owned(processed.iter().step_by(2).copied().collect());
+        }
+    }
+    if channels > carried {
+        processed = Cow::Owned(Downmix::between(carried, channels, levels).apply(&processed));
+    }
+    processed
+}
+
+/// Scale samples by a gain in percent
+fn apply_gain(samples: &[Sample], percent: u8) -> Vec<Sample> {
+    samples
+        .iter()
+        .map(|s| Sample(s.0 * percent as f32 / 100.0))
+        .collect()
+}
+
+/// Wait until at least one player is connected
+///
+/// Returns false if shutdown was requested first.
+async fn wait_for_players(
+    players: &mut watch::Receiver<usize>,
+    shutdown: &mut watch::Receiver<bool>,
+) -> bool {
+    loop {
+        tokio::select! {
+            result = players.wait_for(|count| *count > 0) => return result.is_ok(),
+            _ = shutdown.changed() => {
+                if *shutdown.borrow() {
+                    return false;
+                }
+            }
+        }
+    }
+}
+
+/// Spawn an audio engine task, returning a handle for controlling it
+pub fn spawn_audio_engine(mut engine: AudioEngine) -> (tokio::task::JoinHandle<()>, EngineHandle) {
     let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let (commands_tx, commands_rx) = mpsc::unbounded_channel();
+    let control = EngineHandle {
+        commands: commands_tx,
+        state: engine.state_tx.subscribe(),
+        shutdown: Arc::new(shutdown_tx),
+    };
 
     let handle = tokio::spawn(async move {
-        let mut engine = AudioEngine::new(
-            source,
-            client_manager,
-            clock,
-            chunk_interval_ms,
-            buffer_ahead_ms,
-        );
-        engine.run(shutdown_rx).await;
+        engine.run(shutdown_rx, commands_rx).await;
     });
 
-    (handle, shutdown_tx)
+    (handle, control)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::audio::dither::DitherMode;
+    use crate::audio::downmix::{fold_to_mono, Speaker};
+    use crate::audio::types::Codec;
     use crate::server::audio_source::TestToneSource;
+    use crate::server::client_manager::{ConnectedClient, ServerMessage};
+    use crate::server::link_tier::LinkTier;
 
     #[test]
     fn test_engine_creation() {
@@ -206,10 +1033,694 @@ mod tests {
         let client_manager = Arc::new(ClientManager::new());
         let clock = Arc::new(ServerClock::new());
 
-        let engine = AudioEngine::new(source, client_manager, clock, 20, 500);
+        let group_manager = Arc::new(GroupManager::new());
+
+        let engine = AudioEngine::new(source, client_manager, group_manager, clock, 20, 500);
 
         assert_eq!(engine.state(), EngineState::Stopped);
         // 48000 Hz * 20ms = 960 samples
         assert_eq!(engine.samples_per_chunk, 960);
     }
+
+    #[tokio::test]
+    async fn test_engine_standby_until_player_connects() {
+        let source = Box::new(TestToneSource::new(440.0, 48000));
+        let client_manager = Arc::new(ClientManager::new());
+        let clock = Arc::new(ServerClock::new());
+
+        let group_manager = Arc::new(GroupManager::new());
+        group_manager.set_playback_state("default", crate::server::group::PlaybackState::Playing);
+
+        let mut engine = AudioEngine::new(
+            source,
+            client_manager.clone(),
+            group_manager.clone(),
+            clock,
+            20,
+            500,
+        );
+        engine.set_idle_standby(true);
+        let (handle, control) = spawn_audio_engine(engine);
+        wait_for_state(&control, EngineState::Standby).await;
+
+        // A non-player client does not wake the engine
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        client_manager.add_client(ConnectedClient::new("ctl".into(), "Controller".into(), tx));
+        assert_eq!(client_manager.player_count(), 0);
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut player = ConnectedClient::new("p1".into(), "Player".into(), tx);
+        player.active_roles = vec!["player@v1".to_string()];
+        client_manager.add_client(player);
+        group_manager.add_to_group("p1", "default");
+        assert_eq!(client_manager.player_count(), 1);
+
+        let chunk = tokio::time::timeout(Duration::from_secs(1), rx.recv()).await;
+        assert!(matches!(chunk, Ok(Some(ServerMessage::Binary(_)))));
+        assert_eq!(control.state(), EngineState::Running);
+
+        control.shutdown();
+        let _ = handle.await;
+    }
+
+    async fn wait_for_state(control: &EngineHandle, state: EngineState) {
+        let mut states = control.state.clone();
+        tokio::time::timeout(Duration::from_secs(1), states.wait_for(|now| *now == state))
+            .await
+            .expect("engine state")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_handle_pauses_and_stops_engine() {
+        let source = Box::new(TestToneSource::new(440.0, 48000));
+        let client_manager = Arc::new(ClientManager::new());
+        let group_manager = Arc::new(GroupManager::new());
+        let clock = Arc::new(ServerClock::new());
+        group_manager.set_playback_state("default", crate::server::group::PlaybackState::Playing);
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut player = ConnectedClient::new("p1".into(), "Player".into(), tx);
+        player.active_roles = vec!["player@v1".to_string()];
+        client_manager.add_client(player);
+        group_manager.add_to_group("p1", "default");
+
+        let engine = AudioEngine::new(source, client_manager, group_manager, clock, 20, 500);
+        let (handle, control) = spawn_audio_engine(engine);
+        wait_for_state(&control, EngineState::Running).await;
+
+        assert!(control.pause());
+        wait_for_state(&control, EngineState::Paused).await;
+        // Nothing is queued, so next leaves the engine as it was
+        assert!(control.next());
+        assert!(control.play());
+        wait_for_state(&control, EngineState::Running).await;
+
+        assert!(control.stop());
+        wait_for_state(&control, EngineState::Stopped).await;
+        while rx.try_recv().is_ok() {}
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(rx.try_recv().is_err(), "a stopped engine sends no chunks");
+
+        control.shutdown();
+        let _ = handle.await;
+        assert!(!control.play());
+    }
+
+    #[test]
+    fn test_per_group_buffer_ahead_timestamps() {
+        let source = Box::new(TestToneSource::new(440.0, 48000));
+        let client_manager = Arc::new(ClientManager::new());
+        let group_manager = Arc::new(GroupManager::new());
+        let clock = Arc::new(ServerClock::new());
+
+        group_manager.create_group("bluetooth", "Bluetooth Speakers");
+
+        let mut receivers = Vec::new();
+        for (id, group) in [("wired", "default"), ("bt", "bluetooth")] {
+            let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+            let mut client = ConnectedClient::new(id.into(), id.into(), tx);
+            client.active_roles = vec!["player@v1".to_string()];
+            client_manager.add_client(client);
+            group_manager.add_to_group(id, group);
+            group_manager.set_playback_state(group, crate::server::group::PlaybackState::Playing);
+            receivers.push(rx);
+        }
+        group_manager.set_buffer_ahead("bluetooth", Some(2000));
+
+        let mut engine = AudioEngine::new(source, client_manager, group_manager, clock, 20, 500);
+        engine.state = EngineState::Running;
+        engine.generate_and_broadcast_chunk();
+
+        let timestamps: Vec<i64> = receivers
+            .iter_mut()
+            .map(|rx| match rx.try_recv() {
+                Ok(ServerMessage::Binary(data)) => BinaryFrame::decode(&data).unwrap().timestamp(),
+                other => panic!("Expected audio chunk, got {:?}", other),
+            })
+            .collect();
+
+        // Bluetooth group plays 1.5s later than the default 500ms group
+        let offset = timestamps[1] - timestamps[0];
+        assert!(
+            (1_400_000..=1_600_000).contains(&offset),
+            "offset {}",
+            offset
+        );
+    }
+
+    #[test]
+    fn test_link_tier_delays_only_its_players() {
+        let source = Box::new(TestToneSource::new(440.0, 48000));
+        let client_manager = Arc::new(ClientManager::new());
+        let group_manager = Arc::new(GroupManager::new());
+        let clock = Arc::new(ServerClock::new());
+
+        let mut receivers = Vec::new();
+        for id in ["wired", "porch", "den"] {
+            let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+            let mut client = ConnectedClient::new(id.into(), id.into(), tx);
+            client.active_roles = vec!["player@v1".to_string()];
+            client_manager.add_client(client);
+            group_manager.add_to_group(id, "default");
+            receivers.push(rx);
+        }
+        group_manager.set_playback_state("default", crate::server::group::PlaybackState::Playing);
+        client_manager.set_link_tier("porch", Some(LinkTier::Bluetooth));
+        client_manager.set_link_tier("den", Some(LinkTier::Wireless));
+        client_manager.set_tier_buffer_ahead(LinkTier::Bluetooth, Some(2000));
+
+        let mut engine = AudioEngine::new(source, client_manager, group_manager, clock, 20, 500);
+        engine.state = EngineState::Running;
+        engine.generate_and_broadcast_chunk();
+
+        let timestamps: Vec<i64> = receivers
+            .iter_mut()
+            .map(|rx| loop {
+                match rx.try_recv() {
+                    Ok(ServerMessage::Binary(data)) => {
+                        break BinaryFrame::decode(&data).unwrap().timestamp()
+                    }
+                    Ok(ServerMessage::Text(_)) => continue,
+                    other => panic!("Expected audio chunk, got {:?}", other),
+                }
+            })
+            .collect();
+
+        // Only the Bluetooth player is pushed back; the wireless tier has no
+        // buffer-ahead of its own, so its player stays with the group
+        assert_eq!(timestamps[0], timestamps[2]);
+        let offset = timestamps[1] - timestamps[0];
+        assert_eq!(offset, 1_500_000);
+    }
+
+    /// Finite source playing a fixed number of chunks
+    struct Clip {
+        chunks: usize,
+        sample_rate: u32,
+    }
+
+    impl AudioSource for Clip {
+        fn read_chunk(&mut self, samples_per_channel: usize) -> Option<Vec<Sample>> {
+            self.chunks = self.chunks.checked_sub(1)?;
+            Some(vec![Sample(0.25); samples_per_channel * 2])
+        }
+
+        fn sample_rate(&self) -> u32 {
+            self.sample_rate
+        }
+
+        fn channels(&self) -> u8 {
+            2
+        }
+
+        fn is_exhausted(&self) -> bool {
+            self.chunks == 0
+        }
+    }
+
+    #[test]
+    fn test_queue_plays_after_source_ends_and_on_skip() {
+        let client_manager = Arc::new(ClientManager::new());
+        let group_manager = Arc::new(GroupManager::new());
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut player = ConnectedClient::new("p1".into(), "Player".into(), tx);
+        player.active_roles = vec!["player@v1".to_string()];
+        client_manager.add_client(player);
+        group_manager.add_to_group("p1", "default");
+        group_manager.set_playback_state("default", crate::server::group::PlaybackState::Playing);
+
+        let source = Box::new(Clip {
+            chunks: 1,
+            sample_rate: 48000,
+        });
+        let clock = Arc::new(ServerClock::new());
+        let mut engine = AudioEngine::new(source, client_manager, group_manager, clock, 20, 500);
+        engine.state = EngineState::Running;
+        engine.set_queue_opener(Arc::new(|uri: &str| match uri {
+            "quiet" => Ok(Box::new(SilenceSource::new(48000)) as Box<dyn AudioSource>),
+            "tone" => Ok(Box::new(TestToneSource::new(440.0, 48000))),
+            _ => Err(format!("no such track {}", uri)),
+        }));
+        let control = SourceControl::new();
+        engine.set_source_control(control.clone());
+        let queue = control.queue();
+        queue.enqueue("missing");
+        queue.enqueue("quiet");
+
+        let mut run_until = |playing: &str| {
+            for _ in 0..200 {
+                engine.generate_and_broadcast_chunk();
+                if control.now_playing().source.as_deref() == Some(playing) {
+                    return;
+                }
+                std::thread::sleep(Duration::from_millis(5));
+            }
+            panic!("never played {}", playing);
+        };
+        // The clip ends, the missing track is passed over
+        run_until("Silence");
+        assert!(queue.is_empty());
+
+        // Silence never ends, but can be skipped
+        assert!(!control.skip());
+        queue.enqueue("tone");
+        assert!(control.skip());
+        run_until("Test tone 440 Hz");
+    }
+
+    #[test]
+    fn test_announcement_targets_group_with_short_buffer() {
+        use crate::server::announcement::AnnouncementMix;
+
+        let source = Box::new(crate::server::audio_source::SilenceSource::new(48000));
+        let client_manager = Arc::new(ClientManager::new());
+        let group_manager = Arc::new(GroupManager::new());
+        let clock = Arc::new(ServerClock::new());
+
+        group_manager.create_group("kitchen", "Kitchen");
+
+        let mut receivers = Vec::new();
+        for (id, group) in [("lounge", "default"), ("kitchen", "kitchen")] {
+            let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+            let mut client = ConnectedClient::new(id.into(), id.into(), tx);
+            client.active_roles = vec!["player@v1".to_string()];
+            client_manager.add_client(client);
+            group_manager.add_to_group(id, group);
+            group_manager.set_playback_state(group, crate::server::group::PlaybackState::Playing);
+            receivers.push(rx);
+        }
+
+        let announcements = AnnouncementQueue::new();
+        announcements.play(
+            Announcement::new(Box::new(Clip {
+                chunks: 1,
+                sample_rate: 48000,
+            }))
+            .groups(["kitchen"])
+            .mix(AnnouncementMix::Override)
+            .buffer_ahead_ms(100),
+        );
+
+        let mut engine = AudioEngine::new(source, client_manager, group_manager, clock, 20, 500);
+        engine.set_announcements(announcements);
+        engine.state = EngineState::Running;
+        engine.generate_and_broadcast_chunk();
+
+        let (lounge, kitchen) = receivers.split_at_mut(1);
+        let lounge = &mut lounge[0];
+        let kitchen = &mut kitchen[0];
+        assert!(
+            matches!(kitchen.try_recv(), Ok(ServerMessage::Text(t)) if t.contains("stream/clear"))
+        );
+
+        let chunk_timestamp =
+            |rx: &mut tokio::sync::mpsc::UnboundedReceiver<ServerMessage>| match rx.try_recv() {
+                Ok(ServerMessage::Binary(data)) => {
+                    let frame = BinaryFrame::decode(&data).unwrap();
+                    (frame.timestamp(), frame.payload().iter().any(|b| *b != 0))
+                }
+                other => panic!("Expected audio chunk, got {:?}", other),
+            };
+        let (music_at, music_audible) = chunk_timestamp(lounge);
+        let (announcement_at, announcement_audible) = chunk_timestamp(kitchen);
+        assert!(!music_audible);
+        assert!(announcement_audible);
+        let lead = music_at - announcement_at;
+        assert!((300_000..=500_000).contains(&lead), "lead {}", lead);
+
+        // Once the clip ends the kitchen returns to the group's normal offset
+        engine.generate_and_broadcast_chunk();
+        let (music_at, _) = chunk_timestamp(lounge);
+        let (kitchen_at, kitchen_audible) = chunk_timestamp(kitchen);
+        assert!(!kitchen_audible);
+        assert!((kitchen_at - music_at).abs() < 100_000);
+    }
+
+    #[test]
+    fn test_announcement_is_resampled_to_stream_rate() {
+        let source = Box::new(crate::server::audio_source::SilenceSource::new(48000));
+        let client_manager = Arc::new(ClientManager::new());
+        let group_manager = Arc::new(GroupManager::new());
+        let clock = Arc::new(ServerClock::new());
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut client = ConnectedClient::new("p1".into(), "Player".into(), tx);
+        client.active_roles = vec!["player@v1".to_string()];
+        client_manager.add_client(client);
+        group_manager.add_to_group("p1", "default");
+        group_manager.set_playback_state("default", crate::server::group::PlaybackState::Playing);
+
+        // Five 20ms chunks at 44.1kHz
+        let announcements = AnnouncementQueue::new();
+        announcements.play(Announcement::new(Box::new(Clip {
+            chunks: 5,
+            sample_rate: 44100,
+        })));
+
+        let mut engine = AudioEngine::new(source, client_manager, group_manager, clock, 20, 500);
+        engine.set_announcements(announcements);
+        engine.state = EngineState::Running;
+
+        // The engine runs at most two chunks ahead of the clock
+        let mut audible = 0;
+        for _ in 0..15 {
+            engine.generate_and_broadcast_chunk();
+            while let Ok(message) = rx.try_recv() {
+                if let ServerMessage::Binary(data) = message {
+                    let frame = BinaryFrame::decode(&data).unwrap();
+                    if frame.payload().iter().any(|b| *b != 0) {
+                        audible += 1;
+                    }
+                }
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        // 100ms of audio at 48kHz, plus the filter's tail spilling into a sixth chunk
+        assert!((5..=6).contains(&audible), "{} audible chunks", audible);
+        assert!(engine.announcement.is_none());
+    }
+
+    #[test]
+    fn test_live_switch_clears_players() {
+        let source = Box::new(TestToneSource::new(440.0, 48000));
+        let client_manager = Arc::new(ClientManager::new());
+        let group_manager = Arc::new(GroupManager::new());
+        let clock = Arc::new(ServerClock::new());
+        group_manager.set_playback_state("default", crate::server::group::PlaybackState::Playing);
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut player = ConnectedClient::new("p1".into(), "Player".into(), tx);
+        player.active_roles = vec!["player@v1".to_string()];
+        client_manager.add_client(player);
+        group_manager.add_to_group("p1", "default");
+
+        let mut engine = AudioEngine::new(source, client_manager, group_manager, clock, 20, 500);
+        engine.state = EngineState::Running;
+        engine.generate_and_broadcast_chunk();
+        while rx.try_recv().is_ok() {}
+
+        // Same format: players drop the old source's audio
+        engine.apply(EngineCommand::SetSource(Box::new(TestToneSource::new(
+            880.0, 48000,
+        ))));
+        assert!(matches!(rx.try_recv(), Ok(ServerMessage::Text(t)) if t.contains("stream/clear")));
+        assert!(rx.try_recv().is_err());
+
+        // Another sample rate is resampled, so the stream format stays put
+        let source = Box::new(crate::server::audio_source::SilenceSource::new(44100));
+        engine.apply(EngineCommand::SetSource(source));
+        assert!(matches!(rx.try_recv(), Ok(ServerMessage::Text(t)) if t.contains("stream/clear")));
+        engine.generate_and_broadcast_chunk();
+        assert!(matches!(rx.try_recv(), Ok(ServerMessage::Binary(_))));
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_source_is_resampled_to_negotiated_rate() {
+        let source = Box::new(TestToneSource::new(440.0, 44100));
+        let client_manager = Arc::new(ClientManager::new());
+        let group_manager = Arc::new(GroupManager::new());
+        let clock = Arc::new(ServerClock::new());
+        group_manager.set_playback_state("default", crate::server::group::PlaybackState::Playing);
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut player = ConnectedClient::new("p1".into(), "Player".into(), tx);
+        player.active_roles = vec!["player@v1".to_string()];
+        player.audio_format = Some(ClientManager::default_audio_format());
+        client_manager.add_client(player);
+        group_manager.add_to_group("p1", "default");
+
+        let mut engine = AudioEngine::new(source, client_manager, group_manager, clock, 20, 500);
+        engine.state = EngineState::Running;
+        let mut frames = 0;
+        let mut timestamps = Vec::new();
+        // The timeline lets the engine run two chunks ahead of the clock
+        for _ in 0..2 {
+            engine.generate_and_broadcast_chunk();
+            while let Ok(message) = rx.try_recv() {
+                match message {
+                    ServerMessage::Binary(data) => {
+                        let frame = BinaryFrame::decode(&data).unwrap();
+                        // Each chunk is timestamped by its own first frame
+                        timestamps.push((frame.timestamp(), frames));
+                        // 24-bit stereo PCM
+                        frames += frame.payload().len() / 6;
+                    }
+                    ServerMessage::Text(text) => panic!("format changed: {}", text),
+                }
+            }
+        }
+        // Two 20ms chunks at 48kHz, less the resampler's fill
+        assert!((1_900..=1_920).contains(&frames), "{} frames", frames);
+        let [(first, _), (second, before)] = timestamps[..] else {
+            panic!("{:?}", timestamps);
+        };
+        let expected = before as i64 * 1_000_000 / 48_000;
+        assert!((second - first - expected).abs() <= 1, "{:?}", timestamps);
+    }
+
+    #[test]
+    fn test_channel_map_picks_the_side_for_one_channel_encoders() {
+        let stereo = [Sample(0.125), Sample(-0.375)];
+        let output = |channel_map| OutputProcessing {
+            gain: 100,
+            channel_map,
+            format: StreamFormat {
+                codec: Codec::Pcm,
+                sample_rate: 48000,
+                channels: 2,
+                bit_depth: 16,
+            },
+            encoder: EncoderSettings::default(),
+        };
+        let processed = |map, channels| {
+            process_for_output(
+                &stereo,
+                output(map),
+                (2, channels),
+                DownmixLevels::default(),
+            )
+            .to_vec()
+        };
+
+        assert_eq!(
+            processed(ChannelMap::Swap, 2),
+            [Sample(-0.375), Sample(0.125)]
+        );
+        assert_eq!(processed(ChannelMap::Right, 1), [Sample(-0.375)]);
+        assert_eq!(processed(ChannelMap::Left, 1), [Sample(0.125)]);
+        // Without a side to pick, one channel carries the mono sum
+        let mono = fold_to_mono(&stereo)[0];
+        assert_eq!(processed(ChannelMap::Stereo, 1), [mono]);
+        assert_eq!(processed(ChannelMap::Swap, 1), [mono]);
+    }
+
+    #[test]
+    fn test_surround_is_folded_only_for_clients_with_fewer_channels() {
+        // FL FR FC LFE RL RR
+        let surround = [0.1, 0.2, 0.4, 0.8, 0.04, 0.08].map(Sample);
+        let output = OutputProcessing {
+            gain: 100,
+            channel_map: ChannelMap::Swap,
+            format: StreamFormat {
+                codec: Codec::Pcm,
+                sample_rate: 48000,
+                channels: 6,
+                bit_depth: 24,
+            },
+            encoder: EncoderSettings::default(),
+        };
+        let levels = DownmixLevels::default();
+        let processed = |samples: &[Sample], counts| {
+            process_for_output(samples, output, counts, levels).to_vec()
+        };
+
+        assert_eq!(processed(&surround, (6, 6)), surround);
+        let (left, right) =
+            Downmix::stereo(&Speaker::default_layout(6), levels).frame(&surround.map(|s| s.0));
+        // Folded to stereo, the channel map applies
+        assert_eq!(processed(&surround, (6, 2)), [right, left]);
+        assert_eq!(processed(&surround, (6, 1)).len(), 1);
+        // A 7.1 client hears 5.1 as it is, with silent sides
+        let wider = processed(&surround, (6, 8));
+        assert_eq!(wider[..6], surround);
+        assert_eq!(wider[6..], [Sample::ZERO; 2]);
+        // Stereo spreads over the front pair only
+        let stereo = [Sample(0.125), Sample(-0.375)];
+        let spread = processed(&stereo, (2, 6));
+        assert_eq!(spread, [-0.375, 0.125, 0.0, 0.0, 0.0, 0.0].map(Sample));
+    }
+
+    #[test]
+    fn test_each_encoded_copy_keeps_its_own_encoder_state() {
+        // Chunks sent to (client, group, volume) players with shaped 16-bit dither
+        let stream = |players: &[(&str, &str, u8)]| {
+            let client_manager = Arc::new(ClientManager::new());
+            let group_manager = Arc::new(GroupManager::new());
+            group_manager.create_group("kitchen", "Kitchen");
+            let mut receivers = Vec::new();
+            for &(id, group, volume) in players {
+                let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+                let mut client = ConnectedClient::new(id.into(), id.into(), tx);
+                client.active_roles = vec!["player@v1".to_string()];
+                client.volume = volume;
+                client.audio_format = Some(AudioFormat {
+                    codec: Codec::Pcm,
+                    sample_rate: 48000,
+                    channels: 2,
+                    bit_depth: 16,
+                    codec_header: None,
+                });
+                client_manager.add_client(client);
+                group_manager.add_to_group(id, group);
+                group_manager
+                    .set_playback_state(group, crate::server::group::PlaybackState::Playing);
+                receivers.push(rx);
+            }
+            let source = Box::new(TestToneSource::new(440.0, 48000).with_amplitude(0.001));
+            let clock = Arc::new(ServerClock::new());
+            let mut engine =
+                AudioEngine::new(source, client_manager, group_manager, clock, 20, 500);
+            engine.set_encoder_settings(EncoderSettings {
+                dither: Some(DitherMode::Shaped),
+                ..Default::default()
+            });
+            engine.state = EngineState::Running;
+            // The timeline lets the engine run two chunks ahead of the clock
+            for _ in 0..2 {
+                engine.generate_and_broadcast_chunk();
+            }
+            receivers
+                .iter_mut()
+                .map(|rx| {
+                    let mut payloads = Vec::new();
+                    while let Ok(message) = rx.try_recv() {
+                        if let ServerMessage::Binary(data) = message {
+                            payloads.push(BinaryFrame::decode(&data).unwrap().payload().to_vec());
+                        }
+                    }
+                    payloads
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let together = stream(&[("loud", "default", 100), ("quiet", "kitchen", 30)]);
+        assert_eq!(together[0].len(), 2);
+        assert_eq!(together[0], stream(&[("loud", "default", 100)])[0]);
+        assert_eq!(together[1], stream(&[("quiet", "kitchen", 30)])[0]);
+    }
+
+    #[test]
+    fn test_volume_change_keeps_resampler_state() {
+        // Second chunk sent to a resampled player whose volume is set to
+        // `first` and then `second`
+        let second_chunk = |first: u8, second: u8| {
+            let client_manager = Arc::new(ClientManager::new());
+            let group_manager = Arc::new(GroupManager::new());
+            group_manager
+                .set_playback_state("default", crate::server::group::PlaybackState::Playing);
+            let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+            let mut player = ConnectedClient::new("p1".into(), "Player".into(), tx);
+            player.active_roles = vec!["player@v1".to_string()];
+            player.volume = first;
+            player.audio_format = Some(ClientManager::default_audio_format());
+            client_manager.add_client(player);
+            group_manager.add_to_group("p1", "default");
+
+            let source = Box::new(TestToneSource::new(440.0, 44100));
+            let clock = Arc::new(ServerClock::new());
+            let mut engine = AudioEngine::new(
+                source,
+                client_manager.clone(),
+                group_manager,
+                clock,
+                20,
+                500,
+            );
+            engine.state = EngineState::Running;
+            engine.generate_and_broadcast_chunk();
+            let players = HashSet::from(["p1".to_string()]);
+            client_manager.apply_volume(&players, crate::server::VolumeChange::Set(second));
+            engine.generate_and_broadcast_chunk();
+            let mut chunks = Vec::new();
+            while let Ok(message) = rx.try_recv() {
+                if let ServerMessage::Binary(data) = message {
+                    let frame = BinaryFrame::decode(&data).unwrap();
+                    chunks.push(frame.payload().to_vec());
+                }
+            }
+            assert_eq!(chunks.len(), 2);
+            chunks.pop().unwrap()
+        };
+
+        let stepped = second_chunk(50, 60);
+        let steady = second_chunk(60, 60);
+        assert_eq!(stepped.len(), steady.len());
+        assert!(stepped == steady, "volume change restarted the resampler");
+    }
+
+    #[test]
+    fn test_clients_receive_negotiated_formats() {
+        let source = Box::new(TestToneSource::new(440.0, 48000));
+        let client_manager = Arc::new(ClientManager::new());
+        let group_manager = Arc::new(GroupManager::new());
+        let clock = Arc::new(ServerClock::new());
+        group_manager.set_playback_state("default", crate::server::group::PlaybackState::Playing);
+
+        let mut receivers = Vec::new();
+        for (id, codec, bit_depth) in [("pcm16", Codec::Pcm, 16), ("flac", Codec::Flac, 16)] {
+            let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+            let mut client = ConnectedClient::new(id.into(), id.into(), tx);
+            client.active_roles = vec!["player@v1".to_string()];
+            client.audio_format = Some(AudioFormat {
+                codec,
+                sample_rate: 48000,
+                channels: 2,
+                bit_depth,
+                codec_header: None,
+            });
+            client_manager.add_client(client);
+            group_manager.add_to_group(id, "default");
+            receivers.push(rx);
+        }
+
+        let mut engine = AudioEngine::new(
+            source,
+            client_manager.clone(),
+            group_manager,
+            clock,
+            20,
+            500,
+        );
+        engine.state = EngineState::Running;
+        engine.generate_and_broadcast_chunk();
+
+        let mut payloads = Vec::new();
+        for rx in &mut receivers {
+            // FLAC needs its header, so the client is sent a new stream/start
+            let first = rx.try_recv();
+            let chunk = match first {
+                Ok(ServerMessage::Text(text)) => {
+                    assert!(text.contains("stream/start"));
+                    rx.try_recv()
+                }
+                other => other,
+            };
+            match chunk {
+                Ok(ServerMessage::Binary(data)) => {
+                    payloads.push(BinaryFrame::decode(&data).unwrap().payload().to_vec())
+                }
+                other => panic!("Expected audio chunk, got {:?}", other),
+            }
+        }
+
+        // 960 stereo frames at 16 bits
+        assert_eq!(payloads[0].len(), 960 * 2 * 2);
+        // FLAC frame sync code, variable-blocksize strategy
+        assert_eq!(&payloads[1][..2], &[0xFF, 0xF9]);
+        let flac = client_manager.get_audio_format("flac").unwrap();
+        assert_eq!(flac.codec, Codec::Flac);
+        assert!(flac.codec_header.is_some());
+    }
 }
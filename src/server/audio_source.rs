@@ -1,19 +1,32 @@
 // ABOUTME: Audio source abstraction
 // ABOUTME: Provides test tone and file-based audio sources
 
+use crate::audio::downmix::{Downmix, DownmixLevels, Speaker};
+use crate::audio::replay_gain::{GainTags, ReplayGain};
 use crate::audio::types::Sample;
+use crate::server::capture::{CaptureDevice, CaptureSource, CAPTURE_SCHEME};
+#[cfg(feature = "gstreamer")]
+use crate::server::gst::{GstSource, GST_SCHEME};
+use crate::server::icy::{IcyReader, ICY_METADATA_HEADER, ICY_METAINT_HEADER};
+use crate::server::pipe::{parse_pipe_uri, PipeSource};
+use crate::server::snapcast::{parse_snapcast_uri, SnapcastSource};
+use crate::server::source_events::{Artwork, SourceEvent, SourceEvents, TrackInfo};
+use crate::server::url_cache::{Fetched, UrlCache};
+use parking_lot::Mutex;
 use std::f64::consts::PI;
+use std::sync::Arc;
 
 /// Trait for audio sources
 pub trait AudioSource: Send + Sync {
-    /// Read the next chunk of audio samples (interleaved stereo)
+    /// Read the next chunk of audio samples, interleaved with
+    /// [`channels`](Self::channels) channels
     /// Returns None when the source is exhausted
     fn read_chunk(&mut self, samples_per_channel: usize) -> Option<Vec<Sample>>;
 
     /// Get the sample rate in Hz
     fn sample_rate(&self) -> u32;
 
-    /// Get the number of channels
+    /// Get the number of channels (2 unless a surround layout is kept)
     fn channels(&self) -> u8;
 
     /// Check if the source is exhausted
@@ -21,6 +34,226 @@ pub trait AudioSource: Send + Sync {
 
     /// Reset the source to the beginning (if supported)
     fn reset(&mut self) {}
+
+    /// Human-readable description of what is playing (file path, URL, ...)
+    fn description(&self) -> Option<String> {
+        None
+    }
+
+    /// Tags known for the current track
+    ///
+    /// Reported with the track change when the engine starts this source.
+    fn track_info(&self) -> TrackInfo {
+        TrackInfo::default()
+    }
+
+    /// Give the source a channel for reporting tags and title changes it
+    /// discovers while playing
+    ///
+    /// Called by the engine when it starts the source. The default ignores it.
+    fn set_events(&mut self, _events: SourceEvents) {}
+
+    /// Gain applied from the track's ReplayGain tags, in dB
+    ///
+    /// A source reporting one already plays at
+    /// [`REFERENCE_LUFS`](crate::audio::replay_gain::REFERENCE_LUFS), so loudness
+    /// normalization does not measure it. The default is None.
+    fn replay_gain_db(&self) -> Option<f64> {
+        None
+    }
+}
+
+/// Open a source from a URI
+///
+/// `http://` and `https://` URIs stream with [`UrlSource`], `capture:` URIs
+/// record a local device with [`CaptureSource`], `pipe:` URIs read raw PCM
+/// with [`PipeSource`], `snapcast:` URIs bridge a Snapcast server with
+/// [`SnapcastSource`], and `file://` URIs and plain paths open a looping
+/// [`FileSource`]. With the `gstreamer` feature, `gst:` URIs run a GStreamer
+/// pipeline. Multichannel audio is folded to stereo, or to
+/// `downmix.max_channels`, with `downmix`; files with gain tags are leveled
+/// with `replay_gain` when given; HTTP downloads go through `cache` when one is given.
+pub fn open_source(
+    uri: &str,
+    downmix: DownmixLevels,
+    replay_gain: Option<ReplayGain>,
+    cache: Option<&UrlCache>,
+) -> Result<Box<dyn AudioSource>, Box<dyn std::error::Error + Send + Sync>> {
+    open_uri(uri, downmix, replay_gain, cache, true)
+}
+
+/// Open a source from a URI to play once, as a queued track
+///
+/// Like [`open_source`], except that files end instead of looping.
+pub fn open_track(
+    uri: &str,
+    downmix: DownmixLevels,
+    replay_gain: Option<ReplayGain>,
+    cache: Option<&UrlCache>,
+) -> Result<Box<dyn AudioSource>, Box<dyn std::error::Error + Send + Sync>> {
+    open_uri(uri, downmix, replay_gain, cache, false)
+}
+
+/// Whether a source URI reads from the server's machine rather than the network
+///
+/// Capture devices, pipes, GStreamer pipelines and files are local;
+/// `http://`, `https://` and `snapcast:` URIs are not. Local sources can
+/// record a microphone or, through a pipeline, write files, so URIs from
+/// untrusted callers should be checked with this before opening.
+pub fn is_local_uri(uri: &str) -> bool {
+    let remote = uri.starts_with("http://")
+        || uri.starts_with("https://")
+        || parse_snapcast_uri(uri).is_some();
+    !remote
+}
+
+fn open_uri(
+    uri: &str,
+    downmix: DownmixLevels,
+    replay_gain: Option<ReplayGain>,
+    cache: Option<&UrlCache>,
+    loop_playback: bool,
+) -> Result<Box<dyn AudioSource>, Box<dyn std::error::Error + Send + Sync>> {
+    if uri.starts_with("http://") || uri.starts_with("https://") {
+        let source = match cache {
+            Some(cache) => UrlSource::with_cache(uri, cache)?,
+            None => UrlSource::new(uri)?,
+        };
+        return Ok(Box::new(source.with_downmix(downmix)));
+    }
+    if let Some(device) = uri.strip_prefix(CAPTURE_SCHEME) {
+        let source = CaptureSource::open(CaptureDevice::parse(device), downmix)?;
+        return Ok(Box::new(source));
+    }
+    #[cfg(feature = "gstreamer")]
+    if let Some(pipeline) = uri.strip_prefix(GST_SCHEME) {
+        return Ok(Box::new(GstSource::open(pipeline)?));
+    }
+    if let Some(pipe) = parse_pipe_uri(uri) {
+        let (path, format) = pipe?;
+        return Ok(Box::new(PipeSource::open(&path, format)?));
+    }
+    if let Some(target) = parse_snapcast_uri(uri) {
+        return Ok(Box::new(SnapcastSource::open(&target)?));
+    }
+    let path = uri.strip_prefix("file://").unwrap_or(uri);
+    FileSource::new(path)
+        .map(|source| {
+            let mut source = source.with_downmix(downmix).with_loop(loop_playback);
+            if let Some(settings) = replay_gain {
+                source = source.with_replay_gain(settings);
+            }
+            Box::new(source) as Box<dyn AudioSource>
+        })
+        .map_err(|e| e.to_string().into())
+}
+
+/// Tags found while probing a stream, with its length when known
+///
+/// Tags in the container (Vorbis comments, RIFF INFO, MP4 atoms) win over
+/// tags found ahead of it (ID3v2).
+fn read_track_info(
+    probed: &mut symphonia::core::probe::ProbedMetadata,
+    format: &mut dyn symphonia::core::formats::FormatReader,
+    duration_ms: Option<u64>,
+) -> TrackInfo {
+    let mut track = TrackInfo {
+        duration_ms,
+        ..Default::default()
+    };
+    if let Some(revision) = probed.get().as_ref().and_then(|m| m.current()) {
+        track.merge(tag_info(revision));
+    }
+    if let Some(revision) = format.metadata().current() {
+        track.merge(tag_info(revision));
+    }
+    track
+}
+
+/// ReplayGain and R128 tags of a stream, container tags winning as above
+fn read_gain_tags(
+    probed: &mut symphonia::core::probe::ProbedMetadata,
+    format: &mut dyn symphonia::core::formats::FormatReader,
+) -> GainTags {
+    let mut tags = GainTags::default();
+    let mut add = |revision: &symphonia::core::meta::MetadataRevision| {
+        for tag in revision.tags() {
+            tags.add(&tag.key, &tag.value.to_string());
+        }
+    };
+    if let Some(revision) = probed.get().as_ref().and_then(|m| m.current()) {
+        add(revision);
+    }
+    if let Some(revision) = format.metadata().current() {
+        add(revision);
+    }
+    tags
+}
+
+/// Title, artist, album and pictures from one revision of a stream's tags
+fn tag_info(revision: &symphonia::core::meta::MetadataRevision) -> TrackInfo {
+    use symphonia::core::meta::{StandardTagKey, StandardVisualKey};
+
+    let mut track = TrackInfo::default();
+    for tag in revision.tags() {
+        // RIFF INFO strings keep their NUL terminator
+        let value = tag.value.to_string();
+        let value = value.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+        if value.is_empty() {
+            continue;
+        }
+        match tag.std_key {
+            Some(StandardTagKey::TrackTitle) => track.title = Some(value.to_string()),
+            Some(StandardTagKey::Artist) => track.artist = Some(value.to_string()),
+            Some(StandardTagKey::Album) => track.album = Some(value.to_string()),
+            _ => {}
+        }
+    }
+
+    // The front cover is the album art; untyped pictures do when there is none
+    for visual in revision.visuals() {
+        let artwork = || Artwork {
+            media_type: visual.media_type.clone(),
+            data: visual.data.clone().into(),
+        };
+        match visual.usage {
+            Some(StandardVisualKey::FrontCover) => track.album_art = Some(artwork()),
+            None if track.album_art.is_none() => track.album_art = Some(artwork()),
+            Some(
+                StandardVisualKey::LeadArtistPerformerSoloist
+                | StandardVisualKey::ArtistPerformer
+                | StandardVisualKey::BandOrchestra,
+            ) if track.artist_art.is_none() => track.artist_art = Some(artwork()),
+            _ => {}
+        }
+    }
+    track
+}
+
+/// Map a decoder's channel set to speaker positions in interleaved order
+fn speaker_layout(channels: symphonia::core::audio::Channels) -> Vec<Speaker> {
+    use symphonia::core::audio::Channels;
+
+    let layout: Vec<Speaker> = channels
+        .iter()
+        .map(|channel| match channel {
+            Channels::FRONT_LEFT => Speaker::FrontLeft,
+            Channels::FRONT_RIGHT => Speaker::FrontRight,
+            Channels::FRONT_CENTRE => Speaker::FrontCenter,
+            Channels::LFE1 => Speaker::Lfe,
+            Channels::REAR_LEFT => Speaker::RearLeft,
+            Channels::REAR_RIGHT => Speaker::RearRight,
+            Channels::REAR_CENTRE => Speaker::RearCenter,
+            Channels::SIDE_LEFT => Speaker::SideLeft,
+            Channels::SIDE_RIGHT => Speaker::SideRight,
+            _ => Speaker::Other,
+        })
+        .collect();
+    if layout.iter().all(|&s| s == Speaker::Other) {
+        Speaker::default_layout(layout.len())
+    } else {
+        layout
+    }
 }
 
 /// Test tone source (generates a sine wave)
@@ -43,13 +276,13 @@ impl TestToneSource {
             sample_rate,
             phase: 0.0,
             // Use 50% amplitude to avoid clipping
-            amplitude: 0.5 * Sample::MAX.0 as f64,
+            amplitude: 0.5,
         }
     }
 
     /// Set the amplitude (0.0 to 1.0)
     pub fn with_amplitude(mut self, amplitude: f64) -> Self {
-        self.amplitude = amplitude.clamp(0.0, 1.0) * Sample::MAX.0 as f64;
+        self.amplitude = amplitude.clamp(0.0, 1.0);
         self
     }
 }
@@ -61,8 +294,7 @@ impl AudioSource for TestToneSource {
         let phase_increment = 2.0 * PI * self.frequency / self.sample_rate as f64;
 
         for _ in 0..samples_per_channel {
-            let value = (self.phase.sin() * self.amplitude) as i32;
-            let sample = Sample(value);
+            let sample = Sample((self.phase.sin() * self.amplitude) as f32);
 
             // Interleaved stereo: L, R, L, R, ...
             samples.push(sample);
@@ -92,6 +324,10 @@ impl AudioSource for TestToneSource {
     fn reset(&mut self) {
         self.phase = 0.0;
     }
+
+    fn description(&self) -> Option<String> {
+        Some(format!("Test tone {} Hz", self.frequency))
+    }
 }
 
 /// Silence source (generates silence)
@@ -122,6 +358,10 @@ impl AudioSource for SilenceSource {
     fn is_exhausted(&self) -> bool {
         false
     }
+
+    fn description(&self) -> Option<String> {
+        Some("Silence".to_string())
+    }
 }
 
 /// File-based audio source using symphonia for decoding
@@ -130,11 +370,17 @@ pub struct FileSource {
     format: Box<dyn symphonia::core::formats::FormatReader>,
     track_id: u32,
     sample_rate: u32,
-    channels: u8,
-    sample_buf: symphonia::core::audio::SampleBuffer<i32>,
+    layout: Vec<Speaker>,
+    downmix: Downmix,
+    sample_buf: symphonia::core::audio::SampleBuffer<f32>,
     buffer_pos: usize,
     exhausted: bool,
     loop_playback: bool,
+    path: String,
+    track: TrackInfo,
+    gain_tags: GainTags,
+    /// Linear gain from the gain tags, when ReplayGain is on and the file has them
+    gain: Option<f64>,
 }
 
 impl FileSource {
@@ -161,10 +407,14 @@ impl FileSource {
         }
 
         // Probe the media source
-        let probed = symphonia::default::get_probe()
-            .format(&hint, mss, &FormatOptions::default(), &MetadataOptions::default())?;
+        let mut probed = symphonia::default::get_probe().format(
+            &hint,
+            mss,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )?;
 
-        let format = probed.format;
+        let mut format = probed.format;
 
         // Find the first audio track (skip video/image tracks like album art)
         // Audio tracks will have sample_rate set, video/image tracks won't
@@ -184,30 +434,61 @@ impl FileSource {
         let sample_rate = codec_params.sample_rate.ok_or("Sample rate not found")? as u32;
         let channel_layout = codec_params.channels.ok_or("Channel count not found")?;
         let channels = channel_layout.count() as u8;
+        let layout = speaker_layout(channel_layout);
+        let downmix = Downmix::for_source(&layout, DownmixLevels::default());
+        let duration_ms = codec_params.n_frames.map(|n| n * 1000 / sample_rate as u64);
 
         // Create a decoder for the track
-        let decoder = symphonia::default::get_codecs()
-            .make(&codec_params, &DecoderOptions::default())?;
+        let decoder =
+            symphonia::default::get_codecs().make(codec_params, &DecoderOptions::default())?;
 
         // Create a sample buffer for decoded audio
         // We'll allocate it with a reasonable initial size and resize as needed
         let capacity = 48000 * channels as usize; // 1 second of audio
         let spec = symphonia::core::audio::SignalSpec::new(sample_rate, channel_layout);
         let sample_buf = symphonia::core::audio::SampleBuffer::new(capacity as u64, spec);
+        let track = read_track_info(&mut probed.metadata, format.as_mut(), duration_ms);
+        let gain_tags = read_gain_tags(&mut probed.metadata, format.as_mut());
 
         Ok(Self {
             decoder,
             format,
             track_id,
             sample_rate,
-            channels,
+            layout,
+            downmix,
             sample_buf,
             buffer_pos: 0,
             exhausted: false,
             loop_playback: true, // Loop by default
+            path: path.to_string(),
+            track,
+            gain_tags,
+            gain: None,
         })
     }
 
+    /// Fold multichannel audio down with the given levels
+    ///
+    /// Surround layouts keep up to `levels.max_channels` channels.
+    pub fn with_downmix(mut self, levels: DownmixLevels) -> Self {
+        self.downmix = Downmix::for_source(&self.layout, levels);
+        self
+    }
+
+    /// Apply the file's ReplayGain or R128 gain tags with the given settings
+    ///
+    /// Files without gain tags play unchanged.
+    pub fn with_replay_gain(mut self, settings: ReplayGain) -> Self {
+        self.gain = settings.gain(&self.gain_tags);
+        self
+    }
+
+    /// Gain and peak tags found in the file
+    pub fn gain_tags(&self) -> GainTags {
+        self.gain_tags
+    }
+
     /// Set whether to loop playback (default: true)
     pub fn with_loop(mut self, loop_playback: bool) -> Self {
         self.loop_playback = loop_playback;
@@ -229,8 +510,13 @@ impl FileSource {
                 Err(Error::IoError(ref e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
                     if self.loop_playback {
                         // Reset to beginning
-                        self.format.seek(symphonia::core::formats::SeekMode::Accurate,
-                                       symphonia::core::formats::SeekTo::TimeStamp { ts: 0, track_id: self.track_id })?;
+                        self.format.seek(
+                            symphonia::core::formats::SeekMode::Accurate,
+                            symphonia::core::formats::SeekTo::TimeStamp {
+                                ts: 0,
+                                track_id: self.track_id,
+                            },
+                        )?;
                         self.decoder.reset();
                         continue;
                     } else {
@@ -270,61 +556,41 @@ impl AudioSource for FileSource {
             return None;
         }
 
-        let mut output = Vec::with_capacity(samples_per_channel * 2); // stereo
+        let channels = self.downmix.output_channels();
+        let mut output = Vec::with_capacity(samples_per_channel * channels);
 
-        while output.len() < samples_per_channel * 2 {
+        while output.len() < samples_per_channel * channels {
             // If we've consumed all samples from the current buffer, decode more
-            if self.buffer_pos >= self.sample_buf.len() {
-                if self.decode_next_packet().is_err() {
-                    // End of file or error
-                    if output.is_empty() {
-                        return None;
-                    } else {
-                        // Pad with silence
-                        while output.len() < samples_per_channel * 2 {
-                            output.push(Sample::ZERO);
-                        }
-                        break;
-                    }
-                }
-            }
-
-            let samples = self.sample_buf.samples();
-            let remaining = samples.len() - self.buffer_pos;
-            let needed = (samples_per_channel * 2) - output.len();
-            let to_copy = remaining.min(needed);
-
-            // Convert samples based on channel count
-            match self.channels {
-                1 => {
-                    // Mono: duplicate to stereo
-                    for i in 0..to_copy {
-                        let sample = samples[self.buffer_pos + i];
-                        output.push(Sample(sample));
-                        output.push(Sample(sample));
-                    }
-                }
-                2 => {
-                    // Stereo: direct copy
-                    for i in 0..to_copy {
-                        output.push(Sample(samples[self.buffer_pos + i]));
-                    }
-                }
-                _ => {
-                    // Multi-channel: downmix to stereo (take first 2 channels)
-                    let stride = self.channels as usize;
-                    for i in (0..to_copy).step_by(stride) {
-                        if self.buffer_pos + i + 1 < samples.len() {
-                            output.push(Sample(samples[self.buffer_pos + i]));
-                            output.push(Sample(samples[self.buffer_pos + i + 1]));
-                        }
+            if self.buffer_pos >= self.sample_buf.len() && self.decode_next_packet().is_err() {
+                // End of file or error
+                if output.is_empty() {
+                    return None;
+                } else {
+                    // Pad with silence
+                    while output.len() < samples_per_channel * channels {
+                        output.push(Sample::ZERO);
                     }
+                    break;
                 }
             }
 
-            self.buffer_pos += to_copy;
+            // Fold the decoded layout down to the played one
+            let samples = &self.sample_buf.samples()[self.buffer_pos..];
+            let frames_needed = samples_per_channel - output.len() / channels;
+            let consumed = self.downmix.extend(samples, frames_needed, &mut output);
+            // A trailing partial frame cannot be played; skip to the next packet
+            self.buffer_pos += if consumed == 0 {
+                samples.len()
+            } else {
+                consumed
+            };
         }
 
+        if let Some(gain) = self.gain {
+            for sample in &mut output {
+                *sample = Sample((sample.0 as f64 * gain) as f32).clamp();
+            }
+        }
         Some(output)
     }
 
@@ -333,7 +599,7 @@ impl AudioSource for FileSource {
     }
 
     fn channels(&self) -> u8 {
-        2 // Always output stereo
+        self.downmix.output_channels() as u8
     }
 
     fn is_exhausted(&self) -> bool {
@@ -343,13 +609,31 @@ impl AudioSource for FileSource {
     fn reset(&mut self) {
         use symphonia::core::formats::{SeekMode, SeekTo};
 
-        if let Err(e) = self.format.seek(SeekMode::Accurate, SeekTo::TimeStamp { ts: 0, track_id: self.track_id }) {
+        if let Err(e) = self.format.seek(
+            SeekMode::Accurate,
+            SeekTo::TimeStamp {
+                ts: 0,
+                track_id: self.track_id,
+            },
+        ) {
             log::warn!("Failed to reset file source: {}", e);
         }
         self.decoder.reset();
         self.buffer_pos = 0;
         self.exhausted = false;
     }
+
+    fn description(&self) -> Option<String> {
+        Some(self.path.clone())
+    }
+
+    fn track_info(&self) -> TrackInfo {
+        self.track.clone()
+    }
+
+    fn replay_gain_db(&self) -> Option<f64> {
+        self.gain.map(|gain| 20.0 * gain.log10())
+    }
 }
 
 /// URL-based audio source for streaming from HTTP/HTTPS
@@ -359,11 +643,25 @@ pub struct UrlSource {
     format: Box<dyn symphonia::core::formats::FormatReader>,
     track_id: u32,
     sample_rate: u32,
-    channels: u8,
-    sample_buf: symphonia::core::audio::SampleBuffer<i32>,
+    layout: Vec<Speaker>,
+    downmix: Downmix,
+    sample_buf: symphonia::core::audio::SampleBuffer<f32>,
     buffer_pos: usize,
     exhausted: bool,
+    seekable: bool,
     url: String,
+    track: TrackInfo,
+    icy: Arc<Mutex<IcyTitle>>,
+}
+
+/// Latest ICY title of a radio stream and where to report changes
+///
+/// Shared with the [`IcyReader`] inside the decoder, which sees titles before
+/// the engine hands the source its events channel.
+#[derive(Default)]
+struct IcyTitle {
+    title: Option<String>,
+    events: Option<SourceEvents>,
 }
 
 impl UrlSource {
@@ -372,23 +670,88 @@ impl UrlSource {
     /// Supports: MP3, FLAC, WAV, AAC, and other formats via symphonia
     /// Note: This creates a blocking HTTP request and buffers the response
     pub fn new(url: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
-        use symphonia::core::codecs::DecoderOptions;
-        use symphonia::core::formats::FormatOptions;
-        use symphonia::core::io::{MediaSourceStream, ReadOnlySource};
-        use symphonia::core::meta::MetadataOptions;
-        use symphonia::core::probe::Hint;
-
         log::info!("Opening URL stream: {}", url);
 
         // Fetch the URL using ureq (pure sync, no runtime conflicts)
         // Note: No timeout for streaming - we want to keep connection open indefinitely
         let response = ureq::get(url)
+            .set(ICY_METADATA_HEADER, "1")
             .call()
             .map_err(|e| format!("HTTP request failed: {}", e))?;
+        Self::from_response(url, response)
+    }
+
+    /// Create a URL source that plays finite downloads from an on-disk cache
+    ///
+    /// The first play downloads the whole file; later plays revalidate it and
+    /// read from disk, which also makes [`AudioSource::reset`] work. Live
+    /// streams are not cached and behave as with [`UrlSource::new`].
+    pub fn with_cache(
+        url: &str,
+        cache: &UrlCache,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        use symphonia::core::io::MediaSourceStream;
+
+        log::info!("Opening URL: {}", url);
+        match cache.fetch(url)? {
+            Fetched::Live(response) => Self::from_response(url, *response),
+            Fetched::Cached { path, content_type } => {
+                let file = std::fs::File::open(&path)?;
+                let mss = MediaSourceStream::new(Box::new(file), Default::default());
+                let mut source = Self::from_stream(url, mss, content_type)?;
+                source.seekable = true;
+                Ok(source)
+            }
+        }
+    }
+
+    pub(crate) fn from_response(
+        url: &str,
+        response: ureq::Response,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        use symphonia::core::io::{MediaSourceStream, ReadOnlySource};
 
         // Get content type for format hint
         let content_type = response.header("content-type").map(|s| s.to_string());
 
+        let metaint = response
+            .header(ICY_METAINT_HEADER)
+            .and_then(|value| value.trim().parse::<usize>().ok())
+            .filter(|&metaint| metaint > 0);
+
+        // Wrap response reader in ReadOnlySource (HTTP streams don't support seeking)
+        let reader = response.into_reader();
+        let icy = Arc::new(Mutex::new(IcyTitle::default()));
+        let reader: Box<dyn std::io::Read + Send + Sync> = match metaint {
+            Some(metaint) => {
+                log::info!("Stream carries ICY metadata every {} bytes", metaint);
+                let titles = icy.clone();
+                Box::new(IcyReader::new(reader, metaint, move |title| {
+                    let mut icy = titles.lock();
+                    if let Some(events) = &icy.events {
+                        events.emit(SourceEvent::StreamTitle(title.clone()));
+                    }
+                    icy.title = Some(title);
+                }))
+            }
+            None => reader,
+        };
+        let mss = MediaSourceStream::new(Box::new(ReadOnlySource::new(reader)), Default::default());
+        let mut source = Self::from_stream(url, mss, content_type)?;
+        source.icy = icy;
+        Ok(source)
+    }
+
+    fn from_stream(
+        url: &str,
+        mss: symphonia::core::io::MediaSourceStream,
+        content_type: Option<String>,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        use symphonia::core::codecs::DecoderOptions;
+        use symphonia::core::formats::FormatOptions;
+        use symphonia::core::meta::MetadataOptions;
+        use symphonia::core::probe::Hint;
+
         log::debug!("Content-Type: {:?}", content_type);
 
         // Create a hint based on content type or URL extension
@@ -397,36 +760,47 @@ impl UrlSource {
         // Try content type first
         if let Some(ref ct) = content_type {
             match ct.as_str() {
-                "audio/mpeg" | "audio/mp3" => { hint.with_extension("mp3"); }
-                "audio/flac" => { hint.with_extension("flac"); }
-                "audio/wav" | "audio/x-wav" => { hint.with_extension("wav"); }
-                "audio/aac" | "audio/x-aac" => { hint.with_extension("aac"); }
-                "audio/ogg" => { hint.with_extension("ogg"); }
-                "audio/mp4" | "audio/x-m4a" => { hint.with_extension("m4a"); }
+                "audio/mpeg" | "audio/mp3" => {
+                    hint.with_extension("mp3");
+                }
+                "audio/flac" => {
+                    hint.with_extension("flac");
+                }
+                "audio/wav" | "audio/x-wav" => {
+                    hint.with_extension("wav");
+                }
+                "audio/aac" | "audio/x-aac" => {
+                    hint.with_extension("aac");
+                }
+                "audio/ogg" => {
+                    hint.with_extension("ogg");
+                }
+                "audio/mp4" | "audio/x-m4a" => {
+                    hint.with_extension("m4a");
+                }
                 _ => {
                     // Fall back to URL extension
-                    if let Some(ext) = url.split('.').last() {
+                    if let Some(ext) = url.split('.').next_back() {
                         let ext = ext.split('?').next().unwrap_or(ext);
                         hint.with_extension(ext);
                     }
                 }
             }
-        } else if let Some(ext) = url.split('.').last() {
+        } else if let Some(ext) = url.split('.').next_back() {
             // No content type, use URL extension
             let ext = ext.split('?').next().unwrap_or(ext);
             hint.with_extension(ext);
         }
 
-        // Wrap response reader in ReadOnlySource (HTTP streams don't support seeking)
-        let reader = response.into_reader();
-        let source = ReadOnlySource::new(reader);
-        let mss = MediaSourceStream::new(Box::new(source), Default::default());
-
         // Probe the media source to detect format
-        let probed = symphonia::default::get_probe()
-            .format(&hint, mss, &FormatOptions::default(), &MetadataOptions::default())?;
+        let mut probed = symphonia::default::get_probe().format(
+            &hint,
+            mss,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )?;
 
-        let format = probed.format;
+        let mut format = probed.format;
 
         // Find the first audio track
         let track = format
@@ -445,6 +819,9 @@ impl UrlSource {
         let sample_rate = codec_params.sample_rate.ok_or("Sample rate not found")? as u32;
         let channel_layout = codec_params.channels.ok_or("Channel count not found")?;
         let channels = channel_layout.count() as u8;
+        let layout = speaker_layout(channel_layout);
+        let downmix = Downmix::for_source(&layout, DownmixLevels::default());
+        let duration_ms = codec_params.n_frames.map(|n| n * 1000 / sample_rate as u64);
 
         log::info!(
             "URL stream opened: {}Hz, {} channels",
@@ -453,27 +830,45 @@ impl UrlSource {
         );
 
         // Create a decoder for the track
-        let decoder = symphonia::default::get_codecs()
-            .make(codec_params, &DecoderOptions::default())?;
+        let decoder =
+            symphonia::default::get_codecs().make(codec_params, &DecoderOptions::default())?;
 
         // Create a sample buffer for decoded audio
         let capacity = sample_rate as usize * channels as usize; // 1 second of audio
         let spec = symphonia::core::audio::SignalSpec::new(sample_rate, channel_layout);
         let sample_buf = symphonia::core::audio::SampleBuffer::new(capacity as u64, spec);
+        let track = read_track_info(&mut probed.metadata, format.as_mut(), duration_ms);
 
         Ok(Self {
             decoder,
             format,
             track_id,
             sample_rate,
-            channels,
+            layout,
+            downmix,
             sample_buf,
             buffer_pos: 0,
             exhausted: false,
+            seekable: false,
             url: url.to_string(),
+            track,
+            icy: Arc::default(),
         })
     }
 
+    /// Get the URL this source is streaming from
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Fold multichannel audio down with the given levels
+    ///
+    /// Surround layouts keep up to `levels.max_channels` channels.
+    pub fn with_downmix(mut self, levels: DownmixLevels) -> Self {
+        self.downmix = Downmix::for_source(&self.layout, levels);
+        self
+    }
+
     fn decode_next_packet(&mut self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         use symphonia::core::errors::Error;
 
@@ -524,59 +919,34 @@ impl AudioSource for UrlSource {
             return None;
         }
 
-        let mut output = Vec::with_capacity(samples_per_channel * 2); // stereo
+        let channels = self.downmix.output_channels();
+        let mut output = Vec::with_capacity(samples_per_channel * channels);
 
-        while output.len() < samples_per_channel * 2 {
+        while output.len() < samples_per_channel * channels {
             // If we've consumed all samples from the current buffer, decode more
-            if self.buffer_pos >= self.sample_buf.len() {
-                if self.decode_next_packet().is_err() {
-                    // End of stream or error
-                    if output.is_empty() {
-                        return None;
-                    } else {
-                        // Pad with silence
-                        while output.len() < samples_per_channel * 2 {
-                            output.push(Sample::ZERO);
-                        }
-                        break;
-                    }
-                }
-            }
-
-            let samples = self.sample_buf.samples();
-            let remaining = samples.len() - self.buffer_pos;
-            let needed = (samples_per_channel * 2) - output.len();
-            let to_copy = remaining.min(needed);
-
-            // Convert samples based on channel count (same as FileSource)
-            match self.channels {
-                1 => {
-                    // Mono: duplicate to stereo
-                    for i in 0..to_copy {
-                        let sample = samples[self.buffer_pos + i];
-                        output.push(Sample(sample));
-                        output.push(Sample(sample));
-                    }
-                }
-                2 => {
-                    // Stereo: direct copy
-                    for i in 0..to_copy {
-                        output.push(Sample(samples[self.buffer_pos + i]));
-                    }
-                }
-                _ => {
-                    // Multi-channel: downmix to stereo (take first 2 channels)
-                    let stride = self.channels as usize;
-                    for i in (0..to_copy).step_by(stride) {
-                        if self.buffer_pos + i + 1 < samples.len() {
-                            output.push(Sample(samples[self.buffer_pos + i]));
-                            output.push(Sample(samples[self.buffer_pos + i + 1]));
-                        }
+            if self.buffer_pos >= self.sample_buf.len() && self.decode_next_packet().is_err() {
+                // End of stream or error
+                if output.is_empty() {
+                    return None;
+                } else {
+                    // Pad with silence
+                    while output.len() < samples_per_channel * channels {
+                        output.push(Sample::ZERO);
                     }
+                    break;
                 }
             }
 
-            self.buffer_pos += to_copy;
+            // Fold the decoded layout down to the played one
+            let samples = &self.sample_buf.samples()[self.buffer_pos..];
+            let frames_needed = samples_per_channel - output.len() / channels;
+            let consumed = self.downmix.extend(samples, frames_needed, &mut output);
+            // A trailing partial frame cannot be played; skip to the next packet
+            self.buffer_pos += if consumed == 0 {
+                samples.len()
+            } else {
+                consumed
+            };
         }
 
         Some(output)
@@ -587,15 +957,51 @@ impl AudioSource for UrlSource {
     }
 
     fn channels(&self) -> u8 {
-        2 // Always output stereo
+        self.downmix.output_channels() as u8
     }
 
     fn is_exhausted(&self) -> bool {
         self.exhausted
     }
 
-    // Note: reset() is not supported for URL streams (no seeking in HTTP streams)
-    // The default no-op implementation is used
+    /// Restart from the beginning when playing from the cache
+    ///
+    /// Live HTTP streams cannot seek, so this does nothing for them.
+    fn reset(&mut self) {
+        use symphonia::core::formats::{SeekMode, SeekTo};
+
+        if !self.seekable {
+            return;
+        }
+        let start = SeekTo::TimeStamp {
+            ts: 0,
+            track_id: self.track_id,
+        };
+        if let Err(e) = self.format.seek(SeekMode::Accurate, start) {
+            log::warn!("Failed to reset URL source: {}", e);
+        }
+        self.decoder.reset();
+        self.buffer_pos = 0;
+        self.exhausted = false;
+    }
+
+    fn description(&self) -> Option<String> {
+        Some(self.url.clone())
+    }
+
+    /// Tags read from the stream, titled by the latest ICY `StreamTitle`
+    fn track_info(&self) -> TrackInfo {
+        let mut track = self.track.clone();
+        if let Some(title) = &self.icy.lock().title {
+            track.title = Some(title.clone());
+        }
+        track
+    }
+
+    /// Report ICY title changes as [`SourceEvent::StreamTitle`]
+    fn set_events(&mut self, events: SourceEvents) {
+        self.icy.lock().events = Some(events);
+    }
 }
 
 #[cfg(test)]
@@ -610,7 +1016,7 @@ mod tests {
         // Should generate stereo samples (960 * 2)
         assert_eq!(samples.len(), 1920);
 
-        // Samples should be within 24-bit range
+        // Samples should be within full scale
         for sample in &samples {
             assert!(sample.0 >= Sample::MIN.0);
             assert!(sample.0 <= Sample::MAX.0);
@@ -623,6 +1029,274 @@ mod tests {
         assert!(!source.is_exhausted());
     }
 
+    /// A second of silent 16-bit stereo WAV with RIFF INFO tags
+    fn tagged_wav(tags: &[(&[u8; 4], &str)]) -> Vec<u8> {
+        let mut info = b"INFO".to_vec();
+        for (id, value) in tags {
+            let mut data = value.as_bytes().to_vec();
+            data.push(0);
+            info.extend_from_slice(*id);
+            info.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            if data.len() % 2 == 1 {
+                data.push(0);
+            }
+            info.extend_from_slice(&data);
+        }
+        let (rate, frames) = (48000u32, 48000u32);
+        let mut body = b"WAVE".to_vec();
+        body.extend_from_slice(b"fmt ");
+        body.extend_from_slice(&16u32.to_le_bytes());
+        body.extend_from_slice(&1u16.to_le_bytes());
+        body.extend_from_slice(&2u16.to_le_bytes());
+        body.extend_from_slice(&rate.to_le_bytes());
+        body.extend_from_slice(&(rate * 4).to_le_bytes());
+        body.extend_from_slice(&4u16.to_le_bytes());
+        body.extend_from_slice(&16u16.to_le_bytes());
+        body.extend_from_slice(b"LIST");
+        body.extend_from_slice(&(info.len() as u32).to_le_bytes());
+        body.extend_from_slice(&info);
+        body.extend_from_slice(b"data");
+        body.extend_from_slice(&(frames * 4).to_le_bytes());
+        body.resize(body.len() + frames as usize * 4, 0);
+        let mut wav = b"RIFF".to_vec();
+        wav.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        wav.extend_from_slice(&body);
+        wav
+    }
+
+    #[test]
+    fn test_file_source_reads_tags() {
+        let path = std::env::temp_dir().join(format!("sendspin-tags-{}.wav", std::process::id()));
+        let tags: [(&[u8; 4], &str); 3] = [
+            (b"INAM", "Blue in Green"),
+            (b"IART", "Miles Davis"),
+            (b"IPRD", "Kind of Blue"),
+        ];
+        std::fs::write(&path, tagged_wav(&tags)).unwrap();
+
+        let source = FileSource::new(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let track = source.track_info();
+        assert_eq!(track.title.as_deref(), Some("Blue in Green"));
+        assert_eq!(track.artist.as_deref(), Some("Miles Davis"));
+        assert_eq!(track.album.as_deref(), Some("Kind of Blue"));
+        assert_eq!(track.duration_ms, Some(1000));
+    }
+
+    /// An ID3v2.4 tag holding `picture` as the front cover
+    fn id3_cover(picture: &[u8]) -> Vec<u8> {
+        let syncsafe = |n: usize| (0..4).rev().map(move |i| ((n >> (7 * i)) & 0x7f) as u8);
+        let mut apic = vec![0];
+        apic.extend_from_slice(b"image/png\0");
+        apic.push(3);
+        apic.push(0);
+        apic.extend_from_slice(picture);
+        let mut frame = b"APIC".to_vec();
+        frame.extend(syncsafe(apic.len()));
+        frame.extend_from_slice(&[0, 0]);
+        frame.extend_from_slice(&apic);
+        let mut tag = b"ID3\x04\x00\x00".to_vec();
+        tag.extend(syncsafe(frame.len()));
+        tag.extend_from_slice(&frame);
+        tag
+    }
+
+    #[test]
+    fn test_file_source_reads_cover_art() {
+        let path = std::env::temp_dir().join(format!("sendspin-cover-{}.wav", std::process::id()));
+        let cover = b"\x89PNG\r\n\x1a\nnot really";
+        let mut file = id3_cover(cover);
+        file.extend_from_slice(&tagged_wav(&[(b"INAM", "Flamenco Sketches")]));
+        std::fs::write(&path, file).unwrap();
+
+        let source = FileSource::new(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let track = source.track_info();
+        assert_eq!(track.title.as_deref(), Some("Flamenco Sketches"));
+        let art = track.album_art.unwrap();
+        assert_eq!(art.media_type, "image/png");
+        assert_eq!(&art.data[..], cover);
+        assert!(track.artist_art.is_none());
+    }
+
+    /// An ID3v2.4 tag of UTF-8 `TXXX` frames
+    fn id3_txxx(frames: &[(&str, &str)]) -> Vec<u8> {
+        let syncsafe = |n: usize| (0..4).rev().map(move |i| ((n >> (7 * i)) & 0x7f) as u8);
+        let mut body = Vec::new();
+        for (description, value) in frames {
+            let data = format!("\u{3}{}\0{}", description, value).into_bytes();
+            body.extend_from_slice(b"TXXX");
+            body.extend(syncsafe(data.len()));
+            body.extend_from_slice(&[0, 0]);
+            body.extend_from_slice(&data);
+        }
+        let mut tag = b"ID3\x04\x00\x00".to_vec();
+        tag.extend(syncsafe(body.len()));
+        tag.extend_from_slice(&body);
+        tag
+    }
+
+    #[test]
+    fn test_file_source_applies_replay_gain() {
+        let path = std::env::temp_dir().join(format!("sendspin-gain-{}.wav", std::process::id()));
+        let mut file = id3_txxx(&[
+            ("REPLAYGAIN_TRACK_GAIN", "-6.00 dB"),
+            ("REPLAYGAIN_TRACK_PEAK", "0.5"),
+            ("replaygain_album_gain", "+9.00 dB"),
+            ("REPLAYGAIN_ALBUM_PEAK", "0.5"),
+        ]);
+        file.extend_from_slice(&tagged_wav(&[]));
+        std::fs::write(&path, file).unwrap();
+
+        let source = FileSource::new(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let tags = source.gain_tags();
+        assert_eq!(tags.track_gain_db, Some(-6.0));
+        assert_eq!(tags.album_gain_db, Some(9.0));
+        assert_eq!(source.replay_gain_db(), None);
+
+        let source = source.with_replay_gain(ReplayGain::default());
+        assert!((source.replay_gain_db().unwrap() - -6.0).abs() < 1e-9);
+        // The album peak of 0.5 leaves room for 6dB of the album's 9dB
+        let album = ReplayGain {
+            mode: crate::audio::ReplayGainMode::Album,
+            ..ReplayGain::default()
+        };
+        let source = source.with_replay_gain(album);
+        assert!((source.replay_gain_db().unwrap() - 6.02).abs() < 0.01);
+    }
+
+    /// A tenth of a second of 16-bit 5.1 WAV repeating one frame
+    fn surround_wav(frame: [i16; 6]) -> Vec<u8> {
+        let (rate, frames) = (48000u32, 4800usize);
+        let mut body = b"WAVE".to_vec();
+        body.extend_from_slice(b"fmt ");
+        body.extend_from_slice(&40u32.to_le_bytes());
+        body.extend_from_slice(&0xfffeu16.to_le_bytes());
+        body.extend_from_slice(&6u16.to_le_bytes());
+        body.extend_from_slice(&rate.to_le_bytes());
+        body.extend_from_slice(&(rate * 12).to_le_bytes());
+        body.extend_from_slice(&12u16.to_le_bytes());
+        body.extend_from_slice(&16u16.to_le_bytes());
+        body.extend_from_slice(&22u16.to_le_bytes());
+        body.extend_from_slice(&16u16.to_le_bytes());
+        // FL FR FC LFE BL BR, as PCM
+        body.extend_from_slice(&0x3fu32.to_le_bytes());
+        body.extend_from_slice(&[
+            1, 0, 0, 0, 0, 0, 0x10, 0, 0x80, 0, 0, 0xaa, 0, 0x38, 0x9b, 0x71,
+        ]);
+        body.extend_from_slice(b"data");
+        body.extend_from_slice(&(frames as u32 * 12).to_le_bytes());
+        for _ in 0..frames {
+            body.extend(frame.iter().flat_map(|s| s.to_le_bytes()));
+        }
+        let mut wav = b"RIFF".to_vec();
+        wav.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        wav.extend_from_slice(&body);
+        wav
+    }
+
+    #[test]
+    fn test_file_source_keeps_surround_up_to_max_channels() {
+        let path = std::env::temp_dir().join(format!("sendspin-5-1-{}.wav", std::process::id()));
+        let frame = [100, 200, 400, 800, 40, 80];
+        std::fs::write(&path, surround_wav(frame)).unwrap();
+        let open = |max_channels| {
+            let levels = DownmixLevels {
+                max_channels,
+                ..DownmixLevels::default()
+            };
+            FileSource::new(path.to_str().unwrap())
+                .unwrap()
+                .with_downmix(levels)
+        };
+
+        let mut stereo = open(2);
+        assert_eq!(stereo.channels(), 2);
+        assert_eq!(stereo.read_chunk(960).unwrap().len(), 1920);
+
+        // A 7.1 limit keeps 5.1 as it is
+        let mut surround = open(8);
+        assert_eq!(surround.channels(), 6);
+        let chunk = surround.read_chunk(960).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(chunk.len(), 960 * 6);
+        assert_eq!(chunk[..6], frame.map(Sample::from_i16));
+    }
+
+    #[test]
+    fn test_url_source_reports_icy_titles() {
+        use std::io::{BufRead, BufReader, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/radio", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut request = String::new();
+            let mut reader = BufReader::new(&stream);
+            while reader.read_line(&mut request).unwrap() > 2 {}
+            assert!(request.to_ascii_lowercase().contains("icy-metadata: 1"));
+
+            // A new title every 16 blocks of 4 KiB
+            let mut body = Vec::new();
+            for (i, run) in tagged_wav(&[]).chunks(4096).enumerate() {
+                body.extend_from_slice(run);
+                if run.len() < 4096 {
+                    break;
+                }
+                let mut block = format!("StreamTitle='Song {}';", i / 16).into_bytes();
+                block.resize(block.len().div_ceil(16) * 16, 0);
+                body.push((block.len() / 16) as u8);
+                body.extend_from_slice(&block);
+            }
+            let head = "HTTP/1.1 200 OK\r\nContent-Type: audio/wav\r\nicy-metaint: 4096\r\n\r\n";
+            let mut stream = &stream;
+            stream.write_all(head.as_bytes()).unwrap();
+            let _ = stream.write_all(&body);
+        });
+
+        let mut source = UrlSource::new(&url).unwrap();
+        let events = SourceEvents::new();
+        let mut rx = events.subscribe();
+        source.set_events(events);
+
+        // Metadata bytes never reach the decoder
+        let mut frames = 0;
+        while let Some(chunk) = source.read_chunk(960) {
+            assert!(chunk.iter().all(|s| *s == Sample::ZERO));
+            frames += chunk.len() / 2;
+        }
+        assert_eq!(frames.div_ceil(960), 50);
+        let mut titles = Vec::new();
+        while let Ok(SourceEvent::StreamTitle(title)) = rx.try_recv() {
+            titles.push(title);
+        }
+        assert_eq!(titles, ["Song 0", "Song 1", "Song 2"]);
+        assert_eq!(source.track_info().title.as_deref(), Some("Song 2"));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_local_uris() {
+        for uri in [
+            "capture:default",
+            "pipe:/tmp/snapfifo",
+            "gst:audiotestsrc ! filesink location=/tmp/x",
+            "file:///music/a.flac",
+            "/music/a.flac",
+            "a.flac",
+        ] {
+            assert!(is_local_uri(uri), "{}", uri);
+        }
+        for uri in [
+            "http://radio/stream",
+            "https://radio/stream",
+            "snapcast://snapserver:1704",
+        ] {
+            assert!(!is_local_uri(uri), "{}", uri);
+        }
+    }
+
     #[test]
     fn test_silence_generates_zeros() {
         let mut source = SilenceSource::new(48000);
@@ -630,7 +1304,7 @@ mod tests {
 
         assert_eq!(samples.len(), 1920);
         for sample in &samples {
-            assert_eq!(sample.0, 0);
+            assert_eq!(*sample, Sample::ZERO);
         }
     }
 }
@@ -0,0 +1,120 @@
+// ABOUTME: Tracks each client's reported buffer level over time
+// ABOUTME: Flags clients whose buffer is low or draining toward empty, for pacing and alerts
+
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// A buffer below this level is reported as low (ms)
+pub const LOW_BUFFER_MS: u32 = 100;
+
+/// A draining buffer projected to empty within this time is flagged
+pub const DRAIN_HORIZON: Duration = Duration::from_secs(10);
+
+/// Reports older than this are not used for the trend
+const TREND_WINDOW: Duration = Duration::from_secs(15);
+
+/// Buffer state derived from a client's reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BufferHealth {
+    /// No reports, or a steady or growing buffer
+    Healthy,
+    /// Shrinking fast enough to run empty soon
+    Draining,
+    /// Below the low-water mark
+    Low,
+}
+
+/// Recent buffer-level reports from one client
+#[derive(Debug, Clone, Default)]
+pub struct BufferTrend {
+    samples: VecDeque<(Instant, u32)>,
+}
+
+impl BufferTrend {
+    /// Create an empty trend
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a reported buffer level
+    pub fn record(&mut self, at: Instant, buffered_ms: u32) {
+        self.samples.push_back((at, buffered_ms));
+        while let Some(&(oldest, _)) = self.samples.front() {
+            if at.duration_since(oldest) <= TREND_WINDOW {
+                break;
+            }
+            self.samples.pop_front();
+        }
+    }
+
+    /// Latest reported level in milliseconds
+    pub fn level_ms(&self) -> Option<u32> {
+        self.samples.back().map(|&(_, ms)| ms)
+    }
+
+    /// Change in buffer level per second across the window (negative when draining)
+    pub fn slope_ms_per_sec(&self) -> Option<f64> {
+        let (&(first_at, first), &(last_at, last)) = (self.samples.front()?, self.samples.back()?);
+        let secs = last_at.duration_since(first_at).as_secs_f64();
+        (secs > 0.0).then(|| (last as f64 - first as f64) / secs)
+    }
+
+    /// Classify the buffer against the low-water mark and drain horizon
+    pub fn health(&self) -> BufferHealth {
+        let Some(level) = self.level_ms() else {
+            return BufferHealth::Healthy;
+        };
+        if level < LOW_BUFFER_MS {
+            return BufferHealth::Low;
+        }
+        match self.slope_ms_per_sec() {
+            Some(slope) if slope < 0.0 => {
+                let secs_to_empty = level as f64 / -slope;
+                if secs_to_empty < DRAIN_HORIZON.as_secs_f64() {
+                    BufferHealth::Draining
+                } else {
+                    BufferHealth::Healthy
+                }
+            }
+            _ => BufferHealth::Healthy,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_health_classification() {
+        let start = Instant::now();
+        let mut trend = BufferTrend::new();
+        assert_eq!(trend.health(), BufferHealth::Healthy);
+
+        trend.record(start, 500);
+        trend.record(start + Duration::from_secs(1), 500);
+        assert_eq!(trend.slope_ms_per_sec(), Some(0.0));
+        assert_eq!(trend.health(), BufferHealth::Healthy);
+
+        // Losing 100ms per second from 400ms: empty in 4s
+        trend.record(start + Duration::from_secs(2), 400);
+        assert_eq!(trend.health(), BufferHealth::Draining);
+
+        trend.record(start + Duration::from_secs(3), 80);
+        assert_eq!(trend.health(), BufferHealth::Low);
+    }
+
+    #[test]
+    fn test_old_reports_leave_window() {
+        let start = Instant::now();
+        let mut trend = BufferTrend::new();
+        trend.record(start, 2000);
+        trend.record(start + Duration::from_secs(20), 300);
+        trend.record(start + Duration::from_secs(21), 300);
+        // The 2000ms report is outside the window, so the buffer is steady
+        assert_eq!(trend.slope_ms_per_sec(), Some(0.0));
+        assert_eq!(trend.health(), BufferHealth::Healthy);
+    }
+}
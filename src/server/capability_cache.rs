@@ -0,0 +1,209 @@
+// ABOUTME: Cache of the capabilities each client advertised and the format it got
+// ABOUTME: Lets a client reconnecting with the same capabilities skip format negotiation
+
+use crate::audio::types::AudioFormat;
+use crate::protocol::messages::{AudioFormatSpec, PlayerSupport};
+use crate::server::client_manager::ClientId;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Clients remembered by default before the least recently stored is dropped
+pub const DEFAULT_CAPABILITY_CACHE_SIZE: usize = 256;
+
+/// What a player advertised in its `client/hello`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientCapabilities {
+    /// Formats the player can decode, in its order of preference
+    pub supported_formats: Vec<AudioFormatSpec>,
+    /// Player commands the client accepts
+    pub supported_commands: Vec<String>,
+    /// Buffer capacity in bytes
+    pub buffer_capacity: u32,
+}
+
+impl From<&PlayerSupport> for ClientCapabilities {
+    fn from(support: &PlayerSupport) -> Self {
+        Self {
+            supported_formats: support.supported_formats.clone(),
+            supported_commands: support.supported_commands.clone(),
+            buffer_capacity: support.buffer_capacity,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct CachedClient {
+    capabilities: ClientCapabilities,
+    format: AudioFormat,
+    stored: u64,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    clients: HashMap<ClientId, CachedClient>,
+    next: u64,
+}
+
+/// Capabilities and stream format per client ID
+///
+/// A client that reconnects advertising exactly what it did before gets the
+/// format it was last streamed in, including any codec header, so audio can
+/// start without a second `stream/start`. Cheap to clone; clones share the
+/// same cache.
+#[derive(Debug, Clone)]
+pub struct CapabilityCache {
+    entries: Arc<Mutex<Entries>>,
+    capacity: usize,
+}
+
+impl CapabilityCache {
+    /// Create a cache holding up to `capacity` clients
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: Arc::new(Mutex::new(Entries::default())),
+            capacity,
+        }
+    }
+
+    /// Remember what a client advertised and the format it was given
+    pub fn store(&self, client_id: &str, capabilities: ClientCapabilities, format: AudioFormat) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock();
+        entries.next += 1;
+        let stored = entries.next;
+        entries.clients.insert(
+            client_id.to_string(),
+            CachedClient {
+                capabilities,
+                format,
+                stored,
+            },
+        );
+        if entries.clients.len() > self.capacity {
+            let oldest = entries
+                .clients
+                .iter()
+                .min_by_key(|(_, cached)| cached.stored)
+                .map(|(id, _)| id.clone());
+            if let Some(oldest) = oldest {
+                entries.clients.remove(&oldest);
+            }
+        }
+    }
+
+    /// Replace the format remembered for a client, e.g. once the engine has
+    /// settled the actual stream format
+    pub fn update_format(&self, client_id: &str, format: AudioFormat) {
+        if let Some(cached) = self.entries.lock().clients.get_mut(client_id) {
+            cached.format = format;
+        }
+    }
+
+    /// The format to reuse for a client advertising `capabilities`
+    ///
+    /// Returns None, and forgets the client, if it advertises anything
+    /// different from last time.
+    pub fn format_for(
+        &self,
+        client_id: &str,
+        capabilities: &ClientCapabilities,
+    ) -> Option<AudioFormat> {
+        let mut entries = self.entries.lock();
+        let cached = entries.clients.get(client_id)?;
+        if cached.capabilities == *capabilities {
+            return Some(cached.format.clone());
+        }
+        entries.clients.remove(client_id);
+        None
+    }
+
+    /// Forget a client, so its next connection negotiates from scratch
+    pub fn forget(&self, client_id: &str) {
+        self.entries.lock().clients.remove(client_id);
+    }
+
+    /// Number of clients remembered
+    pub fn len(&self) -> usize {
+        self.entries.lock().clients.len()
+    }
+
+    /// Whether no client is remembered
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for CapabilityCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPABILITY_CACHE_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::types::Codec;
+
+    fn capabilities(codec: &str) -> ClientCapabilities {
+        ClientCapabilities {
+            supported_formats: vec![AudioFormatSpec {
+                codec: codec.to_string(),
+                channels: 2,
+                sample_rate: 48000,
+                bit_depth: 16,
+            }],
+            supported_commands: vec!["volume".to_string()],
+            buffer_capacity: 1_000_000,
+        }
+    }
+
+    fn format(codec: Codec) -> AudioFormat {
+        AudioFormat {
+            codec,
+            sample_rate: 48000,
+            channels: 2,
+            bit_depth: 16,
+            codec_header: None,
+        }
+    }
+
+    #[test]
+    fn test_reuses_format_for_same_capabilities() {
+        let cache = CapabilityCache::default();
+        cache.store("kitchen", capabilities("flac"), format(Codec::Flac));
+
+        let mut settled = format(Codec::Flac);
+        settled.codec_header = Some(vec![1, 2, 3]);
+        cache.update_format("kitchen", settled.clone());
+
+        assert_eq!(
+            cache.format_for("kitchen", &capabilities("flac")),
+            Some(settled)
+        );
+        assert_eq!(cache.format_for("lounge", &capabilities("flac")), None);
+    }
+
+    #[test]
+    fn test_changed_capabilities_renegotiate() {
+        let cache = CapabilityCache::default();
+        cache.store("kitchen", capabilities("flac"), format(Codec::Flac));
+
+        assert_eq!(cache.format_for("kitchen", &capabilities("pcm")), None);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_evicts_least_recently_stored() {
+        let cache = CapabilityCache::new(2);
+        for id in ["a", "b", "c"] {
+            cache.store(id, capabilities("pcm"), format(Codec::Pcm));
+        }
+
+        assert_eq!(cache.len(), 2);
+        assert!(cache.format_for("a", &capabilities("pcm")).is_none());
+        assert!(cache.format_for("c", &capabilities("pcm")).is_some());
+    }
+}
@@ -0,0 +1,270 @@
+// ABOUTME: Stream source recording the host's own audio through cpal
+// ABOUTME: Captures an input device or loops back the system output so every room hears it
+
+use crate::audio::downmix::{Downmix, DownmixLevels, Speaker};
+use crate::audio::types::Sample;
+use crate::protocol::messages::AudioFormatSpec;
+use crate::server::audio_source::AudioSource;
+use crate::server::ingest::{ingest_source, IngestFeed, IngestSource};
+use crate::server::source_events::TrackInfo;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{Device, SampleFormat, SizedSample, Stream, StreamConfig};
+use std::sync::mpsc;
+
+/// URI scheme for [`CaptureSource`]s: `capture:`, `capture:loopback` or `capture:<device>`
+pub const CAPTURE_SCHEME: &str = "capture:";
+
+/// What a [`CaptureSource`] records
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CaptureDevice {
+    /// The host's default input (microphone, line in)
+    DefaultInput,
+    /// Whatever the machine is playing
+    ///
+    /// WASAPI records the default output device directly. PulseAudio and
+    /// PipeWire expose it as a "Monitor of ..." input, which is used instead.
+    Loopback,
+    /// The input device with this name, as listed by [`capture_devices`]
+    Named(String),
+}
+
+impl CaptureDevice {
+    /// Parse what follows [`CAPTURE_SCHEME`] in a source URI
+    pub fn parse(spec: &str) -> Self {
+        match spec {
+            "" | "default" => CaptureDevice::DefaultInput,
+            "loopback" | "system" => CaptureDevice::Loopback,
+            name => CaptureDevice::Named(name.to_string()),
+        }
+    }
+}
+
+/// Names of the host's input devices
+pub fn capture_devices() -> Vec<String> {
+    cpal::default_host()
+        .input_devices()
+        .map(|devices| devices.filter_map(|device| device.name().ok()).collect())
+        .unwrap_or_default()
+}
+
+/// Device to record from, and whether it is an output recorded by loopback
+fn find_device(device: &CaptureDevice) -> Result<(Device, bool), String> {
+    let host = cpal::default_host();
+    let input_named = |wanted: &dyn Fn(&str) -> bool| {
+        host.input_devices()
+            .ok()
+            .and_then(|mut devices| devices.find(|d| d.name().is_ok_and(|name| wanted(&name))))
+    };
+    match device {
+        CaptureDevice::DefaultInput => host
+            .default_input_device()
+            .map(|device| (device, false))
+            .ok_or_else(|| "no default input device".to_string()),
+        CaptureDevice::Named(name) => input_named(&|found| found == name)
+            .map(|device| (device, false))
+            .ok_or_else(|| format!("no input device named '{}'", name)),
+        CaptureDevice::Loopback if cfg!(target_os = "windows") => host
+            .default_output_device()
+            .map(|device| (device, true))
+            .ok_or_else(|| "no output device to loop back".to_string()),
+        CaptureDevice::Loopback => input_named(&|found| found.to_lowercase().contains("monitor"))
+            .map(|device| (device, false))
+            .ok_or_else(|| {
+                "no monitor input found; set PULSE_SOURCE to the output's \
+                 monitor and capture the default input instead"
+                    .to_string()
+            }),
+    }
+}
+
+/// Device sample types the capture callback can read
+trait InputSample: SizedSample + Send + 'static {
+    fn to_server_sample(self) -> Sample;
+}
+
+impl InputSample for f32 {
+    fn to_server_sample(self) -> Sample {
+        Sample(self).clamp()
+    }
+}
+
+impl InputSample for i32 {
+    fn to_server_sample(self) -> Sample {
+        Sample::from_i32(self)
+    }
+}
+
+impl InputSample for i16 {
+    fn to_server_sample(self) -> Sample {
+        Sample::from_i16(self)
+    }
+}
+
+impl InputSample for u16 {
+    fn to_server_sample(self) -> Sample {
+        Sample::from_i16((self as i32 - 32_768) as i16)
+    }
+}
+
+/// Stream source playing what a local audio device records
+///
+/// The device is recorded at its own rate and folded to stereo. Audio is
+/// buffered like a source client's ([`crate::server::INGEST_PREFILL_MS`]
+/// ahead), and the recording stops when the source is dropped.
+pub struct CaptureSource {
+    device_name: String,
+    input: IngestSource,
+    // Dropping this ends the thread that owns the cpal stream
+    _stop: mpsc::Sender<()>,
+}
+
+impl CaptureSource {
+    /// Start recording `device`
+    pub fn open(device: CaptureDevice, downmix: DownmixLevels) -> Result<Self, String> {
+        let (ready_tx, ready_rx) = mpsc::channel();
+        let (stop_tx, stop_rx) = mpsc::channel::<()>();
+        // cpal streams cannot move between threads on every platform, so
+        // one thread owns the stream for as long as the source lives
+        std::thread::Builder::new()
+            .name("audio-capture".to_string())
+            .spawn(move || match start_capture(&device, downmix) {
+                Ok((stream, started)) => {
+                    let _ = ready_tx.send(Ok(started));
+                    let _ = stop_rx.recv();
+                    drop(stream);
+                }
+                Err(e) => {
+                    let _ = ready_tx.send(Err(e));
+                }
+            })
+            .map_err(|e| format!("cannot start capture thread: {}", e))?;
+        let (device_name, input) = ready_rx
+            .recv()
+            .map_err(|_| "capture thread exited".to_string())??;
+        Ok(Self {
+            device_name,
+            input,
+            _stop: stop_tx,
+        })
+    }
+}
+
+/// Open and start the cpal stream for `device`, feeding a new ingest buffer
+fn start_capture(
+    device: &CaptureDevice,
+    downmix: DownmixLevels,
+) -> Result<(Stream, (String, IngestSource)), String> {
+    let (device, loopback) = find_device(device)?;
+    let name = device
+        .name()
+        .unwrap_or_else(|_| "capture device".to_string());
+    let default = if loopback {
+        device.default_output_config()
+    } else {
+        device.default_input_config()
+    }
+    .map_err(|e| format!("cannot read the format of '{}': {}", name, e))?;
+
+    let channels = default.channels() as usize;
+    let format = AudioFormatSpec {
+        codec: "pcm".to_string(),
+        channels: 2,
+        sample_rate: default.sample_rate().0,
+        bit_depth: 24,
+    };
+    let (input, feed) = ingest_source(&name, &format)?;
+    let downmix = Downmix::stereo(&Speaker::default_layout(channels), downmix);
+    let config: StreamConfig = default.config();
+    let stream = match default.sample_format() {
+        SampleFormat::F32 => build_stream::<f32>(&device, &config, feed, downmix),
+        SampleFormat::I32 => build_stream::<i32>(&device, &config, feed, downmix),
+        SampleFormat::I16 => build_stream::<i16>(&device, &config, feed, downmix),
+        SampleFormat::U16 => build_stream::<u16>(&device, &config, feed, downmix),
+        other => Err(format!("device sample format {:?} is not supported", other)),
+    }?;
+    stream
+        .play()
+        .map_err(|e| format!("cannot start recording '{}': {}", name, e))?;
+    log::info!(
+        "Capturing '{}' ({}Hz, {} channels{})",
+        name,
+        format.sample_rate,
+        channels,
+        if loopback { ", loopback" } else { "" }
+    );
+    Ok((stream, (name, input)))
+}
+
+fn build_stream<T: InputSample>(
+    device: &Device,
+    config: &StreamConfig,
+    feed: IngestFeed,
+    downmix: Downmix,
+) -> Result<Stream, String> {
+    let mut frames = Vec::new();
+    let mut stereo = Vec::new();
+    device
+        .build_input_stream(
+            config,
+            move |data: &[T], _: &cpal::InputCallbackInfo| {
+                frames.clear();
+                frames.extend(data.iter().map(|&s| s.to_server_sample().0));
+                stereo.clear();
+                downmix.extend(&frames, usize::MAX, &mut stereo);
+                feed.push_samples(&stereo);
+            },
+            |err| log::warn!("Capture stream error: {}", err),
+            None,
+        )
+        .map_err(|e| e.to_string())
+}
+
+impl AudioSource for CaptureSource {
+    fn read_chunk(&mut self, samples_per_channel: usize) -> Option<Vec<Sample>> {
+        self.input.read_chunk(samples_per_channel)
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn channels(&self) -> u8 {
+        2
+    }
+
+    fn is_exhausted(&self) -> bool {
+        self.input.is_exhausted()
+    }
+
+    fn description(&self) -> Option<String> {
+        Some(format!("capture of {}", self.device_name))
+    }
+
+    fn track_info(&self) -> TrackInfo {
+        self.input.track_info()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_capture_device() {
+        assert_eq!(CaptureDevice::parse(""), CaptureDevice::DefaultInput);
+        assert_eq!(CaptureDevice::parse("loopback"), CaptureDevice::Loopback);
+        assert_eq!(
+            CaptureDevice::parse("USB Audio"),
+            CaptureDevice::Named("USB Audio".to_string())
+        );
+    }
+
+    #[test]
+    fn test_input_samples_convert_to_float() {
+        assert_eq!(1.0f32.to_server_sample(), Sample::MAX);
+        assert_eq!(0.0f32.to_server_sample(), Sample::ZERO);
+        assert_eq!(i16::MIN.to_server_sample(), Sample::MIN);
+        assert_eq!(32_768u16.to_server_sample(), Sample::ZERO);
+        assert_eq!(i32::MAX.to_server_sample(), Sample::MAX);
+    }
+}
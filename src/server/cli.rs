@@ -1,9 +1,22 @@
 // ABOUTME: Shared CLI argument parsing and server builder utilities
 // ABOUTME: Consolidates common code between server binaries (server.rs, server_tui.rs)
 
-use crate::server::{AudioSource, FileSource, ServerConfig, TestToneSource, UrlSource};
+use crate::audio::dither::DitherMode;
+use crate::audio::downmix::{ChannelMap, DownmixLevels};
+use crate::audio::loudness::LoudnessNormalization;
+use crate::audio::replay_gain::{ReplayGain, ReplayGainMode};
+use crate::audio::types::Codec;
+use crate::server::{
+    check_server_config, open_source, AdaptiveBufferConfig, ApiKey, AudioSource, AutoStart,
+    CodecConstraints, CodecOverride, ConfigFile, ConfigReport, EncoderSettings, Fallback,
+    FallbackConfig, FileSource, InboundLimits, LinkTier, Permission, PipeFormat, ReplicationConfig,
+    RoleLimits, ScrobblerConfig, ServerConfig, SilenceTrim, StreamDefinition, TestToneSource,
+    UrlCache, UrlSource, CAPTURE_SCHEME, PIPE_SCHEME, SNAPCAST_SCHEME,
+};
 use clap::Args;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::time::Duration;
 
 /// Common server arguments shared between all server binaries
 ///
@@ -19,6 +32,11 @@ use std::net::SocketAddr;
 /// ```
 #[derive(Args, Debug, Clone)]
 pub struct ServerArgs {
+    /// TOML config file; its settings override these options and safe changes
+    /// (name, buffer-ahead, groups) apply without a restart
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
     /// Address to bind the server to
     #[arg(short, long, default_value = "0.0.0.0:8927")]
     pub bind: SocketAddr,
@@ -39,6 +57,38 @@ pub struct ServerArgs {
     #[arg(long, conflicts_with = "file")]
     pub url: Option<String>,
 
+    /// Stream a local audio device: "loopback" for what the machine is playing,
+    /// "default" for the default input, or an input device's name
+    #[arg(
+        long,
+        value_name = "DEVICE",
+        num_args = 0..=1,
+        default_missing_value = "loopback",
+        conflicts_with_all = ["file", "url"]
+    )]
+    pub capture: Option<String>,
+
+    /// Stream raw PCM written to this named pipe ("-" for stdin), as Snapcast does
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["file", "url", "capture"])]
+    pub pipe: Option<String>,
+
+    /// Sample format of --pipe as rate:bits:channels
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        default_value = "48000:16:2",
+        requires = "pipe"
+    )]
+    pub pipe_format: PipeFormat,
+
+    /// Join this Snapcast server (HOST[:PORT]) as a client and stream what it plays
+    #[arg(
+        long,
+        value_name = "HOST",
+        conflicts_with_all = ["file", "url", "capture", "pipe"]
+    )]
+    pub snapcast: Option<String>,
+
     /// Test tone frequency in Hz (only used if no file/url is specified, 0 for silence)
     #[arg(short, long, default_value = "440.0")]
     pub frequency: f64,
@@ -47,6 +97,14 @@ pub struct ServerArgs {
     #[arg(short, long, default_value = "48000")]
     pub sample_rate: u32,
 
+    /// Pin the output to this sample rate, resampling sources at other rates
+    #[arg(long, value_name = "HZ")]
+    pub fixed_sample_rate: Option<u32>,
+
+    /// Pin the output to this bit depth (16 or 24)
+    #[arg(long, value_name = "BITS")]
+    pub fixed_bit_depth: Option<u8>,
+
     /// Audio chunk interval in milliseconds
     #[arg(long, default_value = "20")]
     pub chunk_ms: u64,
@@ -55,11 +113,433 @@ pub struct ServerArgs {
     #[arg(long, default_value = "500")]
     pub buffer_ahead_ms: u64,
 
+    /// Adapt buffer-ahead per group from client-reported RTT and buffer levels
+    #[arg(long)]
+    pub adaptive_buffer: bool,
+
+    /// Lower bound for adaptive buffer-ahead in milliseconds
+    #[arg(long, default_value = "100")]
+    pub buffer_min_ms: u64,
+
+    /// Upper bound for adaptive buffer-ahead in milliseconds
+    #[arg(long, default_value = "2000")]
+    pub buffer_max_ms: u64,
+
+    /// Keep generating audio even when no players are connected
+    #[arg(long)]
+    pub no_idle_standby: bool,
+
+    /// What a group does when its first player connects
+    #[arg(long, value_enum, default_value = "always")]
+    pub auto_start: AutoStart,
+
+    /// Cap a client's volume, as CLIENT_ID=PERCENT (repeatable)
+    #[arg(long = "max-volume", value_name = "CLIENT_ID=PERCENT", value_parser = parse_client_percent)]
+    pub max_volumes: Vec<(String, u8)>,
+
+    /// Volume for a client's first connection, as CLIENT_ID=PERCENT with `*` for
+    /// all other clients (repeatable)
+    #[arg(long = "initial-volume", value_name = "CLIENT_ID=PERCENT", value_parser = parse_client_percent)]
+    pub initial_volumes: Vec<(String, u8)>,
+
+    /// Start a client muted on its first connection; `*` mutes all other clients (repeatable)
+    #[arg(long = "initial-mute", value_name = "CLIENT_ID")]
+    pub initial_mutes: Vec<String>,
+
+    /// Send a client mono audio (L+R at -3dB), e.g. a single ceiling speaker (repeatable)
+    #[arg(long = "mono", value_name = "CLIENT_ID")]
+    pub mono_clients: Vec<String>,
+
+    /// Map a client's channels, as CLIENT_ID=MAP with MAP one of stereo, mono,
+    /// left, right, swap; left and right split a stereo pair across two speakers (repeatable)
+    #[arg(long = "channel-map", value_name = "CLIENT_ID=MAP", value_parser = parse_channel_map)]
+    pub channel_maps: Vec<(String, ChannelMap)>,
+
+    /// Tag a client with how it is connected, as CLIENT_ID=TIER with TIER one
+    /// of wired, wireless, bluetooth (repeatable)
+    #[arg(long = "link-tier", value_name = "CLIENT_ID=TIER", value_parser = parse_link_tier)]
+    pub link_tiers: Vec<(String, LinkTier)>,
+
+    /// Buffer-ahead for players on a link tier, as TIER=MS; longer than their
+    /// group's, it delays only those players (repeatable)
+    #[arg(long = "tier-buffer-ms", value_name = "TIER=MS", value_parser = parse_tier_buffer)]
+    pub tier_buffer_ahead: Vec<(LinkTier, u64)>,
+
+    /// Serve the HTTP control API under /api
+    #[arg(long)]
+    pub control_api: bool,
+
+    /// Control API key, as KEY or KEY:read / KEY:control (repeatable, default control)
+    #[arg(long = "api-key", value_name = "KEY[:PERMISSION]", value_parser = parse_api_key)]
+    pub api_keys: Vec<ApiKey>,
+
+    /// Prefix for all routes when served under a reverse-proxy path (e.g. /audio)
+    #[arg(long, default_value = "")]
+    pub path_prefix: String,
+
+    /// Externally reachable WebSocket URL to advertise (e.g. wss://example.com/audio/sendspin)
+    #[arg(long)]
+    pub public_url: Option<String>,
+
+    /// Trust X-Forwarded-For / X-Real-IP headers from a reverse proxy
+    /// (one on this host, unless --trusted-proxy is given)
+    #[arg(long)]
+    pub trust_proxy: bool,
+
+    /// Address of a reverse proxy whose forwarded headers to trust (repeatable)
+    #[arg(long = "trusted-proxy", value_name = "IP")]
+    pub trusted_proxies: Vec<IpAddr>,
+
+    /// Expose playback as an MPRIS player so desktop media keys control it (Linux)
+    #[arg(long)]
+    pub mpris: bool,
+
+    /// Don't advertise the server via mDNS (_sendspin-server._tcp)
+    #[arg(long)]
+    pub no_mdns: bool,
+
+    /// Offer XOR parity FEC to clients that request it, protecting up to N chunks per parity frame
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u8).range(2..=32))]
+    pub fec_max_group_size: Option<u8>,
+
+    /// Codecs in order of preference when a client supports several (e.g. flac,opus,pcm)
+    #[arg(long, value_name = "CODECS", value_delimiter = ',', value_parser = parse_codec)]
+    pub codec_preference: Vec<Codec>,
+
+    /// Highest sample rate to stream with a codec, as CODEC=HZ (repeatable)
+    #[arg(long = "codec-max-rate", value_name = "CODEC=HZ", value_parser = parse_codec_value)]
+    pub codec_max_rates: Vec<(Codec, u32)>,
+
+    /// Pin a client to a codec, as CLIENT_ID=CODEC (repeatable)
+    #[arg(long = "codec-override", value_name = "CLIENT_ID=CODEC", value_parser = parse_codec_override)]
+    pub codec_overrides: Vec<(String, CodecOverride)>,
+
+    /// FLAC compression level, 0 (fastest) to 8 (smallest)
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u8).range(0..=8))]
+    pub flac_compression_level: Option<u8>,
+
+    /// Dither used when PCM or FLAC is sent below 24 bits (default tpdf)
+    #[arg(long, value_enum, value_name = "MODE")]
+    pub dither: Option<DitherMode>,
+
+    /// Warn when a group's players drift more than this many milliseconds apart (0 disables)
+    #[arg(long, value_name = "MS", default_value = "5")]
+    pub sync_warn_ms: f64,
+
+    /// Seconds a disconnected client's group and volume are kept for it to reconnect (0 disables)
+    #[arg(long, value_name = "SECS", default_value = "30")]
+    pub reconnect_grace_secs: u64,
+
+    /// Seconds a new connection has to send client/hello before it is refused
+    #[arg(long, value_name = "SECS", default_value = "10", value_parser = clap::value_parser!(u64).range(1..))]
+    pub handshake_timeout_secs: u64,
+
+    /// Accept at most this many controller clients at once
+    #[arg(long, value_name = "N")]
+    pub max_controllers: Option<usize>,
+
+    /// Refuse the metadata role
+    #[arg(long)]
+    pub no_metadata: bool,
+
+    /// Only accept players (no controllers or metadata clients)
+    #[arg(long, conflicts_with_all = ["max_controllers", "no_metadata"])]
+    pub player_only: bool,
+
+    /// Accept source clients pushing live audio, which replaces what their group's stream plays
+    #[arg(long, conflicts_with = "player_only")]
+    pub allow_source: bool,
+
+    /// Close connections that send a message larger than this many bytes
+    #[arg(long, value_name = "BYTES", default_value = "65536", value_parser = clap::value_parser!(u64).range(1024..))]
+    pub max_message_bytes: u64,
+
+    /// Close connections that send more messages per second than this, with bursts of twice as many (0 disables)
+    #[arg(long, value_name = "N", default_value = "50")]
+    pub max_messages_per_sec: u32,
+
+    /// Play this while the source is exhausted or underrunning: silence, tone, tone:HZ, or a URL
+    #[arg(long, value_name = "FALLBACK")]
+    pub fallback: Option<Fallback>,
+
+    /// Seconds the source must keep failing before switching to the fallback
+    #[arg(long, value_name = "SECS", default_value = "3", requires = "fallback")]
+    pub fallback_after_secs: u64,
+
+    /// Seconds between attempts to reopen a failed source
+    #[arg(long, value_name = "SECS", default_value = "5", requires = "fallback")]
+    pub fallback_retry_secs: u64,
+
+    /// Skip leading encoder/decoder silence (up to 2s) at the start of each track
+    #[arg(long)]
+    pub trim_silence: bool,
+
+    /// Level below which leading audio counts as silence, in dBFS
+    #[arg(
+        long,
+        value_name = "DB",
+        default_value = "-60",
+        allow_negative_numbers = true,
+        requires = "trim_silence"
+    )]
+    pub trim_silence_db: f64,
+
+    /// Bring each source's EBU R128 loudness toward a target, so quiet and loud sources match
+    #[arg(long)]
+    pub normalize_loudness: bool,
+
+    /// Loudness sources are brought to, in LUFS
+    #[arg(
+        long,
+        value_name = "LUFS",
+        default_value = "-23",
+        allow_negative_numbers = true,
+        requires = "normalize_loudness"
+    )]
+    pub loudness_target: f64,
+
+    /// Most gain applied to a quiet source, in dB
+    #[arg(
+        long,
+        value_name = "DB",
+        default_value = "12",
+        requires = "normalize_loudness"
+    )]
+    pub loudness_max_gain: f64,
+
+    /// Play files at the level their ReplayGain or R128 tags call for, preferring this gain
+    #[arg(long, value_enum, value_name = "MODE")]
+    pub replay_gain: Option<ReplayGainMode>,
+
+    /// Gain added to the tagged ReplayGain, in dB
+    #[arg(
+        long,
+        value_name = "DB",
+        default_value = "0",
+        allow_negative_numbers = true,
+        requires = "replay_gain"
+    )]
+    pub replay_gain_preamp: f64,
+
+    /// Let the ReplayGain preamp push tagged peaks past full scale
+    #[arg(long, requires = "replay_gain")]
+    pub replay_gain_allow_clipping: bool,
+
+    /// Record each chunk's generation, send, and play-at times to this file (analyse with sendspin-ctl audit)
+    #[arg(long, value_name = "FILE")]
+    pub chunk_audit: Option<PathBuf>,
+
+    /// Keep the playback history in this file (JSON lines) so it survives restarts
+    #[arg(long, value_name = "FILE")]
+    pub history_file: Option<PathBuf>,
+
+    /// Tracks kept in the playback history
+    #[arg(long, value_name = "N", default_value = "500")]
+    pub history_size: usize,
+
+    /// Run as a warm standby mirroring the primary whose control API is at this URL (e.g. http://primary:8927/api)
+    #[arg(long, value_name = "URL")]
+    pub replicate_from: Option<String>,
+
+    /// API key presented to the primary (needs read permission)
+    #[arg(long, value_name = "KEY", requires = "replicate_from")]
+    pub replication_key: Option<String>,
+
+    /// Seconds between replication polls of the primary
+    #[arg(
+        long,
+        value_name = "SECS",
+        default_value = "2",
+        requires = "replicate_from"
+    )]
+    pub replication_interval_secs: u64,
+
+    /// Cache finite HTTP sources (podcasts, files) in this directory
+    #[arg(long, value_name = "DIR")]
+    pub url_cache_dir: Option<PathBuf>,
+
+    /// Evict least recently played downloads beyond this many megabytes
+    #[arg(long, value_name = "MB", requires = "url_cache_dir")]
+    pub url_cache_max_mb: Option<u64>,
+
+    /// Level of the center channel when folding multichannel sources to stereo (dB)
+    #[arg(
+        long,
+        value_name = "DB",
+        default_value = "-3",
+        allow_hyphen_values = true
+    )]
+    pub center_mix_db: f32,
+
+    /// Level of rear and side channels when folding to stereo (dB)
+    #[arg(
+        long,
+        value_name = "DB",
+        default_value = "-3",
+        allow_hyphen_values = true
+    )]
+    pub surround_mix_db: f32,
+
+    /// Level of the LFE channel when folding to stereo (dB; dropped when omitted)
+    #[arg(long, value_name = "DB", allow_hyphen_values = true)]
+    pub lfe_mix_db: Option<f32>,
+
+    /// Level of each side when summing stereo to mono (dB)
+    #[arg(
+        long,
+        value_name = "DB",
+        default_value = "-6",
+        allow_hyphen_values = true
+    )]
+    pub mono_mix_db: f32,
+
+    /// Keep up to this many channels of surround sources (2 folds them to stereo)
+    #[arg(long, value_name = "N", default_value = "2", value_parser = clap::value_parser!(u8).range(2..=8))]
+    pub max_channels: u8,
+
+    /// Directory to keep client volume, mute, name and group in across restarts
+    #[arg(long, value_name = "DIR")]
+    pub state_dir: Option<PathBuf>,
+
+    /// Scrobble plays to ListenBrainz with this user token
+    #[arg(long, value_name = "TOKEN")]
+    pub listenbrainz_token: Option<String>,
+
+    /// Last.fm application API key; scrobbling also needs the secret and a session key
+    #[arg(long, value_name = "KEY", requires_all = ["lastfm_api_secret", "lastfm_session_key"])]
+    pub lastfm_api_key: Option<String>,
+
+    /// Last.fm application shared secret
+    #[arg(long, value_name = "SECRET", requires = "lastfm_api_key")]
+    pub lastfm_api_secret: Option<String>,
+
+    /// Last.fm session key of the account to scrobble to
+    #[arg(long, value_name = "KEY", requires = "lastfm_api_key")]
+    pub lastfm_session_key: Option<String>,
+
+    /// Start an extra stream that groups can be subscribed to (repeatable)
+    #[arg(long = "stream", value_name = "ID=URI", value_parser = parse_stream)]
+    pub streams: Vec<StreamDefinition>,
+
     /// Enable verbose logging
     #[arg(short, long)]
     pub verbose: bool,
 }
 
+/// Parse a `KEY[:read|:control]` argument
+fn parse_api_key(s: &str) -> Result<ApiKey, String> {
+    let (key, permission) = match s.rsplit_once(':') {
+        Some((key, "read")) => (key, Permission::Read),
+        Some((key, "control")) => (key, Permission::Control),
+        _ => (s, Permission::Control),
+    };
+    if key.is_empty() {
+        return Err("API key must not be empty".to_string());
+    }
+    Ok(ApiKey::new(key, permission))
+}
+
+/// Parse a `CLIENT_ID=PERCENT` argument
+fn parse_client_percent(s: &str) -> Result<(String, u8), String> {
+    let (client_id, percent) = s
+        .rsplit_once('=')
+        .ok_or_else(|| format!("expected CLIENT_ID=PERCENT, got '{}'", s))?;
+    let percent: u8 = percent
+        .parse()
+        .map_err(|_| format!("invalid percentage '{}'", percent))?;
+    if client_id.is_empty() || percent > 100 {
+        return Err(format!("expected CLIENT_ID=PERCENT (0-100), got '{}'", s));
+    }
+    Ok((client_id.to_string(), percent))
+}
+
+/// Parse a link tier name
+fn parse_tier(s: &str) -> Result<LinkTier, String> {
+    <LinkTier as clap::ValueEnum>::from_str(s, true)
+        .map_err(|_| format!("unknown link tier '{}' (wired, wireless, bluetooth)", s))
+}
+
+/// Parse a `CLIENT_ID=TIER` argument
+fn parse_link_tier(s: &str) -> Result<(String, LinkTier), String> {
+    let (client_id, tier) = s
+        .rsplit_once('=')
+        .filter(|(client_id, _)| !client_id.is_empty())
+        .ok_or_else(|| format!("expected CLIENT_ID=TIER, got '{}'", s))?;
+    Ok((client_id.to_string(), parse_tier(tier)?))
+}
+
+/// Parse a `CLIENT_ID=MAP` argument
+fn parse_channel_map(s: &str) -> Result<(String, ChannelMap), String> {
+    let (client_id, map) = s
+        .rsplit_once('=')
+        .filter(|(client_id, _)| !client_id.is_empty())
+        .ok_or_else(|| format!("expected CLIENT_ID=MAP, got '{}'", s))?;
+    let map = <ChannelMap as clap::ValueEnum>::from_str(map, true).map_err(|_| {
+        format!(
+            "unknown channel map '{}' (stereo, mono, left, right, swap)",
+            map
+        )
+    })?;
+    Ok((client_id.to_string(), map))
+}
+
+/// Parse a `TIER=MS` argument
+fn parse_tier_buffer(s: &str) -> Result<(LinkTier, u64), String> {
+    let (tier, ms) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected TIER=MS, got '{}'", s))?;
+    let ms = ms
+        .parse()
+        .map_err(|_| format!("invalid buffer-ahead '{}'", ms))?;
+    Ok((parse_tier(tier)?, ms))
+}
+
+/// Parse a codec name
+fn parse_codec(s: &str) -> Result<Codec, String> {
+    Codec::from_name(s).ok_or_else(|| format!("unknown codec '{}' (pcm, opus, flac, mp3)", s))
+}
+
+/// Last value given for a codec in repeated `CODEC=VALUE` arguments
+fn find_codec_value(values: &[(Codec, u32)], codec: Codec) -> Option<u32> {
+    values
+        .iter()
+        .rev()
+        .find(|(c, _)| *c == codec)
+        .map(|&(_, v)| v)
+}
+
+/// Parse a `CODEC=VALUE` argument
+fn parse_codec_value(s: &str) -> Result<(Codec, u32), String> {
+    let (codec, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected CODEC=VALUE, got '{}'", s))?;
+    let value = value
+        .parse()
+        .map_err(|_| format!("invalid value '{}'", value))?;
+    Ok((parse_codec(codec)?, value))
+}
+
+/// Parse a `CLIENT_ID=CODEC` argument
+fn parse_codec_override(s: &str) -> Result<(String, CodecOverride), String> {
+    let (client_id, pin) = s
+        .rsplit_once('=')
+        .filter(|(client_id, _)| !client_id.is_empty())
+        .ok_or_else(|| format!("expected CLIENT_ID=CODEC, got '{}'", s))?;
+    let pin = CodecOverride::new(parse_codec(pin)?);
+    Ok((client_id.to_string(), pin))
+}
+
+/// Parse an `ID=URI` stream argument
+fn parse_stream(s: &str) -> Result<StreamDefinition, String> {
+    match s.split_once('=') {
+        Some((id, uri)) if !id.is_empty() && !uri.is_empty() => Ok(StreamDefinition {
+            id: id.to_string(),
+            uri: uri.to_string(),
+        }),
+        _ => Err(format!("expected ID=URI, got '{}'", s)),
+    }
+}
+
 impl ServerArgs {
     /// Initialize tracing based on verbosity flag
     pub fn init_tracing(&self) {
@@ -84,18 +564,98 @@ impl ServerArgs {
     pub fn log_startup_info(&self) {
         tracing::info!("Sendspin Server v{}", env!("CARGO_PKG_VERSION"));
         tracing::info!("Bind: {}", self.bind);
-        tracing::info!("Endpoint: ws://{}{}", self.bind, self.path);
+        match &self.public_url {
+            Some(url) => tracing::info!("Endpoint: {}", url),
+            None => tracing::info!(
+                "Endpoint: ws://{}{}{}",
+                self.bind,
+                crate::server::proxy::normalize_prefix(&self.path_prefix),
+                self.path
+            ),
+        }
     }
 
-    /// Create audio source based on args (priority: file > url > test tone)
+    /// Downmix levels from the `--*-mix-db` and `--max-channels` arguments
+    pub fn downmix_levels(&self) -> DownmixLevels {
+        DownmixLevels {
+            center: DownmixLevels::db_to_gain(self.center_mix_db),
+            lfe: self.lfe_mix_db.map_or(0.0, DownmixLevels::db_to_gain),
+            surround: DownmixLevels::db_to_gain(self.surround_mix_db),
+            mono: DownmixLevels::db_to_gain(self.mono_mix_db),
+            max_channels: self.max_channels,
+        }
+    }
+
+    /// ReplayGain settings from `--replay-gain` and its options
+    pub fn replay_gain(&self) -> Option<ReplayGain> {
+        self.replay_gain.map(|mode| ReplayGain {
+            mode,
+            preamp_db: self.replay_gain_preamp,
+            prevent_clipping: !self.replay_gain_allow_clipping,
+        })
+    }
+
+    /// URL cache from `--url-cache-dir` and `--url-cache-max-mb`
+    pub fn url_cache(&self) -> Option<UrlCache> {
+        let cache = UrlCache::new(self.url_cache_dir.clone()?);
+        Some(match self.url_cache_max_mb {
+            Some(mb) => cache.with_max_bytes(mb * 1024 * 1024),
+            None => cache,
+        })
+    }
+
+    /// Load the `--config` file, if one was given
+    pub fn config_file(&self) -> Result<Option<ConfigFile>, String> {
+        self.config.as_deref().map(ConfigFile::load).transpose()
+    }
+
+    /// Create audio source based on args (priority: config file > file > url > test tone)
     ///
     /// Returns the audio source and logs information about what was created.
     pub fn create_audio_source(
         &self,
     ) -> Result<Box<dyn AudioSource>, Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(uri) = self.config_file()?.and_then(|file| file.source) {
+            let (downmix, cache) = (self.downmix_levels(), self.url_cache());
+            return match open_source(&uri, downmix, self.replay_gain(), cache.as_ref()) {
+                Ok(source) => {
+                    tracing::info!(
+                        "Audio: Streaming '{}' from config file ({}Hz, {} channels)",
+                        uri,
+                        source.sample_rate(),
+                        source.channels()
+                    );
+                    Ok(source)
+                }
+                Err(e) => {
+                    tracing::error!("Failed to open source '{}': {}", uri, e);
+                    Err(format!("Failed to open source: {}", e).into())
+                }
+            };
+        }
+        if let Some(uri) = self.live_uri() {
+            return match open_source(&uri, self.downmix_levels(), self.replay_gain(), None) {
+                Ok(source) => {
+                    tracing::info!(
+                        "Audio: Streaming {} ({}Hz)",
+                        source.description().unwrap_or(uri),
+                        source.sample_rate()
+                    );
+                    Ok(source)
+                }
+                Err(e) => {
+                    tracing::error!("Failed to open source '{}': {}", uri, e);
+                    Err(format!("Failed to open source: {}", e).into())
+                }
+            };
+        }
         if let Some(file_path) = &self.file {
             match FileSource::new(file_path) {
                 Ok(file_source) => {
+                    let mut file_source = file_source.with_downmix(self.downmix_levels());
+                    if let Some(settings) = self.replay_gain() {
+                        file_source = file_source.with_replay_gain(settings);
+                    }
                     tracing::info!(
                         "Audio: Streaming from file '{}' ({}Hz, {} channels, looping)",
                         file_path,
@@ -110,8 +670,13 @@ impl ServerArgs {
                 }
             }
         } else if let Some(url) = &self.url {
-            match UrlSource::new(url) {
+            let source = match self.url_cache() {
+                Some(cache) => UrlSource::with_cache(url, &cache),
+                None => UrlSource::new(url),
+            };
+            match source {
                 Ok(url_source) => {
+                    let url_source = url_source.with_downmix(self.downmix_levels());
                     tracing::info!(
                         "Audio: Streaming from URL '{}' ({}Hz, {} channels)",
                         url,
@@ -130,28 +695,260 @@ impl ServerArgs {
                 tracing::info!(
                     "Audio: {} Hz test tone at {} Hz sample rate",
                     self.frequency,
-                    self.sample_rate
+                    self.tone_sample_rate()
                 );
             } else {
                 tracing::info!("Audio: Silence");
             }
             Ok(Box::new(TestToneSource::new(
                 self.frequency.max(0.0),
-                self.sample_rate,
+                self.tone_sample_rate(),
             )))
         }
     }
 
+    /// `--capture`, `--pipe` or `--snapcast` as a source URI
+    fn live_uri(&self) -> Option<String> {
+        self.capture_uri()
+            .or_else(|| self.pipe_uri())
+            .or_else(|| self.snapcast_uri())
+    }
+
+    /// `--capture` as a source URI
+    fn capture_uri(&self) -> Option<String> {
+        self.capture
+            .as_ref()
+            .map(|device| format!("{}{}", CAPTURE_SCHEME, device))
+    }
+
+    /// `--pipe` and `--pipe-format` as a source URI
+    fn pipe_uri(&self) -> Option<String> {
+        self.pipe
+            .as_ref()
+            .map(|path| format!("{}{}?sampleformat={}", PIPE_SCHEME, path, self.pipe_format))
+    }
+
+    /// `--snapcast` as a source URI
+    fn snapcast_uri(&self) -> Option<String> {
+        self.snapcast
+            .as_ref()
+            .map(|host| format!("{}//{}", SNAPCAST_SCHEME, host))
+    }
+
+    /// Sample rate of the test tone: the fixed output rate, else `--sample-rate`
+    fn tone_sample_rate(&self) -> u32 {
+        self.fixed_sample_rate.unwrap_or(self.sample_rate)
+    }
+
+    /// Check the configuration and sources these args describe without starting
+    ///
+    /// Uses the same source priority as [`ServerArgs::create_audio_source`].
+    pub fn check_config(&self) -> ConfigReport {
+        let mut config = match self.load_config() {
+            Ok(config) => config,
+            Err(e) => {
+                let mut report = ConfigReport::default();
+                let subject = self.config.as_ref().map(|path| path.display().to_string());
+                report.error(subject.unwrap_or_else(|| "--config".to_string()), e);
+                return report;
+            }
+        };
+        let file_source = self
+            .config_file()
+            .ok()
+            .flatten()
+            .and_then(|file| file.source);
+        let live = self.live_uri();
+        let source = file_source
+            .as_deref()
+            .or(live.as_deref())
+            .or(self.file.as_deref())
+            .or(self.url.as_deref());
+        if source.is_none() {
+            config.default_sample_rate = self.tone_sample_rate();
+        }
+        check_server_config(source, &config)
+    }
+
+    /// Build ServerConfig from these args with the `--config` file applied over them
+    pub fn load_config(&self) -> Result<ServerConfig, String> {
+        let config = self.build_config();
+        match (&self.config, self.config_file()?) {
+            (Some(path), Some(file)) => Ok(file.apply(config)?.config_file(path)),
+            _ => Ok(config),
+        }
+    }
+
     /// Build ServerConfig from these args
     ///
     /// Note: This consumes `path` due to the ServerConfig builder pattern.
     /// Call this after `log_startup_info()` if you need the path for logging.
     pub fn build_config(&self) -> ServerConfig {
-        ServerConfig::new(&self.name)
+        let mut config = ServerConfig::new(&self.name)
             .bind_addr(self.bind)
             .ws_path(self.path.clone())
             .chunk_interval_ms(self.chunk_ms)
             .buffer_ahead_ms(self.buffer_ahead_ms)
+            .idle_standby(!self.no_idle_standby)
+            .auto_start(self.auto_start)
+            .control_api(self.control_api)
+            .path_prefix(&self.path_prefix)
+            .trust_forwarded(self.trust_proxy)
+            .mpris(self.mpris)
+            .mdns(!self.no_mdns)
+            .reconnect_grace(Duration::from_secs(self.reconnect_grace_secs))
+            .handshake_timeout(Duration::from_secs(self.handshake_timeout_secs))
+            .inbound_limits(InboundLimits {
+                max_message_bytes: self.max_message_bytes as usize,
+                messages_per_second: self.max_messages_per_sec,
+                burst: self.max_messages_per_sec.saturating_mul(2),
+            })
+            .role_limits(RoleLimits {
+                max_controllers: self.max_controllers,
+                no_metadata: self.no_metadata,
+                player_only: self.player_only,
+                allow_source: self.allow_source,
+            });
+
+        if let Some(url) = &self.public_url {
+            config = config.public_url(url.clone());
+        }
+        if let Some(sample_rate) = self.fixed_sample_rate {
+            config = config.fixed_sample_rate(sample_rate);
+        }
+        if let Some(bit_depth) = self.fixed_bit_depth {
+            config = config.fixed_bit_depth(bit_depth);
+        }
+        if let Some(path) = &self.chunk_audit {
+            config = config.chunk_audit(path);
+        }
+        if let Some(path) = &self.history_file {
+            config = config.history_file(path);
+        }
+        config = config.history_size(self.history_size);
+        if let Some(dir) = &self.state_dir {
+            config = config.state_dir(dir);
+        }
+        if let Some(token) = &self.listenbrainz_token {
+            config = config.scrobbler(ScrobblerConfig::ListenBrainz {
+                token: token.clone(),
+            });
+        }
+        if let (Some(api_key), Some(api_secret), Some(session_key)) = (
+            &self.lastfm_api_key,
+            &self.lastfm_api_secret,
+            &self.lastfm_session_key,
+        ) {
+            config = config.scrobbler(ScrobblerConfig::LastFm {
+                api_key: api_key.clone(),
+                api_secret: api_secret.clone(),
+                session_key: session_key.clone(),
+            });
+        }
+        if let Some(url) = &self.replicate_from {
+            config = config.replicate_from(ReplicationConfig {
+                api_key: self.replication_key.clone(),
+                interval: Duration::from_secs(self.replication_interval_secs.max(1)),
+                ..ReplicationConfig::new(url.clone())
+            });
+        }
+        if self.trim_silence {
+            config = config.silence_trim(SilenceTrim {
+                threshold_db: self.trim_silence_db,
+                ..SilenceTrim::default()
+            });
+        }
+        if self.normalize_loudness {
+            config = config.loudness_normalization(LoudnessNormalization {
+                target_lufs: self.loudness_target,
+                max_gain_db: self.loudness_max_gain,
+            });
+        }
+        if let Some(settings) = self.replay_gain() {
+            config = config.replay_gain(settings);
+        }
+        if let Some(fallback) = &self.fallback {
+            config = config.source_fallback(FallbackConfig {
+                fallback: fallback.clone(),
+                fail_after: Duration::from_secs(self.fallback_after_secs),
+                retry_interval: Duration::from_secs(self.fallback_retry_secs),
+            });
+        }
+        if let Some(max_group_size) = self.fec_max_group_size {
+            config = config.fec(max_group_size);
+        }
+        config = config.downmix(self.downmix_levels());
+        if let Some(cache) = self.url_cache() {
+            config = config.url_cache(cache);
+        }
+        config = config.encoder_settings(EncoderSettings {
+            flac_compression_level: self.flac_compression_level,
+            dither: self.dither,
+        });
+        if self.sync_warn_ms > 0.0 {
+            config = config.sync_warning((self.sync_warn_ms * 1000.0) as i64);
+        }
+
+        for &proxy in &self.trusted_proxies {
+            config = config.trusted_proxy(proxy);
+        }
+
+        if !self.codec_preference.is_empty() {
+            config = config.codec_preference(self.codec_preference.iter().copied());
+        }
+        for &(codec, _) in &self.codec_max_rates {
+            let constraints = CodecConstraints {
+                max_sample_rate: find_codec_value(&self.codec_max_rates, codec),
+            };
+            config = config.codec_constraints(codec, constraints);
+        }
+
+        for (client_id, pin) in &self.codec_overrides {
+            config = config.codec_override(client_id, *pin);
+        }
+        for stream in &self.streams {
+            config = config.stream(&stream.id, &stream.uri);
+        }
+
+        for (client_id, percent) in &self.max_volumes {
+            config = config.max_volume(client_id, *percent);
+        }
+        for client_id in &self.mono_clients {
+            config = config.mono(client_id);
+        }
+        for (client_id, map) in &self.channel_maps {
+            config = config.channel_map(client_id, *map);
+        }
+        for (client_id, tier) in &self.link_tiers {
+            config = config.link_tier(client_id, *tier);
+        }
+        for (tier, ms) in &self.tier_buffer_ahead {
+            config = config.tier_buffer_ahead(*tier, *ms);
+        }
+        for key in &self.api_keys {
+            config = config.api_key(key.key.clone(), key.permission);
+        }
+        for (client_id, percent) in &self.initial_volumes {
+            let muted = self.initial_mutes.contains(client_id);
+            config = config.initial_volume(client_id, *percent, muted);
+        }
+        for client_id in &self.initial_mutes {
+            if !config.initial_volumes.contains_key(client_id) {
+                let volume = config
+                    .initial_volume_for(client_id)
+                    .map_or(100, |v| v.volume);
+                config = config.initial_volume(client_id, volume, true);
+            }
+        }
+
+        if self.adaptive_buffer {
+            config.adaptive_buffer(AdaptiveBufferConfig::new(
+                self.buffer_min_ms,
+                self.buffer_max_ms,
+            ))
+        } else {
+            config
+        }
     }
 }
 
@@ -163,39 +960,363 @@ mod tests {
     fn test_default_args() {
         // Verify default values are sensible
         let args = ServerArgs {
+            config: None,
             bind: "0.0.0.0:8927".parse().unwrap(),
             name: "Test Server".to_string(),
             path: "/sendspin".to_string(),
             file: None,
             url: None,
+            capture: None,
+            pipe: None,
+            pipe_format: PipeFormat::default(),
+            snapcast: None,
             frequency: 440.0,
             sample_rate: 48000,
+            fixed_sample_rate: None,
+            fixed_bit_depth: None,
             chunk_ms: 20,
             buffer_ahead_ms: 500,
+            adaptive_buffer: false,
+            buffer_min_ms: 100,
+            buffer_max_ms: 2000,
+            no_idle_standby: false,
+            auto_start: AutoStart::Always,
+            max_volumes: Vec::new(),
+            initial_volumes: Vec::new(),
+            mono_clients: Vec::new(),
+            channel_maps: Vec::new(),
+            link_tiers: Vec::new(),
+            tier_buffer_ahead: Vec::new(),
+            initial_mutes: Vec::new(),
+            control_api: false,
+            api_keys: Vec::new(),
+            path_prefix: String::new(),
+            public_url: None,
+            trust_proxy: false,
+            trusted_proxies: Vec::new(),
+            mpris: false,
+            no_mdns: false,
+            fec_max_group_size: None,
+            codec_preference: Vec::new(),
+            codec_max_rates: Vec::new(),
+            codec_overrides: Vec::new(),
+            flac_compression_level: None,
+            dither: None,
+            sync_warn_ms: 5.0,
+            reconnect_grace_secs: 30,
+            handshake_timeout_secs: 10,
+            max_controllers: None,
+            no_metadata: false,
+            player_only: false,
+            allow_source: false,
+            max_message_bytes: 65536,
+            max_messages_per_sec: 50,
+            trim_silence: false,
+            trim_silence_db: -60.0,
+            normalize_loudness: false,
+            loudness_target: -23.0,
+            loudness_max_gain: 12.0,
+            replay_gain: None,
+            replay_gain_preamp: 0.0,
+            replay_gain_allow_clipping: false,
+            chunk_audit: None,
+            history_file: None,
+            history_size: 500,
+            replicate_from: None,
+            replication_key: None,
+            replication_interval_secs: 2,
+            fallback: None,
+            fallback_after_secs: 3,
+            fallback_retry_secs: 5,
+            center_mix_db: -3.0,
+            surround_mix_db: -3.0,
+            lfe_mix_db: None,
+            mono_mix_db: -6.0,
+            max_channels: 2,
+            url_cache_dir: None,
+            url_cache_max_mb: None,
+            state_dir: None,
+            listenbrainz_token: None,
+            lastfm_api_key: None,
+            lastfm_api_secret: None,
+            lastfm_session_key: None,
+            streams: vec![],
             verbose: false,
         };
 
         assert_eq!(args.bind.port(), 8927);
         assert_eq!(args.chunk_ms, 20);
         assert_eq!(args.buffer_ahead_ms, 500);
+        assert!(args.streams.is_empty());
+        assert_eq!(args.load_config().unwrap().config_file, None);
     }
 
     #[test]
     fn test_build_config() {
         let args = ServerArgs {
+            config: None,
             bind: "127.0.0.1:9000".parse().unwrap(),
             name: "Custom Server".to_string(),
             path: "/custom".to_string(),
             file: None,
             url: None,
+            capture: None,
+            pipe: None,
+            pipe_format: PipeFormat::default(),
+            snapcast: None,
             frequency: 440.0,
             sample_rate: 48000,
+            fixed_sample_rate: None,
+            fixed_bit_depth: None,
             chunk_ms: 10,
             buffer_ahead_ms: 1000,
+            adaptive_buffer: true,
+            buffer_min_ms: 50,
+            buffer_max_ms: 800,
+            no_idle_standby: true,
+            auto_start: AutoStart::Never,
+            max_volumes: vec![("kids-room".to_string(), 60)],
+            initial_volumes: vec![("*".to_string(), 30)],
+            initial_mutes: vec!["garage".to_string()],
+            mono_clients: vec!["bathroom".to_string()],
+            channel_maps: vec![parse_channel_map("den=LEFT").unwrap()],
+            link_tiers: vec![parse_link_tier("porch=bluetooth").unwrap()],
+            tier_buffer_ahead: vec![parse_tier_buffer("bluetooth=2000").unwrap()],
+            control_api: true,
+            api_keys: vec![ApiKey::new("secret", Permission::Read)],
+            path_prefix: "audio/".to_string(),
+            public_url: None,
+            trust_proxy: true,
+            trusted_proxies: vec!["10.0.0.1".parse().unwrap()],
+            mpris: true,
+            no_mdns: true,
+            fec_max_group_size: Some(8),
+            codec_preference: vec![Codec::Flac, Codec::Pcm],
+            codec_max_rates: vec![(Codec::Flac, 48000)],
+            codec_overrides: vec![("garage".to_string(), CodecOverride::new(Codec::Opus))],
+            flac_compression_level: Some(8),
+            dither: Some(DitherMode::Shaped),
+            sync_warn_ms: 2.5,
+            reconnect_grace_secs: 0,
+            handshake_timeout_secs: 3,
+            max_controllers: Some(2),
+            no_metadata: true,
+            player_only: false,
+            allow_source: true,
+            max_message_bytes: 4096,
+            max_messages_per_sec: 20,
+            trim_silence: true,
+            trim_silence_db: -50.0,
+            normalize_loudness: true,
+            loudness_target: -16.0,
+            loudness_max_gain: 12.0,
+            replay_gain: Some(ReplayGainMode::Album),
+            replay_gain_preamp: 3.0,
+            replay_gain_allow_clipping: false,
+            chunk_audit: Some(PathBuf::from("/tmp/chunks.audit")),
+            history_file: Some(PathBuf::from("/var/lib/sendspin/history.jsonl")),
+            history_size: 50,
+            replicate_from: Some("http://primary:8927/api".to_string()),
+            replication_key: Some("standby".to_string()),
+            replication_interval_secs: 5,
+            fallback: Some(Fallback::Tone(440.0)),
+            fallback_after_secs: 2,
+            fallback_retry_secs: 10,
+            center_mix_db: 0.0,
+            surround_mix_db: -3.0,
+            lfe_mix_db: Some(-10.0),
+            mono_mix_db: -6.0,
+            max_channels: 6,
+            url_cache_dir: Some(PathBuf::from("/var/cache/sendspin")),
+            url_cache_max_mb: Some(512),
+            state_dir: Some(PathBuf::from("/var/lib/sendspin/state")),
+            listenbrainz_token: Some("lb-token".to_string()),
+            lastfm_api_key: None,
+            lastfm_api_secret: None,
+            lastfm_session_key: None,
+            streams: vec![parse_stream("radio=http://radio.example/stream").unwrap()],
             verbose: false,
         };
 
         let config = args.build_config();
-        assert_eq!(config.bind_addr().port(), 9000);
+        assert_eq!(config.bind_addr.port(), 9000);
+        assert!(!config.idle_standby);
+        assert_eq!(config.auto_start, AutoStart::Never);
+        assert_eq!(config.max_volumes.get("kids-room"), Some(&60));
+        assert!(config.mono_clients.contains("bathroom"));
+        assert_eq!(config.channel_maps.get("den"), Some(&ChannelMap::Left));
+        assert_eq!(config.link_tiers.get("porch"), Some(&LinkTier::Bluetooth));
+        assert_eq!(
+            config.tier_buffer_ahead.get(&LinkTier::Bluetooth),
+            Some(&2000)
+        );
+        assert!(config.control_api);
+        assert!(config.trust_forwarded);
+        assert_eq!(
+            config.trusted_proxies,
+            ["10.0.0.1".parse::<IpAddr>().unwrap()]
+        );
+        assert!(config.mpris);
+        assert!(!config.mdns);
+        assert_eq!(config.fec_max_group_size, Some(8));
+        assert_eq!(config.sync_warn_micros, Some(2500));
+        assert_eq!(config.reconnect_grace, Duration::ZERO);
+        assert_eq!(config.handshake_timeout, Duration::from_secs(3));
+        assert_eq!(config.inbound_limits.max_message_bytes, 4096);
+        assert_eq!(config.inbound_limits.burst, 40);
+        assert_eq!(config.role_limits.max_controllers, Some(2));
+        assert!(!config.role_limits.allows_metadata());
+        assert!(config.role_limits.allows_source());
+        let fallback = config.source_fallback.clone().unwrap();
+        assert_eq!(fallback.fallback, Fallback::Tone(440.0));
+        assert_eq!(fallback.fail_after, Duration::from_secs(2));
+        assert_eq!(config.chunk_audit, Some(PathBuf::from("/tmp/chunks.audit")));
+        assert_eq!(
+            config.history_file,
+            Some(PathBuf::from("/var/lib/sendspin/history.jsonl"))
+        );
+        assert_eq!(config.history_size, 50);
+        assert_eq!(
+            config.state_dir,
+            Some(PathBuf::from("/var/lib/sendspin/state"))
+        );
+        assert_eq!(config.streams.len(), 1);
+        assert_eq!(config.streams[0].uri, "http://radio.example/stream");
+        assert_eq!(
+            config.scrobblers,
+            [ScrobblerConfig::ListenBrainz {
+                token: "lb-token".to_string()
+            }]
+        );
+        let replication = config.replication.as_ref().unwrap();
+        assert_eq!(
+            replication.snapshot_url(),
+            "http://primary:8927/api/replication"
+        );
+        assert_eq!(replication.api_key.as_deref(), Some("standby"));
+        assert_eq!(replication.interval, Duration::from_secs(5));
+        assert_eq!(config.silence_trim.unwrap().threshold_db, -50.0);
+        assert_eq!(config.loudness.unwrap().target_lufs, -16.0);
+        let replay_gain = config.replay_gain.unwrap();
+        assert_eq!(replay_gain.mode, ReplayGainMode::Album);
+        assert_eq!(replay_gain.preamp_db, 3.0);
+        assert!(replay_gain.prevent_clipping);
+        let encoder = config.encoder_settings_for(None);
+        assert_eq!(encoder.flac_compression_level, Some(8));
+        assert_eq!(encoder.dither, Some(DitherMode::Shaped));
+        assert_eq!(config.downmix.center, 1.0);
+        assert!((config.downmix.lfe - 0.316).abs() < 0.001);
+        assert_eq!(config.downmix.max_channels, 6);
+        let cache = config.url_cache.as_ref().unwrap();
+        assert_eq!(cache.dir(), std::path::Path::new("/var/cache/sendspin"));
+        assert_eq!(config.codec_policy.preference, [Codec::Flac, Codec::Pcm]);
+        assert_eq!(
+            config
+                .codec_policy
+                .constraints_for(Codec::Flac)
+                .max_sample_rate,
+            Some(48000)
+        );
+        assert_eq!(
+            config.codec_policy.constraints_for(Codec::Opus),
+            CodecConstraints::default()
+        );
+        assert_eq!(
+            config.codec_overrides.get("garage"),
+            Some(&CodecOverride::new(Codec::Opus))
+        );
+        assert_eq!(config.ws_route(), "/audio/custom");
+        assert_eq!(config.advertised_url(), "ws://127.0.0.1:9000/audio/custom");
+        assert_eq!(config.api_keys, [ApiKey::new("secret", Permission::Read)]);
+        assert_eq!(
+            config.initial_volume_for("kitchen").map(|v| v.volume),
+            Some(30)
+        );
+        assert_eq!(
+            config.initial_volume_for("garage"),
+            Some(crate::server::InitialVolume {
+                volume: 30,
+                muted: true
+            })
+        );
+        let adaptive = config.adaptive_buffer.unwrap();
+        assert_eq!((adaptive.min_ms, adaptive.max_ms), (50, 800));
+    }
+
+    #[test]
+    fn test_load_config_file() {
+        use clap::Parser;
+
+        #[derive(Parser)]
+        struct Args {
+            #[command(flatten)]
+            server: ServerArgs,
+        }
+
+        let path = std::env::temp_dir().join(format!("sendspin-cli-{}.toml", std::process::id()));
+        std::fs::write(&path, "name = \"From File\"\nbuffer_ahead_ms = 750\n").unwrap();
+        let path_arg = path.display().to_string();
+        let args = Args::parse_from(["server", "--name", "From Args", "--config", &path_arg]);
+        let args = args.server;
+        let config = args.load_config().unwrap();
+        assert_eq!(config.name, "From File");
+        assert_eq!(config.buffer_ahead_ms, 750);
+        assert_eq!(config.config_file.as_deref(), Some(path.as_path()));
+
+        std::fs::write(&path, "buffer_ahead_ms = \"soon\"").unwrap();
+        assert!(args.load_config().is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_parse_client_percent() {
+        assert_eq!(
+            parse_client_percent("kids-room=60"),
+            Ok(("kids-room".to_string(), 60))
+        );
+        assert!(parse_client_percent("kids-room").is_err());
+        assert!(parse_client_percent("kids-room=101").is_err());
+        assert!(parse_client_percent("=50").is_err());
+    }
+
+    #[test]
+    fn test_parse_codec_value() {
+        assert_eq!(parse_codec_value("opus=96"), Ok((Codec::Opus, 96)));
+        assert!(parse_codec_value("opus").is_err());
+        assert!(parse_codec_value("vorbis=96").is_err());
+        assert!(parse_codec_value("flac=fast").is_err());
+    }
+
+    #[test]
+    fn test_parse_codec_override() {
+        assert_eq!(
+            parse_codec_override("garage=opus"),
+            Ok(("garage".to_string(), CodecOverride::new(Codec::Opus)))
+        );
+        assert_eq!(
+            parse_codec_override("den=flac"),
+            Ok(("den".to_string(), CodecOverride::new(Codec::Flac)))
+        );
+        assert!(parse_codec_override("opus").is_err());
+        assert!(parse_codec_override("=opus").is_err());
+        // An override pins only the codec, not encoder settings
+        assert!(parse_codec_override("garage=flac@5").is_err());
+    }
+
+    #[test]
+    fn test_parse_api_key() {
+        assert_eq!(
+            parse_api_key("abc:read"),
+            Ok(ApiKey::new("abc", Permission::Read))
+        );
+        assert_eq!(
+            parse_api_key("abc"),
+            Ok(ApiKey::new("abc", Permission::Control))
+        );
+        assert_eq!(
+            parse_api_key("a:b:control"),
+            Ok(ApiKey::new("a:b", Permission::Control))
+        );
+        assert!(parse_api_key(":read").is_err());
     }
 }
@@ -2,46 +2,187 @@
 // ABOUTME: Handles individual client connections, handshake, and message routing
 
 use crate::audio::types::{AudioFormat, Codec};
+use crate::protocol::binary::{BinaryFrame, SOURCE_AUDIO};
+use crate::protocol::fec::FecConfig;
 use crate::protocol::messages::{
-    ClientHello, ClientTime, Message, ServerHello,
-    ServerTime, StreamPlayerConfig, StreamStart,
+    AudioFormatSpec, ClientHello, ClientTime, Message, ServerGoodbye, ServerHello, ServerTime,
+    StreamPlayerConfig, StreamStart,
+};
+use crate::server::artwork::ArtworkState;
+use crate::server::capability_cache::ClientCapabilities;
+use crate::server::client_manager::{
+    ClientId, ClientManager, ConnectedClient, ConnectionState, ServerMessage,
 };
-use crate::server::client_manager::{ClientId, ClientManager, ConnectedClient, ServerMessage};
 use crate::server::clock::ServerClock;
-use crate::server::config::ServerConfig;
-use crate::server::group::GroupManager;
-use axum::extract::ws::{Message as WsMessage, WebSocket};
+use crate::server::config::{InitialVolume, RoleLimits, ServerConfig};
+use crate::server::encoder::EncoderRegistry;
+use crate::server::extensions::Extensions;
+use crate::server::group::{GroupManager, StreamTiming};
+use crate::server::ingest::ingest_source;
+use crate::server::playback::PlaybackController;
+use crate::server::rate_limit::{InboundLimiter, LimitViolation};
+use crate::server::roles::{RoleContext, RoleDispatcher, RoleHandlers};
+use crate::server::stream_manager::StreamManager;
+use crate::server::transport_stats::TRANSPORT_PING_INTERVAL;
+use axum::extract::ws::{close_code, CloseFrame, Message as WsMessage, WebSocket};
+use futures_util::stream::{SplitSink, SplitStream};
 use futures_util::{SinkExt, StreamExt};
+use std::fmt;
+use std::net::IpAddr;
 use std::sync::Arc;
-use tokio::sync::mpsc;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
+
+/// How long a connection closed for abuse gets to deliver its goodbye
+const CLOSE_GRACE: Duration = Duration::from_secs(1);
 
 /// Handle a WebSocket client connection
+///
+/// `remote` is the client's address (after resolving any trusted proxy) and is
+/// used for logging.
+#[allow(clippy::too_many_arguments)]
 pub async fn handle_client(
     socket: WebSocket,
+    remote: IpAddr,
     client_manager: Arc<ClientManager>,
     group_manager: Arc<GroupManager>,
     clock: Arc<ServerClock>,
     config: Arc<ServerConfig>,
+    role_handlers: RoleHandlers,
+    extensions: Extensions,
+    streams: StreamManager,
 ) {
     let (mut ws_tx, mut ws_rx) = socket.split();
 
     // Wait for client/hello
-    let client_hello = match wait_for_client_hello(&mut ws_rx).await {
+    let max_bytes = config.inbound_limits.max_message_bytes;
+    let hello = wait_for_client_hello(&mut ws_rx, config.handshake_timeout, max_bytes).await;
+    let client_hello = match hello {
         Ok(hello) => hello,
         Err(e) => {
-            log::warn!("Failed to receive client/hello: {}", e);
+            log::warn!("Failed to receive client/hello from {}: {}", remote, e);
+            if let Some(reason) = e.reason() {
+                refuse(&mut ws_tx, reason, &e.to_string()).await;
+            }
             return;
         }
     };
 
     log::info!(
-        "Client connected: {} ({})",
+        "Client connected: {} ({}) from {}",
         client_hello.name,
-        client_hello.client_id
+        client_hello.client_id,
+        remote
+    );
+
+    // Negotiate roles and, for players, the audio format
+    let controllers = client_manager.controller_count_except(&client_hello.client_id);
+    let (active_roles, withheld) = negotiate_roles(
+        &client_hello.supported_roles,
+        &config.role_limits,
+        controllers,
     );
+    if !withheld.is_empty() {
+        log::info!(
+            "Not granting {:?} to client {}: not allowed on this server",
+            withheld,
+            client_hello.client_id
+        );
+    }
+    if active_roles.is_empty() && !withheld.is_empty() {
+        let message = format!("the roles {:?} are not allowed on this server", withheld);
+        log::warn!("Refusing client {}: {}", client_hello.client_id, message);
+        refuse(&mut ws_tx, "role_not_allowed", &message).await;
+        return;
+    }
+    if active_roles.is_empty() {
+        let message = format!(
+            "none of the roles {:?} are supported \
+             (expected player, controller, metadata, artwork, or _source)",
+            client_hello.supported_roles
+        );
+        log::warn!("Refusing client {}: {}", client_hello.client_id, message);
+        refuse(&mut ws_tx, "no_supported_roles", &message).await;
+        return;
+    }
 
-    // Negotiate roles
-    let active_roles = negotiate_roles(&client_hello.supported_roles);
+    // A source client pushes audio in the format it declared
+    let ingest = if active_roles.iter().any(|r| r.starts_with("_source@")) {
+        let format = client_hello
+            .source_format
+            .as_ref()
+            .ok_or_else(|| "the _source role needs a _source_format".to_string());
+        match format.and_then(|format| ingest_source(&client_hello.name, format)) {
+            Ok(ingest) => Some(ingest),
+            Err(message) => {
+                log::warn!("Refusing client {}: {}", client_hello.client_id, message);
+                refuse(&mut ws_tx, "invalid_source_format", &message).await;
+                return;
+            }
+        }
+    } else {
+        None
+    };
+    let supported_formats = client_hello
+        .player_support
+        .as_ref()
+        .map_or(&[][..], |support| &support.supported_formats);
+
+    // A codec pinned by the operator wins when the client supports it
+    let pinned_format = client_manager
+        .codec_override(&client_hello.client_id)
+        .and_then(|pin| {
+            let format =
+                config
+                    .codec_policy
+                    .select_codec(supported_formats, pin.codec, streams.encoders());
+            if format.is_none() {
+                log::warn!(
+                    "Client {} does not support its pinned codec {}, negotiating instead",
+                    client_hello.client_id,
+                    pin.codec.name()
+                );
+            }
+            format
+        });
+
+    // A player reconnecting with the same capabilities keeps its last format
+    let capabilities = client_hello
+        .player_support
+        .as_ref()
+        .map(ClientCapabilities::from);
+    let cached_format = capabilities
+        .as_ref()
+        .and_then(|caps| {
+            client_manager
+                .capabilities()
+                .format_for(&client_hello.client_id, caps)
+        })
+        .filter(|format| streams.encoders().contains(format.codec.name()));
+    if cached_format.is_some() {
+        log::debug!(
+            "Client {} capabilities unchanged, reusing its last format",
+            client_hello.client_id
+        );
+    }
+    let Some(audio_format) = pinned_format
+        .or(cached_format)
+        .or_else(|| negotiate_audio_format(supported_formats, &config, streams.encoders()))
+    else {
+        let offered: Vec<String> = client_hello
+            .player_support
+            .iter()
+            .flat_map(|support| &support.supported_formats)
+            .map(|f| format!("{} {}Hz", f.codec, f.sample_rate))
+            .collect();
+        let message = format!(
+            "none of the audio formats [{}] can be streamed",
+            offered.join(", ")
+        );
+        log::warn!("Refusing client {}: {}", client_hello.client_id, message);
+        refuse(&mut ws_tx, "no_supported_format", &message).await;
+        return;
+    };
 
     // Send server/hello
     let server_hello = Message::ServerHello(ServerHello {
@@ -60,7 +201,11 @@ pub async fn handle_client(
         }
     };
 
-    if ws_tx.send(WsMessage::Text(hello_json.into())).await.is_err() {
+    if ws_tx
+        .send(WsMessage::Text(hello_json.into()))
+        .await
+        .is_err()
+    {
         log::warn!("Failed to send server/hello");
         return;
     }
@@ -68,288 +213,503 @@ pub async fn handle_client(
     // Create channel for server->client messages
     let (tx, mut rx) = mpsc::unbounded_channel::<ServerMessage>();
 
-    // Negotiate audio format
-    let audio_format = negotiate_audio_format(&client_hello, &config);
-
     // Create connected client
     let client_id = client_hello.client_id.clone();
-    let mut connected_client = ConnectedClient::new(client_id.clone(), client_hello.name.clone(), tx);
+    let mut connected_client =
+        ConnectedClient::new(client_id.clone(), client_hello.name.clone(), tx);
     connected_client.active_roles = active_roles.clone();
     connected_client.audio_format = Some(audio_format.clone());
+    log::info!(
+        "Client {} format: {} {}Hz {}ch {}bit",
+        client_id,
+        audio_format.codec.name(),
+        audio_format.sample_rate,
+        audio_format.channels,
+        audio_format.bit_depth
+    );
 
     if let Some(ref player_support) = client_hello.player_support {
         connected_client.buffer_capacity = player_support.buffer_capacity;
+        connected_client.supported_formats = player_support.supported_formats.clone();
+        connected_client.supported_commands = player_support.supported_commands.clone();
+        if let Some(fec) = FecConfig::negotiate(player_support.fec, config.fec_max_group_size) {
+            log::info!(
+                "Client {} FEC: parity every {} chunks",
+                client_id,
+                fec.group_size
+            );
+            connected_client.enable_fec(fec);
+        }
+    }
+    if active_roles.iter().any(|r| r.starts_with("artwork@")) {
+        connected_client.artwork =
+            ArtworkState::from_support(client_hello.artwork_support.as_ref());
+    }
+    if let Some(&max_volume) = config.max_volumes.get(&client_id) {
+        connected_client.max_volume = max_volume;
     }
 
+    // A client reconnecting within the grace period resumes its previous
+    // settings; otherwise apply the configured volume the first time it
+    // connects. Registering restores settings stored before a restart.
+    let resumed = client_manager.resume(&client_id);
+    let first_connect = client_manager.mark_seen(&client_id);
+    let initial_volume = match resumed {
+        Some(ref session) => {
+            connected_client.max_volume = session.max_volume;
+            connected_client.group_id = session.group_id.clone();
+            connected_client.restored = true;
+            Some(InitialVolume {
+                volume: session.volume,
+                muted: session.muted,
+            })
+        }
+        None => first_connect
+            .then(|| config.initial_volume_for(&client_id))
+            .flatten(),
+    };
+    if let Some(initial) = initial_volume {
+        connected_client.volume = initial.volume.min(connected_client.max_volume);
+        connected_client.muted = initial.muted;
+    }
+    let set_volume = connected_client.supports_command("volume");
+    let set_mute = connected_client.supports_command("mute");
+    let transport = Arc::clone(&connected_client.transport);
+
     // Register client
-    client_manager.add_client(connected_client);
+    let generation = client_manager.add_client(connected_client);
+    if let Some(capabilities) = capabilities {
+        client_manager
+            .capabilities()
+            .store(&client_id, capabilities, audio_format.clone());
+    }
 
-    // Add to default group
-    group_manager.add_to_group(&client_id, group_manager.default_group_id());
+    if let Some(initial) = initial_volume {
+        log::info!(
+            "Client {} {}: volume {}%{}",
+            client_id,
+            match resumed {
+                Some(ref session) => format!("resumed generation {}", session.generation),
+                None => "first connect".to_string(),
+            },
+            initial.volume,
+            if initial.muted { " (muted)" } else { "" }
+        );
+        if set_volume {
+            client_manager.send_player_command(&client_id, "volume", Some(initial.volume), None);
+        }
+        if set_mute {
+            client_manager.send_player_command(&client_id, "mute", None, Some(initial.muted));
+        }
+    }
 
-    // Send stream/start if client is a player
-    if active_roles.iter().any(|r| r.starts_with("player@")) {
-        let stream_start = create_stream_start(&audio_format);
-        let start_json = match serde_json::to_string(&stream_start) {
-            Ok(json) => json,
-            Err(e) => {
-                log::error!("Failed to serialize stream/start: {}", e);
-                client_manager.remove_client(&client_id);
-                return;
-            }
-        };
+    // Rejoin the previous group (falls back to the default group if it is gone)
+    let group_id = client_manager
+        .get_group_id(&client_id)
+        .unwrap_or_else(|| group_manager.default_group_id().to_string());
+    let playback = PlaybackController::new(client_manager.clone(), group_manager.clone());
+    playback.join_group(&client_id, &group_id);
 
-        log::info!("Sending stream/start to client {}: {}", client_id, start_json);
-        if ws_tx.send(WsMessage::Text(start_json.into())).await.is_err() {
-            log::warn!("Failed to send stream/start");
-            client_manager.remove_client(&client_id);
-            return;
-        }
-        log::info!("stream/start sent successfully to client {}", client_id);
+    // Apply the group's playback state (sends stream/start and group/update)
+    if active_roles.iter().any(|r| r.starts_with("player@")) {
+        playback.player_joined(&client_id);
     }
 
-    // Spawn task to forward server messages to WebSocket
+    // A source client becomes the input of its group's stream until it leaves,
+    // after which the stream falls back as for any source that ends
+    let feed = ingest.and_then(|(source, feed)| {
+        let group_id = group_manager
+            .get_client_group(&client_id)
+            .unwrap_or(group_id);
+        let Some(source_control) = streams.source_control_for_group(&group_id) else {
+            log::warn!(
+                "Client {} has no stream to feed in group {}",
+                client_id,
+                group_id
+            );
+            return None;
+        };
+        log::info!(
+            "Client {} is now the source for group {}",
+            client_id,
+            group_id
+        );
+        source_control.replace(Box::new(source));
+        Some(feed)
+    });
+
+    // Spawn task to forward server messages to WebSocket, until told to
+    // close the connection
     let client_id_send = client_id.clone();
-    let send_task = tokio::spawn(async move {
-        while let Some(msg) = rx.recv().await {
-            let ws_msg = match msg {
-                ServerMessage::Binary(data) => WsMessage::Binary(data.into()),
-                ServerMessage::Text(text) => WsMessage::Text(text.into()),
-            };
-            if ws_tx.send(ws_msg).await.is_err() {
-                log::debug!("Client {} disconnected (send failed)", client_id_send);
-                break;
+    let (close_tx, mut close_rx) = oneshot::channel::<LimitViolation>();
+    let send_transport = Arc::clone(&transport);
+    let mut send_task = tokio::spawn(async move {
+        let mut ping = tokio::time::interval_at(
+            tokio::time::Instant::now() + TRANSPORT_PING_INTERVAL,
+            TRANSPORT_PING_INTERVAL,
+        );
+        loop {
+            tokio::select! {
+                msg = rx.recv() => {
+                    let Some(msg) = msg else { break };
+                    let (ws_msg, len) = match msg {
+                        ServerMessage::Binary(data) => {
+                            let len = data.len();
+                            (WsMessage::Binary(data), len)
+                        }
+                        ServerMessage::Text(text) => {
+                            let len = text.len();
+                            (WsMessage::Text(text.into()), len)
+                        }
+                    };
+                    if ws_tx.send(ws_msg).await.is_err() {
+                        log::debug!("Client {} disconnected (send failed)", client_id_send);
+                        break;
+                    }
+                    send_transport.written(len);
+                }
+                _ = ping.tick() => {
+                    send_transport.ping_sent(Instant::now());
+                    if ws_tx.send(WsMessage::Ping(Default::default())).await.is_err() {
+                        break;
+                    }
+                }
+                Ok(violation) = &mut close_rx => {
+                    refuse(&mut ws_tx, violation.reason(), &violation.to_string()).await;
+                    break;
+                }
             }
         }
     });
 
     // Handle incoming messages
-    let client_id_recv = client_id.clone();
-    let client_manager_recv = client_manager.clone();
-    let clock_recv = clock.clone();
-
+    let roles = RoleDispatcher::new(&role_handlers, &active_roles);
+    let ctx = RoleContext {
+        client_id: &client_id,
+        client_manager: &client_manager,
+        group_manager: &group_manager,
+        playback: &playback,
+        clock: &clock,
+        streams: Some(&streams),
+    };
+    // Metadata and artwork clients start from the track their stream is playing
+    streams.send_metadata(&client_id);
+    roles.joined(&ctx);
+
+    // Oversized or rapid-fire messages close the connection. The upgrade
+    // already caps message size while reading, so the size check here is a
+    // backstop.
+    let mut limiter = InboundLimiter::new(config.inbound_limits, Instant::now());
+    if let Some(feed) = &feed {
+        limiter = limiter.with_byte_budget(feed.byte_budget(), Instant::now());
+    }
+    let mut violation = None;
     while let Some(msg) = ws_rx.next().await {
+        let size = match &msg {
+            Ok(WsMessage::Text(text)) => text.len(),
+            Ok(WsMessage::Binary(data)) => data.len(),
+            _ => 0,
+        };
+        // Source clients send audio at their chunk rate, so it is held to the
+        // byte rate of their declared format rather than the message rate
+        let source_audio = feed.is_some()
+            && matches!(&msg, Ok(WsMessage::Binary(data)) if data.first() == Some(&SOURCE_AUDIO));
+        if size > 0 {
+            let checked = if source_audio {
+                limiter.check_bytes(size, Instant::now())
+            } else {
+                limiter.check(size, Instant::now())
+            };
+            if let Err(e) = checked {
+                log::warn!("Closing client {}: {}", client_id, e);
+                violation = Some(e);
+                break;
+            }
+        }
         match msg {
             Ok(WsMessage::Text(text)) => {
-                handle_text_message(
-                    &text,
-                    &client_id_recv,
-                    &client_manager_recv,
-                    &clock_recv,
-                )
-                .await;
+                handle_text_message(&text, &ctx, &roles, &extensions, &clock).await;
             }
-            Ok(WsMessage::Binary(data)) => {
-                // Clients don't typically send binary data to server
-                log::debug!(
-                    "Received binary from client {} ({} bytes)",
-                    client_id_recv,
-                    data.len()
-                );
-            }
-            Ok(WsMessage::Ping(_)) | Ok(WsMessage::Pong(_)) => {
-                // Handled automatically by axum
+            Ok(WsMessage::Binary(data)) => match (&feed, BinaryFrame::decode(&data)) {
+                (Some(feed), Ok(BinaryFrame::SourceAudio { payload, .. })) => {
+                    match feed.push(payload) {
+                        Ok(0) => {}
+                        Ok(dropped) => {
+                            log::debug!("Client {} ahead, dropped {} samples", client_id, dropped)
+                        }
+                        Err(e) => log::warn!("Bad audio chunk from client {}: {}", client_id, e),
+                    }
+                }
+                _ => {
+                    // Only source clients send audio to the server
+                    log::debug!(
+                        "Received binary from client {} ({} bytes)",
+                        client_id,
+                        data.len()
+                    );
+                }
+            },
+            Ok(WsMessage::Pong(_)) => transport.pong_received(Instant::now()),
+            Ok(WsMessage::Ping(_)) => {
+                // Answered automatically by axum
             }
             Ok(WsMessage::Close(_)) => {
-                log::info!("Client {} closed connection", client_id_recv);
+                log::info!("Client {} closed connection", client_id);
                 break;
             }
             Err(e) => {
-                log::warn!("WebSocket error for client {}: {}", client_id_recv, e);
+                log::warn!("WebSocket error for client {}: {}", client_id, e);
                 break;
             }
         }
     }
 
-    // Cleanup
-    client_manager.remove_client(&client_id);
-    group_manager.remove_client(&client_id);
+    // Cleanup, unless a newer connection for this client has taken over
+    drop(feed);
+    roles.left(&ctx);
+    let current = client_manager.connection_state(&client_id)
+        == Some(ConnectionState::Connected { generation });
+    if current {
+        if client_manager.is_player(&client_id) {
+            playback.player_left(&client_id);
+        }
+        let group_id = group_manager.get_client_group(&client_id);
+        client_manager.disconnect(&client_id, generation, group_id);
+        group_manager.remove_client(&client_id);
+    }
+    if let Some(violation) = violation {
+        if close_tx.send(violation).is_ok() {
+            let _ = tokio::time::timeout(CLOSE_GRACE, &mut send_task).await;
+        }
+    }
     send_task.abort();
 
     log::info!("Client {} disconnected", client_id);
 }
 
+/// Why a connection did not produce a client/hello
+#[derive(Debug)]
+enum HandshakeError {
+    /// Nothing arrived within the handshake timeout
+    Timeout(Duration),
+    /// The first message was not a valid client/hello
+    Invalid(String),
+    /// The connection closed or failed first
+    Closed(String),
+}
+
+impl HandshakeError {
+    /// Reason code for the `_server/goodbye` sent back, if the client can still hear it
+    fn reason(&self) -> Option<&'static str> {
+        match self {
+            HandshakeError::Timeout(_) => Some("handshake_timeout"),
+            HandshakeError::Invalid(_) => Some("invalid_hello"),
+            HandshakeError::Closed(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HandshakeError::Timeout(timeout) => {
+                write!(f, "no client/hello within {}s", timeout.as_secs_f32())
+            }
+            HandshakeError::Invalid(detail) | HandshakeError::Closed(detail) => f.write_str(detail),
+        }
+    }
+}
+
 /// Wait for client/hello message
 async fn wait_for_client_hello(
-    ws_rx: &mut futures_util::stream::SplitStream<WebSocket>,
-) -> Result<ClientHello, String> {
-    // Wait up to 10 seconds for client/hello
-    let timeout = tokio::time::timeout(std::time::Duration::from_secs(10), async {
+    ws_rx: &mut SplitStream<WebSocket>,
+    timeout: Duration,
+    max_bytes: usize,
+) -> Result<ClientHello, HandshakeError> {
+    let hello = tokio::time::timeout(timeout, async {
         while let Some(msg) = ws_rx.next().await {
             match msg {
-                Ok(WsMessage::Text(text)) => {
-                    match serde_json::from_str::<Message>(&text) {
-                        Ok(Message::ClientHello(hello)) => return Ok(hello),
-                        Ok(other) => {
-                            return Err(format!("Expected client/hello, got {:?}", other));
-                        }
-                        Err(e) => {
-                            return Err(format!("Failed to parse message: {}", e));
-                        }
-                    }
+                Ok(WsMessage::Text(text)) if text.len() > max_bytes => {
+                    return Err(HandshakeError::Invalid(format!(
+                        "client/hello of {} bytes exceeds the {} byte limit",
+                        text.len(),
+                        max_bytes
+                    )));
                 }
+                Ok(WsMessage::Text(text)) => match serde_json::from_str::<Message>(&text) {
+                    Ok(Message::ClientHello(hello)) => return Ok(hello),
+                    Ok(other) => {
+                        return Err(HandshakeError::Invalid(format!(
+                            "expected client/hello, got {:?}",
+                            other
+                        )));
+                    }
+                    Err(e) => {
+                        return Err(HandshakeError::Invalid(format!(
+                            "failed to parse client/hello: {}",
+                            e
+                        )));
+                    }
+                },
                 Ok(WsMessage::Ping(_)) | Ok(WsMessage::Pong(_)) => continue,
                 Ok(WsMessage::Close(_)) => {
-                    return Err("Connection closed before hello".to_string());
+                    return Err(HandshakeError::Closed(
+                        "connection closed before hello".to_string(),
+                    ));
                 }
                 Err(e) => {
-                    return Err(format!("WebSocket error: {}", e));
+                    return Err(HandshakeError::Closed(format!("WebSocket error: {}", e)));
                 }
                 _ => continue,
             }
         }
-        Err("Connection closed".to_string())
+        Err(HandshakeError::Closed("connection closed".to_string()))
     });
 
-    match timeout.await {
+    match hello.await {
         Ok(result) => result,
-        Err(_) => Err("Timeout waiting for client/hello".to_string()),
+        Err(_) => Err(HandshakeError::Timeout(timeout)),
     }
 }
 
-/// Negotiate active roles based on client's supported roles
-fn negotiate_roles(supported_roles: &[String]) -> Vec<String> {
-    let mut active = Vec::new();
-
-    // Check for player role (accept "player", "player@v1", etc.)
-    for role in supported_roles {
-        if role == "player" || role.starts_with("player@") {
-            // Normalize to versioned form for consistency
-            if role == "player" {
-                active.push("player@v1".to_string());
-            } else {
-                active.push(role.clone());
-            }
-            break; // Only one player version
-        }
-    }
-
-    // Check for controller role
-    for role in supported_roles {
-        if role == "controller" || role.starts_with("controller@") {
-            if role == "controller" {
-                active.push("controller@v1".to_string());
-            } else {
-                active.push(role.clone());
-            }
-            break;
-        }
+/// Tell a client why it is being refused, then close the connection
+async fn refuse(ws_tx: &mut SplitSink<WebSocket, WsMessage>, reason: &str, message: &str) {
+    let goodbye = Message::ServerGoodbye(ServerGoodbye {
+        reason: reason.to_string(),
+        message: message.to_string(),
+    });
+    if let Ok(json) = serde_json::to_string(&goodbye) {
+        let _ = ws_tx.send(WsMessage::Text(json.into())).await;
     }
+    // Close reasons are limited to 123 bytes; the goodbye carries the details
+    let close = CloseFrame {
+        code: close_code::POLICY,
+        reason: reason.into(),
+    };
+    let _ = ws_tx.send(WsMessage::Close(Some(close))).await;
+}
 
-    // Check for metadata role
-    for role in supported_roles {
-        if role == "metadata" || role.starts_with("metadata@") {
-            if role == "metadata" {
-                active.push("metadata@v1".to_string());
-            } else {
-                active.push(role.clone());
-            }
-            break;
+/// Negotiate active roles based on client's supported roles
+///
+/// Returns the granted roles and those `limits` withheld; `controllers` is
+/// how many other clients are already connected as controllers.
+fn negotiate_roles(
+    supported_roles: &[String],
+    limits: &RoleLimits,
+    controllers: usize,
+) -> (Vec<String>, Vec<String>) {
+    let mut active = Vec::new();
+    let mut withheld = Vec::new();
+
+    // Accept "player", "player@v1", etc., normalizing to the versioned form;
+    // only one version of each role
+    let families = [
+        ("player", true),
+        ("controller", limits.allows_controller(controllers)),
+        ("metadata", limits.allows_metadata()),
+        ("artwork", limits.allows_artwork()),
+        ("_source", limits.allows_source()),
+    ];
+    for (family, allowed) in families {
+        let versioned = format!("{}@", family);
+        let Some(role) = supported_roles
+            .iter()
+            .find(|role| *role == family || role.starts_with(&versioned))
+        else {
+            continue;
+        };
+        let role = if role == family {
+            format!("{}@v1", family)
+        } else {
+            role.clone()
+        };
+        if allowed {
+            active.push(role);
+        } else {
+            withheld.push(role);
         }
     }
 
-    active
+    (active, withheld)
 }
 
-/// Negotiate audio format based on client capabilities
-fn negotiate_audio_format(client_hello: &ClientHello, config: &ServerConfig) -> AudioFormat {
-    // Default format
-    let mut format = AudioFormat {
+/// Negotiate audio format from client capabilities and the server's codec policy
+///
+/// Clients that list no formats get the server default. Returns None if a
+/// client lists formats but none of them can be streamed with `encoders`.
+pub(crate) fn negotiate_audio_format(
+    supported: &[AudioFormatSpec],
+    config: &ServerConfig,
+    encoders: &EncoderRegistry,
+) -> Option<AudioFormat> {
+    if !supported.is_empty() {
+        return config.codec_policy.select(supported, encoders);
+    }
+    Some(AudioFormat {
         codec: Codec::Pcm,
         sample_rate: config.default_sample_rate,
         channels: config.default_channels,
         bit_depth: config.default_bit_depth,
         codec_header: None,
-    };
-
-    // Check client's supported formats
-    if let Some(ref player_support) = client_hello.player_support {
-        // Try to find PCM format first (most compatible)
-        for fmt in &player_support.supported_formats {
-            if fmt.codec == "pcm" {
-                format.sample_rate = fmt.sample_rate;
-                format.channels = fmt.channels;
-                format.bit_depth = fmt.bit_depth;
-                return format;
-            }
-        }
-
-        // Fall back to first supported format (client's preferred)
-        if let Some(fmt) = player_support.supported_formats.first() {
-            format.codec = match fmt.codec.as_str() {
-                "opus" => Codec::Opus,
-                "flac" => Codec::Flac,
-                "mp3" => Codec::Mp3,
-                _ => Codec::Pcm,
-            };
-            format.sample_rate = fmt.sample_rate;
-            format.channels = fmt.channels;
-            format.bit_depth = fmt.bit_depth;
-        }
-    }
-
-    format
+    })
 }
 
 /// Create stream/start message
-fn create_stream_start(format: &AudioFormat) -> Message {
+///
+/// `timing` is the chunk timing of the player's group, when it has one.
+pub(crate) fn create_stream_start(
+    format: &AudioFormat,
+    fec: Option<FecConfig>,
+    timing: Option<StreamTiming>,
+) -> Message {
     Message::StreamStart(StreamStart {
         player: StreamPlayerConfig {
-            codec: match format.codec {
-                Codec::Pcm => "pcm".to_string(),
-                Codec::Opus => "opus".to_string(),
-                Codec::Flac => "flac".to_string(),
-                Codec::Mp3 => "mp3".to_string(),
-            },
+            codec: format.codec.name().to_string(),
             sample_rate: format.sample_rate,
             channels: format.channels,
             bit_depth: format.bit_depth,
             codec_header: format.codec_header.as_ref().map(|h| base64_encode(h)),
+            fec,
+            chunk_interval_ms: timing.map(|t| t.chunk_interval_ms),
+            buffer_ahead_ms: timing.map(|t| t.buffer_ahead_ms),
         },
     })
 }
 
 /// Handle incoming text message from client
+///
+/// Role-specific messages go to the connection's role handlers, message types
+/// the protocol does not define go to registered extensions, and messages
+/// common to all roles are handled here.
 async fn handle_text_message(
     text: &str,
-    client_id: &ClientId,
-    client_manager: &ClientManager,
+    ctx: &RoleContext<'_>,
+    roles: &RoleDispatcher,
+    extensions: &Extensions,
     clock: &ServerClock,
 ) {
     let msg = match serde_json::from_str::<Message>(text) {
         Ok(m) => m,
+        Err(_) if extensions.dispatch(ctx, text) => return,
         Err(e) => {
-            log::warn!("Failed to parse message from {}: {}", client_id, e);
+            log::warn!("Failed to parse message from {}: {}", ctx.client_id, e);
             return;
         }
     };
 
+    let Some(msg) = roles.dispatch(ctx, msg) else {
+        return;
+    };
     match msg {
         Message::ClientTime(client_time) => {
-            handle_client_time(client_id, client_time, client_manager, clock);
-        }
-        Message::ClientState(state) => {
-            // Handle spec-compliant client/state message with player object
-            if let Some(player) = state.player {
-                log::debug!(
-                    "Player {} state: {}, volume: {:?}, muted: {:?}",
-                    client_id,
-                    player.state,
-                    player.volume,
-                    player.muted
-                );
-                // Update volume if provided (both must be present per spec when supported)
-                if let (Some(volume), Some(muted)) = (player.volume, player.muted) {
-                    client_manager.update_volume(client_id, volume, muted);
-                }
-            }
+            handle_client_time(ctx.client_id, client_time, ctx.client_manager, clock);
         }
         Message::ClientGoodbye(goodbye) => {
             // Per spec: client is gracefully disconnecting
             // Reasons: 'another_server', 'shutdown', 'restart', 'user_request'
             log::info!(
                 "Client {} sent goodbye with reason: {}",
-                client_id,
+                ctx.client_id,
                 goodbye.reason
             );
             // The client will be removed when the WebSocket closes
@@ -357,27 +717,12 @@ async fn handle_text_message(
             // - 'restart': auto-reconnect expected
             // - 'another_server', 'shutdown', 'user_request': no auto-reconnect
         }
-        Message::StreamRequestFormat(request) => {
-            // Per spec: client requests format change (adaptive streaming)
-            log::info!(
-                "Client {} requested format change: {:?}",
-                client_id,
-                request
-            );
-            // TODO: Implement format negotiation and send new stream/start
-            // For now, log the request - full implementation requires per-client encoding
-            if let Some(player_req) = request.player {
-                log::debug!(
-                    "Player format request - codec: {:?}, sample_rate: {:?}, channels: {:?}, bit_depth: {:?}",
-                    player_req.codec,
-                    player_req.sample_rate,
-                    player_req.channels,
-                    player_req.bit_depth
-                );
-            }
+        Message::ClientDiagnostics(diagnostics) => {
+            ctx.client_manager
+                .complete_diagnostics(ctx.client_id, diagnostics);
         }
         _ => {
-            log::debug!("Unhandled message from {}: {:?}", client_id, msg);
+            log::debug!("Unhandled message from {}: {:?}", ctx.client_id, msg);
         }
     }
 }
@@ -414,3 +759,70 @@ fn base64_encode(data: &[u8]) -> String {
     use base64::Engine;
     base64::engine::general_purpose::STANDARD.encode(data)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roles(names: &[&str]) -> Vec<String> {
+        names.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_negotiate_roles_normalizes_versions() {
+        let (active, withheld) = negotiate_roles(
+            &roles(&[
+                "metadata",
+                "player@v2",
+                "player",
+                "controller@v1",
+                "artwork",
+            ]),
+            &RoleLimits::default(),
+            0,
+        );
+        assert_eq!(
+            active,
+            roles(&["player@v2", "controller@v1", "metadata@v1", "artwork@v1"])
+        );
+        assert!(withheld.is_empty());
+    }
+
+    #[test]
+    fn test_negotiate_roles_applies_limits() {
+        let supported = roles(&["player", "controller", "metadata"]);
+        let limits = RoleLimits {
+            max_controllers: Some(1),
+            no_metadata: true,
+            ..RoleLimits::default()
+        };
+
+        let (active, withheld) = negotiate_roles(&supported, &limits, 0);
+        assert_eq!(active, roles(&["player@v1", "controller@v1"]));
+        assert_eq!(withheld, roles(&["metadata@v1"]));
+
+        // The one controller slot is taken
+        let (active, withheld) = negotiate_roles(&supported, &limits, 1);
+        assert_eq!(active, roles(&["player@v1"]));
+        assert_eq!(withheld, roles(&["controller@v1", "metadata@v1"]));
+
+        let player_only = RoleLimits {
+            player_only: true,
+            ..RoleLimits::default()
+        };
+        let (active, withheld) = negotiate_roles(&roles(&["controller"]), &player_only, 0);
+        assert!(active.is_empty());
+        assert_eq!(withheld, roles(&["controller@v1"]));
+
+        // Pushing audio to the server is opt-in
+        let (active, withheld) = negotiate_roles(&roles(&["_source"]), &RoleLimits::default(), 0);
+        assert!(active.is_empty());
+        assert_eq!(withheld, roles(&["_source@v1"]));
+        let sources = RoleLimits {
+            allow_source: true,
+            ..RoleLimits::default()
+        };
+        let (active, _) = negotiate_roles(&roles(&["_source"]), &sources, 0);
+        assert_eq!(active, roles(&["_source@v1"]));
+    }
+}
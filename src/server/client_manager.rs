@@ -1,22 +1,75 @@
 // ABOUTME: Client connection manager
 // ABOUTME: Thread-safe registry of connected clients with broadcast capabilities
 
+use crate::audio::downmix::ChannelMap;
 use crate::audio::types::{AudioFormat, Codec};
-use parking_lot::RwLock;
-use std::collections::HashMap;
+use crate::protocol::binary::BinaryFrame;
+use crate::protocol::fec::{FecConfig, ParityEncoder};
+use crate::protocol::messages::{AudioFormatSpec, ClientDiagnostics};
+use crate::protocol::stats::ClientStats;
+use crate::protocol::transfer::Segmenter;
+use crate::server::artwork::ArtworkState;
+use crate::server::buffer_health::{BufferHealth, BufferTrend};
+use crate::server::capability_cache::CapabilityCache;
+use crate::server::clock::ServerClock;
+use crate::server::codec_policy::CodecOverride;
+use crate::server::control_trace::ControlTraces;
+use crate::server::encoder::{EncoderSettings, StreamFormat};
+use crate::server::link_tier::LinkTier;
+use crate::server::persistence::{ClientRecord, Persistence};
+use crate::server::profiling;
+use crate::server::rtt_histogram::{RttHistogram, RttSummary};
+use crate::server::transport_stats::TransportStats;
+use bytes::Bytes;
+use parking_lot::{Mutex, RwLock};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::sync::mpsc;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot, watch};
 
 /// Unique client identifier
 pub type ClientId = String;
 
+/// How long a disconnected client's settings are kept for it to reconnect
+pub const DEFAULT_RECONNECT_GRACE: Duration = Duration::from_secs(30);
+
 /// Message types that can be sent to clients
 #[derive(Debug, Clone)]
 pub enum ServerMessage {
     /// JSON text message
     Text(String),
     /// Binary audio chunk (already formatted with type + timestamp + data)
-    Binary(Vec<u8>),
+    ///
+    /// Shared rather than copied when the same chunk goes to many clients.
+    Binary(Bytes),
+}
+
+/// Delivery counters for a client's outgoing messages
+#[derive(Debug, Default)]
+pub struct SendCounters {
+    bytes_sent: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl SendCounters {
+    /// Total bytes queued for the client
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent.load(Ordering::Relaxed)
+    }
+
+    /// Messages that could not be queued (connection already gone)
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    fn record(&self, len: usize, queued: bool) {
+        if queued {
+            self.bytes_sent.fetch_add(len as u64, Ordering::Relaxed);
+        } else {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
 }
 
 /// A connected client
@@ -38,8 +91,35 @@ pub struct ConnectedClient {
     pub volume: u8,
     /// Whether client is muted
     pub muted: bool,
+    /// Highest volume the client may be set to (0-100)
+    pub max_volume: u8,
+    /// Formats the client can decode (from `player@v1_support`)
+    pub supported_formats: Vec<AudioFormatSpec>,
+    /// Player commands the client accepts (from `player@v1_support`)
+    pub supported_commands: Vec<String>,
     /// Buffer capacity in bytes
     pub buffer_capacity: u32,
+    /// Artwork channels and what they show (artwork role)
+    pub artwork: ArtworkState,
+    /// Latest stream statistics reported by the client
+    pub stats: Option<ClientStats>,
+    /// Recent buffer levels from the client's reports
+    pub buffer_trend: BufferTrend,
+    /// Time-sync round-trip times from the client's reports
+    pub rtt_histogram: RttHistogram,
+    /// Link tier the client was placed on from its round-trip times
+    pub placed_tier: Option<LinkTier>,
+    /// Outgoing delivery counters
+    pub counters: SendCounters,
+    /// WebSocket backlog, rates and ping age, shared with the connection's writer
+    pub transport: Arc<TransportStats>,
+    /// Connection generation, assigned when the client is added
+    pub generation: u64,
+    /// Whether volume and group already come from an earlier connection, so
+    /// stored settings are not applied over them
+    pub restored: bool,
+    /// Parity state when FEC was negotiated
+    parity: Option<Mutex<ParityEncoder>>,
 }
 
 impl ConnectedClient {
@@ -58,28 +138,212 @@ impl ConnectedClient {
             group_id: None,
             volume: 100,
             muted: false,
+            max_volume: 100,
+            supported_formats: Vec::new(),
+            supported_commands: Vec::new(),
             buffer_capacity: 0,
+            artwork: ArtworkState::default(),
+            stats: None,
+            buffer_trend: BufferTrend::new(),
+            rtt_histogram: RttHistogram::new(),
+            placed_tier: None,
+            counters: SendCounters::default(),
+            transport: Arc::new(TransportStats::default()),
+            generation: 0,
+            restored: false,
+            parity: None,
+        }
+    }
+
+    /// Send XOR parity frames alongside this client's audio
+    pub fn enable_fec(&mut self, config: FecConfig) {
+        self.parity = Some(Mutex::new(ParityEncoder::new(config)));
+    }
+
+    /// Negotiated FEC parameters, if any
+    pub fn fec(&self) -> Option<FecConfig> {
+        self.parity.as_ref().map(|p| p.lock().config())
+    }
+
+    /// Send an audio chunk, followed by a parity frame when a FEC group completes
+    fn send_audio(&self, message: &Bytes) {
+        let _ = self.send(ServerMessage::Binary(message.clone()));
+        let Some(parity) = &self.parity else {
+            return;
+        };
+        if let Ok(BinaryFrame::AudioChunk { timestamp, payload }) = BinaryFrame::decode(message) {
+            if let Some(frame) = parity.lock().push(timestamp, payload) {
+                let _ = self.send(ServerMessage::Binary(frame.into()));
+            }
         }
     }
 
     /// Check if client has player role
     pub fn is_player(&self) -> bool {
+        self.active_roles.iter().any(|r| r.starts_with("player@"))
+    }
+
+    /// Check if the client has the controller role
+    pub fn is_controller(&self) -> bool {
         self.active_roles
             .iter()
-            .any(|r| r.starts_with("player@"))
+            .any(|r| r.starts_with("controller@"))
+    }
+
+    /// Check if the client has the metadata role
+    pub fn is_metadata(&self) -> bool {
+        self.active_roles.iter().any(|r| r.starts_with("metadata@"))
+    }
+
+    /// Check if the client has the artwork role
+    pub fn is_artwork(&self) -> bool {
+        self.active_roles.iter().any(|r| r.starts_with("artwork@"))
+    }
+
+    /// Check if the client accepts a player command
+    pub fn supports_command(&self, command: &str) -> bool {
+        self.supported_commands.iter().any(|c| c == command)
+    }
+
+    /// Gain the server applies to this client's audio, in percent
+    ///
+    /// Clients that accept the `volume` command set (and are capped) by
+    /// command, so they get full-scale audio. Other clients get their volume,
+    /// at most `max_volume`, applied to the audio itself. Muted clients that
+    /// do not accept the `mute` command get silence.
+    pub fn server_gain(&self) -> u8 {
+        if self.muted && !self.supports_command("mute") {
+            0
+        } else if self.supports_command("volume") {
+            100
+        } else {
+            self.volume.min(self.max_volume)
+        }
     }
 
     /// Send a message to this client
     pub fn send(&self, msg: ServerMessage) -> Result<(), mpsc::error::SendError<ServerMessage>> {
-        self.tx.send(msg)
+        let len = match &msg {
+            ServerMessage::Text(text) => text.len(),
+            ServerMessage::Binary(data) => data.len(),
+        };
+        let result = self.tx.send(msg);
+        self.counters.record(len, result.is_ok());
+        if result.is_ok() {
+            self.transport.queued(len);
+        }
+        result
     }
 }
 
+/// Processing the server applies to a client's copy of the audio
+///
+/// Clients with equal processing share one encoded copy of each chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct OutputProcessing {
+    /// Software volume in percent (100 leaves the audio untouched)
+    pub gain: u8,
+    /// What the left and right channels carry
+    pub channel_map: ChannelMap,
+    /// Format negotiated for the client's stream
+    pub format: StreamFormat,
+    /// Encoder tuning pinned to the client, over its group's settings
+    pub encoder: EncoderSettings,
+}
+
+/// A volume change applied to several players at once
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VolumeChange {
+    /// Set every player to this volume
+    Set(u8),
+    /// Raise every player by this many points (lower if negative)
+    Step(i16),
+    /// Scale players so their average becomes this volume, keeping their balance
+    Group(u8),
+    /// Mute or unmute every player
+    Mute(bool),
+}
+
+/// Where a client ID is in its connection lifecycle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// Connected under the given generation
+    Connected {
+        /// Generation of the live connection
+        generation: u64,
+    },
+    /// Disconnected, with settings held until the grace period ends
+    Disconnected {
+        /// Generation of the connection that ended
+        generation: u64,
+        /// When the connection ended
+        since: Instant,
+    },
+}
+
+/// Settings kept for a disconnected client so a reconnect can resume them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResumableSession {
+    /// Generation of the connection that ended
+    pub generation: u64,
+    /// Group the client was in
+    pub group_id: Option<String>,
+    /// Volume the client had (0-100)
+    pub volume: u8,
+    /// Whether the client was muted
+    pub muted: bool,
+    /// Volume limit in effect for the client
+    pub max_volume: u8,
+    /// When the connection ended
+    pub disconnected_at: Instant,
+}
+
 /// Manages all connected clients
 #[derive(Debug)]
 pub struct ClientManager {
     /// Map of client_id to client
     clients: Arc<RwLock<HashMap<ClientId, ConnectedClient>>>,
+    /// Number of connected player clients, for watchers like the audio engine
+    player_count: Arc<watch::Sender<usize>>,
+    /// IDs of every client that has connected since the server started
+    seen: Arc<RwLock<HashSet<ClientId>>>,
+    /// Diagnostics requests awaiting a reply, by request ID
+    pending_diagnostics: Arc<Mutex<HashMap<String, PendingDiagnostics>>>,
+    /// Source of diagnostics request IDs
+    next_request_id: Arc<AtomicU64>,
+    /// Source of connection generations
+    next_generation: Arc<AtomicU64>,
+    /// Settings of recently disconnected clients, by client ID
+    sessions: Arc<Mutex<HashMap<ClientId, ResumableSession>>>,
+    /// How long disconnected sessions are kept
+    reconnect_grace: Duration,
+    /// Sessions mirrored from a primary server, for clients failing over to this one
+    replicated: Arc<Mutex<HashMap<ClientId, ResumableSession>>>,
+    /// Channel maps other than stereo, kept across reconnects
+    channel_maps: Arc<RwLock<HashMap<ClientId, ChannelMap>>>,
+    /// Codecs pinned to clients by the operator, kept across reconnects
+    codec_overrides: Arc<RwLock<HashMap<ClientId, CodecOverride>>>,
+    /// Link tiers clients were tagged with by the operator, kept across reconnects
+    link_tiers: Arc<RwLock<HashMap<ClientId, LinkTier>>>,
+    /// Buffer-ahead in milliseconds for players on each link tier
+    tier_buffer_ahead: Arc<RwLock<HashMap<LinkTier, u64>>>,
+    /// What each client advertised and was streamed in, for quick reconnects
+    capabilities: CapabilityCache,
+    /// Splits large binary frames into transfer segments
+    segmenter: Arc<Mutex<Segmenter>>,
+    /// Display names set by the operator, kept across reconnects
+    name_overrides: Arc<RwLock<HashMap<ClientId, String>>>,
+    /// Where client settings are kept across restarts
+    persistence: Arc<RwLock<Option<Arc<dyn Persistence>>>>,
+    /// Commands awaiting the client/state that echoes their trace
+    traces: ControlTraces,
+}
+
+/// A diagnostics request sent to a client
+#[derive(Debug)]
+struct PendingDiagnostics {
+    client_id: ClientId,
+    reply: oneshot::Sender<ClientDiagnostics>,
 }
 
 impl ClientManager {
@@ -87,30 +351,415 @@ impl ClientManager {
     pub fn new() -> Self {
         Self {
             clients: Arc::new(RwLock::new(HashMap::new())),
+            player_count: Arc::new(watch::channel(0).0),
+            seen: Arc::new(RwLock::new(HashSet::new())),
+            pending_diagnostics: Arc::new(Mutex::new(HashMap::new())),
+            next_request_id: Arc::new(AtomicU64::new(1)),
+            next_generation: Arc::new(AtomicU64::new(1)),
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+            reconnect_grace: DEFAULT_RECONNECT_GRACE,
+            replicated: Arc::new(Mutex::new(HashMap::new())),
+            channel_maps: Arc::new(RwLock::new(HashMap::new())),
+            codec_overrides: Arc::new(RwLock::new(HashMap::new())),
+            link_tiers: Arc::new(RwLock::new(HashMap::new())),
+            tier_buffer_ahead: Arc::new(RwLock::new(HashMap::new())),
+            capabilities: CapabilityCache::default(),
+            segmenter: Arc::new(Mutex::new(Segmenter::default())),
+            name_overrides: Arc::new(RwLock::new(HashMap::new())),
+            persistence: Arc::new(RwLock::new(None)),
+            traces: ControlTraces::default(),
         }
     }
 
-    /// Add a client to the manager
-    pub fn add_client(&self, client: ConnectedClient) {
+    /// Keep a disconnected client's settings for `grace` (zero disables resuming)
+    pub fn with_reconnect_grace(mut self, grace: Duration) -> Self {
+        self.reconnect_grace = grace;
+        self
+    }
+
+    /// Timestamp command traces with the server's clock
+    pub fn with_clock(mut self, clock: Arc<ServerClock>) -> Self {
+        self.traces = ControlTraces::new(clock);
+        self
+    }
+
+    /// Traces of commands sent to clients and how long they took to apply
+    pub fn traces(&self) -> &ControlTraces {
+        &self.traces
+    }
+
+    /// Cache of client capabilities and stream formats, kept across reconnects
+    pub fn capabilities(&self) -> &CapabilityCache {
+        &self.capabilities
+    }
+
+    /// Keep client settings in `store`, restoring them when clients reconnect
+    /// after a restart
+    pub fn set_persistence(&self, store: Arc<dyn Persistence>) {
+        *self.persistence.write() = Some(store);
+    }
+
+    /// The stored settings of a client, logging store failures
+    fn stored(&self, client_id: &str) -> Option<ClientRecord> {
+        let store = self.persistence.read().clone()?;
+        store
+            .load(client_id)
+            .map_err(|e| log::warn!("Cannot load settings of client {}: {}", client_id, e))
+            .ok()
+            .flatten()
+    }
+
+    fn store(&self, client_id: &str, record: &ClientRecord) {
+        let Some(store) = self.persistence.read().clone() else {
+            return;
+        };
+        if let Err(e) = store.save(client_id, record) {
+            log::warn!("Cannot save settings of client {}: {}", client_id, e);
+        }
+    }
+
+    /// Store a client's new volume and mute, keeping the rest of its record
+    fn store_volume(&self, client_id: &str, volume: u8, muted: bool) {
+        if self.persistence.read().is_none() {
+            return;
+        }
+        let mut record = self.stored(client_id).unwrap_or_default();
+        if record.volume != Some(volume) || record.muted != muted {
+            record.volume = Some(volume);
+            record.muted = muted;
+            self.store(client_id, &record);
+        }
+    }
+
+    /// Add a client to the manager, returning its connection generation
+    ///
+    /// A client already connected under the same ID is replaced; its later
+    /// [`disconnect`](Self::disconnect) is then ignored. A client not
+    /// [`restored`](ConnectedClient::restored) otherwise gets the volume, mute
+    /// and group it had before the server restarted; its player is sent the
+    /// volume and mute commands it accepts. The operator's name override
+    /// always replaces the client's own name.
+    pub fn add_client(&self, mut client: ConnectedClient) -> u64 {
         let client_id = client.client_id.clone();
-        self.clients.write().insert(client_id.clone(), client);
-        log::info!("Client {} added, total clients: {}", client_id, self.client_count());
+        let record = self.stored(&client_id);
+        let name_override = self.name_override(&client_id);
+        if let Some(name) = name_override.or_else(|| record.as_ref()?.name.clone()) {
+            self.name_overrides
+                .write()
+                .insert(client_id.clone(), name.clone());
+            client.name = name;
+        }
+        if let Some(record) = record.filter(|_| !client.restored) {
+            if let Some(volume) = record.volume {
+                client.volume = volume.min(client.max_volume);
+                client.muted = record.muted;
+                client.group_id = record.group_id;
+                client.restored = true;
+                log::info!(
+                    "Client {} restored: volume {}%{}",
+                    client_id,
+                    client.volume,
+                    if client.muted { " (muted)" } else { "" }
+                );
+                if client.supports_command("volume") {
+                    send_command(&self.traces, &client, "volume", Some(client.volume), None);
+                }
+                if client.supports_command("mute") {
+                    send_command(&self.traces, &client, "mute", None, Some(client.muted));
+                }
+            }
+        }
+        let generation = self.next_generation.fetch_add(1, Ordering::Relaxed);
+        client.generation = generation;
+        if let Some(old) = self.clients.write().insert(client_id.clone(), client) {
+            log::info!(
+                "Client {} replaced connection generation {} with {}",
+                client_id,
+                old.generation,
+                generation
+            );
+        }
+        self.update_player_count();
+        log::info!(
+            "Client {} added, total clients: {}",
+            client_id,
+            self.client_count()
+        );
+        generation
+    }
+
+    /// End a client's connection, keeping its settings for the grace period
+    ///
+    /// Does nothing and returns None when `generation` is not the client's
+    /// current connection, so a stale handler cannot remove a newer one.
+    pub fn disconnect(
+        &self,
+        client_id: &str,
+        generation: u64,
+        group_id: Option<String>,
+    ) -> Option<ConnectedClient> {
+        let client = {
+            let mut clients = self.clients.write();
+            if clients.get(client_id)?.generation != generation {
+                return None;
+            }
+            clients.remove(client_id)?
+        };
+        if let Some(format) = &client.audio_format {
+            self.capabilities.update_format(client_id, format.clone());
+        }
+        self.store(client_id, &self.record_of(&client, group_id.clone()));
+
+        if !self.reconnect_grace.is_zero() {
+            let session = ResumableSession {
+                generation,
+                group_id,
+                volume: client.volume,
+                muted: client.muted,
+                max_volume: client.max_volume,
+                disconnected_at: Instant::now(),
+            };
+            let mut sessions = self.sessions.lock();
+            sessions.retain(|_, s| s.disconnected_at.elapsed() < self.reconnect_grace);
+            sessions.insert(client_id.to_string(), session);
+        }
+        self.update_player_count();
+        log::info!(
+            "Client {} disconnected, total clients: {}",
+            client_id,
+            self.client_count()
+        );
+        Some(client)
+    }
+
+    /// Take the settings of a client that disconnected within the grace period
+    ///
+    /// Falls back to the client's session replicated from a primary server.
+    pub fn resume(&self, client_id: &str) -> Option<ResumableSession> {
+        let session = self
+            .sessions
+            .lock()
+            .remove(client_id)
+            .filter(|s| s.disconnected_at.elapsed() < self.reconnect_grace);
+        session.or_else(|| self.replicated.lock().remove(client_id))
+    }
+
+    /// Replace the sessions mirrored from a primary server
+    ///
+    /// Unlike sessions of clients that disconnected from this server, these
+    /// do not expire; each replication pass replaces the whole set.
+    pub fn replicate_sessions(&self, sessions: HashMap<ClientId, ResumableSession>) {
+        *self.replicated.lock() = sessions;
+    }
+
+    /// Where a client ID is in its connection lifecycle (None when unknown or expired)
+    pub fn connection_state(&self, client_id: &str) -> Option<ConnectionState> {
+        if let Some(client) = self.clients.read().get(client_id) {
+            return Some(ConnectionState::Connected {
+                generation: client.generation,
+            });
+        }
+        self.sessions
+            .lock()
+            .get(client_id)
+            .filter(|s| s.disconnected_at.elapsed() < self.reconnect_grace)
+            .map(|s| ConnectionState::Disconnected {
+                generation: s.generation,
+                since: s.disconnected_at,
+            })
     }
 
     /// Remove a client from the manager
     pub fn remove_client(&self, client_id: &str) -> Option<ConnectedClient> {
         let client = self.clients.write().remove(client_id);
+        self.update_player_count();
         if client.is_some() {
-            log::info!("Client {} removed, total clients: {}", client_id, self.client_count());
+            log::info!(
+                "Client {} removed, total clients: {}",
+                client_id,
+                self.client_count()
+            );
         }
         client
     }
 
+    /// Record that a client ID connected
+    ///
+    /// Returns true the first time an ID is seen since the server started,
+    /// unless the client's settings were stored before a restart.
+    pub fn mark_seen(&self, client_id: &str) -> bool {
+        self.seen.write().insert(client_id.to_string())
+            && self.stored(client_id).is_none_or(|r| r.volume.is_none())
+    }
+
+    /// The group a client rejoins on connecting: its resumed or restored group
+    ///
+    /// Once connected, the group it is in.
+    pub fn get_group_id(&self, client_id: &str) -> Option<String> {
+        self.clients.read().get(client_id)?.group_id.clone()
+    }
+
+    /// Record the group a connected client is in
+    ///
+    /// Returns false for unknown clients.
+    pub fn set_group_id(&self, client_id: &str, group_id: Option<String>) -> bool {
+        match self.clients.write().get_mut(client_id) {
+            Some(client) => {
+                client.group_id = group_id;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Settings of a connected client as they would be stored
+    fn record_of(&self, client: &ConnectedClient, group_id: Option<String>) -> ClientRecord {
+        ClientRecord {
+            volume: Some(client.volume),
+            muted: client.muted,
+            name: self.name_override(&client.client_id),
+            group_id,
+        }
+    }
+
+    /// Store the settings of every connected client, e.g. before shutting down
+    ///
+    /// `group_of` gives each client's current group. Blocks until the store
+    /// has written everything to disk.
+    pub fn persist_all(&self, group_of: impl Fn(&str) -> Option<String>) {
+        let records: Vec<(ClientId, ClientRecord)> = self
+            .clients
+            .read()
+            .values()
+            .map(|c| {
+                (
+                    c.client_id.clone(),
+                    self.record_of(c, group_of(&c.client_id)),
+                )
+            })
+            .collect();
+        for (client_id, record) in records {
+            self.store(&client_id, &record);
+        }
+        if let Some(store) = self.persistence.read().clone() {
+            if let Err(e) = store.flush() {
+                log::warn!("Cannot write client settings to disk: {}", e);
+            }
+        }
+    }
+
+    /// Show a client under `name` instead of the name it reports (None reverts)
+    ///
+    /// A connected client is renamed right away; reverting takes effect when
+    /// it next connects. The override is stored with the client's settings.
+    pub fn set_name_override(&self, client_id: &str, name: Option<String>) {
+        match &name {
+            Some(name) => {
+                self.name_overrides
+                    .write()
+                    .insert(client_id.to_string(), name.clone());
+                if let Some(client) = self.clients.write().get_mut(client_id) {
+                    client.name = name.clone();
+                }
+            }
+            None => {
+                self.name_overrides.write().remove(client_id);
+            }
+        }
+        if self.persistence.read().is_some() {
+            let mut record = self.stored(client_id).unwrap_or_default();
+            record.name = name;
+            self.store(client_id, &record);
+        }
+    }
+
+    /// The operator's display name for a client, if set
+    pub fn name_override(&self, client_id: &str) -> Option<String> {
+        self.name_overrides.read().get(client_id).cloned()
+    }
+
     /// Get the number of connected clients
     pub fn client_count(&self) -> usize {
         self.clients.read().len()
     }
 
+    /// Get the number of connected player clients
+    pub fn player_count(&self) -> usize {
+        *self.player_count.borrow()
+    }
+
+    /// Check if a connected client has the player role
+    pub fn is_player(&self, client_id: &str) -> bool {
+        self.clients
+            .read()
+            .get(client_id)
+            .is_some_and(|c| c.is_player())
+    }
+
+    /// Number of connected controllers other than `client_id`
+    pub fn controller_count_except(&self, client_id: &str) -> usize {
+        self.clients
+            .read()
+            .values()
+            .filter(|c| c.is_controller() && c.client_id != client_id)
+            .count()
+    }
+
+    /// Check if a client has the controller role
+    pub fn is_controller(&self, client_id: &str) -> bool {
+        self.clients
+            .read()
+            .get(client_id)
+            .is_some_and(|c| c.is_controller())
+    }
+
+    /// Check if a client has the metadata role
+    pub fn is_metadata(&self, client_id: &str) -> bool {
+        self.clients
+            .read()
+            .get(client_id)
+            .is_some_and(|c| c.is_metadata())
+    }
+
+    /// Artwork channels of a client with the artwork role
+    pub fn artwork_state(&self, client_id: &str) -> Option<ArtworkState> {
+        let clients = self.clients.read();
+        let client = clients.get(client_id).filter(|c| c.is_artwork())?;
+        Some(client.artwork.clone())
+    }
+
+    /// Change the artwork state of a client with the artwork role
+    ///
+    /// Returns what `f` returns, or None for clients without the role.
+    pub fn update_artwork_state<R>(
+        &self,
+        client_id: &str,
+        f: impl FnOnce(&mut ArtworkState) -> R,
+    ) -> Option<R> {
+        let mut clients = self.clients.write();
+        let client = clients.get_mut(client_id).filter(|c| c.is_artwork())?;
+        Some(f(&mut client.artwork))
+    }
+
+    /// Watch the number of connected player clients
+    pub fn subscribe_player_count(&self) -> watch::Receiver<usize> {
+        self.player_count.subscribe()
+    }
+
+    fn update_player_count(&self) {
+        let count = self
+            .clients
+            .read()
+            .values()
+            .filter(|c| c.is_player())
+            .count();
+        self.player_count.send_if_modified(|current| {
+            let changed = *current != count;
+            *current = count;
+            changed
+        });
+    }
+
     /// Update a client's audio format
     pub fn update_audio_format(&self, client_id: &str, format: AudioFormat) {
         if let Some(client) = self.clients.write().get_mut(client_id) {
@@ -119,23 +768,443 @@ impl ClientManager {
     }
 
     /// Update a client's volume
+    ///
+    /// A reported volume above the client's maximum is clamped, and the client
+    /// is told to lower its volume if it accepts the `volume` command. The
+    /// volume and mute are stored right away.
     pub fn update_volume(&self, client_id: &str, volume: u8, muted: bool) {
+        let mut clients = self.clients.write();
+        let Some(client) = clients.get_mut(client_id) else {
+            return;
+        };
+        client.volume = volume.min(client.max_volume);
+        client.muted = muted;
+        let stored = client.volume;
+        if volume > client.max_volume && client.supports_command("volume") {
+            log::info!(
+                "Client {} volume {}% exceeds maximum {}%, lowering",
+                client_id,
+                volume,
+                client.max_volume
+            );
+            send_command(
+                &self.traces,
+                client,
+                "volume",
+                Some(client.max_volume),
+                None,
+            );
+        }
+        drop(clients);
+        self.store_volume(client_id, stored, muted);
+    }
+
+    /// Set a client's maximum volume (0-100)
+    ///
+    /// If the client's current volume is above the new maximum it is lowered.
+    pub fn set_max_volume(&self, client_id: &str, max_volume: u8) -> bool {
+        let mut clients = self.clients.write();
+        let Some(client) = clients.get_mut(client_id) else {
+            return false;
+        };
+        client.max_volume = max_volume.min(100);
+        if client.volume > client.max_volume {
+            client.volume = client.max_volume;
+            if client.supports_command("volume") {
+                send_command(
+                    &self.traces,
+                    client,
+                    "volume",
+                    Some(client.max_volume),
+                    None,
+                );
+            }
+            let (volume, muted) = (client.volume, client.muted);
+            drop(clients);
+            self.store_volume(client_id, volume, muted);
+        }
+        true
+    }
+
+    /// Get a client's maximum volume
+    pub fn get_max_volume(&self, client_id: &str) -> Option<u8> {
+        Some(self.clients.read().get(client_id)?.max_volume)
+    }
+
+    /// Apply a volume change to a set of players in one step
+    ///
+    /// Volumes are clamped to each player's maximum. Players that accept the
+    /// matching command are sent it, and stored volumes change right away so
+    /// changes in quick succession build on each other; they are stored for
+    /// restarts too. Returns each player's resulting (client ID, volume,
+    /// muted), sorted by client ID.
+    pub fn apply_volume(
+        &self,
+        client_ids: &HashSet<ClientId>,
+        change: VolumeChange,
+    ) -> Vec<(ClientId, u8, bool)> {
+        let mut clients = self.clients.write();
+        let mut players: Vec<&mut ConnectedClient> = clients
+            .values_mut()
+            .filter(|c| c.is_player() && client_ids.contains(&c.client_id))
+            .collect();
+        players.sort_by(|a, b| a.client_id.cmp(&b.client_id));
+
+        let average = match players.len() {
+            0 => 0,
+            n => players.iter().map(|c| c.volume as u32).sum::<u32>() / n as u32,
+        };
+        for client in &mut players {
+            let volume = match change {
+                VolumeChange::Set(volume) => volume as u32,
+                VolumeChange::Step(step) => (client.volume as i32 + step as i32).max(0) as u32,
+                VolumeChange::Group(target) if average == 0 => target as u32,
+                VolumeChange::Group(target) => {
+                    (client.volume as u32 * target as u32 + average / 2) / average
+                }
+                VolumeChange::Mute(muted) => {
+                    client.muted = muted;
+                    if client.supports_command("mute") {
+                        send_command(&self.traces, client, "mute", None, Some(muted));
+                    }
+                    continue;
+                }
+            };
+            let volume = volume.min(client.max_volume as u32) as u8;
+            if volume != client.volume {
+                client.volume = volume;
+                if client.supports_command("volume") {
+                    send_command(&self.traces, client, "volume", Some(volume), None);
+                }
+            }
+        }
+        let results: Vec<(ClientId, u8, bool)> = players
+            .iter()
+            .map(|c| (c.client_id.clone(), c.volume, c.muted))
+            .collect();
+        drop(clients);
+        for (client_id, volume, muted) in &results {
+            self.store_volume(client_id, *volume, *muted);
+        }
+        results
+    }
+
+    /// Average volume of the players among `client_ids` (None if there are none)
+    pub fn group_volume(&self, client_ids: &HashSet<ClientId>) -> Option<u8> {
+        let clients = self.clients.read();
+        let volumes: Vec<u32> = client_ids
+            .iter()
+            .filter_map(|id| clients.get(id))
+            .filter(|c| c.is_player())
+            .map(|c| c.volume as u32)
+            .collect();
+        if volumes.is_empty() {
+            return None;
+        }
+        Some((volumes.iter().sum::<u32>() / volumes.len() as u32) as u8)
+    }
+
+    /// IDs of all connected players
+    pub fn player_ids(&self) -> HashSet<ClientId> {
+        self.clients
+            .read()
+            .values()
+            .filter(|c| c.is_player())
+            .map(|c| c.client_id.clone())
+            .collect()
+    }
+
+    /// Send mono audio to a client (or stereo again)
+    ///
+    /// The choice applies whether or not the client is connected and is kept
+    /// when it reconnects.
+    pub fn set_mono(&self, client_id: &str, mono: bool) {
+        let map = if mono {
+            ChannelMap::Mono
+        } else {
+            ChannelMap::Stereo
+        };
+        self.set_channel_map(client_id, map);
+    }
+
+    /// Whether a client gets mono audio
+    pub fn is_mono(&self, client_id: &str) -> bool {
+        self.channel_map(client_id) == ChannelMap::Mono
+    }
+
+    /// Set what a client's left and right channels carry
+    ///
+    /// Like [`set_mono`](Self::set_mono), the map is kept when the client
+    /// reconnects.
+    pub fn set_channel_map(&self, client_id: &str, map: ChannelMap) {
+        let mut maps = self.channel_maps.write();
+        if map == ChannelMap::Stereo {
+            maps.remove(client_id);
+        } else {
+            maps.insert(client_id.to_string(), map);
+        }
+    }
+
+    /// What a client's left and right channels carry
+    pub fn channel_map(&self, client_id: &str) -> ChannelMap {
+        self.channel_maps
+            .read()
+            .get(client_id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Pin a client to a codec, or clear its pin (None)
+    ///
+    /// Only the stored pin changes; renegotiating a connected client's
+    /// stream is up to the caller. The client's cached format is forgotten
+    /// so its next connection negotiates afresh.
+    pub fn set_codec_override(&self, client_id: &str, pin: Option<CodecOverride>) {
+        let mut overrides = self.codec_overrides.write();
+        match pin {
+            Some(pin) => overrides.insert(client_id.to_string(), pin),
+            None => overrides.remove(client_id),
+        };
+        self.capabilities.forget(client_id);
+    }
+
+    /// The codec pinned to a client, if any
+    pub fn codec_override(&self, client_id: &str) -> Option<CodecOverride> {
+        self.codec_overrides.read().get(client_id).copied()
+    }
+
+    /// Tag a client with the link tier it is on, or clear the tag (None)
+    ///
+    /// Tags apply whether or not the client is connected and are kept when it
+    /// reconnects. Untagged clients are placed on a tier from their RTTs.
+    pub fn set_link_tier(&self, client_id: &str, tier: Option<LinkTier>) {
+        let mut tiers = self.link_tiers.write();
+        match tier {
+            Some(tier) => tiers.insert(client_id.to_string(), tier),
+            None => tiers.remove(client_id),
+        };
+    }
+
+    /// The tier a client is on: its tag, else where its RTTs placed it
+    pub fn link_tier(&self, client_id: &str) -> Option<LinkTier> {
+        if let Some(tier) = self.link_tiers.read().get(client_id) {
+            return Some(*tier);
+        }
+        self.clients.read().get(client_id)?.placed_tier
+    }
+
+    /// The tier a client is tagged with, ignoring where its RTTs placed it
+    pub fn link_tier_tag(&self, client_id: &str) -> Option<LinkTier> {
+        self.link_tiers.read().get(client_id).copied()
+    }
+
+    /// Clients that are connected or have settings of their own, sorted
+    ///
+    /// Settings made for a client that never connected (a name, a channel
+    /// map, a codec pin or a tier tag) keep it on the list.
+    pub fn configured_clients(&self) -> Vec<ClientId> {
+        let mut ids: HashSet<ClientId> = self.clients.read().keys().cloned().collect();
+        ids.extend(self.name_overrides.read().keys().cloned());
+        ids.extend(self.channel_maps.read().keys().cloned());
+        ids.extend(self.codec_overrides.read().keys().cloned());
+        ids.extend(self.link_tiers.read().keys().cloned());
+        let mut ids: Vec<ClientId> = ids.into_iter().collect();
+        ids.sort();
+        ids
+    }
+
+    /// Set the buffer-ahead of players on a tier (None plays them with their group)
+    pub fn set_tier_buffer_ahead(&self, tier: LinkTier, buffer_ahead_ms: Option<u64>) {
+        let mut buffers = self.tier_buffer_ahead.write();
+        match buffer_ahead_ms {
+            Some(ms) => buffers.insert(tier, ms),
+            None => buffers.remove(&tier),
+        };
+    }
+
+    /// Buffer-ahead of players on a tier, if it has one
+    pub fn tier_buffer_ahead(&self, tier: LinkTier) -> Option<u64> {
+        self.tier_buffer_ahead.read().get(&tier).copied()
+    }
+
+    /// Buffer-ahead a client plays with in a group with `group_ms`
+    ///
+    /// A tier's buffer-ahead only ever lengthens the group's, so a slow tier
+    /// never leaves a player short of audio the group needs.
+    pub fn buffer_ahead_for(&self, client_id: &str, group_ms: u64) -> u64 {
+        self.link_tier(client_id)
+            .and_then(|tier| self.tier_buffer_ahead(tier))
+            .map_or(group_ms, |tier_ms| tier_ms.max(group_ms))
+    }
+
+    /// Split clients of a group with `group_ms` by the buffer-ahead they play with
+    pub fn split_by_buffer_ahead(
+        &self,
+        client_ids: &HashSet<ClientId>,
+        group_ms: u64,
+    ) -> Vec<(u64, HashSet<ClientId>)> {
+        if self.tier_buffer_ahead.read().is_empty() {
+            return vec![(group_ms, client_ids.clone())];
+        }
+        let mut by_buffer: HashMap<u64, HashSet<ClientId>> = HashMap::new();
+        for client_id in client_ids {
+            by_buffer
+                .entry(self.buffer_ahead_for(client_id, group_ms))
+                .or_default()
+                .insert(client_id.clone());
+        }
+        by_buffer.into_iter().collect()
+    }
+
+    /// Split clients by the processing the server applies to their audio
+    ///
+    /// Returns (processing, client IDs) pairs; unknown clients are skipped.
+    pub fn group_by_output(
+        &self,
+        client_ids: &HashSet<ClientId>,
+    ) -> Vec<(OutputProcessing, HashSet<ClientId>)> {
+        let clients = self.clients.read();
+        let channel_maps = self.channel_maps.read();
+        let overrides = self.codec_overrides.read();
+        let default_format = StreamFormat::from(&Self::default_audio_format());
+        let mut by_output: HashMap<OutputProcessing, HashSet<ClientId>> = HashMap::new();
+        for client_id in client_ids {
+            if let Some(client) = clients.get(client_id) {
+                let output = OutputProcessing {
+                    gain: client.server_gain(),
+                    channel_map: channel_maps.get(client_id).copied().unwrap_or_default(),
+                    format: client
+                        .audio_format
+                        .as_ref()
+                        .map_or(default_format, StreamFormat::from),
+                    encoder: overrides
+                        .get(client_id)
+                        .map(|pin| pin.settings)
+                        .unwrap_or_default(),
+                };
+                by_output
+                    .entry(output)
+                    .or_default()
+                    .insert(client_id.clone());
+            }
+        }
+        by_output.into_iter().collect()
+    }
+
+    /// Update a client's reported stream statistics
+    ///
+    /// A reported buffer level feeds the client's buffer trend; a client whose
+    /// buffer turns low or starts draining toward empty is logged. Returns the
+    /// link tier the client's RTTs placed it on, the first time they do.
+    pub fn update_stats(&self, client_id: &str, stats: ClientStats) -> Option<LinkTier> {
+        let mut placed = None;
         if let Some(client) = self.clients.write().get_mut(client_id) {
-            client.volume = volume;
-            client.muted = muted;
+            if let Some(buffered_ms) = stats.buffered_ms {
+                let before = client.buffer_trend.health();
+                client.buffer_trend.record(Instant::now(), buffered_ms);
+                let after = client.buffer_trend.health();
+                if after != before && after != BufferHealth::Healthy {
+                    log::warn!(
+                        "Client {} buffer {:?} at {}ms",
+                        client_id,
+                        after,
+                        buffered_ms
+                    );
+                }
+            }
+            // Reports repeat the latest RTT until the next time sync, so only
+            // count a value when it changes
+            let last_rtt = client.stats.as_ref().and_then(|s| s.rtt_micros);
+            if let Some(rtt) = stats.rtt_micros.filter(|&rtt| Some(rtt) != last_rtt) {
+                client.rtt_histogram.record(rtt);
+                if client.placed_tier.is_none() {
+                    placed = client
+                        .rtt_histogram
+                        .summary()
+                        .and_then(|s| LinkTier::infer(&s));
+                    client.placed_tier = placed;
+                }
+            }
+            client.stats = Some(stats);
         }
+        if let Some(tier) = placed {
+            log::info!("Client {} placed on the {} tier", client_id, tier.as_str());
+        }
+        placed
+    }
+
+    /// Get percentiles of a client's reported time-sync RTTs
+    pub fn rtt_summary(&self, client_id: &str) -> Option<RttSummary> {
+        self.clients.read().get(client_id)?.rtt_histogram.summary()
+    }
+
+    /// Get a client's buffer health from its recent reports
+    pub fn buffer_health(&self, client_id: &str) -> Option<BufferHealth> {
+        Some(self.clients.read().get(client_id)?.buffer_trend.health())
+    }
+
+    /// Get a client's latest reported stream statistics
+    pub fn get_stats(&self, client_id: &str) -> Option<ClientStats> {
+        self.clients.read().get(client_id)?.stats.clone()
     }
 
     /// Broadcast a binary message to all player clients
-    pub fn broadcast_audio(&self, message: &[u8]) {
+    ///
+    /// Every client's queue shares the one buffer.
+    pub fn broadcast_audio(&self, message: impl Into<Bytes>) {
+        let _broadcast = profiling::scope("broadcast");
+        let message = message.into();
         let clients = self.clients.read();
         for client in clients.values() {
             if client.is_player() {
-                let _ = client.send(ServerMessage::Binary(message.to_vec()));
+                client.send_audio(&message);
+            }
+        }
+    }
+
+    /// Send a binary message to the given clients that have the player role
+    pub fn broadcast_audio_to(&self, client_ids: &HashSet<ClientId>, message: impl Into<Bytes>) {
+        let _broadcast = profiling::scope("broadcast");
+        let message = message.into();
+        let clients = self.clients.read();
+        for client_id in client_ids {
+            if let Some(client) = clients.get(client_id) {
+                if client.is_player() {
+                    client.send_audio(&message);
+                }
             }
         }
     }
 
+    /// Send a binary frame (artwork, visualizer data) to the given clients
+    ///
+    /// Frames too large for one WebSocket message go out as transfer
+    /// segments, encoded once and shared by every recipient. Returns how many
+    /// clients the frame was queued for.
+    pub fn send_frame_to(&self, client_ids: &HashSet<ClientId>, frame: &BinaryFrame) -> usize {
+        let frames: Vec<Bytes> = match self.segmenter.lock().segment(frame) {
+            Ok(frames) => frames.into_iter().map(Bytes::from).collect(),
+            Err(e) => {
+                log::warn!(
+                    "Cannot send binary message type {}: {}",
+                    frame.message_type(),
+                    e
+                );
+                return 0;
+            }
+        };
+        let clients = self.clients.read();
+        client_ids
+            .iter()
+            .filter_map(|client_id| clients.get(client_id))
+            .filter(|client| {
+                frames
+                    .iter()
+                    .all(|data| client.send(ServerMessage::Binary(data.clone())).is_ok())
+            })
+            .count()
+    }
+
     /// Broadcast a text message to all clients
     pub fn broadcast_text(&self, message: &str) {
         let clients = self.clients.read();
@@ -147,12 +1216,34 @@ impl ClientManager {
     /// Send a text message to a specific client
     pub fn send_to_client(&self, client_id: &str, message: &str) -> bool {
         if let Some(client) = self.clients.read().get(client_id) {
-            client.send(ServerMessage::Text(message.to_string())).is_ok()
+            client
+                .send(ServerMessage::Text(message.to_string()))
+                .is_ok()
         } else {
             false
         }
     }
 
+    /// Send an application-specific message to a client
+    ///
+    /// The message is sent as `{"type": message_type, "payload": payload}`.
+    /// Returns false if the client is not connected or the payload fails to serialize.
+    pub fn send_custom(
+        &self,
+        client_id: &str,
+        message_type: &str,
+        payload: &impl serde::Serialize,
+    ) -> bool {
+        let message = serde_json::json!({ "type": message_type, "payload": payload });
+        match serde_json::to_string(&message) {
+            Ok(json) => self.send_to_client(client_id, &json),
+            Err(e) => {
+                log::error!("Failed to serialize {} message: {}", message_type, e);
+                false
+            }
+        }
+    }
+
     /// Send stream/clear to all player clients
     /// Per spec: instructs clients to clear buffers without ending stream (for seek)
     pub fn broadcast_stream_clear(&self, roles: Option<Vec<String>>) {
@@ -166,7 +1257,10 @@ impl ClientManager {
                     let _ = client.send(ServerMessage::Text(json.clone()));
                 }
             }
-            log::debug!("Broadcast stream/clear to {} player clients", clients.values().filter(|c| c.is_player()).count());
+            log::debug!(
+                "Broadcast stream/clear to {} player clients",
+                clients.values().filter(|c| c.is_player()).count()
+            );
         }
     }
 
@@ -183,49 +1277,115 @@ impl ClientManager {
                     let _ = client.send(ServerMessage::Text(json.clone()));
                 }
             }
-            log::debug!("Broadcast stream/end to {} player clients", clients.values().filter(|c| c.is_player()).count());
+            log::debug!(
+                "Broadcast stream/end to {} player clients",
+                clients.values().filter(|c| c.is_player()).count()
+            );
         }
     }
 
     /// Send server/command with player command to a specific client
     /// Per spec: command must be one of supported_commands from client/hello
-    pub fn send_player_command(&self, client_id: &str, command: &str, volume: Option<u8>, mute: Option<bool>) -> bool {
-        use crate::protocol::messages::{Message, ServerCommand, PlayerCommand};
+    /// Volumes above the client's maximum are clamped.
+    pub fn send_player_command(
+        &self,
+        client_id: &str,
+        command: &str,
+        volume: Option<u8>,
+        mute: Option<bool>,
+    ) -> bool {
+        match self.clients.read().get(client_id) {
+            Some(client) => send_command(&self.traces, client, command, volume, mute),
+            None => false,
+        }
+    }
 
-        let msg = Message::ServerCommand(ServerCommand {
-            player: Some(PlayerCommand {
-                command: command.to_string(),
-                volume,
-                mute,
-            }),
+    /// Send a handoff instructing a client to move to another server
+    ///
+    /// The client connects to `url` and disconnects from this server with
+    /// `client/goodbye` reason `another_server`. If `server_id` is given, the
+    /// client refuses the handoff when the new server reports a different id.
+    pub fn send_handoff(&self, client_id: &str, url: &str, server_id: Option<&str>) -> bool {
+        use crate::protocol::messages::{Message, ServerHandoff};
+
+        let msg = Message::ServerHandoff(ServerHandoff {
+            url: url.to_string(),
+            server_id: server_id.map(|s| s.to_string()),
         });
 
-        if let Ok(json) = serde_json::to_string(&msg) {
-            self.send_to_client(client_id, &json)
-        } else {
-            false
+        match serde_json::to_string(&msg) {
+            Ok(json) => self.send_to_client(client_id, &json),
+            Err(_) => false,
         }
     }
 
-    /// Broadcast server/command with player command to all player clients
-    pub fn broadcast_player_command(&self, command: &str, volume: Option<u8>, mute: Option<bool>) {
-        use crate::protocol::messages::{Message, ServerCommand, PlayerCommand};
+    /// Ask a client for a diagnostics snapshot
+    ///
+    /// The receiver resolves when the client answers; drop it to give up.
+    /// Returns None if the client is not connected.
+    pub fn request_diagnostics(
+        &self,
+        client_id: &str,
+    ) -> Option<oneshot::Receiver<ClientDiagnostics>> {
+        use crate::protocol::messages::{DiagnosticsRequest, Message};
 
-        let msg = Message::ServerCommand(ServerCommand {
-            player: Some(PlayerCommand {
-                command: command.to_string(),
-                volume,
-                mute,
-            }),
+        let request_id = self
+            .next_request_id
+            .fetch_add(1, Ordering::Relaxed)
+            .to_string();
+        let msg = Message::DiagnosticsRequest(DiagnosticsRequest {
+            request_id: request_id.clone(),
         });
+        let json = serde_json::to_string(&msg).ok()?;
 
-        if let Ok(json) = serde_json::to_string(&msg) {
-            let clients = self.clients.read();
-            for client in clients.values() {
-                if client.is_player() {
-                    let _ = client.send(ServerMessage::Text(json.clone()));
+        let (tx, rx) = oneshot::channel();
+        let mut pending = self.pending_diagnostics.lock();
+        // Forget requests whose callers gave up
+        pending.retain(|_, p| !p.reply.is_closed());
+        pending.insert(
+            request_id.clone(),
+            PendingDiagnostics {
+                client_id: client_id.to_string(),
+                reply: tx,
+            },
+        );
+        drop(pending);
+
+        if self.send_to_client(client_id, &json) {
+            Some(rx)
+        } else {
+            self.pending_diagnostics.lock().remove(&request_id);
+            None
+        }
+    }
+
+    /// Deliver a client's diagnostics reply to the request it answers
+    ///
+    /// Replies from a client other than the one asked are ignored.
+    pub(crate) fn complete_diagnostics(&self, client_id: &str, diagnostics: ClientDiagnostics) {
+        let mut pending = self.pending_diagnostics.lock();
+        match pending.get(&diagnostics.request_id) {
+            Some(p) if p.client_id == client_id => {
+                if let Some(p) = pending.remove(&diagnostics.request_id) {
+                    let _ = p.reply.send(diagnostics);
                 }
             }
+            _ => log::debug!(
+                "Ignoring unrequested diagnostics {} from {}",
+                diagnostics.request_id,
+                client_id
+            ),
+        }
+    }
+
+    /// Broadcast server/command with player command to all player clients
+    /// Volumes above a client's maximum are clamped per client.
+    pub fn broadcast_player_command(&self, command: &str, volume: Option<u8>, mute: Option<bool>) {
+        let clients = self.clients.read();
+        for client in clients.values() {
+            if client.is_player() {
+                send_command(&self.traces, client, command, volume, mute);
+            }
         }
     }
 
@@ -239,6 +1399,66 @@ impl ClientManager {
         self.clients.read().get(client_id)?.audio_format.clone()
     }
 
+    /// Get the formats a client can decode
+    pub fn get_supported_formats(&self, client_id: &str) -> Option<Vec<AudioFormatSpec>> {
+        Some(
+            self.clients
+                .read()
+                .get(client_id)?
+                .supported_formats
+                .clone(),
+        )
+    }
+
+    /// Replace a client's negotiated format, returning false for unknown clients
+    pub fn set_audio_format(&self, client_id: &str, format: AudioFormat) -> bool {
+        match self.clients.write().get_mut(client_id) {
+            Some(client) => {
+                client.audio_format = Some(format);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Record the format a set of clients is actually streamed in
+    ///
+    /// Returns the clients whose previous format (the default for clients
+    /// without one) differs, which need a new `stream/start`.
+    pub fn update_stream_format(
+        &self,
+        client_ids: &HashSet<ClientId>,
+        format: &AudioFormat,
+    ) -> Vec<ClientId> {
+        let default_format = Self::default_audio_format();
+        let changed: Vec<ClientId> = {
+            let clients = self.clients.read();
+            client_ids
+                .iter()
+                .filter(|id| {
+                    clients.get(*id).is_some_and(|client| {
+                        client.audio_format.as_ref().unwrap_or(&default_format) != format
+                    })
+                })
+                .cloned()
+                .collect()
+        };
+        if !changed.is_empty() {
+            let mut clients = self.clients.write();
+            for client_id in &changed {
+                if let Some(client) = clients.get_mut(client_id) {
+                    client.audio_format = Some(format.clone());
+                }
+            }
+        }
+        changed
+    }
+
+    /// Get a client's negotiated FEC parameters
+    pub fn get_fec(&self, client_id: &str) -> Option<FecConfig> {
+        self.clients.read().get(client_id)?.fec()
+    }
+
     /// Iterate over all clients with a closure
     pub fn for_each<F>(&self, mut f: F)
     where
@@ -262,6 +1482,33 @@ impl ClientManager {
     }
 }
 
+/// Send server/command to a client, clamping the volume to its maximum
+///
+/// The command carries a trace for measuring how long the client takes to apply it.
+fn send_command(
+    traces: &ControlTraces,
+    client: &ConnectedClient,
+    command: &str,
+    volume: Option<u8>,
+    mute: Option<bool>,
+) -> bool {
+    use crate::protocol::messages::{Message, PlayerCommand, ServerCommand};
+
+    let msg = Message::ServerCommand(ServerCommand {
+        player: Some(PlayerCommand {
+            command: command.to_string(),
+            volume: volume.map(|v| v.min(client.max_volume)),
+            mute,
+        }),
+        trace: Some(traces.start(&client.client_id)),
+    });
+
+    match serde_json::to_string(&msg) {
+        Ok(json) => client.send(ServerMessage::Text(json)).is_ok(),
+        Err(_) => false,
+    }
+}
+
 impl Default for ClientManager {
     fn default() -> Self {
         Self::new()
@@ -272,6 +1519,501 @@ impl Clone for ClientManager {
     fn clone(&self) -> Self {
         Self {
             clients: Arc::clone(&self.clients),
+            player_count: Arc::clone(&self.player_count),
+            seen: Arc::clone(&self.seen),
+            pending_diagnostics: Arc::clone(&self.pending_diagnostics),
+            next_request_id: Arc::clone(&self.next_request_id),
+            next_generation: Arc::clone(&self.next_generation),
+            sessions: Arc::clone(&self.sessions),
+            reconnect_grace: self.reconnect_grace,
+            replicated: Arc::clone(&self.replicated),
+            channel_maps: Arc::clone(&self.channel_maps),
+            codec_overrides: Arc::clone(&self.codec_overrides),
+            link_tiers: Arc::clone(&self.link_tiers),
+            tier_buffer_ahead: Arc::clone(&self.tier_buffer_ahead),
+            capabilities: self.capabilities.clone(),
+            segmenter: Arc::clone(&self.segmenter),
+            name_overrides: Arc::clone(&self.name_overrides),
+            persistence: Arc::clone(&self.persistence),
+            traces: self.traces.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::binary::{AUDIO_CHUNK, FEC_PARITY};
+
+    fn add_client(
+        manager: &ClientManager,
+        id: &str,
+        commands: &[&str],
+        max_volume: u8,
+    ) -> mpsc::UnboundedReceiver<ServerMessage> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut client = ConnectedClient::new(id.to_string(), id.to_string(), tx);
+        client.active_roles = vec!["player@v1".to_string()];
+        client.supported_commands = commands.iter().map(|c| c.to_string()).collect();
+        client.max_volume = max_volume;
+        manager.add_client(client);
+        rx
+    }
+
+    fn generation(manager: &ClientManager, id: &str) -> u64 {
+        match manager.connection_state(id) {
+            Some(ConnectionState::Connected { generation }) => generation,
+            state => panic!("{} not connected: {:?}", id, state),
         }
     }
+
+    fn sent_volume(rx: &mut mpsc::UnboundedReceiver<ServerMessage>) -> Option<u64> {
+        match rx.try_recv() {
+            Ok(ServerMessage::Text(text)) => {
+                let value: serde_json::Value = serde_json::from_str(&text).unwrap();
+                value["payload"]["player"]["volume"].as_u64()
+            }
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_max_volume_clamps_commands_and_reports() {
+        let manager = ClientManager::new();
+        let mut rx = add_client(&manager, "kids", &["volume", "mute"], 60);
+
+        assert!(manager.send_player_command("kids", "volume", Some(90), None));
+        assert_eq!(sent_volume(&mut rx), Some(60));
+
+        // A client reporting a volume above its cap is told to lower it
+        manager.update_volume("kids", 80, false);
+        assert_eq!(sent_volume(&mut rx), Some(60));
+        manager.update_volume("kids", 40, false);
+        assert_eq!(sent_volume(&mut rx), None);
+
+        assert!(manager.set_max_volume("kids", 30));
+        assert_eq!(sent_volume(&mut rx), Some(30));
+    }
+
+    #[test]
+    fn test_group_volume_scales_proportionally() {
+        let manager = ClientManager::new();
+        let mut loud = add_client(&manager, "loud", &["volume", "mute"], 100);
+        let mut quiet = add_client(&manager, "quiet", &["volume", "mute"], 100);
+        let _capped = add_client(&manager, "capped", &[], 50);
+        manager.update_volume("loud", 60, false);
+        manager.update_volume("quiet", 20, false);
+        manager.update_volume("capped", 40, false);
+        let ids: HashSet<ClientId> = ["loud", "quiet", "capped"].map(String::from).into();
+        assert_eq!(manager.group_volume(&ids), Some(40));
+
+        // Halving the group volume halves everyone
+        let result = manager.apply_volume(&ids, VolumeChange::Group(20));
+        assert_eq!(
+            result,
+            [
+                ("capped".to_string(), 20, false),
+                ("loud".to_string(), 30, false),
+                ("quiet".to_string(), 10, false),
+            ]
+        );
+        assert_eq!(sent_volume(&mut loud), Some(30));
+        assert_eq!(sent_volume(&mut quiet), Some(10));
+
+        // Steps build on the stored volumes and respect each maximum
+        manager.apply_volume(&ids, VolumeChange::Step(5));
+        let result = manager.apply_volume(&ids, VolumeChange::Step(30));
+        assert_eq!(result[0].1, 50);
+        assert_eq!(result[1].1, 65);
+        let result = manager.apply_volume(&ids, VolumeChange::Mute(true));
+        assert!(result.iter().all(|(_, _, muted)| *muted));
+    }
+
+    #[test]
+    fn test_server_gain_for_clients_without_volume_command() {
+        let manager = ClientManager::new();
+        let _a = add_client(&manager, "commanded", &["volume"], 60);
+        let _b = add_client(&manager, "uncommanded", &[], 60);
+        let _c = add_client(&manager, "uncapped", &[], 100);
+
+        let ids: HashSet<ClientId> = ["commanded", "uncommanded", "uncapped"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let mut by_gain = manager.group_by_output(&ids);
+        by_gain.sort_by_key(|(output, _)| output.gain);
+
+        assert_eq!(by_gain.len(), 2);
+        assert_eq!(by_gain[0].0.gain, 60);
+        assert!(by_gain[0].1.contains("uncommanded"));
+        assert_eq!(by_gain[1].1.len(), 2);
+
+        // Mono is remembered while the client is away
+        manager.set_mono("uncapped", true);
+        manager.remove_client("uncapped");
+        let _c = add_client(&manager, "uncapped", &[], 100);
+        let by_output = manager.group_by_output(&ids);
+        assert_eq!(by_output.len(), 3);
+        assert!(by_output.iter().any(
+            |(output, ids)| output.channel_map == ChannelMap::Mono && ids.contains("uncapped")
+        ));
+    }
+
+    #[test]
+    fn test_server_gain_applies_volume_and_mute() {
+        let manager = ClientManager::new();
+        let _a = add_client(&manager, "speaker", &[], 100);
+        let _b = add_client(&manager, "mutable", &["volume", "mute"], 100);
+        let ids: HashSet<ClientId> = ["speaker", "mutable"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let gain = |id: &str| {
+            manager
+                .group_by_output(&ids)
+                .into_iter()
+                .find(|(_, clients)| clients.contains(id))
+                .map(|(output, _)| output.gain)
+        };
+
+        manager.apply_volume(&ids, VolumeChange::Set(35));
+        assert_eq!(gain("speaker"), Some(35));
+        assert_eq!(gain("mutable"), Some(100));
+
+        // Muting silences only the client that cannot mute itself
+        manager.apply_volume(&ids, VolumeChange::Mute(true));
+        assert_eq!(gain("speaker"), Some(0));
+        assert_eq!(gain("mutable"), Some(100));
+        manager.apply_volume(&ids, VolumeChange::Mute(false));
+        assert_eq!(gain("speaker"), Some(35));
+    }
+
+    #[test]
+    fn test_codec_override_splits_encoder_settings() {
+        let manager = ClientManager::new();
+        let _a = add_client(&manager, "garage", &[], 100);
+        let _b = add_client(&manager, "den", &[], 100);
+        let ids: HashSet<ClientId> = ["garage", "den"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(manager.group_by_output(&ids).len(), 1);
+
+        let pin = CodecOverride {
+            codec: Codec::Flac,
+            settings: EncoderSettings {
+                flac_compression_level: Some(8),
+                ..Default::default()
+            },
+        };
+        manager.set_codec_override("garage", Some(pin));
+        let by_output = manager.group_by_output(&ids);
+        assert_eq!(by_output.len(), 2);
+        assert!(by_output.iter().any(|(output, ids)| {
+            output.encoder.flac_compression_level == Some(8) && ids.contains("garage")
+        }));
+
+        // The pin outlives the connection until cleared
+        manager.remove_client("garage");
+        assert_eq!(manager.codec_override("garage"), Some(pin));
+        manager.set_codec_override("garage", None);
+        assert_eq!(manager.codec_override("garage"), None);
+    }
+
+    #[test]
+    fn test_diagnostics_reply_reaches_requester() {
+        let manager = ClientManager::new();
+        let mut rx = add_client(&manager, "p1", &[], 100);
+        let mut reply = manager.request_diagnostics("p1").unwrap();
+        assert!(manager.request_diagnostics("missing").is_none());
+
+        let request_id = match rx.try_recv() {
+            Ok(ServerMessage::Text(text)) => {
+                let value: serde_json::Value = serde_json::from_str(&text).unwrap();
+                assert_eq!(value["type"], "_server/diagnostics");
+                value["payload"]["request_id"].as_str().unwrap().to_string()
+            }
+            _ => panic!("Expected diagnostics request"),
+        };
+        let diagnostics = ClientDiagnostics {
+            request_id,
+            stats: ClientStats {
+                underruns: 3,
+                ..Default::default()
+            },
+            sync_quality: None,
+            device_info: None,
+        };
+
+        // Only the client that was asked can answer
+        manager.complete_diagnostics("p2", diagnostics.clone());
+        assert!(reply.try_recv().is_err());
+        manager.complete_diagnostics("p1", diagnostics);
+        assert_eq!(reply.try_recv().unwrap().stats.underruns, 3);
+    }
+
+    #[test]
+    fn test_mark_seen_survives_reconnect() {
+        let manager = ClientManager::new();
+        assert!(manager.mark_seen("p1"));
+        let _rx = add_client(&manager, "p1", &[], 100);
+        manager.remove_client("p1");
+        assert!(!manager.mark_seen("p1"));
+    }
+
+    #[test]
+    fn test_settings_restored_after_restart() {
+        let store: Arc<dyn Persistence> = Arc::new(crate::server::MemoryPersistence::new());
+        let before = ClientManager::new();
+        before.set_persistence(store.clone());
+        let _rx = add_client(&before, "den", &["volume", "mute"], 100);
+        before.update_volume("den", 35, true);
+        before.set_name_override("den", Some("Den Speaker".to_string()));
+        let generation = generation(&before, "den");
+        before.disconnect("den", generation, Some("downstairs".to_string()));
+
+        // A new manager over the same store stands in for a restarted server
+        let after = ClientManager::new();
+        after.set_persistence(store);
+        assert!(!after.mark_seen("den"));
+        assert!(after.mark_seen("garage"));
+        let mut rx = add_client(&after, "den", &["volume", "mute"], 30);
+        assert_eq!(sent_volume(&mut rx), Some(30));
+        assert_eq!(after.get_group_id("den").as_deref(), Some("downstairs"));
+        assert_eq!(after.name_override("den").as_deref(), Some("Den Speaker"));
+        after.for_each(|client| {
+            assert_eq!(client.name, "Den Speaker");
+            assert!(client.muted);
+        });
+    }
+
+    #[test]
+    fn test_volume_stored_when_it_changes() {
+        let store: Arc<dyn Persistence> = Arc::new(crate::server::MemoryPersistence::new());
+        let manager = ClientManager::new();
+        manager.set_persistence(store.clone());
+        let _rx = add_client(&manager, "den", &["volume", "mute"], 100);
+        let volume = |id: &str| {
+            let record = store.load(id).unwrap().unwrap_or_default();
+            (record.volume, record.muted)
+        };
+
+        manager.update_volume("den", 35, false);
+        assert_eq!(volume("den"), (Some(35), false));
+        let ids: HashSet<ClientId> = ["den".to_string()].into();
+        manager.apply_volume(&ids, VolumeChange::Mute(true));
+        assert_eq!(volume("den"), (Some(35), true));
+        manager.set_max_volume("den", 20);
+        assert_eq!(volume("den"), (Some(20), true));
+    }
+
+    #[test]
+    fn test_rtt_recorded_once_per_sync() {
+        let manager = ClientManager::new();
+        let _rx = add_client(&manager, "p1", &[], 100);
+        for rtt in [2_000, 2_000, 3_000, 90_000] {
+            let stats = ClientStats {
+                rtt_micros: Some(rtt),
+                ..Default::default()
+            };
+            manager.update_stats("p1", stats);
+        }
+        let summary = manager.rtt_summary("p1").unwrap();
+        assert_eq!(summary.samples, 3);
+        assert_eq!(summary.max_micros, 90_000);
+    }
+
+    #[test]
+    fn test_rtts_place_untagged_clients_once() {
+        let manager = ClientManager::new();
+        let _rx = add_client(&manager, "p1", &[], 100);
+        let _rx = add_client(&manager, "p2", &[], 100);
+        manager.set_link_tier("p2", Some(LinkTier::Bluetooth));
+        manager.set_tier_buffer_ahead(LinkTier::Wireless, Some(900));
+
+        let mut placed = Vec::new();
+        for i in 0..40i64 {
+            let rtt = if i % 3 == 0 { 60_000 + i } else { 4_000 + i };
+            for id in ["p1", "p2"] {
+                let stats = ClientStats {
+                    rtt_micros: Some(rtt),
+                    ..Default::default()
+                };
+                placed.extend(manager.update_stats(id, stats).map(|tier| (id, tier)));
+            }
+        }
+        // Both are placed from their RTTs, but p2's tag wins
+        assert_eq!(
+            placed,
+            [("p1", LinkTier::Wireless), ("p2", LinkTier::Wireless)]
+        );
+        assert_eq!(manager.link_tier("p1"), Some(LinkTier::Wireless));
+        assert_eq!(manager.link_tier("p2"), Some(LinkTier::Bluetooth));
+
+        let ids: HashSet<ClientId> = ["p1", "p2"].iter().map(|id| id.to_string()).collect();
+        let mut split = manager.split_by_buffer_ahead(&ids, 500);
+        split.sort_by_key(|(ms, _)| *ms);
+        assert_eq!(split[0], (500, HashSet::from(["p2".to_string()])));
+        assert_eq!(split[1], (900, HashSet::from(["p1".to_string()])));
+    }
+
+    #[test]
+    fn test_reconnect_resumes_settings() {
+        let manager = ClientManager::new();
+        let _rx = add_client(&manager, "p1", &[], 80);
+        manager.update_volume("p1", 40, true);
+        let generation = generation(&manager, "p1");
+        assert!(manager
+            .disconnect("p1", generation, Some("kitchen".to_string()))
+            .is_some());
+        assert!(matches!(
+            manager.connection_state("p1"),
+            Some(ConnectionState::Disconnected { generation: g, .. }) if g == generation
+        ));
+
+        let session = manager.resume("p1").unwrap();
+        assert_eq!(session.group_id.as_deref(), Some("kitchen"));
+        assert_eq!(
+            (session.volume, session.muted, session.max_volume),
+            (40, true, 80)
+        );
+        assert!(manager.resume("p1").is_none());
+    }
+
+    #[test]
+    fn test_disconnect_keeps_settled_format() {
+        use crate::server::capability_cache::ClientCapabilities;
+
+        let manager = ClientManager::new();
+        let _rx = add_client(&manager, "p1", &[], 100);
+        let capabilities = ClientCapabilities {
+            supported_formats: Vec::new(),
+            supported_commands: Vec::new(),
+            buffer_capacity: 100_000,
+        };
+        let negotiated = ClientManager::default_audio_format();
+        manager
+            .capabilities()
+            .store("p1", capabilities.clone(), negotiated);
+
+        let mut settled = ClientManager::default_audio_format();
+        settled.codec = Codec::Flac;
+        settled.codec_header = Some(vec![0x66]);
+        let ids: HashSet<ClientId> = ["p1".to_string()].into();
+        assert_eq!(manager.update_stream_format(&ids, &settled), ["p1"]);
+        assert!(manager.update_stream_format(&ids, &settled).is_empty());
+
+        let generation = generation(&manager, "p1");
+        manager.disconnect("p1", generation, None);
+        assert_eq!(
+            manager.capabilities().format_for("p1", &capabilities),
+            Some(settled)
+        );
+    }
+
+    #[test]
+    fn test_stale_connection_cannot_remove_newer_one() {
+        let manager = ClientManager::new();
+        let _old = add_client(&manager, "p1", &[], 100);
+        let stale = generation(&manager, "p1");
+        let _new = add_client(&manager, "p1", &[], 100);
+        let current = generation(&manager, "p1");
+        assert_ne!(stale, current);
+
+        assert!(manager.disconnect("p1", stale, None).is_none());
+        assert_eq!(
+            manager.connection_state("p1"),
+            Some(ConnectionState::Connected {
+                generation: current
+            })
+        );
+    }
+
+    #[test]
+    fn test_sessions_expire_after_grace() {
+        let manager = ClientManager::new().with_reconnect_grace(Duration::ZERO);
+        let _rx = add_client(&manager, "p1", &[], 100);
+        let generation = generation(&manager, "p1");
+        manager.disconnect("p1", generation, None);
+        assert!(manager.connection_state("p1").is_none());
+        assert!(manager.resume("p1").is_none());
+    }
+
+    #[test]
+    fn test_broadcast_shares_one_buffer() {
+        let manager = ClientManager::new();
+        let mut receivers: Vec<_> = ["p1", "p2"]
+            .iter()
+            .map(|id| add_client(&manager, id, &[], 100))
+            .collect();
+        let clients = HashSet::from(["p1".to_string(), "p2".to_string()]);
+        manager.broadcast_audio_to(&clients, vec![AUDIO_CHUNK; 64]);
+
+        let pointers: Vec<*const u8> = receivers
+            .iter_mut()
+            .map(|rx| match rx.try_recv() {
+                Ok(ServerMessage::Binary(data)) => data.as_ptr(),
+                other => panic!("Expected audio chunk, got {:?}", other),
+            })
+            .collect();
+        assert_eq!(pointers[0], pointers[1]);
+    }
+
+    #[test]
+    fn test_large_frames_are_segmented() {
+        use crate::protocol::transfer::{Reassembler, DEFAULT_MAX_FRAME};
+
+        let manager = ClientManager::new();
+        let mut rx = add_client(&manager, "p1", &[], 100);
+        let image = vec![7u8; DEFAULT_MAX_FRAME * 2];
+        let frame = BinaryFrame::Artwork {
+            channel: 1,
+            timestamp: 42,
+            payload: &image,
+        };
+        let clients = HashSet::from(["p1".to_string(), "gone".to_string()]);
+        assert_eq!(manager.send_frame_to(&clients, &frame), 1);
+
+        let mut reassembler = Reassembler::default();
+        let mut segments = 0;
+        let mut reassembled = None;
+        while let Ok(ServerMessage::Binary(data)) = rx.try_recv() {
+            assert!(data.len() <= DEFAULT_MAX_FRAME);
+            segments += 1;
+            match BinaryFrame::decode(&data).unwrap() {
+                BinaryFrame::Segment { timestamp, payload } => {
+                    reassembled = reassembler
+                        .push(timestamp, payload, Instant::now())
+                        .unwrap();
+                }
+                other => panic!("Expected segment, got {:?}", other),
+            }
+        }
+        assert_eq!(segments, 3);
+        assert_eq!(reassembled, Some(frame.encode()));
+    }
+
+    #[test]
+    fn test_fec_client_receives_parity() {
+        let manager = ClientManager::new();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut client = ConnectedClient::new("p1".to_string(), "p1".to_string(), tx);
+        client.active_roles = vec!["player@v1".to_string()];
+        client.enable_fec(FecConfig { group_size: 2 });
+        manager.add_client(client);
+
+        let types: Vec<u8> = (0..2)
+            .flat_map(|i| {
+                let frame = BinaryFrame::AudioChunk {
+                    timestamp: i * 1000,
+                    payload: &[1, 2, 3],
+                };
+                manager.broadcast_audio(frame.encode());
+                std::iter::from_fn(|| match rx.try_recv() {
+                    Ok(ServerMessage::Binary(data)) => Some(data[0]),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+            })
+            .collect();
+        assert_eq!(types, [AUDIO_CHUNK, AUDIO_CHUNK, FEC_PARITY]);
+        assert_eq!(manager.get_fec("p1"), Some(FecConfig { group_size: 2 }));
+    }
 }
@@ -1,6 +1,8 @@
 // ABOUTME: Server-side monotonic clock
 // ABOUTME: Provides stable timestamps for audio synchronization
 
+use crate::sync::time::{Clock, SystemClock};
+use std::sync::Arc;
 use std::time::Instant;
 
 /// Server clock for generating timestamps
@@ -11,20 +13,28 @@ use std::time::Instant;
 pub struct ServerClock {
     /// When the server started
     start: Instant,
+    /// Source of the current time
+    clock: Arc<dyn Clock>,
 }
 
 impl ServerClock {
     /// Create a new server clock starting now
     pub fn new() -> Self {
+        Self::with_clock(SystemClock::shared())
+    }
+
+    /// Create a server clock that reads time from `clock`, starting now
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
         Self {
-            start: Instant::now(),
+            start: clock.now(),
+            clock,
         }
     }
 
     /// Get current server time in microseconds
     #[inline]
     pub fn now_micros(&self) -> i64 {
-        self.start.elapsed().as_micros() as i64
+        self.clock.now().duration_since(self.start).as_micros() as i64
     }
 
     /// Get the server start instant (for computing deltas)
@@ -47,6 +57,7 @@ impl Default for ServerClock {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::sync::time::ManualClock;
     use std::thread::sleep;
     use std::time::Duration;
 
@@ -60,4 +71,14 @@ mod tests {
         assert!(t2 > t1, "Clock should be monotonically increasing");
         assert!(t2 - t1 >= 10_000, "At least 10ms should have passed");
     }
+
+    #[test]
+    fn test_clock_follows_injected_time() {
+        let time = ManualClock::new();
+        let clock = ServerClock::with_clock(time.shared());
+        assert_eq!(clock.now_micros(), 0);
+
+        time.advance(Duration::from_millis(20));
+        assert_eq!(clock.now_micros(), 20_000);
+    }
 }
@@ -0,0 +1,272 @@
+// ABOUTME: Server codec preference policy
+// ABOUTME: Picks each client's stream format from its supported formats, a preference order and per-codec limits
+
+use crate::audio::types::{AudioFormat, Codec};
+use crate::protocol::messages::AudioFormatSpec;
+use crate::server::encoder::{EncoderRegistry, EncoderSettings};
+use std::collections::HashMap;
+
+/// Limits applied to one codec during format negotiation
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CodecConstraints {
+    /// Highest sample rate to stream with this codec; client formats above it are skipped
+    pub max_sample_rate: Option<u32>,
+}
+
+/// A codec the operator pins a client to, bypassing the preference order
+///
+/// Useful for a client that copes badly with the codec it would get, e.g. PCM
+/// for a speaker with a slow CPU while the rest of its group streams FLAC.
+/// The pinned encoder tuning covers what the encoders honor: FLAC level and
+/// dither.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CodecOverride {
+    /// Codec streamed to the client
+    pub codec: Codec,
+    /// Encoder tuning for the client's stream, over its group's settings
+    pub settings: EncoderSettings,
+}
+
+impl CodecOverride {
+    /// Pin a client to `codec` with its group's encoder settings
+    pub fn new(codec: Codec) -> Self {
+        Self {
+            codec,
+            settings: EncoderSettings::default(),
+        }
+    }
+}
+
+/// How the server chooses a codec for each client
+///
+/// Codecs in `preference` are tried in order against the client's supported
+/// formats. If the client supports none of them, its own first acceptable
+/// format is used. Only codecs with an encoder in the server's
+/// [`EncoderRegistry`] are acceptable. The default prefers PCM, the most
+/// compatible choice.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CodecPolicy {
+    /// Codecs in order of server preference
+    pub preference: Vec<Codec>,
+    /// Per-codec limits
+    pub constraints: HashMap<Codec, CodecConstraints>,
+}
+
+impl CodecPolicy {
+    /// Create a policy with the given preference order and no constraints
+    pub fn new(preference: impl IntoIterator<Item = Codec>) -> Self {
+        Self {
+            preference: preference.into_iter().collect(),
+            constraints: HashMap::new(),
+        }
+    }
+
+    /// Set the limits for a codec
+    pub fn constrain(mut self, codec: Codec, constraints: CodecConstraints) -> Self {
+        self.constraints.insert(codec, constraints);
+        self
+    }
+
+    /// Limits for a codec (unconstrained if none were set)
+    pub fn constraints_for(&self, codec: Codec) -> CodecConstraints {
+        self.constraints.get(&codec).copied().unwrap_or_default()
+    }
+
+    /// Choose a format from a client's supported formats
+    ///
+    /// Returns None if no supported format has a codec `encoders` can build
+    /// within its limits. Formats with more channels than their codec carries
+    /// are skipped, so a client lists a surround format ahead of its stereo
+    /// one to get surround.
+    pub fn select(
+        &self,
+        supported: &[AudioFormatSpec],
+        encoders: &EncoderRegistry,
+    ) -> Option<AudioFormat> {
+        let candidates: Vec<(Codec, &AudioFormatSpec)> = supported
+            .iter()
+            .filter_map(|spec| Some((Codec::from_name(&spec.codec)?, spec)))
+            .filter(|(codec, spec)| self.allows(*codec, spec, encoders))
+            .collect();
+
+        let (codec, spec) = self
+            .preference
+            .iter()
+            .find_map(|preferred| candidates.iter().find(|(codec, _)| codec == preferred))
+            .or_else(|| candidates.first())?;
+        Some(format_from(*codec, spec))
+    }
+
+    /// Choose a format in one codec from a client's supported formats
+    ///
+    /// The first matching format within the codec's limits wins. Returns None
+    /// if the client does not support the codec or `encoders` cannot build it.
+    pub fn select_codec(
+        &self,
+        supported: &[AudioFormatSpec],
+        codec: Codec,
+        encoders: &EncoderRegistry,
+    ) -> Option<AudioFormat> {
+        supported
+            .iter()
+            .filter(|spec| Codec::from_name(&spec.codec) == Some(codec))
+            .find(|spec| self.allows(codec, spec, encoders))
+            .map(|spec| format_from(codec, spec))
+    }
+
+    /// Whether a client format can be streamed in `codec` within its limits
+    fn allows(&self, codec: Codec, spec: &AudioFormatSpec, encoders: &EncoderRegistry) -> bool {
+        encoders.contains(codec.name())
+            && (1..=codec.max_channels()).contains(&spec.channels)
+            && self
+                .constraints_for(codec)
+                .max_sample_rate
+                .is_none_or(|max| spec.sample_rate <= max)
+    }
+}
+
+/// The stream format for a client's format spec
+fn format_from(codec: Codec, spec: &AudioFormatSpec) -> AudioFormat {
+    AudioFormat {
+        codec,
+        sample_rate: spec.sample_rate,
+        channels: spec.channels,
+        bit_depth: spec.bit_depth,
+        codec_header: None,
+    }
+}
+
+impl Default for CodecPolicy {
+    fn default() -> Self {
+        Self::new([Codec::Pcm])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::encoder::{AudioEncoder, EncoderParams, PcmEncoder};
+
+    fn spec(codec: &str, sample_rate: u32) -> AudioFormatSpec {
+        AudioFormatSpec {
+            codec: codec.to_string(),
+            channels: 2,
+            sample_rate,
+            bit_depth: 16,
+        }
+    }
+
+    /// The built-in encoders plus a stand-in registered as Opus
+    fn with_opus() -> EncoderRegistry {
+        let encoders = EncoderRegistry::new();
+        encoders.register(Codec::Opus.name(), |p: EncoderParams| {
+            Ok(Box::new(PcmEncoder::new(p.sample_rate, p.channels)) as Box<dyn AudioEncoder>)
+        });
+        encoders
+    }
+
+    #[test]
+    fn test_default_prefers_pcm_then_client_order() {
+        let policy = CodecPolicy::default();
+        let encoders = with_opus();
+        let supported = [spec("opus", 48000), spec("pcm", 44100)];
+        let format = policy.select(&supported, &encoders).unwrap();
+        assert_eq!((format.codec, format.sample_rate), (Codec::Pcm, 44100));
+
+        let supported = [
+            spec("vorbis", 48000),
+            spec("flac", 96000),
+            spec("opus", 48000),
+        ];
+        assert_eq!(
+            policy.select(&supported, &encoders).unwrap().codec,
+            Codec::Flac
+        );
+        assert!(policy.select(&[spec("vorbis", 48000)], &encoders).is_none());
+    }
+
+    #[test]
+    fn test_preference_order_and_constraints() {
+        let policy = CodecPolicy::new([Codec::Flac, Codec::Opus]).constrain(
+            Codec::Flac,
+            CodecConstraints {
+                max_sample_rate: Some(48000),
+            },
+        );
+
+        let encoders = with_opus();
+        let supported = [spec("pcm", 48000), spec("opus", 48000), spec("flac", 96000)];
+        // FLAC at 96kHz exceeds its limit, so Opus is next
+        assert_eq!(
+            policy.select(&supported, &encoders).unwrap().codec,
+            Codec::Opus
+        );
+
+        let supported = [spec("pcm", 48000), spec("flac", 96000), spec("flac", 48000)];
+        let format = policy.select(&supported, &encoders).unwrap();
+        assert_eq!((format.codec, format.sample_rate), (Codec::Flac, 48000));
+    }
+
+    #[test]
+    fn test_select_codec_ignores_preference() {
+        let policy = CodecPolicy::new([Codec::Flac]).constrain(
+            Codec::Opus,
+            CodecConstraints {
+                max_sample_rate: Some(48000),
+            },
+        );
+        let supported = [
+            spec("flac", 48000),
+            spec("opus", 96000),
+            spec("opus", 48000),
+        ];
+        let encoders = with_opus();
+        let format = policy
+            .select_codec(&supported, Codec::Opus, &encoders)
+            .unwrap();
+        assert_eq!((format.codec, format.sample_rate), (Codec::Opus, 48000));
+        assert!(policy
+            .select_codec(&supported, Codec::Mp3, &encoders)
+            .is_none());
+    }
+
+    #[test]
+    fn test_surround_formats_within_codec_channels() {
+        let channels = |codec, channels| AudioFormatSpec {
+            channels,
+            ..spec(codec, 48000)
+        };
+        let policy = CodecPolicy::new([Codec::Opus, Codec::Flac]);
+        let encoders = with_opus();
+        // Opus carries two channels at most, so the 5.1 listing is passed over
+        let supported = [
+            channels("opus", 6),
+            channels("flac", 6),
+            channels("opus", 2),
+        ];
+        let format = policy.select(&supported, &encoders).unwrap();
+        assert_eq!((format.codec, format.channels), (Codec::Opus, 2));
+        let format = policy
+            .select_codec(&supported, Codec::Flac, &encoders)
+            .unwrap();
+        assert_eq!(format.channels, 6);
+        assert!(policy.select(&[channels("pcm", 12)], &encoders).is_none());
+    }
+
+    #[test]
+    fn test_codecs_without_an_encoder_are_skipped() {
+        let policy = CodecPolicy::new([Codec::Opus, Codec::Pcm]);
+        let encoders = EncoderRegistry::new();
+        let supported = [spec("opus", 48000), spec("mp3", 44100), spec("flac", 44100)];
+        assert_eq!(
+            policy.select(&supported, &encoders).unwrap().codec,
+            Codec::Flac
+        );
+        assert!(policy
+            .select_codec(&supported, Codec::Opus, &encoders)
+            .is_none());
+        assert!(policy
+            .select(&[spec("opus", 48000), spec("mp3", 44100)], &encoders)
+            .is_none());
+    }
+}
@@ -1,7 +1,78 @@
 // ABOUTME: Server configuration
 // ABOUTME: Defines configurable parameters for the Sendspin server
 
-use std::net::SocketAddr;
+use crate::audio::downmix::{ChannelMap, DownmixLevels};
+use crate::audio::loudness::LoudnessNormalization;
+use crate::audio::replay_gain::ReplayGain;
+use crate::audio::types::Codec;
+use crate::server::adaptive_buffer::AdaptiveBufferConfig;
+use crate::server::client_manager::DEFAULT_RECONNECT_GRACE;
+use crate::server::codec_policy::{CodecConstraints, CodecOverride, CodecPolicy};
+use crate::server::control_api::{ApiKey, Permission};
+use crate::server::encoder::EncoderSettings;
+use crate::server::group::{AutoStart, GroupDefinition};
+use crate::server::history::DEFAULT_HISTORY_SIZE;
+use crate::server::link_tier::LinkTier;
+use crate::server::proxy::normalize_prefix;
+use crate::server::rate_limit::InboundLimits;
+use crate::server::replication::ReplicationConfig;
+use crate::server::scrobbler::ScrobblerConfig;
+use crate::server::source_fallback::FallbackConfig;
+use crate::server::stream_manager::StreamDefinition;
+use crate::server::track_start::SilenceTrim;
+use crate::server::url_cache::UrlCache;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Client ID that matches any client in per-client settings
+pub const ANY_CLIENT: &str = "*";
+
+/// Volume and mute state applied to a client the first time it connects
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InitialVolume {
+    /// Volume (0-100)
+    pub volume: u8,
+    /// Whether the client starts muted
+    pub muted: bool,
+}
+
+/// Which roles clients may take, for locked-down installations
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RoleLimits {
+    /// Most clients connected as controllers at once (None is unlimited)
+    pub max_controllers: Option<usize>,
+    /// Refuse the metadata role
+    pub no_metadata: bool,
+    /// Only grant the player role
+    pub player_only: bool,
+    /// Grant the `_source` role, letting clients replace what their group's stream plays
+    pub allow_source: bool,
+}
+
+impl RoleLimits {
+    /// Whether the controller role may go to another client while
+    /// `controllers` are connected
+    pub fn allows_controller(&self, controllers: usize) -> bool {
+        !self.player_only && self.max_controllers.is_none_or(|max| controllers < max)
+    }
+
+    /// Whether the metadata role may be granted
+    pub fn allows_metadata(&self) -> bool {
+        !self.player_only && !self.no_metadata
+    }
+
+    /// Whether the artwork role may be granted
+    pub fn allows_artwork(&self) -> bool {
+        !self.player_only
+    }
+
+    /// Whether the `_source` role may be granted
+    pub fn allows_source(&self) -> bool {
+        self.allow_source && !self.player_only
+    }
+}
 
 /// Server configuration
 #[derive(Clone, Debug)]
@@ -24,6 +95,92 @@ pub struct ServerConfig {
     pub default_channels: u8,
     /// Default bit depth
     pub default_bit_depth: u8,
+    /// Sample rate the output is pinned to; sources at other rates are resampled
+    pub fixed_sample_rate: Option<u32>,
+    /// Bit depth the output is pinned to
+    pub fixed_bit_depth: Option<u8>,
+    /// Stop generating audio while no player clients are connected
+    pub idle_standby: bool,
+    /// Auto-start policy for groups when their first player connects
+    pub auto_start: AutoStart,
+    /// Adapt each group's buffer-ahead from client RTT and buffer reports
+    pub adaptive_buffer: Option<AdaptiveBufferConfig>,
+    /// Maximum volume (0-100) per client ID
+    pub max_volumes: HashMap<String, u8>,
+    /// Initial volume per client ID, with `*` as the fallback for other clients
+    pub initial_volumes: HashMap<String, InitialVolume>,
+    /// Client IDs that get both channels summed to mono
+    pub mono_clients: HashSet<String>,
+    /// What each listed client's left and right channels carry
+    pub channel_maps: HashMap<String, ChannelMap>,
+    /// Serve the HTTP control API under `/api`
+    pub control_api: bool,
+    /// Keys accepted by the control API (empty leaves it open)
+    pub api_keys: Vec<ApiKey>,
+    /// Prefix applied to every route, for serving under a reverse-proxy path
+    pub path_prefix: String,
+    /// Externally reachable WebSocket URL, advertised instead of the bind address
+    pub public_url: Option<String>,
+    /// Take client addresses from `X-Forwarded-For` / `X-Real-IP`
+    pub trust_forwarded: bool,
+    /// Proxies whose forwarded headers are honored (loopback when empty)
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Expose playback as an MPRIS player on the D-Bus session bus (Unix only)
+    pub mpris: bool,
+    /// Advertise the server via mDNS so clients can find it without a URL
+    pub mdns: bool,
+    /// Largest FEC parity group offered to clients that request FEC (None disables it)
+    pub fec_max_group_size: Option<u8>,
+    /// Codec preference order and per-codec limits used in format negotiation
+    pub codec_policy: CodecPolicy,
+    /// Codecs pinned to individual clients, bypassing the codec policy
+    pub codec_overrides: HashMap<String, CodecOverride>,
+    /// Link tiers clients are tagged with, by client ID
+    pub link_tiers: HashMap<String, LinkTier>,
+    /// Buffer-ahead in milliseconds for players on each link tier
+    pub tier_buffer_ahead: HashMap<LinkTier, u64>,
+    /// Warn when a group's members drift further apart than this (None disables it)
+    pub sync_warn_micros: Option<i64>,
+    /// Levels used to fold multichannel sources down to stereo
+    pub downmix: DownmixLevels,
+    /// On-disk cache for finite HTTP sources (None streams every play)
+    pub url_cache: Option<UrlCache>,
+    /// How long a disconnected client's group and volume are kept for it to reconnect
+    pub reconnect_grace: Duration,
+    /// How long a new connection has to send `client/hello`
+    pub handshake_timeout: Duration,
+    /// Size and rate caps on messages from clients
+    pub inbound_limits: InboundLimits,
+    /// Roles clients may take
+    pub role_limits: RoleLimits,
+    /// Encoder tuning for streams whose group has no override
+    pub encoder_settings: EncoderSettings,
+    /// Source to fail over to while the playing one keeps failing (None disables it)
+    pub source_fallback: Option<FallbackConfig>,
+    /// Leading silence trimmed from each new track (None plays it)
+    pub silence_trim: Option<SilenceTrim>,
+    /// Loudness each source is brought toward (None plays sources as they are)
+    pub loudness: Option<LoudnessNormalization>,
+    /// How files' ReplayGain tags are applied (None ignores them)
+    pub replay_gain: Option<ReplayGain>,
+    /// File recording each chunk's generation, send, and play-at times (None disables it)
+    pub chunk_audit: Option<PathBuf>,
+    /// File the playback history is kept in across restarts (None keeps it in memory)
+    pub history_file: Option<PathBuf>,
+    /// Tracks kept in the playback history
+    pub history_size: usize,
+    /// Primary server mirrored by this warm standby (None runs standalone)
+    pub replication: Option<ReplicationConfig>,
+    /// Directory of the store keeping client settings across restarts
+    pub state_dir: Option<PathBuf>,
+    /// Services plays are scrobbled to
+    pub scrobblers: Vec<ScrobblerConfig>,
+    /// Groups created at startup
+    pub groups: Vec<GroupDefinition>,
+    /// Config file this configuration came from, watched for changes
+    pub config_file: Option<PathBuf>,
+    /// Streams started alongside the default one, each with its own engine
+    pub streams: Vec<StreamDefinition>,
 }
 
 impl ServerConfig {
@@ -53,11 +210,325 @@ impl ServerConfig {
         self
     }
 
+    /// Pin the output to a sample rate, resampling sources at other rates
+    ///
+    /// Also makes it the default sample rate for clients that list no formats.
+    pub fn fixed_sample_rate(mut self, sample_rate: u32) -> Self {
+        self.fixed_sample_rate = Some(sample_rate);
+        self.default_sample_rate = sample_rate;
+        self
+    }
+
+    /// Pin the output to a bit depth (also the default for clients that list no formats)
+    pub fn fixed_bit_depth(mut self, bit_depth: u8) -> Self {
+        self.fixed_bit_depth = Some(bit_depth);
+        self.default_bit_depth = bit_depth;
+        self
+    }
+
     /// Set the buffer ahead time in milliseconds
     pub fn buffer_ahead_ms(mut self, ms: u64) -> Self {
         self.buffer_ahead_ms = ms;
         self
     }
+
+    /// Enable or disable idle standby when no players are connected
+    pub fn idle_standby(mut self, enabled: bool) -> Self {
+        self.idle_standby = enabled;
+        self
+    }
+
+    /// Set the auto-start policy for groups
+    pub fn auto_start(mut self, policy: AutoStart) -> Self {
+        self.auto_start = policy;
+        self
+    }
+
+    /// Enable dynamic buffer-ahead adaptation
+    pub fn adaptive_buffer(mut self, config: AdaptiveBufferConfig) -> Self {
+        self.adaptive_buffer = Some(config);
+        self
+    }
+
+    /// Cap a client's volume at `percent` (0-100)
+    pub fn max_volume(mut self, client_id: impl Into<String>, percent: u8) -> Self {
+        self.max_volumes.insert(client_id.into(), percent.min(100));
+        self
+    }
+
+    /// Set the volume and mute state for a client's first connection
+    ///
+    /// Use `*` as the client ID to set the default for all other clients.
+    pub fn initial_volume(mut self, client_id: impl Into<String>, volume: u8, muted: bool) -> Self {
+        let initial = InitialVolume {
+            volume: volume.min(100),
+            muted,
+        };
+        self.initial_volumes.insert(client_id.into(), initial);
+        self
+    }
+
+    /// Send a client mono audio, for single-speaker installs
+    pub fn mono(mut self, client_id: impl Into<String>) -> Self {
+        self.mono_clients.insert(client_id.into());
+        self
+    }
+
+    /// Map a client's channels, e.g. to split a stereo pair across two speakers
+    pub fn channel_map(mut self, client_id: impl Into<String>, map: ChannelMap) -> Self {
+        self.channel_maps.insert(client_id.into(), map);
+        self
+    }
+
+    /// Enable or disable the HTTP control API
+    pub fn control_api(mut self, enabled: bool) -> Self {
+        self.control_api = enabled;
+        self
+    }
+
+    /// Add a key accepted by the control API
+    pub fn api_key(mut self, key: impl Into<String>, permission: Permission) -> Self {
+        self.api_keys.push(ApiKey::new(key, permission));
+        self
+    }
+
+    /// Serve all routes under a path prefix (e.g. `/audio`)
+    pub fn path_prefix(mut self, prefix: impl AsRef<str>) -> Self {
+        self.path_prefix = normalize_prefix(prefix.as_ref());
+        self
+    }
+
+    /// Set the WebSocket URL advertised to clients (e.g. `wss://example.com/audio/sendspin`)
+    pub fn public_url(mut self, url: impl Into<String>) -> Self {
+        self.public_url = Some(url.into());
+        self
+    }
+
+    /// Trust forwarded-for headers set by a reverse proxy
+    pub fn trust_forwarded(mut self, trust: bool) -> Self {
+        self.trust_forwarded = trust;
+        self
+    }
+
+    /// Honor forwarded headers from the proxy at `addr`, instead of only
+    /// from loopback
+    pub fn trusted_proxy(mut self, addr: IpAddr) -> Self {
+        self.trusted_proxies.push(addr);
+        self
+    }
+
+    /// Enable or disable the MPRIS player on the session bus
+    pub fn mpris(mut self, enabled: bool) -> Self {
+        self.mpris = enabled;
+        self
+    }
+
+    /// Enable or disable mDNS advertisement
+    pub fn mdns(mut self, enabled: bool) -> Self {
+        self.mdns = enabled;
+        self
+    }
+
+    /// Offer XOR parity FEC to clients that request it, with at most
+    /// `max_group_size` chunks per parity frame
+    pub fn fec(mut self, max_group_size: u8) -> Self {
+        self.fec_max_group_size = Some(max_group_size);
+        self
+    }
+
+    /// Log a warning when a group's members drift more than `micros` apart
+    pub fn sync_warning(mut self, micros: i64) -> Self {
+        self.sync_warn_micros = Some(micros);
+        self
+    }
+
+    /// Set the levels used to fold multichannel sources down to stereo
+    pub fn downmix(mut self, levels: DownmixLevels) -> Self {
+        self.downmix = levels;
+        self
+    }
+
+    /// Cache finite HTTP sources on disk so repeat plays skip the download
+    pub fn url_cache(mut self, cache: UrlCache) -> Self {
+        self.url_cache = Some(cache);
+        self
+    }
+
+    /// Keep a disconnected client's group and volume for `grace` so a quick
+    /// reconnect resumes them (zero disables resuming)
+    pub fn reconnect_grace(mut self, grace: Duration) -> Self {
+        self.reconnect_grace = grace;
+        self
+    }
+
+    /// Close connections that have not sent `client/hello` within `timeout`
+    pub fn handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = timeout;
+        self
+    }
+
+    /// Restrict which roles clients may take
+    pub fn role_limits(mut self, limits: RoleLimits) -> Self {
+        self.role_limits = limits;
+        self
+    }
+
+    /// Close connections whose messages exceed `limits`
+    pub fn inbound_limits(mut self, limits: InboundLimits) -> Self {
+        self.inbound_limits = limits;
+        self
+    }
+
+    /// Fail over to a fallback source while the playing one is exhausted or
+    /// underrunning, switching back once it can be reopened
+    pub fn source_fallback(mut self, fallback: FallbackConfig) -> Self {
+        self.source_fallback = Some(fallback);
+        self
+    }
+
+    /// Trim leading encoder/decoder silence from each new track
+    pub fn silence_trim(mut self, trim: SilenceTrim) -> Self {
+        self.silence_trim = Some(trim);
+        self
+    }
+
+    /// Normalize each source's EBU R128 loudness toward a target
+    pub fn loudness_normalization(mut self, settings: LoudnessNormalization) -> Self {
+        self.loudness = Some(settings);
+        self
+    }
+
+    /// Play files at the level their ReplayGain or R128 gain tags call for
+    pub fn replay_gain(mut self, settings: ReplayGain) -> Self {
+        self.replay_gain = Some(settings);
+        self
+    }
+
+    /// Record each chunk's generation, send, and play-at times to `path`
+    ///
+    /// A debugging aid; analyse the file with `sendspin-ctl audit`.
+    pub fn chunk_audit(mut self, path: impl Into<PathBuf>) -> Self {
+        self.chunk_audit = Some(path.into());
+        self
+    }
+
+    /// Keep the playback history in `path`, so it survives restarts
+    pub fn history_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.history_file = Some(path.into());
+        self
+    }
+
+    /// Keep up to `tracks` tracks in the playback history
+    pub fn history_size(mut self, tracks: usize) -> Self {
+        self.history_size = tracks;
+        self
+    }
+
+    /// Run as a warm standby mirroring the primary's groups, clients, and source
+    pub fn replicate_from(mut self, replication: ReplicationConfig) -> Self {
+        self.replication = Some(replication);
+        self
+    }
+
+    /// Set the order in which codecs are preferred when a client supports several
+    pub fn codec_preference(mut self, preference: impl IntoIterator<Item = Codec>) -> Self {
+        self.codec_policy.preference = preference.into_iter().collect();
+        self
+    }
+
+    /// Set the limits for a codec (max sample rate)
+    pub fn codec_constraints(mut self, codec: Codec, constraints: CodecConstraints) -> Self {
+        self.codec_policy = self.codec_policy.constrain(codec, constraints);
+        self
+    }
+
+    /// Keep client volume, mute, name and group in a store in `dir` across restarts
+    pub fn state_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.state_dir = Some(dir.into());
+        self
+    }
+
+    /// Scrobble plays to a Last.fm or ListenBrainz account
+    pub fn scrobbler(mut self, scrobbler: ScrobblerConfig) -> Self {
+        self.scrobblers.push(scrobbler);
+        self
+    }
+
+    /// Create a group at startup
+    pub fn group(mut self, definition: GroupDefinition) -> Self {
+        self.groups.push(definition);
+        self
+    }
+
+    /// Watch the config file at `path`, applying changes that are safe while running
+    pub fn config_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config_file = Some(path.into());
+        self
+    }
+
+    /// Start an extra stream playing `uri` that groups can be subscribed to
+    pub fn stream(mut self, id: impl Into<String>, uri: impl Into<String>) -> Self {
+        self.streams.push(StreamDefinition {
+            id: id.into(),
+            uri: uri.into(),
+        });
+        self
+    }
+
+    /// Pin a client to a codec whenever it supports it
+    pub fn codec_override(mut self, client_id: impl Into<String>, pin: CodecOverride) -> Self {
+        self.codec_overrides.insert(client_id.into(), pin);
+        self
+    }
+
+    /// Tag a client with the link tier it is on
+    pub fn link_tier(mut self, client_id: impl Into<String>, tier: LinkTier) -> Self {
+        self.link_tiers.insert(client_id.into(), tier);
+        self
+    }
+
+    /// Play players on a link tier at least `ms` ahead, whatever their group's buffer-ahead
+    pub fn tier_buffer_ahead(mut self, tier: LinkTier, ms: u64) -> Self {
+        self.tier_buffer_ahead.insert(tier, ms);
+        self
+    }
+
+    /// Set the encoder tuning used by groups without an override
+    pub fn encoder_settings(mut self, settings: EncoderSettings) -> Self {
+        self.encoder_settings = settings;
+        self
+    }
+
+    /// Encoder tuning for a stream, applying a group's override over the
+    /// server defaults
+    ///
+    /// Anything still unset falls back to the encoders' built-in defaults.
+    pub fn encoder_settings_for(&self, group: Option<EncoderSettings>) -> EncoderSettings {
+        group
+            .unwrap_or_default()
+            .or(self.encoder_settings)
+            .or(EncoderSettings::defaults())
+    }
+
+    /// Full path of the WebSocket endpoint, including the prefix
+    pub fn ws_route(&self) -> String {
+        format!("{}{}", self.path_prefix, self.ws_path)
+    }
+
+    /// WebSocket URL clients should use to reach this server
+    pub fn advertised_url(&self) -> String {
+        self.public_url
+            .clone()
+            .unwrap_or_else(|| format!("ws://{}{}", self.bind_addr, self.ws_route()))
+    }
+
+    /// Look up the initial volume for a client, falling back to the `*` entry
+    pub fn initial_volume_for(&self, client_id: &str) -> Option<InitialVolume> {
+        self.initial_volumes
+            .get(client_id)
+            .or_else(|| self.initial_volumes.get(ANY_CLIENT))
+            .copied()
+    }
 }
 
 impl Default for ServerConfig {
@@ -72,6 +543,49 @@ impl Default for ServerConfig {
             default_sample_rate: 48000,
             default_channels: 2,
             default_bit_depth: 24,
+            fixed_sample_rate: None,
+            fixed_bit_depth: None,
+            idle_standby: true,
+            auto_start: AutoStart::default(),
+            adaptive_buffer: None,
+            max_volumes: HashMap::new(),
+            initial_volumes: HashMap::new(),
+            mono_clients: HashSet::new(),
+            channel_maps: HashMap::new(),
+            control_api: false,
+            api_keys: Vec::new(),
+            path_prefix: String::new(),
+            public_url: None,
+            trust_forwarded: false,
+            trusted_proxies: Vec::new(),
+            mpris: false,
+            mdns: true,
+            fec_max_group_size: None,
+            codec_policy: CodecPolicy::default(),
+            codec_overrides: HashMap::new(),
+            link_tiers: HashMap::new(),
+            tier_buffer_ahead: HashMap::new(),
+            sync_warn_micros: None,
+            downmix: DownmixLevels::default(),
+            url_cache: None,
+            reconnect_grace: DEFAULT_RECONNECT_GRACE,
+            handshake_timeout: Duration::from_secs(10),
+            inbound_limits: InboundLimits::default(),
+            role_limits: RoleLimits::default(),
+            encoder_settings: EncoderSettings::default(),
+            source_fallback: None,
+            silence_trim: None,
+            loudness: None,
+            replay_gain: None,
+            chunk_audit: None,
+            history_file: None,
+            history_size: DEFAULT_HISTORY_SIZE,
+            replication: None,
+            state_dir: None,
+            scrobblers: Vec::new(),
+            groups: Vec::new(),
+            config_file: None,
+            streams: Vec::new(),
         }
     }
 }
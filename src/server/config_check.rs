@@ -0,0 +1,339 @@
+// ABOUTME: Dry-run validation of a server configuration for `--check-config`
+// ABOUTME: Probes sources, codecs, and output paths without binding any ports
+
+use crate::audio::types::Codec;
+use crate::server::audio_source::{AudioSource, FileSource, UrlSource};
+use crate::server::config::ServerConfig;
+use crate::server::encoder::{EncoderParams, EncoderRegistry};
+use crate::server::format_probe::plan_conversion;
+use crate::server::icy::ICY_METADATA_HEADER;
+use crate::server::source_fallback::Fallback;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::path::Path;
+use std::time::Duration;
+
+/// How long a URL source may take to connect or send data while probed
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How serious a [`Finding`] is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// The server starts, but not as configured
+    Warning,
+    /// The server fails to start or a declared source cannot play
+    Error,
+}
+
+/// One problem found in a configuration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    /// How serious the problem is
+    pub severity: Severity,
+    /// What the problem is about, such as a source URI or config key
+    pub subject: String,
+    /// What is wrong
+    pub message: String,
+}
+
+/// Result of checking a configuration
+#[derive(Debug, Clone, Default)]
+pub struct ConfigReport {
+    /// Sources that were opened, with their sample rate and channel count
+    pub sources: Vec<(String, u32, u8)>,
+    /// Problems found, in the order they were found
+    pub findings: Vec<Finding>,
+}
+
+impl ConfigReport {
+    /// Whether nothing found would stop the server from starting as configured
+    pub fn is_ok(&self) -> bool {
+        !self.findings.iter().any(|f| f.severity == Severity::Error)
+    }
+
+    /// Record an error about `subject`
+    pub fn error(&mut self, subject: impl Into<String>, message: impl Into<String>) {
+        self.push(Severity::Error, subject.into(), message.into());
+    }
+
+    /// Record a warning about `subject`
+    pub fn warning(&mut self, subject: impl Into<String>, message: impl Into<String>) {
+        self.push(Severity::Warning, subject.into(), message.into());
+    }
+
+    fn push(&mut self, severity: Severity, subject: String, message: String) {
+        self.findings.push(Finding {
+            severity,
+            subject,
+            message,
+        });
+    }
+}
+
+impl fmt::Display for ConfigReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (uri, sample_rate, channels) in &self.sources {
+            writeln!(
+                f,
+                "ok      {} ({}Hz, {} channels)",
+                uri, sample_rate, channels
+            )?;
+        }
+        for finding in &self.findings {
+            let label = match finding.severity {
+                Severity::Warning => "warning",
+                Severity::Error => "error",
+            };
+            writeln!(f, "{:<7} {}: {}", label, finding.subject, finding.message)?;
+        }
+        let errors = self
+            .findings
+            .iter()
+            .filter(|f| f.severity == Severity::Error)
+            .count();
+        let warnings = self.findings.len() - errors;
+        write!(f, "{} error(s), {} warning(s)", errors, warnings)
+    }
+}
+
+/// Check `config` and the main `source` URI (None for the test tone)
+///
+/// Opens every declared source — the main one, extra streams, and a URI
+/// fallback — and checks that each fits a fixed output format, that preferred
+/// and pinned codecs can encode at their sample rates, and that directories
+/// for state and log files exist. URL sources are probed without the
+/// download cache, so nothing is written.
+pub fn check_server_config(source: Option<&str>, config: &ServerConfig) -> ConfigReport {
+    let mut report = ConfigReport::default();
+    let mut rates = BTreeSet::new();
+
+    let fallback = config
+        .source_fallback
+        .as_ref()
+        .and_then(|f| match &f.fallback {
+            Fallback::Uri(uri) => Some(uri.as_str()),
+            Fallback::Silence | Fallback::Tone(_) => None,
+        });
+    let uris = source
+        .into_iter()
+        .chain(config.streams.iter().map(|s| s.uri.as_str()))
+        .chain(fallback);
+    for uri in uris {
+        match probe_source(uri) {
+            Ok((sample_rate, channels)) => {
+                rates.insert(sample_rate);
+                report
+                    .sources
+                    .push((uri.to_string(), sample_rate, channels));
+                if let Err(e) = plan_conversion(sample_rate, channels, config) {
+                    report.error(uri, e);
+                }
+            }
+            Err(e) => report.error(uri, e),
+        }
+    }
+    if source.is_none() {
+        rates.insert(config.default_sample_rate);
+    }
+
+    check_codecs(config, &rates, &mut report);
+
+    let files = [
+        ("history_file", config.history_file.as_deref()),
+        ("chunk_audit", config.chunk_audit.as_deref()),
+    ];
+    for (key, path) in files {
+        if let Some(parent) = path.and_then(Path::parent) {
+            check_dir(key, parent, &mut report);
+        }
+    }
+    let dirs = [
+        ("state_dir", config.state_dir.as_deref()),
+        ("url_cache_dir", config.url_cache.as_ref().map(|c| c.dir())),
+    ];
+    for (key, dir) in dirs {
+        if let Some(dir) = dir {
+            check_dir(key, dir, &mut report);
+        }
+    }
+
+    if let Some(Err(e)) = config.loudness.map(|loudness| loudness.validate()) {
+        report.error("loudness", e);
+    }
+    if let Some(Err(e)) = config.replay_gain.map(|replay_gain| replay_gain.validate()) {
+        report.error("replay_gain", e);
+    }
+
+    if let Some(url) = &config.public_url {
+        if !url.starts_with("ws://") && !url.starts_with("wss://") {
+            report.error(
+                "public_url",
+                format!("'{}' is not a ws:// or wss:// URL", url),
+            );
+        }
+    }
+    report
+}
+
+/// Open a source as the server would, returning its sample rate and channels
+fn probe_source(uri: &str) -> Result<(u32, u8), String> {
+    if uri.starts_with("http://") || uri.starts_with("https://") {
+        let response = ureq::AgentBuilder::new()
+            .timeout_connect(PROBE_TIMEOUT)
+            .timeout_read(PROBE_TIMEOUT)
+            .build()
+            .get(uri)
+            .set(ICY_METADATA_HEADER, "1")
+            .call()
+            .map_err(|e| format!("HTTP request failed: {}", e))?;
+        let source = UrlSource::from_response(uri, response).map_err(|e| e.to_string())?;
+        return Ok((source.sample_rate(), source.channels()));
+    }
+    let path = uri.strip_prefix("file://").unwrap_or(uri);
+    if !Path::new(path).is_file() {
+        return Err("file not found".to_string());
+    }
+    let source = FileSource::new(path).map_err(|e| e.to_string())?;
+    Ok((source.sample_rate(), source.channels()))
+}
+
+/// Warn about preferred or pinned codecs that cannot encode the sources
+///
+/// Clients negotiate another codec they support instead, so these are not fatal.
+fn check_codecs(config: &ServerConfig, rates: &BTreeSet<u32>, report: &mut ConfigReport) {
+    let registry = EncoderRegistry::default();
+    let mut codecs: Vec<(String, Codec)> = config
+        .codec_policy
+        .preference
+        .iter()
+        .map(|&codec| ("codec_preference".to_string(), codec))
+        .collect();
+    let mut pinned: Vec<_> = config.codec_overrides.iter().collect();
+    pinned.sort_by_key(|(client_id, _)| client_id.as_str());
+    codecs.extend(
+        pinned
+            .into_iter()
+            .map(|(client_id, pin)| (format!("codec_overrides.{}", client_id), pin.codec)),
+    );
+
+    for (subject, codec) in codecs {
+        if !registry.contains(codec.name()) {
+            report.warning(subject, format!("no encoder for {}", codec.name()));
+            continue;
+        }
+        for &sample_rate in rates {
+            let params = EncoderParams {
+                sample_rate,
+                channels: config.default_channels,
+                bit_depth: config.default_bit_depth,
+                chunk_frames: (sample_rate as u64 * config.chunk_interval_ms / 1000) as usize,
+                settings: config.encoder_settings,
+            };
+            if let Err(e) = registry.create(codec.name(), params) {
+                let message = format!("{} cannot encode {}Hz: {}", codec.name(), sample_rate, e);
+                report.warning(subject.clone(), message);
+            }
+        }
+    }
+}
+
+fn check_dir(key: &str, dir: &Path, report: &mut ConfigReport) {
+    // A bare file name has an empty parent: the working directory
+    if dir.as_os_str().is_empty() || dir.is_dir() {
+        return;
+    }
+    if dir.exists() {
+        report.error(key, format!("{} is not a directory", dir.display()));
+    } else {
+        report.warning(key, format!("{} does not exist yet", dir.display()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("sendspin-check-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_wav(path: &Path, sample_rate: u32) {
+        let frames = sample_rate / 10;
+        let data_len = frames * 4;
+        let mut wav = Vec::new();
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&2u16.to_le_bytes());
+        wav.extend_from_slice(&sample_rate.to_le_bytes());
+        wav.extend_from_slice(&(sample_rate * 4).to_le_bytes());
+        wav.extend_from_slice(&4u16.to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&data_len.to_le_bytes());
+        wav.resize(wav.len() + data_len as usize, 0);
+        std::fs::write(path, wav).unwrap();
+    }
+
+    #[test]
+    fn test_readable_file_passes() {
+        let dir = temp_dir("ok");
+        let path = dir.join("song.wav");
+        write_wav(&path, 48000);
+        let config = ServerConfig::default().history_file(dir.join("history.json"));
+
+        let report = check_server_config(path.to_str(), &config);
+        assert!(report.is_ok(), "{}", report);
+        assert_eq!(report.sources[0].1, 48000);
+        assert!(report.findings.is_empty(), "{}", report);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_missing_sources_are_errors() {
+        let mut config = ServerConfig::default().public_url("http://example.com/sendspin");
+        config.streams.push(crate::server::StreamDefinition {
+            id: "radio".to_string(),
+            // Nothing listens on the discard port
+            uri: "http://127.0.0.1:9/stream.mp3".to_string(),
+        });
+
+        let report = check_server_config(Some("/no/such/file.flac"), &config);
+        assert!(!report.is_ok());
+        let subjects: Vec<&str> = report.findings.iter().map(|f| f.subject.as_str()).collect();
+        assert_eq!(
+            subjects,
+            [
+                "/no/such/file.flac",
+                "http://127.0.0.1:9/stream.mp3",
+                "public_url"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_codec_that_cannot_encode_is_a_warning() {
+        let dir = temp_dir("codecs");
+        let path = dir.join("song.wav");
+        write_wav(&path, 44100);
+        let config = ServerConfig::default()
+            .codec_preference([Codec::Opus, Codec::Mp3, Codec::Pcm])
+            .state_dir(dir.join("missing"));
+
+        let report = check_server_config(path.to_str(), &config);
+        assert!(report.is_ok(), "{}", report);
+        let warnings: Vec<&str> = report.findings.iter().map(|f| f.subject.as_str()).collect();
+        assert_eq!(
+            warnings,
+            ["codec_preference", "codec_preference", "state_dir"]
+        );
+        assert_eq!(report.findings[0].message, "no encoder for opus");
+        assert!(report.to_string().ends_with("0 error(s), 3 warning(s)"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,505 @@
+// ABOUTME: Server settings loaded from a TOML config file
+// ABOUTME: Watches the file and applies name, buffer-ahead and group changes while running
+
+use crate::audio::downmix::ChannelMap;
+use crate::audio::types::Codec;
+use crate::server::config::ServerConfig;
+use crate::server::control_api::Permission;
+use crate::server::group::{AutoStart, GroupDefinition, GroupManager};
+use crate::server::link_tier::LinkTier;
+use crate::server::mdns::MdnsAdvertisement;
+use crate::server::playback::PlaybackController;
+use parking_lot::RwLock;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// How often the config file is checked for changes
+pub const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// An API key in the config file
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigApiKey {
+    /// Bearer token
+    pub key: String,
+    /// What the key may do (defaults to control)
+    #[serde(default = "control_permission")]
+    pub permission: Permission,
+}
+
+fn control_permission() -> Permission {
+    Permission::Control
+}
+
+/// Settings read from a TOML config file
+///
+/// Every setting is optional and overrides the matching command-line option.
+/// `name`, `buffer_ahead_ms` and `groups` are applied to a running server
+/// when the file changes; the rest take effect on the next start.
+///
+/// ```toml
+/// name = "Living Room"
+/// source = "/srv/music/radio.flac"
+/// buffer_ahead_ms = 800
+///
+/// [[groups]]
+/// id = "upstairs"
+/// name = "Upstairs"
+/// auto_start = "never"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    /// Server name shown to clients
+    pub name: Option<String>,
+    /// Address to listen on
+    pub bind: Option<SocketAddr>,
+    /// Audio file path or HTTP(S) URL to stream
+    pub source: Option<String>,
+    /// Audio chunk interval in milliseconds
+    pub chunk_interval_ms: Option<u64>,
+    /// How far ahead of playback audio is sent, in milliseconds
+    pub buffer_ahead_ms: Option<u64>,
+    /// Stop encoding while no group is playing
+    pub idle_standby: Option<bool>,
+    /// Default auto-start policy for groups
+    pub auto_start: Option<AutoStart>,
+    /// Serve the HTTP control API
+    pub control_api: Option<bool>,
+    /// Keys accepted by the control API
+    #[serde(default)]
+    pub api_keys: Vec<ConfigApiKey>,
+    /// Path prefix when served behind a reverse proxy
+    pub path_prefix: Option<String>,
+    /// URL advertised to clients
+    pub public_url: Option<String>,
+    /// Advertise the server via mDNS
+    pub mdns: Option<bool>,
+    /// Volume ceiling per client ID, in percent
+    #[serde(default)]
+    pub max_volumes: HashMap<String, u8>,
+    /// Clients that receive a mono mix
+    #[serde(default)]
+    pub mono: Vec<String>,
+    /// Channel map per client ID (stereo, mono, left, right, swap)
+    #[serde(default)]
+    pub channel_maps: HashMap<String, ChannelMap>,
+    /// Link tier per client ID
+    #[serde(default)]
+    pub link_tiers: HashMap<String, LinkTier>,
+    /// Buffer-ahead per link tier, in milliseconds
+    #[serde(default)]
+    pub tier_buffer_ahead_ms: HashMap<LinkTier, u64>,
+    /// Codecs in order of preference
+    #[serde(default)]
+    pub codec_preference: Vec<String>,
+    /// File the playback history is kept in
+    pub history_file: Option<PathBuf>,
+    /// Tracks kept in the playback history
+    pub history_size: Option<usize>,
+    /// Groups to create
+    #[serde(default)]
+    pub groups: Vec<GroupDefinition>,
+}
+
+impl ConfigFile {
+    /// Parse and validate config file contents
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let file: Self = toml::from_str(text).map_err(|e| e.to_string())?;
+        file.validate()?;
+        Ok(file)
+    }
+
+    /// Read and parse a config file
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        Self::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        self.codecs()?;
+        if let Some((client_id, percent)) = self.max_volumes.iter().find(|(_, p)| **p > 100) {
+            return Err(format!(
+                "max volume {} for {} is over 100",
+                percent, client_id
+            ));
+        }
+        let mut ids = HashSet::new();
+        for group in &self.groups {
+            if group.id.is_empty() {
+                return Err("group id must not be empty".to_string());
+            }
+            if !ids.insert(&group.id) {
+                return Err(format!("group {} is defined twice", group.id));
+            }
+        }
+        Ok(())
+    }
+
+    fn codecs(&self) -> Result<Vec<Codec>, String> {
+        self.codec_preference
+            .iter()
+            .map(|name| Codec::from_name(name).ok_or_else(|| format!("unknown codec '{}'", name)))
+            .collect()
+    }
+
+    /// Apply these settings over `config`
+    pub fn apply(&self, mut config: ServerConfig) -> Result<ServerConfig, String> {
+        if let Some(name) = &self.name {
+            config.name = name.clone();
+        }
+        if let Some(bind) = self.bind {
+            config = config.bind_addr(bind);
+        }
+        if let Some(ms) = self.chunk_interval_ms {
+            config = config.chunk_interval_ms(ms);
+        }
+        if let Some(ms) = self.buffer_ahead_ms {
+            config = config.buffer_ahead_ms(ms);
+        }
+        if let Some(enabled) = self.idle_standby {
+            config = config.idle_standby(enabled);
+        }
+        if let Some(policy) = self.auto_start {
+            config = config.auto_start(policy);
+        }
+        if let Some(enabled) = self.control_api {
+            config = config.control_api(enabled);
+        }
+        for key in &self.api_keys {
+            config = config.api_key(key.key.clone(), key.permission);
+        }
+        if let Some(prefix) = &self.path_prefix {
+            config = config.path_prefix(prefix);
+        }
+        if let Some(url) = &self.public_url {
+            config = config.public_url(url.clone());
+        }
+        if let Some(enabled) = self.mdns {
+            config = config.mdns(enabled);
+        }
+        for (client_id, percent) in &self.max_volumes {
+            config = config.max_volume(client_id, *percent);
+        }
+        for client_id in &self.mono {
+            config = config.mono(client_id);
+        }
+        for (client_id, map) in &self.channel_maps {
+            config = config.channel_map(client_id, *map);
+        }
+        for (client_id, tier) in &self.link_tiers {
+            config = config.link_tier(client_id, *tier);
+        }
+        for (tier, ms) in &self.tier_buffer_ahead_ms {
+            config = config.tier_buffer_ahead(*tier, *ms);
+        }
+        let codecs = self.codecs()?;
+        if !codecs.is_empty() {
+            config = config.codec_preference(codecs);
+        }
+        if let Some(path) = &self.history_file {
+            config = config.history_file(path);
+        }
+        if let Some(tracks) = self.history_size {
+            config = config.history_size(tracks);
+        }
+        if !self.groups.is_empty() {
+            config.groups = self.groups.clone();
+        }
+        Ok(config)
+    }
+
+    /// Settings that differ from `other` and only take effect after a restart
+    pub fn restart_required(&self, other: &Self) -> Vec<&'static str> {
+        let changed = [
+            ("bind", self.bind != other.bind),
+            ("source", self.source != other.source),
+            (
+                "chunk_interval_ms",
+                self.chunk_interval_ms != other.chunk_interval_ms,
+            ),
+            ("idle_standby", self.idle_standby != other.idle_standby),
+            ("auto_start", self.auto_start != other.auto_start),
+            ("control_api", self.control_api != other.control_api),
+            ("api_keys", self.api_keys != other.api_keys),
+            ("path_prefix", self.path_prefix != other.path_prefix),
+            ("public_url", self.public_url != other.public_url),
+            ("mdns", self.mdns != other.mdns),
+            ("max_volumes", self.max_volumes != other.max_volumes),
+            ("mono", self.mono != other.mono),
+            ("channel_maps", self.channel_maps != other.channel_maps),
+            ("link_tiers", self.link_tiers != other.link_tiers),
+            (
+                "tier_buffer_ahead_ms",
+                self.tier_buffer_ahead_ms != other.tier_buffer_ahead_ms,
+            ),
+            (
+                "codec_preference",
+                self.codec_preference != other.codec_preference,
+            ),
+            ("history_file", self.history_file != other.history_file),
+            ("history_size", self.history_size != other.history_size),
+        ];
+        changed
+            .into_iter()
+            .filter(|(_, changed)| *changed)
+            .map(|(field, _)| field)
+            .collect()
+    }
+}
+
+/// The server configuration in effect, replaced when the config file changes
+///
+/// Readers take a snapshot with [`current`](Self::current); a reload swaps in
+/// a new configuration without affecting snapshots already taken.
+#[derive(Debug, Clone)]
+pub struct LiveConfig(Arc<RwLock<Arc<ServerConfig>>>);
+
+impl LiveConfig {
+    /// Start from `config`
+    pub fn new(config: Arc<ServerConfig>) -> Self {
+        Self(Arc::new(RwLock::new(config)))
+    }
+
+    /// The configuration in effect now
+    pub fn current(&self) -> Arc<ServerConfig> {
+        self.0.read().clone()
+    }
+
+    /// Put a new configuration into effect
+    pub fn replace(&self, config: ServerConfig) {
+        *self.0.write() = Arc::new(config);
+    }
+}
+
+/// Re-reads the config file and applies what can change while running
+pub struct ConfigReloader {
+    path: PathBuf,
+    file: ConfigFile,
+    modified: Option<SystemTime>,
+    live: LiveConfig,
+    group_manager: Arc<GroupManager>,
+    playback: PlaybackController,
+    mdns: Option<(MdnsAdvertisement, u16)>,
+}
+
+impl ConfigReloader {
+    /// Watch `path`, whose contents `file` the server started with
+    pub fn new(
+        path: impl Into<PathBuf>,
+        file: ConfigFile,
+        live: LiveConfig,
+        group_manager: Arc<GroupManager>,
+        playback: PlaybackController,
+    ) -> Self {
+        let path = path.into();
+        Self {
+            modified: modified(&path),
+            path,
+            file,
+            live,
+            group_manager,
+            playback,
+            mdns: None,
+        }
+    }
+
+    /// Re-advertise under the new name when the server is renamed
+    pub fn with_mdns(mut self, advertisement: MdnsAdvertisement, port: u16) -> Self {
+        self.mdns = Some((advertisement, port));
+        self
+    }
+
+    /// Reload the file if it was modified since the last check
+    ///
+    /// Returns whether any setting changed. A file that fails to parse is
+    /// reported and leaves the running configuration alone.
+    pub fn poll(&mut self) -> Result<bool, String> {
+        let modified = modified(&self.path);
+        if modified == self.modified {
+            return Ok(false);
+        }
+        self.modified = modified;
+        self.reload()
+    }
+
+    /// Reload the file and apply the changes, returning whether anything changed
+    pub fn reload(&mut self) -> Result<bool, String> {
+        let file = ConfigFile::load(&self.path)?;
+        if file == self.file {
+            return Ok(false);
+        }
+        for field in file.restart_required(&self.file) {
+            log::warn!(
+                "Config setting '{}' changed; restart the server to apply it",
+                field
+            );
+        }
+
+        let mut config = (*self.live.current()).clone();
+        let renamed = file.name.as_ref().filter(|name| **name != config.name);
+        if let Some(name) = renamed {
+            log::info!("Server renamed to {}", name);
+            config.name = name.clone();
+            if let Some((advertisement, port)) = self.mdns.take() {
+                drop(advertisement);
+                self.mdns = MdnsAdvertisement::start(&config, port)
+                    .map_err(|e| log::warn!("mDNS advertisement unavailable: {}", e))
+                    .ok()
+                    .map(|advertisement| (advertisement, port));
+            }
+        }
+        if let Some(ms) = file.buffer_ahead_ms {
+            config.buffer_ahead_ms = ms;
+            self.playback.set_default_buffer_ahead(ms);
+        }
+        self.apply_groups(&file);
+        config.groups = file.groups.clone();
+        self.live.replace(config);
+        self.file = file;
+        Ok(true)
+    }
+
+    /// Create and update declared groups, deleting those no longer declared
+    fn apply_groups(&self, file: &ConfigFile) {
+        for definition in &file.groups {
+            // Renaming notifies the members; define_group then finds the name set
+            let name = definition.name.as_deref().unwrap_or(&definition.id);
+            self.playback.rename_group(&definition.id, name);
+            self.group_manager.define_group(definition);
+            self.playback
+                .set_buffer_ahead(&definition.id, definition.buffer_ahead_ms);
+        }
+        for removed in &self.file.groups {
+            if file.groups.iter().all(|g| g.id != removed.id) {
+                let moved = self.playback.delete_group(&removed.id);
+                log::info!(
+                    "Group {} removed from config; {} member(s) moved to the default group",
+                    removed.id,
+                    moved.len()
+                );
+            }
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Check the config file for changes every `interval`
+pub fn spawn_config_watcher(
+    mut reloader: ConfigReloader,
+    interval: Duration,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        loop {
+            ticker.tick().await;
+            match reloader.poll() {
+                Ok(true) => log::info!("Reloaded {}", reloader.path.display()),
+                Ok(false) => {}
+                Err(e) => log::warn!("Keeping current configuration: {}", e),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::client_manager::ClientManager;
+
+    #[test]
+    fn test_parse_and_apply() {
+        let file = ConfigFile::parse(
+            r#"
+            name = "Kitchen"
+            bind = "127.0.0.1:9000"
+            buffer_ahead_ms = 800
+            codec_preference = ["flac", "opus"]
+            max_volumes = { kids = 60 }
+            link_tiers = { porch = "bluetooth" }
+            channel_maps = { den_left = "left", den_right = "right" }
+            tier_buffer_ahead_ms = { bluetooth = 2000 }
+
+            [[api_keys]]
+            key = "viewer"
+            permission = "read"
+
+            [[groups]]
+            id = "upstairs"
+            auto_start = "never"
+            "#,
+        )
+        .unwrap();
+        let config = file.apply(ServerConfig::new("Default")).unwrap();
+        assert_eq!(config.name, "Kitchen");
+        assert_eq!(config.bind_addr.port(), 9000);
+        assert_eq!(config.buffer_ahead_ms, 800);
+        assert_eq!(config.max_volumes["kids"], 60);
+        assert_eq!(config.link_tiers["porch"], LinkTier::Bluetooth);
+        assert_eq!(config.channel_maps["den_right"], ChannelMap::Right);
+        assert_eq!(config.tier_buffer_ahead[&LinkTier::Bluetooth], 2000);
+        assert_eq!(config.api_keys[0].permission, Permission::Read);
+        assert_eq!(config.groups[0].auto_start, Some(AutoStart::Never));
+
+        assert!(ConfigFile::parse("nmae = \"typo\"").is_err());
+        assert!(ConfigFile::parse("codec_preference = [\"wav\"]").is_err());
+        assert!(ConfigFile::parse("[[groups]]\nid = \"a\"\n[[groups]]\nid = \"a\"").is_err());
+    }
+
+    #[test]
+    fn test_restart_required() {
+        let before = ConfigFile::parse("name = \"A\"\nbind = \"0.0.0.0:8927\"").unwrap();
+        let after = ConfigFile::parse("name = \"B\"\nbind = \"0.0.0.0:9000\"").unwrap();
+        assert_eq!(after.restart_required(&before), vec!["bind"]);
+        assert!(before.restart_required(&before).is_empty());
+    }
+
+    #[test]
+    fn test_reload_applies_safe_changes() {
+        let dir = std::env::temp_dir().join(format!("sendspin-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("server.toml");
+        std::fs::write(&path, "name = \"Before\"\n[[groups]]\nid = \"old\"\n").unwrap();
+
+        let file = ConfigFile::load(&path).unwrap();
+        let config = file.apply(ServerConfig::default()).unwrap();
+        let live = LiveConfig::new(Arc::new(config));
+        let groups = Arc::new(GroupManager::new());
+        for group in &file.groups {
+            groups.define_group(group);
+        }
+        let playback = PlaybackController::new(Arc::new(ClientManager::new()), groups.clone());
+        let mut reloader = ConfigReloader::new(&path, file, live.clone(), groups.clone(), playback);
+
+        std::fs::write(
+            &path,
+            "name = \"After\"\nbuffer_ahead_ms = 900\n\
+             [[groups]]\nid = \"new\"\nname = \"New\"\nbuffer_ahead_ms = 300\n",
+        )
+        .unwrap();
+        assert!(reloader.reload().unwrap());
+        assert_eq!(live.current().name, "After");
+        assert_eq!(live.current().buffer_ahead_ms, 900);
+        assert_eq!(groups.default_buffer_ahead(), Some(900));
+        assert_eq!(groups.get_group("new").unwrap().1, "New");
+        assert_eq!(groups.get_buffer_ahead("new"), Some(300));
+        assert!(groups.get_group("old").is_none());
+
+        // Unchanged or broken files leave the configuration alone
+        assert!(!reloader.reload().unwrap());
+        std::fs::write(&path, "name = 3").unwrap();
+        assert!(reloader.reload().is_err());
+        assert_eq!(live.current().name, "After");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
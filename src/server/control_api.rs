@@ -0,0 +1,1535 @@
+// ABOUTME: HTTP control API for the server
+// ABOUTME: JSON endpoints for inspecting and controlling groups and clients, guarded by API keys
+
+use crate::audio::downmix::ChannelMap;
+use crate::audio::drc::NightMode;
+use crate::audio::spatial::StereoWidth;
+use crate::audio::types::Codec;
+use crate::server::audio_source::{is_local_uri, open_source, AudioSource};
+use crate::server::buffer_health::BufferHealth;
+use crate::server::client_handler::negotiate_audio_format;
+use crate::server::client_manager::{ClientId, VolumeChange};
+use crate::server::codec_policy::CodecOverride;
+use crate::server::config::ServerConfig;
+use crate::server::encoder::EncoderSettings;
+use crate::server::encoder_metrics::EncoderStats;
+use crate::server::format_probe::plan_conversion;
+use crate::server::group_stats::GroupStats;
+use crate::server::history::{HistoryQuery, PlayRecord};
+use crate::server::link_tier::LinkTier;
+use crate::server::pipeline::{describe_pipeline, PipelineGraph};
+use crate::server::play_queue::QueueEntry;
+use crate::server::playback::PlaybackController;
+use crate::server::profiling::{folded_stacks, profiling_enabled, reset_profile};
+use crate::server::replication::{ReplicationSnapshot, REPLICATION_PATH};
+use crate::server::rtt_histogram::RttSummary;
+use crate::server::server::AppState;
+use crate::server::snapshot::{ServerSnapshot, SNAPSHOT_PATH};
+use crate::server::source_control::NowPlaying;
+use crate::server::stream_manager::{StreamInfo, DEFAULT_STREAM};
+use crate::server::transport_stats::TransportSnapshot;
+use axum::{
+    extract::{Path, Query, Request, State},
+    http::{header, HeaderMap, Method, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post, put},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+/// Header accepted as an alternative to `Authorization: Bearer <key>`
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// How long to wait for a client to answer a diagnostics request
+const DIAGNOSTICS_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// What an API key is allowed to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Permission {
+    /// Read-only access (GET requests)
+    Read,
+    /// Read access plus changing playback, volume, and groups
+    Control,
+}
+
+/// A key accepted by the control API
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiKey {
+    /// Secret sent by the caller as a bearer token or `X-API-Key` header
+    pub key: String,
+    /// Granted permission level
+    pub permission: Permission,
+}
+
+impl ApiKey {
+    /// Create an API key with the given permission
+    pub fn new(key: impl Into<String>, permission: Permission) -> Self {
+        Self {
+            key: key.into(),
+            permission,
+        }
+    }
+}
+
+/// A connected client as reported by the control API
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientInfo {
+    /// Client identifier
+    pub client_id: String,
+    /// Human-readable name
+    pub name: String,
+    /// Active roles
+    pub roles: Vec<String>,
+    /// Group the client belongs to
+    pub group_id: Option<String>,
+    /// Current volume (0-100)
+    pub volume: u8,
+    /// Whether the client is muted
+    pub muted: bool,
+    /// Highest volume the client may be set to
+    pub max_volume: u8,
+    /// Whether the server sums the client's audio to mono
+    pub mono: bool,
+    /// What the client's left and right channels carry
+    pub channel_map: ChannelMap,
+    /// Link tier the client is tagged with or was placed on
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub link_tier: Option<LinkTier>,
+    /// Latest reported buffer level in milliseconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub buffered_ms: Option<u32>,
+    /// Buffer state derived from recent reports
+    pub buffer_health: BufferHealth,
+    /// Percentiles of reported time-sync round-trip times
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rtt: Option<RttSummary>,
+    /// Milliseconds the client took to apply and acknowledge its last command
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub control_latency_ms: Option<f64>,
+    /// WebSocket backlog, rates and ping age of the connection
+    pub transport: TransportSnapshot,
+}
+
+/// A client's time-sync round-trip times as reported by the control API
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientRtt {
+    /// Client identifier
+    pub client_id: String,
+    /// Human-readable name
+    pub name: String,
+    /// RTT percentiles
+    #[serde(flatten)]
+    pub rtt: RttSummary,
+}
+
+/// Body of a volume change request
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct VolumeRequest {
+    /// New volume (0-100)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub volume: Option<u8>,
+    /// New mute state
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub muted: Option<bool>,
+}
+
+/// Body of a request changing several players' volume at once
+///
+/// Applies to every player, or to one group's players if `group_id` is set.
+/// `step` is applied after `volume`, and `muted` last.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BatchVolumeRequest {
+    /// Only change players in this group
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group_id: Option<String>,
+    /// Set every player to this volume (0-100)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub volume: Option<u8>,
+    /// Raise every player by this many points (lower if negative)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub step: Option<i16>,
+    /// Mute or unmute every player
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub muted: Option<bool>,
+}
+
+/// A player's volume after a volume change
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlayerVolume {
+    /// Client identifier
+    pub client_id: String,
+    /// Volume (0-100)
+    pub volume: u8,
+    /// Whether the player is muted
+    pub muted: bool,
+}
+
+/// Body of a request switching a client between stereo and mono
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MonoRequest {
+    /// Sum both channels to mono
+    pub enabled: bool,
+}
+
+/// Body of a request setting what a client's channels carry
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChannelMapRequest {
+    /// `stereo`, `mono`, `left`, `right` or `swap`
+    pub channel_map: ChannelMap,
+}
+
+/// Body of a request tagging a client with its link tier
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LinkTierRequest {
+    /// Tier the client is on (null clears the tag, leaving it to be placed from its RTTs)
+    pub tier: Option<LinkTier>,
+}
+
+/// Body of a request setting the buffer-ahead of a link tier
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TierBufferRequest {
+    /// Buffer-ahead in milliseconds (null plays the tier with its groups)
+    pub buffer_ahead_ms: Option<u64>,
+}
+
+/// Body of a request renaming a client
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NameRequest {
+    /// Name to show instead of the client's own (null reverts to it)
+    pub name: Option<String>,
+}
+
+/// Body of a request pinning a client to a codec
+///
+/// A missing or null codec clears the pin, returning the client to the
+/// server's codec policy.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CodecRequest {
+    /// Codec to stream to the client (flac or pcm)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub codec: Option<String>,
+    /// Encoder tuning for the client's stream, over its group's settings
+    #[serde(flatten)]
+    pub settings: EncoderSettings,
+}
+
+/// A client's codec as reported by the control API
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CodecInfo {
+    /// Client identifier
+    pub client_id: String,
+    /// Codec the client is pinned to, if any
+    pub pinned: Option<String>,
+    /// Encoder tuning pinned with the codec
+    pub settings: EncoderSettings,
+    /// Codec the client is streamed in, while connected
+    pub streaming: Option<String>,
+}
+
+/// Body of a request moving a client to another group
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MoveRequest {
+    /// Destination group
+    pub group_id: String,
+}
+
+/// Body of a request toggling a group's night mode
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NightModeRequest {
+    /// Compress the group's audio for quiet listening
+    pub enabled: bool,
+    /// Also attenuate bass (default true)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bass_cut: Option<bool>,
+}
+
+/// Body of a request setting a group's stereo width
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StereoWidthRequest {
+    /// Width from 0 (mono) through 1 (unchanged) to 2 (widest); null bypasses width processing
+    pub width: Option<f32>,
+    /// Keep bass below this frequency centered (Hz)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bass_mono_hz: Option<f32>,
+}
+
+/// Body of a request subscribing a group to a stream
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StreamRequest {
+    /// Stream to play (null returns the group to the default stream)
+    pub stream_id: Option<String>,
+}
+
+/// Body of a request starting a new stream
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewStreamRequest {
+    /// Unique stream identifier
+    pub stream_id: String,
+    /// File path, `file://` URI, or HTTP(S) URL
+    pub uri: String,
+}
+
+/// Body of a request changing the audio source
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourceRequest {
+    /// File path, `file://` URI, or HTTP(S) URL
+    pub uri: String,
+}
+
+/// Body of a request giving a group its own timeline or returning it to the shared one
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimelineRequest {
+    /// Run the group on its own timeline
+    pub independent: bool,
+    /// What the group's own timeline plays (default: what the group plays now)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uri: Option<String>,
+}
+
+/// Body of a request adding a track to a stream's play queue
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueueRequest {
+    /// File path, `file://` URI, or HTTP(S) URL
+    pub uri: String,
+}
+
+/// Body of a request moving a queued track
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueuePositionRequest {
+    /// New position (0 plays next)
+    pub position: usize,
+}
+
+/// What the server is streaming and to which groups
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NowPlayingInfo {
+    /// The engine's current source
+    #[serde(flatten)]
+    pub now_playing: NowPlaying,
+    /// Groups currently playing it
+    pub playing_groups: Vec<String>,
+}
+
+/// What importing a snapshot changed beyond groups and client settings
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SnapshotImport {
+    /// Whether the main source was replaced
+    pub source_replaced: bool,
+    /// Streams started from the snapshot
+    pub streams_started: Vec<String>,
+    /// Connected clients moved into their group from the snapshot
+    pub clients_moved: Vec<String>,
+    /// Sources that could not be opened, with the reason
+    pub failed_sources: Vec<String>,
+}
+
+/// A group's encoder tuning as reported by the control API
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncoderSettingsInfo {
+    /// Group identifier
+    pub group_id: String,
+    /// Whether the group overrides the server defaults
+    pub overridden: bool,
+    /// Settings in effect for the group's stream
+    pub settings: EncoderSettings,
+}
+
+/// Build the control API routes (mounted under `/api` by the server)
+pub(crate) fn router(state: AppState) -> Router<AppState> {
+    Router::new()
+        .route("/clients", get(list_clients))
+        .route("/clients/{client_id}/volume", put(set_volume))
+        .route("/clients/{client_id}/mono", put(set_mono))
+        .route("/clients/{client_id}/channel-map", put(set_channel_map))
+        .route("/clients/{client_id}/link-tier", put(set_link_tier))
+        .route("/clients/{client_id}/name", put(set_client_name))
+        .route("/clients/{client_id}/codec", get(get_codec).put(set_codec))
+        .route("/clients/{client_id}/group", put(move_client))
+        .route("/clients/{client_id}/diagnostics", get(client_diagnostics))
+        .route("/groups", get(list_groups))
+        .route("/link-tiers/{tier}", put(set_tier_buffer_ahead))
+        .route("/groups/{group_id}/source", put(set_source))
+        .route("/groups/{group_id}/timeline", put(set_timeline))
+        .route("/groups/{group_id}/merge", post(merge_groups))
+        .route(
+            "/groups/{group_id}/queue",
+            get(list_queue).post(enqueue).delete(clear_queue),
+        )
+        .route("/groups/{group_id}/queue/skip", post(skip_queued))
+        .route(
+            "/groups/{group_id}/queue/{entry_id}",
+            put(move_queued).delete(remove_queued),
+        )
+        .route("/groups/{group_id}/stream", put(set_group_stream))
+        .route("/groups/{group_id}/night-mode", put(set_night_mode))
+        .route("/groups/{group_id}/stereo-width", put(set_stereo_width))
+        .route("/groups/{group_id}/volume", put(set_group_volume))
+        .route("/volume", post(batch_volume))
+        .route(
+            "/groups/{group_id}/encoder",
+            get(get_encoder_settings).put(set_encoder_settings),
+        )
+        .route("/groups/{group_id}/pipeline", get(group_pipeline))
+        .route("/groups/{group_id}/{action}", post(group_action))
+        .route("/pipeline", get(list_pipelines))
+        .route("/now-playing", get(now_playing))
+        .route("/streams", get(list_streams).post(add_stream))
+        .route("/streams/{stream_id}", delete(remove_stream))
+        .route("/streams/{stream_id}/{action}", post(stream_action))
+        .route("/history", get(history))
+        .route("/metrics/encoders", get(encoder_metrics))
+        .route("/metrics/rtt", get(rtt_metrics))
+        .route("/metrics/profile", get(profile).delete(clear_profile))
+        .route(REPLICATION_PATH, get(replication_snapshot))
+        .route(SNAPSHOT_PATH, get(export_snapshot).put(import_snapshot))
+        .route_layer(middleware::from_fn_with_state(state, require_permission))
+}
+
+async fn require_permission(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let required = match *request.method() {
+        Method::GET | Method::HEAD => Permission::Read,
+        _ => Permission::Control,
+    };
+    match authorize(
+        request.headers(),
+        &state.config.current().api_keys,
+        required,
+    ) {
+        Ok(()) => next.run(request).await,
+        Err(StatusCode::UNAUTHORIZED) => (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+        )
+            .into_response(),
+        Err(status) => status.into_response(),
+    }
+}
+
+/// Check a request's credentials against the configured keys
+///
+/// With no keys configured the API is open. Otherwise a missing or unknown key
+/// is `401 Unauthorized` and a key without the required permission is
+/// `403 Forbidden`.
+pub fn authorize(
+    headers: &HeaderMap,
+    keys: &[ApiKey],
+    required: Permission,
+) -> Result<(), StatusCode> {
+    if keys.is_empty() {
+        return Ok(());
+    }
+
+    let presented = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .or_else(|| headers.get(API_KEY_HEADER).and_then(|v| v.to_str().ok()))
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    let key = keys
+        .iter()
+        .find(|k| constant_time_eq(k.key.as_bytes(), presented.trim().as_bytes()))
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    if key.permission >= required {
+        Ok(())
+    } else {
+        Err(StatusCode::FORBIDDEN)
+    }
+}
+
+/// Compare secrets without exiting early on the first mismatch
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+async fn list_clients(State(state): State<AppState>) -> Json<Vec<ClientInfo>> {
+    let mut clients = Vec::new();
+    state.client_manager.for_each(|client| {
+        clients.push(ClientInfo {
+            client_id: client.client_id.clone(),
+            name: client.name.clone(),
+            roles: client.active_roles.clone(),
+            group_id: None,
+            volume: client.volume,
+            muted: client.muted,
+            max_volume: client.max_volume,
+            mono: false,
+            channel_map: ChannelMap::Stereo,
+            link_tier: None,
+            buffered_ms: client.buffer_trend.level_ms(),
+            buffer_health: client.buffer_trend.health(),
+            rtt: client.rtt_histogram.summary(),
+            control_latency_ms: None,
+            transport: client.transport.snapshot(Instant::now()),
+        });
+    });
+    for client in &mut clients {
+        client.group_id = state.group_manager.get_client_group(&client.client_id);
+        client.channel_map = state.client_manager.channel_map(&client.client_id);
+        client.mono = client.channel_map == ChannelMap::Mono;
+        client.link_tier = state.client_manager.link_tier(&client.client_id);
+        client.control_latency_ms = (state.client_manager.traces())
+            .last_latency_micros(&client.client_id)
+            .map(|micros| micros as f64 / 1000.0);
+    }
+    clients.sort_by(|a, b| a.client_id.cmp(&b.client_id));
+    Json(clients)
+}
+
+async fn set_volume(
+    State(state): State<AppState>,
+    Path(client_id): Path<String>,
+    Json(request): Json<VolumeRequest>,
+) -> StatusCode {
+    if !state.client_manager.is_player(&client_id) {
+        return StatusCode::NOT_FOUND;
+    }
+    if request.volume.is_some_and(|v| v > 100) {
+        return StatusCode::UNPROCESSABLE_ENTITY;
+    }
+    let player = HashSet::from([client_id]);
+    let changes = [
+        request.volume.map(VolumeChange::Set),
+        request.muted.map(VolumeChange::Mute),
+    ];
+    for change in changes.into_iter().flatten() {
+        state.client_manager.apply_volume(&player, change);
+    }
+    StatusCode::NO_CONTENT
+}
+
+/// Set a group's volume, scaling its players proportionally, and/or mute it
+async fn set_group_volume(
+    State(state): State<AppState>,
+    Path(group_id): Path<String>,
+    Json(request): Json<VolumeRequest>,
+) -> Response {
+    if request.volume.is_some_and(|v| v > 100) {
+        return StatusCode::UNPROCESSABLE_ENTITY.into_response();
+    }
+    if state.group_manager.get_group(&group_id).is_none() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let playback =
+        PlaybackController::new(state.client_manager.clone(), state.group_manager.clone());
+    let mut result = Vec::new();
+    if let Some(volume) = request.volume {
+        result = playback.set_group_volume(&group_id, VolumeChange::Group(volume));
+    }
+    if let Some(muted) = request.muted {
+        result = playback.set_group_volume(&group_id, VolumeChange::Mute(muted));
+    }
+    log::info!(
+        "Group {} volume: {:?}, muted: {:?}",
+        group_id,
+        request.volume,
+        request.muted
+    );
+    Json(player_volumes(result)).into_response()
+}
+
+/// Change the volume of every player, or of one group's players
+async fn batch_volume(
+    State(state): State<AppState>,
+    Json(request): Json<BatchVolumeRequest>,
+) -> Response {
+    if request.volume.is_some_and(|v| v > 100) {
+        return StatusCode::UNPROCESSABLE_ENTITY.into_response();
+    }
+    if request.volume.is_none() && request.step.is_none() && request.muted.is_none() {
+        let message = "set at least one of volume, step, or muted";
+        return (StatusCode::UNPROCESSABLE_ENTITY, message).into_response();
+    }
+    let players: HashSet<ClientId> = match &request.group_id {
+        Some(group_id) => {
+            if state.group_manager.get_group(group_id).is_none() {
+                return StatusCode::NOT_FOUND.into_response();
+            }
+            state
+                .group_manager
+                .get_group_members(group_id)
+                .into_iter()
+                .collect()
+        }
+        None => state.client_manager.player_ids(),
+    };
+    let changes = [
+        request.volume.map(VolumeChange::Set),
+        request.step.map(VolumeChange::Step),
+        request.muted.map(VolumeChange::Mute),
+    ];
+    let mut result = Vec::new();
+    for change in changes.into_iter().flatten() {
+        result = state.client_manager.apply_volume(&players, change);
+    }
+    log::info!("Volume change {:?} for {} players", request, result.len());
+    Json(player_volumes(result)).into_response()
+}
+
+fn player_volumes(volumes: Vec<(ClientId, u8, bool)>) -> Vec<PlayerVolume> {
+    volumes
+        .into_iter()
+        .map(|(client_id, volume, muted)| PlayerVolume {
+            client_id,
+            volume,
+            muted,
+        })
+        .collect()
+}
+
+async fn set_mono(
+    State(state): State<AppState>,
+    Path(client_id): Path<String>,
+    Json(request): Json<MonoRequest>,
+) -> StatusCode {
+    if !state.client_manager.is_player(&client_id) {
+        return StatusCode::NOT_FOUND;
+    }
+    state.client_manager.set_mono(&client_id, request.enabled);
+    log::info!(
+        "Mono output {} for client {}",
+        if request.enabled {
+            "enabled"
+        } else {
+            "disabled"
+        },
+        client_id
+    );
+    StatusCode::NO_CONTENT
+}
+
+async fn set_channel_map(
+    State(state): State<AppState>,
+    Path(client_id): Path<String>,
+    Json(request): Json<ChannelMapRequest>,
+) -> StatusCode {
+    if !state.client_manager.is_player(&client_id) {
+        return StatusCode::NOT_FOUND;
+    }
+    state
+        .client_manager
+        .set_channel_map(&client_id, request.channel_map);
+    log::info!(
+        "Channel map {} for client {}",
+        request.channel_map.as_str(),
+        client_id
+    );
+    StatusCode::NO_CONTENT
+}
+
+/// Tag a client with its link tier, connected or not
+async fn set_link_tier(
+    State(state): State<AppState>,
+    Path(client_id): Path<String>,
+    Json(request): Json<LinkTierRequest>,
+) -> StatusCode {
+    let playback =
+        PlaybackController::new(state.client_manager.clone(), state.group_manager.clone());
+    playback.set_link_tier(&client_id, request.tier);
+    StatusCode::NO_CONTENT
+}
+
+async fn set_tier_buffer_ahead(
+    State(state): State<AppState>,
+    Path(tier): Path<LinkTier>,
+    Json(request): Json<TierBufferRequest>,
+) -> StatusCode {
+    let playback =
+        PlaybackController::new(state.client_manager.clone(), state.group_manager.clone());
+    playback.set_tier_buffer_ahead(tier, request.buffer_ahead_ms);
+    StatusCode::NO_CONTENT
+}
+
+/// Set or clear the operator's name for a client, connected or not
+async fn set_client_name(
+    State(state): State<AppState>,
+    Path(client_id): Path<String>,
+    Json(request): Json<NameRequest>,
+) -> StatusCode {
+    let name = request.name.map(|name| name.trim().to_string());
+    if name.as_deref() == Some("") {
+        return StatusCode::UNPROCESSABLE_ENTITY;
+    }
+    log::info!("Client {} name override: {:?}", client_id, name);
+    state.client_manager.set_name_override(&client_id, name);
+    StatusCode::NO_CONTENT
+}
+
+async fn get_codec(
+    State(state): State<AppState>,
+    Path(client_id): Path<String>,
+) -> Result<Json<CodecInfo>, StatusCode> {
+    let pin = state.client_manager.codec_override(&client_id);
+    let streaming = state.client_manager.get_audio_format(&client_id);
+    if pin.is_none() && !state.client_manager.is_player(&client_id) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    Ok(Json(CodecInfo {
+        client_id,
+        pinned: pin.map(|pin| pin.codec.name().to_string()),
+        settings: pin.map(|pin| pin.settings).unwrap_or_default(),
+        streaming: streaming.map(|format| format.codec.name().to_string()),
+    }))
+}
+
+/// Pin a client to a codec, restarting its stream if it is connected
+///
+/// Clients not connected yet get the pin on their next connection; 422 for a
+/// codec the server has no encoder for.
+async fn set_codec(
+    State(state): State<AppState>,
+    Path(client_id): Path<String>,
+    Json(request): Json<CodecRequest>,
+) -> Response {
+    if let Err(message) = request.settings.validate() {
+        return (StatusCode::UNPROCESSABLE_ENTITY, message).into_response();
+    }
+    let pin = match request.codec.as_deref().map(Codec::from_name) {
+        None => None,
+        Some(Some(codec)) => Some(CodecOverride {
+            codec,
+            settings: request.settings,
+        }),
+        Some(None) => {
+            let message = format!("unknown codec '{}'", request.codec.unwrap_or_default());
+            return (StatusCode::UNPROCESSABLE_ENTITY, message).into_response();
+        }
+    };
+    if let Some(pin) = pin.filter(|pin| !state.streams.encoders().contains(pin.codec.name())) {
+        let message = format!("the server cannot encode {}", pin.codec.name());
+        return (StatusCode::UNPROCESSABLE_ENTITY, message).into_response();
+    }
+
+    if !state.client_manager.is_player(&client_id) {
+        state.client_manager.set_codec_override(&client_id, pin);
+        log::info!("Codec for client {} on next connect: {:?}", client_id, pin);
+        return StatusCode::NO_CONTENT.into_response();
+    }
+    let supported = state
+        .client_manager
+        .get_supported_formats(&client_id)
+        .unwrap_or_default();
+    let format = match pin {
+        Some(pin) => state.config.current().codec_policy.select_codec(
+            &supported,
+            pin.codec,
+            state.streams.encoders(),
+        ),
+        None => negotiate_audio_format(
+            &supported,
+            &state.config.current(),
+            state.streams.encoders(),
+        ),
+    };
+    let Some(format) = format else {
+        let message = match pin {
+            Some(pin) => format!("client {} does not support {}", client_id, pin.codec.name()),
+            None => format!("no format of client {} can be streamed", client_id),
+        };
+        return (StatusCode::UNPROCESSABLE_ENTITY, message).into_response();
+    };
+
+    state.client_manager.set_codec_override(&client_id, pin);
+    log::info!(
+        "Client {} renegotiated to {} {}Hz{}",
+        client_id,
+        format.codec.name(),
+        format.sample_rate,
+        if pin.is_some() { " (pinned)" } else { "" }
+    );
+    let playback =
+        PlaybackController::new(state.client_manager.clone(), state.group_manager.clone());
+    playback.renegotiate(&client_id, format);
+    StatusCode::NO_CONTENT.into_response()
+}
+
+async fn move_client(
+    State(state): State<AppState>,
+    Path(client_id): Path<String>,
+    Json(request): Json<MoveRequest>,
+) -> Response {
+    if state.group_manager.get_client_group(&client_id).is_none() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let playback =
+        PlaybackController::new(state.client_manager.clone(), state.group_manager.clone());
+    if playback.move_client(&client_id, &request.group_id) {
+        StatusCode::NO_CONTENT.into_response()
+    } else {
+        let message = format!("no group named '{}'", request.group_id);
+        (StatusCode::UNPROCESSABLE_ENTITY, message).into_response()
+    }
+}
+
+/// Ask a client for a diagnostics snapshot and wait for its answer
+///
+/// Answers 504 if the client does not reply in time (older clients ignore the
+/// request).
+async fn client_diagnostics(
+    State(state): State<AppState>,
+    Path(client_id): Path<String>,
+) -> Response {
+    let Some(reply) = state.client_manager.request_diagnostics(&client_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    match tokio::time::timeout(DIAGNOSTICS_TIMEOUT, reply).await {
+        Ok(Ok(diagnostics)) => Json(diagnostics).into_response(),
+        Ok(Err(_)) => StatusCode::NOT_FOUND.into_response(),
+        Err(_) => {
+            let message = format!("client '{}' did not answer", client_id);
+            (StatusCode::GATEWAY_TIMEOUT, message).into_response()
+        }
+    }
+}
+
+/// Refuse a local source URI while the API is open to anyone
+///
+/// Capture devices, pipes, GStreamer pipelines and files reach into the
+/// server's machine, so without API keys they can only be chosen in the
+/// server's configuration or on its command line.
+fn check_source_uri(uri: &str, config: &ServerConfig) -> Result<(), String> {
+    if config.api_keys.is_empty() && is_local_uri(uri) {
+        return Err(format!(
+            "'{}' is a local source; configure API keys to open it through the API",
+            uri
+        ));
+    }
+    Ok(())
+}
+
+/// Open a source, refusing one the server's output format cannot carry
+///
+/// Blocks on probing the file or starting the HTTP request.
+fn open_for_output(uri: &str, config: &ServerConfig) -> Result<Box<dyn AudioSource>, String> {
+    let source = open_source(
+        uri,
+        config.downmix,
+        config.replay_gain,
+        config.url_cache.as_ref(),
+    )
+    .map_err(|e| e.to_string())?;
+    plan_conversion(source.sample_rate(), source.channels(), config)?;
+    Ok(source)
+}
+
+/// Switch the source of the stream a group plays
+///
+/// Only the group's stream changes, so every group subscribed to it hears the
+/// new source and groups on other streams do not; 404 if the group does not
+/// exist, 403 for a local `uri` while the API has no keys.
+async fn set_source(
+    State(state): State<AppState>,
+    Path(group_id): Path<String>,
+    Json(request): Json<SourceRequest>,
+) -> Response {
+    let Some(source_control) = state.streams.source_control_for_group(&group_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let config = state.config.current();
+    if let Err(e) = check_source_uri(&request.uri, &config) {
+        return (StatusCode::FORBIDDEN, e).into_response();
+    }
+    // Opening a source probes the file or starts an HTTP request, both blocking
+    let uri = request.uri.clone();
+    match tokio::task::spawn_blocking(move || open_for_output(&uri, &config)).await {
+        Ok(Ok(source)) => {
+            source_control.replace(source);
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(Err(e)) => {
+            log::warn!("Failed to open source {}: {}", request.uri, e);
+            (StatusCode::UNPROCESSABLE_ENTITY, e).into_response()
+        }
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// Detach a group onto its own timeline, or return it to the shared one
+///
+/// A detached group starts over from the beginning of its source, since
+/// sources cannot be copied mid-play; 409 if it is already detached, 403 for a
+/// local `uri` while the API has no keys.
+async fn set_timeline(
+    State(state): State<AppState>,
+    Path(group_id): Path<String>,
+    Json(request): Json<TimelineRequest>,
+) -> Response {
+    let Some(source_control) = state.streams.source_control_for_group(&group_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if !request.independent {
+        state.streams.rejoin_shared(&group_id);
+        return StatusCode::NO_CONTENT.into_response();
+    }
+    if state.streams.is_detached(&group_id) {
+        return StatusCode::CONFLICT.into_response();
+    }
+    let config = state.config.current();
+    if let Some(Err(e)) = request
+        .uri
+        .as_ref()
+        .map(|uri| check_source_uri(uri, &config))
+    {
+        return (StatusCode::FORBIDDEN, e).into_response();
+    }
+    let Some(uri) = request.uri.or(source_control.now_playing().source) else {
+        let message = "the group's stream has no source to play again; give a uri";
+        return (StatusCode::UNPROCESSABLE_ENTITY, message).into_response();
+    };
+    let opened = tokio::task::spawn_blocking(move || open_for_output(&uri, &config)).await;
+    let source = match opened {
+        Ok(Ok(source)) => source,
+        Ok(Err(e)) => return (StatusCode::UNPROCESSABLE_ENTITY, e).into_response(),
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+    match state.streams.detach_group(&group_id, source) {
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => (StatusCode::UNPROCESSABLE_ENTITY, e).into_response(),
+    }
+}
+
+/// Merge a group into another, answering with the members that moved
+async fn merge_groups(
+    State(state): State<AppState>,
+    Path(group_id): Path<String>,
+    Json(request): Json<MoveRequest>,
+) -> Response {
+    if state.group_manager.get_group(&group_id).is_none() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    match state.streams.merge_groups(&group_id, &request.group_id) {
+        Ok(moved) => Json(moved).into_response(),
+        Err(e) => (StatusCode::UNPROCESSABLE_ENTITY, e).into_response(),
+    }
+}
+
+/// Tracks queued on the stream a group plays
+///
+/// Every group on the stream shares its queue.
+async fn list_queue(
+    State(state): State<AppState>,
+    Path(group_id): Path<String>,
+) -> Result<Json<Vec<QueueEntry>>, StatusCode> {
+    let source_control = state
+        .streams
+        .source_control_for_group(&group_id)
+        .ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(source_control.queue().entries()))
+}
+
+async fn enqueue(
+    State(state): State<AppState>,
+    Path(group_id): Path<String>,
+    Json(request): Json<QueueRequest>,
+) -> Response {
+    let Some(source_control) = state.streams.source_control_for_group(&group_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if request.uri.is_empty() {
+        return (StatusCode::UNPROCESSABLE_ENTITY, "uri must not be empty").into_response();
+    }
+    if let Err(e) = check_source_uri(&request.uri, &state.config.current()) {
+        return (StatusCode::FORBIDDEN, e).into_response();
+    }
+    let entry = source_control.queue().enqueue(request.uri);
+    log::info!("Queued {} for group {}", entry.uri, group_id);
+    (StatusCode::CREATED, Json(entry)).into_response()
+}
+
+async fn clear_queue(State(state): State<AppState>, Path(group_id): Path<String>) -> StatusCode {
+    match state.streams.source_control_for_group(&group_id) {
+        Some(source_control) => {
+            source_control.queue().clear();
+            StatusCode::NO_CONTENT
+        }
+        None => StatusCode::NOT_FOUND,
+    }
+}
+
+/// Move on to the next queued track; 409 if nothing is queued
+async fn skip_queued(State(state): State<AppState>, Path(group_id): Path<String>) -> StatusCode {
+    let Some(source_control) = state.streams.source_control_for_group(&group_id) else {
+        return StatusCode::NOT_FOUND;
+    };
+    if source_control.skip() {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::CONFLICT
+    }
+}
+
+async fn move_queued(
+    State(state): State<AppState>,
+    Path((group_id, entry_id)): Path<(String, u64)>,
+    Json(request): Json<QueuePositionRequest>,
+) -> StatusCode {
+    let moved = state
+        .streams
+        .source_control_for_group(&group_id)
+        .is_some_and(|control| control.queue().move_to(entry_id, request.position));
+    if moved {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+async fn remove_queued(
+    State(state): State<AppState>,
+    Path((group_id, entry_id)): Path<(String, u64)>,
+) -> StatusCode {
+    let removed = state
+        .streams
+        .source_control_for_group(&group_id)
+        .is_some_and(|control| control.queue().remove(entry_id));
+    if removed {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+async fn set_group_stream(
+    State(state): State<AppState>,
+    Path(group_id): Path<String>,
+    Json(request): Json<StreamRequest>,
+) -> Response {
+    let stream_id = request
+        .stream_id
+        .unwrap_or_else(|| DEFAULT_STREAM.to_string());
+    if !state.streams.contains(&stream_id) {
+        let message = format!("no stream {}", stream_id);
+        return (StatusCode::UNPROCESSABLE_ENTITY, message).into_response();
+    }
+    if !state
+        .group_manager
+        .set_stream(&group_id, Some(stream_id.clone()))
+    {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    log::info!("Group {} now plays stream {}", group_id, stream_id);
+    for member in state.group_manager.get_group_members(&group_id) {
+        state.streams.send_metadata(&member);
+    }
+    StatusCode::NO_CONTENT.into_response()
+}
+
+async fn list_streams(State(state): State<AppState>) -> Json<Vec<StreamInfo>> {
+    Json(state.streams.streams())
+}
+
+async fn add_stream(
+    State(state): State<AppState>,
+    Json(request): Json<NewStreamRequest>,
+) -> Response {
+    if state.streams.contains(&request.stream_id) {
+        return StatusCode::CONFLICT.into_response();
+    }
+    let config = state.config.current();
+    if let Err(e) = check_source_uri(&request.uri, &config) {
+        return (StatusCode::FORBIDDEN, e).into_response();
+    }
+    let uri = request.uri.clone();
+    let opened = tokio::task::spawn_blocking(move || open_for_output(&uri, &config)).await;
+    let source = match opened {
+        Ok(Ok(source)) => source,
+        Ok(Err(e)) => {
+            log::warn!("Failed to open source {}: {}", request.uri, e);
+            return (StatusCode::UNPROCESSABLE_ENTITY, e).into_response();
+        }
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+    match state.streams.add_stream(&request.stream_id, source) {
+        Ok(_) => StatusCode::CREATED.into_response(),
+        Err(e) => (StatusCode::UNPROCESSABLE_ENTITY, e).into_response(),
+    }
+}
+
+async fn remove_stream(State(state): State<AppState>, Path(stream_id): Path<String>) -> Response {
+    if stream_id == DEFAULT_STREAM {
+        let message = "the default stream cannot be removed";
+        return (StatusCode::UNPROCESSABLE_ENTITY, message).into_response();
+    }
+    if state.streams.remove_stream(&stream_id) {
+        StatusCode::NO_CONTENT.into_response()
+    } else {
+        StatusCode::NOT_FOUND.into_response()
+    }
+}
+
+async fn stream_action(
+    State(state): State<AppState>,
+    Path((stream_id, action)): Path<(String, String)>,
+) -> StatusCode {
+    let Some(engine) = state.streams.engine_handle(&stream_id) else {
+        return StatusCode::NOT_FOUND;
+    };
+    let sent = match action.as_str() {
+        "play" => engine.play(),
+        "pause" => engine.pause(),
+        "stop" => engine.stop(),
+        "next" => engine.next(),
+        _ => return StatusCode::NOT_FOUND,
+    };
+    if sent {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+async fn now_playing(State(state): State<AppState>) -> Json<NowPlayingInfo> {
+    let mut playing_groups: Vec<String> = state
+        .group_manager
+        .playing_groups()
+        .into_iter()
+        .map(|(id, _, _)| id)
+        .collect();
+    playing_groups.sort();
+    Json(NowPlayingInfo {
+        now_playing: state.source_control.now_playing(),
+        playing_groups,
+    })
+}
+
+/// Recently played tracks, newest first, filtered by `group`, `since_ms` and `limit`
+async fn history(
+    State(state): State<AppState>,
+    Query(query): Query<HistoryQuery>,
+) -> Json<Vec<PlayRecord>> {
+    Json(state.history.query(&query))
+}
+
+async fn list_pipelines(State(state): State<AppState>) -> Json<Vec<PipelineGraph>> {
+    let mut group_ids = state.group_manager.group_ids();
+    group_ids.sort();
+    let pipelines = group_ids
+        .iter()
+        .filter_map(|group_id| {
+            describe_pipeline(
+                group_id,
+                &state.config.current(),
+                &state.client_manager,
+                &state.group_manager,
+                &state
+                    .streams
+                    .source_control_for_group(group_id)
+                    .unwrap_or_else(|| state.source_control.clone()),
+            )
+        })
+        .collect();
+    Json(pipelines)
+}
+
+async fn group_pipeline(
+    State(state): State<AppState>,
+    Path(group_id): Path<String>,
+) -> Result<Json<PipelineGraph>, StatusCode> {
+    let source_control = state
+        .streams
+        .source_control_for_group(&group_id)
+        .unwrap_or_else(|| state.source_control.clone());
+    describe_pipeline(
+        &group_id,
+        &state.config.current(),
+        &state.client_manager,
+        &state.group_manager,
+        &source_control,
+    )
+    .map(Json)
+    .ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn replication_snapshot(State(state): State<AppState>) -> Json<ReplicationSnapshot> {
+    Json(ReplicationSnapshot::capture(
+        &state.config.current(),
+        &state.client_manager,
+        &state.group_manager,
+        &state.source_control,
+    ))
+}
+
+async fn export_snapshot(State(state): State<AppState>) -> Json<ServerSnapshot> {
+    Json(ServerSnapshot::capture(
+        &state.config.current(),
+        &state.client_manager,
+        &state.group_manager,
+        &state.source_control,
+        &state.streams.streams(),
+    ))
+}
+
+/// Import a snapshot exported by this or another server
+///
+/// Missing streams are started and the main source is replaced before the
+/// groups are applied, so groups find the streams they subscribe to. A
+/// source that cannot be opened, or is local while the API has no keys, is
+/// reported without failing the import; 422 if the snapshot itself cannot be
+/// read by this server.
+async fn import_snapshot(
+    State(state): State<AppState>,
+    Json(snapshot): Json<ServerSnapshot>,
+) -> Response {
+    if let Err(message) = snapshot.validate() {
+        return (StatusCode::UNPROCESSABLE_ENTITY, message).into_response();
+    }
+    let config = state.config.current();
+    let open = |uri: String| {
+        let config = config.clone();
+        tokio::task::spawn_blocking(move || {
+            check_source_uri(&uri, &config)?;
+            open_for_output(&uri, &config)
+        })
+    };
+    let mut report = SnapshotImport::default();
+
+    let current = state.source_control.now_playing().source;
+    if let Some(uri) = snapshot
+        .state
+        .source
+        .clone()
+        .filter(|uri| Some(uri) != current.as_ref())
+    {
+        match open(uri.clone()).await {
+            Ok(Ok(source)) => {
+                state.source_control.replace(source);
+                report.source_replaced = true;
+            }
+            Ok(Err(e)) => report.failed_sources.push(format!("{}: {}", uri, e)),
+            Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        }
+    }
+    for stream in &snapshot.streams {
+        if state.streams.contains(&stream.id) {
+            continue;
+        }
+        let added = match open(stream.uri.clone()).await {
+            Ok(Ok(source)) => state.streams.add_stream(&stream.id, source).map(|_| ()),
+            Ok(Err(e)) => Err(e),
+            Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        };
+        match added {
+            Ok(()) => report.streams_started.push(stream.id.clone()),
+            Err(e) => report.failed_sources.push(format!("{}: {}", stream.uri, e)),
+        }
+    }
+
+    snapshot.apply(&state.client_manager, &state.group_manager);
+    // Connected clients would only pick up their snapshot group on reconnect
+    let playback =
+        PlaybackController::new(state.client_manager.clone(), state.group_manager.clone());
+    for client in &snapshot.state.clients {
+        let Some(current) = state.group_manager.get_client_group(&client.client_id) else {
+            continue;
+        };
+        if let Some(group_id) = client.group_id.as_ref().filter(|&group| *group != current) {
+            if playback.move_client(&client.client_id, group_id) {
+                report.clients_moved.push(client.client_id.clone());
+            }
+        }
+        let player = HashSet::from([client.client_id.clone()]);
+        for change in [
+            VolumeChange::Set(client.volume),
+            VolumeChange::Mute(client.muted),
+        ] {
+            state.client_manager.apply_volume(&player, change);
+        }
+    }
+    log::info!(
+        "Imported snapshot of server {}: {} groups, {} clients",
+        snapshot.state.server_id,
+        snapshot.state.groups.len(),
+        snapshot.state.clients.len()
+    );
+    Json(report).into_response()
+}
+
+async fn list_groups(State(state): State<AppState>) -> Json<Vec<GroupStats>> {
+    Json(state.stats.group_stats())
+}
+
+async fn set_night_mode(
+    State(state): State<AppState>,
+    Path(group_id): Path<String>,
+    Json(request): Json<NightModeRequest>,
+) -> StatusCode {
+    let night_mode = request.enabled.then(|| {
+        let mut profile = NightMode::default();
+        if request.bass_cut == Some(false) {
+            profile.bass_cut_db = None;
+        }
+        profile
+    });
+    if state.group_manager.set_night_mode(&group_id, night_mode) {
+        log::info!(
+            "Night mode {} for group {}",
+            if request.enabled {
+                "enabled"
+            } else {
+                "disabled"
+            },
+            group_id
+        );
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+async fn set_stereo_width(
+    State(state): State<AppState>,
+    Path(group_id): Path<String>,
+    Json(request): Json<StereoWidthRequest>,
+) -> Response {
+    let stereo_width = request.width.map(|width| StereoWidth {
+        width,
+        bass_mono_hz: request.bass_mono_hz,
+    });
+    if let Some(Err(message)) = stereo_width.map(|profile| profile.validate()) {
+        return (StatusCode::UNPROCESSABLE_ENTITY, message).into_response();
+    }
+    if !state
+        .group_manager
+        .set_stereo_width(&group_id, stereo_width)
+    {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    log::info!("Group {} stereo width: {:?}", group_id, stereo_width);
+    StatusCode::NO_CONTENT.into_response()
+}
+
+async fn get_encoder_settings(
+    State(state): State<AppState>,
+    Path(group_id): Path<String>,
+) -> Result<Json<EncoderSettingsInfo>, StatusCode> {
+    if state.group_manager.get_group(&group_id).is_none() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let group = state.group_manager.get_encoder_settings(&group_id);
+    Ok(Json(EncoderSettingsInfo {
+        group_id,
+        overridden: group.is_some(),
+        settings: state.config.current().encoder_settings_for(group),
+    }))
+}
+
+/// Replace a group's encoder override; an empty body clears it
+async fn set_encoder_settings(
+    State(state): State<AppState>,
+    Path(group_id): Path<String>,
+    Json(settings): Json<EncoderSettings>,
+) -> Response {
+    if let Err(message) = settings.validate() {
+        return (StatusCode::UNPROCESSABLE_ENTITY, message).into_response();
+    }
+    let settings = (!settings.is_empty()).then_some(settings);
+    if state
+        .group_manager
+        .set_encoder_settings(&group_id, settings)
+    {
+        log::info!("Encoder settings for group {}: {:?}", group_id, settings);
+        StatusCode::NO_CONTENT.into_response()
+    } else {
+        StatusCode::NOT_FOUND.into_response()
+    }
+}
+
+async fn encoder_metrics(State(state): State<AppState>) -> Json<Vec<EncoderStats>> {
+    Json(state.encoder_metrics.snapshot())
+}
+
+async fn rtt_metrics(State(state): State<AppState>) -> Json<Vec<ClientRtt>> {
+    let mut clients = Vec::new();
+    state.client_manager.for_each(|client| {
+        if let Some(rtt) = client.rtt_histogram.summary() {
+            clients.push(ClientRtt {
+                client_id: client.client_id.clone(),
+                name: client.name.clone(),
+                rtt,
+            });
+        }
+    });
+    clients.sort_by(|a, b| a.client_id.cmp(&b.client_id));
+    Json(clients)
+}
+
+async fn profile() -> Result<String, (StatusCode, &'static str)> {
+    if !profiling_enabled() {
+        return Err((
+            StatusCode::NOT_FOUND,
+            "server was built without the profiling feature",
+        ));
+    }
+    Ok(folded_stacks())
+}
+
+async fn clear_profile() -> StatusCode {
+    reset_profile();
+    StatusCode::NO_CONTENT
+}
+
+async fn group_action(
+    State(state): State<AppState>,
+    Path((group_id, action)): Path<(String, String)>,
+) -> StatusCode {
+    let playback =
+        PlaybackController::new(state.client_manager.clone(), state.group_manager.clone());
+    let found = match action.as_str() {
+        "play" => playback.play(&group_id),
+        "pause" => playback.pause(&group_id),
+        "stop" => playback.stop(&group_id),
+        _ => return StatusCode::NOT_FOUND,
+    };
+    if found {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::client_manager::ClientManager;
+    use crate::server::clock::ServerClock;
+    use crate::server::config_file::LiveConfig;
+    use crate::server::encoder::EncoderRegistry;
+    use crate::server::encoder_metrics::EncoderMetrics;
+    use crate::server::extensions::Extensions;
+    use crate::server::group::GroupManager;
+    use crate::server::group_stats::StatsCollector;
+    use crate::server::history::{PlaybackHistory, DEFAULT_HISTORY_SIZE};
+    use crate::server::roles::RoleHandlers;
+    use crate::server::source_control::SourceControl;
+    use crate::server::stream_manager::StreamManager;
+    use axum::http::HeaderValue;
+    use std::sync::Arc;
+
+    fn state() -> AppState {
+        let config = Arc::new(ServerConfig::new("Test"));
+        let client_manager = Arc::new(ClientManager::new());
+        let group_manager = Arc::new(GroupManager::new());
+        let clock = Arc::new(ServerClock::new());
+        let encoder_metrics = EncoderMetrics::new();
+        let streams = StreamManager::new(
+            config.clone(),
+            client_manager.clone(),
+            group_manager.clone(),
+            clock.clone(),
+            EncoderRegistry::default(),
+            encoder_metrics.clone(),
+        );
+        AppState {
+            config: LiveConfig::new(config),
+            stats: StatsCollector::new(client_manager.clone(), group_manager.clone()),
+            client_manager,
+            group_manager,
+            clock,
+            encoder_metrics,
+            source_control: SourceControl::new(),
+            history: PlaybackHistory::new(DEFAULT_HISTORY_SIZE),
+            role_handlers: RoleHandlers::new(),
+            extensions: Extensions::new(),
+            streams,
+        }
+    }
+
+    fn keys() -> Vec<ApiKey> {
+        vec![
+            ApiKey::new("reader", Permission::Read),
+            ApiKey::new("admin", Permission::Control),
+        ]
+    }
+
+    fn bearer(key: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let value = HeaderValue::from_str(&format!("Bearer {}", key)).unwrap();
+        headers.insert(header::AUTHORIZATION, value);
+        headers
+    }
+
+    #[test]
+    fn test_open_without_keys() {
+        assert_eq!(
+            authorize(&HeaderMap::new(), &[], Permission::Control),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_permission_levels() {
+        let keys = keys();
+        assert_eq!(
+            authorize(&bearer("reader"), &keys, Permission::Read),
+            Ok(())
+        );
+        assert_eq!(
+            authorize(&bearer("reader"), &keys, Permission::Control),
+            Err(StatusCode::FORBIDDEN)
+        );
+        assert_eq!(
+            authorize(&bearer("admin"), &keys, Permission::Control),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_missing_or_unknown_key() {
+        let keys = keys();
+        assert_eq!(
+            authorize(&HeaderMap::new(), &keys, Permission::Read),
+            Err(StatusCode::UNAUTHORIZED)
+        );
+        assert_eq!(
+            authorize(&bearer("guess"), &keys, Permission::Read),
+            Err(StatusCode::UNAUTHORIZED)
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.insert(API_KEY_HEADER, HeaderValue::from_static("admin"));
+        assert_eq!(authorize(&headers, &keys, Permission::Control), Ok(()));
+    }
+
+    #[test]
+    fn test_local_sources_need_api_keys() {
+        let open = ServerConfig::new("Test");
+        assert!(check_source_uri("http://radio/stream", &open).is_ok());
+        for uri in [
+            "capture:default",
+            "pipe:/tmp/snapfifo",
+            "gst:fakesrc",
+            "/etc/passwd",
+        ] {
+            assert!(check_source_uri(uri, &open).is_err(), "{}", uri);
+        }
+
+        let keyed = ServerConfig::new("Test").api_key("admin", Permission::Control);
+        assert!(check_source_uri("capture:default", &keyed).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_codec_pin_needs_an_encoder() {
+        let pin = |codec: &str| {
+            Json(CodecRequest {
+                codec: Some(codec.to_string()),
+                ..Default::default()
+            })
+        };
+        let state = state();
+        let response = set_codec(State(state.clone()), Path("kitchen".into()), pin("opus")).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(state.client_manager.codec_override("kitchen").is_none());
+
+        let response = set_codec(State(state), Path("kitchen".into()), pin("flac")).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    }
+}
@@ -0,0 +1,115 @@
+// ABOUTME: Correlation IDs on server/command, matched against the client/state that echoes them
+// ABOUTME: Measures how long players take to apply control changes like volume
+
+use crate::protocol::messages::MessageTrace;
+use crate::server::clock::ServerClock;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// How long a command waits for its echo before it is forgotten (µs)
+///
+/// Clients that ignore `_trace` never echo it.
+pub const TRACE_TIMEOUT_MICROS: i64 = 30_000_000;
+
+/// Traces of commands sent to clients, and the latency of the last echoed one
+#[derive(Debug, Clone)]
+pub struct ControlTraces {
+    clock: Arc<ServerClock>,
+    next_id: Arc<AtomicU64>,
+    /// Client each outstanding trace was sent to and when, by trace ID
+    pending: Arc<Mutex<HashMap<String, (String, i64)>>>,
+    /// Microseconds from sending to echo of each client's last traced command
+    latency: Arc<Mutex<HashMap<String, i64>>>,
+}
+
+impl ControlTraces {
+    /// Track traces timestamped with `clock`
+    pub fn new(clock: Arc<ServerClock>) -> Self {
+        Self {
+            clock,
+            next_id: Arc::new(AtomicU64::new(1)),
+            pending: Arc::new(Mutex::new(HashMap::new())),
+            latency: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Trace for a command about to be sent to `client_id`
+    pub fn start(&self, client_id: &str) -> MessageTrace {
+        let now = self.clock.now_micros();
+        let trace = MessageTrace {
+            id: format!("s{}", self.next_id.fetch_add(1, Ordering::Relaxed)),
+            sent_at: now,
+            handled_at: None,
+        };
+        let mut pending = self.pending.lock();
+        pending.retain(|_, (_, sent_at)| *sent_at > now - TRACE_TIMEOUT_MICROS);
+        pending.insert(trace.id.clone(), (client_id.to_string(), now));
+        trace
+    }
+
+    /// Match an echoed trace from `client_id`, returning the round trip in µs
+    ///
+    /// Echoes of unknown, expired or another client's traces are ignored.
+    pub fn finish(&self, client_id: &str, trace: &MessageTrace) -> Option<i64> {
+        let sent_at = {
+            let mut pending = self.pending.lock();
+            match pending.get(&trace.id) {
+                Some((sent_to, _)) if sent_to == client_id => {}
+                _ => return None,
+            }
+            pending.remove(&trace.id)?.1
+        };
+        let round_trip = self.clock.now_micros() - sent_at;
+        self.latency
+            .lock()
+            .insert(client_id.to_string(), round_trip);
+        Some(round_trip)
+    }
+
+    /// Round trip of the last traced command `client_id` echoed (µs)
+    pub fn last_latency_micros(&self, client_id: &str) -> Option<i64> {
+        self.latency.lock().get(client_id).copied()
+    }
+
+    /// Stamp a client's trace with when the server handled it, for echoing back
+    pub fn handled(&self, trace: MessageTrace) -> MessageTrace {
+        MessageTrace {
+            handled_at: Some(self.clock.now_micros()),
+            ..trace
+        }
+    }
+}
+
+impl Default for ControlTraces {
+    fn default() -> Self {
+        Self::new(Arc::new(ServerClock::new()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::time::ManualClock;
+    use std::time::Duration;
+
+    #[test]
+    fn test_echo_measures_round_trip() {
+        let time = ManualClock::new();
+        let traces = ControlTraces::new(Arc::new(ServerClock::with_clock(time.shared())));
+        let trace = traces.start("kitchen");
+
+        time.advance(Duration::from_millis(35));
+        // Only the client the command went to can complete it, once
+        assert_eq!(traces.finish("bedroom", &trace), None);
+        assert_eq!(traces.finish("kitchen", &trace), Some(35_000));
+        assert_eq!(traces.finish("kitchen", &trace), None);
+        assert_eq!(traces.last_latency_micros("kitchen"), Some(35_000));
+
+        let stale = traces.start("kitchen");
+        time.advance(Duration::from_micros(TRACE_TIMEOUT_MICROS as u64 + 1));
+        traces.start("bedroom");
+        assert_eq!(traces.finish("kitchen", &stale), None);
+    }
+}
@@ -1,11 +1,22 @@
 // ABOUTME: Audio encoders for different codecs
-// ABOUTME: PCM 24-bit, Opus, and FLAC encoding
+// ABOUTME: PCM and FLAC encoding, with a registry of encoders by codec name
 
-use crate::audio::types::{Codec, Sample};
+use crate::audio::dither::{DitherMode, Ditherer};
+use crate::audio::types::{AudioFormat, Codec, Sample};
+use crate::server::flac::{FlacEncoder, DEFAULT_COMPRESSION_LEVEL, MAX_COMPRESSION_LEVEL};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
 
 /// Trait for audio encoders
+///
+/// Each call to `encode` receives one audio chunk and must flush everything it
+/// encodes: the returned bytes decode on their own given only `codec_header`,
+/// so a client that starts mid-stream or after `stream/clear` can play from
+/// any chunk.
 pub trait AudioEncoder: Send + Sync {
-    /// Encode samples to bytes
+    /// Encode one chunk of interleaved samples to bytes
     fn encode(&mut self, samples: &[Sample]) -> Vec<u8>;
 
     /// Get the codec type
@@ -26,29 +37,56 @@ pub trait AudioEncoder: Send + Sync {
     }
 }
 
-/// PCM 24-bit little-endian encoder
+/// PCM little-endian encoder (24-bit unless set to 16)
 pub struct PcmEncoder {
     sample_rate: u32,
     channels: u8,
+    bit_depth: u8,
+    ditherer: Ditherer,
 }
 
 impl PcmEncoder {
-    /// Create a new PCM encoder
+    /// Create a new 24-bit PCM encoder, rounding without dither
     pub fn new(sample_rate: u32, channels: u8) -> Self {
         Self {
             sample_rate,
             channels,
+            bit_depth: 24,
+            ditherer: Ditherer::new(DitherMode::Off, 24, channels),
+        }
+    }
+
+    /// Dither samples when encoding below 24 bits
+    pub fn with_dither(mut self, mode: DitherMode) -> Self {
+        self.ditherer = Ditherer::new(mode, self.bit_depth, self.channels);
+        self
+    }
+
+    /// Encode at 16 or 24 bits per sample
+    pub fn with_bit_depth(mut self, bit_depth: u8) -> Result<Self, String> {
+        if bit_depth != 16 && bit_depth != 24 {
+            return Err(format!("PCM supports 16 or 24-bit, not {}-bit", bit_depth));
         }
+        self.bit_depth = bit_depth;
+        self.ditherer = Ditherer::new(self.ditherer.mode(), bit_depth, self.channels);
+        Ok(self)
     }
 }
 
 impl AudioEncoder for PcmEncoder {
     fn encode(&mut self, samples: &[Sample]) -> Vec<u8> {
+        let values = self.ditherer.quantize(samples);
+        if self.bit_depth == 16 {
+            return values
+                .iter()
+                .flat_map(|&val| (val as i16).to_le_bytes())
+                .collect();
+        }
+
         let mut out = Vec::with_capacity(samples.len() * 3);
 
-        for sample in samples {
+        for val in values {
             // 24-bit little-endian: [low, mid, high]
-            let val = sample.0;
             out.push((val & 0xFF) as u8);
             out.push(((val >> 8) & 0xFF) as u8);
             out.push(((val >> 16) & 0xFF) as u8);
@@ -70,139 +108,221 @@ impl AudioEncoder for PcmEncoder {
     }
 
     fn bit_depth(&self) -> u8 {
-        24
+        self.bit_depth
     }
 }
 
-/// Opus encoder (placeholder - requires opus crate)
-pub struct OpusEncoder {
-    sample_rate: u32,
-    channels: u8,
-    // encoder: Option<opus::Encoder>,
+/// Encoder tuning for a stream, trading quality and CPU for bandwidth
+///
+/// Unset fields use the encoder's defaults. Settings for codecs a stream does
+/// not use are ignored.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct EncoderSettings {
+    /// FLAC compression level (0 fastest to 8 smallest)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flac_compression_level: Option<u8>,
+    /// Dither used when quantizing below 24 bits (PCM and FLAC)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dither: Option<DitherMode>,
 }
 
-impl OpusEncoder {
-    /// Create a new Opus encoder
-    ///
-    /// Note: Opus requires 48kHz sample rate
-    pub fn new(sample_rate: u32, channels: u8) -> Result<Self, String> {
-        if sample_rate != 48000 {
-            return Err("Opus requires 48kHz sample rate".to_string());
+impl EncoderSettings {
+    /// The encoders' built-in defaults, with every supported field set
+    pub fn defaults() -> Self {
+        Self {
+            flac_compression_level: Some(DEFAULT_COMPRESSION_LEVEL),
+            dither: Some(DitherMode::default()),
         }
+    }
 
-        // TODO: Initialize opus encoder when we add the opus crate
-        // let encoder = opus::Encoder::new(sample_rate, channels, opus::Application::Audio)?;
-
-        Ok(Self {
-            sample_rate,
-            channels,
-            // encoder: Some(encoder),
-        })
+    /// Whether every setting is left at its default
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
     }
-}
 
-impl AudioEncoder for OpusEncoder {
-    fn encode(&mut self, samples: &[Sample]) -> Vec<u8> {
-        // TODO: Implement actual Opus encoding
-        // For now, fall back to PCM
-        let mut out = Vec::with_capacity(samples.len() * 3);
-        for sample in samples {
-            let val = sample.0;
-            out.push((val & 0xFF) as u8);
-            out.push(((val >> 8) & 0xFF) as u8);
-            out.push(((val >> 16) & 0xFF) as u8);
+    /// These settings, with unset fields taken from `fallback`
+    pub fn or(self, fallback: EncoderSettings) -> Self {
+        Self {
+            flac_compression_level: self
+                .flac_compression_level
+                .or(fallback.flac_compression_level),
+            dither: self.dither.or(fallback.dither),
         }
-        out
     }
 
-    fn codec(&self) -> Codec {
-        Codec::Opus
+    /// Check that every set value is supported and in range
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(level) = self
+            .flac_compression_level
+            .filter(|&l| l > MAX_COMPRESSION_LEVEL)
+        {
+            return Err(format!(
+                "FLAC compression level {} is outside 0-{}",
+                level, MAX_COMPRESSION_LEVEL
+            ));
+        }
+        Ok(())
     }
+}
 
-    fn sample_rate(&self) -> u32 {
-        self.sample_rate
-    }
+/// Stream parameters an encoder is created for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncoderParams {
+    /// Sample rate in Hz
+    pub sample_rate: u32,
+    /// Number of channels
+    pub channels: u8,
+    /// Bit depth of the source samples
+    pub bit_depth: u8,
+    /// Samples per channel in each audio chunk
+    pub chunk_frames: usize,
+    /// Tuning for this stream
+    pub settings: EncoderSettings,
+}
 
-    fn channels(&self) -> u8 {
-        self.channels
-    }
+/// Codec, sample rate, channel count and bit depth a client's stream is encoded with
+///
+/// A source at another sample rate is resampled to the client's.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StreamFormat {
+    /// Codec
+    pub codec: Codec,
+    /// Sample rate in Hz
+    pub sample_rate: u32,
+    /// Number of channels (1 to 8)
+    pub channels: u8,
+    /// Bits per sample
+    pub bit_depth: u8,
+}
 
-    fn bit_depth(&self) -> u8 {
-        16 // Opus uses 16-bit internally
+impl From<&AudioFormat> for StreamFormat {
+    fn from(format: &AudioFormat) -> Self {
+        Self {
+            codec: format.codec,
+            sample_rate: format.sample_rate,
+            channels: format.channels,
+            bit_depth: format.bit_depth,
+        }
     }
 }
 
-/// FLAC encoder (placeholder - requires flac crate)
-pub struct FlacEncoder {
-    sample_rate: u32,
-    channels: u8,
-    bit_depth: u8,
-    // encoder: Option<flac::Encoder>,
+/// Builds an encoder for the given parameters, or explains why it cannot
+pub type EncoderFactory =
+    Arc<dyn Fn(EncoderParams) -> Result<Box<dyn AudioEncoder>, String> + Send + Sync>;
+
+/// Encoders available to the server, by codec name
+///
+/// Starts with the built-in `flac` and `pcm` encoders. Registering a
+/// name replaces any encoder already registered under it, so downstream
+/// crates can add codecs or swap in their own implementation of a built-in one.
+/// Cheap to clone; clones share the same registry.
+#[derive(Clone)]
+pub struct EncoderRegistry {
+    factories: Arc<RwLock<HashMap<String, EncoderFactory>>>,
 }
 
-impl FlacEncoder {
-    /// Create a new FLAC encoder
-    pub fn new(sample_rate: u32, channels: u8, bit_depth: u8) -> Self {
-        Self {
-            sample_rate,
-            channels,
-            bit_depth,
-        }
+impl Default for EncoderRegistry {
+    fn default() -> Self {
+        let registry = Self::empty();
+        registry.register(Codec::Pcm.name(), |p: EncoderParams| {
+            let encoder = PcmEncoder::new(p.sample_rate, p.channels)
+                .with_bit_depth(p.bit_depth)?
+                .with_dither(p.settings.dither.unwrap_or_default());
+            Ok(Box::new(encoder) as Box<dyn AudioEncoder>)
+        });
+        registry.register(Codec::Flac.name(), |p: EncoderParams| {
+            let level = p
+                .settings
+                .flac_compression_level
+                .unwrap_or(DEFAULT_COMPRESSION_LEVEL);
+            Ok(Box::new(
+                FlacEncoder::new(p.sample_rate, p.channels, p.bit_depth)
+                    .with_block_size(p.chunk_frames)
+                    .with_compression_level(level)
+                    .with_dither(p.settings.dither.unwrap_or_default()),
+            ) as Box<dyn AudioEncoder>)
+        });
+        registry
     }
 }
 
-impl AudioEncoder for FlacEncoder {
-    fn encode(&mut self, samples: &[Sample]) -> Vec<u8> {
-        // TODO: Implement actual FLAC encoding
-        // For now, fall back to PCM
-        let mut out = Vec::with_capacity(samples.len() * 3);
-        for sample in samples {
-            let val = sample.0;
-            out.push((val & 0xFF) as u8);
-            out.push(((val >> 8) & 0xFF) as u8);
-            out.push(((val >> 16) & 0xFF) as u8);
+impl EncoderRegistry {
+    /// Create a registry with the built-in encoders
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a registry with no encoders
+    pub fn empty() -> Self {
+        Self {
+            factories: Arc::new(RwLock::new(HashMap::new())),
         }
-        out
     }
 
-    fn codec(&self) -> Codec {
-        Codec::Flac
+    /// Register a factory for a codec name
+    pub fn register<F>(&self, name: impl Into<String>, factory: F)
+    where
+        F: Fn(EncoderParams) -> Result<Box<dyn AudioEncoder>, String> + Send + Sync + 'static,
+    {
+        self.factories
+            .write()
+            .insert(name.into(), Arc::new(factory));
     }
 
-    fn sample_rate(&self) -> u32 {
-        self.sample_rate
+    /// Remove a codec, returning whether it was registered
+    pub fn unregister(&self, name: &str) -> bool {
+        self.factories.write().remove(name).is_some()
     }
 
-    fn channels(&self) -> u8 {
-        self.channels
+    /// Whether an encoder is registered under `name`
+    pub fn contains(&self, name: &str) -> bool {
+        self.factories.read().contains_key(name)
     }
 
-    fn bit_depth(&self) -> u8 {
-        self.bit_depth
+    /// Registered codec names, sorted
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.factories.read().keys().cloned().collect();
+        names.sort();
+        names
     }
 
-    fn codec_header(&self) -> Option<Vec<u8>> {
-        // TODO: Return FLAC stream info header
-        None
+    /// Create an encoder for `name`
+    pub fn create(
+        &self,
+        name: &str,
+        params: EncoderParams,
+    ) -> Result<Box<dyn AudioEncoder>, String> {
+        // Clone the factory so it runs without holding the lock
+        let factory = self
+            .factories
+            .read()
+            .get(name)
+            .cloned()
+            .ok_or_else(|| format!("no encoder registered for '{}'", name))?;
+        factory(params)
     }
 }
 
-/// Create an encoder for the given codec
-pub fn create_encoder(codec: Codec, sample_rate: u32, channels: u8, bit_depth: u8) -> Box<dyn AudioEncoder> {
-    match codec {
-        Codec::Pcm => Box::new(PcmEncoder::new(sample_rate, channels)),
-        Codec::Opus => {
-            match OpusEncoder::new(sample_rate, channels) {
-                Ok(enc) => Box::new(enc),
-                Err(_) => Box::new(PcmEncoder::new(sample_rate, channels)), // Fallback
-            }
-        }
-        Codec::Flac => Box::new(FlacEncoder::new(sample_rate, channels, bit_depth)),
-        Codec::Mp3 => {
-            // MP3 encoding not supported, fall back to PCM
-            Box::new(PcmEncoder::new(sample_rate, channels))
-        }
-    }
+/// Create a built-in encoder for the given codec
+///
+/// Falls back to PCM when the codec has no encoder or cannot encode at these
+/// parameters. Use an [`EncoderRegistry`] to include custom encoders.
+pub fn create_encoder(
+    codec: Codec,
+    sample_rate: u32,
+    channels: u8,
+    bit_depth: u8,
+) -> Box<dyn AudioEncoder> {
+    let params = EncoderParams {
+        sample_rate,
+        channels,
+        bit_depth,
+        chunk_frames: (sample_rate / 50) as usize,
+        settings: EncoderSettings::default(),
+    };
+    EncoderRegistry::default()
+        .create(codec.name(), params)
+        .unwrap_or_else(|_| Box::new(PcmEncoder::new(sample_rate, channels)))
 }
 
 #[cfg(test)]
@@ -214,10 +334,10 @@ mod tests {
         let mut encoder = PcmEncoder::new(48000, 2);
 
         let samples = vec![
-            Sample(0x123456),
-            Sample(-0x123456),
-            Sample(0),
-            Sample(Sample::MAX.0),
+            Sample::from_i24(0x123456),
+            Sample::from_i24(-0x123456),
+            Sample::ZERO,
+            Sample::MAX,
         ];
 
         let encoded = encoder.encode(&samples);
@@ -231,6 +351,43 @@ mod tests {
         assert_eq!(encoded[2], 0x12);
     }
 
+    #[test]
+    fn test_pcm_encode_16_bit() {
+        let mut encoder = PcmEncoder::new(48000, 2).with_bit_depth(16).unwrap();
+        assert_eq!(encoder.bit_depth(), 16);
+
+        let samples = [Sample::from_i24(0x123456), Sample::from_i24(-0x123456)];
+        let encoded = encoder.encode(&samples);
+        assert_eq!(encoded, [0x34, 0x12, 0xCC, 0xED]);
+
+        assert!(PcmEncoder::new(48000, 2).with_bit_depth(20).is_err());
+    }
+
+    #[test]
+    fn test_16_bit_streams_are_dithered_by_default() {
+        let params = |dither| EncoderParams {
+            sample_rate: 48000,
+            channels: 2,
+            bit_depth: 16,
+            chunk_frames: 960,
+            settings: EncoderSettings {
+                dither,
+                ..Default::default()
+            },
+        };
+        // Under half a step rounds to zero without dither
+        let quiet = vec![Sample(0.4 / 32_768.0); 1920];
+        let registry = EncoderRegistry::new();
+        let off = registry.create("pcm", params(Some(DitherMode::Off)));
+        assert!(off.unwrap().encode(&quiet).iter().all(|&b| b == 0));
+        let dithered = registry.create("pcm", params(None)).unwrap().encode(&quiet);
+        assert!(dithered.iter().any(|&b| b != 0));
+
+        // 24-bit output is left alone
+        let mut encoder = PcmEncoder::new(48000, 2).with_dither(DitherMode::Shaped);
+        assert_eq!(encoder.encode(&[Sample::from_i24(5)]), [5, 0, 0]);
+    }
+
     #[test]
     fn test_encoder_traits() {
         let encoder = PcmEncoder::new(48000, 2);
@@ -239,4 +396,76 @@ mod tests {
         assert_eq!(encoder.channels(), 2);
         assert_eq!(encoder.bit_depth(), 24);
     }
+
+    #[test]
+    fn test_registry_custom_codec() {
+        struct NullEncoder(EncoderParams);
+        impl AudioEncoder for NullEncoder {
+            fn encode(&mut self, _samples: &[Sample]) -> Vec<u8> {
+                Vec::new()
+            }
+            fn codec(&self) -> Codec {
+                Codec::Pcm
+            }
+            fn sample_rate(&self) -> u32 {
+                self.0.sample_rate
+            }
+            fn channels(&self) -> u8 {
+                self.0.channels
+            }
+            fn bit_depth(&self) -> u8 {
+                self.0.bit_depth
+            }
+        }
+
+        let registry = EncoderRegistry::new();
+        assert_eq!(registry.names(), ["flac", "pcm"]);
+
+        let params = EncoderParams {
+            sample_rate: 44100,
+            channels: 2,
+            bit_depth: 16,
+            chunk_frames: 882,
+            settings: EncoderSettings::default(),
+        };
+        assert!(registry.create("null", params).is_err());
+        // Only FLAC and PCM are built in
+        assert_eq!(registry.names(), ["flac", "pcm"]);
+        assert!(registry.create("opus", params).is_err());
+
+        registry.register("null", |p| {
+            Ok(Box::new(NullEncoder(p)) as Box<dyn AudioEncoder>)
+        });
+        let mut encoder = registry.create("null", params).unwrap();
+        assert!(encoder.encode(&[Sample::ZERO; 4]).is_empty());
+        assert_eq!(encoder.sample_rate(), 44100);
+
+        assert!(registry.clone().unregister("null"));
+        assert!(!registry.contains("null"));
+    }
+
+    #[test]
+    fn test_encoder_settings_layering() {
+        let group = EncoderSettings {
+            dither: Some(DitherMode::Shaped),
+            ..Default::default()
+        };
+        let server = EncoderSettings {
+            flac_compression_level: Some(2),
+            dither: Some(DitherMode::Off),
+        };
+        let effective = group.or(server).or(EncoderSettings::defaults());
+        assert_eq!(effective.dither, Some(DitherMode::Shaped));
+        assert_eq!(effective.flac_compression_level, Some(2));
+
+        assert!(effective.validate().is_ok());
+        let too_high = EncoderSettings {
+            flac_compression_level: Some(9),
+            ..Default::default()
+        };
+        assert!(too_high.validate().is_err());
+        let settings: EncoderSettings =
+            serde_json::from_str(r#"{"flac_compression_level": null}"#).unwrap();
+        assert!(settings.is_empty());
+    }
 }
@@ -0,0 +1,112 @@
+// ABOUTME: Per-codec encoder timing metrics
+// ABOUTME: Records CPU time spent encoding each chunk so operators can see encoder load
+
+use crate::audio::types::Codec;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Encoding cost for one codec since the server started
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EncoderStats {
+    /// Codec name as used in the protocol ("pcm", "flac", ...)
+    pub codec: String,
+    /// Chunks encoded
+    pub chunks: u64,
+    /// Total time spent encoding (microseconds)
+    pub total_micros: u64,
+    /// Average time per chunk (microseconds)
+    pub mean_micros: f64,
+    /// Slowest chunk (microseconds)
+    pub max_micros: u64,
+    /// Encoding time as a fraction of the audio duration encoded
+    /// (1.0 means one core is fully busy keeping up)
+    pub load: f64,
+}
+
+#[derive(Debug, Default)]
+struct Totals {
+    chunks: u64,
+    total: Duration,
+    max: Duration,
+    audio: Duration,
+}
+
+/// Shared per-codec encoder timings
+///
+/// Cheap to clone; the audio engine records into it and the control API and
+/// dashboard read snapshots.
+#[derive(Debug, Clone, Default)]
+pub struct EncoderMetrics {
+    totals: Arc<Mutex<HashMap<Codec, Totals>>>,
+}
+
+impl EncoderMetrics {
+    /// Create empty metrics
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record one encoded chunk that took `elapsed` for `audio` worth of samples
+    pub fn record(&self, codec: Codec, elapsed: Duration, audio: Duration) {
+        let mut totals = self.totals.lock();
+        let entry = totals.entry(codec).or_default();
+        entry.chunks += 1;
+        entry.total += elapsed;
+        entry.max = entry.max.max(elapsed);
+        entry.audio += audio;
+    }
+
+    /// Per-codec totals, sorted by codec name
+    pub fn snapshot(&self) -> Vec<EncoderStats> {
+        let mut stats: Vec<EncoderStats> = self
+            .totals
+            .lock()
+            .iter()
+            .map(|(codec, t)| EncoderStats {
+                codec: codec.name().to_string(),
+                chunks: t.chunks,
+                total_micros: t.total.as_micros() as u64,
+                mean_micros: t.total.as_micros() as f64 / t.chunks.max(1) as f64,
+                max_micros: t.max.as_micros() as u64,
+                load: if t.audio.is_zero() {
+                    0.0
+                } else {
+                    t.total.as_secs_f64() / t.audio.as_secs_f64()
+                },
+            })
+            .collect();
+        stats.sort_by(|a, b| a.codec.cmp(&b.codec));
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_per_codec() {
+        let metrics = EncoderMetrics::new();
+        let chunk = Duration::from_millis(20);
+        metrics.record(Codec::Pcm, Duration::from_micros(100), chunk);
+        metrics.record(Codec::Pcm, Duration::from_micros(300), chunk);
+        metrics.record(Codec::Flac, Duration::from_millis(10), chunk);
+
+        let stats = metrics.snapshot();
+        assert_eq!(stats.len(), 2);
+
+        assert_eq!(stats[0].codec, "flac");
+        assert_eq!(stats[0].load, 0.5);
+
+        let pcm = &stats[1];
+        assert_eq!(pcm.codec, "pcm");
+        assert_eq!(pcm.chunks, 2);
+        assert_eq!(pcm.total_micros, 400);
+        assert_eq!(pcm.mean_micros, 200.0);
+        assert_eq!(pcm.max_micros, 300);
+        assert_eq!(pcm.load, 0.01);
+    }
+}
@@ -0,0 +1,139 @@
+// ABOUTME: Extension point for application-specific protocol messages
+// ABOUTME: Downstream crates register handlers for message types the core protocol does not define
+
+use crate::server::roles::RoleContext;
+use parking_lot::RwLock;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Handles one application-specific message type
+///
+/// Implemented for closures taking the sender's context and the message payload.
+pub trait ExtensionHandler: Send + Sync {
+    /// Handle a message's payload (`null` if it had none)
+    fn handle(&self, ctx: &RoleContext, payload: Value);
+}
+
+impl<F> ExtensionHandler for F
+where
+    F: Fn(&RoleContext, Value) + Send + Sync,
+{
+    fn handle(&self, ctx: &RoleContext, payload: Value) {
+        self(ctx, payload)
+    }
+}
+
+/// Envelope of a message the core protocol did not recognize
+#[derive(Deserialize)]
+struct RawMessage {
+    #[serde(rename = "type")]
+    message_type: String,
+    #[serde(default)]
+    payload: Value,
+}
+
+/// Registry of handlers for vendor message types
+///
+/// Messages the core protocol parses never reach extensions, so vendor types
+/// should use an application-specific name, by convention prefixed with `_`
+/// (e.g. `_acme/eq`). Handlers can be registered while the server runs; reply
+/// with [`crate::server::ClientManager::send_custom`].
+#[derive(Clone, Default)]
+pub struct Extensions {
+    handlers: Arc<RwLock<HashMap<String, Arc<dyn ExtensionHandler>>>>,
+}
+
+impl Extensions {
+    /// Create an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Handle messages of `message_type` with `handler`, replacing any previous handler
+    pub fn register(
+        &self,
+        message_type: impl Into<String>,
+        handler: impl ExtensionHandler + 'static,
+    ) {
+        self.handlers
+            .write()
+            .insert(message_type.into(), Arc::new(handler));
+    }
+
+    /// Stop handling `message_type`, returning whether a handler was registered
+    pub fn unregister(&self, message_type: &str) -> bool {
+        self.handlers.write().remove(message_type).is_some()
+    }
+
+    /// Pass an unrecognized message to its handler
+    ///
+    /// Returns false if the text is not a message envelope or no handler is
+    /// registered for its type.
+    pub(crate) fn dispatch(&self, ctx: &RoleContext, text: &str) -> bool {
+        let Ok(raw) = serde_json::from_str::<RawMessage>(text) else {
+            return false;
+        };
+        // Release the lock before running the handler so it can (un)register
+        let handler = self.handlers.read().get(&raw.message_type).cloned();
+        match handler {
+            Some(handler) => {
+                handler.handle(ctx, raw.payload);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::client_manager::{ClientManager, ConnectedClient, ServerMessage};
+    use crate::server::clock::ServerClock;
+    use crate::server::group::GroupManager;
+    use crate::server::playback::PlaybackController;
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn test_dispatch_and_reply() {
+        let client_manager = Arc::new(ClientManager::new());
+        let group_manager = Arc::new(GroupManager::new());
+        let playback = PlaybackController::new(client_manager.clone(), group_manager.clone());
+        let clock = ServerClock::new();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        client_manager.add_client(ConnectedClient::new("p1".to_string(), "p1".to_string(), tx));
+        let client_id = "p1".to_string();
+        let ctx = RoleContext {
+            client_id: &client_id,
+            client_manager: &client_manager,
+            group_manager: &group_manager,
+            playback: &playback,
+            clock: &clock,
+            streams: None,
+        };
+
+        let extensions = Extensions::new();
+        extensions.register("_acme/ping", |ctx: &RoleContext, payload: Value| {
+            let reply = serde_json::json!({ "seq": payload["seq"] });
+            ctx.client_manager
+                .send_custom(ctx.client_id, "_acme/pong", &reply);
+        });
+
+        assert!(extensions.dispatch(&ctx, r#"{"type":"_acme/ping","payload":{"seq":7}}"#));
+        match rx.try_recv() {
+            Ok(ServerMessage::Text(text)) => {
+                let reply: Value = serde_json::from_str(&text).unwrap();
+                assert_eq!(reply["type"], "_acme/pong");
+                assert_eq!(reply["payload"]["seq"], 7);
+            }
+            _ => panic!("Expected reply"),
+        }
+
+        assert!(!extensions.dispatch(&ctx, r#"{"type":"_acme/other"}"#));
+        assert!(!extensions.dispatch(&ctx, "not json"));
+        assert!(extensions.unregister("_acme/ping"));
+        assert!(!extensions.dispatch(&ctx, r#"{"type":"_acme/ping"}"#));
+    }
+}
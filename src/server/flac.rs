@@ -0,0 +1,556 @@
+// ABOUTME: Chunk-aligned FLAC encoder
+// ABOUTME: Emits variable-blocksize FLAC frames covering each chunk so every chunk decodes on its own
+
+use crate::audio::dither::{DitherMode, Ditherer};
+use crate::audio::types::{Codec, Sample};
+use crate::server::encoder::AudioEncoder;
+
+/// Highest fixed-predictor order FLAC defines
+const MAX_FIXED_ORDER: usize = 4;
+
+/// Compression level used when none is set (matches the reference encoder)
+pub const DEFAULT_COMPRESSION_LEVEL: u8 = 5;
+
+/// Highest compression level accepted
+pub const MAX_COMPRESSION_LEVEL: u8 = 8;
+
+/// Smallest block size a variable-blocksize stream announces
+///
+/// Shorter chunks are padded with silence up to it, since decoders may reject
+/// a frame below the STREAMINFO minimum.
+const MIN_BLOCK_SIZE: u16 = 16;
+
+/// Headroom over the chunk size in the max block size, as a fraction of it
+///
+/// Resampled chunks run a frame or so over the nominal size; they still fit
+/// in one frame.
+const BLOCK_SIZE_HEADROOM: usize = 64;
+
+/// Largest Rice parameter with the 4-bit parameter encoding (15 is the escape code)
+const MAX_RICE_PARAMETER: u32 = 14;
+
+/// FLAC encoder producing self-contained frames
+///
+/// Each call to [`AudioEncoder::encode`] emits whole frames covering exactly
+/// the samples given, so a chunk never depends on audio in another chunk and a
+/// client can start decoding at any chunk after `stream/clear`. Frames use the
+/// variable-blocksize strategy, numbered by their first sample, because
+/// resampled chunks differ from the nominal chunk size by a frame or so. A
+/// chunk longer than [`FlacEncoder::max_block_size`] is split into near-equal
+/// frames, and one shorter than 16 frames is padded with silence. Subframes use the best FLAC fixed predictor
+/// with Rice-coded residuals, or verbatim samples when that is smaller; lower
+/// compression levels try fewer predictor orders to save CPU.
+pub struct FlacEncoder {
+    sample_rate: u32,
+    channels: u8,
+    bit_depth: u8,
+    block_size: u16,
+    compression_level: u8,
+    /// Number of the next frame's first sample per channel
+    sample_number: u64,
+    ditherer: Ditherer,
+}
+
+impl FlacEncoder {
+    /// Create a FLAC encoder with 20ms blocks, rounding without dither
+    pub fn new(sample_rate: u32, channels: u8, bit_depth: u8) -> Self {
+        let channels = channels.clamp(1, 8);
+        let bit_depth = bit_depth.clamp(8, 24);
+        Self {
+            sample_rate,
+            channels,
+            bit_depth,
+            block_size: (sample_rate / 50).clamp(16, u16::MAX as u32) as u16,
+            compression_level: DEFAULT_COMPRESSION_LEVEL,
+            sample_number: 0,
+            ditherer: Ditherer::new(DitherMode::Off, bit_depth, channels),
+        }
+    }
+
+    /// Dither samples when encoding below 24 bits
+    pub fn with_dither(mut self, mode: DitherMode) -> Self {
+        self.ditherer = Ditherer::new(mode, self.bit_depth, self.channels);
+        self
+    }
+
+    /// Use `frames` samples per channel per block (the audio chunk size)
+    pub fn with_block_size(mut self, frames: usize) -> Self {
+        self.block_size = frames.clamp(16, u16::MAX as usize) as u16;
+        self
+    }
+
+    /// Set the compression level (0 fastest to 8 smallest)
+    pub fn with_compression_level(mut self, level: u8) -> Self {
+        self.compression_level = level.min(MAX_COMPRESSION_LEVEL);
+        self
+    }
+
+    /// Nominal samples per channel in each frame (the chunk size)
+    pub fn block_size(&self) -> usize {
+        self.block_size as usize
+    }
+
+    /// Most samples per channel in one frame
+    pub fn max_block_size(&self) -> usize {
+        let block = self.block_size();
+        (block + block / BLOCK_SIZE_HEADROOM + 1).min(u16::MAX as usize)
+    }
+
+    /// Compression level in use
+    pub fn compression_level(&self) -> u8 {
+        self.compression_level
+    }
+
+    /// Highest fixed-predictor order tried at the current level
+    fn max_order(&self) -> usize {
+        match self.compression_level {
+            0 => 1,
+            1 | 2 => 2,
+            3 | 4 => 3,
+            _ => MAX_FIXED_ORDER,
+        }
+    }
+
+    fn sample_rate_code(&self) -> u8 {
+        match self.sample_rate {
+            88_200 => 0b0001,
+            176_400 => 0b0010,
+            192_000 => 0b0011,
+            8_000 => 0b0100,
+            16_000 => 0b0101,
+            22_050 => 0b0110,
+            24_000 => 0b0111,
+            32_000 => 0b1000,
+            44_100 => 0b1001,
+            48_000 => 0b1010,
+            96_000 => 0b1011,
+            // Taken from STREAMINFO
+            _ => 0b0000,
+        }
+    }
+
+    fn sample_size_code(&self) -> u8 {
+        match self.bit_depth {
+            8 => 0b001,
+            12 => 0b010,
+            16 => 0b100,
+            20 => 0b101,
+            24 => 0b110,
+            32 => 0b111,
+            _ => 0b000,
+        }
+    }
+
+    /// Encode one frame of interleaved samples already scaled to `bit_depth`
+    fn write_frame(&mut self, interleaved: &[i32], frames: usize) -> Vec<u8> {
+        let channels = self.channels as usize;
+        let mut w = BitWriter::default();
+
+        // Frame header: sync code, variable-blocksize strategy
+        w.write(0b1111_1111_1111_1001, 16);
+        w.write(0b0111, 4); // block size as 16-bit value at the end of the header
+        w.write(self.sample_rate_code() as u64, 4);
+        w.write(channels as u64 - 1, 4); // independent channels
+        w.write(self.sample_size_code() as u64, 3);
+        w.write(0, 1);
+        w.write_utf8(self.sample_number);
+        w.write(frames as u64 - 1, 16);
+        let crc = crc8(w.bytes());
+        w.write(crc as u64, 8);
+
+        let bps = self.bit_depth as u32;
+        let max_order = self.max_order();
+        let mut channel = Vec::with_capacity(frames);
+        for c in 0..channels {
+            channel.clear();
+            channel.extend(
+                interleaved
+                    .iter()
+                    .skip(c)
+                    .step_by(channels)
+                    .map(|&s| s as i64),
+            );
+            write_subframe(&mut w, &channel, bps, max_order);
+        }
+
+        w.align();
+        let crc = crc16(w.bytes());
+        w.write(crc as u64, 16);
+        self.sample_number += frames as u64;
+        w.into_bytes()
+    }
+}
+
+impl AudioEncoder for FlacEncoder {
+    fn encode(&mut self, samples: &[Sample]) -> Vec<u8> {
+        let channels = self.channels as usize;
+        let mut scaled = self.ditherer.quantize(samples);
+        if !scaled.is_empty() && scaled.len() < MIN_BLOCK_SIZE as usize * channels {
+            scaled.resize(MIN_BLOCK_SIZE as usize * channels, 0);
+        }
+
+        let total = scaled.len() / channels;
+        let count = total.div_ceil(self.max_block_size());
+        let mut out = Vec::new();
+        let mut start = 0;
+        for n in 0..count {
+            // Spread the remainder so no frame ends up much shorter than the rest
+            let frames = (total - start) / (count - n);
+            let end = start + frames;
+            out.extend(self.write_frame(&scaled[start * channels..end * channels], frames));
+            start = end;
+        }
+        out
+    }
+
+    fn codec(&self) -> Codec {
+        Codec::Flac
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> u8 {
+        self.channels
+    }
+
+    fn bit_depth(&self) -> u8 {
+        self.bit_depth
+    }
+
+    /// `fLaC` marker and STREAMINFO block
+    fn codec_header(&self) -> Option<Vec<u8>> {
+        let mut w = BitWriter::default();
+        w.write(u32::from_be_bytes(*b"fLaC") as u64, 32);
+        // Last metadata block, type STREAMINFO, 34 bytes
+        w.write(1, 1);
+        w.write(0, 7);
+        w.write(34, 24);
+        w.write(MIN_BLOCK_SIZE.min(self.block_size) as u64, 16);
+        w.write(self.max_block_size() as u64, 16);
+        w.write(0, 24); // min frame size unknown
+        w.write(0, 24); // max frame size unknown
+        w.write(self.sample_rate as u64, 20);
+        w.write(self.channels as u64 - 1, 3);
+        w.write(self.bit_depth as u64 - 1, 5);
+        w.write(0, 36); // total samples unknown (live stream)
+        w.write(0, 64); // no MD5 signature
+        w.write(0, 64);
+        Some(w.into_bytes())
+    }
+}
+
+/// Write the smallest of the fixed-predictor (up to `max_order`) and verbatim encodings
+fn write_subframe(w: &mut BitWriter, samples: &[i64], bps: u32, max_order: usize) {
+    let n = samples.len();
+    if samples.iter().all(|&s| s == samples[0]) {
+        w.write(0b0000_0000, 8); // CONSTANT
+        w.write_signed(samples[0], bps);
+        return;
+    }
+
+    let verbatim_bits = n as u64 * bps as u64;
+    let best = (0..=max_order.min(n.saturating_sub(1)))
+        .map(|order| {
+            let residual = fixed_residual(samples, order);
+            let (parameter, bits) = best_rice_parameter(&residual);
+            let total = order as u64 * bps as u64 + 6 + 4 + bits;
+            (order, residual, parameter, total)
+        })
+        .min_by_key(|(_, _, _, total)| *total);
+
+    match best {
+        Some((order, residual, parameter, total)) if total < verbatim_bits => {
+            w.write(0b0001_0000 | (order as u64) << 1, 8); // FIXED, no wasted bits
+            for &warm_up in &samples[..order] {
+                w.write_signed(warm_up, bps);
+            }
+            w.write(0b00, 2); // Rice coding with 4-bit parameters
+            w.write(0, 4); // one partition
+            w.write(parameter as u64, 4);
+            for &r in &residual {
+                w.write_rice(r, parameter);
+            }
+        }
+        _ => {
+            w.write(0b0000_0010, 8); // VERBATIM
+            for &s in samples {
+                w.write_signed(s, bps);
+            }
+        }
+    }
+}
+
+/// Residual of the FLAC fixed predictor of the given order
+fn fixed_residual(samples: &[i64], order: usize) -> Vec<i64> {
+    (order..samples.len())
+        .map(|i| {
+            let s = |k: usize| samples[i - k];
+            match order {
+                0 => s(0),
+                1 => s(0) - s(1),
+                2 => s(0) - 2 * s(1) + s(2),
+                3 => s(0) - 3 * s(1) + 3 * s(2) - s(3),
+                _ => s(0) - 4 * s(1) + 6 * s(2) - 4 * s(3) + s(4),
+            }
+        })
+        .collect()
+}
+
+/// Rice parameter with the fewest bits for these residuals, and that bit count
+fn best_rice_parameter(residual: &[i64]) -> (u32, u64) {
+    let zigzag: Vec<u64> = residual.iter().map(|&r| zigzag(r)).collect();
+    (0..=MAX_RICE_PARAMETER)
+        .map(|k| {
+            let bits = zigzag.iter().map(|&u| (u >> k) + 1 + k as u64).sum::<u64>();
+            (k, bits)
+        })
+        .min_by_key(|&(_, bits)| bits)
+        .unwrap_or((0, 0))
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+/// MSB-first bit writer
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    current: u64,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u64, bits: u32) {
+        for i in (0..bits).rev() {
+            self.current = (self.current << 1) | ((value >> i) & 1);
+            self.bits += 1;
+            if self.bits == 8 {
+                self.bytes.push(self.current as u8);
+                self.current = 0;
+                self.bits = 0;
+            }
+        }
+    }
+
+    fn write_signed(&mut self, value: i64, bits: u32) {
+        self.write(value as u64 & ((1u64 << bits) - 1), bits);
+    }
+
+    fn write_rice(&mut self, value: i64, parameter: u32) {
+        let u = zigzag(value);
+        let mut quotient = u >> parameter;
+        while quotient >= 32 {
+            self.write(0, 32);
+            quotient -= 32;
+        }
+        self.write(1, quotient as u32 + 1);
+        self.write(u & ((1u64 << parameter) - 1), parameter);
+    }
+
+    /// FLAC's UTF-8-like variable length integer
+    fn write_utf8(&mut self, value: u64) {
+        if value < 0x80 {
+            self.write(value, 8);
+            return;
+        }
+        // Continuation bytes carry 6 bits each; the lead byte carries 6 - extra
+        let bits = 64 - value.leading_zeros();
+        let extra = (1..=6u32).find(|&n| bits <= 5 * n + 6).unwrap_or(6);
+        let marker = (0xFF00u64 >> (extra + 1)) & 0xFF;
+        self.write(marker | (value >> (6 * extra)), 8);
+        for i in (0..extra).rev() {
+            self.write(0x80 | ((value >> (6 * i)) & 0x3F), 8);
+        }
+    }
+
+    fn align(&mut self) {
+        if self.bits > 0 {
+            self.write(0, 8 - self.bits);
+        }
+    }
+
+    /// Bytes completed so far
+    fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    fn into_bytes(mut self) -> Vec<u8> {
+        self.align();
+        self.bytes
+    }
+}
+
+/// CRC-8, polynomial x^8 + x^2 + x + 1
+fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |crc, &byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// CRC-16, polynomial x^16 + x^15 + x^2 + 1
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0u16, |crc, &byte| {
+        (0..8).fold(crc ^ ((byte as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use symphonia::core::audio::SampleBuffer;
+    use symphonia::core::codecs::DecoderOptions;
+    use symphonia::core::formats::FormatOptions;
+    use symphonia::core::io::MediaSourceStream;
+    use symphonia::core::meta::MetadataOptions;
+    use symphonia::core::probe::Hint;
+
+    fn decode(bytes: Vec<u8>) -> (Vec<i32>, usize) {
+        let stream =
+            MediaSourceStream::new(Box::new(std::io::Cursor::new(bytes)), Default::default());
+        let mut hint = Hint::new();
+        hint.with_extension("flac");
+        let probed = symphonia::default::get_probe()
+            .format(
+                &hint,
+                stream,
+                &FormatOptions::default(),
+                &MetadataOptions::default(),
+            )
+            .unwrap();
+        let mut format = probed.format;
+        let track = format.default_track().unwrap();
+        let mut decoder = symphonia::default::get_codecs()
+            .make(&track.codec_params, &DecoderOptions::default())
+            .unwrap();
+
+        let mut samples = Vec::new();
+        let mut packets = 0;
+        while let Ok(packet) = format.next_packet() {
+            let decoded = decoder.decode(&packet).unwrap();
+            let mut buf = SampleBuffer::<i32>::new(decoded.capacity() as u64, *decoded.spec());
+            buf.copy_interleaved_ref(decoded);
+            samples.extend_from_slice(buf.samples());
+            packets += 1;
+        }
+        (samples, packets)
+    }
+
+    fn chunk(frames: usize, offset: usize) -> Vec<Sample> {
+        (0..frames)
+            .flat_map(|i| {
+                let t = (offset + i) as f64 / 48_000.0;
+                let left = (t * 440.0 * std::f64::consts::TAU).sin() * 4_000_000.0;
+                [
+                    Sample::from_i24(left as i32),
+                    Sample::from_i24((left / 2.0) as i32),
+                ]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_each_chunk_is_one_lossless_frame() {
+        let mut encoder = FlacEncoder::new(48_000, 2, 24).with_block_size(960);
+        let header = encoder.codec_header().unwrap();
+        let chunks: Vec<Vec<Sample>> = (0..3).map(|n| chunk(960, n * 960)).collect();
+        let encoded: Vec<Vec<u8>> = chunks.iter().map(|c| encoder.encode(c)).collect();
+
+        // Compression actually happened
+        assert!(encoded[1].len() < 960 * 2 * 3);
+
+        // A later chunk decodes without any of the earlier ones
+        let mut stream = header.clone();
+        stream.extend_from_slice(&encoded[2]);
+        let (samples, packets) = decode(stream);
+        assert_eq!(packets, 1);
+        // symphonia scales 24-bit samples into the upper bits of an i32
+        let expected: Vec<i32> = chunks[2].iter().map(|s| s.to_i24() << 8).collect();
+        assert_eq!(samples, expected);
+    }
+
+    #[test]
+    fn test_chunks_of_varying_length_decode_in_sequence() {
+        // Resampled chunks drift a frame either side of the nominal size
+        let mut encoder = FlacEncoder::new(48_000, 2, 24).with_block_size(960);
+        let mut stream = encoder.codec_header().unwrap();
+        let mut input = Vec::new();
+        let mut offset = 0;
+        for frames in [959, 961, 960, 2000] {
+            let chunk = chunk(frames, offset);
+            stream.extend(encoder.encode(&chunk));
+            input.extend(chunk);
+            offset += frames;
+        }
+
+        let (samples, packets) = decode(stream);
+        // Only the chunk well over the max block size is split
+        assert_eq!(packets, 6);
+        let expected: Vec<i32> = input.iter().map(|s| s.to_i24() << 8).collect();
+        assert_eq!(samples, expected);
+    }
+
+    #[test]
+    fn test_compression_level_trades_size_for_speed() {
+        let input = chunk(960, 0);
+        let size = |level| {
+            let mut encoder = FlacEncoder::new(48_000, 2, 24).with_compression_level(level);
+            encoder.encode(&input).len()
+        };
+        assert!(size(8) < size(0));
+        assert_eq!(
+            FlacEncoder::new(48_000, 2, 24)
+                .with_compression_level(12)
+                .compression_level(),
+            MAX_COMPRESSION_LEVEL
+        );
+    }
+
+    #[test]
+    fn test_oversized_chunk_splits_into_even_frames() {
+        let mut encoder = FlacEncoder::new(44_100, 2, 16).with_block_size(441);
+        let mut stream = encoder.codec_header().unwrap();
+        let input = chunk(1000, 0);
+        stream.extend(encoder.encode(&input));
+        stream.extend(encoder.encode(&vec![Sample::ZERO; 441 * 2]));
+
+        let (samples, packets) = decode(stream);
+        assert_eq!(packets, 4);
+        assert_eq!(samples.len(), (1000 + 441) * 2);
+        assert_eq!(samples[0], (input[0].to_i16() as i32) << 16);
+        assert!(samples[2000..].iter().all(|&s| s == 0));
+    }
+
+    #[test]
+    fn test_short_chunk_is_padded_to_min_block_size() {
+        let mut encoder = FlacEncoder::new(48_000, 2, 24).with_block_size(960);
+        let header = encoder.codec_header().unwrap();
+        // STREAMINFO follows the marker and the 4-byte block header
+        let min_block_size = u16::from_be_bytes([header[8], header[9]]);
+        assert_eq!(min_block_size, MIN_BLOCK_SIZE);
+
+        let input = chunk(10, 0);
+        let mut stream = header;
+        stream.extend(encoder.encode(&input));
+        let (samples, packets) = decode(stream);
+        assert_eq!(packets, 1);
+        assert_eq!(samples.len(), min_block_size as usize * 2);
+        let expected: Vec<i32> = input.iter().map(|s| s.to_i24() << 8).collect();
+        assert_eq!(samples[..20], expected[..]);
+        assert!(samples[20..].iter().all(|&s| s == 0));
+    }
+}
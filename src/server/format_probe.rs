@@ -0,0 +1,227 @@
+// ABOUTME: Works out how a source's audio is converted into the server's output format
+// ABOUTME: Reports the conversion chain at startup and refuses conversions the server cannot do
+
+use crate::audio::downmix::MAX_CHANNELS;
+use crate::audio::types::Codec;
+use crate::server::config::ServerConfig;
+use crate::server::encoder::{EncoderParams, EncoderRegistry};
+use std::fmt;
+
+/// Bit depth sources are assumed to carry; float samples hold it losslessly
+const DECODED_BIT_DEPTH: u8 = 24;
+
+/// One stage between a source and what clients are sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConversionStep {
+    /// Decode the source to float samples
+    Decode {
+        /// Source sample rate in Hz
+        sample_rate: u32,
+        /// Source channel count
+        channels: u8,
+    },
+    /// Convert to the sample rate clients are sent
+    Resample {
+        /// Source sample rate in Hz
+        from: u32,
+        /// Output sample rate in Hz
+        to: u32,
+    },
+    /// Mix the audio to another channel count
+    Remix {
+        /// Channel count before
+        from: u8,
+        /// Channel count after
+        to: u8,
+    },
+    /// Sum stereo to a single channel
+    FoldToMono,
+    /// Change the sample width
+    Requantize {
+        /// Bits per sample before
+        from: u8,
+        /// Bits per sample after
+        to: u8,
+    },
+    /// Encode for clients
+    Encode {
+        /// Codec sent
+        codec: Codec,
+        /// Sample rate sent, in Hz
+        sample_rate: u32,
+    },
+}
+
+impl fmt::Display for ConversionStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConversionStep::Decode {
+                sample_rate,
+                channels,
+            } => write!(f, "decode {}Hz {}ch", sample_rate, channels),
+            ConversionStep::Resample { from, to } => write!(f, "resample {}Hz to {}Hz", from, to),
+            ConversionStep::Remix { from, to: 2 } => write!(f, "mix {}ch to stereo", from),
+            ConversionStep::Remix { from, to } => write!(f, "mix {}ch to {}ch", from, to),
+            ConversionStep::FoldToMono => f.write_str("fold to mono"),
+            ConversionStep::Requantize { from, to } => write!(f, "{}-bit to {}-bit", from, to),
+            ConversionStep::Encode { codec, sample_rate } => {
+                write!(f, "encode {} {}Hz", codec.name(), sample_rate)
+            }
+        }
+    }
+}
+
+/// Stages a source's audio goes through on its way to clients
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConversionChain {
+    /// Stages in the order they run
+    pub steps: Vec<ConversionStep>,
+}
+
+impl ConversionChain {
+    /// Whether the audio is changed beyond decoding and encoding
+    pub fn transcodes(&self) -> bool {
+        self.steps.iter().any(|step| {
+            !matches!(
+                step,
+                ConversionStep::Decode { .. } | ConversionStep::Encode { .. }
+            )
+        })
+    }
+}
+
+impl fmt::Display for ConversionChain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, step) in self.steps.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" -> ")?;
+            }
+            write!(f, "{}", step)?;
+        }
+        Ok(())
+    }
+}
+
+/// Plan how a source is converted to the format clients without their own
+/// format preferences are sent
+///
+/// A source at another rate than [`ServerConfig::default_sample_rate`] is
+/// resampled to it. Surround sources keep up to the downmix's
+/// `max_channels` and are then mixed to the output channel count. A fixed
+/// bit depth or channel count the encoder cannot produce is refused.
+pub fn plan_conversion(
+    sample_rate: u32,
+    channels: u8,
+    config: &ServerConfig,
+) -> Result<ConversionChain, String> {
+    let mut steps = vec![ConversionStep::Decode {
+        sample_rate,
+        channels,
+    }];
+    let output_rate = config.default_sample_rate;
+    if output_rate != sample_rate {
+        steps.push(ConversionStep::Resample {
+            from: sample_rate,
+            to: output_rate,
+        });
+    }
+    let output = config.default_channels;
+    if !(1..=MAX_CHANNELS).contains(&output) {
+        return Err(format!(
+            "cannot stream {} channels, at most {}",
+            output, MAX_CHANNELS
+        ));
+    }
+    let carried = config.downmix.source_channels(channels as usize) as u8;
+    if channels != carried {
+        steps.push(ConversionStep::Remix {
+            from: channels,
+            to: carried,
+        });
+    }
+    if output.max(2) != carried {
+        steps.push(ConversionStep::Remix {
+            from: carried,
+            to: output.max(2),
+        });
+    }
+    if output == 1 {
+        steps.push(ConversionStep::FoldToMono);
+    }
+    if config.default_bit_depth != DECODED_BIT_DEPTH {
+        steps.push(ConversionStep::Requantize {
+            from: DECODED_BIT_DEPTH,
+            to: config.default_bit_depth,
+        });
+    }
+
+    let params = EncoderParams {
+        sample_rate: output_rate,
+        channels: config.default_channels,
+        bit_depth: config.default_bit_depth,
+        chunk_frames: (output_rate as u64 * config.chunk_interval_ms / 1000) as usize,
+        settings: config.encoder_settings,
+    };
+    EncoderRegistry::default()
+        .create(Codec::Pcm.name(), params)
+        .map_err(|e| format!("cannot encode the output: {}", e))?;
+    steps.push(ConversionStep::Encode {
+        codec: Codec::Pcm,
+        sample_rate: output_rate,
+    });
+    Ok(ConversionChain { steps })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::downmix::DownmixLevels;
+
+    #[test]
+    fn test_chain_for_surround_source_at_16_bit() {
+        let config = ServerConfig::default().fixed_bit_depth(16);
+        let chain = plan_conversion(48000, 6, &config).unwrap();
+        assert!(chain.transcodes());
+        assert_eq!(
+            chain.to_string(),
+            "decode 48000Hz 6ch -> mix 6ch to stereo -> 24-bit to 16-bit -> encode pcm 48000Hz"
+        );
+
+        let passthrough = plan_conversion(48000, 2, &ServerConfig::default()).unwrap();
+        assert!(!passthrough.transcodes());
+    }
+
+    #[test]
+    fn test_surround_is_kept_up_to_max_channels() {
+        let mut config = ServerConfig::default().downmix(DownmixLevels {
+            max_channels: 6,
+            ..DownmixLevels::default()
+        });
+        let chain = plan_conversion(48000, 8, &config).unwrap();
+        assert_eq!(
+            chain.to_string(),
+            "decode 48000Hz 8ch -> mix 8ch to 6ch -> mix 6ch to stereo -> encode pcm 48000Hz"
+        );
+
+        config.default_channels = 6;
+        let surround = plan_conversion(48000, 6, &config).unwrap();
+        assert!(!surround.transcodes());
+    }
+
+    #[test]
+    fn test_other_rates_are_resampled() {
+        let config = ServerConfig::default().fixed_sample_rate(48000);
+        let chain = plan_conversion(44100, 2, &config).unwrap();
+        assert!(chain.transcodes());
+        assert_eq!(
+            chain.to_string(),
+            "decode 44100Hz 2ch -> resample 44100Hz to 48000Hz -> encode pcm 48000Hz"
+        );
+    }
+
+    #[test]
+    fn test_impossible_output_formats_are_refused() {
+        let odd_depth = ServerConfig::default().fixed_bit_depth(20);
+        assert!(plan_conversion(48000, 2, &odd_depth).is_err());
+    }
+}
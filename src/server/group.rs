@@ -1,12 +1,18 @@
 // ABOUTME: Group management for multi-room audio
 // ABOUTME: Handles grouping of clients for synchronized playback
 
+use crate::audio::drc::NightMode;
+use crate::audio::spatial::StereoWidth;
+use crate::server::encoder::EncoderSettings;
+use crate::server::stream_manager::DEFAULT_STREAM;
 use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
 /// Playback state of a group
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum PlaybackState {
     /// Not playing anything
     Stopped,
@@ -27,6 +33,60 @@ impl PlaybackState {
     }
 }
 
+/// What a group does when its first player connects
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum AutoStart {
+    /// Start playing as soon as a player joins
+    #[default]
+    Always,
+    /// Resume playing only if the group was playing when its last player left
+    Resume,
+    /// Stay stopped until a controller starts playback
+    Never,
+}
+
+/// A group declared up front, e.g. in the config file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GroupDefinition {
+    /// Unique group identifier
+    pub id: String,
+    /// Human-readable name (defaults to the ID)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Behavior when the first player joins (defaults to the server's policy)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auto_start: Option<AutoStart>,
+    /// Buffer-ahead override in milliseconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub buffer_ahead_ms: Option<u64>,
+    /// Stream the group plays (defaults to the default stream)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream: Option<String>,
+}
+
+/// Chunk timing a group's players are streamed with
+///
+/// Sent to players in `stream/start` so they can size their buffers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamTiming {
+    /// Duration of each audio chunk in milliseconds
+    pub chunk_interval_ms: u64,
+    /// How far ahead of its play time each chunk is sent, in milliseconds
+    pub buffer_ahead_ms: u64,
+}
+
+impl Default for StreamTiming {
+    /// The timing of an engine built from `ServerConfig::default()`
+    fn default() -> Self {
+        Self {
+            chunk_interval_ms: 20,
+            buffer_ahead_ms: 500,
+        }
+    }
+}
+
 /// A group of synchronized clients
 #[derive(Debug)]
 pub struct Group {
@@ -42,6 +102,20 @@ pub struct Group {
     pub volume: u8,
     /// Group mute state
     pub muted: bool,
+    /// Behavior when the first player joins
+    pub auto_start: AutoStart,
+    /// Whether the group was playing when its last player left
+    pub resume_playing: bool,
+    /// Buffer-ahead override in milliseconds (None uses the server default)
+    pub buffer_ahead_ms: Option<u64>,
+    /// Night-mode compression applied to this group's audio (None plays it unprocessed)
+    pub night_mode: Option<NightMode>,
+    /// Stereo width applied to this group's audio (None leaves the image untouched)
+    pub stereo_width: Option<StereoWidth>,
+    /// Stream the group is subscribed to (None plays the default stream)
+    pub stream: Option<String>,
+    /// Encoder tuning override (None uses the server defaults)
+    pub encoder_settings: Option<EncoderSettings>,
 }
 
 impl Group {
@@ -54,6 +128,13 @@ impl Group {
             playback_state: PlaybackState::Stopped,
             volume: 100,
             muted: false,
+            auto_start: AutoStart::default(),
+            resume_playing: false,
+            buffer_ahead_ms: None,
+            night_mode: None,
+            stereo_width: None,
+            stream: None,
+            encoder_settings: None,
         }
     }
 
@@ -90,6 +171,12 @@ pub struct GroupManager {
     groups: Arc<RwLock<HashMap<String, Group>>>,
     /// Default group ID
     default_group_id: String,
+    /// Auto-start policy for newly created groups
+    default_auto_start: AutoStart,
+    /// Buffer-ahead for groups without an override (None leaves it to the engine)
+    default_buffer_ahead: Arc<RwLock<Option<u64>>>,
+    /// Chunk interval and buffer-ahead the audio engines are configured with
+    engine_timing: StreamTiming,
 }
 
 impl GroupManager {
@@ -104,9 +191,27 @@ impl GroupManager {
         Self {
             groups: Arc::new(RwLock::new(groups)),
             default_group_id: default_id,
+            default_auto_start: AutoStart::default(),
+            default_buffer_ahead: Arc::new(RwLock::new(None)),
+            engine_timing: StreamTiming::default(),
         }
     }
 
+    /// Set the auto-start policy for the default group and groups created later
+    pub fn with_default_auto_start(mut self, policy: AutoStart) -> Self {
+        self.default_auto_start = policy;
+        self.set_auto_start(&self.default_group_id, policy);
+        self
+    }
+
+    /// Set the chunk interval and buffer-ahead the audio engines run with
+    ///
+    /// Groups without a buffer-ahead of their own report the engine's.
+    pub fn with_engine_timing(mut self, timing: StreamTiming) -> Self {
+        self.engine_timing = timing;
+        self
+    }
+
     /// Get the default group ID
     pub fn default_group_id(&self) -> &str {
         &self.default_group_id
@@ -115,11 +220,44 @@ impl GroupManager {
     /// Create a new group
     pub fn create_group(&self, id: impl Into<String>, name: impl Into<String>) -> String {
         let id = id.into();
-        let group = Group::new(&id, name);
+        let mut group = Group::new(&id, name);
+        group.auto_start = self.default_auto_start;
         self.groups.write().insert(id.clone(), group);
         id
     }
 
+    /// Create a declared group, or bring an existing one in line with its
+    /// declaration
+    ///
+    /// Settings the definition leaves out are not touched on an existing
+    /// group. The buffer-ahead is left to the caller, since changing it needs
+    /// the group's players told (see `PlaybackController::set_buffer_ahead`).
+    pub fn define_group(&self, definition: &GroupDefinition) {
+        let name = definition.name.as_deref().unwrap_or(&definition.id);
+        if self.get_group(&definition.id).is_none() {
+            self.create_group(&definition.id, name);
+        } else {
+            self.set_name(&definition.id, name);
+        }
+        if let Some(policy) = definition.auto_start {
+            self.set_auto_start(&definition.id, policy);
+        }
+        if definition.stream.is_some() {
+            self.set_stream(&definition.id, definition.stream.clone());
+        }
+    }
+
+    /// Rename a group
+    pub fn set_name(&self, group_id: &str, name: impl Into<String>) -> bool {
+        match self.groups.write().get_mut(group_id) {
+            Some(group) => {
+                group.name = name.into();
+                true
+            }
+            None => false,
+        }
+    }
+
     /// Delete a group (members will be moved to default group)
     pub fn delete_group(&self, group_id: &str) -> Vec<String> {
         if group_id == self.default_group_id {
@@ -187,9 +325,9 @@ impl GroupManager {
     /// Get group info by ID
     pub fn get_group(&self, group_id: &str) -> Option<(String, String, PlaybackState)> {
         let groups = self.groups.read();
-        groups.get(group_id).map(|g| {
-            (g.id.clone(), g.name.clone(), g.playback_state)
-        })
+        groups
+            .get(group_id)
+            .map(|g| (g.id.clone(), g.name.clone(), g.playback_state))
     }
 
     /// Set playback state for a group
@@ -204,6 +342,220 @@ impl GroupManager {
         self.groups.read().get(group_id).map(|g| g.playback_state)
     }
 
+    /// Set the auto-start policy for a group
+    pub fn set_auto_start(&self, group_id: &str, policy: AutoStart) {
+        if let Some(group) = self.groups.write().get_mut(group_id) {
+            group.auto_start = policy;
+        }
+    }
+
+    /// Get the auto-start policy for a group
+    pub fn get_auto_start(&self, group_id: &str) -> Option<AutoStart> {
+        self.groups.read().get(group_id).map(|g| g.auto_start)
+    }
+
+    /// Set a group's buffer-ahead override (None reverts to the server default)
+    pub fn set_buffer_ahead(&self, group_id: &str, buffer_ahead_ms: Option<u64>) -> bool {
+        match self.groups.write().get_mut(group_id) {
+            Some(group) => {
+                group.buffer_ahead_ms = buffer_ahead_ms;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Get a group's buffer-ahead override
+    pub fn get_buffer_ahead(&self, group_id: &str) -> Option<u64> {
+        self.groups.read().get(group_id)?.buffer_ahead_ms
+    }
+
+    /// Enable night mode with the given settings, or disable it with None
+    pub fn set_night_mode(&self, group_id: &str, night_mode: Option<NightMode>) -> bool {
+        match self.groups.write().get_mut(group_id) {
+            Some(group) => {
+                group.night_mode = night_mode;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Get a group's night-mode settings, if enabled
+    pub fn get_night_mode(&self, group_id: &str) -> Option<NightMode> {
+        self.groups.read().get(group_id)?.night_mode
+    }
+
+    /// Set a group's stereo width, or bypass width processing with None
+    pub fn set_stereo_width(&self, group_id: &str, stereo_width: Option<StereoWidth>) -> bool {
+        match self.groups.write().get_mut(group_id) {
+            Some(group) => {
+                group.stereo_width = stereo_width;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Get a group's stereo width, if width processing is active
+    pub fn get_stereo_width(&self, group_id: &str) -> Option<StereoWidth> {
+        self.groups.read().get(group_id)?.stereo_width
+    }
+
+    /// Subscribe a group to a stream, or back to the default stream with None
+    pub fn set_stream(&self, group_id: &str, stream_id: Option<String>) -> bool {
+        match self.groups.write().get_mut(group_id) {
+            Some(group) => {
+                group.stream = stream_id.filter(|id| id != DEFAULT_STREAM);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Get the stream a group is subscribed to
+    pub fn get_stream(&self, group_id: &str) -> Option<String> {
+        let groups = self.groups.read();
+        let group = groups.get(group_id)?;
+        Some(
+            group
+                .stream
+                .as_deref()
+                .unwrap_or(DEFAULT_STREAM)
+                .to_string(),
+        )
+    }
+
+    /// IDs of the groups subscribed to a stream
+    pub fn groups_on_stream(&self, stream_id: &str) -> Vec<String> {
+        self.groups
+            .read()
+            .values()
+            .filter(|g| g.stream.as_deref().unwrap_or(DEFAULT_STREAM) == stream_id)
+            .map(|g| g.id.clone())
+            .collect()
+    }
+
+    /// Override the encoder tuning for a group, or clear the override with None
+    pub fn set_encoder_settings(&self, group_id: &str, settings: Option<EncoderSettings>) -> bool {
+        match self.groups.write().get_mut(group_id) {
+            Some(group) => {
+                group.encoder_settings = settings;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Get a group's encoder tuning override
+    pub fn get_encoder_settings(&self, group_id: &str) -> Option<EncoderSettings> {
+        self.groups.read().get(group_id)?.encoder_settings
+    }
+
+    /// Change the buffer-ahead of groups without their own (None leaves it
+    /// to the audio engine's setting)
+    pub fn set_default_buffer_ahead(&self, buffer_ahead_ms: Option<u64>) {
+        *self.default_buffer_ahead.write() = buffer_ahead_ms;
+    }
+
+    /// The chunk interval and effective buffer-ahead of a group
+    ///
+    /// The buffer-ahead is the group's override, else the changed default, else
+    /// the engine's.
+    pub fn stream_timing(&self, group_id: &str) -> Option<StreamTiming> {
+        let buffer_ahead_ms = self.groups.read().get(group_id)?.buffer_ahead_ms;
+        Some(StreamTiming {
+            chunk_interval_ms: self.engine_timing.chunk_interval_ms,
+            buffer_ahead_ms: buffer_ahead_ms
+                .or(self.default_buffer_ahead())
+                .unwrap_or(self.engine_timing.buffer_ahead_ms),
+        })
+    }
+
+    /// Buffer-ahead of groups without their own, if changed since startup
+    pub fn default_buffer_ahead(&self) -> Option<u64> {
+        *self.default_buffer_ahead.read()
+    }
+
+    /// Get the ID, members and buffer-ahead of each playing group
+    ///
+    /// The buffer-ahead is the group's override or the changed default; None
+    /// means the engine's own setting.
+    pub fn playing_groups(&self) -> Vec<(String, HashSet<String>, Option<u64>)> {
+        let default = self.default_buffer_ahead();
+        self.groups
+            .read()
+            .values()
+            .filter(|g| g.playback_state == PlaybackState::Playing && !g.is_empty())
+            .map(|g| {
+                (
+                    g.id.clone(),
+                    g.members.clone(),
+                    g.buffer_ahead_ms.or(default),
+                )
+            })
+            .collect()
+    }
+
+    /// Like [`playing_groups`](Self::playing_groups), limited to groups
+    /// subscribed to one stream
+    pub fn playing_groups_on(
+        &self,
+        stream_id: &str,
+    ) -> Vec<(String, HashSet<String>, Option<u64>)> {
+        let default = self.default_buffer_ahead();
+        self.groups
+            .read()
+            .values()
+            .filter(|g| g.playback_state == PlaybackState::Playing && !g.is_empty())
+            .filter(|g| g.stream.as_deref().unwrap_or(DEFAULT_STREAM) == stream_id)
+            .map(|g| {
+                (
+                    g.id.clone(),
+                    g.members.clone(),
+                    g.buffer_ahead_ms.or(default),
+                )
+            })
+            .collect()
+    }
+
+    /// Get the members of all groups that are currently playing
+    pub fn playing_members(&self) -> HashSet<String> {
+        self.groups
+            .read()
+            .values()
+            .filter(|g| g.playback_state == PlaybackState::Playing)
+            .flat_map(|g| g.members.iter().cloned())
+            .collect()
+    }
+
+    /// Decide the playback state for a group whose first player just joined
+    ///
+    /// Applies the group's auto-start policy and returns the new state.
+    pub fn apply_auto_start(&self, group_id: &str) -> Option<PlaybackState> {
+        let mut groups = self.groups.write();
+        let group = groups.get_mut(group_id)?;
+        let start = match group.auto_start {
+            AutoStart::Always => true,
+            AutoStart::Resume => group.resume_playing,
+            AutoStart::Never => false,
+        };
+        if start {
+            group.playback_state = PlaybackState::Playing;
+        }
+        Some(group.playback_state)
+    }
+
+    /// Record that the last player left a group and stop it
+    ///
+    /// Remembers whether it was playing so `AutoStart::Resume` can pick up again.
+    pub fn suspend_group(&self, group_id: &str) {
+        if let Some(group) = self.groups.write().get_mut(group_id) {
+            group.resume_playing = group.playback_state == PlaybackState::Playing;
+            group.playback_state = PlaybackState::Stopped;
+        }
+    }
+
     /// Set volume for a group
     pub fn set_volume(&self, group_id: &str, volume: u8) {
         if let Some(group) = self.groups.write().get_mut(group_id) {
@@ -211,6 +563,19 @@ impl GroupManager {
         }
     }
 
+    /// Get a group's volume (0-100)
+    pub fn get_volume(&self, group_id: &str) -> Option<u8> {
+        self.groups.read().get(group_id).map(|g| g.volume)
+    }
+
+    /// Set whether a group resumes playing when its first player joins
+    /// under `AutoStart::Resume`
+    pub fn set_resume_playing(&self, group_id: &str, resume: bool) {
+        if let Some(group) = self.groups.write().get_mut(group_id) {
+            group.resume_playing = resume;
+        }
+    }
+
     /// Set mute state for a group
     pub fn set_muted(&self, group_id: &str, muted: bool) {
         if let Some(group) = self.groups.write().get_mut(group_id) {
@@ -218,6 +583,11 @@ impl GroupManager {
         }
     }
 
+    /// Get a group's mute state
+    pub fn get_muted(&self, group_id: &str) -> Option<bool> {
+        self.groups.read().get(group_id).map(|g| g.muted)
+    }
+
     /// Get all members of a group
     pub fn get_group_members(&self, group_id: &str) -> Vec<String> {
         self.groups
@@ -231,6 +601,16 @@ impl GroupManager {
     pub fn group_ids(&self) -> Vec<String> {
         self.groups.read().keys().cloned().collect()
     }
+
+    /// Iterate over all groups with a closure
+    pub fn for_each<F>(&self, mut f: F)
+    where
+        F: FnMut(&Group),
+    {
+        for group in self.groups.read().values() {
+            f(group);
+        }
+    }
 }
 
 impl Default for GroupManager {
@@ -244,6 +624,9 @@ impl Clone for GroupManager {
         Self {
             groups: Arc::clone(&self.groups),
             default_group_id: self.default_group_id.clone(),
+            default_auto_start: self.default_auto_start,
+            default_buffer_ahead: Arc::clone(&self.default_buffer_ahead),
+            engine_timing: self.engine_timing,
         }
     }
 }
@@ -274,15 +657,106 @@ mod tests {
 
         // Add client to default group
         manager.add_to_group("client1", "default");
-        assert_eq!(manager.get_client_group("client1"), Some("default".to_string()));
+        assert_eq!(
+            manager.get_client_group("client1"),
+            Some("default".to_string())
+        );
 
         // Create new group and move client
         manager.create_group("room1", "Living Room");
         manager.add_to_group("client1", "room1");
-        assert_eq!(manager.get_client_group("client1"), Some("room1".to_string()));
+        assert_eq!(
+            manager.get_client_group("client1"),
+            Some("room1".to_string())
+        );
 
         // Remove client
         manager.remove_client("client1");
         assert_eq!(manager.get_client_group("client1"), None);
     }
+
+    #[test]
+    fn test_auto_start_policies() {
+        let manager = GroupManager::new().with_default_auto_start(AutoStart::Never);
+        assert_eq!(manager.get_auto_start("default"), Some(AutoStart::Never));
+        assert_eq!(
+            manager.apply_auto_start("default"),
+            Some(PlaybackState::Stopped)
+        );
+
+        manager.create_group("room1", "Living Room");
+        assert_eq!(manager.get_auto_start("room1"), Some(AutoStart::Never));
+
+        // Resume only restarts a group that was playing when it emptied
+        manager.set_auto_start("room1", AutoStart::Resume);
+        assert_eq!(
+            manager.apply_auto_start("room1"),
+            Some(PlaybackState::Stopped)
+        );
+        manager.set_playback_state("room1", PlaybackState::Playing);
+        manager.suspend_group("room1");
+        assert_eq!(
+            manager.get_playback_state("room1"),
+            Some(PlaybackState::Stopped)
+        );
+        assert_eq!(
+            manager.apply_auto_start("room1"),
+            Some(PlaybackState::Playing)
+        );
+
+        manager.add_to_group("client1", "room1");
+        assert!(manager.playing_members().contains("client1"));
+    }
+
+    #[test]
+    fn test_buffer_ahead_override() {
+        let manager = GroupManager::new();
+        manager.create_group("bt", "Bluetooth Speakers");
+        manager.add_to_group("speaker1", "bt");
+        manager.set_playback_state("bt", PlaybackState::Playing);
+
+        assert_eq!(manager.get_buffer_ahead("bt"), None);
+        assert!(manager.set_buffer_ahead("bt", Some(2000)));
+        assert!(!manager.set_buffer_ahead("missing", Some(2000)));
+
+        let playing = manager.playing_groups();
+        assert_eq!(playing.len(), 1);
+        assert_eq!(playing[0].0, "bt");
+        assert!(playing[0].1.contains("speaker1"));
+        assert_eq!(playing[0].2, Some(2000));
+    }
+
+    #[test]
+    fn test_stream_timing_layers() {
+        let manager = GroupManager::new().with_engine_timing(StreamTiming {
+            chunk_interval_ms: 10,
+            buffer_ahead_ms: 300,
+        });
+        manager.create_group("bt", "Bluetooth Speakers");
+        let timing = |id| manager.stream_timing(id).map(|t| t.buffer_ahead_ms);
+
+        assert_eq!(manager.stream_timing("bt").unwrap().chunk_interval_ms, 10);
+        assert_eq!(timing("bt"), Some(300));
+        manager.set_default_buffer_ahead(Some(600));
+        assert_eq!(timing("bt"), Some(600));
+        manager.set_buffer_ahead("bt", Some(2000));
+        assert_eq!(timing("bt"), Some(2000));
+        assert_eq!(timing("missing"), None);
+    }
+
+    #[test]
+    fn test_night_mode_toggle() {
+        let manager = GroupManager::new();
+        manager.create_group("bedroom", "Bedroom");
+        assert_eq!(manager.get_night_mode("bedroom"), None);
+
+        assert!(manager.set_night_mode("bedroom", Some(NightMode::default())));
+        assert_eq!(
+            manager.get_night_mode("bedroom"),
+            Some(NightMode::default())
+        );
+        assert!(manager.set_night_mode("bedroom", None));
+        assert_eq!(manager.get_night_mode("bedroom"), None);
+        assert!(!manager.set_night_mode("missing", None));
+    }
 }
@@ -0,0 +1,363 @@
+// ABOUTME: Per-group aggregated statistics
+// ABOUTME: Combines client delivery counters and reported stats into one snapshot per group
+
+use crate::server::client_manager::ClientManager;
+use crate::server::group::GroupManager;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Shortest window used to compute throughput
+const MIN_RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Aggregated statistics for one group
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GroupStats {
+    /// Group identifier
+    pub group_id: String,
+    /// Human-readable group name
+    pub name: String,
+    /// Playback state ("playing", "paused", "stopped")
+    pub playback_state: String,
+    /// Connected members of the group
+    pub client_count: usize,
+    /// Connected members with the player role
+    pub player_count: usize,
+    /// Total bytes queued to current members
+    pub bytes_sent: u64,
+    /// Recent throughput to the group's members
+    pub bytes_per_second: f64,
+    /// Messages to current members that could not be queued
+    pub dropped: u64,
+    /// Smallest sync error reported by a member (microseconds)
+    pub min_sync_error_micros: Option<i64>,
+    /// Largest sync error reported by a member (microseconds)
+    pub max_sync_error_micros: Option<i64>,
+    /// How far apart the members are playing: largest minus smallest sync error
+    /// (microseconds)
+    pub sync_deviation_micros: Option<i64>,
+    /// Whether night-mode compression is applied to the group
+    pub night_mode: bool,
+}
+
+#[derive(Default)]
+struct RateState {
+    /// When the baseline counters were taken
+    since: Option<Instant>,
+    /// Bytes sent per group at the baseline
+    baseline: HashMap<String, u64>,
+    /// Last computed rate per group
+    rates: HashMap<String, f64>,
+}
+
+/// Computes per-group statistics for dashboards and APIs
+///
+/// One collector is meant to be shared (it is cheap to clone): throughput is
+/// measured between successive snapshots at least a second apart, so every
+/// consumer sees the same rates however often it polls.
+#[derive(Clone)]
+pub struct StatsCollector {
+    client_manager: Arc<ClientManager>,
+    group_manager: Arc<GroupManager>,
+    rates: Arc<Mutex<RateState>>,
+}
+
+impl StatsCollector {
+    /// Create a collector over the given managers
+    pub fn new(client_manager: Arc<ClientManager>, group_manager: Arc<GroupManager>) -> Self {
+        Self {
+            client_manager,
+            group_manager,
+            rates: Arc::new(Mutex::new(RateState::default())),
+        }
+    }
+
+    /// Take a snapshot of every group, sorted by group ID
+    pub fn group_stats(&self) -> Vec<GroupStats> {
+        self.snapshot_at(Instant::now())
+    }
+
+    fn snapshot_at(&self, now: Instant) -> Vec<GroupStats> {
+        struct ClientTotals {
+            is_player: bool,
+            bytes_sent: u64,
+            dropped: u64,
+            sync_error_micros: Option<i64>,
+        }
+
+        let mut clients = HashMap::new();
+        self.client_manager.for_each(|client| {
+            clients.insert(
+                client.client_id.clone(),
+                ClientTotals {
+                    is_player: client.is_player(),
+                    bytes_sent: client.counters.bytes_sent(),
+                    dropped: client.counters.dropped(),
+                    sync_error_micros: client.stats.as_ref().and_then(|s| s.sync_error_micros),
+                },
+            );
+        });
+
+        let mut snapshot: Vec<GroupStats> = self
+            .group_manager
+            .group_ids()
+            .into_iter()
+            .filter_map(|group_id| {
+                let (group_id, name, state) = self.group_manager.get_group(&group_id)?;
+                let night_mode = self.group_manager.get_night_mode(&group_id).is_some();
+                let mut stats = GroupStats {
+                    group_id,
+                    name,
+                    playback_state: state.as_str().to_string(),
+                    night_mode,
+                    ..Default::default()
+                };
+                for member in self.group_manager.get_group_members(&stats.group_id) {
+                    let Some(client) = clients.get(&member) else {
+                        continue;
+                    };
+                    stats.client_count += 1;
+                    stats.player_count += client.is_player as usize;
+                    stats.bytes_sent += client.bytes_sent;
+                    stats.dropped += client.dropped;
+                    if let Some(error) = client.sync_error_micros {
+                        stats.min_sync_error_micros =
+                            Some(stats.min_sync_error_micros.map_or(error, |m| m.min(error)));
+                        stats.max_sync_error_micros =
+                            Some(stats.max_sync_error_micros.map_or(error, |m| m.max(error)));
+                    }
+                }
+                stats.sync_deviation_micros = stats
+                    .max_sync_error_micros
+                    .zip(stats.min_sync_error_micros)
+                    .map(|(max, min)| max - min);
+                Some(stats)
+            })
+            .collect();
+        snapshot.sort_by(|a, b| a.group_id.cmp(&b.group_id));
+
+        self.update_rates(&mut snapshot, now);
+        snapshot
+    }
+
+    fn update_rates(&self, snapshot: &mut [GroupStats], now: Instant) {
+        let mut state = self.rates.lock();
+        let elapsed = state.since.map(|since| now.duration_since(since));
+
+        match elapsed {
+            Some(elapsed) if elapsed < MIN_RATE_WINDOW => {}
+            _ => {
+                let secs = elapsed.map(|e| e.as_secs_f64());
+                let mut rates = HashMap::new();
+                for stats in snapshot.iter() {
+                    let rate = match (secs, state.baseline.get(&stats.group_id)) {
+                        (Some(secs), Some(&before)) => {
+                            stats.bytes_sent.saturating_sub(before) as f64 / secs
+                        }
+                        _ => 0.0,
+                    };
+                    rates.insert(stats.group_id.clone(), rate);
+                }
+                state.baseline = snapshot
+                    .iter()
+                    .map(|s| (s.group_id.clone(), s.bytes_sent))
+                    .collect();
+                state.rates = rates;
+                state.since = Some(now);
+            }
+        }
+
+        for stats in snapshot.iter_mut() {
+            stats.bytes_per_second = state.rates.get(&stats.group_id).copied().unwrap_or(0.0);
+        }
+    }
+}
+
+/// Spawn a task that warns when a group's members drift too far apart
+///
+/// Every `interval` the group snapshot is checked against `threshold_micros`;
+/// a warning is logged when a group's sync deviation first exceeds it, and a
+/// notice once it is back within bounds.
+pub fn spawn_sync_monitor(
+    stats: StatsCollector,
+    threshold_micros: i64,
+    interval: Duration,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        let mut out_of_sync = HashSet::new();
+        loop {
+            ticker.tick().await;
+            let snapshot = stats.group_stats();
+            for (group, exceeded) in deviation_changes(&snapshot, threshold_micros, &out_of_sync) {
+                let deviation = snapshot
+                    .iter()
+                    .find(|g| g.group_id == group)
+                    .and_then(|g| g.sync_deviation_micros)
+                    .unwrap_or(0);
+                if exceeded {
+                    log::warn!(
+                        "Group {} members are {:.1}ms apart (threshold {:.1}ms)",
+                        group,
+                        deviation as f64 / 1000.0,
+                        threshold_micros as f64 / 1000.0
+                    );
+                    out_of_sync.insert(group);
+                } else {
+                    log::info!("Group {} back in sync", group);
+                    out_of_sync.remove(&group);
+                }
+            }
+        }
+    })
+}
+
+/// Groups whose over-threshold state differs from `out_of_sync`
+///
+/// Returns each changed group with `true` when it now exceeds the threshold.
+/// Groups that disappeared from the snapshot are reported as recovered.
+fn deviation_changes(
+    snapshot: &[GroupStats],
+    threshold_micros: i64,
+    out_of_sync: &HashSet<String>,
+) -> Vec<(String, bool)> {
+    let exceeding: HashSet<&String> = snapshot
+        .iter()
+        .filter(|g| {
+            g.sync_deviation_micros
+                .is_some_and(|d| d > threshold_micros)
+        })
+        .map(|g| &g.group_id)
+        .collect();
+    let mut changes: Vec<(String, bool)> = exceeding
+        .iter()
+        .filter(|group| !out_of_sync.contains(**group))
+        .map(|group| ((*group).clone(), true))
+        .collect();
+    changes.extend(
+        out_of_sync
+            .iter()
+            .filter(|group| !exceeding.contains(group))
+            .map(|group| (group.clone(), false)),
+    );
+    changes.sort();
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::stats::ClientStats;
+    use crate::server::client_manager::{ConnectedClient, ServerMessage};
+    use tokio::sync::mpsc;
+
+    fn add_player(
+        client_manager: &ClientManager,
+        group_manager: &GroupManager,
+        id: &str,
+        group: &str,
+    ) -> mpsc::UnboundedReceiver<ServerMessage> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut client = ConnectedClient::new(id.to_string(), id.to_string(), tx);
+        client.active_roles = vec!["player@v1".to_string()];
+        client_manager.add_client(client);
+        group_manager.add_to_group(id, group);
+        rx
+    }
+
+    #[test]
+    fn test_group_aggregates() {
+        let client_manager = Arc::new(ClientManager::new());
+        let group_manager = Arc::new(GroupManager::new());
+        group_manager.create_group("kitchen", "Kitchen");
+
+        let _a = add_player(&client_manager, &group_manager, "a", "kitchen");
+        let b = add_player(&client_manager, &group_manager, "b", "kitchen");
+        let _c = add_player(&client_manager, &group_manager, "c", "default");
+
+        for (id, error) in [("a", -300), ("b", 1200)] {
+            let stats = ClientStats {
+                sync_error_micros: Some(error),
+                ..Default::default()
+            };
+            client_manager.update_stats(id, stats);
+        }
+
+        let members = ["a".to_string(), "b".to_string()].into_iter().collect();
+        client_manager.broadcast_audio_to(&members, vec![0u8; 100]);
+        drop(b);
+        client_manager.broadcast_audio_to(&members, vec![0u8; 100]);
+
+        let collector = StatsCollector::new(client_manager, group_manager);
+        let stats = collector.group_stats();
+        assert_eq!(stats.len(), 2);
+
+        let kitchen = stats.iter().find(|s| s.group_id == "kitchen").unwrap();
+        assert_eq!(kitchen.client_count, 2);
+        assert_eq!(kitchen.player_count, 2);
+        assert_eq!(kitchen.bytes_sent, 300);
+        assert_eq!(kitchen.dropped, 1);
+        assert_eq!(kitchen.min_sync_error_micros, Some(-300));
+        assert_eq!(kitchen.max_sync_error_micros, Some(1200));
+        assert_eq!(kitchen.sync_deviation_micros, Some(1500));
+
+        let default = stats.iter().find(|s| s.group_id == "default").unwrap();
+        assert_eq!(default.client_count, 1);
+        assert_eq!(default.bytes_sent, 0);
+        assert_eq!(default.min_sync_error_micros, None);
+        assert_eq!(default.sync_deviation_micros, None);
+    }
+
+    #[test]
+    fn test_rate_between_snapshots() {
+        let client_manager = Arc::new(ClientManager::new());
+        let group_manager = Arc::new(GroupManager::new());
+        let _rx = add_player(&client_manager, &group_manager, "a", "default");
+        let collector = StatsCollector::new(client_manager.clone(), group_manager);
+
+        let start = Instant::now();
+        assert_eq!(collector.snapshot_at(start)[0].bytes_per_second, 0.0);
+
+        let members = ["a".to_string()].into_iter().collect();
+        client_manager.broadcast_audio_to(&members, vec![0u8; 1000]);
+
+        // Within the minimum window the previous rate is kept
+        let snapshot = collector.snapshot_at(start + Duration::from_millis(500));
+        assert_eq!(snapshot[0].bytes_per_second, 0.0);
+
+        let snapshot = collector.snapshot_at(start + Duration::from_secs(2));
+        assert_eq!(snapshot[0].bytes_per_second, 500.0);
+    }
+
+    #[test]
+    fn test_deviation_changes() {
+        let group = |id: &str, deviation: Option<i64>| GroupStats {
+            group_id: id.to_string(),
+            sync_deviation_micros: deviation,
+            ..Default::default()
+        };
+        let snapshot = [
+            group("a", Some(8000)),
+            group("b", Some(1000)),
+            group("c", None),
+        ];
+
+        let mut out_of_sync = HashSet::new();
+        assert_eq!(
+            deviation_changes(&snapshot, 5000, &out_of_sync),
+            vec![("a".to_string(), true)]
+        );
+
+        // Already reported: no change until it recovers
+        out_of_sync.insert("a".to_string());
+        assert!(deviation_changes(&snapshot, 5000, &out_of_sync).is_empty());
+
+        let recovered = [group("a", Some(2000))];
+        assert_eq!(
+            deviation_changes(&recovered, 5000, &out_of_sync),
+            vec![("a".to_string(), false)]
+        );
+    }
+}
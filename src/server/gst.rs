@@ -0,0 +1,133 @@
+// ABOUTME: Stream source playing the output of an arbitrary GStreamer pipeline
+// ABOUTME: Runs gst-launch-1.0 and reads its decoded PCM, for RTSP, DVB and hardware decoders
+
+use crate::audio::types::Sample;
+use crate::protocol::messages::AudioFormatSpec;
+use crate::server::audio_source::AudioSource;
+use crate::server::ingest::{ingest_source, IngestSource};
+use crate::server::pipe::{pump_pcm, PipeFormat};
+use std::process::{Child, Command, Stdio};
+
+/// URI scheme for [`GstSource`]s: `gst:rtspsrc location=rtsp://cam/audio ! decodebin`
+pub const GST_SCHEME: &str = "gst:";
+
+/// Program that runs the pipeline
+pub const GST_LAUNCH: &str = "gst-launch-1.0";
+
+/// Rate the pipeline's audio is resampled to
+pub const GST_SAMPLE_RATE: u32 = 48000;
+
+/// Arguments running `pipeline` with its audio converted to raw PCM on stdout
+///
+/// The pipeline must end in decoded audio; it is followed by a conversion to
+/// interleaved 16-bit stereo at `sample_rate`.
+pub fn launch_args(pipeline: &str, sample_rate: u32) -> Vec<String> {
+    vec![
+        "-q".to_string(),
+        format!(
+            "{} ! audioconvert ! audioresample \
+             ! audio/x-raw,format=S16LE,layout=interleaved,rate={},channels=2 \
+             ! fdsink fd=1",
+            pipeline.trim().trim_end_matches('!').trim(),
+            sample_rate
+        ),
+    ]
+}
+
+/// Stream source playing what a GStreamer pipeline decodes
+///
+/// The pipeline runs in a `gst-launch-1.0` child process, so GStreamer must be
+/// installed but is not linked. Its audio is buffered like a source client's,
+/// and the source ends when the pipeline does. Dropping the source stops it.
+pub struct GstSource {
+    pipeline: String,
+    input: IngestSource,
+    child: Child,
+}
+
+impl GstSource {
+    /// Start `pipeline` (a `gst-launch-1.0` description ending in decoded audio)
+    pub fn open(pipeline: &str) -> Result<Self, String> {
+        let format = PipeFormat {
+            sample_rate: GST_SAMPLE_RATE,
+            bit_depth: 16,
+            channels: 2,
+        };
+        let mut child = Command::new(GST_LAUNCH)
+            .args(launch_args(pipeline, format.sample_rate))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("cannot run {}: {}", GST_LAUNCH, e))?;
+        let mut stdout = child.stdout.take().ok_or("pipeline has no stdout")?;
+
+        let spec = AudioFormatSpec {
+            codec: "pcm".to_string(),
+            channels: format.channels,
+            sample_rate: format.sample_rate,
+            bit_depth: format.bit_depth,
+        };
+        let (input, feed) = ingest_source(pipeline, &spec)?;
+        let label = pipeline.to_string();
+        std::thread::Builder::new()
+            .name("audio-gst".to_string())
+            .spawn(move || {
+                pump_pcm(&label, &mut stdout, format, &feed);
+                log::info!("GStreamer pipeline '{}' ended", label);
+            })
+            .map_err(|e| format!("cannot start pipeline reader: {}", e))?;
+        log::info!("Running GStreamer pipeline '{}'", pipeline);
+        Ok(Self {
+            pipeline: pipeline.to_string(),
+            input,
+            child,
+        })
+    }
+}
+
+impl Drop for GstSource {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl AudioSource for GstSource {
+    fn read_chunk(&mut self, samples_per_channel: usize) -> Option<Vec<Sample>> {
+        self.input.read_chunk(samples_per_channel)
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn channels(&self) -> u8 {
+        2
+    }
+
+    fn is_exhausted(&self) -> bool {
+        self.input.is_exhausted()
+    }
+
+    fn description(&self) -> Option<String> {
+        Some(format!("gstreamer {}", self.pipeline))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_launch_args_end_in_raw_pcm_on_stdout() {
+        let args = launch_args("uridecodebin uri=rtsp://cam/audio ! ", 44100);
+        assert_eq!(args[0], "-q");
+        assert!(
+            args[1].starts_with("uridecodebin uri=rtsp://cam/audio ! audioconvert"),
+            "{}",
+            args[1]
+        );
+        assert!(args[1].contains("rate=44100,channels=2"));
+        assert!(args[1].ends_with("! fdsink fd=1"));
+    }
+}
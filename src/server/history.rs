@@ -0,0 +1,452 @@
+// ABOUTME: Playback history: which tracks played, when, and on which groups
+// ABOUTME: Records tracks from source events into a bounded store, optionally persisted as JSON lines
+
+use crate::server::group::GroupManager;
+use crate::server::source_events::{SourceEvent, SourceEvents, TrackInfo};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast::error::RecvError;
+
+/// Tracks remembered by default before the oldest is dropped
+pub const DEFAULT_HISTORY_SIZE: usize = 500;
+
+/// How often the recorder notes which groups are playing the current track
+pub const DEFAULT_HISTORY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// One track in the playback history
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlayRecord {
+    /// Sequence number, increasing across restarts when the history is persisted
+    pub id: u64,
+    /// Source description (file path, URL, ...)
+    pub source: Option<String>,
+    /// Tags of the track, as last known while it played
+    #[serde(default)]
+    pub track: TrackInfo,
+    /// When the track started, in milliseconds since the Unix epoch
+    pub started_at_ms: u64,
+    /// When the track ended, or None while it is still playing
+    pub ended_at_ms: Option<u64>,
+    /// Groups that played any part of the track, sorted
+    pub groups: Vec<String>,
+}
+
+impl PlayRecord {
+    /// How long the track has played, up to `now_ms` if it has not ended
+    pub fn played_ms(&self, now_ms: u64) -> u64 {
+        self.ended_at_ms
+            .unwrap_or(now_ms)
+            .saturating_sub(self.started_at_ms)
+    }
+}
+
+/// Filter for [`PlaybackHistory::query`]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryQuery {
+    /// Only tracks played on this group
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// Only tracks started at or after this time (milliseconds since the Unix epoch)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub since_ms: Option<u64>,
+    /// Return at most this many tracks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    records: VecDeque<PlayRecord>,
+    /// Groups heard playing the current track
+    current_groups: BTreeSet<String>,
+    capacity: usize,
+    next_id: u64,
+    path: Option<PathBuf>,
+    /// Lines in the file, which is compacted once it holds twice the capacity
+    persisted: usize,
+}
+
+/// Bounded history of played tracks, newest last
+///
+/// The last record is the current track until the next one starts. Tracks
+/// that no group played are dropped when they end. With a file, finished
+/// tracks are appended to it as JSON lines and read back on
+/// [`open`](Self::open). Cheap to clone; clones share the same history.
+#[derive(Debug, Clone)]
+pub struct PlaybackHistory {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Default for PlaybackHistory {
+    fn default() -> Self {
+        Self::new(DEFAULT_HISTORY_SIZE)
+    }
+}
+
+impl PlaybackHistory {
+    /// Keep up to `capacity` tracks in memory only
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                records: VecDeque::new(),
+                current_groups: BTreeSet::new(),
+                capacity: capacity.max(1),
+                next_id: 1,
+                path: None,
+                persisted: 0,
+            })),
+        }
+    }
+
+    /// Keep up to `capacity` tracks, persisted in the JSON lines file at `path`
+    ///
+    /// Tracks already in the file are loaded; lines that fail to parse are
+    /// skipped. The file is rewritten without the tracks beyond `capacity`.
+    pub fn open(path: impl Into<PathBuf>, capacity: usize) -> io::Result<Self> {
+        let path = path.into();
+        let history = Self::new(capacity);
+        let loaded = match File::open(&path) {
+            Ok(file) => read_records(BufReader::new(file), &path),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        {
+            let mut inner = history.inner.lock();
+            let skip = loaded.len().saturating_sub(inner.capacity);
+            inner.next_id = loaded.iter().map(|r| r.id + 1).max().unwrap_or(1);
+            inner.records = loaded.into_iter().skip(skip).collect();
+            inner.persisted = inner.records.len();
+            if skip > 0 {
+                write_records(&path, &inner.records)?;
+            } else if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+                fs::create_dir_all(parent)?;
+            }
+            inner.path = Some(path);
+        }
+        Ok(history)
+    }
+
+    /// Start recording a new track, ending the current one
+    pub fn start(
+        &self,
+        source: Option<String>,
+        track: TrackInfo,
+        groups: impl IntoIterator<Item = String>,
+        now_ms: u64,
+    ) {
+        let mut inner = self.inner.lock();
+        inner.finish(now_ms);
+        let id = inner.next_id;
+        inner.next_id += 1;
+        inner.current_groups = groups.into_iter().collect();
+        let record = PlayRecord {
+            id,
+            source,
+            track,
+            started_at_ms: now_ms,
+            ended_at_ms: None,
+            groups: inner.current_groups.iter().cloned().collect(),
+        };
+        inner.records.push_back(record);
+        while inner.records.len() > inner.capacity {
+            inner.records.pop_front();
+        }
+    }
+
+    /// Start a new track from the same source under a new title, as live
+    /// streams do when they announce the next song
+    pub fn retitle(&self, title: String, groups: impl IntoIterator<Item = String>, now_ms: u64) {
+        let source = self.current().and_then(|record| record.source);
+        let track = TrackInfo {
+            title: Some(title),
+            ..Default::default()
+        };
+        self.start(source, track, groups, now_ms);
+    }
+
+    /// Merge newly read tags into the current track
+    pub fn update_tags(&self, tags: TrackInfo) {
+        if let Some(current) = self.inner.lock().current_mut() {
+            current.track.merge(tags);
+        }
+    }
+
+    /// Note groups playing the current track
+    pub fn add_groups(&self, groups: impl IntoIterator<Item = String>) {
+        let mut inner = self.inner.lock();
+        if inner.current_mut().is_none() {
+            return;
+        }
+        let before = inner.current_groups.len();
+        inner.current_groups.extend(groups);
+        if inner.current_groups.len() != before {
+            let groups = inner.current_groups.iter().cloned().collect();
+            if let Some(current) = inner.current_mut() {
+                current.groups = groups;
+            }
+        }
+    }
+
+    /// End the current track, e.g. on shutdown
+    pub fn finish(&self, now_ms: u64) {
+        self.inner.lock().finish(now_ms);
+    }
+
+    /// The track playing now, if any
+    pub fn current(&self) -> Option<PlayRecord> {
+        self.inner.lock().current_mut().cloned()
+    }
+
+    /// Tracks matching `query`, newest first
+    pub fn query(&self, query: &HistoryQuery) -> Vec<PlayRecord> {
+        self.inner
+            .lock()
+            .records
+            .iter()
+            .rev()
+            .filter(|record| {
+                query
+                    .since_ms
+                    .is_none_or(|since| record.started_at_ms >= since)
+            })
+            .filter(|record| {
+                query
+                    .group
+                    .as_ref()
+                    .is_none_or(|group| record.groups.contains(group))
+            })
+            .take(query.limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect()
+    }
+
+    /// Number of tracks remembered, including the current one
+    pub fn len(&self) -> usize {
+        self.inner.lock().records.len()
+    }
+
+    /// Whether nothing has been recorded
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Inner {
+    fn current_mut(&mut self) -> Option<&mut PlayRecord> {
+        self.records
+            .back_mut()
+            .filter(|record| record.ended_at_ms.is_none())
+    }
+
+    /// End the current track, keeping it only if some group played it
+    fn finish(&mut self, now_ms: u64) {
+        let Some(current) = self.current_mut() else {
+            return;
+        };
+        current.ended_at_ms = Some(now_ms);
+        if current.groups.is_empty() {
+            self.records.pop_back();
+            return;
+        }
+        let Some(path) = &self.path else {
+            return;
+        };
+        let result = if self.persisted >= self.capacity * 2 {
+            self.persisted = self.records.len();
+            write_records(path, &self.records)
+        } else {
+            self.persisted += 1;
+            append_record(path, self.records.back().expect("current track present"))
+        };
+        if let Err(e) = result {
+            log::warn!("Cannot write playback history {}: {}", path.display(), e);
+        }
+    }
+}
+
+fn read_records(reader: impl BufRead, path: &Path) -> Vec<PlayRecord> {
+    let mut records = Vec::new();
+    for (number, line) in reader.lines().map_while(Result::ok).enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(record) => records.push(record),
+            Err(e) => log::warn!(
+                "Skipping line {} of playback history {}: {}",
+                number + 1,
+                path.display(),
+                e
+            ),
+        }
+    }
+    records
+}
+
+fn append_record(path: &Path, record: &PlayRecord) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    let line = serde_json::to_string(record).map_err(io::Error::other)?;
+    writeln!(file, "{}", line)
+}
+
+fn write_records(path: &Path, records: &VecDeque<PlayRecord>) -> io::Result<()> {
+    let mut contents = String::new();
+    for record in records {
+        contents.push_str(&serde_json::to_string(record).map_err(io::Error::other)?);
+        contents.push('\n');
+    }
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, contents)?;
+    fs::rename(tmp, path)
+}
+
+/// Milliseconds since the Unix epoch
+pub fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+fn playing_group_ids(group_manager: &GroupManager) -> Vec<String> {
+    group_manager
+        .playing_groups()
+        .into_iter()
+        .map(|(id, _, _)| id)
+        .collect()
+}
+
+/// Spawn a task recording tracks from `events` into `history`
+///
+/// Every `interval` the groups playing are added to the current track, so a
+/// group that plays only part of a track is still credited with it.
+pub fn spawn_history_recorder(
+    history: PlaybackHistory,
+    events: SourceEvents,
+    group_manager: Arc<GroupManager>,
+    interval: Duration,
+) -> tokio::task::JoinHandle<()> {
+    let mut rx = events.subscribe();
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            tokio::select! {
+                event = rx.recv() => match event {
+                    Ok(SourceEvent::TrackChanged { source, track }) => {
+                        let groups = playing_group_ids(&group_manager);
+                        history.start(source, track, groups, unix_millis());
+                    }
+                    Ok(SourceEvent::Tags(tags)) => history.update_tags(tags),
+                    Ok(SourceEvent::StreamTitle(title)) => {
+                        let groups = playing_group_ids(&group_manager);
+                        history.retitle(title, groups, unix_millis());
+                    }
+                    Ok(_) => {}
+                    Err(RecvError::Lagged(missed)) => {
+                        log::warn!("Playback history missed {} source events", missed);
+                    }
+                    Err(RecvError::Closed) => break,
+                },
+                _ = ticker.tick() => history.add_groups(playing_group_ids(&group_manager)),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn title(title: &str) -> TrackInfo {
+        TrackInfo {
+            title: Some(title.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn groups(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn test_records_tracks_and_groups() {
+        let history = PlaybackHistory::new(10);
+        history.start(
+            Some("a.flac".into()),
+            title("A"),
+            groups(&["kitchen"]),
+            1_000,
+        );
+        history.update_tags(TrackInfo {
+            artist: Some("Band".to_string()),
+            ..Default::default()
+        });
+        history.add_groups(groups(&["den"]));
+        history.start(Some("b.flac".into()), title("B"), groups(&["den"]), 5_000);
+
+        let records = history.query(&HistoryQuery::default());
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].track, title("B"));
+        assert_eq!(records[0].ended_at_ms, None);
+        assert_eq!(records[1].groups, ["den", "kitchen"]);
+        assert_eq!(records[1].track.artist.as_deref(), Some("Band"));
+        assert_eq!(records[1].played_ms(9_000), 4_000);
+
+        let kitchen = history.query(&HistoryQuery {
+            group: Some("kitchen".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(kitchen.len(), 1);
+        let recent = history.query(&HistoryQuery {
+            since_ms: Some(2_000),
+            ..Default::default()
+        });
+        assert_eq!(recent[0].track, title("B"));
+        assert_eq!(recent.len(), 1);
+    }
+
+    #[test]
+    fn test_unheard_tracks_are_dropped() {
+        let history = PlaybackHistory::new(2);
+        history.start(None, title("A"), groups(&["den"]), 0);
+        history.start(None, title("Skipped"), Vec::new(), 1);
+        history.retitle("Live".to_string(), groups(&["den"]), 2);
+        history.start(None, title("C"), groups(&["den"]), 3);
+
+        let titles: Vec<_> = history
+            .query(&HistoryQuery::default())
+            .into_iter()
+            .map(|r| r.track.title.unwrap())
+            .collect();
+        assert_eq!(titles, ["C", "Live"]);
+    }
+
+    #[test]
+    fn test_history_persists_across_restarts() {
+        let path =
+            std::env::temp_dir().join(format!("sendspin-history-{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let history = PlaybackHistory::open(&path, 2).unwrap();
+        for (i, name) in ["A", "B", "C"].iter().enumerate() {
+            history.start(None, title(name), groups(&["den"]), i as u64);
+        }
+        history.finish(10);
+
+        let reopened = PlaybackHistory::open(&path, 2).unwrap();
+        let records = reopened.query(&HistoryQuery::default());
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].track, title("C"));
+        assert_eq!(records[0].ended_at_ms, Some(10));
+
+        // New tracks continue the sequence
+        reopened.start(None, title("D"), groups(&["den"]), 20);
+        assert_eq!(reopened.current().unwrap().id, 4);
+        let _ = fs::remove_file(&path);
+    }
+}
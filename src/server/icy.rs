@@ -0,0 +1,164 @@
+// ABOUTME: ICY (SHOUTcast/Icecast) inline metadata for internet radio streams
+// ABOUTME: Strips metadata blocks from the audio bytes and reports StreamTitle changes
+
+use std::io::{self, Read};
+
+/// Request header asking a radio server to interleave metadata
+pub const ICY_METADATA_HEADER: &str = "Icy-MetaData";
+
+/// Response header giving the number of audio bytes between metadata blocks
+pub const ICY_METAINT_HEADER: &str = "icy-metaint";
+
+/// Reader removing ICY metadata blocks from an HTTP body
+///
+/// Every `metaint` audio bytes the server inserts a length byte (in 16-byte
+/// units) and a block such as `StreamTitle='Artist - Song';`. The audio is
+/// passed through untouched; each new non-empty title goes to `on_title`.
+pub struct IcyReader<R> {
+    inner: R,
+    metaint: usize,
+    remaining: usize,
+    title: Option<String>,
+    on_title: Box<dyn FnMut(String) + Send + Sync>,
+}
+
+impl<R: Read> IcyReader<R> {
+    /// Wrap `inner`, whose server announced `metaint` bytes between blocks
+    pub fn new(
+        inner: R,
+        metaint: usize,
+        on_title: impl FnMut(String) + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            inner,
+            metaint,
+            remaining: metaint,
+            title: None,
+            on_title: Box::new(on_title),
+        }
+    }
+
+    /// Most recent title announced by the stream
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    fn read_metadata(&mut self) -> io::Result<()> {
+        let mut length = [0u8; 1];
+        self.inner.read_exact(&mut length)?;
+        let mut block = vec![0u8; length[0] as usize * 16];
+        self.inner.read_exact(&mut block)?;
+        self.remaining = self.metaint;
+
+        let Some(title) = parse_stream_title(&block) else {
+            return Ok(());
+        };
+        if self.title.as_deref() != Some(title.as_str()) {
+            log::info!("Stream title: {}", title);
+            self.title = Some(title.clone());
+            (self.on_title)(title);
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for IcyReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.remaining == 0 {
+            match self.read_metadata() {
+                Ok(()) => {}
+                // The stream ended where a block would have started
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(0),
+                Err(e) => return Err(e),
+            }
+        }
+        let len = buf.len().min(self.remaining);
+        let read = self.inner.read(&mut buf[..len])?;
+        self.remaining -= read;
+        Ok(read)
+    }
+}
+
+/// The `StreamTitle` of a metadata block, if set and not empty
+///
+/// Blocks are NUL-padded `key='value';` pairs. Titles are decoded as UTF-8,
+/// replacing invalid bytes, since stations do not agree on an encoding.
+pub fn parse_stream_title(block: &[u8]) -> Option<String> {
+    let text = String::from_utf8_lossy(block);
+    let text = text.trim_end_matches('\0');
+    let start = text.find("StreamTitle='")? + "StreamTitle='".len();
+    let rest = &text[start..];
+    // Titles may contain quotes; the value ends at the last `';` before the next key
+    let end = rest
+        .find("';StreamUrl=")
+        .or_else(|| rest.rfind("';"))
+        .or_else(|| rest.rfind('\''))
+        .unwrap_or(rest.len());
+    let title = rest[..end].trim();
+    (!title.is_empty()).then(|| title.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// `audio` split into `metaint`-byte runs, each followed by the next block
+    fn icy_stream(audio: &[u8], metaint: usize, blocks: &[&str]) -> Vec<u8> {
+        let mut stream = Vec::new();
+        for (i, run) in audio.chunks(metaint).enumerate() {
+            stream.extend_from_slice(run);
+            if run.len() < metaint {
+                break;
+            }
+            let mut block = blocks.get(i).copied().unwrap_or("").as_bytes().to_vec();
+            block.resize(block.len().div_ceil(16) * 16, 0);
+            stream.push((block.len() / 16) as u8);
+            stream.extend_from_slice(&block);
+        }
+        stream
+    }
+
+    #[test]
+    fn test_parse_stream_title() {
+        let block = b"StreamTitle='Miles Davis - So What';StreamUrl='';\0\0\0";
+        assert_eq!(
+            parse_stream_title(block).as_deref(),
+            Some("Miles Davis - So What")
+        );
+        assert_eq!(
+            parse_stream_title(b"StreamTitle='Don't Stop';").as_deref(),
+            Some("Don't Stop")
+        );
+        assert_eq!(parse_stream_title(b"StreamTitle='';"), None);
+        assert_eq!(parse_stream_title(b"StreamUrl='http://x';"), None);
+        assert_eq!(parse_stream_title(b""), None);
+    }
+
+    #[test]
+    fn test_reader_strips_blocks_and_reports_titles() {
+        let audio: Vec<u8> = (0..100u8).collect();
+        let blocks = [
+            "StreamTitle='One';",
+            "",
+            "StreamTitle='One';",
+            "StreamTitle='Two';",
+        ];
+        let stream = icy_stream(&audio, 16, &blocks);
+
+        let titles = Arc::new(Mutex::new(Vec::new()));
+        let seen = titles.clone();
+        let mut reader = IcyReader::new(stream.as_slice(), 16, move |title| {
+            seen.lock().unwrap().push(title);
+        });
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output).unwrap();
+
+        assert_eq!(output, audio);
+        assert_eq!(*titles.lock().unwrap(), ["One", "Two"]);
+        assert_eq!(reader.title(), Some("Two"));
+    }
+}
@@ -0,0 +1,289 @@
+// ABOUTME: Live audio pushed to the server by clients with the source role
+// ABOUTME: Buffers upstream PCM chunks and plays them as a stream's source
+
+use crate::audio::decode::{Decoder, PcmDecoder};
+use crate::audio::types::Sample;
+use crate::protocol::messages::AudioFormatSpec;
+use crate::server::audio_source::AudioSource;
+use crate::server::rate_limit::ByteBudget;
+use crate::server::source_events::TrackInfo;
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+
+/// Audio buffered from a source client before it starts playing (ms)
+///
+/// Absorbs network jitter between the client's chunks. Playback waits for
+/// this much again after the buffer runs dry.
+pub const INGEST_PREFILL_MS: u64 = 100;
+
+/// Most audio buffered from a source client (ms); older audio is dropped
+///
+/// Bounds the delay a client sending faster than real time can build up.
+pub const INGEST_MAX_BUFFER_MS: u64 = 2000;
+
+/// How far above its declared format's byte rate a source client may send (%)
+///
+/// Leaves room for frame headers and for catching up after a network stall.
+pub const INGEST_RATE_HEADROOM_PERCENT: u64 = 150;
+
+/// Sample rates a source client may declare (Hz)
+///
+/// The rate sizes the client's buffer and byte budget, so it must be bounded.
+pub const INGEST_SAMPLE_RATES: RangeInclusive<u32> = 8000..=192_000;
+
+#[derive(Default)]
+struct IngestBuffer {
+    /// Interleaved stereo samples not yet played
+    samples: VecDeque<Sample>,
+    /// Whether the prefill has been reached since the buffer last ran dry
+    playing: bool,
+    /// Whether the client has gone
+    closed: bool,
+}
+
+/// Stream source playing the audio a source client pushes
+///
+/// Plays silence until [`INGEST_PREFILL_MS`] is buffered and while the client
+/// falls behind, and is exhausted once the client disconnects and its
+/// buffered audio has played.
+pub struct IngestSource {
+    client_name: String,
+    sample_rate: u32,
+    prefill: usize,
+    buffer: Arc<Mutex<IngestBuffer>>,
+}
+
+/// Write end of an [`IngestSource`], fed with the client's audio chunk payloads
+///
+/// Dropping it marks the source as ended.
+pub struct IngestFeed {
+    decoder: PcmDecoder,
+    channels: u8,
+    capacity: usize,
+    bytes_per_second: u64,
+    buffer: Arc<Mutex<IngestBuffer>>,
+}
+
+/// Create a source for a client pushing audio in `format`
+///
+/// Only PCM at 16 or 24 bits, mono or stereo, at a rate in
+/// [`INGEST_SAMPLE_RATES`] is accepted; mono is played on both channels.
+pub fn ingest_source(
+    client_name: &str,
+    format: &AudioFormatSpec,
+) -> Result<(IngestSource, IngestFeed), String> {
+    if format.codec != "pcm" {
+        return Err(format!(
+            "cannot ingest {} audio (expected pcm)",
+            format.codec
+        ));
+    }
+    if !matches!(format.bit_depth, 16 | 24) {
+        return Err(format!("cannot ingest {}-bit PCM", format.bit_depth));
+    }
+    if !matches!(format.channels, 1 | 2) || !INGEST_SAMPLE_RATES.contains(&format.sample_rate) {
+        return Err(format!(
+            "cannot ingest {} channels at {}Hz",
+            format.channels, format.sample_rate
+        ));
+    }
+
+    let samples_for = |ms: u64| (format.sample_rate as u64 * ms / 1000) as usize * 2;
+    let buffer = Arc::new(Mutex::new(IngestBuffer::default()));
+    let source = IngestSource {
+        client_name: client_name.to_string(),
+        sample_rate: format.sample_rate,
+        prefill: samples_for(INGEST_PREFILL_MS),
+        buffer: buffer.clone(),
+    };
+    let feed = IngestFeed {
+        decoder: PcmDecoder::new(format.bit_depth),
+        channels: format.channels,
+        capacity: samples_for(INGEST_MAX_BUFFER_MS),
+        bytes_per_second: format.sample_rate as u64
+            * format.channels as u64
+            * (format.bit_depth / 8) as u64,
+        buffer,
+    };
+    Ok((source, feed))
+}
+
+impl IngestFeed {
+    /// Queue one chunk of the client's PCM audio
+    ///
+    /// Returns the number of samples dropped to stay within
+    /// [`INGEST_MAX_BUFFER_MS`].
+    pub fn push(&self, payload: &[u8]) -> Result<usize, String> {
+        let decoded = self.decoder.decode(payload).map_err(|e| e.to_string())?;
+        Ok(self.push_samples(&decoded))
+    }
+
+    /// Queue interleaved samples already in the feed's channel count
+    ///
+    /// Returns the number of samples dropped, like [`IngestFeed::push`].
+    pub(crate) fn push_samples(&self, samples: &[Sample]) -> usize {
+        let mut buffer = self.buffer.lock();
+        if self.channels == 1 {
+            buffer.samples.extend(samples.iter().flat_map(|&s| [s, s]));
+        } else {
+            buffer.samples.extend(samples.iter().copied());
+        }
+        let excess = buffer.samples.len().saturating_sub(self.capacity);
+        // Whole frames only, so the channels stay in step
+        let excess = excess.div_ceil(2) * 2;
+        buffer.samples.drain(..excess);
+        excess
+    }
+
+    /// Byte rate the client may push audio at
+    ///
+    /// The declared format's rate with [`INGEST_RATE_HEADROOM_PERCENT`], and a
+    /// burst of [`INGEST_MAX_BUFFER_MS`] of audio, as much as the buffer holds.
+    pub fn byte_budget(&self) -> ByteBudget {
+        ByteBudget {
+            bytes_per_second: self.bytes_per_second * INGEST_RATE_HEADROOM_PERCENT / 100,
+            burst_bytes: self.bytes_per_second * INGEST_MAX_BUFFER_MS / 1000,
+        }
+    }
+
+    /// Interleaved stereo samples waiting to be played
+    pub(crate) fn buffered_samples(&self) -> usize {
+        self.buffer.lock().samples.len()
+    }
+
+    /// Whether the source this feeds still exists
+    pub(crate) fn is_listening(&self) -> bool {
+        Arc::strong_count(&self.buffer) > 1
+    }
+}
+
+impl Drop for IngestFeed {
+    fn drop(&mut self) {
+        self.buffer.lock().closed = true;
+    }
+}
+
+impl AudioSource for IngestSource {
+    fn read_chunk(&mut self, samples_per_channel: usize) -> Option<Vec<Sample>> {
+        let wanted = samples_per_channel * 2;
+        let mut buffer = self.buffer.lock();
+        if buffer.closed && buffer.samples.is_empty() {
+            return None;
+        }
+        if !buffer.playing {
+            if buffer.samples.len() < self.prefill && !buffer.closed {
+                return Some(vec![Sample::ZERO; wanted]);
+            }
+            buffer.playing = true;
+        }
+
+        let available = buffer.samples.len().min(wanted);
+        let mut chunk: Vec<Sample> = buffer.samples.drain(..available).collect();
+        if chunk.len() < wanted {
+            if !buffer.closed {
+                log::debug!("Source client {} fell behind, refilling", self.client_name);
+                buffer.playing = false;
+            }
+            chunk.resize(wanted, Sample::ZERO);
+        }
+        Some(chunk)
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> u8 {
+        2
+    }
+
+    fn is_exhausted(&self) -> bool {
+        let buffer = self.buffer.lock();
+        buffer.closed && buffer.samples.is_empty()
+    }
+
+    fn description(&self) -> Option<String> {
+        Some(format!("live input from {}", self.client_name))
+    }
+
+    fn track_info(&self) -> TrackInfo {
+        TrackInfo {
+            title: Some(self.client_name.clone()),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pcm16(sample_rate: u32, channels: u8) -> AudioFormatSpec {
+        AudioFormatSpec {
+            codec: "pcm".to_string(),
+            channels,
+            sample_rate,
+            bit_depth: 16,
+        }
+    }
+
+    fn payload(frames: usize, channels: usize, value: i16) -> Vec<u8> {
+        std::iter::repeat_n(value.to_le_bytes(), frames * channels)
+            .flatten()
+            .collect()
+    }
+
+    #[test]
+    fn test_plays_after_prefill_and_ends_with_client() {
+        let (mut source, feed) = ingest_source("laptop", &pcm16(8000, 1)).unwrap();
+        // 100ms prefill is 800 frames at 8kHz
+        feed.push(&payload(480, 1, 100)).unwrap();
+        assert_eq!(source.read_chunk(20).unwrap(), vec![Sample::ZERO; 40]);
+
+        feed.push(&payload(480, 1, 100)).unwrap();
+        let chunk = source.read_chunk(20).unwrap();
+        assert_eq!(chunk, vec![Sample::from_i16(100); 40]);
+
+        // Buffered audio still plays after the client leaves, then the source ends
+        drop(feed);
+        assert!(!source.is_exhausted());
+        let tail = source.read_chunk(1000).unwrap();
+        assert_eq!(tail[..1880], vec![Sample::from_i16(100); 1880][..]);
+        assert_eq!(tail[1880], Sample::ZERO);
+        assert!(source.is_exhausted());
+        assert!(source.read_chunk(20).is_none());
+    }
+
+    #[test]
+    fn test_buffer_is_bounded() {
+        let (source, feed) = ingest_source("laptop", &pcm16(8000, 2)).unwrap();
+        assert_eq!(feed.push(&payload(12000, 2, 1)).unwrap(), 0);
+        // 2s at 8kHz holds 16000 frames; the oldest 4000 go
+        assert_eq!(feed.push(&payload(8000, 2, 2)).unwrap(), 8000);
+        assert_eq!(source.buffer.lock().samples.len(), 32000);
+    }
+
+    #[test]
+    fn test_rejects_unsupported_formats() {
+        let opus = AudioFormatSpec {
+            codec: "opus".to_string(),
+            ..pcm16(48000, 2)
+        };
+        assert!(ingest_source("laptop", &opus).is_err());
+        assert!(ingest_source("laptop", &pcm16(48000, 6)).is_err());
+        assert!(ingest_source("laptop", &pcm16(0, 2)).is_err());
+        assert!(ingest_source("laptop", &pcm16(4_000_000_000, 2)).is_err());
+        assert!(ingest_source("laptop", &pcm16(192_000, 2)).is_ok());
+    }
+
+    #[test]
+    fn test_byte_budget_follows_declared_format() {
+        let (_source, feed) = ingest_source("laptop", &pcm16(48000, 2)).unwrap();
+        let budget = feed.byte_budget();
+        // 48kHz 16-bit stereo is 192000 bytes per second
+        assert_eq!(budget.bytes_per_second, 288_000);
+        assert_eq!(budget.burst_bytes, 384_000);
+    }
+}
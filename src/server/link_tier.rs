@@ -0,0 +1,86 @@
+// ABOUTME: Link-quality tiers (wired, wireless, Bluetooth) for players within a group
+// ABOUTME: Players on a slower tier get a longer buffer-ahead without delaying the rest
+
+use crate::server::rtt_histogram::RttSummary;
+use serde::{Deserialize, Serialize};
+
+/// RTT samples needed before an untagged player is placed on a tier
+pub const PLACEMENT_SAMPLES: u64 = 20;
+
+/// Spread between median and 90th percentile RTT marking a wireless link
+pub const WIRELESS_SPREAD_MICROS: i64 = 15_000;
+
+/// How a player is connected, which decides how far ahead it must be sent audio
+///
+/// Within a group, players on a tier with a configured buffer-ahead play that
+/// far ahead instead of the group's own buffer-ahead when it is longer, so one
+/// Bluetooth bridge does not push the whole group to its delay.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkTier {
+    /// Ethernet or another link with steady latency
+    Wired,
+    /// Wi-Fi or another link with variable latency
+    Wireless,
+    /// A player forwarding to a Bluetooth speaker
+    Bluetooth,
+}
+
+impl LinkTier {
+    /// Name used in config files and the control API
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LinkTier::Wired => "wired",
+            LinkTier::Wireless => "wireless",
+            LinkTier::Bluetooth => "bluetooth",
+        }
+    }
+
+    /// Tier for an untagged player from its time-sync round trips
+    ///
+    /// Returns None until [`PLACEMENT_SAMPLES`] RTTs have been reported. A
+    /// Bluetooth hop sits behind the player, out of sight of time sync, so
+    /// Bluetooth players must be tagged.
+    pub fn infer(rtt: &RttSummary) -> Option<Self> {
+        if rtt.samples < PLACEMENT_SAMPLES {
+            return None;
+        }
+        if rtt.p90_micros - rtt.p50_micros >= WIRELESS_SPREAD_MICROS {
+            Some(LinkTier::Wireless)
+        } else {
+            Some(LinkTier::Wired)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::rtt_histogram::RttHistogram;
+
+    fn summary(rtts: impl IntoIterator<Item = i64>) -> RttSummary {
+        let mut histogram = RttHistogram::new();
+        rtts.into_iter().for_each(|rtt| histogram.record(rtt));
+        histogram.summary().unwrap()
+    }
+
+    #[test]
+    fn test_infer_tier_from_rtt_spread() {
+        let steady = summary(std::iter::repeat_n(1_500, 30));
+        assert_eq!(LinkTier::infer(&steady), Some(LinkTier::Wired));
+
+        let jittery = summary((0..30).map(|i| if i % 3 == 0 { 60_000 } else { 4_000 }));
+        assert_eq!(LinkTier::infer(&jittery), Some(LinkTier::Wireless));
+
+        let early = summary(std::iter::repeat_n(1_500, 5));
+        assert_eq!(LinkTier::infer(&early), None);
+    }
+
+    #[test]
+    fn test_tier_names() {
+        for tier in [LinkTier::Wired, LinkTier::Wireless, LinkTier::Bluetooth] {
+            let json = serde_json::to_string(&tier).unwrap();
+            assert_eq!(json, format!("\"{}\"", tier.as_str()));
+        }
+    }
+}
@@ -0,0 +1,140 @@
+// ABOUTME: mDNS/DNS-SD advertisement of the server for client-initiated connections
+// ABOUTME: Publishes `_sendspin-server._tcp` with the WebSocket path, name, and server ID
+
+pub use crate::protocol::client::SERVER_SERVICE_TYPE;
+use crate::server::config::ServerConfig;
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+use std::collections::HashMap;
+
+/// TXT records published with the advertisement
+///
+/// `path` is the key the spec requires; `name` and `server_id` let clients
+/// label servers and recognise the last played one before connecting.
+pub fn txt_records(config: &ServerConfig) -> HashMap<String, String> {
+    HashMap::from([
+        ("path".to_string(), config.ws_route()),
+        ("name".to_string(), config.name.clone()),
+        ("server_id".to_string(), config.server_id.clone()),
+    ])
+}
+
+/// Build the service record for a server listening on `port`
+///
+/// Advertises the bind address when it is a specific IP, and every interface
+/// address otherwise.
+pub fn service_info(config: &ServerConfig, port: u16) -> Result<ServiceInfo, mdns_sd::Error> {
+    let ip = config.bind_addr.ip();
+    let host = format!("sendspin-{}.local.", host_label(&config.server_id));
+    let info = if ip.is_unspecified() {
+        ServiceInfo::new(
+            SERVER_SERVICE_TYPE,
+            &instance_name(&config.name),
+            &host,
+            (),
+            port,
+            txt_records(config),
+        )?
+        .enable_addr_auto()
+    } else {
+        ServiceInfo::new(
+            SERVER_SERVICE_TYPE,
+            &instance_name(&config.name),
+            &host,
+            ip,
+            port,
+            txt_records(config),
+        )?
+    };
+    Ok(info)
+}
+
+/// A running advertisement, withdrawn when dropped
+pub struct MdnsAdvertisement {
+    daemon: ServiceDaemon,
+    fullname: String,
+}
+
+impl MdnsAdvertisement {
+    /// Start advertising a server listening on `port`
+    pub fn start(config: &ServerConfig, port: u16) -> Result<Self, mdns_sd::Error> {
+        let info = service_info(config, port)?;
+        let fullname = info.get_fullname().to_string();
+        let daemon = ServiceDaemon::new()?;
+        daemon.register(info)?;
+        log::info!("Advertising {} via mDNS", fullname);
+        Ok(Self { daemon, fullname })
+    }
+
+    /// Full DNS-SD name of the advertised service
+    pub fn fullname(&self) -> &str {
+        &self.fullname
+    }
+}
+
+impl Drop for MdnsAdvertisement {
+    fn drop(&mut self) {
+        if let Err(e) = self.daemon.unregister(&self.fullname) {
+            log::debug!("Failed to withdraw mDNS advertisement: {}", e);
+        }
+        let _ = self.daemon.shutdown();
+    }
+}
+
+/// DNS-SD instance labels are limited to 63 bytes and may not contain dots
+fn instance_name(name: &str) -> String {
+    let mut label: String = name.replace('.', " ");
+    while label.len() > 63 {
+        label.pop();
+    }
+    if label.trim().is_empty() {
+        "Sendspin".to_string()
+    } else {
+        label
+    }
+}
+
+/// Hostname label derived from the server ID (letters, digits, and hyphens)
+fn host_label(server_id: &str) -> String {
+    let label: String = server_id
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .take(12)
+        .collect::<String>()
+        .to_ascii_lowercase();
+    if label.is_empty() {
+        "server".to_string()
+    } else {
+        label
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> ServerConfig {
+        let mut config = ServerConfig::new("Living Room v2.1").path_prefix("/audio");
+        config.server_id = "3f2a9c1e-aaaa-bbbb-cccc-000000000000".to_string();
+        config
+    }
+
+    #[test]
+    fn test_txt_records() {
+        let txt = txt_records(&config());
+        assert_eq!(txt["path"], "/audio/sendspin");
+        assert_eq!(txt["name"], "Living Room v2.1");
+        assert_eq!(txt["server_id"], "3f2a9c1e-aaaa-bbbb-cccc-000000000000");
+    }
+
+    #[test]
+    fn test_service_info() {
+        let info = service_info(&config(), 8927).unwrap();
+        assert_eq!(
+            info.get_fullname(),
+            "Living Room v2 1._sendspin-server._tcp.local."
+        );
+        assert_eq!(info.get_hostname(), "sendspin-3f2a9c1eaaaa.local.");
+        assert_eq!(info.get_port(), 8927);
+        assert_eq!(info.get_property_val_str("path"), Some("/audio/sendspin"));
+    }
+}
@@ -0,0 +1,216 @@
+// ABOUTME: Track metadata and artwork for clients with the metadata and artwork roles
+// ABOUTME: Sends the playing track on join and whenever a stream's track changes
+
+use crate::protocol::messages::{Message, MetadataState, ServerState};
+use crate::server::artwork::send_artwork;
+use crate::server::client_manager::ClientManager;
+use crate::server::clock::ServerClock;
+use crate::server::group::GroupManager;
+use crate::server::source_events::{SourceEvent, SourceEvents, TrackInfo};
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+
+/// `server/state` carrying a track's tags, stamped with server time `timestamp`
+pub fn metadata_state(track: &TrackInfo, timestamp: i64) -> Message {
+    Message::ServerState(ServerState {
+        metadata: Some(MetadataState {
+            timestamp,
+            title: track.title.clone(),
+            artist: track.artist.clone(),
+            album: track.album.clone(),
+        }),
+        controller: None,
+        trace: None,
+    })
+}
+
+/// Sends track metadata and artwork to clients with the metadata and artwork roles
+#[derive(Clone)]
+pub struct MetadataPublisher {
+    client_manager: Arc<ClientManager>,
+    group_manager: Arc<GroupManager>,
+    clock: Arc<ServerClock>,
+}
+
+impl MetadataPublisher {
+    /// Create a publisher over the given managers
+    pub fn new(
+        client_manager: Arc<ClientManager>,
+        group_manager: Arc<GroupManager>,
+        clock: Arc<ServerClock>,
+    ) -> Self {
+        Self {
+            client_manager,
+            group_manager,
+            clock,
+        }
+    }
+
+    /// Send a track to one client, as `server/state` for the metadata role
+    /// and as images for the artwork role
+    ///
+    /// Artwork clients are only sent pictures they do not already show.
+    pub fn send_to(&self, client_id: &str, track: &TrackInfo) -> bool {
+        let timestamp = self.clock.now_micros();
+        let artwork = send_artwork(&self.client_manager, client_id, track, timestamp);
+        if !self.client_manager.is_metadata(client_id) {
+            return artwork;
+        }
+        let message = metadata_state(track, timestamp);
+        match serde_json::to_string(&message) {
+            Ok(json) => self.client_manager.send_to_client(client_id, &json) || artwork,
+            Err(e) => {
+                log::error!("Failed to serialize server/state: {}", e);
+                artwork
+            }
+        }
+    }
+
+    /// Send a track to the metadata and artwork clients of every group on `stream_id`
+    pub fn publish(&self, stream_id: &str, track: &TrackInfo) {
+        for group_id in self.group_manager.groups_on_stream(stream_id) {
+            for member in self.group_manager.get_group_members(&group_id) {
+                self.send_to(&member, track);
+            }
+        }
+    }
+
+    /// Spawn a task publishing the track of `stream_id` each time it changes
+    pub fn spawn(self, stream_id: String, events: SourceEvents) -> tokio::task::JoinHandle<()> {
+        let mut rx = events.subscribe();
+        tokio::spawn(async move {
+            loop {
+                match rx.recv().await {
+                    Ok(
+                        SourceEvent::TrackChanged { .. }
+                        | SourceEvent::Tags(_)
+                        | SourceEvent::StreamTitle(_),
+                    ) => self.publish(&stream_id, &events.current()),
+                    Ok(_) => {}
+                    Err(RecvError::Lagged(missed)) => {
+                        log::warn!("Metadata for {} missed {} source events", stream_id, missed);
+                        self.publish(&stream_id, &events.current());
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::client_manager::{ConnectedClient, ServerMessage};
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    fn connect(
+        clients: &ClientManager,
+        groups: &GroupManager,
+        id: &str,
+        role: &str,
+    ) -> mpsc::UnboundedReceiver<ServerMessage> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut client = ConnectedClient::new(id.to_string(), id.to_string(), tx);
+        client.active_roles = vec![role.to_string()];
+        clients.add_client(client);
+        groups.add_to_group(id, "default");
+        rx
+    }
+
+    fn received(rx: &mut mpsc::UnboundedReceiver<ServerMessage>) -> Option<MetadataState> {
+        let Ok(ServerMessage::Text(text)) = rx.try_recv() else {
+            return None;
+        };
+        match serde_json::from_str(&text).unwrap() {
+            Message::ServerState(state) => state.metadata,
+            other => panic!("expected server/state, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_track_changes_reach_metadata_clients() {
+        let clients = Arc::new(ClientManager::new());
+        let groups = Arc::new(GroupManager::new());
+        let mut display = connect(&clients, &groups, "display", "metadata@v1");
+        let mut speaker = connect(&clients, &groups, "speaker", "player@v1");
+        let publisher = MetadataPublisher::new(clients, groups, Arc::new(ServerClock::new()));
+
+        let events = SourceEvents::new();
+        let handle = publisher.spawn("default".to_string(), events.clone());
+        events.emit(SourceEvent::TrackChanged {
+            source: Some("a.flac".to_string()),
+            track: TrackInfo {
+                title: Some("So What".to_string()),
+                artist: Some("Miles Davis".to_string()),
+                ..Default::default()
+            },
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let first = received(&mut display).unwrap();
+        assert_eq!(first.title.as_deref(), Some("So What"));
+        assert_eq!(first.album, None);
+
+        // Later tags are merged into the current track
+        events.emit(SourceEvent::Tags(TrackInfo {
+            album: Some("Kind of Blue".to_string()),
+            ..Default::default()
+        }));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let second = received(&mut display).unwrap();
+        assert_eq!(second.artist.as_deref(), Some("Miles Davis"));
+        assert_eq!(second.album.as_deref(), Some("Kind of Blue"));
+        assert!(second.timestamp >= first.timestamp);
+        assert!(received(&mut speaker).is_none());
+        handle.abort();
+    }
+
+    #[test]
+    fn test_artwork_clients_get_pictures_once() {
+        use crate::protocol::binary::BinaryFrame;
+        use crate::server::artwork::ArtworkState;
+        use crate::server::source_events::Artwork;
+
+        let clients = Arc::new(ClientManager::new());
+        let groups = Arc::new(GroupManager::new());
+        let mut frame = connect(&clients, &groups, "frame", "artwork@v1");
+        clients.update_artwork_state("frame", |state| *state = ArtworkState::from_support(None));
+        let publisher = MetadataPublisher::new(clients, groups, Arc::new(ServerClock::new()));
+
+        let mut cover = std::io::Cursor::new(Vec::new());
+        image::RgbImage::new(8, 8)
+            .write_to(&mut cover, image::ImageFormat::Jpeg)
+            .unwrap();
+        let track = TrackInfo {
+            title: Some("So What".to_string()),
+            album_art: Some(Artwork {
+                media_type: "image/jpeg".to_string(),
+                data: cover.get_ref().as_slice().into(),
+            }),
+            ..Default::default()
+        };
+        publisher.publish("default", &track);
+        let Ok(ServerMessage::Binary(data)) = frame.try_recv() else {
+            panic!("expected an artwork frame");
+        };
+        let BinaryFrame::Artwork {
+            channel, payload, ..
+        } = BinaryFrame::decode(&data).unwrap()
+        else {
+            panic!("expected an artwork frame");
+        };
+        assert_eq!((channel, payload), (0, cover.get_ref().as_slice()));
+        assert!(frame.try_recv().is_err());
+
+        // A title change alone sends nothing to artwork clients
+        publisher.publish(
+            "default",
+            &TrackInfo {
+                title: None,
+                ..track
+            },
+        );
+        assert!(frame.try_recv().is_err());
+    }
+}
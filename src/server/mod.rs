@@ -1,26 +1,175 @@
 // ABOUTME: Server module for Sendspin protocol
 // ABOUTME: Provides WebSocket server, client management, and audio streaming
 
+mod adaptive_buffer;
+mod announcement;
+mod artwork;
 mod audio_engine;
 mod audio_source;
+mod buffer_health;
+mod capability_cache;
+mod capture;
+/// Shared CLI arguments for server binaries
+pub mod cli;
 mod client_handler;
 mod client_manager;
-pub mod cli;
 mod clock;
+mod codec_policy;
 mod config;
+mod config_check;
+mod config_file;
+mod control_api;
+mod control_trace;
 mod encoder;
+mod encoder_metrics;
+mod extensions;
+mod flac;
+mod format_probe;
 mod group;
+mod group_stats;
+#[cfg(feature = "gstreamer")]
+mod gst;
+mod history;
+mod icy;
+mod ingest;
+mod link_tier;
+mod mdns;
+mod metadata;
+#[cfg(unix)]
+mod mpris;
+mod persistence;
+mod pipe;
+mod pipeline;
+mod play_queue;
+mod playback;
+mod profiling;
+mod proxy;
+mod rate_limit;
+mod replication;
+mod roles;
+mod rtt_histogram;
+mod scrobbler;
+#[allow(clippy::module_inception)]
 mod server;
+mod snapcast;
+mod snapshot;
+mod source_control;
+mod source_events;
+mod source_fallback;
+mod status;
+mod stream_manager;
+mod track_start;
+mod transport_stats;
+/// Terminal dashboard for the server
 pub mod tui;
+mod url_cache;
 
-pub use audio_engine::AudioEngine;
-pub use audio_source::{AudioSource, FileSource, TestToneSource, UrlSource};
+pub use adaptive_buffer::AdaptiveBufferConfig;
+pub use announcement::{Announcement, AnnouncementMix, AnnouncementQueue};
+pub use artwork::{
+    render_artwork, resend_artwork, send_artwork, ArtworkChannel, ArtworkFormat, ArtworkSource,
+    ArtworkState, DEFAULT_ARTWORK_SIZE, MAX_ARTWORK_CHANNELS,
+};
+pub use audio_engine::{AudioEngine, EngineCommand, EngineHandle, EngineState};
+pub use audio_source::{
+    is_local_uri, open_source, open_track, AudioSource, FileSource, SilenceSource, TestToneSource,
+    UrlSource,
+};
+pub use buffer_health::{BufferHealth, BufferTrend, DRAIN_HORIZON, LOW_BUFFER_MS};
+pub use capability_cache::{CapabilityCache, ClientCapabilities, DEFAULT_CAPABILITY_CACHE_SIZE};
+pub use capture::{capture_devices, CaptureDevice, CaptureSource, CAPTURE_SCHEME};
 pub use cli::ServerArgs;
 pub use client_handler::handle_client;
-pub use client_manager::{ClientManager, ConnectedClient};
+pub use client_manager::{
+    ClientManager, ConnectedClient, ConnectionState, OutputProcessing, ResumableSession,
+    SendCounters, VolumeChange, DEFAULT_RECONNECT_GRACE,
+};
 pub use clock::ServerClock;
-pub use config::ServerConfig;
-pub use encoder::{AudioEncoder, FlacEncoder, OpusEncoder, PcmEncoder};
-pub use group::{Group, GroupManager};
+pub use codec_policy::{CodecConstraints, CodecOverride, CodecPolicy};
+pub use config::{InitialVolume, RoleLimits, ServerConfig, ANY_CLIENT};
+pub use config_check::{check_server_config, ConfigReport, Finding, Severity};
+pub use config_file::{
+    spawn_config_watcher, ConfigApiKey, ConfigFile, ConfigReloader, LiveConfig,
+    CONFIG_POLL_INTERVAL,
+};
+pub use control_api::{
+    ApiKey, BatchVolumeRequest, ChannelMapRequest, ClientInfo, ClientRtt, CodecInfo, CodecRequest,
+    EncoderSettingsInfo, LinkTierRequest, MonoRequest, MoveRequest, NameRequest, NewStreamRequest,
+    NightModeRequest, NowPlayingInfo, Permission, PlayerVolume, QueuePositionRequest, QueueRequest,
+    SourceRequest, StereoWidthRequest, StreamRequest, TierBufferRequest, TimelineRequest,
+    VolumeRequest,
+};
+pub use control_trace::{ControlTraces, TRACE_TIMEOUT_MICROS};
+pub use encoder::{
+    create_encoder, AudioEncoder, EncoderFactory, EncoderParams, EncoderRegistry, EncoderSettings,
+    PcmEncoder,
+};
+pub use encoder_metrics::{EncoderMetrics, EncoderStats};
+pub use extensions::{ExtensionHandler, Extensions};
+pub use flac::FlacEncoder;
+pub use format_probe::{plan_conversion, ConversionChain, ConversionStep};
+pub use group::{AutoStart, Group, GroupDefinition, GroupManager, PlaybackState, StreamTiming};
+pub use group_stats::{GroupStats, StatsCollector};
+#[cfg(feature = "gstreamer")]
+pub use gst::{launch_args, GstSource, GST_LAUNCH, GST_SAMPLE_RATE, GST_SCHEME};
+pub use history::{
+    spawn_history_recorder, HistoryQuery, PlayRecord, PlaybackHistory, DEFAULT_HISTORY_SIZE,
+};
+pub use icy::{parse_stream_title, IcyReader};
+pub use ingest::{
+    ingest_source, IngestFeed, IngestSource, INGEST_MAX_BUFFER_MS, INGEST_PREFILL_MS,
+    INGEST_RATE_HEADROOM_PERCENT, INGEST_SAMPLE_RATES,
+};
+pub use link_tier::{LinkTier, PLACEMENT_SAMPLES, WIRELESS_SPREAD_MICROS};
+pub use mdns::{txt_records, MdnsAdvertisement, SERVER_SERVICE_TYPE};
+pub use metadata::{metadata_state, MetadataPublisher};
+#[cfg(unix)]
+pub use mpris::{spawn_mpris, MprisPlayer, BUS_NAME as MPRIS_BUS_NAME};
+pub use persistence::{
+    ClientRecord, MemoryPersistence, Persistence, SledPersistence, FLUSH_INTERVAL_MS,
+};
+pub use pipe::{
+    parse_pipe_uri, PipeFormat, PipeSource, PIPE_HIGH_WATER_MS, PIPE_SCHEME, STDIN_PIPE,
+};
+pub use pipeline::{
+    describe_pipeline, EncoderBranch, PipelineGraph, PipelineSource, PipelineStage,
+};
+pub use play_queue::{PlayQueue, QueueEntry};
+pub use playback::{PlaybackController, CONTROLLER_COMMANDS};
+pub use profiling::{folded_stacks, profiling_enabled, reset_profile, scope, Scope};
+pub use rate_limit::{ByteBudget, InboundLimiter, InboundLimits, LimitViolation};
+pub use replication::{
+    fetch_snapshot, spawn_replicator, ClientState, GroupState, ReplicationConfig,
+    ReplicationSnapshot, REPLICATION_PATH,
+};
+pub use roles::{
+    ArtworkHandler, ControllerHandler, DefaultArtworkHandler, DefaultControllerHandler,
+    DefaultMetadataHandler, DefaultPlayerHandler, MetadataHandler, PlayerHandler, RoleContext,
+    RoleHandlers,
+};
+pub use rtt_histogram::{RttHistogram, RttSummary};
+pub use scrobbler::{
+    spawn_scrobbler, LastFm, ListenBrainz, Scrobble, ScrobbleSink, ScrobbleTracker,
+    ScrobblerConfig, LASTFM_API_URL, LISTENBRAINZ_API_URL, MIN_SCROBBLE_LENGTH, SCROBBLE_AFTER,
+    SCROBBLE_INTERVAL,
+};
 pub use server::SendspinServer;
+pub use snapcast::{
+    parse_snapcast_uri, SnapcastSource, SnapcastTarget, DEFAULT_BRIDGE_ID, SNAPCAST_PORT,
+    SNAPCAST_SCHEME,
+};
+pub use snapshot::{ClientSettings, ServerSnapshot, SNAPSHOT_PATH, SNAPSHOT_VERSION};
+pub use source_control::{NowPlaying, SourceControl};
+pub use source_events::{Artwork, SourceEvent, SourceEvents, TrackInfo};
+pub use source_fallback::{Fallback, FallbackConfig, FallbackSource, SourceOpener};
+pub use status::{spawn_status_publisher, StatusPublisher, STATUS, STATUS_REQUEST};
+pub use stream_manager::{
+    group_timeline_id, StreamDefinition, StreamInfo, StreamManager, DEFAULT_STREAM,
+    GROUP_TIMELINE_PREFIX,
+};
+pub use track_start::{ChunkTimeline, SilenceTrim, TrimSilence};
+pub use transport_stats::{
+    TransportSnapshot, TransportStats, RATE_WINDOW, TRANSPORT_PING_INTERVAL,
+};
 pub use tui::{ServerStats, TuiApp};
+pub use url_cache::UrlCache;
@@ -0,0 +1,560 @@
+// ABOUTME: MPRIS media player interface on the D-Bus session bus
+// ABOUTME: Lets desktop media keys and widgets play, pause, stop, and skip whole-house playback
+
+use crate::server::client_manager::ClientManager;
+use crate::server::group::{GroupManager, PlaybackState};
+use crate::server::playback::PlaybackController;
+use crate::server::source_control::SourceControl;
+use crate::server::source_events::TrackInfo;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+use zbus::object_server::SignalEmitter;
+use zbus::zvariant::{ObjectPath, OwnedValue, Value};
+use zbus::{connection, interface};
+
+/// Well-known bus name the server registers
+pub const BUS_NAME: &str = "org.mpris.MediaPlayer2.sendspin";
+
+const OBJECT_PATH: &str = "/org/mpris/MediaPlayer2";
+const TRACK_ID: &str = "/org/sendspin/stream";
+
+/// How often playback state, the queue and the track are polled for
+/// PropertiesChanged signals
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// MPRIS view of the server's playback
+///
+/// The player represents the whole house: it reports `Playing` while any
+/// group plays, and Play/Pause/Stop apply to every group with members. Next
+/// skips to the next track in the main stream's play queue, and the
+/// metadata is that stream's current track.
+#[derive(Clone)]
+pub struct MprisPlayer {
+    identity: String,
+    group_manager: Arc<GroupManager>,
+    playback: PlaybackController,
+    source_control: SourceControl,
+}
+
+impl MprisPlayer {
+    /// Create a player over the server's managers and the main stream's source
+    pub fn new(
+        identity: impl Into<String>,
+        client_manager: Arc<ClientManager>,
+        group_manager: Arc<GroupManager>,
+        source_control: SourceControl,
+    ) -> Self {
+        Self {
+            identity: identity.into(),
+            playback: PlaybackController::new(client_manager, group_manager.clone()),
+            group_manager,
+            source_control,
+        }
+    }
+
+    /// MPRIS playback status: "Playing", "Paused", or "Stopped"
+    pub fn playback_status(&self) -> &'static str {
+        let states: Vec<PlaybackState> = self
+            .group_manager
+            .group_ids()
+            .iter()
+            .filter_map(|id| self.group_manager.get_playback_state(id))
+            .collect();
+        if states.contains(&PlaybackState::Playing) {
+            "Playing"
+        } else if states.contains(&PlaybackState::Paused) {
+            "Paused"
+        } else {
+            "Stopped"
+        }
+    }
+
+    fn occupied_groups(&self) -> Vec<String> {
+        self.group_manager
+            .group_ids()
+            .into_iter()
+            .filter(|id| !self.group_manager.get_group_members(id).is_empty())
+            .collect()
+    }
+
+    /// Start playback in every group with members
+    pub fn play(&self) {
+        for group_id in self.occupied_groups() {
+            self.playback.play(&group_id);
+        }
+    }
+
+    /// Pause every playing group
+    pub fn pause(&self) {
+        for group_id in self.group_manager.group_ids() {
+            if self.group_manager.get_playback_state(&group_id) == Some(PlaybackState::Playing) {
+                self.playback.pause(&group_id);
+            }
+        }
+    }
+
+    /// Pause if anything is playing, otherwise play
+    pub fn play_pause(&self) {
+        if self.playback_status() == "Playing" {
+            self.pause();
+        } else {
+            self.play();
+        }
+    }
+
+    /// Stop every group
+    pub fn stop(&self) {
+        for group_id in self.group_manager.group_ids() {
+            self.playback.stop(&group_id);
+        }
+    }
+
+    /// Whether a queued track is waiting to be skipped to
+    pub fn can_go_next(&self) -> bool {
+        !self.source_control.queue().is_empty()
+    }
+
+    /// Skip to the next queued track; false if nothing is queued
+    pub fn next(&self) -> bool {
+        self.source_control.skip()
+    }
+
+    /// Tags of the track the main stream is playing
+    pub fn track(&self) -> TrackInfo {
+        self.source_control.now_playing().track
+    }
+}
+
+/// What the poll compares to decide which PropertiesChanged signals to send
+#[derive(PartialEq)]
+struct Polled {
+    status: &'static str,
+    can_go_next: bool,
+    track: (Option<String>, Option<String>, Option<String>),
+}
+
+impl Polled {
+    fn of(player: &MprisPlayer) -> Self {
+        let track = player.track();
+        Self {
+            status: player.playback_status(),
+            can_go_next: player.can_go_next(),
+            track: (track.title, track.artist, track.album),
+        }
+    }
+}
+
+/// The `org.mpris.MediaPlayer2` interface
+struct RootInterface {
+    identity: String,
+}
+
+#[interface(name = "org.mpris.MediaPlayer2")]
+impl RootInterface {
+    fn raise(&self) {}
+
+    fn quit(&self) {}
+
+    #[zbus(property)]
+    fn can_quit(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn can_raise(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn has_track_list(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn identity(&self) -> String {
+        self.identity.clone()
+    }
+
+    #[zbus(property)]
+    fn supported_uri_schemes(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    #[zbus(property)]
+    fn supported_mime_types(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+/// The `org.mpris.MediaPlayer2.Player` interface
+///
+/// Methods that change playback wake the status poll so the change is
+/// signalled right away.
+struct PlayerInterface {
+    player: MprisPlayer,
+    changed: Arc<Notify>,
+}
+
+#[interface(name = "org.mpris.MediaPlayer2.Player")]
+impl PlayerInterface {
+    fn play(&self) {
+        self.player.play();
+        self.changed.notify_one();
+    }
+
+    fn pause(&self) {
+        self.player.pause();
+        self.changed.notify_one();
+    }
+
+    fn play_pause(&self) {
+        self.player.play_pause();
+        self.changed.notify_one();
+    }
+
+    fn stop(&self) {
+        self.player.stop();
+        self.changed.notify_one();
+    }
+
+    fn next(&self) {
+        self.player.next();
+        self.changed.notify_one();
+    }
+
+    // The queue plays each track once and keeps no history, so
+    // CanGoPrevious is false; like Seek with CanSeek false, a no-op per
+    // the spec
+    fn previous(&self) {}
+
+    fn seek(&self, _offset: i64) {}
+
+    fn set_position(&self, _track_id: ObjectPath<'_>, _position: i64) {}
+
+    fn open_uri(&self, _uri: &str) {}
+
+    #[zbus(property)]
+    fn playback_status(&self) -> String {
+        self.player.playback_status().to_string()
+    }
+
+    #[zbus(property)]
+    fn rate(&self) -> f64 {
+        1.0
+    }
+
+    /// The current track's tags, titled with the player's identity when
+    /// the source has no title
+    #[zbus(property)]
+    fn metadata(&self) -> HashMap<String, OwnedValue> {
+        let track = self.player.track();
+        let track_id = ObjectPath::from_static_str_unchecked(TRACK_ID);
+        let title = track.title.unwrap_or_else(|| self.player.identity.clone());
+        let mut metadata = vec![
+            ("mpris:trackid", Value::from(track_id)),
+            ("xesam:title", Value::from(title)),
+        ];
+        if let Some(artist) = track.artist {
+            metadata.push(("xesam:artist", Value::from(vec![artist])));
+        }
+        if let Some(album) = track.album {
+            metadata.push(("xesam:album", Value::from(album)));
+        }
+        if let Some(duration_ms) = track.duration_ms {
+            metadata.push(("mpris:length", Value::from(duration_ms as i64 * 1000)));
+        }
+        metadata
+            .into_iter()
+            .filter_map(|(key, value)| Some((key.to_string(), value.try_into().ok()?)))
+            .collect()
+    }
+
+    #[zbus(property)]
+    fn volume(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn position(&self) -> i64 {
+        0
+    }
+
+    #[zbus(property)]
+    fn minimum_rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn maximum_rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn can_go_next(&self) -> bool {
+        self.player.can_go_next()
+    }
+
+    #[zbus(property)]
+    fn can_go_previous(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn can_play(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_pause(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_seek(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn can_control(&self) -> bool {
+        true
+    }
+}
+
+/// Register the player on the session bus and serve it until the bus goes away
+///
+/// Failing to reach the bus (e.g. on a headless machine) is logged and ends the task.
+pub fn spawn_mpris(player: MprisPlayer) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let served = async {
+            let bus = connection::Builder::session()?.name(BUS_NAME)?;
+            serve(bus, player).await
+        };
+        if let Err(e) = served.await {
+            log::warn!("MPRIS unavailable: {}", e);
+        }
+    })
+}
+
+/// Export the player on the connection `bus` builds, signalling changes to
+/// the status, the queue and the track
+async fn serve(bus: connection::Builder<'_>, player: MprisPlayer) -> zbus::Result<()> {
+    let changed = Arc::new(Notify::new());
+    let root = RootInterface {
+        identity: player.identity.clone(),
+    };
+    let interface = PlayerInterface {
+        player: player.clone(),
+        changed: changed.clone(),
+    };
+    let connection = bus
+        .serve_at(OBJECT_PATH, root)?
+        .serve_at(OBJECT_PATH, interface)?
+        .build()
+        .await?;
+    log::info!("MPRIS player registered as {}", BUS_NAME);
+
+    let emitter = SignalEmitter::new(&connection, OBJECT_PATH)?;
+    let mut ticker = tokio::time::interval(POLL_INTERVAL);
+    let mut last = Polled::of(&player);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = changed.notified() => {}
+        }
+        let polled = Polled::of(&player);
+        if polled == last {
+            continue;
+        }
+        let interface = connection
+            .object_server()
+            .interface::<_, PlayerInterface>(OBJECT_PATH)
+            .await?;
+        let interface = interface.get().await;
+        if polled.status != last.status {
+            interface.playback_status_changed(&emitter).await?;
+        }
+        if polled.can_go_next != last.can_go_next {
+            interface.can_go_next_changed(&emitter).await?;
+        }
+        if polled.track != last.track {
+            interface.metadata_changed(&emitter).await?;
+        }
+        last = polled;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::source_events::SourceEvent;
+    use futures_util::StreamExt;
+    use zbus::message::Type;
+    use zbus::{Connection, MessageStream};
+
+    const PLAYER_INTERFACE: &str = "org.mpris.MediaPlayer2.Player";
+    const PROPERTIES_INTERFACE: &str = "org.freedesktop.DBus.Properties";
+
+    fn player() -> (MprisPlayer, Arc<GroupManager>) {
+        let client_manager = Arc::new(ClientManager::new());
+        let group_manager = Arc::new(GroupManager::new());
+        group_manager.add_to_group("p1", "default");
+        let player = MprisPlayer::new(
+            "Test House",
+            client_manager,
+            group_manager.clone(),
+            SourceControl::new(),
+        );
+        (player, group_manager)
+    }
+
+    /// Serve the player over a socket pair, returning the peer's connection
+    async fn connect(player: MprisPlayer) -> Connection {
+        let (server, client) = tokio::net::UnixStream::pair().unwrap();
+        let bus = connection::Builder::unix_stream(server)
+            .server(zbus::Guid::generate())
+            .unwrap()
+            .p2p();
+        tokio::spawn(serve(bus, player));
+        connection::Builder::unix_stream(client)
+            .p2p()
+            .build()
+            .await
+            .unwrap()
+    }
+
+    async fn call<B>(peer: &Connection, interface: &str, member: &str, body: &B) -> zbus::Message
+    where
+        B: serde::Serialize + zbus::zvariant::DynamicType,
+    {
+        peer.call_method(Some(BUS_NAME), OBJECT_PATH, Some(interface), member, body)
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn test_play_pause_whole_house() {
+        let (player, group_manager) = player();
+        assert_eq!(player.playback_status(), "Stopped");
+
+        player.play_pause();
+        assert_eq!(player.playback_status(), "Playing");
+        assert_eq!(
+            group_manager.get_playback_state("default"),
+            Some(PlaybackState::Playing)
+        );
+
+        player.play_pause();
+        assert_eq!(player.playback_status(), "Paused");
+
+        player.stop();
+        assert_eq!(player.playback_status(), "Stopped");
+    }
+
+    #[tokio::test]
+    async fn test_properties_over_dbus() {
+        let (player, _) = player();
+        let peer = connect(player).await;
+
+        let args = ("org.mpris.MediaPlayer2", "Identity");
+        let reply = call(&peer, PROPERTIES_INTERFACE, "Get", &args).await;
+        let identity: OwnedValue = reply.body().deserialize().unwrap();
+        assert_eq!(String::try_from(identity).unwrap(), "Test House");
+
+        let reply = call(&peer, PROPERTIES_INTERFACE, "GetAll", &PLAYER_INTERFACE).await;
+        let properties: HashMap<String, OwnedValue> = reply.body().deserialize().unwrap();
+        assert_eq!(properties.len(), 13);
+        let status = properties["PlaybackStatus"].try_clone().unwrap();
+        assert_eq!(String::try_from(status).unwrap(), "Stopped");
+    }
+
+    #[tokio::test]
+    async fn test_play_pause_signals_the_new_status() {
+        let (player, group_manager) = player();
+        let peer = connect(player).await;
+        let mut messages = MessageStream::from(&peer);
+
+        call(&peer, PLAYER_INTERFACE, "PlayPause", &()).await;
+        assert_eq!(
+            group_manager.get_playback_state("default"),
+            Some(PlaybackState::Playing)
+        );
+        let signal = loop {
+            let message = messages.next().await.unwrap().unwrap();
+            if message.message_type() == Type::Signal {
+                break message;
+            }
+        };
+        assert_eq!(signal.header().member().unwrap(), "PropertiesChanged");
+        let (interface, changed, _): (String, HashMap<String, OwnedValue>, Vec<String>) =
+            signal.body().deserialize().unwrap();
+        assert_eq!(interface, PLAYER_INTERFACE);
+        let status = changed["PlaybackStatus"].try_clone().unwrap();
+        assert_eq!(String::try_from(status).unwrap(), "Playing");
+    }
+
+    #[tokio::test]
+    async fn test_next_skips_queue_and_metadata_follows_track() {
+        let source_control = SourceControl::new();
+        let player = MprisPlayer::new(
+            "Test House",
+            Arc::new(ClientManager::new()),
+            Arc::new(GroupManager::new()),
+            source_control.clone(),
+        );
+        let peer = connect(player).await;
+        let get = |property: &'static str| {
+            let peer = peer.clone();
+            async move {
+                let args = (PLAYER_INTERFACE, property);
+                let reply = call(&peer, PROPERTIES_INTERFACE, "Get", &args).await;
+                reply.body().deserialize::<OwnedValue>().unwrap()
+            }
+        };
+
+        // Nothing queued: Next has nothing to skip to
+        assert!(!bool::try_from(get("CanGoNext").await).unwrap());
+        call(&peer, PLAYER_INTERFACE, "Next", &()).await;
+        assert!(!source_control.take_skip());
+
+        source_control.queue().enqueue("next.flac");
+        assert!(bool::try_from(get("CanGoNext").await).unwrap());
+        call(&peer, PLAYER_INTERFACE, "Next", &()).await;
+        assert!(source_control.take_skip());
+
+        source_control.events().emit(SourceEvent::TrackChanged {
+            source: Some("song.flac".to_string()),
+            track: TrackInfo {
+                title: Some("Song".to_string()),
+                artist: Some("Band".to_string()),
+                ..TrackInfo::default()
+            },
+        });
+        let metadata: HashMap<String, OwnedValue> = get("Metadata").await.try_into().unwrap();
+        let title = metadata["xesam:title"].try_clone().unwrap();
+        assert_eq!(String::try_from(title).unwrap(), "Song");
+        let artist = metadata["xesam:artist"].try_clone().unwrap();
+        assert_eq!(Vec::<String>::try_from(artist).unwrap(), ["Band"]);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_method_is_error() {
+        let (player, _) = player();
+        let peer = connect(player).await;
+        let error = peer
+            .call_method(
+                Some(BUS_NAME),
+                OBJECT_PATH,
+                Some(PLAYER_INTERFACE),
+                "Explode",
+                &(),
+            )
+            .await
+            .unwrap_err();
+        let zbus::Error::MethodError(name, _, _) = &error else {
+            panic!("expected a D-Bus error reply, got {}", error);
+        };
+        assert_eq!(name.as_str(), "org.freedesktop.DBus.Error.UnknownMethod");
+    }
+}
@@ -0,0 +1,176 @@
+// ABOUTME: Per-client settings kept across server restarts
+// ABOUTME: Persistence trait with an embedded sled store and an in-memory store for tests
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// How often sled writes saved settings to disk (ms)
+///
+/// Saves only update sled's in-memory state, so a burst of volume changes
+/// costs one write per interval.
+pub const FLUSH_INTERVAL_MS: u64 = 500;
+
+/// Settings of one client worth keeping across restarts
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientRecord {
+    /// Volume the client last had (None if it never connected)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub volume: Option<u8>,
+    /// Whether the client was muted
+    #[serde(default)]
+    pub muted: bool,
+    /// Display name set by the operator, replacing the client's own
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Group the client was in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group_id: Option<String>,
+}
+
+/// Storage for [`ClientRecord`]s, keyed by client ID
+pub trait Persistence: Send + Sync + std::fmt::Debug {
+    /// The stored settings of a client, if any
+    fn load(&self, client_id: &str) -> Result<Option<ClientRecord>, String>;
+
+    /// Store a client's settings, replacing any stored before
+    fn save(&self, client_id: &str, record: &ClientRecord) -> Result<(), String>;
+
+    /// Forget a client
+    fn remove(&self, client_id: &str) -> Result<(), String>;
+
+    /// Write anything not yet durable to disk, blocking until done
+    ///
+    /// Stores that write through on every save need not override this.
+    fn flush(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Client settings in an embedded sled database
+///
+/// Changes reach disk every [`FLUSH_INTERVAL_MS`] and when the database is
+/// closed, not on every save.
+#[derive(Debug)]
+pub struct SledPersistence {
+    db: sled::Db,
+}
+
+impl SledPersistence {
+    /// Open or create the database in directory `path`
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let db = sled::Config::new()
+            .path(path)
+            .flush_every_ms(Some(FLUSH_INTERVAL_MS))
+            .open()
+            .map_err(|e| format!("cannot open {}: {}", path.display(), e))?;
+        Ok(Self { db })
+    }
+}
+
+impl Persistence for SledPersistence {
+    fn load(&self, client_id: &str) -> Result<Option<ClientRecord>, String> {
+        let Some(bytes) = self.db.get(client_id).map_err(|e| e.to_string())? else {
+            return Ok(None);
+        };
+        serde_json::from_slice(&bytes)
+            .map(Some)
+            .map_err(|e| format!("corrupt record for {}: {}", client_id, e))
+    }
+
+    fn save(&self, client_id: &str, record: &ClientRecord) -> Result<(), String> {
+        let bytes = serde_json::to_vec(record).map_err(|e| e.to_string())?;
+        self.db
+            .insert(client_id, bytes)
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    fn remove(&self, client_id: &str) -> Result<(), String> {
+        self.db.remove(client_id).map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), String> {
+        self.db.flush().map(|_| ()).map_err(|e| e.to_string())
+    }
+}
+
+/// Client settings kept in memory, lost when the process exits
+#[derive(Debug, Default)]
+pub struct MemoryPersistence {
+    records: Mutex<HashMap<String, ClientRecord>>,
+}
+
+impl MemoryPersistence {
+    /// Create an empty store
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Persistence for MemoryPersistence {
+    fn load(&self, client_id: &str) -> Result<Option<ClientRecord>, String> {
+        Ok(self.records.lock().get(client_id).cloned())
+    }
+
+    fn save(&self, client_id: &str, record: &ClientRecord) -> Result<(), String> {
+        self.records
+            .lock()
+            .insert(client_id.to_string(), record.clone());
+        Ok(())
+    }
+
+    fn remove(&self, client_id: &str) -> Result<(), String> {
+        self.records.lock().remove(client_id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temporary_store() -> SledPersistence {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        SledPersistence { db }
+    }
+
+    fn kitchen() -> ClientRecord {
+        ClientRecord {
+            volume: Some(35),
+            muted: true,
+            name: Some("Kitchen".to_string()),
+            group_id: Some("downstairs".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_sled_round_trip() {
+        let store = temporary_store();
+        assert_eq!(store.load("kitchen").unwrap(), None);
+        store.save("kitchen", &kitchen()).unwrap();
+        store.save("den", &ClientRecord::default()).unwrap();
+        store.remove("den").unwrap();
+
+        assert_eq!(store.load("kitchen").unwrap(), Some(kitchen()));
+        assert_eq!(store.load("den").unwrap(), None);
+    }
+
+    #[test]
+    fn test_sled_survives_reopen() {
+        let dir = std::env::temp_dir().join(format!("sendspin-persist-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        {
+            let store = SledPersistence::open(&dir).unwrap();
+            store.save("kitchen", &kitchen()).unwrap();
+            store.flush().unwrap();
+        }
+        let store = SledPersistence::open(&dir).unwrap();
+        assert_eq!(store.load("kitchen").unwrap(), Some(kitchen()));
+        drop(store);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
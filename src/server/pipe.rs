@@ -0,0 +1,293 @@
+// ABOUTME: Stream source reading raw PCM from a named pipe or stdin, as Snapcast does
+// ABOUTME: Lets MPD, Mopidy and shairport-sync feed the server through their FIFO outputs
+
+use crate::audio::types::Sample;
+use crate::protocol::messages::AudioFormatSpec;
+use crate::server::audio_source::AudioSource;
+use crate::server::ingest::{ingest_source, IngestFeed, IngestSource};
+use std::fmt;
+use std::fs::File;
+use std::io::{ErrorKind, Read};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// URI scheme for [`PipeSource`]s: `pipe:///tmp/snapfifo?sampleformat=48000:16:2`
+pub const PIPE_SCHEME: &str = "pipe:";
+
+/// Pipe path that reads the server's standard input
+pub const STDIN_PIPE: &str = "-";
+
+/// Buffered audio at which the reader stops draining the pipe (ms)
+///
+/// Leaves writers that produce faster than real time blocked on the pipe
+/// instead of overflowing the buffer.
+pub const PIPE_HIGH_WATER_MS: u64 = 500;
+
+/// How often a reader waiting for the buffer to drain checks again
+const DRAIN_POLL: Duration = Duration::from_millis(10);
+
+/// Layout of the raw PCM in a pipe, written `rate:bits:channels` as in Snapcast
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PipeFormat {
+    /// Sample rate in Hz
+    pub sample_rate: u32,
+    /// Bits per little-endian sample (16 or 24)
+    pub bit_depth: u8,
+    /// Interleaved channels (1 or 2)
+    pub channels: u8,
+}
+
+impl Default for PipeFormat {
+    fn default() -> Self {
+        Self {
+            sample_rate: 48000,
+            bit_depth: 16,
+            channels: 2,
+        }
+    }
+}
+
+impl FromStr for PipeFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = s.split(':').collect();
+        let [rate, bits, channels] = fields[..] else {
+            return Err(format!("'{}' is not rate:bits:channels", s));
+        };
+        let number = |field: &str| {
+            field
+                .parse::<u32>()
+                .map_err(|_| format!("'{}' in sample format '{}' is not a number", field, s))
+        };
+        Ok(Self {
+            sample_rate: number(rate)?,
+            bit_depth: number(bits)? as u8,
+            channels: number(channels)? as u8,
+        })
+    }
+}
+
+impl fmt::Display for PipeFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}",
+            self.sample_rate, self.bit_depth, self.channels
+        )
+    }
+}
+
+/// Split a `pipe:` URI into its path and sample format
+///
+/// Returns None for URIs of other schemes. Query parameters other than
+/// `sampleformat` (Snapcast's `name`, `mode`, ...) are ignored.
+pub fn parse_pipe_uri(uri: &str) -> Option<Result<(String, PipeFormat), String>> {
+    let rest = uri.strip_prefix(PIPE_SCHEME)?;
+    let rest = rest.strip_prefix("//").unwrap_or(rest);
+    let (path, query) = rest.split_once('?').unwrap_or((rest, ""));
+    let format = query
+        .split('&')
+        .find_map(|param| param.strip_prefix("sampleformat="))
+        .map(str::parse)
+        .unwrap_or(Ok(PipeFormat::default()));
+    Some(format.map(|format| (path.to_string(), format)))
+}
+
+/// Stream source playing raw PCM written to a named pipe or stdin
+///
+/// A reader thread drains the pipe into a buffer like a source client's, so
+/// the stream plays silence while the writer is paused. When the writer of
+/// a FIFO closes it, the reader waits for the next one; stdin and regular
+/// files end the source at end of file. A reader blocked on an idle pipe
+/// notices the source is gone the next time it reads.
+pub struct PipeSource {
+    path: String,
+    input: IngestSource,
+}
+
+impl PipeSource {
+    /// Start reading `path` ([`STDIN_PIPE`] for stdin) as PCM in `format`
+    pub fn open(path: &str, format: PipeFormat) -> Result<Self, String> {
+        if path != STDIN_PIPE && !Path::new(path).exists() {
+            return Err(format!("pipe '{}' does not exist", path));
+        }
+        let spec = AudioFormatSpec {
+            codec: "pcm".to_string(),
+            channels: format.channels,
+            sample_rate: format.sample_rate,
+            bit_depth: format.bit_depth,
+        };
+        let (input, feed) = ingest_source(path, &spec)?;
+        let reader_path = path.to_string();
+        std::thread::Builder::new()
+            .name("audio-pipe".to_string())
+            .spawn(move || read_pipe(&reader_path, format, feed))
+            .map_err(|e| format!("cannot start pipe reader: {}", e))?;
+        log::info!("Reading {} PCM from pipe '{}'", format, path);
+        Ok(Self {
+            path: path.to_string(),
+            input,
+        })
+    }
+}
+
+/// Whether the writer closing `path` should be waited out rather than end it
+fn reopens(path: &str) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        path != STDIN_PIPE && std::fs::metadata(path).is_ok_and(|m| m.file_type().is_fifo())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        false
+    }
+}
+
+/// Reopen the pipe after each writer until the source goes away
+fn read_pipe(path: &str, format: PipeFormat, feed: IngestFeed) {
+    loop {
+        let mut reader: Box<dyn Read> = if path == STDIN_PIPE {
+            Box::new(std::io::stdin())
+        } else {
+            // Opening a FIFO waits for a writer
+            match File::open(path) {
+                Ok(file) => Box::new(file),
+                Err(e) => {
+                    log::warn!("Cannot open pipe '{}': {}", path, e);
+                    return;
+                }
+            }
+        };
+        if !pump_pcm(path, &mut reader, format, &feed) {
+            return;
+        }
+        if !reopens(path) {
+            log::info!("Pipe '{}' ended", path);
+            return;
+        }
+        log::debug!("Writer closed pipe '{}', waiting for the next", path);
+    }
+}
+
+/// Copy whole frames of PCM in `format` from `reader` into `feed` until end of file
+///
+/// Returns false if reading should stop for good: the source is gone or the
+/// audio could not be decoded.
+pub(crate) fn pump_pcm(
+    label: &str,
+    reader: &mut dyn Read,
+    format: PipeFormat,
+    feed: &IngestFeed,
+) -> bool {
+    let frame_bytes = format.bit_depth as usize / 8 * format.channels as usize;
+    let high_water = (format.sample_rate as u64 * PIPE_HIGH_WATER_MS / 1000) as usize * 2;
+    let mut buf = vec![0u8; frame_bytes * 1024];
+    // Bytes of a partial frame kept at the start of `buf`
+    let mut pending = 0;
+    loop {
+        let filled = match reader.read(&mut buf[pending..]) {
+            Ok(0) => return feed.is_listening(),
+            Ok(n) => pending + n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => {
+                log::warn!("Reading '{}' failed: {}", label, e);
+                return feed.is_listening();
+            }
+        };
+        let whole = filled - filled % frame_bytes;
+        if let Err(e) = feed.push(&buf[..whole]) {
+            log::warn!("'{}' sent undecodable audio: {}", label, e);
+            return false;
+        }
+        buf.copy_within(whole..filled, 0);
+        pending = filled - whole;
+        while feed.is_listening() && feed.buffered_samples() >= high_water {
+            std::thread::sleep(DRAIN_POLL);
+        }
+        if !feed.is_listening() {
+            return false;
+        }
+    }
+}
+
+impl AudioSource for PipeSource {
+    fn read_chunk(&mut self, samples_per_channel: usize) -> Option<Vec<Sample>> {
+        self.input.read_chunk(samples_per_channel)
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn channels(&self) -> u8 {
+        2
+    }
+
+    fn is_exhausted(&self) -> bool {
+        self.input.is_exhausted()
+    }
+
+    fn description(&self) -> Option<String> {
+        Some(format!("pipe {}", self.path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn test_parse_pipe_uri() {
+        let (path, format) =
+            parse_pipe_uri("pipe:///tmp/snapfifo?name=mpd&sampleformat=44100:24:1")
+                .unwrap()
+                .unwrap();
+        assert_eq!(path, "/tmp/snapfifo");
+        assert_eq!(format.to_string(), "44100:24:1");
+
+        let (path, format) = parse_pipe_uri("pipe:-").unwrap().unwrap();
+        assert_eq!(path, STDIN_PIPE);
+        assert_eq!(format, PipeFormat::default());
+
+        assert!(parse_pipe_uri("pipe:/tmp/fifo?sampleformat=48000:16")
+            .unwrap()
+            .is_err());
+        assert!(parse_pipe_uri("/tmp/song.flac").is_none());
+    }
+
+    #[test]
+    fn test_reads_file_to_end() {
+        let path = std::env::temp_dir().join(format!("sendspin-pipe-{}.raw", std::process::id()));
+        // 2400 stereo frames at 8kHz, split across the reader's buffer
+        let pcm: Vec<u8> = std::iter::repeat_n(100i16.to_le_bytes(), 4800)
+            .flatten()
+            .collect();
+        std::fs::write(&path, &pcm).unwrap();
+        let format = PipeFormat {
+            sample_rate: 8000,
+            bit_depth: 16,
+            channels: 2,
+        };
+        let mut source = PipeSource::open(path.to_str().unwrap(), format).unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut played = 0;
+        while let Some(chunk) = source.read_chunk(50) {
+            played += chunk
+                .iter()
+                .filter(|&&s| s == Sample::from_i16(100))
+                .count();
+            assert!(Instant::now() < deadline, "pipe never ended");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(played, 4800);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(PipeSource::open("/nonexistent/fifo", format).is_err());
+    }
+}
@@ -0,0 +1,281 @@
+// ABOUTME: Description of the audio pipeline each group is playing through
+// ABOUTME: Source, processing stages, and the encoder branches feeding each set of clients
+
+use crate::audio::downmix::ChannelMap;
+use crate::audio::replay_gain::ReplayGainMode;
+use crate::server::client_manager::{ClientId, ClientManager};
+use crate::server::config::ServerConfig;
+use crate::server::encoder::EncoderSettings;
+use crate::server::group::{GroupManager, PlaybackState};
+use crate::server::source_control::SourceControl;
+use crate::server::source_fallback::Fallback;
+use serde::{Deserialize, Serialize};
+
+/// The source feeding every group
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PipelineSource {
+    /// Source description (file path, URL, ...)
+    pub description: Option<String>,
+    /// Sample rate in Hz
+    pub sample_rate: u32,
+    /// Channel count
+    pub channels: u8,
+}
+
+/// A processing stage between the source and the encoders, in pipeline order
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PipelineStage {
+    /// Gain from each file's ReplayGain tags
+    ReplayGain {
+        /// Which gain is preferred
+        mode: ReplayGainMode,
+        /// Gain added to the tagged gain (dB)
+        preamp_db: f64,
+        /// Whether the gain is lowered where the tagged peak would clip
+        prevent_clipping: bool,
+    },
+    /// Leading silence trimmed from each new track
+    SilenceTrim {
+        /// Level below which audio counts as silence, in dBFS
+        threshold_db: f64,
+        /// Most silence trimmed from one track
+        max_ms: u64,
+    },
+    /// Failover while the source keeps failing
+    Fallback {
+        /// What plays instead
+        fallback: String,
+        /// How long the source must fail before switching
+        fail_after_ms: u64,
+    },
+    /// Loudness normalization of each source
+    Loudness {
+        /// Integrated loudness sources are brought to (LUFS)
+        target_lufs: f64,
+        /// Most gain applied to a quiet source (dB)
+        max_gain_db: f64,
+    },
+    /// Night-mode compression of this group's audio
+    NightMode {
+        /// Level above which gain is reduced (dBFS)
+        threshold_db: f32,
+        /// Compression ratio
+        ratio: f32,
+        /// Bass attenuation, if enabled (dB)
+        bass_cut_db: Option<f32>,
+    },
+    /// Mid/side stereo width of this group's audio
+    StereoWidth {
+        /// Side-signal gain (1 leaves the image unchanged)
+        width: f32,
+        /// Frequency below which bass is kept centered (Hz)
+        bass_mono_hz: Option<f32>,
+    },
+}
+
+/// One encoder and the clients sharing its output
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EncoderBranch {
+    /// Codec name
+    pub codec: String,
+    /// Sample rate sent in Hz; the source is resampled to it if needed
+    pub sample_rate: u32,
+    /// Channel count
+    pub channels: u8,
+    /// Bits per sample
+    pub bit_depth: u8,
+    /// Encoder tuning in effect
+    pub settings: EncoderSettings,
+    /// Software volume applied before encoding, in percent (100 leaves the audio untouched)
+    pub gain: u8,
+    /// What the left and right channels carry
+    pub channel_map: ChannelMap,
+    /// Clients receiving this branch, sorted
+    pub clients: Vec<ClientId>,
+}
+
+/// The pipeline of one group, from source to clients
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PipelineGraph {
+    /// Group identifier
+    pub group_id: String,
+    /// Playback state; only playing groups have audio flowing
+    pub playback_state: PlaybackState,
+    /// How far ahead of playback chunks are sent
+    pub buffer_ahead_ms: u64,
+    /// Stream the group is subscribed to
+    pub stream_id: String,
+    /// Where the audio comes from
+    pub source: PipelineSource,
+    /// Processing applied before the audio is split per client
+    pub stages: Vec<PipelineStage>,
+    /// Encoders feeding the group's clients, sorted by codec and client
+    pub branches: Vec<EncoderBranch>,
+}
+
+/// Describe the pipeline a group's audio goes through, or None for an unknown group
+pub fn describe_pipeline(
+    group_id: &str,
+    config: &ServerConfig,
+    client_manager: &ClientManager,
+    group_manager: &GroupManager,
+    source_control: &SourceControl,
+) -> Option<PipelineGraph> {
+    let (_, _, playback_state) = group_manager.get_group(group_id)?;
+    let now_playing = source_control.now_playing();
+
+    // Trimming wraps the source inside the fallback, as in the engine
+    let mut stages = Vec::new();
+    if let Some(replay_gain) = config.replay_gain {
+        stages.push(PipelineStage::ReplayGain {
+            mode: replay_gain.mode,
+            preamp_db: replay_gain.preamp_db,
+            prevent_clipping: replay_gain.prevent_clipping,
+        });
+    }
+    if let Some(trim) = config.silence_trim {
+        stages.push(PipelineStage::SilenceTrim {
+            threshold_db: trim.threshold_db,
+            max_ms: trim.max.as_millis() as u64,
+        });
+    }
+    if let Some(fallback) = &config.source_fallback {
+        stages.push(PipelineStage::Fallback {
+            fallback: match &fallback.fallback {
+                Fallback::Silence => "silence".to_string(),
+                Fallback::Tone(hz) => format!("tone {} Hz", hz),
+                Fallback::Uri(uri) => uri.clone(),
+            },
+            fail_after_ms: fallback.fail_after.as_millis() as u64,
+        });
+    }
+    if let Some(loudness) = config.loudness {
+        stages.push(PipelineStage::Loudness {
+            target_lufs: loudness.target_lufs,
+            max_gain_db: loudness.max_gain_db,
+        });
+    }
+    if let Some(night) = group_manager.get_night_mode(group_id) {
+        stages.push(PipelineStage::NightMode {
+            threshold_db: night.threshold_db,
+            ratio: night.ratio,
+            bass_cut_db: night.bass_cut_db,
+        });
+    }
+    if let Some(width) = group_manager.get_stereo_width(group_id) {
+        if !width.is_bypass() {
+            stages.push(PipelineStage::StereoWidth {
+                width: width.width,
+                bass_mono_hz: width.bass_mono_hz,
+            });
+        }
+    }
+
+    let settings = config.encoder_settings_for(group_manager.get_encoder_settings(group_id));
+    let members = group_manager
+        .get_group_members(group_id)
+        .into_iter()
+        .collect();
+    let mut branches: Vec<EncoderBranch> = client_manager
+        .group_by_output(&members)
+        .into_iter()
+        .map(|(output, clients)| {
+            let mut clients: Vec<ClientId> = clients.into_iter().collect();
+            clients.sort();
+            EncoderBranch {
+                codec: output.format.codec.name().to_string(),
+                sample_rate: output.format.sample_rate,
+                channels: output.format.channels,
+                bit_depth: output.format.bit_depth,
+                settings: output.encoder.or(settings),
+                gain: output.gain,
+                channel_map: output.channel_map,
+                clients,
+            }
+        })
+        .collect();
+    branches.sort_by(|a, b| (&a.codec, &a.clients).cmp(&(&b.codec, &b.clients)));
+
+    Some(PipelineGraph {
+        group_id: group_id.to_string(),
+        playback_state,
+        buffer_ahead_ms: group_manager
+            .get_buffer_ahead(group_id)
+            .or(group_manager.default_buffer_ahead())
+            .unwrap_or(config.buffer_ahead_ms),
+        stream_id: group_manager.get_stream(group_id)?,
+        source: PipelineSource {
+            description: now_playing.source,
+            sample_rate: now_playing.sample_rate,
+            channels: now_playing.channels,
+        },
+        stages,
+        branches,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::drc::NightMode;
+    use crate::audio::spatial::StereoWidth;
+    use crate::server::client_manager::ConnectedClient;
+    use crate::server::track_start::SilenceTrim;
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn test_describe_pipeline() {
+        let clients = ClientManager::new();
+        let groups = GroupManager::new();
+        let mut receivers = Vec::new();
+        for id in ["den", "kitchen"] {
+            let (tx, rx) = mpsc::unbounded_channel();
+            clients.add_client(ConnectedClient::new(id.to_string(), id.to_string(), tx));
+            groups.add_to_group(id, groups.default_group_id());
+            receivers.push(rx);
+        }
+        clients.set_mono("den", true);
+        groups.set_night_mode(groups.default_group_id(), Some(NightMode::default()));
+        groups.set_stereo_width(groups.default_group_id(), Some(StereoWidth::new(1.5)));
+        let config = ServerConfig::default().silence_trim(SilenceTrim::default());
+
+        let graph = describe_pipeline(
+            groups.default_group_id(),
+            &config,
+            &clients,
+            &groups,
+            &SourceControl::default(),
+        )
+        .unwrap();
+        assert_eq!(graph.buffer_ahead_ms, config.buffer_ahead_ms);
+        assert_eq!(graph.stream_id, "default");
+        assert!(matches!(
+            graph.stages[0],
+            PipelineStage::SilenceTrim { max_ms: 2000, .. }
+        ));
+        assert!(matches!(graph.stages[1], PipelineStage::NightMode { .. }));
+        assert!(matches!(
+            graph.stages[2],
+            PipelineStage::StereoWidth { width: 1.5, .. }
+        ));
+
+        // Mono changes the processing, so the two clients get separate branches
+        assert_eq!(graph.branches.len(), 2);
+        let den = graph
+            .branches
+            .iter()
+            .find(|b| b.clients == ["den"])
+            .unwrap();
+        assert_eq!(den.channel_map, ChannelMap::Mono);
+
+        assert!(describe_pipeline(
+            "nope",
+            &config,
+            &clients,
+            &groups,
+            &SourceControl::default()
+        )
+        .is_none());
+    }
+}
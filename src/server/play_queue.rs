@@ -0,0 +1,127 @@
+// ABOUTME: Play queue of files and URLs a stream plays once each, in order
+// ABOUTME: The audio engine opens the next entry when its current source ends or is skipped
+
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+/// A file or URL waiting in a [`PlayQueue`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct QueueEntry {
+    /// Identifier for removing or moving the entry, unique within its queue
+    pub id: u64,
+    /// File path, `file://` URI, or HTTP(S) URL
+    pub uri: String,
+}
+
+#[derive(Default)]
+struct QueueState {
+    entries: VecDeque<QueueEntry>,
+    next_id: u64,
+}
+
+/// Tracks a stream plays after its current source
+///
+/// Cloning gives another handle to the same queue. Entries are opened when
+/// they come up, so one that fails to open is skipped then rather than
+/// refused when enqueued.
+#[derive(Clone, Default)]
+pub struct PlayQueue {
+    state: Arc<Mutex<QueueState>>,
+}
+
+impl PlayQueue {
+    /// Create an empty queue
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a file or URL to the end of the queue
+    pub fn enqueue(&self, uri: impl Into<String>) -> QueueEntry {
+        let mut state = self.state.lock();
+        state.next_id += 1;
+        let entry = QueueEntry {
+            id: state.next_id,
+            uri: uri.into(),
+        };
+        state.entries.push_back(entry.clone());
+        entry
+    }
+
+    /// Entries in the order they will play
+    pub fn entries(&self) -> Vec<QueueEntry> {
+        self.state.lock().entries.iter().cloned().collect()
+    }
+
+    /// Whether nothing is queued
+    pub fn is_empty(&self) -> bool {
+        self.state.lock().entries.is_empty()
+    }
+
+    /// Remove an entry; false if it is not queued
+    pub fn remove(&self, id: u64) -> bool {
+        let mut state = self.state.lock();
+        let before = state.entries.len();
+        state.entries.retain(|entry| entry.id != id);
+        state.entries.len() != before
+    }
+
+    /// Move an entry to `position` (0 plays next, past the end moves it last)
+    ///
+    /// Returns false if the entry is not queued.
+    pub fn move_to(&self, id: u64, position: usize) -> bool {
+        let mut state = self.state.lock();
+        let Some(index) = state.entries.iter().position(|entry| entry.id == id) else {
+            return false;
+        };
+        if let Some(entry) = state.entries.remove(index) {
+            let position = position.min(state.entries.len());
+            state.entries.insert(position, entry);
+        }
+        true
+    }
+
+    /// Remove every entry, returning how many there were
+    pub fn clear(&self) -> usize {
+        let mut state = self.state.lock();
+        let cleared = state.entries.len();
+        state.entries.clear();
+        cleared
+    }
+
+    /// Take the entry that plays next
+    pub(crate) fn pop(&self) -> Option<QueueEntry> {
+        self.state.lock().entries.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uris(queue: &PlayQueue) -> Vec<String> {
+        queue.entries().into_iter().map(|entry| entry.uri).collect()
+    }
+
+    #[test]
+    fn test_enqueue_reorder_and_remove() {
+        let queue = PlayQueue::new();
+        let a = queue.enqueue("a.flac");
+        let b = queue.enqueue("b.flac");
+        let c = queue.enqueue("http://radio/c.mp3");
+        assert_ne!(a.id, b.id);
+
+        assert!(queue.move_to(c.id, 0));
+        assert_eq!(uris(&queue), ["http://radio/c.mp3", "a.flac", "b.flac"]);
+        assert!(queue.move_to(c.id, 10));
+        assert_eq!(uris(&queue), ["a.flac", "b.flac", "http://radio/c.mp3"]);
+
+        assert!(queue.remove(b.id));
+        assert!(!queue.remove(b.id));
+        assert!(!queue.move_to(b.id, 0));
+        assert_eq!(queue.pop(), Some(a));
+        assert_eq!(queue.clear(), 1);
+        assert!(queue.is_empty());
+    }
+}
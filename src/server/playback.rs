@@ -0,0 +1,770 @@
+// ABOUTME: Group playback state machine
+// ABOUTME: Applies auto-start policies and notifies group members of play/pause/stop transitions
+
+use crate::audio::types::AudioFormat;
+use crate::protocol::messages::{
+    ControllerState, GroupUpdate, Message, MessageTrace, ServerState, StreamClear,
+};
+use crate::server::client_handler::create_stream_start;
+use crate::server::client_manager::{ClientId, ClientManager, VolumeChange};
+use crate::server::group::{GroupManager, PlaybackState, StreamTiming};
+use crate::server::link_tier::LinkTier;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// Controller commands the server applies, as listed in `server/state`
+///
+/// `next` skips the group's stream to its next queued track. `previous` is not
+/// offered; play queues are managed through the control API.
+pub const CONTROLLER_COMMANDS: [&str; 6] = ["play", "pause", "stop", "next", "volume", "mute"];
+
+/// Coordinates group playback state with the connected clients
+///
+/// Transitions update the group's `PlaybackState` and tell its members:
+/// starting playback sends `stream/start` to players, stopping sends
+/// `stream/end`, and every change sends `group/update`. The audio engine only
+/// streams to members of playing groups.
+///
+/// Group membership changes made here are also recorded on the client, so
+/// `ConnectedClient::group_id` follows the `GroupManager`.
+#[derive(Debug, Clone)]
+pub struct PlaybackController {
+    client_manager: Arc<ClientManager>,
+    group_manager: Arc<GroupManager>,
+}
+
+impl PlaybackController {
+    /// Create a playback controller over the given managers
+    pub fn new(client_manager: Arc<ClientManager>, group_manager: Arc<GroupManager>) -> Self {
+        Self {
+            client_manager,
+            group_manager,
+        }
+    }
+
+    /// Start playback for a group
+    pub fn play(&self, group_id: &str) -> bool {
+        self.transition(group_id, PlaybackState::Playing)
+    }
+
+    /// Pause playback for a group (stream stays open, no audio is sent)
+    pub fn pause(&self, group_id: &str) -> bool {
+        self.transition(group_id, PlaybackState::Paused)
+    }
+
+    /// Stop playback for a group
+    pub fn stop(&self, group_id: &str) -> bool {
+        self.transition(group_id, PlaybackState::Stopped)
+    }
+
+    /// Change the volume or mute state of a group's players
+    ///
+    /// Players receive the matching commands, the group records its new volume
+    /// or mute state, and controllers in the group receive `server/state`.
+    /// Returns each player's resulting (client ID, volume, muted), sorted by
+    /// client ID.
+    pub fn set_group_volume(
+        &self,
+        group_id: &str,
+        change: VolumeChange,
+    ) -> Vec<(ClientId, u8, bool)> {
+        let members: HashSet<ClientId> = self
+            .group_manager
+            .get_group_members(group_id)
+            .into_iter()
+            .collect();
+        let result = self.client_manager.apply_volume(&members, change);
+        match change {
+            VolumeChange::Group(volume) => self.group_manager.set_volume(group_id, volume),
+            VolumeChange::Mute(muted) => self.group_manager.set_muted(group_id, muted),
+            _ => {}
+        }
+        self.notify_controllers(group_id);
+        result
+    }
+
+    /// Change a group's buffer-ahead (None reverts to the server default)
+    ///
+    /// Players in the group receive `stream/clear`, since audio already queued
+    /// with the old offset would otherwise overlap or leave a gap, followed by
+    /// `stream/start` carrying the new buffer-ahead.
+    pub fn set_buffer_ahead(&self, group_id: &str, buffer_ahead_ms: Option<u64>) -> bool {
+        if self.group_manager.get_buffer_ahead(group_id) == buffer_ahead_ms {
+            return self.group_manager.get_group(group_id).is_some();
+        }
+        if !self
+            .group_manager
+            .set_buffer_ahead(group_id, buffer_ahead_ms)
+        {
+            return false;
+        }
+        log::info!("Group {} buffer-ahead: {:?}ms", group_id, buffer_ahead_ms);
+        self.restart_players(group_id);
+        true
+    }
+
+    /// Change the buffer-ahead of every group without its own
+    ///
+    /// Players in those groups receive `stream/clear` and `stream/start`, as with
+    /// [`set_buffer_ahead`](Self::set_buffer_ahead).
+    pub fn set_default_buffer_ahead(&self, buffer_ahead_ms: u64) {
+        if self.group_manager.default_buffer_ahead() == Some(buffer_ahead_ms) {
+            return;
+        }
+        self.group_manager
+            .set_default_buffer_ahead(Some(buffer_ahead_ms));
+        log::info!("Default buffer-ahead: {}ms", buffer_ahead_ms);
+        for group_id in self.group_manager.group_ids() {
+            if self.group_manager.get_buffer_ahead(&group_id).is_none() {
+                self.restart_players(&group_id);
+            }
+        }
+    }
+
+    /// Tag a client with its link tier (None clears the tag)
+    ///
+    /// A connected player whose buffer-ahead changes with its tier is
+    /// restarted alone, as with [`set_buffer_ahead`](Self::set_buffer_ahead).
+    pub fn set_link_tier(&self, client_id: &str, tier: Option<LinkTier>) {
+        let before = self.client_timing(client_id);
+        self.client_manager.set_link_tier(client_id, tier);
+        log::info!("Client {} link tier: {:?}", client_id, tier);
+        self.retime_player(client_id, before);
+    }
+
+    /// Change the buffer-ahead of players on a link tier (None plays them
+    /// with their group)
+    ///
+    /// Connected players on the tier whose buffer-ahead changes are restarted.
+    pub fn set_tier_buffer_ahead(&self, tier: LinkTier, buffer_ahead_ms: Option<u64>) {
+        let players: Vec<(ClientId, Option<StreamTiming>)> = self
+            .client_manager
+            .player_ids()
+            .into_iter()
+            .filter(|id| self.client_manager.link_tier(id) == Some(tier))
+            .map(|id| {
+                let timing = self.client_timing(&id);
+                (id, timing)
+            })
+            .collect();
+        self.client_manager
+            .set_tier_buffer_ahead(tier, buffer_ahead_ms);
+        log::info!(
+            "{} tier buffer-ahead: {:?}ms",
+            tier.as_str(),
+            buffer_ahead_ms
+        );
+        for (client_id, before) in players {
+            self.retime_player(&client_id, before);
+        }
+    }
+
+    /// Restart a player its RTTs just placed on a tier, if that changed its timing
+    ///
+    /// `tier` is what [`ClientManager::update_stats`] returned.
+    pub fn link_tier_placed(&self, client_id: &str, tier: LinkTier) {
+        let Some(tier_ms) = self.client_manager.tier_buffer_ahead(tier) else {
+            return;
+        };
+        let before = self.group_timing(client_id);
+        if before.is_some_and(|timing| tier_ms > timing.buffer_ahead_ms) {
+            self.retime_player(client_id, before);
+        }
+    }
+
+    /// Put a client in a group and record it on the client, without notifying anyone
+    pub fn join_group(&self, client_id: &str, group_id: &str) -> bool {
+        if !self.group_manager.add_to_group(client_id, group_id) {
+            return false;
+        }
+        self.client_manager
+            .set_group_id(client_id, Some(group_id.to_string()));
+        true
+    }
+
+    /// Rename a group, sending `group/update` to its members if the name changed
+    pub fn rename_group(&self, group_id: &str, name: &str) -> bool {
+        let Some((_, previous, _)) = self.group_manager.get_group(group_id) else {
+            return false;
+        };
+        if previous != name {
+            self.group_manager.set_name(group_id, name);
+            self.notify_group(group_id);
+        }
+        true
+    }
+
+    /// Delete a group, moving its members to the default group
+    ///
+    /// Each member is moved as with [`move_client`](Self::move_client), so it
+    /// receives `group/update` for its new group. Returns the moved members;
+    /// the default group cannot be deleted.
+    pub fn delete_group(&self, group_id: &str) -> Vec<String> {
+        let default = self.group_manager.default_group_id().to_string();
+        if group_id == default {
+            return Vec::new();
+        }
+        let members = self.group_manager.get_group_members(group_id);
+        for member in &members {
+            self.move_client(member, &default);
+        }
+        self.group_manager.delete_group(group_id);
+        members
+    }
+
+    /// Combine `from` into `into`, so its members play in sync with `into`
+    ///
+    /// Players that were streaming drop the audio buffered from their old
+    /// group's timeline, then each member moves as with
+    /// [`move_client`](Self::move_client). `from` is deleted afterwards, or
+    /// left empty if it is the default group. Returns the moved members.
+    pub fn merge_groups(&self, from: &str, into: &str) -> Vec<String> {
+        if from == into || self.group_manager.get_group(into).is_none() {
+            return Vec::new();
+        }
+        let streaming = self
+            .group_manager
+            .get_playback_state(from)
+            .is_some_and(|state| state != PlaybackState::Stopped);
+        let clear = Message::StreamClear(StreamClear { roles: None });
+        let members = self.group_manager.get_group_members(from);
+        for member in &members {
+            if streaming && self.is_player(member) {
+                self.send(member, &clear);
+            }
+            self.move_client(member, into);
+        }
+        if from != self.group_manager.default_group_id() {
+            self.group_manager.delete_group(from);
+        }
+        members
+    }
+
+    /// Send `group/update` with the group's current state to all its members
+    pub fn notify_group(&self, group_id: &str) {
+        for member in self.group_manager.get_group_members(group_id) {
+            self.send_group_update(&member, group_id);
+        }
+    }
+
+    /// Send `server/state` with the group's controller state to its controllers
+    pub fn notify_controllers(&self, group_id: &str) {
+        for member in self.group_manager.get_group_members(group_id) {
+            self.send_controller_state(&member);
+        }
+    }
+
+    /// Send `server/state` to a controller for the group it is in
+    ///
+    /// Clients without the controller role are skipped.
+    pub fn send_controller_state(&self, client_id: &str) {
+        self.send_traced_controller_state(client_id, None);
+    }
+
+    /// Send `server/state` to a controller, echoing the `_trace` of the
+    /// client/command it reflects
+    pub fn send_traced_controller_state(&self, client_id: &str, trace: Option<MessageTrace>) {
+        if !self.client_manager.is_controller(client_id) {
+            return;
+        }
+        let Some(group_id) = self.group_manager.get_client_group(client_id) else {
+            return;
+        };
+        let (Some(volume), Some(muted)) = (
+            self.group_manager.get_volume(&group_id),
+            self.group_manager.get_muted(&group_id),
+        ) else {
+            return;
+        };
+        let state = Message::ServerState(ServerState {
+            metadata: None,
+            controller: Some(ControllerState {
+                supported_commands: CONTROLLER_COMMANDS.map(String::from).to_vec(),
+                volume,
+                muted,
+            }),
+            trace,
+        });
+        self.send(client_id, &state);
+    }
+
+    /// Tell a group's players to drop the audio they have queued
+    ///
+    /// Players of a group with a stream also get a fresh `stream/start` so
+    /// they can resize their buffers to the new timing.
+    pub fn restart_players(&self, group_id: &str) {
+        let clear = Message::StreamClear(StreamClear { roles: None });
+        let streaming = self
+            .group_manager
+            .get_playback_state(group_id)
+            .is_some_and(|state| state != PlaybackState::Stopped);
+        for member in self.group_manager.get_group_members(group_id) {
+            if self.is_player(&member) {
+                self.send(&member, &clear);
+                if streaming {
+                    self.send_stream_start(&member);
+                }
+            }
+        }
+    }
+
+    /// Restart one player if its timing is no longer `before`
+    fn retime_player(&self, client_id: &str, before: Option<StreamTiming>) {
+        if !self.is_player(client_id) || self.client_timing(client_id) == before {
+            return;
+        }
+        self.send(
+            client_id,
+            &Message::StreamClear(StreamClear { roles: None }),
+        );
+        let streaming = self
+            .group_manager
+            .get_client_group(client_id)
+            .and_then(|group_id| self.group_manager.get_playback_state(&group_id))
+            .is_some_and(|state| state != PlaybackState::Stopped);
+        if streaming {
+            self.send_stream_start(client_id);
+        }
+    }
+
+    /// Timing of a client's group
+    fn group_timing(&self, client_id: &str) -> Option<StreamTiming> {
+        let group_id = self.group_manager.get_client_group(client_id)?;
+        self.group_manager.stream_timing(&group_id)
+    }
+
+    /// Timing of a client's group with its link tier's buffer-ahead applied
+    fn client_timing(&self, client_id: &str) -> Option<StreamTiming> {
+        self.group_timing(client_id).map(|timing| StreamTiming {
+            buffer_ahead_ms: self
+                .client_manager
+                .buffer_ahead_for(client_id, timing.buffer_ahead_ms),
+            ..timing
+        })
+    }
+
+    fn transition(&self, group_id: &str, state: PlaybackState) -> bool {
+        let Some(previous) = self.group_manager.get_playback_state(group_id) else {
+            return false;
+        };
+        if previous == state {
+            return true;
+        }
+
+        self.group_manager.set_playback_state(group_id, state);
+        log::info!(
+            "Group {} playback: {} -> {}",
+            group_id,
+            previous.as_str(),
+            state.as_str()
+        );
+
+        let members = self.group_manager.get_group_members(group_id);
+        match state {
+            PlaybackState::Playing if previous == PlaybackState::Stopped => {
+                for member in &members {
+                    self.send_stream_start(member);
+                }
+            }
+            PlaybackState::Stopped => {
+                let end = Message::StreamEnd(crate::protocol::messages::StreamEnd { roles: None });
+                for member in &members {
+                    if self.is_player(member) {
+                        self.send(member, &end);
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        for member in &members {
+            self.send_group_update(member, group_id);
+        }
+        true
+    }
+
+    /// Handle a player joining its group
+    ///
+    /// If it is the group's first player, the group's auto-start policy decides
+    /// whether playback starts. The joining player receives `stream/start` if
+    /// the group is playing (or paused) and a `group/update` either way.
+    pub fn player_joined(&self, client_id: &str) {
+        let Some(group_id) = self.group_manager.get_client_group(client_id) else {
+            return;
+        };
+
+        let first_player = !self
+            .group_manager
+            .get_group_members(&group_id)
+            .iter()
+            .any(|m| m != client_id && self.is_player(m));
+
+        let state = if first_player {
+            self.group_manager.apply_auto_start(&group_id)
+        } else {
+            self.group_manager.get_playback_state(&group_id)
+        };
+
+        match state {
+            Some(PlaybackState::Playing) | Some(PlaybackState::Paused) => {
+                self.send_stream_start(client_id);
+            }
+            Some(PlaybackState::Stopped) => {
+                log::info!(
+                    "Group {} is stopped, player {} waits for play",
+                    group_id,
+                    client_id
+                );
+            }
+            None => return,
+        }
+        self.send_group_update(client_id, &group_id);
+    }
+
+    /// Handle a player leaving
+    ///
+    /// Call before the client is removed from its group. When the last player
+    /// leaves, the group is stopped and remembers whether it was playing.
+    pub fn player_left(&self, client_id: &str) {
+        let Some(group_id) = self.group_manager.get_client_group(client_id) else {
+            return;
+        };
+
+        let other_players = self
+            .group_manager
+            .get_group_members(&group_id)
+            .iter()
+            .any(|m| m != client_id && self.is_player(m));
+
+        if !other_players {
+            self.group_manager.suspend_group(&group_id);
+        }
+    }
+
+    /// Move a client to another group
+    ///
+    /// The client leaves its old group (stopping it if it was the last player)
+    /// and joins the new one as if it had just connected. A player that was
+    /// streaming receives `stream/end` if the new group is stopped.
+    pub fn move_client(&self, client_id: &str, group_id: &str) -> bool {
+        if self.group_manager.get_group(group_id).is_none() {
+            return false;
+        }
+        let previous = self.group_manager.get_client_group(client_id);
+        if previous.as_deref() == Some(group_id) {
+            return true;
+        }
+
+        if !self.is_player(client_id) {
+            self.join_group(client_id, group_id);
+            self.send_group_update(client_id, group_id);
+            self.send_controller_state(client_id);
+            return true;
+        }
+
+        let was_streaming = previous
+            .and_then(|id| self.group_manager.get_playback_state(&id))
+            .is_some_and(|state| state != PlaybackState::Stopped);
+        self.player_left(client_id);
+        self.join_group(client_id, group_id);
+        log::info!("Client {} moved to group {}", client_id, group_id);
+        self.player_joined(client_id);
+
+        if was_streaming
+            && self.group_manager.get_playback_state(group_id) == Some(PlaybackState::Stopped)
+        {
+            let end = Message::StreamEnd(crate::protocol::messages::StreamEnd { roles: None });
+            self.send(client_id, &end);
+        }
+        self.send_controller_state(client_id);
+        true
+    }
+
+    /// Switch a player to a new stream format
+    ///
+    /// A player whose group is playing or paused gets a fresh `stream/start`
+    /// straight away; the rest of its group keeps streaming undisturbed.
+    /// Returns false for unknown clients.
+    pub fn renegotiate(&self, client_id: &str, format: AudioFormat) -> bool {
+        if !self.client_manager.set_audio_format(client_id, format) {
+            return false;
+        }
+        let streaming = self
+            .group_manager
+            .get_client_group(client_id)
+            .and_then(|group_id| self.group_manager.get_playback_state(&group_id))
+            .is_some_and(|state| state != PlaybackState::Stopped);
+        if streaming {
+            self.send_stream_start(client_id);
+        }
+        true
+    }
+
+    fn is_player(&self, client_id: &str) -> bool {
+        self.client_manager.is_player(client_id)
+    }
+
+    fn send_stream_start(&self, client_id: &str) {
+        if !self.is_player(client_id) {
+            return;
+        }
+        let format = self
+            .client_manager
+            .get_audio_format(client_id)
+            .unwrap_or_else(ClientManager::default_audio_format);
+        let fec = self.client_manager.get_fec(client_id);
+        let timing = self.client_timing(client_id);
+        self.send(client_id, &create_stream_start(&format, fec, timing));
+    }
+
+    fn send_group_update(&self, client_id: &str, group_id: &str) {
+        let Some((id, name, state)) = self.group_manager.get_group(group_id) else {
+            return;
+        };
+        let update = Message::GroupUpdate(GroupUpdate {
+            playback_state: Some(state.as_str().to_string()),
+            group_id: Some(id),
+            group_name: Some(name),
+        });
+        self.send(client_id, &update);
+    }
+
+    fn send(&self, client_id: &str, msg: &Message) {
+        if let Ok(json) = serde_json::to_string(msg) {
+            self.client_manager.send_to_client(client_id, &json);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::client_manager::{ConnectedClient, ServerMessage};
+    use crate::server::group::{AutoStart, StreamTiming};
+    use tokio::sync::mpsc;
+
+    fn add_player(
+        client_manager: &ClientManager,
+        group_manager: &GroupManager,
+        id: &str,
+    ) -> mpsc::UnboundedReceiver<ServerMessage> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut client = ConnectedClient::new(id.to_string(), id.to_string(), tx);
+        client.active_roles = vec!["player@v1".to_string()];
+        client_manager.add_client(client);
+        group_manager.add_to_group(id, "default");
+        rx
+    }
+
+    fn message_types(rx: &mut mpsc::UnboundedReceiver<ServerMessage>) -> Vec<String> {
+        let mut types = Vec::new();
+        while let Ok(ServerMessage::Text(text)) = rx.try_recv() {
+            let value: serde_json::Value = serde_json::from_str(&text).unwrap();
+            types.push(value["type"].as_str().unwrap().to_string());
+        }
+        types
+    }
+
+    #[test]
+    fn test_never_waits_for_play() {
+        let client_manager = Arc::new(ClientManager::new());
+        let group_manager = Arc::new(GroupManager::new().with_default_auto_start(AutoStart::Never));
+        let playback = PlaybackController::new(client_manager.clone(), group_manager.clone());
+
+        let mut rx = add_player(&client_manager, &group_manager, "p1");
+        playback.player_joined("p1");
+        assert_eq!(message_types(&mut rx), ["group/update"]);
+        assert!(group_manager.playing_members().is_empty());
+
+        assert!(playback.play("default"));
+        assert_eq!(message_types(&mut rx), ["stream/start", "group/update"]);
+        assert!(group_manager.playing_members().contains("p1"));
+
+        assert!(playback.stop("default"));
+        assert_eq!(message_types(&mut rx), ["stream/end", "group/update"]);
+    }
+
+    #[test]
+    fn test_always_starts_on_first_player() {
+        let client_manager = Arc::new(ClientManager::new());
+        let group_manager = Arc::new(GroupManager::new());
+        let playback = PlaybackController::new(client_manager.clone(), group_manager.clone());
+
+        let mut rx = add_player(&client_manager, &group_manager, "p1");
+        playback.player_joined("p1");
+        assert_eq!(message_types(&mut rx), ["stream/start", "group/update"]);
+        assert_eq!(
+            group_manager.get_playback_state("default"),
+            Some(PlaybackState::Playing)
+        );
+
+        playback.player_left("p1");
+        assert_eq!(
+            group_manager.get_playback_state("default"),
+            Some(PlaybackState::Stopped)
+        );
+    }
+
+    #[test]
+    fn test_move_client_between_groups() {
+        let client_manager = Arc::new(ClientManager::new());
+        let group_manager = Arc::new(GroupManager::new());
+        let playback = PlaybackController::new(client_manager.clone(), group_manager.clone());
+        group_manager.create_group("kitchen", "Kitchen");
+        group_manager.set_auto_start("kitchen", AutoStart::Never);
+
+        let mut rx = add_player(&client_manager, &group_manager, "p1");
+        playback.player_joined("p1");
+        message_types(&mut rx);
+
+        assert!(!playback.move_client("p1", "missing"));
+        assert!(playback.move_client("p1", "kitchen"));
+        assert_eq!(message_types(&mut rx), ["group/update", "stream/end"]);
+        assert_eq!(
+            group_manager.get_client_group("p1").as_deref(),
+            Some("kitchen")
+        );
+        assert_eq!(
+            group_manager.get_playback_state("default"),
+            Some(PlaybackState::Stopped)
+        );
+    }
+
+    #[test]
+    fn test_group_changes_notify_members() {
+        let client_manager = Arc::new(ClientManager::new());
+        let group_manager = Arc::new(GroupManager::new());
+        let playback = PlaybackController::new(client_manager.clone(), group_manager.clone());
+        group_manager.create_group("kitchen", "Kitchen");
+
+        let mut rx = add_player(&client_manager, &group_manager, "p1");
+        playback.player_joined("p1");
+        assert!(playback.move_client("p1", "kitchen"));
+        message_types(&mut rx);
+        assert_eq!(
+            client_manager.get_group_id("p1").as_deref(),
+            Some("kitchen")
+        );
+
+        // Renaming tells the members, unless the name is unchanged
+        assert!(playback.rename_group("kitchen", "Kitchen & Dining"));
+        assert_eq!(message_types(&mut rx), ["group/update"]);
+        assert!(playback.rename_group("kitchen", "Kitchen & Dining"));
+        assert!(message_types(&mut rx).is_empty());
+
+        // Deleting the group moves its members back to the default group
+        assert_eq!(playback.delete_group("kitchen"), ["p1"]);
+        let mut last = None;
+        while let Ok(ServerMessage::Text(text)) = rx.try_recv() {
+            last = Some(serde_json::from_str::<serde_json::Value>(&text).unwrap());
+        }
+        let update = last.unwrap();
+        assert_eq!(update["type"], "group/update");
+        assert_eq!(update["payload"]["group_id"], "default");
+        assert_eq!(
+            client_manager.get_group_id("p1").as_deref(),
+            Some("default")
+        );
+        assert!(group_manager.get_group("kitchen").is_none());
+    }
+
+    #[test]
+    fn test_renegotiate_restarts_only_that_player() {
+        let client_manager = Arc::new(ClientManager::new());
+        let group_manager = Arc::new(GroupManager::new());
+        let playback = PlaybackController::new(client_manager.clone(), group_manager.clone());
+        let mut garage = add_player(&client_manager, &group_manager, "garage");
+        playback.player_joined("garage");
+        let mut den = add_player(&client_manager, &group_manager, "den");
+        playback.player_joined("den");
+        message_types(&mut garage);
+        message_types(&mut den);
+
+        let opus = AudioFormat {
+            codec: crate::audio::types::Codec::Opus,
+            ..ClientManager::default_audio_format()
+        };
+        assert!(playback.renegotiate("garage", opus.clone()));
+        assert_eq!(message_types(&mut garage), ["stream/start"]);
+        assert!(message_types(&mut den).is_empty());
+        assert_eq!(
+            client_manager.get_audio_format("garage"),
+            Some(opus.clone())
+        );
+
+        // A stopped group gets the new format when playback starts
+        playback.stop("default");
+        message_types(&mut garage);
+        assert!(playback.renegotiate("garage", opus.clone()));
+        assert!(message_types(&mut garage).is_empty());
+        assert!(!playback.renegotiate("missing", opus));
+    }
+
+    #[test]
+    fn test_buffer_ahead_change_announces_timing() {
+        let client_manager = Arc::new(ClientManager::new());
+        let group_manager = Arc::new(GroupManager::new().with_engine_timing(StreamTiming {
+            chunk_interval_ms: 10,
+            buffer_ahead_ms: 400,
+        }));
+        let playback = PlaybackController::new(client_manager.clone(), group_manager.clone());
+        let mut rx = add_player(&client_manager, &group_manager, "p1");
+        playback.player_joined("p1");
+        let start = |rx: &mut mpsc::UnboundedReceiver<ServerMessage>| loop {
+            let Ok(ServerMessage::Text(text)) = rx.try_recv() else {
+                panic!("no stream/start");
+            };
+            if let Message::StreamStart(start) = serde_json::from_str(&text).unwrap() {
+                return start.player;
+            }
+        };
+        let player = start(&mut rx);
+        assert_eq!(player.chunk_interval_ms, Some(10));
+        assert_eq!(player.buffer_ahead_ms, Some(400));
+
+        assert!(playback.set_buffer_ahead("default", Some(900)));
+        assert_eq!(start(&mut rx).buffer_ahead_ms, Some(900));
+
+        // Stopped groups only drop their audio
+        playback.stop("default");
+        message_types(&mut rx);
+        assert!(playback.set_buffer_ahead("default", None));
+        assert_eq!(message_types(&mut rx), ["stream/clear"]);
+    }
+
+    #[test]
+    fn test_link_tier_lengthens_one_players_buffer() {
+        let client_manager = Arc::new(ClientManager::new());
+        let group_manager = Arc::new(GroupManager::new());
+        let playback = PlaybackController::new(client_manager.clone(), group_manager.clone());
+        let mut wired = add_player(&client_manager, &group_manager, "wired");
+        let mut speaker = add_player(&client_manager, &group_manager, "speaker");
+        assert!(playback.play("default"));
+        message_types(&mut wired);
+        message_types(&mut speaker);
+
+        playback.set_tier_buffer_ahead(LinkTier::Bluetooth, Some(2000));
+        assert!(message_types(&mut speaker).is_empty());
+        playback.set_link_tier("speaker", Some(LinkTier::Bluetooth));
+        let Ok(ServerMessage::Text(clear)) = speaker.try_recv() else {
+            panic!("expected stream/clear");
+        };
+        assert!(clear.contains("stream/clear"));
+        let Ok(ServerMessage::Text(start)) = speaker.try_recv() else {
+            panic!("expected stream/start");
+        };
+        let Message::StreamStart(start) = serde_json::from_str(&start).unwrap() else {
+            panic!("expected stream/start");
+        };
+        assert_eq!(start.player.buffer_ahead_ms, Some(2000));
+        assert!(message_types(&mut wired).is_empty());
+
+        // A tier never shortens the group's buffer-ahead
+        playback.set_tier_buffer_ahead(LinkTier::Bluetooth, Some(100));
+        assert_eq!(client_manager.buffer_ahead_for("speaker", 500), 500);
+        assert_eq!(
+            message_types(&mut speaker),
+            ["stream/clear", "stream/start"]
+        );
+    }
+}
@@ -0,0 +1,123 @@
+// ABOUTME: Lightweight timing scopes around the engine tick, encoders and broadcasts
+// ABOUTME: Only recorded with the `profiling` feature; exported as folded stacks for flamegraphs
+
+use parking_lot::Mutex;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+/// Microseconds spent in each stack of scopes, outside their child scopes
+static SELF_MICROS: Mutex<BTreeMap<String, u64>> = Mutex::new(BTreeMap::new());
+
+struct Frame {
+    name: &'static str,
+    start: Instant,
+    child_micros: u64,
+}
+
+thread_local! {
+    static STACK: RefCell<Vec<Frame>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Whether this build records [`scope`]s
+pub const fn profiling_enabled() -> bool {
+    cfg!(feature = "profiling")
+}
+
+/// Time the rest of the enclosing block as `name`
+///
+/// Scopes opened while another is open on the same thread nest under it.
+/// Without the `profiling` feature this does nothing.
+#[inline]
+pub fn scope(name: &'static str) -> Scope {
+    if profiling_enabled() {
+        enter(name)
+    } else {
+        Scope { active: false }
+    }
+}
+
+fn enter(name: &'static str) -> Scope {
+    STACK.with(|stack| {
+        stack.borrow_mut().push(Frame {
+            name,
+            start: Instant::now(),
+            child_micros: 0,
+        })
+    });
+    Scope { active: true }
+}
+
+/// Guard returned by [`scope`]; the time is recorded when it drops
+#[must_use = "the scope ends as soon as the guard is dropped"]
+pub struct Scope {
+    active: bool,
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        if !self.active {
+            return;
+        }
+        STACK.with(|stack| {
+            let mut stack = stack.borrow_mut();
+            let Some(frame) = stack.pop() else {
+                return;
+            };
+            let total = frame.start.elapsed().as_micros() as u64;
+            let mut path: Vec<&str> = stack.iter().map(|frame| frame.name).collect();
+            path.push(frame.name);
+            if let Some(parent) = stack.last_mut() {
+                parent.child_micros += total;
+            }
+            *SELF_MICROS.lock().entry(path.join(";")).or_default() +=
+                total.saturating_sub(frame.child_micros);
+        });
+    }
+}
+
+/// Recorded time as folded stacks, one `outer;inner micros` line per stack
+///
+/// This is the input format of `flamegraph.pl`, `inferno-flamegraph` and
+/// speedscope.
+pub fn folded_stacks() -> String {
+    SELF_MICROS
+        .lock()
+        .iter()
+        .map(|(stack, micros)| format!("{} {}\n", stack, micros))
+        .collect()
+}
+
+/// Forget everything recorded so far
+pub fn reset_profile() {
+    SELF_MICROS.lock().clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nested_scopes_fold_into_stacks() {
+        {
+            let _tick = enter("test_tick");
+            {
+                let _encode = enter("test_encode");
+                std::thread::sleep(std::time::Duration::from_millis(5));
+            }
+            let _broadcast = enter("test_broadcast");
+        }
+        let folded = folded_stacks();
+        let micros = |stack: &str| -> u64 {
+            folded
+                .lines()
+                .find_map(|line| line.strip_prefix(stack)?.strip_prefix(' '))
+                .and_then(|micros| micros.parse().ok())
+                .unwrap_or_else(|| panic!("no {} in {}", stack, folded))
+        };
+        assert!(micros("test_tick;test_encode") >= 5_000);
+        // The parent is only charged for time outside its children
+        assert!(micros("test_tick") < 5_000);
+        micros("test_tick;test_broadcast");
+    }
+}
@@ -0,0 +1,143 @@
+// ABOUTME: Reverse-proxy support
+// ABOUTME: Resolves the real client address from forwarded headers and normalizes path prefixes
+
+use axum::http::HeaderMap;
+use std::net::{IpAddr, SocketAddr};
+
+/// Resolve the address of the client behind any trusted proxy
+///
+/// Forwarded headers are only honored when `trust_forwarded` is set and the
+/// socket peer is one of `trusted_proxies` (any loopback address when none
+/// are given). `X-Forwarded-For` is then walked from the right, past the
+/// trusted proxies, to the first address they did not add themselves;
+/// anything left of it is whatever the client claimed. Without that header
+/// `X-Real-IP` is used. Otherwise the socket peer address is returned.
+pub fn client_addr(
+    headers: &HeaderMap,
+    peer: SocketAddr,
+    trust_forwarded: bool,
+    trusted_proxies: &[IpAddr],
+) -> IpAddr {
+    let trusted = |addr: IpAddr| {
+        let addr = addr.to_canonical();
+        if trusted_proxies.is_empty() {
+            addr.is_loopback()
+        } else {
+            trusted_proxies
+                .iter()
+                .any(|proxy| proxy.to_canonical() == addr)
+        }
+    };
+    if !trust_forwarded || !trusted(peer.ip()) {
+        return peer.ip();
+    }
+
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+    if let Some(forwarded_for) = header("x-forwarded-for") {
+        let mut client = peer.ip();
+        for hop in forwarded_for.rsplit(',') {
+            let Ok(addr) = hop.trim().parse() else {
+                break;
+            };
+            client = addr;
+            if !trusted(addr) {
+                break;
+            }
+        }
+        return client;
+    }
+    header("x-real-ip")
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or_else(|| peer.ip())
+}
+
+/// Normalize a path prefix to `/segment` form ("" for no prefix)
+pub fn normalize_prefix(prefix: &str) -> String {
+    let trimmed = prefix.trim_matches('/');
+    if trimmed.is_empty() {
+        String::new()
+    } else {
+        format!("/{}", trimmed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn peer() -> SocketAddr {
+        "10.0.0.1:4000".parse().unwrap()
+    }
+
+    fn proxies() -> Vec<IpAddr> {
+        vec!["10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap()]
+    }
+
+    #[test]
+    fn test_forwarded_for_when_trusted() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-forwarded-for",
+            HeaderValue::from_static("192.168.1.20, 10.0.0.2"),
+        );
+
+        assert_eq!(
+            client_addr(&headers, peer(), true, &proxies()),
+            "192.168.1.20".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(
+            client_addr(&headers, peer(), false, &proxies()),
+            peer().ip()
+        );
+        // Without a list only a proxy on this host is trusted
+        assert_eq!(client_addr(&headers, peer(), true, &[]), peer().ip());
+        let local = "127.0.0.1:4000".parse().unwrap();
+        headers.insert("x-forwarded-for", HeaderValue::from_static("192.168.1.20"));
+        assert_eq!(
+            client_addr(&headers, local, true, &[]),
+            "192.168.1.20".parse::<IpAddr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_spoofed_forwarded_for_is_ignored() {
+        // The client sent its own X-Forwarded-For, which the proxy appended to
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-forwarded-for",
+            HeaderValue::from_static("10.0.0.2, 203.0.113.9, 192.168.1.20"),
+        );
+        assert_eq!(
+            client_addr(&headers, peer(), true, &proxies()),
+            "192.168.1.20".parse::<IpAddr>().unwrap()
+        );
+
+        // A client connecting directly cannot claim another address
+        let direct = "192.168.1.50:4000".parse().unwrap();
+        headers.insert("x-forwarded-for", HeaderValue::from_static("10.0.0.2"));
+        headers.insert("x-real-ip", HeaderValue::from_static("10.0.0.2"));
+        assert_eq!(client_addr(&headers, direct, true, &proxies()), direct.ip());
+    }
+
+    #[test]
+    fn test_real_ip_and_invalid_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-real-ip", HeaderValue::from_static("192.168.1.30"));
+        assert_eq!(
+            client_addr(&headers, peer(), true, &proxies()),
+            "192.168.1.30".parse::<IpAddr>().unwrap()
+        );
+
+        headers.insert("x-forwarded-for", HeaderValue::from_static("garbage"));
+        assert_eq!(client_addr(&headers, peer(), true, &proxies()), peer().ip());
+    }
+
+    #[test]
+    fn test_normalize_prefix() {
+        assert_eq!(normalize_prefix(""), "");
+        assert_eq!(normalize_prefix("/"), "");
+        assert_eq!(normalize_prefix("audio/"), "/audio");
+        assert_eq!(normalize_prefix("/audio/sendspin"), "/audio/sendspin");
+    }
+}
@@ -0,0 +1,288 @@
+// ABOUTME: Receive-side limits for client connections
+// ABOUTME: Caps inbound message size and rate with a token bucket so one client cannot flood the server
+
+use std::fmt;
+use std::time::Instant;
+
+/// Limits on what a client may send
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InboundLimits {
+    /// Largest message accepted, in bytes
+    pub max_message_bytes: usize,
+    /// Sustained messages per second (0 disables rate limiting)
+    pub messages_per_second: u32,
+    /// Messages that may arrive back to back before the rate applies
+    pub burst: u32,
+}
+
+impl Default for InboundLimits {
+    fn default() -> Self {
+        Self {
+            max_message_bytes: 64 * 1024,
+            messages_per_second: 50,
+            burst: 100,
+        }
+    }
+}
+
+/// Byte rate allowed for a stream a client pushes, such as a source's audio
+///
+/// Messages it covers count against this budget instead of the message rate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteBudget {
+    /// Sustained bytes per second
+    pub bytes_per_second: u64,
+    /// Bytes that may arrive back to back before the rate applies
+    pub burst_bytes: u64,
+}
+
+/// Why a connection is being closed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LimitViolation {
+    /// A message was larger than allowed
+    TooLarge {
+        /// Size of the message in bytes
+        size: usize,
+        /// The limit it broke
+        max: usize,
+    },
+    /// Messages arrived faster than allowed
+    RateExceeded {
+        /// The sustained rate allowed
+        per_second: u32,
+    },
+    /// Streamed bytes arrived faster than their [`ByteBudget`]
+    ByteRateExceeded {
+        /// The sustained byte rate allowed
+        bytes_per_second: u64,
+    },
+}
+
+impl LimitViolation {
+    /// Reason code for the `_server/goodbye` sent before closing
+    pub fn reason(&self) -> &'static str {
+        match self {
+            LimitViolation::TooLarge { .. } => "message_too_large",
+            LimitViolation::RateExceeded { .. } | LimitViolation::ByteRateExceeded { .. } => {
+                "rate_limited"
+            }
+        }
+    }
+}
+
+impl fmt::Display for LimitViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitViolation::TooLarge { size, max } => {
+                write!(
+                    f,
+                    "message of {} bytes exceeds the {} byte limit",
+                    size, max
+                )
+            }
+            LimitViolation::RateExceeded { per_second } => {
+                write!(f, "more than {} messages per second", per_second)
+            }
+            LimitViolation::ByteRateExceeded { bytes_per_second } => {
+                write!(f, "more than {} bytes per second", bytes_per_second)
+            }
+        }
+    }
+}
+
+/// Token bucket enforcing [`InboundLimits`] on one connection
+#[derive(Debug)]
+pub struct InboundLimiter {
+    limits: InboundLimits,
+    tokens: f64,
+    refilled: Instant,
+    bytes: Option<ByteBucket>,
+}
+
+/// Token bucket for a [`ByteBudget`]
+#[derive(Debug)]
+struct ByteBucket {
+    budget: ByteBudget,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl InboundLimiter {
+    /// Start with a full bucket
+    pub fn new(limits: InboundLimits, now: Instant) -> Self {
+        Self {
+            limits,
+            tokens: limits.burst.max(1) as f64,
+            refilled: now,
+            bytes: None,
+        }
+    }
+
+    /// Also allow a byte stream within `budget`, starting with a full bucket
+    ///
+    /// Messages checked with [`InboundLimiter::check_bytes`] are charged to it.
+    pub fn with_byte_budget(mut self, budget: ByteBudget, now: Instant) -> Self {
+        self.bytes = Some(ByteBucket {
+            budget,
+            tokens: budget.burst_bytes as f64,
+            refilled: now,
+        });
+        self
+    }
+
+    /// Account for a message of `size` bytes received at `now`
+    pub fn check(&mut self, size: usize, now: Instant) -> Result<(), LimitViolation> {
+        self.check_size(size)?;
+        if self.limits.messages_per_second == 0 {
+            return Ok(());
+        }
+
+        let elapsed = now.saturating_duration_since(self.refilled);
+        self.refilled = now;
+        self.tokens = (self.tokens
+            + elapsed.as_secs_f64() * self.limits.messages_per_second as f64)
+            .min(self.limits.burst.max(1) as f64);
+        if self.tokens < 1.0 {
+            return Err(LimitViolation::RateExceeded {
+                per_second: self.limits.messages_per_second,
+            });
+        }
+        self.tokens -= 1.0;
+        Ok(())
+    }
+
+    /// Account for a streamed message of `size` bytes received at `now`
+    ///
+    /// The message is charged to the byte budget rather than the message
+    /// rate. Without a byte budget it is checked like any other message.
+    pub fn check_bytes(&mut self, size: usize, now: Instant) -> Result<(), LimitViolation> {
+        self.check_size(size)?;
+        let Some(bucket) = &mut self.bytes else {
+            return self.check(size, now);
+        };
+
+        let budget = bucket.budget;
+        let elapsed = now.saturating_duration_since(bucket.refilled);
+        bucket.refilled = now;
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * budget.bytes_per_second as f64)
+            .min(budget.burst_bytes as f64);
+        if bucket.tokens < size as f64 {
+            return Err(LimitViolation::ByteRateExceeded {
+                bytes_per_second: budget.bytes_per_second,
+            });
+        }
+        bucket.tokens -= size as f64;
+        Ok(())
+    }
+
+    /// Check only the size of a message
+    pub fn check_size(&self, size: usize) -> Result<(), LimitViolation> {
+        if size > self.limits.max_message_bytes {
+            return Err(LimitViolation::TooLarge {
+                size,
+                max: self.limits.max_message_bytes,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_rejects_large_messages() {
+        let now = Instant::now();
+        let mut limiter = InboundLimiter::new(InboundLimits::default(), now);
+        assert!(limiter.check(64 * 1024, now).is_ok());
+        let violation = limiter.check(64 * 1024 + 1, now).unwrap_err();
+        assert_eq!(violation.reason(), "message_too_large");
+    }
+
+    #[test]
+    fn test_rate_allows_burst_then_refills() {
+        let limits = InboundLimits {
+            messages_per_second: 10,
+            burst: 5,
+            ..InboundLimits::default()
+        };
+        let start = Instant::now();
+        let mut limiter = InboundLimiter::new(limits, start);
+        for _ in 0..5 {
+            assert!(limiter.check(10, start).is_ok());
+        }
+        assert_eq!(
+            limiter.check(10, start),
+            Err(LimitViolation::RateExceeded { per_second: 10 })
+        );
+
+        // 100ms buys one more message
+        let later = start + Duration::from_millis(100);
+        assert!(limiter.check(10, later).is_ok());
+        assert!(limiter.check(10, later).is_err());
+    }
+
+    #[test]
+    fn test_zero_rate_disables_limit() {
+        let limits = InboundLimits {
+            messages_per_second: 0,
+            ..InboundLimits::default()
+        };
+        let now = Instant::now();
+        let mut limiter = InboundLimiter::new(limits, now);
+        for _ in 0..1000 {
+            assert!(limiter.check(10, now).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_byte_budget_replaces_message_rate() {
+        let limits = InboundLimits {
+            messages_per_second: 1,
+            burst: 1,
+            ..InboundLimits::default()
+        };
+        let budget = ByteBudget {
+            bytes_per_second: 1000,
+            burst_bytes: 2000,
+        };
+        let start = Instant::now();
+        let mut limiter = InboundLimiter::new(limits, start).with_byte_budget(budget, start);
+
+        // Many small messages fit the byte budget despite the message rate
+        for _ in 0..20 {
+            assert!(limiter.check_bytes(100, start).is_ok());
+        }
+        assert_eq!(
+            limiter.check_bytes(100, start),
+            Err(LimitViolation::ByteRateExceeded {
+                bytes_per_second: 1000
+            })
+        );
+
+        // 500ms buys 500 bytes
+        let later = start + Duration::from_millis(500);
+        assert!(limiter.check_bytes(500, later).is_ok());
+        assert!(limiter.check_bytes(1, later).is_err());
+        assert_eq!(
+            limiter.check_bytes(1, later).unwrap_err().reason(),
+            "rate_limited"
+        );
+    }
+
+    #[test]
+    fn test_check_bytes_without_budget_uses_message_rate() {
+        let limits = InboundLimits {
+            messages_per_second: 1,
+            burst: 2,
+            ..InboundLimits::default()
+        };
+        let now = Instant::now();
+        let mut limiter = InboundLimiter::new(limits, now);
+        assert!(limiter.check_bytes(10, now).is_ok());
+        assert!(limiter.check_bytes(10, now).is_ok());
+        assert!(limiter.check_bytes(10, now).is_err());
+    }
+}
@@ -0,0 +1,379 @@
+// ABOUTME: Warm-standby replication of group, client, and source state from a primary server
+// ABOUTME: The primary serves snapshots on its control API; a secondary polls and mirrors them
+
+use crate::audio::drc::NightMode;
+use crate::audio::spatial::StereoWidth;
+use crate::server::audio_source::open_source;
+use crate::server::client_manager::{ClientId, ClientManager, ResumableSession};
+use crate::server::config::ServerConfig;
+use crate::server::encoder::EncoderSettings;
+use crate::server::group::{AutoStart, GroupManager, PlaybackState};
+use crate::server::source_control::SourceControl;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Path of the snapshot endpoint under the control API
+pub const REPLICATION_PATH: &str = "/replication";
+
+/// How a secondary server follows its primary
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplicationConfig {
+    /// Base URL of the primary's control API (e.g. `http://primary:8927/api`)
+    pub primary_url: String,
+    /// Key presented to the primary; it needs read permission
+    pub api_key: Option<String>,
+    /// How often the primary is polled
+    pub interval: Duration,
+}
+
+impl ReplicationConfig {
+    /// Follow the primary whose control API is at `primary_url`, polling every two seconds
+    pub fn new(primary_url: impl Into<String>) -> Self {
+        Self {
+            primary_url: primary_url.into().trim_end_matches('/').to_string(),
+            api_key: None,
+            interval: Duration::from_secs(2),
+        }
+    }
+
+    /// URL of the primary's snapshot endpoint
+    pub fn snapshot_url(&self) -> String {
+        format!("{}{}", self.primary_url, REPLICATION_PATH)
+    }
+}
+
+/// A group as replicated to a secondary
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GroupState {
+    /// Group identifier
+    pub id: String,
+    /// Human-readable name
+    pub name: String,
+    /// Playback state on the primary
+    pub playback_state: PlaybackState,
+    /// Whether the group picks up playing when a player joins it again
+    pub resume_playing: bool,
+    /// Group volume (0-100)
+    pub volume: u8,
+    /// Group mute state
+    pub muted: bool,
+    /// Behavior when the first player joins
+    pub auto_start: AutoStart,
+    /// Buffer-ahead override in milliseconds
+    pub buffer_ahead_ms: Option<u64>,
+    /// Night-mode settings, if enabled
+    pub night_mode: Option<NightMode>,
+    /// Stereo width, if width processing is active
+    pub stereo_width: Option<StereoWidth>,
+    /// Stream the group is subscribed to, if not the default one
+    pub stream: Option<String>,
+    /// Encoder tuning override
+    pub encoder_settings: Option<EncoderSettings>,
+}
+
+/// A client's settings as replicated to a secondary
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientState {
+    /// Client identifier
+    pub client_id: ClientId,
+    /// Group the client is in
+    pub group_id: Option<String>,
+    /// Volume (0-100)
+    pub volume: u8,
+    /// Whether the client is muted
+    pub muted: bool,
+    /// Volume limit in effect for the client
+    pub max_volume: u8,
+}
+
+/// Everything a secondary needs to take over from the primary
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReplicationSnapshot {
+    /// ID of the server the snapshot was taken on
+    pub server_id: String,
+    /// Source the primary is playing (file path or URL)
+    pub source: Option<String>,
+    /// Every group, sorted by ID
+    pub groups: Vec<GroupState>,
+    /// Every connected client, sorted by ID
+    pub clients: Vec<ClientState>,
+}
+
+impl ReplicationSnapshot {
+    /// Capture this server's state
+    pub fn capture(
+        config: &ServerConfig,
+        client_manager: &ClientManager,
+        group_manager: &GroupManager,
+        source_control: &SourceControl,
+    ) -> Self {
+        let mut groups = Vec::new();
+        group_manager.for_each(|group| {
+            groups.push(GroupState {
+                id: group.id.clone(),
+                name: group.name.clone(),
+                playback_state: group.playback_state,
+                resume_playing: group.resume_playing,
+                volume: group.volume,
+                muted: group.muted,
+                auto_start: group.auto_start,
+                buffer_ahead_ms: group.buffer_ahead_ms,
+                night_mode: group.night_mode,
+                stereo_width: group.stereo_width,
+                stream: group.stream.clone(),
+                encoder_settings: group.encoder_settings,
+            });
+        });
+        groups.sort_by(|a, b| a.id.cmp(&b.id));
+
+        let mut clients = Vec::new();
+        client_manager.for_each(|client| {
+            clients.push(ClientState {
+                client_id: client.client_id.clone(),
+                group_id: None,
+                volume: client.volume,
+                muted: client.muted,
+                max_volume: client.max_volume,
+            });
+        });
+        for client in &mut clients {
+            client.group_id = group_manager.get_client_group(&client.client_id);
+        }
+        clients.sort_by(|a, b| a.client_id.cmp(&b.client_id));
+
+        Self {
+            server_id: config.server_id.clone(),
+            source: source_control.now_playing().source,
+            groups,
+            clients,
+        }
+    }
+
+    /// Mirror the snapshot's groups and client sessions onto this server
+    ///
+    /// Groups are created or updated to match, and empty groups the primary
+    /// no longer has are deleted. Group membership is left alone: clients
+    /// join their replicated group when they connect, which also restores
+    /// their volume. A group that was playing resumes once a player joins.
+    pub fn apply(&self, client_manager: &ClientManager, group_manager: &GroupManager) {
+        let ids: HashSet<&str> = self.groups.iter().map(|g| g.id.as_str()).collect();
+        for id in group_manager.group_ids() {
+            if !ids.contains(id.as_str())
+                && id != group_manager.default_group_id()
+                && group_manager.get_group_members(&id).is_empty()
+            {
+                group_manager.delete_group(&id);
+            }
+        }
+
+        for group in &self.groups {
+            if group_manager.get_group(&group.id).is_none() {
+                group_manager.create_group(&group.id, &group.name);
+            }
+            group_manager.set_playback_state(&group.id, group.playback_state);
+            group_manager.set_resume_playing(
+                &group.id,
+                group.resume_playing || group.playback_state == PlaybackState::Playing,
+            );
+            group_manager.set_volume(&group.id, group.volume);
+            group_manager.set_muted(&group.id, group.muted);
+            group_manager.set_auto_start(&group.id, group.auto_start);
+            group_manager.set_buffer_ahead(&group.id, group.buffer_ahead_ms);
+            group_manager.set_night_mode(&group.id, group.night_mode);
+            group_manager.set_stereo_width(&group.id, group.stereo_width);
+            group_manager.set_stream(&group.id, group.stream.clone());
+            group_manager.set_encoder_settings(&group.id, group.encoder_settings);
+        }
+
+        let now = Instant::now();
+        let sessions = self
+            .clients
+            .iter()
+            .map(|client| {
+                let session = ResumableSession {
+                    generation: 0,
+                    group_id: client.group_id.clone(),
+                    volume: client.volume,
+                    muted: client.muted,
+                    max_volume: client.max_volume,
+                    disconnected_at: now,
+                };
+                (client.client_id.clone(), session)
+            })
+            .collect::<HashMap<_, _>>();
+        client_manager.replicate_sessions(sessions);
+    }
+}
+
+/// Fetch a snapshot from the primary (blocking)
+pub fn fetch_snapshot(
+    config: &ReplicationConfig,
+) -> Result<ReplicationSnapshot, Box<dyn std::error::Error + Send + Sync>> {
+    let timeout = config.interval.max(Duration::from_secs(1));
+    let mut request = ureq::get(&config.snapshot_url()).timeout(timeout);
+    if let Some(key) = &config.api_key {
+        request = request.set("Authorization", &format!("Bearer {}", key));
+    }
+    let body = request.call()?.into_string()?;
+    Ok(serde_json::from_str(&body)?)
+}
+
+/// Spawn a task keeping this server a warm standby for the configured primary
+///
+/// Polls the primary's snapshot every `interval` and applies it. While the
+/// primary is unreachable the last replicated state is kept, ready for its
+/// clients to fail over. The primary's source is opened here too when it
+/// changes, so the standby is already playing the same stream.
+pub fn spawn_replicator(
+    replication: ReplicationConfig,
+    config: Arc<ServerConfig>,
+    client_manager: Arc<ClientManager>,
+    group_manager: Arc<GroupManager>,
+    source_control: SourceControl,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(replication.interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        let mut source = source_control.now_playing().source;
+        let mut reachable = None;
+        loop {
+            ticker.tick().await;
+            let request = replication.clone();
+            let snapshot = match tokio::task::spawn_blocking(move || fetch_snapshot(&request)).await
+            {
+                Ok(Ok(snapshot)) => snapshot,
+                Ok(Err(e)) => {
+                    if reachable != Some(false) {
+                        log::warn!(
+                            "Primary {} unreachable, keeping last replicated state: {}",
+                            replication.primary_url,
+                            e
+                        );
+                        reachable = Some(false);
+                    }
+                    continue;
+                }
+                Err(_) => continue,
+            };
+            if snapshot.server_id == config.server_id {
+                log::warn!(
+                    "Replication source {} is this server",
+                    replication.primary_url
+                );
+                continue;
+            }
+            if reachable != Some(true) {
+                log::info!(
+                    "Replicating from primary {} ({} groups, {} clients)",
+                    replication.primary_url,
+                    snapshot.groups.len(),
+                    snapshot.clients.len()
+                );
+                reachable = Some(true);
+            }
+
+            snapshot.apply(&client_manager, &group_manager);
+
+            if snapshot.source.is_some() && snapshot.source != source {
+                source = snapshot.source.clone();
+                let uri = snapshot.source.unwrap_or_default();
+                let config = config.clone();
+                let opened = tokio::task::spawn_blocking(move || {
+                    open_source(
+                        &uri,
+                        config.downmix,
+                        config.replay_gain,
+                        config.url_cache.as_ref(),
+                    )
+                    .map_err(|e| (uri, e.to_string()))
+                })
+                .await;
+                match opened {
+                    Ok(Ok(opened)) => source_control.replace(opened),
+                    Ok(Err((uri, e))) => log::warn!("Cannot open replicated source {}: {}", uri, e),
+                    Err(_) => {}
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::client_manager::ConnectedClient;
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let primary_clients = ClientManager::new();
+        let primary_groups = GroupManager::new();
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let mut client = ConnectedClient::new("kitchen".to_string(), "Kitchen".to_string(), tx);
+        client.volume = 35;
+        client.muted = true;
+        primary_clients.add_client(client);
+        primary_groups.create_group("downstairs", "Downstairs");
+        primary_groups.add_to_group("kitchen", "downstairs");
+        primary_groups.set_playback_state("downstairs", PlaybackState::Playing);
+        primary_groups.set_volume("downstairs", 60);
+        primary_groups.set_buffer_ahead("downstairs", Some(750));
+        primary_groups.set_night_mode("downstairs", Some(NightMode::default()));
+        primary_groups.set_stereo_width("downstairs", Some(StereoWidth::new(0.5)));
+
+        let config = ServerConfig::default();
+        let snapshot = ReplicationSnapshot::capture(
+            &config,
+            &primary_clients,
+            &primary_groups,
+            &SourceControl::default(),
+        );
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let snapshot: ReplicationSnapshot = serde_json::from_str(&json).unwrap();
+
+        let secondary_clients = ClientManager::new();
+        let secondary_groups = GroupManager::new();
+        secondary_groups.create_group("attic", "Attic");
+        snapshot.apply(&secondary_clients, &secondary_groups);
+
+        assert!(secondary_groups.get_group("attic").is_none());
+        let (_, name, state) = secondary_groups.get_group("downstairs").unwrap();
+        assert_eq!(name, "Downstairs");
+        assert_eq!(state, PlaybackState::Playing);
+        assert_eq!(secondary_groups.get_buffer_ahead("downstairs"), Some(750));
+        assert!(secondary_groups.get_night_mode("downstairs").is_some());
+        assert_eq!(
+            secondary_groups.get_stereo_width("downstairs"),
+            Some(StereoWidth::new(0.5))
+        );
+
+        // The client fails over into its group at its volume
+        let session = secondary_clients.resume("kitchen").unwrap();
+        assert_eq!(session.group_id.as_deref(), Some("downstairs"));
+        assert_eq!((session.volume, session.muted), (35, true));
+        assert!(secondary_clients.resume("kitchen").is_none());
+    }
+
+    #[test]
+    fn test_apply_keeps_occupied_groups() {
+        let secondary = GroupManager::new();
+        secondary.create_group("attic", "Attic");
+        secondary.add_to_group("p1", "attic");
+        let snapshot = ReplicationSnapshot {
+            server_id: "primary".to_string(),
+            source: None,
+            groups: Vec::new(),
+            clients: Vec::new(),
+        };
+        snapshot.apply(&ClientManager::new(), &secondary);
+        assert!(secondary.get_group("attic").is_some());
+    }
+
+    #[test]
+    fn test_snapshot_url() {
+        let config = ReplicationConfig::new("http://primary:8927/api/");
+        assert_eq!(config.snapshot_url(), "http://primary:8927/api/replication");
+    }
+}
@@ -0,0 +1,775 @@
+// ABOUTME: Per-role handlers for client messages
+// ABOUTME: Routes each message to the handler of the role it belongs to, with overridable defaults
+
+use crate::audio::types::{AudioFormat, Codec};
+use crate::protocol::messages::{
+    ArtworkFormatRequest, AudioFormatSpec, ControllerCommand, Message, PlayerFormatRequest,
+    PlayerState,
+};
+use crate::server::artwork::resend_artwork;
+use crate::server::client_manager::{ClientId, ClientManager, VolumeChange};
+use crate::server::clock::ServerClock;
+use crate::server::encoder::EncoderRegistry;
+use crate::server::group::GroupManager;
+use crate::server::playback::PlaybackController;
+use crate::server::stream_manager::StreamManager;
+use std::sync::Arc;
+
+/// The client a role handler is acting for, and the server state it can use
+pub struct RoleContext<'a> {
+    /// Client that sent the message
+    pub client_id: &'a ClientId,
+    /// Connected clients
+    pub client_manager: &'a ClientManager,
+    /// Groups
+    pub group_manager: &'a GroupManager,
+    /// Group playback, for commands that change what a group plays
+    pub playback: &'a PlaybackController,
+    /// Server clock, for timestamping what is sent
+    pub clock: &'a ServerClock,
+    /// Running streams, for commands that control a stream's engine
+    pub streams: Option<&'a StreamManager>,
+}
+
+/// Handles messages from clients with the player role
+///
+/// Every method has a default, so custom handlers only override what they change.
+pub trait PlayerHandler: Send + Sync {
+    /// The client finished its handshake with this role active
+    fn joined(&self, _ctx: &RoleContext) {}
+
+    /// The client disconnected
+    fn left(&self, _ctx: &RoleContext) {}
+
+    /// Player object of `client/state`
+    fn state(&self, ctx: &RoleContext, state: PlayerState) {
+        log::debug!(
+            "Player {} state: {}, volume: {:?}, muted: {:?}",
+            ctx.client_id,
+            state.state,
+            state.volume,
+            state.muted
+        );
+        // Update volume if provided (both must be present per spec when supported)
+        if let (Some(volume), Some(muted)) = (state.volume, state.muted) {
+            ctx.client_manager
+                .update_volume(ctx.client_id, volume, muted);
+        }
+    }
+
+    /// Player object of `stream/request-format`
+    ///
+    /// Switches the player to the first format it announced that matches
+    /// every requested field, filling the rest from its current format.
+    /// Formats in codecs the server has no encoder for never match. A request
+    /// nothing matches leaves the stream as it is.
+    fn request_format(&self, ctx: &RoleContext, request: PlayerFormatRequest) {
+        let supported = ctx
+            .client_manager
+            .get_supported_formats(ctx.client_id)
+            .unwrap_or_default();
+        let current = ctx
+            .client_manager
+            .get_audio_format(ctx.client_id)
+            .unwrap_or_else(ClientManager::default_audio_format);
+        let encoders = ctx
+            .streams
+            .map_or_else(EncoderRegistry::default, |s| s.encoders().clone());
+        match requested_format(&supported, &current, &request, &encoders) {
+            Some(format) => {
+                log::info!(
+                    "Player {} switching to {} {}Hz {}ch {}bit",
+                    ctx.client_id,
+                    format.codec.name(),
+                    format.sample_rate,
+                    format.channels,
+                    format.bit_depth
+                );
+                ctx.playback.renegotiate(ctx.client_id, format);
+            }
+            None => log::warn!(
+                "Player {} requested a format it does not support: {:?}",
+                ctx.client_id,
+                request
+            ),
+        }
+    }
+}
+
+/// The supported format matching a player's format request
+///
+/// Fields left out of the request prefer the current format's values.
+fn requested_format(
+    supported: &[AudioFormatSpec],
+    current: &AudioFormat,
+    request: &PlayerFormatRequest,
+    encoders: &EncoderRegistry,
+) -> Option<AudioFormat> {
+    supported
+        .iter()
+        .enumerate()
+        .filter_map(|(index, spec)| Some((index, spec, Codec::from_name(&spec.codec)?)))
+        .filter(|(_, spec, codec)| {
+            encoders.contains(codec.name())
+                && request.codec.as_deref().is_none_or(|c| c == codec.name())
+                && request.sample_rate.is_none_or(|r| r == spec.sample_rate)
+                && request.channels.is_none_or(|c| c == spec.channels)
+                && request.bit_depth.is_none_or(|b| b == spec.bit_depth)
+        })
+        .max_by_key(|(index, spec, codec)| {
+            (
+                *codec == current.codec,
+                spec.sample_rate == current.sample_rate,
+                spec.channels == current.channels,
+                spec.bit_depth == current.bit_depth,
+                std::cmp::Reverse(*index),
+            )
+        })
+        .map(|(_, spec, codec)| AudioFormat {
+            codec,
+            sample_rate: spec.sample_rate,
+            channels: spec.channels,
+            bit_depth: spec.bit_depth,
+            codec_header: None,
+        })
+}
+
+/// Handles messages from clients with the controller role
+pub trait ControllerHandler: Send + Sync {
+    /// The client finished its handshake with this role active
+    ///
+    /// By default the controller receives `server/state` for its group.
+    fn joined(&self, ctx: &RoleContext) {
+        ctx.playback.send_controller_state(ctx.client_id);
+    }
+
+    /// The client disconnected
+    fn left(&self, _ctx: &RoleContext) {}
+
+    /// Controller object of `client/command`
+    ///
+    /// By default `play`, `pause` and `stop` change the controller's group
+    /// playback, `next` skips the group's stream to its next queued track,
+    /// `volume` scales the group proportionally and `mute` mutes it; other
+    /// commands are logged and ignored.
+    fn command(&self, ctx: &RoleContext, command: ControllerCommand) {
+        let Some(group_id) = ctx.group_manager.get_client_group(ctx.client_id) else {
+            log::debug!("Controller {} is not in a group", ctx.client_id);
+            return;
+        };
+        let playback = ctx.playback;
+        match (command.command.as_str(), command.volume, command.mute) {
+            ("play", ..) => {
+                playback.play(&group_id);
+            }
+            ("pause", ..) => {
+                playback.pause(&group_id);
+            }
+            ("stop", ..) => {
+                playback.stop(&group_id);
+            }
+            ("next", ..) => {
+                let engine = ctx
+                    .streams
+                    .and_then(|s| s.engine_handle_for_group(&group_id));
+                if let Some(engine) = engine {
+                    engine.next();
+                }
+            }
+            ("volume", Some(volume), _) => {
+                playback.set_group_volume(&group_id, VolumeChange::Group(volume.min(100)));
+            }
+            ("mute", _, Some(mute)) => {
+                playback.set_group_volume(&group_id, VolumeChange::Mute(mute));
+            }
+            _ => {
+                log::debug!(
+                    "Controller {} sent unsupported command '{}'",
+                    ctx.client_id,
+                    command.command
+                );
+                return;
+            }
+        }
+        log::info!(
+            "Controller {} sent {} to group {}",
+            ctx.client_id,
+            command.command,
+            group_id
+        );
+    }
+}
+
+/// Handles clients with the metadata role
+///
+/// Metadata clients only receive; the hooks let a handler send initial state.
+pub trait MetadataHandler: Send + Sync {
+    /// The client finished its handshake with this role active
+    fn joined(&self, _ctx: &RoleContext) {}
+
+    /// The client disconnected
+    fn left(&self, _ctx: &RoleContext) {}
+}
+
+/// Handles clients with the artwork role
+pub trait ArtworkHandler: Send + Sync {
+    /// The client finished its handshake with this role active
+    fn joined(&self, _ctx: &RoleContext) {}
+
+    /// The client disconnected
+    fn left(&self, _ctx: &RoleContext) {}
+
+    /// Artwork object of `stream/request-format`
+    ///
+    /// By default the channel is changed as requested and the client is
+    /// sent its pictures again in the new format.
+    fn request_format(&self, ctx: &RoleContext, request: ArtworkFormatRequest) {
+        let changed = ctx
+            .client_manager
+            .update_artwork_state(ctx.client_id, |state| state.apply(&request));
+        if changed != Some(true) {
+            log::debug!(
+                "Client {} requested unknown artwork channel {}",
+                ctx.client_id,
+                request.channel
+            );
+            return;
+        }
+        resend_artwork(ctx.client_manager, ctx.client_id, ctx.clock.now_micros());
+    }
+}
+
+/// Built-in player behavior: track reported volume and mute
+pub struct DefaultPlayerHandler;
+
+impl PlayerHandler for DefaultPlayerHandler {}
+
+/// Built-in controller behavior: playback, volume and mute commands for the controller's group
+pub struct DefaultControllerHandler;
+
+impl ControllerHandler for DefaultControllerHandler {}
+
+/// Built-in metadata behavior: nothing beyond the server's broadcasts
+pub struct DefaultMetadataHandler;
+
+impl MetadataHandler for DefaultMetadataHandler {}
+
+/// Built-in artwork behavior: channel changes requested by the client
+pub struct DefaultArtworkHandler;
+
+impl ArtworkHandler for DefaultArtworkHandler {}
+
+/// Handlers the server uses for each role
+///
+/// Set on [`crate::server::SendspinServer::with_role_handlers`] to replace the
+/// built-in behavior of a role.
+#[derive(Clone)]
+pub struct RoleHandlers {
+    player: Arc<dyn PlayerHandler>,
+    controller: Arc<dyn ControllerHandler>,
+    metadata: Arc<dyn MetadataHandler>,
+    artwork: Arc<dyn ArtworkHandler>,
+}
+
+impl RoleHandlers {
+    /// Built-in handlers for every role
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace the player handler
+    pub fn player(mut self, handler: impl PlayerHandler + 'static) -> Self {
+        self.player = Arc::new(handler);
+        self
+    }
+
+    /// Replace the controller handler
+    pub fn controller(mut self, handler: impl ControllerHandler + 'static) -> Self {
+        self.controller = Arc::new(handler);
+        self
+    }
+
+    /// Replace the metadata handler
+    pub fn metadata(mut self, handler: impl MetadataHandler + 'static) -> Self {
+        self.metadata = Arc::new(handler);
+        self
+    }
+
+    /// Replace the artwork handler
+    pub fn artwork(mut self, handler: impl ArtworkHandler + 'static) -> Self {
+        self.artwork = Arc::new(handler);
+        self
+    }
+}
+
+impl Default for RoleHandlers {
+    fn default() -> Self {
+        Self {
+            player: Arc::new(DefaultPlayerHandler),
+            controller: Arc::new(DefaultControllerHandler),
+            metadata: Arc::new(DefaultMetadataHandler),
+            artwork: Arc::new(DefaultArtworkHandler),
+        }
+    }
+}
+
+/// Routes one connection's messages to the handlers of its active roles
+pub(crate) struct RoleDispatcher {
+    player: Option<Arc<dyn PlayerHandler>>,
+    controller: Option<Arc<dyn ControllerHandler>>,
+    metadata: Option<Arc<dyn MetadataHandler>>,
+    artwork: Option<Arc<dyn ArtworkHandler>>,
+}
+
+impl RoleDispatcher {
+    /// Select the handlers for the roles negotiated in the handshake
+    pub(crate) fn new(handlers: &RoleHandlers, active_roles: &[String]) -> Self {
+        let has = |role: &str| active_roles.iter().any(|r| r.starts_with(role));
+        Self {
+            player: has("player@").then(|| handlers.player.clone()),
+            controller: has("controller@").then(|| handlers.controller.clone()),
+            metadata: has("metadata@").then(|| handlers.metadata.clone()),
+            artwork: has("artwork@").then(|| handlers.artwork.clone()),
+        }
+    }
+
+    /// Notify each active role that the client joined
+    pub(crate) fn joined(&self, ctx: &RoleContext) {
+        self.player.iter().for_each(|h| h.joined(ctx));
+        self.controller.iter().for_each(|h| h.joined(ctx));
+        self.metadata.iter().for_each(|h| h.joined(ctx));
+        self.artwork.iter().for_each(|h| h.joined(ctx));
+    }
+
+    /// Notify each active role that the client left
+    pub(crate) fn left(&self, ctx: &RoleContext) {
+        self.player.iter().for_each(|h| h.left(ctx));
+        self.controller.iter().for_each(|h| h.left(ctx));
+        self.metadata.iter().for_each(|h| h.left(ctx));
+        self.artwork.iter().for_each(|h| h.left(ctx));
+    }
+
+    /// Route a role message, returning it if it is not role-specific
+    ///
+    /// Role objects for roles the client did not negotiate are dropped.
+    pub(crate) fn dispatch(&self, ctx: &RoleContext, msg: Message) -> Option<Message> {
+        match msg {
+            Message::ClientState(state) => {
+                if let Some(trace) = &state.trace {
+                    if let Some(micros) = ctx.client_manager.traces().finish(ctx.client_id, trace) {
+                        log::debug!(
+                            "Client {} applied command {} after {:.1}ms",
+                            ctx.client_id,
+                            trace.id,
+                            micros as f64 / 1000.0
+                        );
+                    }
+                }
+                if let Some(player) = state.player {
+                    self.to_player(ctx, |h| h.state(ctx, player));
+                }
+                if let Some(stats) = state.stats {
+                    if stats.gaps > 0 || stats.overlaps > 0 {
+                        log::debug!(
+                            "Client {} reports {} chunk gaps, {} overlaps ({} chunks)",
+                            ctx.client_id,
+                            stats.gaps,
+                            stats.overlaps,
+                            stats.chunks_received
+                        );
+                    }
+                    if let Some(tier) = ctx.client_manager.update_stats(ctx.client_id, stats) {
+                        ctx.playback.link_tier_placed(ctx.client_id, tier);
+                    }
+                }
+            }
+            Message::ClientCommand(command) => {
+                if let Some(controller) = command.controller {
+                    match &self.controller {
+                        Some(handler) => {
+                            handler.command(ctx, controller);
+                            if let Some(trace) = command.trace {
+                                let trace = ctx.client_manager.traces().handled(trace);
+                                ctx.playback
+                                    .send_traced_controller_state(ctx.client_id, Some(trace));
+                            }
+                        }
+                        None => log::warn!(
+                            "Client {} sent a controller command without the controller role",
+                            ctx.client_id
+                        ),
+                    }
+                }
+            }
+            Message::StreamRequestFormat(request) => {
+                log::info!(
+                    "Client {} requested format change: {:?}",
+                    ctx.client_id,
+                    request
+                );
+                if let Some(player) = request.player {
+                    self.to_player(ctx, |h| h.request_format(ctx, player));
+                }
+                if let Some(artwork) = request.artwork {
+                    match &self.artwork {
+                        Some(handler) => handler.request_format(ctx, artwork),
+                        None => log::warn!(
+                            "Client {} sent an artwork request without the artwork role",
+                            ctx.client_id
+                        ),
+                    }
+                }
+            }
+            other => return Some(other),
+        }
+        None
+    }
+
+    fn to_player(&self, ctx: &RoleContext, f: impl FnOnce(&dyn PlayerHandler)) {
+        match &self.player {
+            Some(handler) => f(handler.as_ref()),
+            None => log::warn!(
+                "Client {} sent a player message without the player role",
+                ctx.client_id
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::messages::{
+        ClientCommand, ClientState, MessageTrace, StreamRequestFormat,
+    };
+    use crate::server::client_manager::ServerMessage;
+    use crate::server::group::PlaybackState;
+    use parking_lot::Mutex;
+    use std::collections::HashSet;
+
+    #[derive(Default)]
+    struct RecordingController {
+        commands: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl ControllerHandler for RecordingController {
+        fn command(&self, ctx: &RoleContext, command: ControllerCommand) {
+            self.commands
+                .lock()
+                .push(format!("{}:{}", ctx.client_id, command.command));
+        }
+    }
+
+    fn command(name: &str) -> Message {
+        Message::ClientCommand(ClientCommand {
+            controller: Some(ControllerCommand {
+                command: name.to_string(),
+                volume: None,
+                mute: None,
+            }),
+            trace: None,
+        })
+    }
+
+    #[test]
+    fn test_dispatch_to_active_roles_only() {
+        let recorder = RecordingController::default();
+        let commands = recorder.commands.clone();
+        let handlers = RoleHandlers::new().controller(recorder);
+        let client_manager = Arc::new(ClientManager::new());
+        let group_manager = Arc::new(GroupManager::new());
+        let playback = PlaybackController::new(client_manager.clone(), group_manager.clone());
+        let clock = ServerClock::new();
+        let client_id = "remote".to_string();
+        let ctx = RoleContext {
+            client_id: &client_id,
+            client_manager: &client_manager,
+            group_manager: &group_manager,
+            playback: &playback,
+            clock: &clock,
+            streams: None,
+        };
+
+        let controller = RoleDispatcher::new(&handlers, &["controller@v1".to_string()]);
+        assert!(controller.dispatch(&ctx, command("play")).is_none());
+        let player = RoleDispatcher::new(&handlers, &["player@v1".to_string()]);
+        assert!(player.dispatch(&ctx, command("pause")).is_none());
+        assert_eq!(*commands.lock(), ["remote:play"]);
+    }
+
+    #[test]
+    fn test_controller_volume_scales_group() {
+        let client_manager = Arc::new(ClientManager::new());
+        let group_manager = Arc::new(GroupManager::new());
+        let playback = PlaybackController::new(client_manager.clone(), group_manager.clone());
+        let clock = ServerClock::new();
+        for (id, volume) in [("a", 80), ("b", 40)] {
+            let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+            let mut client = crate::server::ConnectedClient::new(id.into(), id.into(), tx);
+            client.active_roles = vec!["player@v1".to_string()];
+            client.volume = volume;
+            client_manager.add_client(client);
+            group_manager.add_to_group(id, "default");
+        }
+        let client_id = "remote".to_string();
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut remote =
+            crate::server::ConnectedClient::new(client_id.clone(), "Remote".into(), tx);
+        remote.active_roles = vec!["controller@v1".to_string()];
+        client_manager.add_client(remote);
+        group_manager.add_to_group(&client_id, "default");
+        let ctx = RoleContext {
+            client_id: &client_id,
+            client_manager: &client_manager,
+            group_manager: &group_manager,
+            playback: &playback,
+            clock: &clock,
+            streams: None,
+        };
+
+        let volume = ControllerCommand {
+            command: "volume".to_string(),
+            volume: Some(30),
+            mute: None,
+        };
+        DefaultControllerHandler.command(&ctx, volume);
+
+        let members: HashSet<ClientId> = ["a", "b"].map(String::from).into();
+        assert_eq!(client_manager.group_volume(&members), Some(30));
+        let mut volumes = Vec::new();
+        client_manager.for_each(|c| {
+            if c.is_player() {
+                volumes.push((c.client_id.clone(), c.volume));
+            }
+        });
+        volumes.sort();
+        assert_eq!(volumes, [("a".to_string(), 40), ("b".to_string(), 20)]);
+
+        // The controller hears the group's new volume
+        let Ok(ServerMessage::Text(text)) = rx.try_recv() else {
+            panic!("no server/state");
+        };
+        let Message::ServerState(state) = serde_json::from_str(&text).unwrap() else {
+            panic!("expected server/state, got {}", text);
+        };
+        let controller = state.controller.unwrap();
+        assert_eq!((controller.volume, controller.muted), (30, false));
+        assert!(controller.supported_commands.contains(&"pause".to_string()));
+    }
+
+    #[test]
+    fn test_controller_playback_commands() {
+        let client_manager = Arc::new(ClientManager::new());
+        let group_manager = Arc::new(GroupManager::new());
+        let playback = PlaybackController::new(client_manager.clone(), group_manager.clone());
+        let clock = ServerClock::new();
+        let client_id = "remote".to_string();
+        group_manager.add_to_group(&client_id, "default");
+        let ctx = RoleContext {
+            client_id: &client_id,
+            client_manager: &client_manager,
+            group_manager: &group_manager,
+            playback: &playback,
+            clock: &clock,
+            streams: None,
+        };
+
+        let send = |name: &str| {
+            let Message::ClientCommand(command) = command(name) else {
+                unreachable!();
+            };
+            DefaultControllerHandler.command(&ctx, command.controller.unwrap());
+            group_manager.get_playback_state("default").unwrap()
+        };
+        assert_eq!(send("play"), PlaybackState::Playing);
+        assert_eq!(send("pause"), PlaybackState::Paused);
+        assert_eq!(send("next"), PlaybackState::Paused);
+        assert_eq!(send("stop"), PlaybackState::Stopped);
+    }
+
+    /// Messages of `kind` queued for a test client
+    fn received(
+        rx: &mut tokio::sync::mpsc::UnboundedReceiver<ServerMessage>,
+        kind: &str,
+    ) -> Vec<Message> {
+        let mut messages = Vec::new();
+        while let Ok(ServerMessage::Text(text)) = rx.try_recv() {
+            let message: serde_json::Value = serde_json::from_str(&text).unwrap();
+            if message["type"] == kind {
+                messages.push(serde_json::from_value(message).unwrap());
+            }
+        }
+        messages
+    }
+
+    #[test]
+    fn test_command_traces_are_echoed() {
+        let client_manager = Arc::new(ClientManager::new());
+        let group_manager = Arc::new(GroupManager::new());
+        let playback = PlaybackController::new(client_manager.clone(), group_manager.clone());
+        let clock = ServerClock::new();
+        let mut receivers = Vec::new();
+        for (id, role) in [("speaker", "player@v1"), ("remote", "controller@v1")] {
+            let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+            let mut client = crate::server::ConnectedClient::new(id.into(), id.into(), tx);
+            client.active_roles = vec![role.to_string()];
+            client.supported_commands = vec!["volume".to_string(), "mute".to_string()];
+            client_manager.add_client(client);
+            group_manager.add_to_group(id, "default");
+            receivers.push(rx);
+        }
+        let (speaker, remote) = ("speaker".to_string(), "remote".to_string());
+        let context = |client_id| RoleContext {
+            client_id,
+            client_manager: &client_manager,
+            group_manager: &group_manager,
+            playback: &playback,
+            clock: &clock,
+            streams: None,
+        };
+
+        // The controller's trace comes back on the server/state its command caused
+        let controller = RoleDispatcher::new(&RoleHandlers::new(), &["controller@v1".into()]);
+        let trace = MessageTrace {
+            id: "c1".to_string(),
+            sent_at: 42,
+            handled_at: None,
+        };
+        let volume = Message::ClientCommand(ClientCommand {
+            controller: Some(ControllerCommand {
+                command: "volume".to_string(),
+                volume: Some(30),
+                mute: None,
+            }),
+            trace: Some(trace.clone()),
+        });
+        controller.dispatch(&context(&remote), volume);
+        let echoed = received(&mut receivers[1], "server/state")
+            .into_iter()
+            .find_map(|message| match message {
+                Message::ServerState(state) => state.trace,
+                _ => None,
+            })
+            .expect("no traced server/state");
+        assert_eq!((echoed.id.as_str(), echoed.sent_at), ("c1", 42));
+        assert!(echoed.handled_at.is_some());
+
+        // The player's echo of the server's trace completes its round trip
+        let sent = received(&mut receivers[0], "server/command")
+            .into_iter()
+            .find_map(|message| match message {
+                Message::ServerCommand(command) => command.trace,
+                _ => None,
+            })
+            .expect("no traced server/command");
+        let player = RoleDispatcher::new(&RoleHandlers::new(), &["player@v1".into()]);
+        let state = Message::ClientState(ClientState {
+            player: None,
+            stats: None,
+            trace: Some(sent),
+        });
+        player.dispatch(&context(&speaker), state);
+        assert!(client_manager
+            .traces()
+            .last_latency_micros("speaker")
+            .is_some());
+    }
+
+    #[test]
+    fn test_common_messages_returned() {
+        let client_manager = Arc::new(ClientManager::new());
+        let group_manager = Arc::new(GroupManager::new());
+        let playback = PlaybackController::new(client_manager.clone(), group_manager.clone());
+        let clock = ServerClock::new();
+        let client_id = "p1".to_string();
+        let ctx = RoleContext {
+            client_id: &client_id,
+            client_manager: &client_manager,
+            group_manager: &group_manager,
+            playback: &playback,
+            clock: &clock,
+            streams: None,
+        };
+        let dispatcher = RoleDispatcher::new(&RoleHandlers::new(), &["player@v1".to_string()]);
+
+        let state = Message::ClientState(ClientState {
+            player: None,
+            stats: None,
+            trace: None,
+        });
+        assert!(dispatcher.dispatch(&ctx, state).is_none());
+        let goodbye: Message =
+            serde_json::from_str(r#"{"type":"client/goodbye","payload":{"reason":"shutdown"}}"#)
+                .unwrap();
+        assert!(matches!(
+            dispatcher.dispatch(&ctx, goodbye),
+            Some(Message::ClientGoodbye(_))
+        ));
+    }
+
+    #[test]
+    fn test_request_format_renegotiates_player() {
+        let client_manager = Arc::new(ClientManager::new());
+        let group_manager = Arc::new(GroupManager::new());
+        let playback = PlaybackController::new(client_manager.clone(), group_manager.clone());
+        let clock = ServerClock::new();
+        let client_id = "kitchen".to_string();
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut client =
+            crate::server::ConnectedClient::new(client_id.clone(), "Kitchen".into(), tx);
+        client.active_roles = vec!["player@v1".to_string()];
+        client.supported_formats = [("pcm", 16), ("flac", 24)]
+            .map(|(codec, bit_depth)| AudioFormatSpec {
+                codec: codec.to_string(),
+                channels: 2,
+                sample_rate: 48000,
+                bit_depth,
+            })
+            .to_vec();
+        client.audio_format = Some(ClientManager::default_audio_format());
+        client_manager.add_client(client);
+        group_manager.add_to_group(&client_id, "default");
+        group_manager.set_playback_state("default", PlaybackState::Playing);
+        let ctx = RoleContext {
+            client_id: &client_id,
+            client_manager: &client_manager,
+            group_manager: &group_manager,
+            playback: &playback,
+            clock: &clock,
+            streams: None,
+        };
+        let handlers = RoleHandlers::new();
+        let dispatcher = RoleDispatcher::new(&handlers, &["player@v1".to_string()]);
+        let request = |codec: &str| {
+            Message::StreamRequestFormat(StreamRequestFormat {
+                player: Some(PlayerFormatRequest {
+                    codec: Some(codec.to_string()),
+                    channels: None,
+                    sample_rate: None,
+                    bit_depth: None,
+                }),
+                artwork: None,
+            })
+        };
+
+        assert!(dispatcher.dispatch(&ctx, request("flac")).is_none());
+        let format = client_manager.get_audio_format(&client_id).unwrap();
+        assert_eq!((format.codec, format.bit_depth), (Codec::Flac, 24));
+        let Ok(ServerMessage::Text(text)) = rx.try_recv() else {
+            panic!("no stream/start");
+        };
+        let Message::StreamStart(start) = serde_json::from_str(&text).unwrap() else {
+            panic!("expected stream/start, got {}", text);
+        };
+        assert_eq!(
+            (start.player.codec.as_str(), start.player.bit_depth),
+            ("flac", 24)
+        );
+
+        // A format the player never announced leaves the stream alone
+        dispatcher.dispatch(&ctx, request("opus"));
+        assert!(rx.try_recv().is_err());
+        let format = client_manager.get_audio_format(&client_id).unwrap();
+        assert_eq!(format.codec, Codec::Flac);
+    }
+}
@@ -0,0 +1,139 @@
+// ABOUTME: Histogram of time-sync round-trip times reported by a client
+// ABOUTME: Exposes percentiles so congested or asymmetric links stand out instead of averaging away
+
+use serde::{Deserialize, Serialize};
+
+/// Upper bounds of the histogram buckets (microseconds); one more bucket
+/// collects everything slower
+const BUCKET_BOUNDS_MICROS: [i64; 18] = [
+    500, 1_000, 2_000, 3_000, 5_000, 7_500, 10_000, 15_000, 20_000, 30_000, 50_000, 75_000,
+    100_000, 150_000, 200_000, 300_000, 500_000, 1_000_000,
+];
+
+/// RTT percentiles for one client
+///
+/// Percentiles are bucket upper bounds (capped at the largest RTT seen), so
+/// they are accurate to the bucket width.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RttSummary {
+    /// RTT samples recorded
+    pub samples: u64,
+    /// Median RTT (microseconds)
+    pub p50_micros: i64,
+    /// 90th percentile RTT (microseconds)
+    pub p90_micros: i64,
+    /// 99th percentile RTT (microseconds)
+    pub p99_micros: i64,
+    /// Smallest RTT seen (microseconds)
+    pub min_micros: i64,
+    /// Largest RTT seen (microseconds)
+    pub max_micros: i64,
+}
+
+impl RttSummary {
+    /// Spread between the median and the 99th percentile (microseconds)
+    ///
+    /// A wide spread means a congested link or queueing on one direction of it.
+    pub fn jitter_micros(&self) -> i64 {
+        self.p99_micros - self.p50_micros
+    }
+}
+
+/// Histogram of one client's reported time-sync RTTs
+#[derive(Debug, Clone, Default)]
+pub struct RttHistogram {
+    counts: [u64; BUCKET_BOUNDS_MICROS.len() + 1],
+    samples: u64,
+    min_micros: i64,
+    max_micros: i64,
+}
+
+impl RttHistogram {
+    /// Create an empty histogram
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record one RTT (negative values are clamped to zero)
+    pub fn record(&mut self, rtt_micros: i64) {
+        let rtt = rtt_micros.max(0);
+        let bucket = BUCKET_BOUNDS_MICROS.partition_point(|&bound| bound < rtt);
+        self.counts[bucket] += 1;
+        if self.samples == 0 {
+            self.min_micros = rtt;
+            self.max_micros = rtt;
+        } else {
+            self.min_micros = self.min_micros.min(rtt);
+            self.max_micros = self.max_micros.max(rtt);
+        }
+        self.samples += 1;
+    }
+
+    /// Number of RTTs recorded
+    pub fn samples(&self) -> u64 {
+        self.samples
+    }
+
+    /// RTT below which `fraction` (0.0-1.0) of the samples fall
+    pub fn percentile(&self, fraction: f64) -> Option<i64> {
+        if self.samples == 0 {
+            return None;
+        }
+        let rank = ((fraction.clamp(0.0, 1.0) * self.samples as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, &count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                let bound = BUCKET_BOUNDS_MICROS
+                    .get(bucket)
+                    .copied()
+                    .unwrap_or(self.max_micros);
+                return Some(bound.clamp(self.min_micros, self.max_micros));
+            }
+        }
+        Some(self.max_micros)
+    }
+
+    /// Percentile summary, or None before the first sample
+    pub fn summary(&self) -> Option<RttSummary> {
+        Some(RttSummary {
+            samples: self.samples,
+            p50_micros: self.percentile(0.50)?,
+            p90_micros: self.percentile(0.90)?,
+            p99_micros: self.percentile(0.99)?,
+            min_micros: self.min_micros,
+            max_micros: self.max_micros,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_histogram_has_no_summary() {
+        assert!(RttHistogram::new().summary().is_none());
+    }
+
+    #[test]
+    fn test_percentiles_expose_slow_tail() {
+        let mut histogram = RttHistogram::new();
+        for _ in 0..95 {
+            histogram.record(1_800);
+        }
+        for _ in 0..5 {
+            histogram.record(180_000);
+        }
+
+        let summary = histogram.summary().unwrap();
+        assert_eq!(summary.samples, 100);
+        assert_eq!(summary.p50_micros, 2_000);
+        assert_eq!(summary.p90_micros, 2_000);
+        // The slow tail is capped at the largest RTT seen
+        assert_eq!(summary.p99_micros, 180_000);
+        assert_eq!(summary.min_micros, 1_800);
+        assert_eq!(summary.max_micros, 180_000);
+        assert_eq!(summary.jitter_micros(), 178_000);
+    }
+}
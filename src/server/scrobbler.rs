@@ -0,0 +1,485 @@
+// ABOUTME: Submits plays of the current track to Last.fm, ListenBrainz or custom services
+// ABOUTME: Consumes source events and applies the half-track / four-minute scrobble rule
+
+use crate::server::group::GroupManager;
+use crate::server::history::unix_millis;
+use crate::server::source_events::{SourceEvent, SourceEvents, TrackInfo};
+use serde::Serialize;
+use serde_json::json;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::error::RecvError;
+
+/// Tracks shorter than this are never scrobbled
+pub const MIN_SCROBBLE_LENGTH: Duration = Duration::from_secs(30);
+
+/// Play time after which a track is scrobbled however long it is
+pub const SCROBBLE_AFTER: Duration = Duration::from_secs(4 * 60);
+
+/// How often play time is counted
+pub const SCROBBLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Default Last.fm API endpoint
+pub const LASTFM_API_URL: &str = "https://ws.audioscrobbler.com/2.0/";
+
+/// Default ListenBrainz API endpoint
+pub const LISTENBRAINZ_API_URL: &str = "https://api.listenbrainz.org";
+
+/// Timeout for one submission
+const SUBMIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A play of one track, as submitted to scrobbling services
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Scrobble {
+    /// Artist name
+    pub artist: String,
+    /// Track title
+    pub title: String,
+    /// Album name
+    pub album: Option<String>,
+    /// Track length in milliseconds, if known
+    pub duration_ms: Option<u64>,
+    /// When the track started, in milliseconds since the Unix epoch
+    pub started_at_ms: u64,
+}
+
+impl Scrobble {
+    /// A scrobble for `track`, or None if its artist or title is unknown
+    ///
+    /// Live streams often announce `Artist - Title` as the title alone; such
+    /// titles are split when no artist tag is set.
+    pub fn from_track(track: &TrackInfo, started_at_ms: u64) -> Option<Self> {
+        let title = track.title.as_deref()?.trim();
+        let (artist, title) = match track.artist.as_deref() {
+            Some(artist) => (artist.trim(), title),
+            None => {
+                let (artist, title) = title.split_once(" - ")?;
+                (artist.trim(), title.trim())
+            }
+        };
+        if artist.is_empty() || title.is_empty() {
+            return None;
+        }
+        Some(Self {
+            artist: artist.to_string(),
+            title: title.to_string(),
+            album: track.album.clone(),
+            duration_ms: track.duration_ms,
+            started_at_ms,
+        })
+    }
+
+    /// Play time after which this track counts as listened to, or None if
+    /// it is too short to scrobble
+    pub fn threshold(&self) -> Option<Duration> {
+        match self.duration_ms.map(Duration::from_millis) {
+            Some(length) if length < MIN_SCROBBLE_LENGTH => None,
+            Some(length) => Some((length / 2).min(SCROBBLE_AFTER)),
+            None => Some(SCROBBLE_AFTER),
+        }
+    }
+}
+
+/// A service plays are submitted to
+///
+/// Calls block on the network and are made off the async runtime.
+pub trait ScrobbleSink: Send + Sync {
+    /// Service name for logging
+    fn name(&self) -> &str;
+
+    /// Announce the track that just started (default: nothing)
+    fn now_playing(&self, _scrobble: &Scrobble) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Submit a track that has been listened to
+    fn scrobble(&self, scrobble: &Scrobble) -> Result<(), String>;
+}
+
+/// Scrobbling service credentials
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScrobblerConfig {
+    /// ListenBrainz with a user token
+    ListenBrainz {
+        /// User token from the ListenBrainz profile page
+        token: String,
+    },
+    /// Last.fm with an authorized session
+    LastFm {
+        /// Application API key
+        api_key: String,
+        /// Application shared secret
+        api_secret: String,
+        /// Session key of the user being scrobbled to
+        session_key: String,
+    },
+}
+
+impl ScrobblerConfig {
+    /// Create the sink for these credentials
+    pub fn sink(&self) -> Arc<dyn ScrobbleSink> {
+        match self.clone() {
+            Self::ListenBrainz { token } => Arc::new(ListenBrainz::new(token)),
+            Self::LastFm {
+                api_key,
+                api_secret,
+                session_key,
+            } => Arc::new(LastFm::new(api_key, api_secret, session_key)),
+        }
+    }
+}
+
+/// Submits listens to ListenBrainz
+pub struct ListenBrainz {
+    token: String,
+    api_url: String,
+}
+
+impl ListenBrainz {
+    /// Submit with the user's token
+    pub fn new(token: impl Into<String>) -> Self {
+        Self {
+            token: token.into(),
+            api_url: LISTENBRAINZ_API_URL.to_string(),
+        }
+    }
+
+    /// Use another ListenBrainz-compatible server
+    pub fn with_api_url(mut self, url: impl Into<String>) -> Self {
+        self.api_url = url.into().trim_end_matches('/').to_string();
+        self
+    }
+
+    fn submit(&self, listen_type: &str, scrobble: &Scrobble) -> Result<(), String> {
+        let mut listen = json!({
+            "track_metadata": {
+                "artist_name": scrobble.artist,
+                "track_name": scrobble.title,
+            }
+        });
+        if let Some(album) = &scrobble.album {
+            listen["track_metadata"]["release_name"] = json!(album);
+        }
+        if let Some(duration_ms) = scrobble.duration_ms {
+            listen["track_metadata"]["additional_info"] = json!({ "duration_ms": duration_ms });
+        }
+        if listen_type == "single" {
+            listen["listened_at"] = json!(scrobble.started_at_ms / 1000);
+        }
+        let body = json!({ "listen_type": listen_type, "payload": [listen] });
+        ureq::post(&format!("{}/1/submit-listens", self.api_url))
+            .timeout(SUBMIT_TIMEOUT)
+            .set("Authorization", &format!("Token {}", self.token))
+            .set("Content-Type", "application/json")
+            .send_string(&body.to_string())
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+}
+
+impl ScrobbleSink for ListenBrainz {
+    fn name(&self) -> &str {
+        "ListenBrainz"
+    }
+
+    fn now_playing(&self, scrobble: &Scrobble) -> Result<(), String> {
+        self.submit("playing_now", scrobble)
+    }
+
+    fn scrobble(&self, scrobble: &Scrobble) -> Result<(), String> {
+        self.submit("single", scrobble)
+    }
+}
+
+/// Scrobbles to Last.fm
+pub struct LastFm {
+    api_key: String,
+    api_secret: String,
+    session_key: String,
+    api_url: String,
+}
+
+impl LastFm {
+    /// Scrobble with an application key and a user's session key
+    pub fn new(
+        api_key: impl Into<String>,
+        api_secret: impl Into<String>,
+        session_key: impl Into<String>,
+    ) -> Self {
+        Self {
+            api_key: api_key.into(),
+            api_secret: api_secret.into(),
+            session_key: session_key.into(),
+            api_url: LASTFM_API_URL.to_string(),
+        }
+    }
+
+    /// Use another Last.fm-compatible server (e.g. Libre.fm)
+    pub fn with_api_url(mut self, url: impl Into<String>) -> Self {
+        self.api_url = url.into();
+        self
+    }
+
+    /// Request parameters for `method`, signed as the API requires
+    fn signed_params(&self, method: &str, scrobble: &Scrobble) -> Vec<(String, String)> {
+        let mut params = vec![
+            ("method".to_string(), method.to_string()),
+            ("api_key".to_string(), self.api_key.clone()),
+            ("sk".to_string(), self.session_key.clone()),
+            ("artist".to_string(), scrobble.artist.clone()),
+            ("track".to_string(), scrobble.title.clone()),
+        ];
+        if let Some(album) = &scrobble.album {
+            params.push(("album".to_string(), album.clone()));
+        }
+        if let Some(duration_ms) = scrobble.duration_ms {
+            params.push(("duration".to_string(), (duration_ms / 1000).to_string()));
+        }
+        if method == "track.scrobble" {
+            let timestamp = scrobble.started_at_ms / 1000;
+            params.push(("timestamp".to_string(), timestamp.to_string()));
+        }
+        params.sort();
+        let mut signed: String = params.iter().map(|(k, v)| format!("{}{}", k, v)).collect();
+        signed.push_str(&self.api_secret);
+        params.push(("api_sig".to_string(), format!("{:x}", md5::compute(signed))));
+        params.push(("format".to_string(), "json".to_string()));
+        params
+    }
+
+    fn call(&self, method: &str, scrobble: &Scrobble) -> Result<(), String> {
+        let params = self.signed_params(method, scrobble);
+        let form: Vec<(&str, &str)> = params
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        let body = ureq::post(&self.api_url)
+            .timeout(SUBMIT_TIMEOUT)
+            .send_form(&form)
+            .map_err(|e| e.to_string())?
+            .into_string()
+            .map_err(|e| e.to_string())?;
+        // Errors come back with a 200 status and an error object
+        match serde_json::from_str::<serde_json::Value>(&body) {
+            Ok(reply) if reply.get("error").is_some() => Err(format!(
+                "error {}: {}",
+                reply["error"],
+                reply["message"].as_str().unwrap_or("unknown")
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl ScrobbleSink for LastFm {
+    fn name(&self) -> &str {
+        "Last.fm"
+    }
+
+    fn now_playing(&self, scrobble: &Scrobble) -> Result<(), String> {
+        self.call("track.updateNowPlaying", scrobble)
+    }
+
+    fn scrobble(&self, scrobble: &Scrobble) -> Result<(), String> {
+        self.call("track.scrobble", scrobble)
+    }
+}
+
+/// Counts how long the current track has played and decides when to scrobble it
+#[derive(Debug, Default)]
+pub struct ScrobbleTracker {
+    track: TrackInfo,
+    started_at_ms: u64,
+    current: Option<Scrobble>,
+    played: Duration,
+    submitted: bool,
+}
+
+impl ScrobbleTracker {
+    /// Create a tracker with no current track
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A new track started; returns it if it can be announced as now playing
+    pub fn track_changed(&mut self, track: TrackInfo, now_ms: u64) -> Option<Scrobble> {
+        self.track = track;
+        self.started_at_ms = now_ms;
+        self.played = Duration::ZERO;
+        self.submitted = false;
+        self.current = Scrobble::from_track(&self.track, now_ms);
+        self.current.clone()
+    }
+
+    /// Tags of the current track arrived; returns the track if this made it
+    /// announceable
+    pub fn tags_changed(&mut self, tags: TrackInfo) -> Option<Scrobble> {
+        self.track.merge(tags);
+        let had_track = self.current.is_some();
+        if !self.submitted {
+            self.current = Scrobble::from_track(&self.track, self.started_at_ms);
+        }
+        self.current.clone().filter(|_| !had_track)
+    }
+
+    /// Count `elapsed` of play time if something is playing; returns the
+    /// track once it has played long enough to scrobble
+    pub fn advance(&mut self, elapsed: Duration, playing: bool) -> Option<Scrobble> {
+        if playing {
+            self.played += elapsed;
+        }
+        let current = self.current.as_ref()?;
+        if self.submitted || self.played < current.threshold()? {
+            return None;
+        }
+        self.submitted = true;
+        Some(current.clone())
+    }
+}
+
+/// Send a play to every sink without blocking the runtime
+fn submit(sinks: &[Arc<dyn ScrobbleSink>], scrobble: Scrobble, now_playing: bool) {
+    for sink in sinks {
+        let sink = Arc::clone(sink);
+        let scrobble = scrobble.clone();
+        tokio::task::spawn_blocking(move || {
+            let result = if now_playing {
+                sink.now_playing(&scrobble)
+            } else {
+                sink.scrobble(&scrobble)
+            };
+            match result {
+                Ok(()) if !now_playing => log::info!(
+                    "Scrobbled {} - {} to {}",
+                    scrobble.artist,
+                    scrobble.title,
+                    sink.name()
+                ),
+                Ok(()) => {}
+                Err(e) => log::warn!("{} submission failed: {}", sink.name(), e),
+            }
+        });
+    }
+}
+
+/// Spawn a task scrobbling tracks from `events` to `sinks`
+///
+/// Play time only counts while some group is playing, so paused or stopped
+/// tracks are not scrobbled early.
+pub fn spawn_scrobbler(
+    sinks: Vec<Arc<dyn ScrobbleSink>>,
+    events: SourceEvents,
+    group_manager: Arc<GroupManager>,
+    interval: Duration,
+) -> tokio::task::JoinHandle<()> {
+    let mut rx = events.subscribe();
+    tokio::spawn(async move {
+        let mut tracker = ScrobbleTracker::new();
+        tracker.track_changed(events.current(), unix_millis());
+        let mut ticker = tokio::time::interval(interval);
+        let mut last_tick = Instant::now();
+        loop {
+            tokio::select! {
+                event = rx.recv() => {
+                    let announce = match event {
+                        Ok(SourceEvent::TrackChanged { track, .. }) => {
+                            tracker.track_changed(track, unix_millis())
+                        }
+                        Ok(SourceEvent::Tags(tags)) => tracker.tags_changed(tags),
+                        Ok(SourceEvent::StreamTitle(title)) => {
+                            let track = TrackInfo {
+                                title: Some(title),
+                                ..Default::default()
+                            };
+                            tracker.track_changed(track, unix_millis())
+                        }
+                        Ok(_) => None,
+                        Err(RecvError::Lagged(missed)) => {
+                            log::warn!("Scrobbler missed {} source events", missed);
+                            None
+                        }
+                        Err(RecvError::Closed) => break,
+                    };
+                    if let Some(scrobble) = announce {
+                        submit(&sinks, scrobble, true);
+                    }
+                }
+                _ = ticker.tick() => {
+                    let now = Instant::now();
+                    let playing = !group_manager.playing_groups().is_empty();
+                    if let Some(scrobble) = tracker.advance(now - last_tick, playing) {
+                        submit(&sinks, scrobble, false);
+                    }
+                    last_tick = now;
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(artist: Option<&str>, title: &str, duration_ms: Option<u64>) -> TrackInfo {
+        TrackInfo {
+            title: Some(title.to_string()),
+            artist: artist.map(str::to_string),
+            duration_ms,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_scrobble_rules() {
+        let short = Scrobble::from_track(&track(Some("A"), "Jingle", Some(20_000)), 0).unwrap();
+        assert_eq!(short.threshold(), None);
+        let song = Scrobble::from_track(&track(Some("A"), "Song", Some(180_000)), 0).unwrap();
+        assert_eq!(song.threshold(), Some(Duration::from_secs(90)));
+        let long = Scrobble::from_track(&track(Some("A"), "Suite", Some(1_200_000)), 0).unwrap();
+        assert_eq!(long.threshold(), Some(SCROBBLE_AFTER));
+        let live = Scrobble::from_track(&track(None, "Band - Hit", None), 0).unwrap();
+        assert_eq!((live.artist.as_str(), live.title.as_str()), ("Band", "Hit"));
+        assert_eq!(live.threshold(), Some(SCROBBLE_AFTER));
+        assert!(Scrobble::from_track(&track(None, "Untitled", None), 0).is_none());
+    }
+
+    #[test]
+    fn test_tracker_counts_only_play_time() {
+        let mut tracker = ScrobbleTracker::new();
+        assert!(tracker
+            .track_changed(track(None, "Song", Some(100_000)), 1_000)
+            .is_none());
+        // The artist arrives late, making the track announceable once
+        let tags = TrackInfo {
+            artist: Some("Band".to_string()),
+            ..Default::default()
+        };
+        let announced = tracker.tags_changed(tags.clone()).unwrap();
+        assert_eq!(announced.started_at_ms, 1_000);
+        assert!(tracker.tags_changed(tags).is_none());
+
+        assert!(tracker.advance(Duration::from_secs(40), true).is_none());
+        assert!(tracker.advance(Duration::from_secs(60), false).is_none());
+        let scrobble = tracker.advance(Duration::from_secs(10), true).unwrap();
+        assert_eq!(scrobble.title, "Song");
+        assert!(tracker.advance(Duration::from_secs(60), true).is_none());
+
+        tracker.track_changed(track(Some("Band"), "Next", Some(100_000)), 200_000);
+        assert!(tracker.advance(Duration::from_secs(50), true).is_some());
+    }
+
+    #[test]
+    fn test_lastfm_signature() {
+        let lastfm = LastFm::new("key", "secret", "session");
+        let scrobble =
+            Scrobble::from_track(&track(Some("A"), "B", None), 1_700_000_000_000).unwrap();
+        let params = lastfm.signed_params("track.scrobble", &scrobble);
+        let signature = &params.iter().find(|(k, _)| k == "api_sig").unwrap().1;
+        let expected = md5::compute(
+            "api_keykeyartistAmethodtrack.scrobblesksessiontimestamp1700000000trackBsecret",
+        );
+        assert_eq!(*signature, format!("{:x}", expected));
+        assert_eq!(params.last().unwrap(), &("format".into(), "json".into()));
+    }
+}
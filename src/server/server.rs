@@ -1,33 +1,82 @@
 // ABOUTME: Main Sendspin server implementation
 // ABOUTME: Provides WebSocket endpoint and coordinates all server components
 
-use crate::server::audio_engine::spawn_audio_engine;
-use crate::server::audio_source::{AudioSource, TestToneSource};
+use crate::server::adaptive_buffer::spawn_buffer_adapter;
+use crate::server::announcement::AnnouncementQueue;
+use crate::server::audio_source::{open_source, AudioSource, TestToneSource};
 use crate::server::client_handler::handle_client;
 use crate::server::client_manager::ClientManager;
 use crate::server::clock::ServerClock;
 use crate::server::config::ServerConfig;
-use crate::server::group::GroupManager;
+use crate::server::config_file::{
+    spawn_config_watcher, ConfigFile, ConfigReloader, LiveConfig, CONFIG_POLL_INTERVAL,
+};
+use crate::server::control_api;
+use crate::server::encoder::EncoderRegistry;
+use crate::server::encoder_metrics::EncoderMetrics;
+use crate::server::extensions::Extensions;
+use crate::server::format_probe::plan_conversion;
+use crate::server::group::{GroupManager, StreamTiming};
+use crate::server::group_stats::{spawn_sync_monitor, StatsCollector};
+use crate::server::history::{
+    spawn_history_recorder, unix_millis, PlaybackHistory, DEFAULT_HISTORY_INTERVAL,
+};
+use crate::server::mdns::MdnsAdvertisement;
+use crate::server::persistence::{Persistence, SledPersistence};
+use crate::server::playback::PlaybackController;
+use crate::server::proxy;
+use crate::server::replication::spawn_replicator;
+use crate::server::roles::RoleHandlers;
+use crate::server::scrobbler::{spawn_scrobbler, ScrobbleSink, SCROBBLE_INTERVAL};
+use crate::server::source_control::SourceControl;
+use crate::server::source_events::SourceEvents;
+use crate::server::status::{spawn_status_publisher, StatusPublisher};
+use crate::server::stream_manager::{StreamManager, DEFAULT_STREAM};
+use crate::sync::audit::AuditLog;
 use axum::{
     extract::ws::WebSocketUpgrade,
-    extract::State,
+    extract::{ConnectInfo, State},
+    http::HeaderMap,
     response::IntoResponse,
     routing::any,
     Router,
 };
+use std::future::Future;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
+
+/// How often group sync deviation is checked against the warning threshold
+const SYNC_MONITOR_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often status subscribers are checked for changes
+const STATUS_INTERVAL: Duration = Duration::from_secs(1);
 
 /// Shared application state
 #[derive(Clone)]
 pub struct AppState {
-    /// Server configuration
-    pub config: Arc<ServerConfig>,
+    /// Server configuration, replaced when the config file is reloaded
+    pub config: LiveConfig,
     /// Client manager
     pub client_manager: Arc<ClientManager>,
     /// Group manager
     pub group_manager: Arc<GroupManager>,
     /// Server clock
     pub clock: Arc<ServerClock>,
+    /// Per-group statistics
+    pub stats: StatsCollector,
+    /// Encoder timings per codec
+    pub encoder_metrics: EncoderMetrics,
+    /// Source replacement and now-playing
+    pub source_control: SourceControl,
+    /// Tracks played so far
+    pub history: PlaybackHistory,
+    /// Handlers for each client role
+    pub role_handlers: RoleHandlers,
+    /// Handlers for application-specific message types
+    pub extensions: Extensions,
+    /// Audio engines, one per stream
+    pub streams: StreamManager,
 }
 
 /// Sendspin server
@@ -42,6 +91,26 @@ pub struct SendspinServer {
     clock: Arc<ServerClock>,
     /// Audio source
     source: Option<Box<dyn AudioSource>>,
+    /// Announcements to mix over the music
+    announcements: AnnouncementQueue,
+    /// Per-group statistics shared by the dashboard and APIs
+    stats: StatsCollector,
+    /// Encoder timings shared by the dashboard and APIs
+    encoder_metrics: EncoderMetrics,
+    /// Runtime source replacement
+    source_control: SourceControl,
+    /// Tracks played, with the groups that played them
+    history: PlaybackHistory,
+    /// Handlers for each client role
+    role_handlers: RoleHandlers,
+    /// Handlers for application-specific message types
+    extensions: Extensions,
+    /// Encoders available to the engine, by codec name
+    encoders: EncoderRegistry,
+    /// Services plays are scrobbled to
+    scrobblers: Vec<Arc<dyn ScrobbleSink>>,
+    /// Audio engines, one per stream
+    streams: StreamManager,
 }
 
 impl SendspinServer {
@@ -52,12 +121,80 @@ impl SendspinServer {
 
     /// Create a new Sendspin server with custom configuration
     pub fn with_config(config: ServerConfig) -> Self {
+        let clock = Arc::new(ServerClock::new());
+        let client_manager = Arc::new(
+            ClientManager::new()
+                .with_reconnect_grace(config.reconnect_grace)
+                .with_clock(clock.clone()),
+        );
+        for client_id in &config.mono_clients {
+            client_manager.set_mono(client_id, true);
+        }
+        for (client_id, map) in &config.channel_maps {
+            client_manager.set_channel_map(client_id, *map);
+        }
+        for (client_id, pin) in &config.codec_overrides {
+            client_manager.set_codec_override(client_id, Some(*pin));
+        }
+        for (client_id, tier) in &config.link_tiers {
+            client_manager.set_link_tier(client_id, Some(*tier));
+        }
+        for (tier, ms) in &config.tier_buffer_ahead {
+            client_manager.set_tier_buffer_ahead(*tier, Some(*ms));
+        }
+        if let Some(dir) = &config.state_dir {
+            match SledPersistence::open(dir) {
+                Ok(store) => client_manager.set_persistence(Arc::new(store)),
+                Err(e) => log::warn!("Client settings will not be kept: {}", e),
+            }
+        }
+        let group_manager = Arc::new(
+            GroupManager::new()
+                .with_default_auto_start(config.auto_start)
+                .with_engine_timing(StreamTiming {
+                    chunk_interval_ms: config.chunk_interval_ms,
+                    buffer_ahead_ms: config.buffer_ahead_ms,
+                }),
+        );
+        for definition in &config.groups {
+            group_manager.define_group(definition);
+            group_manager.set_buffer_ahead(&definition.id, definition.buffer_ahead_ms);
+        }
+        let scrobblers = config.scrobblers.iter().map(|s| s.sink()).collect();
+        let history = match &config.history_file {
+            Some(path) => PlaybackHistory::open(path, config.history_size).unwrap_or_else(|e| {
+                log::warn!("Cannot open playback history {}: {}", path.display(), e);
+                PlaybackHistory::new(config.history_size)
+            }),
+            None => PlaybackHistory::new(config.history_size),
+        };
+        let config = Arc::new(config);
+        let encoders = EncoderRegistry::default();
+        let encoder_metrics = EncoderMetrics::new();
+        let streams = StreamManager::new(
+            config.clone(),
+            client_manager.clone(),
+            group_manager.clone(),
+            clock.clone(),
+            encoders.clone(),
+            encoder_metrics.clone(),
+        );
         Self {
-            config: Arc::new(config),
-            client_manager: Arc::new(ClientManager::new()),
-            group_manager: Arc::new(GroupManager::new()),
-            clock: Arc::new(ServerClock::new()),
+            config,
+            stats: StatsCollector::new(client_manager.clone(), group_manager.clone()),
+            encoder_metrics,
+            client_manager,
+            group_manager,
+            clock,
             source: None,
+            announcements: AnnouncementQueue::new(),
+            source_control: SourceControl::new(),
+            history,
+            role_handlers: RoleHandlers::default(),
+            extensions: Extensions::new(),
+            encoders,
+            scrobblers,
+            streams,
         }
     }
 
@@ -67,6 +204,24 @@ impl SendspinServer {
         self
     }
 
+    /// Replace the built-in handling of client roles
+    pub fn with_role_handlers(mut self, handlers: RoleHandlers) -> Self {
+        self.role_handlers = handlers;
+        self
+    }
+
+    /// Keep client settings in a store of your own across restarts
+    pub fn with_persistence(self, store: Arc<dyn Persistence>) -> Self {
+        self.client_manager.set_persistence(store);
+        self
+    }
+
+    /// Also scrobble plays to a service of your own
+    pub fn with_scrobbler(mut self, sink: Arc<dyn ScrobbleSink>) -> Self {
+        self.scrobblers.push(sink);
+        self
+    }
+
     /// Get the server configuration
     pub fn config(&self) -> &ServerConfig {
         &self.config
@@ -82,63 +237,299 @@ impl SendspinServer {
         Arc::clone(&self.group_manager)
     }
 
-    /// Run the server
+    /// Get a playback controller for starting and stopping groups
+    pub fn playback(&self) -> PlaybackController {
+        PlaybackController::new(self.client_manager(), self.group_manager())
+    }
+
+    /// Get the shared per-group statistics collector
+    pub fn stats(&self) -> StatsCollector {
+        self.stats.clone()
+    }
+
+    /// Get the per-codec encoder timings
+    pub fn encoder_metrics(&self) -> EncoderMetrics {
+        self.encoder_metrics.clone()
+    }
+
+    /// Get the announcement queue (doorbells, alerts, TTS)
+    pub fn announcements(&self) -> AnnouncementQueue {
+        self.announcements.clone()
+    }
+
+    /// Get the handle for switching the audio source at runtime
+    pub fn source_control(&self) -> SourceControl {
+        self.source_control.clone()
+    }
+
+    /// Get the playback history, for "recently played" views and scrobbling
+    pub fn history(&self) -> PlaybackHistory {
+        self.history.clone()
+    }
+
+    /// Get the encoder registry, for adding or replacing codecs before `run`
+    pub fn encoders(&self) -> EncoderRegistry {
+        self.encoders.clone()
+    }
+
+    /// Get the stream manager, for running extra streams once the server runs
+    ///
+    /// The default stream plays the server's source; groups subscribed to
+    /// another stream only receive that stream's audio.
+    pub fn streams(&self) -> StreamManager {
+        self.streams.clone()
+    }
+
+    /// Get the channel of track, tag and stream-title changes from the playing source
+    ///
+    /// Subscribe to drive metadata, artwork or notifications from what is playing.
+    pub fn source_events(&self) -> SourceEvents {
+        self.source_control.events()
+    }
+
+    /// Get the registry for application-specific message handlers
+    ///
+    /// Send messages of your own to clients with [`ClientManager::send_custom`].
+    pub fn extensions(&self) -> Extensions {
+        self.extensions.clone()
+    }
+
+    /// Run the server until Ctrl-C
     pub async fn run(self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let listener = tokio::net::TcpListener::bind(&self.config.bind_addr).await?;
+        let shutdown_signal = async {
+            tokio::signal::ctrl_c()
+                .await
+                .expect("Failed to listen for Ctrl-C");
+            log::info!("Received shutdown signal");
+        };
+        self.serve(listener, shutdown_signal).await
+    }
+
+    /// Run the server on an already bound listener until `shutdown` completes
+    ///
+    /// Bind to port 0 and read the listener's address to run on a free port.
+    pub async fn serve(
+        self,
+        listener: tokio::net::TcpListener,
+        shutdown: impl Future<Output = ()> + Send + 'static,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         let config = self.config.clone();
         let client_manager = self.client_manager.clone();
         let group_manager = self.group_manager.clone();
         let clock = self.clock.clone();
 
         // Start audio engine
-        let source = self.source.unwrap_or_else(|| {
-            Box::new(TestToneSource::new(440.0, config.default_sample_rate))
+        let source = self
+            .source
+            .unwrap_or_else(|| Box::new(TestToneSource::new(440.0, config.default_sample_rate)));
+        // Refuse a source the output format cannot carry before anything starts
+        let chain = plan_conversion(source.sample_rate(), source.channels(), &config)?;
+        log::info!("Audio conversion: {}", chain);
+
+        // Announcements and the chunk audit only cover the default stream
+        let mut engine = self
+            .streams
+            .engine(DEFAULT_STREAM, source, self.source_control.clone());
+        engine.set_announcements(self.announcements.clone());
+        if let Some(path) = &config.chunk_audit {
+            match AuditLog::create(path) {
+                Ok(audit) => {
+                    log::info!("Recording chunk timing to {}", path.display());
+                    engine.set_chunk_audit(audit);
+                }
+                Err(e) => log::warn!("Cannot create chunk audit log {}: {}", path.display(), e),
+            }
+        }
+        // Subscribe before the engine starts so the first track is recorded
+        let history_handle = spawn_history_recorder(
+            self.history.clone(),
+            self.source_control.events(),
+            group_manager.clone(),
+            DEFAULT_HISTORY_INTERVAL,
+        );
+        let scrobbler_handle = (!self.scrobblers.is_empty()).then(|| {
+            spawn_scrobbler(
+                self.scrobblers.clone(),
+                self.source_control.events(),
+                group_manager.clone(),
+                SCROBBLE_INTERVAL,
+            )
         });
+        self.streams
+            .start(DEFAULT_STREAM, engine, self.source_control.clone())?;
+        for stream in &config.streams {
+            let uri = stream.uri.clone();
+            let (downmix, replay_gain) = (config.downmix, config.replay_gain);
+            let cache = config.url_cache.clone();
+            let opened = tokio::task::spawn_blocking(move || {
+                open_source(&uri, downmix, replay_gain, cache.as_ref())
+            })
+            .await?;
+            let checked = opened.and_then(|source| {
+                let chain = plan_conversion(source.sample_rate(), source.channels(), &config)?;
+                log::info!("Stream {} audio conversion: {}", stream.id, chain);
+                Ok(source)
+            });
+            match checked {
+                Ok(source) => {
+                    if let Err(e) = self.streams.add_stream(&stream.id, source) {
+                        log::warn!("Cannot start stream {}: {}", stream.id, e);
+                    }
+                }
+                Err(e) => log::warn!("Cannot open stream {} ({}): {}", stream.id, stream.uri, e),
+            }
+        }
 
-        let (audio_handle, audio_shutdown) = spawn_audio_engine(
-            source,
+        // Start buffer-ahead adaptation if enabled
+        let adapter_handle = config.adaptive_buffer.clone().map(|adaptive| {
+            spawn_buffer_adapter(
+                adaptive,
+                client_manager.clone(),
+                group_manager.clone(),
+                config.buffer_ahead_ms,
+            )
+        });
+
+        // Watch for groups drifting out of sync
+        let sync_monitor_handle = config.sync_warn_micros.map(|threshold| {
+            spawn_sync_monitor(self.stats.clone(), threshold, SYNC_MONITOR_INTERVAL)
+        });
+
+        // Serve status snapshots and diffs to controllers
+        let status = StatusPublisher::new(
             client_manager.clone(),
-            clock.clone(),
-            config.chunk_interval_ms,
-            config.buffer_ahead_ms,
+            group_manager.clone(),
+            self.source_control.clone(),
         );
+        status.register(&self.extensions);
+        let status_handle = spawn_status_publisher(status, STATUS_INTERVAL);
+
+        // Follow the primary as a warm standby
+        let replication_handle = config.replication.clone().map(|replication| {
+            log::info!("Warm standby for primary {}", replication.primary_url);
+            spawn_replicator(
+                replication,
+                config.clone(),
+                client_manager.clone(),
+                group_manager.clone(),
+                self.source_control.clone(),
+            )
+        });
+
+        // Expose playback to desktop media controls
+        #[cfg(unix)]
+        let mpris_handle = config.mpris.then(|| {
+            crate::server::mpris::spawn_mpris(crate::server::mpris::MprisPlayer::new(
+                config.name.clone(),
+                client_manager.clone(),
+                group_manager.clone(),
+                self.source_control.clone(),
+            ))
+        });
 
         // Build application state
+        let live_config = LiveConfig::new(config.clone());
         let state = AppState {
-            config: config.clone(),
-            client_manager,
-            group_manager,
+            config: live_config.clone(),
+            client_manager: client_manager.clone(),
+            group_manager: group_manager.clone(),
             clock,
+            stats: self.stats.clone(),
+            encoder_metrics: self.encoder_metrics.clone(),
+            source_control: self.source_control.clone(),
+            history: self.history.clone(),
+            role_handlers: self.role_handlers.clone(),
+            extensions: self.extensions.clone(),
+            streams: self.streams.clone(),
         };
 
         // Build router
-        let app = Router::new()
-            .route(&config.ws_path, any(ws_handler))
-            .with_state(state);
+        let mut app = Router::new().route(&config.ws_route(), any(ws_handler));
+        if config.control_api {
+            if config.api_keys.is_empty() {
+                log::warn!(
+                    "Control API enabled without API keys; anyone on the network can use it"
+                );
+            }
+            let api_path = format!("{}/api", config.path_prefix);
+            app = app.nest(&api_path, control_api::router(state.clone()));
+        }
+        let app = app.with_state(state);
 
-        // Bind and serve
-        let listener = tokio::net::TcpListener::bind(&config.bind_addr).await?;
         log::info!(
-            "Sendspin server listening on {} (endpoint: {})",
-            config.bind_addr,
-            config.ws_path
+            "Sendspin server listening on {} (endpoint: {}, advertised as {})",
+            listener.local_addr()?,
+            config.ws_route(),
+            config.advertised_url()
         );
 
-        // Setup graceful shutdown
-        let shutdown_signal = async {
-            tokio::signal::ctrl_c()
-                .await
-                .expect("Failed to listen for Ctrl-C");
-            log::info!("Received shutdown signal");
+        // Let clients on the LAN find the server
+        let port = listener.local_addr()?.port();
+        let mut mdns = if config.mdns {
+            MdnsAdvertisement::start(&config, port)
+                .map_err(|e| log::warn!("mDNS advertisement unavailable: {}", e))
+                .ok()
+        } else {
+            None
         };
 
+        // Apply config file edits; the watcher re-advertises on a rename
+        let watcher_handle = config.config_file.as_ref().and_then(|path| {
+            let file = ConfigFile::load(path)
+                .map_err(|e| log::warn!("Not watching config file: {}", e))
+                .ok()?;
+            let mut reloader = ConfigReloader::new(
+                path,
+                file,
+                live_config.clone(),
+                group_manager.clone(),
+                PlaybackController::new(client_manager.clone(), group_manager.clone()),
+            );
+            if let Some(advertisement) = mdns.take() {
+                reloader = reloader.with_mdns(advertisement, port);
+            }
+            Some(spawn_config_watcher(reloader, CONFIG_POLL_INTERVAL))
+        });
+
         // Run server with graceful shutdown
-        axum::serve(listener, app)
-            .with_graceful_shutdown(shutdown_signal)
-            .await?;
+        axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .with_graceful_shutdown(shutdown)
+        .await?;
+
+        // Withdraw the advertisement before the engine stops
+        drop(mdns);
+        let groups = self.group_manager.clone();
+        self.client_manager
+            .persist_all(|client_id| groups.get_client_group(client_id));
+        if let Some(handle) = watcher_handle {
+            handle.abort();
+        }
 
         // Shutdown audio engine
-        let _ = audio_shutdown.send(true);
-        let _ = audio_handle.await;
+        if let Some(handle) = adapter_handle {
+            handle.abort();
+        }
+        if let Some(handle) = sync_monitor_handle {
+            handle.abort();
+        }
+        if let Some(handle) = replication_handle {
+            handle.abort();
+        }
+        status_handle.abort();
+        history_handle.abort();
+        if let Some(handle) = scrobbler_handle {
+            handle.abort();
+        }
+        self.history.finish(unix_millis());
+        #[cfg(unix)]
+        if let Some(handle) = mpris_handle {
+            handle.abort();
+        }
+        self.streams.shutdown().await;
 
         log::info!("Server shutdown complete");
         Ok(())
@@ -154,15 +545,32 @@ impl Default for SendspinServer {
 /// WebSocket upgrade handler
 async fn ws_handler(
     ws: WebSocketUpgrade,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     State(state): State<AppState>,
 ) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| {
-        handle_client(
-            socket,
-            state.client_manager,
-            state.group_manager,
-            state.clock,
-            state.config,
-        )
-    })
+    let config = state.config.current();
+    let remote = proxy::client_addr(
+        &headers,
+        peer,
+        config.trust_forwarded,
+        &config.trusted_proxies,
+    );
+    // Refuse oversized messages while reading them rather than after buffering
+    let max_bytes = config.inbound_limits.max_message_bytes;
+    ws.max_message_size(max_bytes)
+        .max_frame_size(max_bytes)
+        .on_upgrade(move |socket| {
+            handle_client(
+                socket,
+                remote,
+                state.client_manager,
+                state.group_manager,
+                state.clock,
+                config,
+                state.role_handlers,
+                state.extensions,
+                state.streams,
+            )
+        })
 }
@@ -0,0 +1,494 @@
+// ABOUTME: Stream source joining a Snapcast server as a Snapclient and replaying its PCM
+// ABOUTME: Bridges an existing Snapcast setup onto Sendspin players during a migration
+
+use crate::audio::types::Sample;
+use crate::protocol::messages::AudioFormatSpec;
+use crate::server::audio_source::AudioSource;
+use crate::server::ingest::{ingest_source, IngestFeed, IngestSource};
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// URI scheme for [`SnapcastSource`]s: `snapcast://host:1704?id=living-room-bridge`
+pub const SNAPCAST_SCHEME: &str = "snapcast:";
+
+/// Port Snapcast servers accept clients on
+pub const SNAPCAST_PORT: u16 = 1704;
+
+/// Client ID the bridge registers with unless the URI names one
+pub const DEFAULT_BRIDGE_ID: &str = "sendspin-bridge";
+
+/// How long the server may take to send the stream's codec header
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Bytes in the header that starts every Snapcast message
+const BASE_HEADER_LEN: usize = 26;
+
+/// Largest message payload accepted, well above a chunk of 192kHz audio
+const MAX_PAYLOAD: usize = 4 << 20;
+
+/// Snapcast message types used by the bridge
+mod message_type {
+    pub const CODEC_HEADER: u16 = 1;
+    pub const WIRE_CHUNK: u16 = 2;
+    pub const SERVER_SETTINGS: u16 = 3;
+    pub const HELLO: u16 = 5;
+    pub const ERROR: u16 = 8;
+}
+
+/// Where to find the Snapcast server and who to register as
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapcastTarget {
+    /// `host:port` of the server's stream port
+    pub address: String,
+    /// Client ID shown in Snapcast's client list
+    pub client_id: String,
+}
+
+/// Split a `snapcast:` URI into the server address and client ID
+///
+/// Returns None for URIs of other schemes. The port defaults to
+/// [`SNAPCAST_PORT`] and the ID to [`DEFAULT_BRIDGE_ID`].
+pub fn parse_snapcast_uri(uri: &str) -> Option<SnapcastTarget> {
+    let rest = uri.strip_prefix(SNAPCAST_SCHEME)?;
+    let rest = rest.strip_prefix("//").unwrap_or(rest);
+    let (host, query) = rest.split_once('?').unwrap_or((rest, ""));
+    let host = host.trim_end_matches('/');
+    let address = if host
+        .rsplit_once(':')
+        .is_some_and(|(_, port)| port.parse::<u16>().is_ok())
+    {
+        host.to_string()
+    } else {
+        format!("{}:{}", host, SNAPCAST_PORT)
+    };
+    let client_id = query
+        .split('&')
+        .find_map(|param| param.strip_prefix("id="))
+        .filter(|id| !id.is_empty())
+        .unwrap_or(DEFAULT_BRIDGE_ID);
+    Some(SnapcastTarget {
+        address,
+        client_id: client_id.to_string(),
+    })
+}
+
+/// A Snapcast message: its type and payload
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SnapMessage {
+    kind: u16,
+    payload: Vec<u8>,
+}
+
+impl SnapMessage {
+    /// The message framed for the wire, with zeroed timestamps
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(BASE_HEADER_LEN + self.payload.len());
+        bytes.extend_from_slice(&self.kind.to_le_bytes());
+        // ID, refers-to, and the sent and received timestamps
+        bytes.extend_from_slice(&[0; 20]);
+        bytes.extend_from_slice(&(self.payload.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.payload);
+        bytes
+    }
+
+    /// Read one message from `reader`
+    fn read(reader: &mut impl Read) -> io::Result<Self> {
+        let mut header = [0u8; BASE_HEADER_LEN];
+        reader.read_exact(&mut header)?;
+        let kind = u16::from_le_bytes([header[0], header[1]]);
+        let size = u32::from_le_bytes([header[22], header[23], header[24], header[25]]) as usize;
+        if size > MAX_PAYLOAD {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}-byte Snapcast message", size),
+            ));
+        }
+        let mut payload = vec![0u8; size];
+        reader.read_exact(&mut payload)?;
+        Ok(Self { kind, payload })
+    }
+}
+
+/// Cursor over a message payload's little-endian fields
+struct Fields<'a>(&'a [u8]);
+
+impl<'a> Fields<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.0.len() < len {
+            return Err("truncated Snapcast message".to_string());
+        }
+        let (field, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(field)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// A length-prefixed byte string
+    fn sized(&mut self) -> Result<&'a [u8], String> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+}
+
+/// The Hello a Snapclient opens its connection with
+fn hello(client_id: &str) -> SnapMessage {
+    let hostname = std::env::var("HOSTNAME").unwrap_or_else(|_| "sendspin".to_string());
+    let json = serde_json::json!({
+        "Arch": std::env::consts::ARCH,
+        "ClientName": "Sendspin",
+        "HostName": hostname,
+        "ID": client_id,
+        "Instance": 1,
+        "MAC": "00:00:00:00:00:00",
+        "OS": std::env::consts::OS,
+        "SnapStreamProtocolVersion": 2,
+        "Version": env!("CARGO_PKG_VERSION"),
+    })
+    .to_string();
+    let mut payload = (json.len() as u32).to_le_bytes().to_vec();
+    payload.extend_from_slice(json.as_bytes());
+    SnapMessage {
+        kind: message_type::HELLO,
+        payload,
+    }
+}
+
+/// PCM layout announced by a Snapcast `pcm` codec header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SnapPcm {
+    sample_rate: u32,
+    bit_depth: u8,
+    channels: u8,
+    /// 24-bit samples sent in 32-bit containers, as Snapcast stores them
+    padded: bool,
+}
+
+impl SnapPcm {
+    /// Read the RIFF/WAVE header Snapcast sends as the `pcm` codec header
+    fn from_wav_header(header: &[u8]) -> Result<Self, String> {
+        if header.len() < 12 || &header[..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+            return Err("Snapcast pcm header is not a WAVE header".to_string());
+        }
+        let mut chunks = Fields(&header[12..]);
+        loop {
+            let id = chunks.take(4)?;
+            let chunk = chunks.sized()?;
+            if id != b"fmt " {
+                continue;
+            }
+            if chunk.len() < 16 {
+                return Err("Snapcast pcm header has a short fmt chunk".to_string());
+            }
+            let u16_at = |at: usize| u16::from_le_bytes([chunk[at], chunk[at + 1]]);
+            let channels = u16_at(2) as u8;
+            let sample_rate = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
+            let block_align = u16_at(12);
+            let bit_depth = u16_at(14) as u8;
+            return Ok(Self {
+                sample_rate,
+                bit_depth,
+                channels,
+                padded: bit_depth == 24 && block_align == channels as u16 * 4,
+            });
+        }
+    }
+
+    fn spec(&self) -> AudioFormatSpec {
+        AudioFormatSpec {
+            codec: "pcm".to_string(),
+            channels: self.channels,
+            sample_rate: self.sample_rate,
+            bit_depth: self.bit_depth,
+        }
+    }
+
+    /// Queue a chunk of the stream's PCM
+    fn push(&self, feed: &IngestFeed, pcm: &[u8]) -> Result<(), String> {
+        if !self.padded {
+            return feed.push(pcm).map(|_| ());
+        }
+        let samples: Vec<Sample> = pcm
+            .as_chunks::<4>()
+            .0
+            .iter()
+            .map(|&bytes| Sample::from_i24(i32::from_le_bytes(bytes)))
+            .collect();
+        feed.push_samples(&samples);
+        Ok(())
+    }
+}
+
+/// Stream source playing what a Snapcast server streams to its clients
+///
+/// The bridge registers with the server as a Snapclient (it shows up in
+/// Snapcast's client list, where its stream can be chosen) and buffers the
+/// audio like a source client's. Only streams using Snapcast's `pcm` codec
+/// can be bridged. The source ends when the server closes the connection;
+/// dropping the source disconnects it.
+pub struct SnapcastSource {
+    address: String,
+    input: IngestSource,
+    stream: TcpStream,
+}
+
+impl SnapcastSource {
+    /// Connect to the Snapcast server at `target` and start receiving its stream
+    pub fn open(target: &SnapcastTarget) -> Result<Self, String> {
+        let address = target
+            .address
+            .to_socket_addrs()
+            .map_err(|e| format!("cannot resolve {}: {}", target.address, e))?
+            .next()
+            .ok_or_else(|| format!("{} has no address", target.address))?;
+        let mut stream = TcpStream::connect_timeout(&address, HEADER_TIMEOUT)
+            .map_err(|e| format!("cannot connect to {}: {}", target.address, e))?;
+        let _ = stream.set_nodelay(true);
+        stream
+            .write_all(&hello(&target.client_id).encode())
+            .map_err(|e| format!("cannot greet {}: {}", target.address, e))?;
+
+        stream
+            .set_read_timeout(Some(HEADER_TIMEOUT))
+            .map_err(|e| e.to_string())?;
+        let pcm = read_codec_header(&mut stream, &target.address)?;
+        stream.set_read_timeout(None).map_err(|e| e.to_string())?;
+
+        let (input, feed) = ingest_source(&target.address, &pcm.spec())?;
+        let mut reader = stream.try_clone().map_err(|e| e.to_string())?;
+        let label = target.address.clone();
+        std::thread::Builder::new()
+            .name("audio-snapcast".to_string())
+            .spawn(move || {
+                relay_chunks(&label, &mut reader, pcm, &feed);
+                log::info!("Snapcast stream from {} ended", label);
+            })
+            .map_err(|e| format!("cannot start Snapcast reader: {}", e))?;
+        log::info!(
+            "Bridging Snapcast server {} as client '{}' ({}Hz, {}-bit, {} channels)",
+            target.address,
+            target.client_id,
+            pcm.sample_rate,
+            pcm.bit_depth,
+            pcm.channels
+        );
+        Ok(Self {
+            address: target.address.clone(),
+            input,
+            stream,
+        })
+    }
+}
+
+/// Wait for the codec header that precedes the stream's audio
+fn read_codec_header(stream: &mut TcpStream, address: &str) -> Result<SnapPcm, String> {
+    loop {
+        let message = SnapMessage::read(stream)
+            .map_err(|e| format!("no stream header from {}: {}", address, e))?;
+        match message.kind {
+            message_type::CODEC_HEADER => {
+                let mut fields = Fields(&message.payload);
+                let codec = String::from_utf8_lossy(fields.sized()?).to_string();
+                if codec != "pcm" {
+                    return Err(format!(
+                        "Snapcast stream uses {}; set codec = pcm on the stream to bridge it",
+                        codec
+                    ));
+                }
+                return SnapPcm::from_wav_header(fields.sized()?);
+            }
+            message_type::ERROR => {
+                return Err(format!("{} refused the bridge", address));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Copy wire chunks into `feed` until the connection or the source goes away
+fn relay_chunks(label: &str, reader: &mut TcpStream, pcm: SnapPcm, feed: &IngestFeed) {
+    while feed.is_listening() {
+        let message = match SnapMessage::read(reader) {
+            Ok(message) => message,
+            Err(e) => {
+                if feed.is_listening() {
+                    log::warn!("Snapcast connection to {} lost: {}", label, e);
+                }
+                return;
+            }
+        };
+        match message.kind {
+            message_type::WIRE_CHUNK => {
+                // Skip the chunk's play time; the ingest buffer paces playback
+                let mut fields = Fields(&message.payload);
+                let chunk = fields.take(8).and_then(|_| fields.sized());
+                if let Err(e) = chunk.and_then(|pcm_bytes| pcm.push(feed, pcm_bytes)) {
+                    log::warn!("Bad Snapcast chunk from {}: {}", label, e);
+                    return;
+                }
+            }
+            message_type::SERVER_SETTINGS => {
+                let settings = Fields(&message.payload).sized().unwrap_or_default();
+                log::debug!(
+                    "Snapcast settings from {}: {}",
+                    label,
+                    String::from_utf8_lossy(settings)
+                );
+            }
+            message_type::CODEC_HEADER => {
+                log::warn!(
+                    "Snapcast stream on {} changed format; reconnect the bridge",
+                    label
+                );
+                return;
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Drop for SnapcastSource {
+    fn drop(&mut self) {
+        // Unblocks the reader thread
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+impl AudioSource for SnapcastSource {
+    fn read_chunk(&mut self, samples_per_channel: usize) -> Option<Vec<Sample>> {
+        self.input.read_chunk(samples_per_channel)
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn channels(&self) -> u8 {
+        2
+    }
+
+    fn is_exhausted(&self) -> bool {
+        self.input.is_exhausted()
+    }
+
+    fn description(&self) -> Option<String> {
+        Some(format!("snapcast {}", self.address))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::time::Instant;
+
+    /// A 44-byte WAVE header like Snapcast's pcm encoder writes
+    fn wav_header(rate: u32, bits: u16, channels: u16, sample_bytes: u16) -> Vec<u8> {
+        let block_align = channels * sample_bytes;
+        let mut header = b"RIFF".to_vec();
+        header.extend_from_slice(&36u32.to_le_bytes());
+        header.extend_from_slice(b"WAVEfmt ");
+        header.extend_from_slice(&16u32.to_le_bytes());
+        header.extend_from_slice(&1u16.to_le_bytes());
+        header.extend_from_slice(&channels.to_le_bytes());
+        header.extend_from_slice(&rate.to_le_bytes());
+        header.extend_from_slice(&(rate * block_align as u32).to_le_bytes());
+        header.extend_from_slice(&block_align.to_le_bytes());
+        header.extend_from_slice(&bits.to_le_bytes());
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header
+    }
+
+    fn sized(bytes: &[u8]) -> Vec<u8> {
+        let mut field = (bytes.len() as u32).to_le_bytes().to_vec();
+        field.extend_from_slice(bytes);
+        field
+    }
+
+    #[test]
+    fn test_parse_snapcast_uri() {
+        let target = parse_snapcast_uri("snapcast://snapserver").unwrap();
+        assert_eq!(target.address, "snapserver:1704");
+        assert_eq!(target.client_id, DEFAULT_BRIDGE_ID);
+
+        let target = parse_snapcast_uri("snapcast://10.0.0.2:1800/?id=attic").unwrap();
+        assert_eq!(target.address, "10.0.0.2:1800");
+        assert_eq!(target.client_id, "attic");
+
+        assert!(parse_snapcast_uri("pipe:///tmp/snapfifo").is_none());
+    }
+
+    #[test]
+    fn test_wav_header_formats() {
+        let pcm = SnapPcm::from_wav_header(&wav_header(48000, 16, 2, 2)).unwrap();
+        assert_eq!(
+            (pcm.sample_rate, pcm.bit_depth, pcm.channels),
+            (48000, 16, 2)
+        );
+        assert!(!pcm.padded);
+        assert!(
+            SnapPcm::from_wav_header(&wav_header(96000, 24, 2, 4))
+                .unwrap()
+                .padded
+        );
+        assert!(SnapPcm::from_wav_header(b"fLaC").is_err());
+    }
+
+    #[test]
+    fn test_bridges_a_snapcast_stream() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = std::thread::spawn(move || {
+            let (mut client, _) = listener.accept().unwrap();
+            let hello = SnapMessage::read(&mut client).unwrap();
+            assert_eq!(hello.kind, message_type::HELLO);
+
+            let mut header = sized(b"pcm");
+            header.extend(sized(&wav_header(8000, 16, 2, 2)));
+            let mut messages = vec![SnapMessage {
+                kind: message_type::CODEC_HEADER,
+                payload: header,
+            }];
+            // 1600 stereo frames at 8kHz in two chunks
+            let pcm: Vec<u8> = std::iter::repeat_n(100i16.to_le_bytes(), 1600)
+                .flatten()
+                .collect();
+            for _ in 0..2 {
+                let mut chunk = vec![0u8; 8];
+                chunk.extend(sized(&pcm));
+                messages.push(SnapMessage {
+                    kind: message_type::WIRE_CHUNK,
+                    payload: chunk,
+                });
+            }
+            for message in messages {
+                client.write_all(&message.encode()).unwrap();
+            }
+            String::from_utf8(hello.payload[4..].to_vec()).unwrap()
+        });
+
+        let target = SnapcastTarget {
+            address,
+            client_id: "attic".to_string(),
+        };
+        let mut source = SnapcastSource::open(&target).unwrap();
+        assert!(server.join().unwrap().contains("\"ID\":\"attic\""));
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut played = 0;
+        while let Some(chunk) = source.read_chunk(50) {
+            played += chunk
+                .iter()
+                .filter(|&&s| s == Sample::from_i16(100))
+                .count();
+            assert!(Instant::now() < deadline, "stream never ended");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(played, 3200);
+    }
+}
@@ -0,0 +1,276 @@
+// ABOUTME: Export of a server's groups, clients, volumes and sources as one JSON document
+// ABOUTME: Imported on another server instance to migrate or restore a multi-room setup
+
+use crate::audio::downmix::ChannelMap;
+use crate::audio::types::Codec;
+use crate::server::client_manager::{ClientId, ClientManager};
+use crate::server::codec_policy::CodecOverride;
+use crate::server::config::ServerConfig;
+use crate::server::encoder::EncoderSettings;
+use crate::server::group::GroupManager;
+use crate::server::link_tier::LinkTier;
+use crate::server::replication::ReplicationSnapshot;
+use crate::server::source_control::SourceControl;
+use crate::server::stream_manager::{StreamDefinition, StreamInfo, DEFAULT_STREAM};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Path of the export/import endpoint under the control API
+pub const SNAPSHOT_PATH: &str = "/snapshot";
+
+/// Format version written by this server; newer snapshots are refused
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// A client's own settings, kept whether or not it is connected
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientSettings {
+    /// Client identifier
+    pub client_id: ClientId,
+    /// Operator's display name for the client
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Whether the client gets mono audio
+    #[serde(default)]
+    pub mono: bool,
+    /// Channel map other than stereo or mono (left, right, swap)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel_map: Option<ChannelMap>,
+    /// Codec the client is pinned to (flac or pcm)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub codec: Option<String>,
+    /// Encoder tuning pinned with the codec
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub codec_settings: Option<EncoderSettings>,
+    /// Link tier the client is tagged with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link_tier: Option<LinkTier>,
+}
+
+/// A server's configuration as exported by the control API
+///
+/// Holds what replication mirrors (groups, client volumes and memberships,
+/// the main source) plus the extra streams and the per-client and per-tier
+/// settings an operator made.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServerSnapshot {
+    /// Format version, [`SNAPSHOT_VERSION`] when written by this server
+    pub version: u32,
+    /// Groups, clients and the main source
+    #[serde(flatten)]
+    pub state: ReplicationSnapshot,
+    /// Streams besides the default one, sorted by ID
+    #[serde(default)]
+    pub streams: Vec<StreamDefinition>,
+    /// Per-client settings, sorted by client ID
+    #[serde(default)]
+    pub client_settings: Vec<ClientSettings>,
+    /// Buffer-ahead of players on each tier that has one (ms)
+    #[serde(default)]
+    pub tier_buffer_ahead: BTreeMap<String, u64>,
+}
+
+impl ServerSnapshot {
+    /// Capture this server's configuration, with `streams` as running now
+    pub fn capture(
+        config: &ServerConfig,
+        client_manager: &ClientManager,
+        group_manager: &GroupManager,
+        source_control: &SourceControl,
+        streams: &[StreamInfo],
+    ) -> Self {
+        let state =
+            ReplicationSnapshot::capture(config, client_manager, group_manager, source_control);
+        let streams = streams
+            .iter()
+            .filter(|stream| stream.stream_id != DEFAULT_STREAM)
+            .filter_map(|stream| {
+                Some(StreamDefinition {
+                    id: stream.stream_id.clone(),
+                    uri: stream.now_playing.source.clone()?,
+                })
+            })
+            .collect();
+        let client_settings = client_manager
+            .configured_clients()
+            .into_iter()
+            .map(|client_id| {
+                let pin = client_manager.codec_override(&client_id);
+                let channel_map = client_manager.channel_map(&client_id);
+                ClientSettings {
+                    name: client_manager.name_override(&client_id),
+                    mono: channel_map == ChannelMap::Mono,
+                    channel_map: Some(channel_map)
+                        .filter(|map| !matches!(map, ChannelMap::Stereo | ChannelMap::Mono)),
+                    codec: pin.map(|pin| pin.codec.name().to_string()),
+                    codec_settings: pin.map(|pin| pin.settings),
+                    link_tier: client_manager.link_tier_tag(&client_id),
+                    client_id,
+                }
+            })
+            .filter(|settings| {
+                settings.name.is_some()
+                    || settings.mono
+                    || settings.channel_map.is_some()
+                    || settings.codec.is_some()
+                    || settings.link_tier.is_some()
+            })
+            .collect();
+        let tier_buffer_ahead = LinkTier::value_variants()
+            .iter()
+            .filter_map(|tier| {
+                let ms = client_manager.tier_buffer_ahead(*tier)?;
+                Some((tier.as_str().to_string(), ms))
+            })
+            .collect();
+        Self {
+            version: SNAPSHOT_VERSION,
+            state,
+            streams,
+            client_settings,
+            tier_buffer_ahead,
+        }
+    }
+
+    /// Check the snapshot can be imported by this server
+    pub fn validate(&self) -> Result<(), String> {
+        if self.version > SNAPSHOT_VERSION {
+            return Err(format!(
+                "snapshot version {} is newer than this server's {}",
+                self.version, SNAPSHOT_VERSION
+            ));
+        }
+        for settings in &self.client_settings {
+            if let Some(codec) = &settings.codec {
+                if Codec::from_name(codec).is_none() {
+                    return Err(format!(
+                        "unknown codec '{}' for client {}",
+                        codec, settings.client_id
+                    ));
+                }
+            }
+            if let Some(encoder) = &settings.codec_settings {
+                encoder.validate()?;
+            }
+        }
+        for tier in self.tier_buffer_ahead.keys() {
+            LinkTier::from_str(tier, true).map_err(|_| format!("unknown link tier '{}'", tier))?;
+        }
+        Ok(())
+    }
+
+    /// Apply the snapshot's groups and settings to this server
+    ///
+    /// Groups and client sessions are applied as a replica applies them, so
+    /// clients join their group at their volume when they connect. Client
+    /// and tier settings replace this server's for the clients and tiers the
+    /// snapshot names. Sources are left to the caller, since opening them
+    /// blocks.
+    pub fn apply(&self, client_manager: &ClientManager, group_manager: &GroupManager) {
+        self.state.apply(client_manager, group_manager);
+        for settings in &self.client_settings {
+            let client_id = &settings.client_id;
+            if client_manager.name_override(client_id) != settings.name {
+                client_manager.set_name_override(client_id, settings.name.clone());
+            }
+            match settings.channel_map {
+                Some(map) => client_manager.set_channel_map(client_id, map),
+                None => client_manager.set_mono(client_id, settings.mono),
+            }
+            let pin = settings
+                .codec
+                .as_deref()
+                .and_then(Codec::from_name)
+                .map(|codec| CodecOverride {
+                    codec,
+                    settings: settings.codec_settings.unwrap_or_default(),
+                });
+            client_manager.set_codec_override(client_id, pin);
+            client_manager.set_link_tier(client_id, settings.link_tier);
+        }
+        for (tier, ms) in &self.tier_buffer_ahead {
+            if let Ok(tier) = LinkTier::from_str(tier, true) {
+                client_manager.set_tier_buffer_ahead(tier, Some(*ms));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::client_manager::ConnectedClient;
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn test_snapshot_moves_settings_between_servers() {
+        let old_clients = ClientManager::new();
+        let old_groups = GroupManager::new();
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let mut client = ConnectedClient::new("kitchen".to_string(), "Kitchen".to_string(), tx);
+        client.volume = 40;
+        old_clients.add_client(client);
+        old_groups.create_group("downstairs", "Downstairs");
+        old_groups.add_to_group("kitchen", "downstairs");
+        old_groups.set_volume("downstairs", 70);
+        old_clients.set_name_override("kitchen", Some("Kitchen Shelf".to_string()));
+        old_clients.set_codec_override("kitchen", Some(CodecOverride::new(Codec::Opus)));
+        // Settings of a client that is not connected are exported too
+        old_clients.set_mono("porch", true);
+        old_clients.set_channel_map("patio", ChannelMap::Right);
+        old_clients.set_link_tier("porch", Some(LinkTier::Bluetooth));
+        old_clients.set_tier_buffer_ahead(LinkTier::Bluetooth, Some(400));
+
+        let snapshot = ServerSnapshot::capture(
+            &ServerConfig::default(),
+            &old_clients,
+            &old_groups,
+            &SourceControl::default(),
+            &[],
+        );
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let snapshot: ServerSnapshot = serde_json::from_str(&json).unwrap();
+        assert_eq!(snapshot.version, SNAPSHOT_VERSION);
+        snapshot.validate().unwrap();
+
+        let new_clients = ClientManager::new();
+        let new_groups = GroupManager::new();
+        snapshot.apply(&new_clients, &new_groups);
+
+        assert_eq!(new_groups.get_group("downstairs").unwrap().1, "Downstairs");
+        let session = new_clients.resume("kitchen").unwrap();
+        assert_eq!(session.group_id.as_deref(), Some("downstairs"));
+        assert_eq!(session.volume, 40);
+        assert_eq!(
+            new_clients.name_override("kitchen").as_deref(),
+            Some("Kitchen Shelf")
+        );
+        assert_eq!(
+            new_clients.codec_override("kitchen").map(|pin| pin.codec),
+            Some(Codec::Opus)
+        );
+        assert!(new_clients.is_mono("porch"));
+        assert_eq!(new_clients.channel_map("patio"), ChannelMap::Right);
+        assert_eq!(
+            new_clients.link_tier_tag("porch"),
+            Some(LinkTier::Bluetooth)
+        );
+        assert_eq!(
+            new_clients.tier_buffer_ahead(LinkTier::Bluetooth),
+            Some(400)
+        );
+    }
+
+    #[test]
+    fn test_newer_snapshots_are_refused() {
+        let mut snapshot = ServerSnapshot::capture(
+            &ServerConfig::default(),
+            &ClientManager::new(),
+            &GroupManager::new(),
+            &SourceControl::default(),
+            &[],
+        );
+        snapshot.version = SNAPSHOT_VERSION + 1;
+        assert!(snapshot.validate().is_err());
+    }
+}
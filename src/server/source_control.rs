@@ -0,0 +1,160 @@
+// ABOUTME: Runtime control of the audio engine's source
+// ABOUTME: Queues replacement sources for the engine and reports what is currently playing
+
+use crate::server::audio_source::AudioSource;
+use crate::server::play_queue::PlayQueue;
+use crate::server::source_events::{SourceEvent, SourceEvents, TrackInfo};
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// The source the audio engine is currently streaming
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NowPlaying {
+    /// Source description (file path, URL, ...)
+    pub source: Option<String>,
+    /// Source sample rate in Hz
+    pub sample_rate: u32,
+    /// Source channel count
+    pub channels: u8,
+    /// Tags of the current track
+    pub track: TrackInfo,
+}
+
+/// Handle for replacing the engine's source while the server runs
+///
+/// All groups share the engine's stream, so a new source plays everywhere.
+/// The engine picks up the replacement before generating its next chunk, and
+/// moves on to the stream's play queue when a source ends or is skipped.
+#[derive(Clone, Default)]
+pub struct SourceControl {
+    pending: Arc<Mutex<Option<Box<dyn AudioSource>>>>,
+    current: Arc<RwLock<NowPlaying>>,
+    events: SourceEvents,
+    queue: PlayQueue,
+    skip: Arc<AtomicBool>,
+}
+
+impl SourceControl {
+    /// Create a source control with nothing queued
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Switch to a new source (replaces any replacement not yet picked up)
+    ///
+    /// The new source is reported as now playing right away, even while the
+    /// engine is in standby.
+    pub fn replace(&self, source: Box<dyn AudioSource>) {
+        self.set_current(source.as_ref());
+        *self.pending.lock() = Some(source);
+    }
+
+    /// What the engine is currently streaming
+    pub fn now_playing(&self) -> NowPlaying {
+        NowPlaying {
+            track: self.events.current(),
+            ..self.current.read().clone()
+        }
+    }
+
+    /// Channel of track, tag and title changes from the playing source
+    pub fn events(&self) -> SourceEvents {
+        self.events.clone()
+    }
+
+    /// Tracks to play after the current source
+    pub fn queue(&self) -> PlayQueue {
+        self.queue.clone()
+    }
+
+    /// Move on to the next queued track
+    ///
+    /// The current source keeps playing until the next track has opened.
+    /// Returns false, skipping nothing, if the queue is empty.
+    pub fn skip(&self) -> bool {
+        if self.queue.is_empty() {
+            return false;
+        }
+        self.skip.store(true, Ordering::Relaxed);
+        true
+    }
+
+    /// Take a skip requested since the last call
+    pub(crate) fn take_skip(&self) -> bool {
+        self.skip.swap(false, Ordering::Relaxed)
+    }
+
+    /// Take the queued replacement, if any
+    pub(crate) fn take(&self) -> Option<Box<dyn AudioSource>> {
+        self.pending.lock().take()
+    }
+
+    /// Record the source the engine is now streaming
+    pub(crate) fn set_current(&self, source: &dyn AudioSource) {
+        *self.current.write() = NowPlaying {
+            source: source.description(),
+            sample_rate: source.sample_rate(),
+            channels: source.channels(),
+            track: TrackInfo::default(),
+        };
+    }
+
+    /// Record a source the engine just started and announce the track change
+    pub(crate) fn started(&self, source: &mut dyn AudioSource) {
+        self.set_current(source);
+        source.set_events(self.events.clone());
+        self.events.emit(SourceEvent::TrackChanged {
+            source: source.description(),
+            track: source.track_info(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::audio_source::{SilenceSource, TestToneSource};
+
+    #[test]
+    fn test_replace_and_report() {
+        let control = SourceControl::new();
+        assert!(control.take().is_none());
+
+        control.set_current(&TestToneSource::new(440.0, 48000));
+        assert_eq!(
+            control.now_playing().source.as_deref(),
+            Some("Test tone 440 Hz")
+        );
+
+        control.replace(Box::new(SilenceSource::new(44100)));
+        assert!(control.take().is_some());
+        assert!(control.take().is_none());
+        assert_eq!(
+            control.now_playing(),
+            NowPlaying {
+                source: Some("Silence".to_string()),
+                sample_rate: 44100,
+                channels: 2,
+                track: TrackInfo::default(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_started_emits_track_change() {
+        let control = SourceControl::new();
+        let mut events = control.events().subscribe();
+
+        let mut source = TestToneSource::new(440.0, 48000);
+        control.started(&mut source);
+        assert_eq!(
+            events.try_recv().unwrap(),
+            SourceEvent::TrackChanged {
+                source: Some("Test tone 440 Hz".to_string()),
+                track: TrackInfo::default(),
+            }
+        );
+    }
+}
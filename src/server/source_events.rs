@@ -0,0 +1,215 @@
+// ABOUTME: Events raised by audio sources (track changes, tags, stream titles, failover)
+// ABOUTME: Broadcast to server components that react to what is playing instead of polling sources
+
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+/// Events buffered per subscriber before the slowest one starts missing some
+const EVENT_CAPACITY: usize = 64;
+
+/// Descriptive tags of the current track
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrackInfo {
+    /// Track title
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// Artist name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artist: Option<String>,
+    /// Album name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub album: Option<String>,
+    /// Track length in milliseconds, when the source knows it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    /// Cover art embedded in the track
+    #[serde(skip)]
+    pub album_art: Option<Artwork>,
+    /// Picture of the artist embedded in the track
+    #[serde(skip)]
+    pub artist_art: Option<Artwork>,
+}
+
+/// An encoded image attached to a track
+///
+/// Cheap to clone; the image bytes are shared.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Artwork {
+    /// MIME type of `data` (e.g. `image/jpeg`)
+    pub media_type: String,
+    /// Encoded image
+    pub data: Arc<[u8]>,
+}
+
+impl std::fmt::Debug for Artwork {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Artwork({}, {} bytes)", self.media_type, self.data.len())
+    }
+}
+
+impl TrackInfo {
+    /// Whether no tag is set
+    pub fn is_empty(&self) -> bool {
+        self.title.is_none()
+            && self.artist.is_none()
+            && self.album.is_none()
+            && self.duration_ms.is_none()
+            && self.album_art.is_none()
+            && self.artist_art.is_none()
+    }
+
+    /// Overwrite the tags that `other` sets, keeping the rest
+    pub fn merge(&mut self, other: TrackInfo) {
+        if other.title.is_some() {
+            self.title = other.title;
+        }
+        if other.artist.is_some() {
+            self.artist = other.artist;
+        }
+        if other.album.is_some() {
+            self.album = other.album;
+        }
+        if other.duration_ms.is_some() {
+            self.duration_ms = other.duration_ms;
+        }
+        if other.album_art.is_some() {
+            self.album_art = other.album_art;
+        }
+        if other.artist_art.is_some() {
+            self.artist_art = other.artist_art;
+        }
+    }
+}
+
+/// Something changed about what a source is playing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SourceEvent {
+    /// A new track started: the engine switched sources, or a source moved on
+    TrackChanged {
+        /// Source description (file path, URL, ...)
+        source: Option<String>,
+        /// Tags known when the track started
+        track: TrackInfo,
+    },
+    /// Tags of the current track were read or updated
+    Tags(TrackInfo),
+    /// A live stream announced a new title (e.g. ICY `StreamTitle`)
+    StreamTitle(String),
+    /// The source kept failing and a fallback is playing instead
+    FailedOver {
+        /// Description of the failing source
+        source: Option<String>,
+        /// Description of the fallback now playing
+        fallback: Option<String>,
+    },
+    /// The failed source was reopened and is playing again
+    Recovered {
+        /// Description of the recovered source
+        source: Option<String>,
+    },
+}
+
+/// Channel carrying [`SourceEvent`]s from sources to the rest of the server
+///
+/// Cheap to clone. Sources receive a handle through
+/// [`AudioSource::set_events`](crate::server::AudioSource::set_events) and
+/// emit into it; consumers such as metadata broadcasting subscribe. The
+/// current track is kept so late subscribers can start from it.
+#[derive(Clone)]
+pub struct SourceEvents {
+    sender: broadcast::Sender<SourceEvent>,
+    current: Arc<RwLock<TrackInfo>>,
+}
+
+impl Default for SourceEvents {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SourceEvents {
+    /// Create a channel with no subscribers
+    pub fn new() -> Self {
+        Self {
+            sender: broadcast::channel(EVENT_CAPACITY).0,
+            current: Arc::new(RwLock::new(TrackInfo::default())),
+        }
+    }
+
+    /// Publish an event and update the current track
+    pub fn emit(&self, event: SourceEvent) {
+        {
+            let mut current = self.current.write();
+            match &event {
+                SourceEvent::TrackChanged { track, .. } => *current = track.clone(),
+                SourceEvent::Tags(tags) => current.merge(tags.clone()),
+                SourceEvent::StreamTitle(title) => current.title = Some(title.clone()),
+                SourceEvent::FailedOver { .. } | SourceEvent::Recovered { .. } => {}
+            }
+        }
+        log::debug!("Source event: {:?}", event);
+        // No subscribers is fine
+        let _ = self.sender.send(event);
+    }
+
+    /// Receive events emitted from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<SourceEvent> {
+        self.sender.subscribe()
+    }
+
+    /// Tags of the track currently playing
+    pub fn current(&self) -> TrackInfo {
+        self.current.read().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn title(title: &str) -> TrackInfo {
+        TrackInfo {
+            title: Some(title.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_events_update_current_track() {
+        let events = SourceEvents::new();
+        let mut rx = events.subscribe();
+
+        events.emit(SourceEvent::TrackChanged {
+            source: Some("a.flac".to_string()),
+            track: title("Intro"),
+        });
+        events.emit(SourceEvent::Tags(TrackInfo {
+            artist: Some("Band".to_string()),
+            ..Default::default()
+        }));
+        assert_eq!(events.current().title.as_deref(), Some("Intro"));
+        assert_eq!(events.current().artist.as_deref(), Some("Band"));
+
+        events.emit(SourceEvent::StreamTitle("Live".to_string()));
+        assert_eq!(events.current().title.as_deref(), Some("Live"));
+
+        // A track change replaces every tag
+        events.emit(SourceEvent::TrackChanged {
+            source: None,
+            track: TrackInfo::default(),
+        });
+        assert!(events.current().is_empty());
+
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(SourceEvent::TrackChanged { .. })
+        ));
+        assert!(matches!(rx.try_recv(), Ok(SourceEvent::Tags(_))));
+        assert_eq!(
+            rx.try_recv(),
+            Ok(SourceEvent::StreamTitle("Live".to_string()))
+        );
+    }
+}
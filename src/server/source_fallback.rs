@@ -0,0 +1,467 @@
+// ABOUTME: Source health monitoring with automatic failover to a fallback source
+// ABOUTME: Plays silence, a tone, or a backup URL while the primary source is failing
+
+use crate::audio::types::Sample;
+use crate::server::audio_source::{AudioSource, SilenceSource, TestToneSource};
+use crate::server::source_events::{SourceEvent, SourceEvents, TrackInfo};
+use crossbeam::channel::{self, Receiver, TryRecvError};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Opens a source from a URI or path, as [`open_source`](crate::server::open_source) does
+pub type SourceOpener = Arc<dyn Fn(&str) -> Result<Box<dyn AudioSource>, String> + Send + Sync>;
+
+/// Result of a source being opened by [`spawn_open`], once it is ready
+pub(crate) type Opening = Receiver<Result<Box<dyn AudioSource>, String>>;
+
+/// What plays while the primary source is failing
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fallback {
+    /// Silence
+    Silence,
+    /// A sine tone at the given frequency in Hz
+    Tone(f64),
+    /// A backup stream or file
+    Uri(String),
+}
+
+impl FromStr for Fallback {
+    type Err = String;
+
+    /// Parse `silence`, `tone`, `tone:<hz>`, or a URL or path
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "" => Err("fallback must not be empty".to_string()),
+            "silence" => Ok(Self::Silence),
+            "tone" => Ok(Self::Tone(440.0)),
+            _ => match s.strip_prefix("tone:") {
+                Some(hz) => hz
+                    .parse()
+                    .ok()
+                    .filter(|hz: &f64| *hz > 0.0)
+                    .map(Self::Tone)
+                    .ok_or_else(|| format!("invalid tone frequency '{}'", hz)),
+                None => Ok(Self::Uri(s.to_string())),
+            },
+        }
+    }
+}
+
+/// When to fail over and how often to try the primary again
+#[derive(Debug, Clone, PartialEq)]
+pub struct FallbackConfig {
+    /// What to play instead of the primary
+    pub fallback: Fallback,
+    /// How long the primary must keep failing before switching
+    pub fail_after: Duration,
+    /// How often to reopen the primary while on the fallback
+    pub retry_interval: Duration,
+}
+
+impl FallbackConfig {
+    /// Fail over to `fallback` with the default timings
+    pub fn new(fallback: Fallback) -> Self {
+        Self {
+            fallback,
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for FallbackConfig {
+    fn default() -> Self {
+        Self {
+            fallback: Fallback::Silence,
+            fail_after: Duration::from_secs(3),
+            retry_interval: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Wraps the engine's source and swaps in a fallback while it fails
+///
+/// A read fails when the source is exhausted or takes longer than the chunk
+/// it returns lasts (the engine would underrun). Once reads have failed for
+/// [`FallbackConfig::fail_after`] the fallback plays and the primary is
+/// reopened in the background every [`FallbackConfig::retry_interval`];
+/// playback switches back as soon as that succeeds. Both switches are
+/// reported as [`SourceEvent`]s.
+pub struct FallbackSource {
+    primary: Option<Box<dyn AudioSource>>,
+    fallback: Option<Box<dyn AudioSource>>,
+    config: FallbackConfig,
+    opener: SourceOpener,
+    sample_rate: u32,
+    channels: u8,
+    description: Option<String>,
+    events: Option<SourceEvents>,
+    failing_since: Option<Instant>,
+    /// Backup source being opened after failing over
+    opening_fallback: Option<Opening>,
+    /// Primary being reopened while on the fallback
+    reopening: Option<Opening>,
+    /// When the primary was last reopened (or failed)
+    last_attempt: Option<Instant>,
+}
+
+impl FallbackSource {
+    /// Monitor `primary`, reopening it (and opening backup URIs) with `opener`
+    pub fn new(
+        primary: Box<dyn AudioSource>,
+        config: FallbackConfig,
+        opener: SourceOpener,
+    ) -> Self {
+        Self {
+            sample_rate: primary.sample_rate(),
+            channels: primary.channels(),
+            description: primary.description(),
+            primary: Some(primary),
+            fallback: None,
+            config,
+            opener,
+            events: None,
+            failing_since: None,
+            opening_fallback: None,
+            reopening: None,
+            last_attempt: None,
+        }
+    }
+
+    /// Whether the fallback is playing instead of the primary
+    pub fn is_failed_over(&self) -> bool {
+        self.primary.is_none()
+    }
+
+    fn read_primary(&mut self, samples_per_channel: usize) -> Option<Vec<Sample>> {
+        let primary = self.primary.as_mut()?;
+        let started = Instant::now();
+        let chunk = primary.read_chunk(samples_per_channel);
+        let budget = Duration::from_secs_f64(samples_per_channel as f64 / self.sample_rate as f64);
+
+        if chunk.is_some() && started.elapsed() <= budget {
+            if self.failing_since.take().is_some() {
+                log::info!("Source recovered before failing over");
+            }
+            return chunk;
+        }
+
+        let since = *self.failing_since.get_or_insert(started);
+        if since.elapsed() >= self.config.fail_after {
+            let reason = if chunk.is_none() {
+                "exhausted"
+            } else {
+                "underrunning"
+            };
+            self.fail_over(reason);
+        }
+        chunk
+    }
+
+    fn fail_over(&mut self, reason: &str) {
+        self.primary = None;
+        self.failing_since = None;
+        self.last_attempt = Some(Instant::now());
+        let fallback = match &self.config.fallback {
+            Fallback::Silence => {
+                Box::new(SilenceSource::new(self.sample_rate)) as Box<dyn AudioSource>
+            }
+            Fallback::Tone(hz) => Box::new(TestToneSource::new(*hz, self.sample_rate)),
+            Fallback::Uri(uri) => {
+                // Opening a stream blocks; play silence until it is ready
+                self.opening_fallback = Some(spawn_open(self.opener.clone(), uri.clone()));
+                Box::new(SilenceSource::new(self.sample_rate))
+            }
+        };
+        log::warn!(
+            "Source {} {}, falling back to {}",
+            self.description.as_deref().unwrap_or("(unnamed)"),
+            reason,
+            fallback
+                .description()
+                .unwrap_or_else(|| "(unnamed)".to_string())
+        );
+        self.emit(SourceEvent::FailedOver {
+            source: self.description.clone(),
+            fallback: fallback_description(&self.config.fallback),
+        });
+        self.fallback = Some(fallback);
+    }
+
+    /// Pick up a backup source that finished opening
+    fn poll_fallback(&mut self) {
+        let Some(result) = self
+            .opening_fallback
+            .as_ref()
+            .and_then(|rx| rx.try_recv().ok())
+        else {
+            return;
+        };
+        self.opening_fallback = None;
+        match result {
+            Ok(source) if self.matches(source.as_ref()) => self.fallback = Some(source),
+            Ok(source) => log::warn!(
+                "Fallback source runs at {}Hz, not {}Hz; keeping silence",
+                source.sample_rate(),
+                self.sample_rate
+            ),
+            Err(e) => log::warn!("Failed to open fallback source: {}", e),
+        }
+    }
+
+    /// Start or finish reopening the primary
+    fn poll_primary(&mut self) {
+        if let Some(rx) = &self.reopening {
+            match rx.try_recv() {
+                Ok(Ok(source)) if self.matches(source.as_ref()) => {
+                    self.reopening = None;
+                    self.recover(source);
+                    return;
+                }
+                Ok(Ok(_)) => {
+                    log::warn!("Reopened source changed format; staying on fallback");
+                    self.reopening = None;
+                }
+                Ok(Err(e)) => {
+                    log::debug!("Source still unavailable: {}", e);
+                    self.reopening = None;
+                }
+                Err(TryRecvError::Empty) => return,
+                Err(TryRecvError::Disconnected) => self.reopening = None,
+            }
+        }
+
+        let Some(uri) = self.description.clone() else {
+            return;
+        };
+        let due = self
+            .last_attempt
+            .is_none_or(|at| at.elapsed() >= self.config.retry_interval);
+        if due {
+            self.last_attempt = Some(Instant::now());
+            self.reopening = Some(spawn_open(self.opener.clone(), uri));
+        }
+    }
+
+    fn recover(&mut self, mut source: Box<dyn AudioSource>) {
+        log::info!(
+            "Source {} recovered, leaving fallback",
+            self.description.as_deref().unwrap_or("(unnamed)")
+        );
+        self.fallback = None;
+        self.opening_fallback = None;
+        if let Some(events) = &self.events {
+            source.set_events(events.clone());
+        }
+        self.emit(SourceEvent::Recovered {
+            source: self.description.clone(),
+        });
+        self.primary = Some(source);
+    }
+
+    fn matches(&self, source: &dyn AudioSource) -> bool {
+        source.sample_rate() == self.sample_rate && source.channels() == self.channels
+    }
+
+    fn emit(&self, event: SourceEvent) {
+        if let Some(events) = &self.events {
+            events.emit(event);
+        }
+    }
+}
+
+impl AudioSource for FallbackSource {
+    fn read_chunk(&mut self, samples_per_channel: usize) -> Option<Vec<Sample>> {
+        if self.primary.is_some() {
+            return self.read_primary(samples_per_channel);
+        }
+
+        self.poll_primary();
+        if self.primary.is_some() {
+            return self.read_primary(samples_per_channel);
+        }
+        self.poll_fallback();
+        self.fallback.as_mut()?.read_chunk(samples_per_channel)
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> u8 {
+        self.channels
+    }
+
+    /// Never exhausted: a failed primary is replaced and retried
+    fn is_exhausted(&self) -> bool {
+        false
+    }
+
+    fn reset(&mut self) {
+        if let Some(primary) = &mut self.primary {
+            primary.reset();
+        }
+    }
+
+    fn description(&self) -> Option<String> {
+        self.description.clone()
+    }
+
+    fn track_info(&self) -> TrackInfo {
+        self.primary
+            .as_ref()
+            .map(|primary| primary.track_info())
+            .unwrap_or_default()
+    }
+
+    fn replay_gain_db(&self) -> Option<f64> {
+        self.primary.as_ref()?.replay_gain_db()
+    }
+
+    fn set_events(&mut self, events: SourceEvents) {
+        if let Some(primary) = &mut self.primary {
+            primary.set_events(events.clone());
+        }
+        self.events = Some(events);
+    }
+}
+
+/// Open a source on its own thread, since opening can block on the network
+pub(crate) fn spawn_open(opener: SourceOpener, uri: String) -> Opening {
+    let (tx, rx) = channel::bounded(1);
+    std::thread::spawn(move || {
+        let _ = tx.send(opener(&uri));
+    });
+    rx
+}
+
+fn fallback_description(fallback: &Fallback) -> Option<String> {
+    match fallback {
+        Fallback::Silence => Some("Silence".to_string()),
+        Fallback::Tone(hz) => Some(format!("Test tone {} Hz", hz)),
+        Fallback::Uri(uri) => Some(uri.clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parking_lot::Mutex;
+
+    /// Plays a fixed number of chunks of full-scale samples, then runs dry
+    struct Finite {
+        remaining: usize,
+    }
+
+    impl AudioSource for Finite {
+        fn read_chunk(&mut self, samples_per_channel: usize) -> Option<Vec<Sample>> {
+            if self.remaining == 0 {
+                return None;
+            }
+            self.remaining -= 1;
+            Some(vec![Sample::MAX; samples_per_channel * 2])
+        }
+
+        fn sample_rate(&self) -> u32 {
+            48000
+        }
+
+        fn channels(&self) -> u8 {
+            2
+        }
+
+        fn is_exhausted(&self) -> bool {
+            self.remaining == 0
+        }
+
+        fn description(&self) -> Option<String> {
+            Some("http://radio/live".to_string())
+        }
+    }
+
+    fn immediate(fallback: Fallback) -> FallbackConfig {
+        FallbackConfig {
+            fallback,
+            fail_after: Duration::ZERO,
+            retry_interval: Duration::ZERO,
+        }
+    }
+
+    #[test]
+    fn test_parse_fallback() {
+        assert_eq!("silence".parse(), Ok(Fallback::Silence));
+        assert_eq!("tone".parse(), Ok(Fallback::Tone(440.0)));
+        assert_eq!("tone:1000".parse(), Ok(Fallback::Tone(1000.0)));
+        assert_eq!(
+            "http://backup/stream".parse(),
+            Ok(Fallback::Uri("http://backup/stream".to_string()))
+        );
+        assert!("tone:loud".parse::<Fallback>().is_err());
+    }
+
+    #[test]
+    fn test_fails_over_and_recovers() {
+        let available = Arc::new(Mutex::new(false));
+        let opener: SourceOpener = {
+            let available = available.clone();
+            Arc::new(move |_| {
+                if *available.lock() {
+                    Ok(Box::new(Finite { remaining: 10 }) as Box<dyn AudioSource>)
+                } else {
+                    Err("connection refused".to_string())
+                }
+            })
+        };
+        let events = SourceEvents::new();
+        let mut rx = events.subscribe();
+        let mut source = FallbackSource::new(
+            Box::new(Finite { remaining: 1 }),
+            immediate(Fallback::Tone(440.0)),
+            opener,
+        );
+        source.set_events(events);
+
+        assert_eq!(source.read_chunk(4).unwrap()[0], Sample::MAX);
+        assert!(source.read_chunk(4).is_none());
+        assert!(source.is_failed_over());
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            SourceEvent::FailedOver {
+                source: Some("http://radio/live".to_string()),
+                fallback: Some("Test tone 440 Hz".to_string()),
+            }
+        );
+
+        // The tone plays while the primary cannot be reopened
+        assert_eq!(source.read_chunk(4).unwrap().len(), 8);
+        assert!(source.is_failed_over());
+
+        *available.lock() = true;
+        for _ in 0..200 {
+            if source.read_chunk(4).unwrap()[0] == Sample::MAX {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert!(!source.is_failed_over());
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            SourceEvent::Recovered {
+                source: Some("http://radio/live".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_brief_failure_does_not_fail_over() {
+        let opener: SourceOpener = Arc::new(|_| Err("unused".to_string()));
+        let config = FallbackConfig {
+            fail_after: Duration::from_secs(60),
+            ..Default::default()
+        };
+        let mut source = FallbackSource::new(Box::new(Finite { remaining: 0 }), config, opener);
+
+        assert!(source.read_chunk(4).is_none());
+        assert!(!source.is_failed_over());
+    }
+}
@@ -0,0 +1,309 @@
+// ABOUTME: Full server status snapshots and diffs for controller clients
+// ABOUTME: Answers _client/status requests and pushes _server/status diffs to subscribers
+
+use crate::protocol::messages::{
+    ClientStatus, GroupStatus, NowPlayingStatus, ServerStatus, StatusRequest, StreamStatus,
+};
+use crate::server::client_manager::{ClientId, ClientManager};
+use crate::server::extensions::Extensions;
+use crate::server::group::GroupManager;
+use crate::server::roles::RoleContext;
+use crate::server::source_control::SourceControl;
+use parking_lot::Mutex;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Message type of a controller's status request
+pub const STATUS_REQUEST: &str = "_client/status";
+
+/// Message type of the server's status snapshots and diffs
+pub const STATUS: &str = "_server/status";
+
+/// Server state at one point in time, keyed for diffing
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Snapshot {
+    groups: BTreeMap<String, GroupStatus>,
+    clients: BTreeMap<String, ClientStatus>,
+    now_playing: NowPlayingStatus,
+}
+
+impl Snapshot {
+    fn full(&self, revision: u64) -> ServerStatus {
+        ServerStatus {
+            revision,
+            full: true,
+            groups: self.groups.values().cloned().collect(),
+            removed_groups: Vec::new(),
+            clients: self.clients.values().cloned().collect(),
+            removed_clients: Vec::new(),
+            now_playing: Some(self.now_playing.clone()),
+        }
+    }
+
+    /// Changes from `previous` to this snapshot
+    fn diff(&self, previous: &Snapshot, revision: u64) -> ServerStatus {
+        ServerStatus {
+            revision,
+            full: false,
+            groups: changed(&previous.groups, &self.groups),
+            removed_groups: removed(&previous.groups, &self.groups),
+            clients: changed(&previous.clients, &self.clients),
+            removed_clients: removed(&previous.clients, &self.clients),
+            now_playing: (self.now_playing != previous.now_playing)
+                .then(|| self.now_playing.clone()),
+        }
+    }
+}
+
+fn changed<T: Clone + PartialEq>(
+    previous: &BTreeMap<String, T>,
+    current: &BTreeMap<String, T>,
+) -> Vec<T> {
+    current
+        .iter()
+        .filter(|(id, value)| previous.get(*id) != Some(value))
+        .map(|(_, value)| value.clone())
+        .collect()
+}
+
+fn removed<T>(previous: &BTreeMap<String, T>, current: &BTreeMap<String, T>) -> Vec<String> {
+    previous
+        .keys()
+        .filter(|id| !current.contains_key(*id))
+        .cloned()
+        .collect()
+}
+
+#[derive(Default)]
+struct PublisherState {
+    revision: u64,
+    last: Snapshot,
+    subscribers: HashSet<ClientId>,
+}
+
+/// Serves server status to controller clients over the native protocol
+///
+/// Controllers send `_client/status` and get a full `_server/status` snapshot
+/// of groups, clients, their streams and what is playing; subscribers then get
+/// a diff each time [`refresh`](Self::refresh) finds a change. Cheap to clone.
+#[derive(Clone)]
+pub struct StatusPublisher {
+    client_manager: Arc<ClientManager>,
+    group_manager: Arc<GroupManager>,
+    source_control: SourceControl,
+    state: Arc<Mutex<PublisherState>>,
+}
+
+impl StatusPublisher {
+    /// Create a publisher reading from the server's managers
+    pub fn new(
+        client_manager: Arc<ClientManager>,
+        group_manager: Arc<GroupManager>,
+        source_control: SourceControl,
+    ) -> Self {
+        Self {
+            client_manager,
+            group_manager,
+            source_control,
+            state: Arc::new(Mutex::new(PublisherState::default())),
+        }
+    }
+
+    /// Answer `_client/status` requests received through `extensions`
+    pub fn register(&self, extensions: &Extensions) {
+        let publisher = self.clone();
+        extensions.register(STATUS_REQUEST, move |ctx: &RoleContext, payload| {
+            let request: StatusRequest = serde_json::from_value(payload).unwrap_or_default();
+            publisher.handle_request(ctx.client_id, &request);
+        });
+    }
+
+    /// Send a snapshot to a controller, subscribing or unsubscribing it
+    ///
+    /// Returns false if the client is not a connected controller.
+    pub fn handle_request(&self, client_id: &str, request: &StatusRequest) -> bool {
+        if !self.client_manager.is_controller(client_id) {
+            log::debug!("Ignoring status request from non-controller {}", client_id);
+            return false;
+        }
+        // Bring subscribers up to date first so revisions stay consistent
+        self.refresh();
+        let mut state = self.state.lock();
+        let status = state.last.full(state.revision);
+        if request.subscribe {
+            state.subscribers.insert(client_id.to_string());
+        } else {
+            state.subscribers.remove(client_id);
+        }
+        drop(state);
+        self.client_manager.send_custom(client_id, STATUS, &status)
+    }
+
+    /// Check for changes and send a diff to subscribers if there are any
+    ///
+    /// Returns the new revision when something changed.
+    pub fn refresh(&self) -> Option<u64> {
+        let snapshot = self.snapshot();
+        let mut state = self.state.lock();
+        if snapshot == state.last {
+            return None;
+        }
+        state.revision += 1;
+        let diff = snapshot.diff(&state.last, state.revision);
+        state.last = snapshot;
+        let client_manager = &self.client_manager;
+        state
+            .subscribers
+            .retain(|id| client_manager.send_custom(id, STATUS, &diff));
+        Some(state.revision)
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        let mut snapshot = Snapshot::default();
+        let mut group_of = BTreeMap::new();
+        self.group_manager.for_each(|group| {
+            let mut members: Vec<String> = group.members.iter().cloned().collect();
+            members.sort();
+            for member in &members {
+                group_of.insert(member.clone(), group.id.clone());
+            }
+            snapshot.groups.insert(
+                group.id.clone(),
+                GroupStatus {
+                    group_id: group.id.clone(),
+                    name: group.name.clone(),
+                    playback_state: group.playback_state.as_str().to_string(),
+                    volume: group.volume,
+                    muted: group.muted,
+                    members,
+                    night_mode: group.night_mode.is_some(),
+                },
+            );
+        });
+        self.client_manager.for_each(|client| {
+            let stream = client
+                .audio_format
+                .as_ref()
+                .filter(|_| client.is_player())
+                .map(|format| StreamStatus {
+                    codec: format.codec.name().to_string(),
+                    sample_rate: format.sample_rate,
+                    channels: format.channels,
+                    bit_depth: format.bit_depth,
+                });
+            snapshot.clients.insert(
+                client.client_id.clone(),
+                ClientStatus {
+                    client_id: client.client_id.clone(),
+                    name: client.name.clone(),
+                    roles: client.active_roles.clone(),
+                    group_id: group_of.get(&client.client_id).cloned(),
+                    volume: client.volume,
+                    muted: client.muted,
+                    mono: self.client_manager.is_mono(&client.client_id),
+                    stream,
+                },
+            );
+        });
+        let now_playing = self.source_control.now_playing();
+        snapshot.now_playing = NowPlayingStatus {
+            source: now_playing.source,
+            title: now_playing.track.title,
+            artist: now_playing.track.artist,
+            album: now_playing.track.album,
+        };
+        snapshot
+    }
+}
+
+/// Spawn a task that sends status diffs to subscribers every `interval`
+pub fn spawn_status_publisher(
+    publisher: StatusPublisher,
+    interval: Duration,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            publisher.refresh();
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::client_manager::{ConnectedClient, ServerMessage};
+    use tokio::sync::mpsc;
+
+    fn connect(
+        manager: &ClientManager,
+        groups: &GroupManager,
+        id: &str,
+        role: &str,
+    ) -> mpsc::UnboundedReceiver<ServerMessage> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut client = ConnectedClient::new(id.to_string(), id.to_string(), tx);
+        client.active_roles = vec![role.to_string()];
+        client.audio_format = Some(ClientManager::default_audio_format());
+        manager.add_client(client);
+        groups.add_to_group(id, groups.default_group_id());
+        rx
+    }
+
+    fn received(rx: &mut mpsc::UnboundedReceiver<ServerMessage>) -> Option<ServerStatus> {
+        match rx.try_recv() {
+            Ok(ServerMessage::Text(text)) => {
+                let value: serde_json::Value = serde_json::from_str(&text).unwrap();
+                assert_eq!(value["type"], STATUS);
+                Some(serde_json::from_value(value["payload"].clone()).unwrap())
+            }
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_snapshot_then_diffs() {
+        let clients = Arc::new(ClientManager::new());
+        let groups = Arc::new(GroupManager::new());
+        let publisher = StatusPublisher::new(clients.clone(), groups.clone(), SourceControl::new());
+        let mut controller = connect(&clients, &groups, "remote", "controller@v1");
+        let _player = connect(&clients, &groups, "kitchen", "player@v1");
+
+        let request = StatusRequest { subscribe: true };
+        assert!(publisher.handle_request("remote", &request));
+        let snapshot = received(&mut controller).unwrap();
+        assert!(snapshot.full);
+        assert_eq!(snapshot.clients.len(), 2);
+        assert_eq!(snapshot.groups[0].members, ["kitchen", "remote"]);
+        let kitchen = snapshot.clients.iter().find(|c| c.client_id == "kitchen");
+        assert_eq!(kitchen.unwrap().stream.as_ref().unwrap().codec, "pcm");
+
+        // Nothing changed: no diff
+        assert_eq!(publisher.refresh(), None);
+        assert!(received(&mut controller).is_none());
+
+        clients.update_volume("kitchen", 30, false);
+        clients.remove_client("kitchen");
+        groups.remove_client("kitchen");
+        let revision = publisher.refresh().unwrap();
+        let diff = received(&mut controller).unwrap();
+        assert!(!diff.full);
+        assert_eq!(diff.revision, revision);
+        assert_eq!(diff.removed_clients, ["kitchen"]);
+        assert_eq!(diff.groups[0].members, ["remote"]);
+        assert!(diff.now_playing.is_none());
+    }
+
+    #[test]
+    fn test_only_controllers_get_status() {
+        let clients = Arc::new(ClientManager::new());
+        let groups = Arc::new(GroupManager::new());
+        let publisher = StatusPublisher::new(clients.clone(), groups.clone(), SourceControl::new());
+        let mut player = connect(&clients, &groups, "kitchen", "player@v1");
+
+        assert!(!publisher.handle_request("kitchen", &StatusRequest { subscribe: true }));
+        assert!(received(&mut player).is_none());
+    }
+}
@@ -0,0 +1,524 @@
+// ABOUTME: Independent audio streams, each played by its own audio engine
+// ABOUTME: Groups subscribe to one stream and only receive chunks generated for it
+
+use crate::server::audio_engine::{spawn_audio_engine, AudioEngine, EngineHandle, EngineState};
+use crate::server::audio_source::{open_source, open_track, AudioSource};
+use crate::server::client_manager::ClientManager;
+use crate::server::clock::ServerClock;
+use crate::server::config::ServerConfig;
+use crate::server::encoder::EncoderRegistry;
+use crate::server::encoder_metrics::EncoderMetrics;
+use crate::server::group::GroupManager;
+use crate::server::metadata::MetadataPublisher;
+use crate::server::playback::PlaybackController;
+use crate::server::source_control::{NowPlaying, SourceControl};
+use crate::server::source_fallback::SourceOpener;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::task::JoinHandle;
+
+/// Stream played by groups that were not assigned another one
+pub const DEFAULT_STREAM: &str = "default";
+
+/// Prefix of the streams giving single groups their own timeline
+///
+/// Stream IDs starting with it are reserved; see
+/// [`StreamManager::detach_group`].
+pub const GROUP_TIMELINE_PREFIX: &str = "group:";
+
+/// ID of the stream running a group's own timeline
+pub fn group_timeline_id(group_id: &str) -> String {
+    format!("{}{}", GROUP_TIMELINE_PREFIX, group_id)
+}
+
+/// An extra stream to start with the server
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StreamDefinition {
+    /// Unique stream identifier
+    pub id: String,
+    /// File path, `file://` URI, or HTTP(S) URL played by the stream
+    pub uri: String,
+}
+
+/// A running stream as reported by the control API
+#[derive(Debug, Clone, Serialize)]
+pub struct StreamInfo {
+    /// Stream identifier
+    pub stream_id: String,
+    /// What the stream's engine is playing
+    #[serde(flatten)]
+    pub now_playing: NowPlaying,
+    /// Groups subscribed to the stream, sorted
+    pub groups: Vec<String>,
+    /// Whether the stream's engine is running, paused or stopped
+    pub state: EngineState,
+}
+
+struct RunningStream {
+    source_control: SourceControl,
+    handle: JoinHandle<()>,
+    engine: EngineHandle,
+    metadata: JoinHandle<()>,
+}
+
+/// Runs one audio engine per stream
+///
+/// Cloning gives another handle to the same set of streams.
+#[derive(Clone)]
+pub struct StreamManager {
+    config: Arc<ServerConfig>,
+    client_manager: Arc<ClientManager>,
+    group_manager: Arc<GroupManager>,
+    clock: Arc<ServerClock>,
+    encoders: EncoderRegistry,
+    encoder_metrics: EncoderMetrics,
+    metadata: MetadataPublisher,
+    streams: Arc<Mutex<HashMap<String, RunningStream>>>,
+}
+
+impl StreamManager {
+    /// Create a manager with no streams running
+    pub fn new(
+        config: Arc<ServerConfig>,
+        client_manager: Arc<ClientManager>,
+        group_manager: Arc<GroupManager>,
+        clock: Arc<ServerClock>,
+        encoders: EncoderRegistry,
+        encoder_metrics: EncoderMetrics,
+    ) -> Self {
+        let metadata =
+            MetadataPublisher::new(client_manager.clone(), group_manager.clone(), clock.clone());
+        Self {
+            config,
+            client_manager,
+            group_manager,
+            clock,
+            encoders,
+            encoder_metrics,
+            metadata,
+            streams: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Build an engine for `stream_id` with the server's engine settings
+    ///
+    /// The engine reports through `source_control` and only plays to groups
+    /// subscribed to the stream.
+    pub fn engine(
+        &self,
+        stream_id: &str,
+        source: Box<dyn AudioSource>,
+        source_control: SourceControl,
+    ) -> AudioEngine {
+        let config = &self.config;
+        let mut engine = AudioEngine::new(
+            source,
+            self.client_manager.clone(),
+            self.group_manager.clone(),
+            self.clock.clone(),
+            config.chunk_interval_ms,
+            config.buffer_ahead_ms,
+        );
+        engine.set_stream(stream_id);
+        engine.set_idle_standby(config.idle_standby);
+        engine.set_source_control(source_control);
+        engine.set_encoder_metrics(self.encoder_metrics.clone());
+        engine.set_encoders(self.encoders.clone());
+        engine.set_encoder_settings(config.encoder_settings_for(None));
+        engine.set_downmix(config.downmix);
+        // Trimming wraps the primary source, so it goes on before the fallback
+        if let Some(trim) = config.silence_trim {
+            engine.set_silence_trim(trim);
+        }
+        if let Some(loudness) = config.loudness {
+            engine.set_loudness_normalization(loudness);
+        }
+        let (downmix, replay_gain) = (config.downmix, config.replay_gain);
+        let cache = config.url_cache.clone();
+        engine.set_queue_opener(Arc::new(move |uri: &str| {
+            open_track(uri, downmix, replay_gain, cache.as_ref()).map_err(|e| e.to_string())
+        }));
+        if let Some(fallback) = config.source_fallback.clone() {
+            let (downmix, replay_gain) = (config.downmix, config.replay_gain);
+            let cache = config.url_cache.clone();
+            let opener: SourceOpener = Arc::new(move |uri: &str| {
+                open_source(uri, downmix, replay_gain, cache.as_ref()).map_err(|e| e.to_string())
+            });
+            engine.set_source_fallback(fallback, opener);
+        }
+        engine
+    }
+
+    /// Run an engine built with [`engine`](Self::engine) as `stream_id`
+    ///
+    /// Metadata clients in groups on the stream are sent each track it plays.
+    pub fn start(
+        &self,
+        stream_id: &str,
+        engine: AudioEngine,
+        source_control: SourceControl,
+    ) -> Result<(), String> {
+        let mut streams = self.streams.lock();
+        if streams.contains_key(stream_id) {
+            return Err(format!("stream {} already exists", stream_id));
+        }
+        // Subscribe before the engine starts so the first track is sent
+        let metadata = self
+            .metadata
+            .clone()
+            .spawn(stream_id.to_string(), source_control.events());
+        let (handle, engine) = spawn_audio_engine(engine);
+        streams.insert(
+            stream_id.to_string(),
+            RunningStream {
+                source_control,
+                handle,
+                engine,
+                metadata,
+            },
+        );
+        log::info!("Stream {} started", stream_id);
+        Ok(())
+    }
+
+    /// Start a new stream playing `source`
+    ///
+    /// Returns the stream's source control for replacing its source later.
+    pub fn add_stream(
+        &self,
+        stream_id: &str,
+        source: Box<dyn AudioSource>,
+    ) -> Result<SourceControl, String> {
+        if stream_id.is_empty() {
+            return Err("stream ID must not be empty".to_string());
+        }
+        if stream_id.starts_with(GROUP_TIMELINE_PREFIX) {
+            return Err(format!(
+                "stream IDs starting with '{}' are reserved",
+                GROUP_TIMELINE_PREFIX
+            ));
+        }
+        self.add_stream_unchecked(stream_id, source)
+    }
+
+    fn add_stream_unchecked(
+        &self,
+        stream_id: &str,
+        source: Box<dyn AudioSource>,
+    ) -> Result<SourceControl, String> {
+        if self.streams.lock().contains_key(stream_id) {
+            return Err(format!("stream {} already exists", stream_id));
+        }
+        let source_control = SourceControl::new();
+        let engine = self.engine(stream_id, source, source_control.clone());
+        self.start(stream_id, engine, source_control.clone())?;
+        Ok(source_control)
+    }
+
+    /// Stop a stream, moving its groups back to the default stream
+    ///
+    /// The default stream cannot be removed.
+    pub fn remove_stream(&self, stream_id: &str) -> bool {
+        if stream_id == DEFAULT_STREAM {
+            return false;
+        }
+        let Some(stream) = self.streams.lock().remove(stream_id) else {
+            return false;
+        };
+        stream.engine.shutdown();
+        stream.metadata.abort();
+        for group_id in self.group_manager.groups_on_stream(stream_id) {
+            self.group_manager.set_stream(&group_id, None);
+            for member in self.group_manager.get_group_members(&group_id) {
+                self.send_metadata(&member);
+            }
+        }
+        log::info!("Stream {} removed", stream_id);
+        true
+    }
+
+    /// Give a group its own timeline, playing `source`
+    ///
+    /// Groups on one stream share its timeline: they pause, resume, and skip
+    /// together. A detached group runs a stream of its own instead, so it can
+    /// pause (holding its place in the source) and skip through its own play
+    /// queue while other groups carry on. Its players drop the audio buffered
+    /// from the shared timeline and start again on the new one. Returns the
+    /// control of the group's stream.
+    pub fn detach_group(
+        &self,
+        group_id: &str,
+        source: Box<dyn AudioSource>,
+    ) -> Result<SourceControl, String> {
+        if self.group_manager.get_group(group_id).is_none() {
+            return Err(format!("no group named '{}'", group_id));
+        }
+        let stream_id = group_timeline_id(group_id);
+        let source_control = self.add_stream_unchecked(&stream_id, source)?;
+        self.group_manager.set_stream(group_id, Some(stream_id));
+        log::info!("Group {} now runs its own timeline", group_id);
+        self.playback().restart_players(group_id);
+        for member in self.group_manager.get_group_members(group_id) {
+            self.send_metadata(&member);
+        }
+        Ok(source_control)
+    }
+
+    /// Whether a group runs its own timeline
+    pub fn is_detached(&self, group_id: &str) -> bool {
+        self.group_manager.get_stream(group_id) == Some(group_timeline_id(group_id))
+    }
+
+    /// Return a detached group to the default stream's shared timeline
+    ///
+    /// The group's own stream and play queue are dropped. Returns false if
+    /// the group does not run its own timeline.
+    pub fn rejoin_shared(&self, group_id: &str) -> bool {
+        if !self.is_detached(group_id) {
+            return false;
+        }
+        self.remove_stream(&group_timeline_id(group_id));
+        log::info!("Group {} rejoined the shared timeline", group_id);
+        self.playback().restart_players(group_id);
+        true
+    }
+
+    /// Combine two groups, as when the rooms they play in are opened up
+    ///
+    /// The members of `from` join `into` and play in sync with it on its
+    /// timeline; see [`PlaybackController::merge_groups`]. A timeline `from`
+    /// ran on its own is stopped. Returns the members that moved.
+    pub fn merge_groups(&self, from: &str, into: &str) -> Result<Vec<String>, String> {
+        for group_id in [from, into] {
+            if self.group_manager.get_group(group_id).is_none() {
+                return Err(format!("no group named '{}'", group_id));
+            }
+        }
+        if from == into {
+            return Err("a group cannot be merged into itself".to_string());
+        }
+        let detached = self.is_detached(from);
+        let moved = self.playback().merge_groups(from, into);
+        if detached {
+            self.remove_stream(&group_timeline_id(from));
+        }
+        for member in &moved {
+            self.send_metadata(member);
+        }
+        log::info!("Merged group {} into {}", from, into);
+        Ok(moved)
+    }
+
+    fn playback(&self) -> PlaybackController {
+        PlaybackController::new(self.client_manager.clone(), self.group_manager.clone())
+    }
+
+    /// Encoders the streams' engines build from, which bounds what clients can negotiate
+    pub fn encoders(&self) -> &EncoderRegistry {
+        &self.encoders
+    }
+
+    /// Whether a stream is running
+    pub fn contains(&self, stream_id: &str) -> bool {
+        self.streams.lock().contains_key(stream_id)
+    }
+
+    /// The source control of a stream, for replacing its source
+    pub fn source_control(&self, stream_id: &str) -> Option<SourceControl> {
+        Some(self.streams.lock().get(stream_id)?.source_control.clone())
+    }
+
+    /// The source control of the stream a group is subscribed to
+    pub fn source_control_for_group(&self, group_id: &str) -> Option<SourceControl> {
+        let stream_id = self.group_manager.get_stream(group_id)?;
+        self.source_control(&stream_id)
+    }
+
+    /// The handle controlling a stream's engine
+    pub fn engine_handle(&self, stream_id: &str) -> Option<EngineHandle> {
+        Some(self.streams.lock().get(stream_id)?.engine.clone())
+    }
+
+    /// The engine handle of the stream a group is subscribed to
+    pub fn engine_handle_for_group(&self, group_id: &str) -> Option<EngineHandle> {
+        let stream_id = self.group_manager.get_stream(group_id)?;
+        self.engine_handle(&stream_id)
+    }
+
+    /// Send the track of a client's stream to the client, if it has the metadata or artwork role
+    pub fn send_metadata(&self, client_id: &str) -> bool {
+        let Some(group_id) = self.group_manager.get_client_group(client_id) else {
+            return false;
+        };
+        let Some(source_control) = self.source_control_for_group(&group_id) else {
+            return false;
+        };
+        self.metadata
+            .send_to(client_id, &source_control.now_playing().track)
+    }
+
+    /// Running streams with what they play and who listens, sorted by ID
+    pub fn streams(&self) -> Vec<StreamInfo> {
+        let mut streams: Vec<StreamInfo> = self
+            .streams
+            .lock()
+            .iter()
+            .map(|(stream_id, stream)| {
+                let mut groups = self.group_manager.groups_on_stream(stream_id);
+                groups.sort();
+                StreamInfo {
+                    stream_id: stream_id.clone(),
+                    now_playing: stream.source_control.now_playing(),
+                    groups,
+                    state: stream.engine.state(),
+                }
+            })
+            .collect();
+        streams.sort_by(|a, b| a.stream_id.cmp(&b.stream_id));
+        streams
+    }
+
+    /// Stop every stream and wait for the engines to finish
+    pub async fn shutdown(&self) {
+        let streams: Vec<RunningStream> = self.streams.lock().drain().map(|(_, s)| s).collect();
+        for stream in &streams {
+            stream.engine.shutdown();
+            stream.metadata.abort();
+        }
+        for stream in streams {
+            let _ = stream.handle.await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::audio_source::TestToneSource;
+    use crate::server::client_manager::{ConnectedClient, ServerMessage};
+    use crate::server::group::PlaybackState;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    fn has_audio(rx: &mut mpsc::UnboundedReceiver<ServerMessage>) -> bool {
+        let mut audio = false;
+        while let Ok(message) = rx.try_recv() {
+            audio |= matches!(message, ServerMessage::Binary(_));
+        }
+        audio
+    }
+
+    #[tokio::test]
+    async fn test_chunks_only_reach_subscribed_groups() {
+        let clients = Arc::new(ClientManager::new());
+        let groups = Arc::new(GroupManager::new());
+        let mut receivers = HashMap::new();
+        for (client, group) in [("kitchen", "downstairs"), ("den", "upstairs")] {
+            let (tx, rx) = mpsc::unbounded_channel();
+            let mut connected = ConnectedClient::new(client.into(), client.into(), tx);
+            connected.active_roles = vec!["player@v1".to_string()];
+            clients.add_client(connected);
+            groups.create_group(group, group);
+            groups.add_to_group(client, group);
+            groups.set_playback_state(group, PlaybackState::Playing);
+            receivers.insert(client, rx);
+        }
+
+        let streams = StreamManager::new(
+            Arc::new(ServerConfig::default()),
+            clients,
+            groups.clone(),
+            Arc::new(ServerClock::new()),
+            EncoderRegistry::default(),
+            EncoderMetrics::new(),
+        );
+        streams
+            .add_stream("radio", Box::new(TestToneSource::new(440.0, 48000)))
+            .unwrap();
+        assert!(streams
+            .add_stream("radio", Box::new(TestToneSource::new(440.0, 48000)))
+            .is_err());
+        assert!(groups.set_stream("upstairs", Some("radio".to_string())));
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(has_audio(receivers.get_mut("den").unwrap()));
+        assert!(!has_audio(receivers.get_mut("kitchen").unwrap()));
+        assert_eq!(streams.streams()[0].groups, ["upstairs"]);
+
+        // Removing the stream sends its groups back to the default stream
+        assert!(streams.remove_stream("radio"));
+        assert_eq!(
+            groups.get_stream("upstairs").as_deref(),
+            Some(DEFAULT_STREAM)
+        );
+        assert!(!streams.remove_stream(DEFAULT_STREAM));
+        streams.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_detached_group_pauses_alone_and_merges_back() {
+        let clients = Arc::new(ClientManager::new());
+        let groups = Arc::new(GroupManager::new());
+        let mut receivers = HashMap::new();
+        for (client, group) in [("kitchen", "default"), ("den", "den")] {
+            let (tx, rx) = mpsc::unbounded_channel();
+            let mut connected = ConnectedClient::new(client.into(), client.into(), tx);
+            connected.active_roles = vec!["player@v1".to_string()];
+            clients.add_client(connected);
+            groups.create_group(group, group);
+            groups.add_to_group(client, group);
+            groups.set_playback_state(group, PlaybackState::Playing);
+            receivers.insert(client, rx);
+        }
+        let streams = StreamManager::new(
+            Arc::new(ServerConfig::default()),
+            clients.clone(),
+            groups.clone(),
+            Arc::new(ServerClock::new()),
+            EncoderRegistry::default(),
+            EncoderMetrics::new(),
+        );
+        let source_control = SourceControl::new();
+        let engine = streams.engine(
+            DEFAULT_STREAM,
+            Box::new(TestToneSource::new(440.0, 48000)),
+            source_control.clone(),
+        );
+        streams
+            .start(DEFAULT_STREAM, engine, source_control)
+            .unwrap();
+        assert!(streams
+            .add_stream("group:den", Box::new(TestToneSource::new(440.0, 48000)))
+            .is_err());
+
+        streams
+            .detach_group("den", Box::new(TestToneSource::new(220.0, 48000)))
+            .unwrap();
+        assert!(streams.is_detached("den"));
+        let den = receivers.get_mut("den").unwrap();
+        let restarted = std::iter::from_fn(|| den.try_recv().ok()).any(
+            |message| matches!(message, ServerMessage::Text(text) if text.contains("stream/clear")),
+        );
+        assert!(restarted);
+
+        // Pausing the detached group leaves the other one playing
+        let playback = PlaybackController::new(clients, groups.clone());
+        playback.pause("den");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        has_audio(receivers.get_mut("den").unwrap());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!has_audio(receivers.get_mut("den").unwrap()));
+        assert!(has_audio(receivers.get_mut("kitchen").unwrap()));
+
+        // Merging puts the den back on the kitchen's timeline
+        assert_eq!(streams.merge_groups("den", "default").unwrap(), ["den"]);
+        assert!(groups.get_group("den").is_none());
+        assert!(!streams.contains("group:den"));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(has_audio(receivers.get_mut("den").unwrap()));
+        assert!(streams.merge_groups("den", "default").is_err());
+        streams.shutdown().await;
+    }
+}
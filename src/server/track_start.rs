@@ -0,0 +1,320 @@
+// ABOUTME: Clean track starts: chunk-boundary alignment of play-at times and leading silence trimming
+// ABOUTME: Keeps every room starting a track at the same instant without a partial-chunk stutter
+
+use crate::audio::types::Sample;
+use crate::server::audio_source::AudioSource;
+use crate::server::source_events::{SourceEvents, TrackInfo};
+use std::time::Duration;
+
+/// Play-at base times for consecutive chunks of the playing source
+///
+/// Chunks follow each other back to back, timed by the samples they carry
+/// rather than by when the engine's ticker happened to fire. Each new track
+/// (and each restart after playback stopped) begins on a multiple of the
+/// chunk interval, so groups with the same buffer-ahead start it at exactly
+/// the same server time.
+#[derive(Debug, Clone)]
+pub struct ChunkTimeline {
+    chunk_micros: i64,
+    /// Base time of the first chunk since the last (re)start
+    anchor: Option<i64>,
+    /// Frames played since `anchor`
+    frames: u64,
+    sample_rate: u32,
+    /// The next chunk starts a new track
+    restart: bool,
+}
+
+impl ChunkTimeline {
+    /// Timeline for chunks of `chunk_interval`
+    pub fn new(chunk_interval: Duration) -> Self {
+        Self {
+            chunk_micros: (chunk_interval.as_micros() as i64).max(1),
+            anchor: None,
+            frames: 0,
+            sample_rate: 0,
+            restart: true,
+        }
+    }
+
+    /// Start the next chunk on a fresh chunk boundary, after the current track ends
+    pub fn restart(&mut self) {
+        self.restart = true;
+    }
+
+    /// Forget the timeline; the next chunk is aligned to the clock again
+    pub fn stop(&mut self) {
+        self.anchor = None;
+        self.restart = true;
+    }
+
+    /// Base time for a chunk of `frames` generated at `now`
+    ///
+    /// Re-anchors when the ticker has fallen more than a chunk behind the
+    /// timeline (after a stall, say) rather than letting the lead shrink.
+    /// Returns None while the timeline is more than two chunks ahead, so the
+    /// caller can skip a tick instead of letting the lead grow.
+    pub fn next(&mut self, now: i64, frames: usize, sample_rate: u32) -> Option<i64> {
+        let end = self.anchor.map(|anchor| anchor + self.elapsed_micros());
+        let start = match end {
+            Some(end) if end - now > 2 * self.chunk_micros => return None,
+            // A new track starts on the first boundary after the previous one
+            Some(end) if now - end <= self.chunk_micros => {
+                if self.restart || sample_rate != self.sample_rate {
+                    self.align(end)
+                } else {
+                    end
+                }
+            }
+            _ => self.align(now),
+        };
+        if end != Some(start) || self.restart || sample_rate != self.sample_rate {
+            self.anchor = Some(start);
+            self.frames = 0;
+            self.sample_rate = sample_rate;
+        }
+        self.restart = false;
+        self.frames += frames as u64;
+        Some(start)
+    }
+
+    fn elapsed_micros(&self) -> i64 {
+        if self.sample_rate == 0 {
+            return 0;
+        }
+        (self.frames * 1_000_000 / self.sample_rate as u64) as i64
+    }
+
+    /// First chunk boundary at or after `micros`
+    fn align(&self, micros: i64) -> i64 {
+        (micros + self.chunk_micros - 1).div_euclid(self.chunk_micros) * self.chunk_micros
+    }
+}
+
+/// How leading silence is trimmed from new tracks
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SilenceTrim {
+    /// Level below which audio counts as silence, in dBFS
+    pub threshold_db: f64,
+    /// Most silence trimmed from one track
+    pub max: Duration,
+}
+
+impl Default for SilenceTrim {
+    fn default() -> Self {
+        Self {
+            threshold_db: -60.0,
+            max: Duration::from_secs(2),
+        }
+    }
+}
+
+impl SilenceTrim {
+    /// Largest sample amplitude still counted as silence
+    fn threshold(&self) -> f32 {
+        Sample::MAX.0 * 10f64.powf(self.threshold_db / 20.0) as f32
+    }
+}
+
+/// Source wrapper dropping the encoder/decoder padding at the start of a track
+///
+/// Reads past leading frames quieter than the threshold, up to the
+/// configured maximum, then plays on from the first audible frame.
+/// Resetting the source trims again.
+pub struct TrimSilence {
+    inner: Box<dyn AudioSource>,
+    trim: SilenceTrim,
+    /// Frames that may still be trimmed from the current track
+    budget: Option<usize>,
+    /// Audio read past the end of the last chunk
+    carry: Vec<Sample>,
+}
+
+impl TrimSilence {
+    /// Trim leading silence from `inner`
+    pub fn new(inner: Box<dyn AudioSource>, trim: SilenceTrim) -> Self {
+        let mut source = Self {
+            inner,
+            trim,
+            budget: None,
+            carry: Vec::new(),
+        };
+        source.rearm();
+        source
+    }
+
+    fn rearm(&mut self) {
+        let frames = self.trim.max.as_micros() * self.inner.sample_rate() as u128 / 1_000_000;
+        self.budget = Some(frames as usize);
+        self.carry.clear();
+    }
+
+    /// Skip leading silence; returns false once the source is exhausted
+    fn skip_silence(&mut self, samples_per_channel: usize) -> bool {
+        let Some(mut budget) = self.budget.take() else {
+            return true;
+        };
+        let channels = self.inner.channels().max(1) as usize;
+        let threshold = self.trim.threshold();
+        while budget > 0 {
+            let Some(chunk) = self.inner.read_chunk(samples_per_channel) else {
+                return false;
+            };
+            let frames = chunk.len() / channels;
+            let quiet = chunk
+                .chunks(channels)
+                .position(|frame| frame.iter().any(|s| s.0.abs() > threshold))
+                .unwrap_or(frames);
+            let skipped = quiet.min(budget);
+            if skipped > 0 {
+                log::debug!("Trimmed {} frames of leading silence", skipped);
+            }
+            if skipped < frames {
+                self.carry = chunk[skipped * channels..].to_vec();
+                return true;
+            }
+            budget -= skipped;
+        }
+        true
+    }
+}
+
+impl AudioSource for TrimSilence {
+    fn read_chunk(&mut self, samples_per_channel: usize) -> Option<Vec<Sample>> {
+        if !self.skip_silence(samples_per_channel) {
+            return None;
+        }
+        let len = samples_per_channel * self.inner.channels().max(1) as usize;
+        let mut output = std::mem::take(&mut self.carry);
+        while output.len() < len {
+            match self.inner.read_chunk(samples_per_channel) {
+                Some(samples) => output.extend(samples),
+                None if output.is_empty() => return None,
+                None => output.resize(len, Sample::ZERO),
+            }
+        }
+        self.carry = output.split_off(len);
+        Some(output)
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn channels(&self) -> u8 {
+        self.inner.channels()
+    }
+
+    fn is_exhausted(&self) -> bool {
+        self.carry.is_empty() && self.inner.is_exhausted()
+    }
+
+    fn reset(&mut self) {
+        self.inner.reset();
+        self.rearm();
+    }
+
+    fn description(&self) -> Option<String> {
+        self.inner.description()
+    }
+
+    fn track_info(&self) -> TrackInfo {
+        self.inner.track_info()
+    }
+
+    fn replay_gain_db(&self) -> Option<f64> {
+        self.inner.replay_gain_db()
+    }
+
+    fn set_events(&mut self, events: SourceEvents) {
+        self.inner.set_events(events);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Source playing `silent` quiet frames, then a constant level
+    struct Padded {
+        silent: usize,
+    }
+
+    impl AudioSource for Padded {
+        fn read_chunk(&mut self, samples_per_channel: usize) -> Option<Vec<Sample>> {
+            let quiet = self.silent.min(samples_per_channel);
+            self.silent -= quiet;
+            let mut samples = vec![Sample(1e-6); quiet * 2];
+            samples.resize(samples_per_channel * 2, Sample(0.5));
+            Some(samples)
+        }
+
+        fn sample_rate(&self) -> u32 {
+            48000
+        }
+
+        fn channels(&self) -> u8 {
+            2
+        }
+
+        fn is_exhausted(&self) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn test_trim_leading_silence() {
+        let mut source =
+            TrimSilence::new(Box::new(Padded { silent: 1500 }), SilenceTrim::default());
+        let chunk = source.read_chunk(960).unwrap();
+        assert_eq!(chunk.len(), 1920);
+        assert!(chunk.iter().all(|s| s.0 == 0.5));
+    }
+
+    #[test]
+    fn test_trim_stops_at_max() {
+        let trim = SilenceTrim {
+            max: Duration::from_millis(10),
+            ..SilenceTrim::default()
+        };
+        let mut source = TrimSilence::new(Box::new(Padded { silent: 960 }), trim);
+        let chunk = source.read_chunk(960).unwrap();
+        // 480 of the 960 quiet frames are left
+        assert!(chunk[..960].iter().all(|s| s.0 == 1e-6));
+        assert!(chunk[960..].iter().all(|s| s.0 == 0.5));
+    }
+
+    #[test]
+    fn test_timeline_aligns_track_start() {
+        let mut timeline = ChunkTimeline::new(Duration::from_millis(20));
+        assert_eq!(timeline.next(1_003_217, 960, 48000), Some(1_020_000));
+        // Ticker jitter does not move the following chunks
+        assert_eq!(timeline.next(1_024_900, 960, 48000), Some(1_040_000));
+        assert_eq!(timeline.next(1_041_300, 960, 48000), Some(1_060_000));
+
+        // A new track starting with a half-played chunk still lands on a boundary
+        timeline.restart();
+        assert_eq!(timeline.next(1_061_000, 480, 48000), Some(1_080_000));
+        timeline.restart();
+        assert_eq!(timeline.next(1_081_000, 960, 48000), Some(1_100_000));
+    }
+
+    #[test]
+    fn test_timeline_reanchors_after_stall() {
+        let mut timeline = ChunkTimeline::new(Duration::from_millis(20));
+        assert_eq!(timeline.next(0, 960, 48000), Some(0));
+        assert_eq!(timeline.next(500_010, 960, 48000), Some(520_000));
+        timeline.stop();
+        assert_eq!(timeline.next(600_000, 960, 48000), Some(600_000));
+    }
+
+    #[test]
+    fn test_timeline_waits_when_ahead() {
+        let mut timeline = ChunkTimeline::new(Duration::from_millis(20));
+        for expected in [0, 20_000, 40_000] {
+            assert_eq!(timeline.next(0, 960, 48000), Some(expected));
+        }
+        assert_eq!(timeline.next(0, 960, 48000), None);
+        assert_eq!(timeline.next(20_000, 960, 48000), Some(60_000));
+    }
+}
@@ -0,0 +1,180 @@
+// ABOUTME: Per-connection WebSocket counters: send backlog, frame and byte rates, ping age
+// ABOUTME: Tells a slow network apart from a slow client from the server side
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// How often the server pings each connection to see that it is still answered
+pub const TRANSPORT_PING_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Shortest span frame and byte rates are averaged over
+pub const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Frames written to a connection and the time they were counted at
+#[derive(Debug, Clone, Copy)]
+struct RateSample {
+    at: Instant,
+    frames: u64,
+    bytes: u64,
+}
+
+#[derive(Debug, Default)]
+struct Rates {
+    /// Start of the window being measured
+    window: Option<RateSample>,
+    frames_per_sec: f64,
+    bytes_per_sec: f64,
+}
+
+#[derive(Debug, Default)]
+struct Pings {
+    /// When the unanswered ping was sent
+    outstanding: Option<Instant>,
+    last_pong: Option<Instant>,
+    round_trip: Option<Duration>,
+}
+
+/// WebSocket counters for one connection
+///
+/// Frames are counted when queued for the connection and again when written
+/// to its socket; the difference is the backlog the network has not taken
+/// yet. TCP acknowledgements are not visible to the server, so the age of the
+/// last answered WebSocket ping stands in for them.
+#[derive(Debug, Default)]
+pub struct TransportStats {
+    queued_frames: AtomicU64,
+    queued_bytes: AtomicU64,
+    written_frames: AtomicU64,
+    written_bytes: AtomicU64,
+    rates: Mutex<Rates>,
+    pings: Mutex<Pings>,
+}
+
+/// A connection's transport counters at one moment
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct TransportSnapshot {
+    /// Frames queued for the connection but not yet written to its socket
+    pub backlog_frames: u64,
+    /// Bytes of those frames
+    pub backlog_bytes: u64,
+    /// Frames written per second over the last window
+    pub frames_per_sec: f64,
+    /// Bytes written per second over the last window
+    pub bytes_per_sec: f64,
+    /// Milliseconds since the client last answered a ping
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_ack_age_ms: Option<u64>,
+    /// Round trip of the last answered ping in milliseconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ping_ms: Option<f64>,
+}
+
+impl TransportStats {
+    /// Count a frame of `len` bytes queued for the connection
+    pub fn queued(&self, len: usize) {
+        self.queued_frames.fetch_add(1, Ordering::Relaxed);
+        self.queued_bytes.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    /// Count a frame of `len` bytes written to the socket
+    pub fn written(&self, len: usize) {
+        self.written_frames.fetch_add(1, Ordering::Relaxed);
+        self.written_bytes.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    /// Record a ping sent at `now`
+    ///
+    /// A ping still unanswered keeps its send time, so a connection that
+    /// stops answering shows its full silence.
+    pub fn ping_sent(&self, now: Instant) {
+        self.pings.lock().outstanding.get_or_insert(now);
+    }
+
+    /// Record a pong received at `now`
+    pub fn pong_received(&self, now: Instant) {
+        let mut pings = self.pings.lock();
+        if let Some(sent) = pings.outstanding.take() {
+            pings.round_trip = Some(now.saturating_duration_since(sent));
+        }
+        pings.last_pong = Some(now);
+    }
+
+    /// The counters as of `now`
+    ///
+    /// Rates are recomputed once at least [`RATE_WINDOW`] has passed since
+    /// the previous window began, and hold their value in between.
+    pub fn snapshot(&self, now: Instant) -> TransportSnapshot {
+        let written = RateSample {
+            at: now,
+            frames: self.written_frames.load(Ordering::Relaxed),
+            bytes: self.written_bytes.load(Ordering::Relaxed),
+        };
+        let (frames_per_sec, bytes_per_sec) = {
+            let mut rates = self.rates.lock();
+            match rates.window {
+                Some(start) if now.saturating_duration_since(start.at) >= RATE_WINDOW => {
+                    let secs = now.saturating_duration_since(start.at).as_secs_f64();
+                    rates.frames_per_sec = (written.frames - start.frames) as f64 / secs;
+                    rates.bytes_per_sec = (written.bytes - start.bytes) as f64 / secs;
+                    rates.window = Some(written);
+                }
+                Some(_) => {}
+                None => rates.window = Some(written),
+            }
+            (rates.frames_per_sec, rates.bytes_per_sec)
+        };
+        let pings = self.pings.lock();
+        TransportSnapshot {
+            backlog_frames: self
+                .queued_frames
+                .load(Ordering::Relaxed)
+                .saturating_sub(written.frames),
+            backlog_bytes: self
+                .queued_bytes
+                .load(Ordering::Relaxed)
+                .saturating_sub(written.bytes),
+            frames_per_sec,
+            bytes_per_sec,
+            last_ack_age_ms: pings
+                .last_pong
+                .map(|pong| now.saturating_duration_since(pong).as_millis() as u64),
+            ping_ms: pings.round_trip.map(|rtt| rtt.as_secs_f64() * 1000.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backlog_rates_and_ping_age() {
+        let stats = TransportStats::default();
+        let start = Instant::now();
+        assert_eq!(stats.snapshot(start), TransportSnapshot::default());
+
+        for _ in 0..10 {
+            stats.queued(1_000);
+        }
+        for _ in 0..4 {
+            stats.written(1_000);
+        }
+        let half = stats.snapshot(start + Duration::from_millis(500));
+        assert_eq!((half.backlog_frames, half.backlog_bytes), (6, 6_000));
+        // Rates wait for a full window
+        assert_eq!(half.frames_per_sec, 0.0);
+
+        let later = stats.snapshot(start + Duration::from_secs(2));
+        assert_eq!(later.frames_per_sec, 2.0);
+        assert_eq!(later.bytes_per_sec, 2_000.0);
+
+        stats.ping_sent(start);
+        stats.ping_sent(start + Duration::from_secs(1));
+        stats.pong_received(start + Duration::from_millis(1_200));
+        let acked = stats.snapshot(start + Duration::from_millis(1_500));
+        assert_eq!(acked.last_ack_age_ms, Some(300));
+        assert_eq!(acked.ping_ms, Some(1_200.0));
+    }
+}
@@ -1,8 +1,17 @@
 // ABOUTME: Terminal UI for Sendspin server
 // ABOUTME: Real-time dashboard showing server stats, clients, and audio metrics
 
-use crate::server::client_manager::ClientManager;
+use crate::audio::downmix::ChannelMap;
+use crate::audio::drc::NightMode;
+use crate::server::audio_engine::{EngineHandle, EngineState};
+use crate::server::buffer_health::BufferHealth;
+use crate::server::client_manager::{ClientManager, ConnectedClient};
 use crate::server::config::ServerConfig;
+use crate::server::group::GroupManager;
+use crate::server::group_stats::StatsCollector;
+use crate::server::rtt_histogram::RttSummary;
+use crate::server::stream_manager::{StreamManager, DEFAULT_STREAM};
+use crate::server::transport_stats::TransportSnapshot;
 use crossterm::{
     event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode},
     execute,
@@ -16,10 +25,18 @@ use ratatui::{
     widgets::{Block, Borders, List, ListItem, Paragraph},
     Frame, Terminal,
 };
+use std::cell::Cell;
+use std::cmp::Ordering;
 use std::io;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
+/// RTT spread (p99 - p50) above which a client's link is highlighted
+const RTT_JITTER_WARN_MICROS: i64 = 20_000;
+
+/// Send backlog (frames) above which a client's link is highlighted
+const BACKLOG_WARN_FRAMES: u64 = 50;
+
 /// Server statistics
 pub struct ServerStats {
     /// Server start time
@@ -35,6 +52,7 @@ pub struct ServerStats {
 }
 
 impl ServerStats {
+    /// Create a new stats tracker
     pub fn new(sample_rate: u32, chunk_size_ms: u64) -> Self {
         Self {
             start_time: Instant::now(),
@@ -45,10 +63,12 @@ impl ServerStats {
         }
     }
 
+    /// Time elapsed since the server started
     pub fn uptime(&self) -> Duration {
         self.start_time.elapsed()
     }
 
+    /// Average chunks sent per second since start
     pub fn chunks_per_second(&self) -> f64 {
         let uptime_secs = self.uptime().as_secs_f64();
         if uptime_secs > 0.0 {
@@ -58,6 +78,7 @@ impl ServerStats {
         }
     }
 
+    /// Average bytes sent per second since start
     pub fn bytes_per_second(&self) -> f64 {
         let uptime_secs = self.uptime().as_secs_f64();
         if uptime_secs > 0.0 {
@@ -73,10 +94,20 @@ pub struct TuiApp {
     config: Arc<ServerConfig>,
     client_manager: Arc<ClientManager>,
     stats: Arc<parking_lot::Mutex<ServerStats>>,
+    group_stats: Option<StatsCollector>,
+    group_manager: Option<Arc<GroupManager>>,
+    streams: Option<StreamManager>,
+    client_sort: ClientSort,
+    compact: bool,
+    /// Index of the first client shown, clamped on each redraw
+    client_scroll: Cell<usize>,
+    /// Clients that fit on screen at the last redraw
+    client_page: Cell<usize>,
     should_quit: bool,
 }
 
 impl TuiApp {
+    /// Create a new TUI application
     pub fn new(
         config: Arc<ServerConfig>,
         client_manager: Arc<ClientManager>,
@@ -86,10 +117,71 @@ impl TuiApp {
             config,
             client_manager,
             stats,
+            group_stats: None,
+            group_manager: None,
+            streams: None,
+            client_sort: ClientSort::default(),
+            compact: false,
+            client_scroll: Cell::new(0),
+            client_page: Cell::new(1),
             should_quit: false,
         }
     }
 
+    /// Show a per-group statistics panel
+    pub fn with_group_stats(mut self, collector: StatsCollector) -> Self {
+        self.group_stats = Some(collector);
+        self
+    }
+
+    /// Allow toggling night mode for all groups with the `n` key
+    pub fn with_group_manager(mut self, group_manager: Arc<GroupManager>) -> Self {
+        self.group_manager = Some(group_manager);
+        self
+    }
+
+    /// Allow pausing and skipping the default stream with the `p` and `>` keys
+    pub fn with_streams(mut self, streams: StreamManager) -> Self {
+        self.streams = Some(streams);
+        self
+    }
+
+    fn default_engine(&self) -> Option<EngineHandle> {
+        self.streams.as_ref()?.engine_handle(DEFAULT_STREAM)
+    }
+
+    /// Pause the default stream's engine, or resume it if paused or stopped
+    fn toggle_pause(&self) {
+        let Some(engine) = self.default_engine() else {
+            return;
+        };
+        match engine.state() {
+            EngineState::Paused | EngineState::Stopped => engine.play(),
+            EngineState::Running | EngineState::Standby => engine.pause(),
+        };
+    }
+
+    /// Skip the default stream to its next queued track
+    fn next_track(&self) {
+        if let Some(engine) = self.default_engine() {
+            engine.next();
+        }
+    }
+
+    /// Turn night mode on for every group, or off if all groups have it
+    fn toggle_night_mode(&self) {
+        let Some(groups) = &self.group_manager else {
+            return;
+        };
+        let ids = groups.group_ids();
+        let enable = ids.iter().any(|id| groups.get_night_mode(id).is_none());
+        let night_mode = enable.then(NightMode::default);
+        for id in &ids {
+            groups.set_night_mode(id, night_mode);
+        }
+    }
+
+    /// Run the TUI event loop until the user quits
     pub fn run<B: ratatui::backend::Backend>(
         &mut self,
         terminal: &mut Terminal<B>,
@@ -103,6 +195,17 @@ impl TuiApp {
                         KeyCode::Char('q') | KeyCode::Esc => {
                             self.should_quit = true;
                         }
+                        KeyCode::Char('n') => self.toggle_night_mode(),
+                        KeyCode::Char('p') => self.toggle_pause(),
+                        KeyCode::Char('>') => self.next_track(),
+                        KeyCode::Up | KeyCode::Char('k') => self.scroll_clients(-1),
+                        KeyCode::Down | KeyCode::Char('j') => self.scroll_clients(1),
+                        KeyCode::PageUp => self.scroll_clients(-(self.client_page.get() as isize)),
+                        KeyCode::PageDown => self.scroll_clients(self.client_page.get() as isize),
+                        KeyCode::Home => self.client_scroll.set(0),
+                        KeyCode::End => self.scroll_clients(isize::MAX),
+                        KeyCode::Char('s') => self.client_sort = self.client_sort.next(),
+                        KeyCode::Char('c') => self.compact = !self.compact,
                         _ => {}
                     }
                 }
@@ -117,20 +220,35 @@ impl TuiApp {
     }
 
     fn ui(&self, f: &mut Frame) {
+        let group_stats = self
+            .group_stats
+            .as_ref()
+            .map(|c| c.group_stats())
+            .unwrap_or_default();
+        let groups_height = if group_stats.is_empty() {
+            0
+        } else {
+            group_stats.len() as u16 + 2
+        };
+
         let chunks = Layout::default()
             .direction(Direction::Vertical)
             .constraints([
-                Constraint::Length(7),  // Server info
-                Constraint::Length(7),  // Stats
-                Constraint::Min(10),    // Clients
-                Constraint::Length(3),  // Help
+                Constraint::Length(7),             // Server info
+                Constraint::Length(7),             // Stats
+                Constraint::Length(groups_height), // Groups
+                Constraint::Min(10),               // Clients
+                Constraint::Length(3),             // Help
             ])
             .split(f.area());
 
         self.render_server_info(f, chunks[0]);
         self.render_stats(f, chunks[1]);
-        self.render_clients(f, chunks[2]);
-        self.render_help(f, chunks[3]);
+        if !group_stats.is_empty() {
+            render_groups(f, chunks[2], &group_stats);
+        }
+        self.render_clients(f, chunks[3]);
+        self.render_help(f, chunks[4]);
     }
 
     fn render_server_info(&self, f: &mut Frame, area: Rect) {
@@ -150,18 +268,13 @@ impl TuiApp {
             ]),
             Line::from(vec![
                 Span::styled("Endpoint: ", Style::default().fg(Color::Cyan)),
-                Span::raw(format!(
-                    "ws://{}{}",
-                    self.config.bind_addr, self.config.ws_path
-                )),
+                Span::raw(self.config.advertised_url()),
             ]),
             Line::from(vec![
                 Span::styled("Audio: ", Style::default().fg(Color::Cyan)),
                 Span::raw(format!(
                     "{}Hz {}ch {}bit PCM",
-                    stats.sample_rate,
-                    self.config.default_channels,
-                    self.config.default_bit_depth
+                    stats.sample_rate, self.config.default_channels, self.config.default_bit_depth
                 )),
             ]),
             Line::from(vec![
@@ -219,79 +332,35 @@ impl TuiApp {
     fn render_clients(&self, f: &mut Frame, area: Rect) {
         let client_count = self.client_manager.client_count();
 
-        // Collect client data into owned strings first
-        struct ClientDisplay {
-            name: String,
-            client_id: String,
-            roles: String,
-            format_str: String,
-            volume_str: String,
+        let mut rows = Vec::new();
+        self.client_manager
+            .for_each(|client| rows.push(ClientRow::new(client)));
+        for row in &mut rows {
+            row.channel_map = self.client_manager.channel_map(&row.client_id);
         }
+        self.client_sort.sort(&mut rows);
 
-        let mut client_data = Vec::new();
-
-        self.client_manager.for_each(|client| {
-            let roles = client.active_roles.join(", ");
-            let volume_str = if client.muted {
-                format!("{}% (muted)", client.volume)
-            } else {
-                format!("{}%", client.volume)
-            };
-
-            let format_str = if let Some(ref fmt) = client.audio_format {
-                format!(
-                    "{}Hz {}ch {}bit {}",
-                    fmt.sample_rate,
-                    fmt.channels,
-                    fmt.bit_depth,
-                    match fmt.codec {
-                        crate::audio::types::Codec::Pcm => "PCM",
-                        crate::audio::types::Codec::Opus => "Opus",
-                        crate::audio::types::Codec::Flac => "FLAC",
-                        crate::audio::types::Codec::Mp3 => "MP3",
-                    }
-                )
-            } else {
-                "No format".to_string()
-            };
-
-            client_data.push(ClientDisplay {
-                name: client.name.clone(),
-                client_id: client.client_id.clone(),
-                roles,
-                format_str,
-                volume_str,
-            });
-        });
+        let alerts = rows
+            .iter()
+            .filter(|c| c.buffer_health != BufferHealth::Healthy)
+            .count();
 
-        // Now build the list items from owned data
+        // Show as many clients as fit, starting at the scroll position
+        let height = area.height.saturating_sub(2) as usize;
+        let first = self.client_scroll.get().min(rows.len().saturating_sub(1));
         let mut items = Vec::new();
-
-        for client in &client_data {
-            items.push(ListItem::new(vec![
-                Line::from(vec![
-                    Span::styled("Name: ", Style::default().fg(Color::Magenta)),
-                    Span::raw(&client.name),
-                ]),
-                Line::from(vec![
-                    Span::styled("  ID: ", Style::default().fg(Color::DarkGray)),
-                    Span::raw(&client.client_id),
-                ]),
-                Line::from(vec![
-                    Span::styled("  Roles: ", Style::default().fg(Color::DarkGray)),
-                    Span::raw(&client.roles),
-                ]),
-                Line::from(vec![
-                    Span::styled("  Format: ", Style::default().fg(Color::DarkGray)),
-                    Span::raw(&client.format_str),
-                ]),
-                Line::from(vec![
-                    Span::styled("  Volume: ", Style::default().fg(Color::DarkGray)),
-                    Span::raw(&client.volume_str),
-                ]),
-                Line::from(""),
-            ]));
+        let mut used = 0;
+        for row in rows.iter().skip(first) {
+            let lines = row.lines(self.compact);
+            if used + lines.len() > height && !items.is_empty() {
+                break;
+            }
+            used += lines.len();
+            items.push(ListItem::new(lines));
         }
+        let shown = items.len();
+        self.client_scroll.set(first);
+        self.client_page.set(shown.max(1));
 
         if items.is_empty() {
             items.push(ListItem::new(Line::from(Span::styled(
@@ -302,24 +371,70 @@ impl TuiApp {
             ))));
         }
 
+        let mut title = format!("Connected Clients ({})", client_count);
+        if shown < rows.len() {
+            title.push_str(&format!(" [{}-{}]", first + 1, first + shown));
+        }
+        title.push_str(&format!(" sort: {}", self.client_sort.label()));
+        if alerts > 0 {
+            title.push_str(&format!(" - {} buffer alert(s)", alerts));
+        }
+
         let list = List::new(items).block(
             Block::default()
-                .title(format!("Connected Clients ({})", client_count))
+                .title(title)
                 .borders(Borders::ALL)
-                .border_style(Style::default().fg(Color::Magenta)),
+                .border_style(Style::default().fg(if alerts > 0 {
+                    Color::Yellow
+                } else {
+                    Color::Magenta
+                })),
         );
 
         f.render_widget(list, area);
     }
 
+    /// Scroll the client list by `delta` clients
+    fn scroll_clients(&self, delta: isize) {
+        let last = self.client_manager.client_count().saturating_sub(1);
+        let scroll = self.client_scroll.get().saturating_add_signed(delta);
+        self.client_scroll.set(scroll.min(last));
+    }
+
     fn render_help(&self, f: &mut Frame, area: Rect) {
-        let text = Line::from(vec![
+        let mut spans = vec![
             Span::styled("Press ", Style::default().fg(Color::DarkGray)),
             Span::styled("q", Style::default().fg(Color::Yellow)),
             Span::styled(" or ", Style::default().fg(Color::DarkGray)),
             Span::styled("ESC", Style::default().fg(Color::Yellow)),
-            Span::styled(" to quit", Style::default().fg(Color::DarkGray)),
-        ]);
+            Span::styled(" to quit, ", Style::default().fg(Color::DarkGray)),
+            Span::styled("↑↓/PgUp/PgDn", Style::default().fg(Color::Yellow)),
+            Span::styled(" to scroll, ", Style::default().fg(Color::DarkGray)),
+            Span::styled("s", Style::default().fg(Color::Yellow)),
+            Span::styled(" to sort, ", Style::default().fg(Color::DarkGray)),
+            Span::styled("c", Style::default().fg(Color::Yellow)),
+            Span::styled(" for compact view", Style::default().fg(Color::DarkGray)),
+        ];
+        if self.group_manager.is_some() {
+            spans.extend([
+                Span::styled(", ", Style::default().fg(Color::DarkGray)),
+                Span::styled("n", Style::default().fg(Color::Yellow)),
+                Span::styled(
+                    " to toggle night mode",
+                    Style::default().fg(Color::DarkGray),
+                ),
+            ]);
+        }
+        if self.streams.is_some() {
+            spans.extend([
+                Span::styled(", ", Style::default().fg(Color::DarkGray)),
+                Span::styled("p", Style::default().fg(Color::Yellow)),
+                Span::styled(" to pause/play, ", Style::default().fg(Color::DarkGray)),
+                Span::styled(">", Style::default().fg(Color::Yellow)),
+                Span::styled(" for next track", Style::default().fg(Color::DarkGray)),
+            ]);
+        }
+        let text = Line::from(spans);
 
         let paragraph = Paragraph::new(text).block(
             Block::default()
@@ -331,6 +446,304 @@ impl TuiApp {
     }
 }
 
+/// Order of the client list
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum ClientSort {
+    /// Alphabetical by name
+    #[default]
+    Name,
+    /// By group, ungrouped clients last
+    Group,
+    /// Loudest first
+    Volume,
+    /// Largest absolute sync error first, clients without stats last
+    SyncError,
+}
+
+impl ClientSort {
+    fn next(self) -> Self {
+        match self {
+            ClientSort::Name => ClientSort::Group,
+            ClientSort::Group => ClientSort::Volume,
+            ClientSort::Volume => ClientSort::SyncError,
+            ClientSort::SyncError => ClientSort::Name,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            ClientSort::Name => "name",
+            ClientSort::Group => "group",
+            ClientSort::Volume => "volume",
+            ClientSort::SyncError => "sync error",
+        }
+    }
+
+    fn sort(self, rows: &mut [ClientRow]) {
+        // Name breaks ties so the order is stable between redraws
+        rows.sort_by(|a, b| {
+            let order = match self {
+                ClientSort::Name => Ordering::Equal,
+                ClientSort::Group => match (&a.group_id, &b.group_id) {
+                    (Some(x), Some(y)) => x.cmp(y),
+                    (a, b) => b.is_some().cmp(&a.is_some()),
+                },
+                ClientSort::Volume => b.volume.cmp(&a.volume),
+                ClientSort::SyncError => {
+                    let abs = |r: &ClientRow| r.sync_error_micros.map(i64::abs);
+                    abs(b).cmp(&abs(a))
+                }
+            };
+            order
+                .then_with(|| a.name.to_lowercase().cmp(&b.name.to_lowercase()))
+                .then_with(|| a.client_id.cmp(&b.client_id))
+        });
+    }
+}
+
+/// Display data for one client, copied out of the client manager
+struct ClientRow {
+    name: String,
+    client_id: String,
+    group_id: Option<String>,
+    roles: String,
+    format_str: String,
+    channel_map: ChannelMap,
+    volume: u8,
+    muted: bool,
+    sync_error_micros: Option<i64>,
+    buffer_str: Option<String>,
+    buffer_health: BufferHealth,
+    rtt: Option<RttSummary>,
+    transport: Option<TransportSnapshot>,
+}
+
+impl ClientRow {
+    fn new(client: &ConnectedClient) -> Self {
+        let format_str = if let Some(ref fmt) = client.audio_format {
+            format!(
+                "{}Hz {}ch {}bit {}",
+                fmt.sample_rate,
+                fmt.channels,
+                fmt.bit_depth,
+                match fmt.codec {
+                    crate::audio::types::Codec::Pcm => "PCM",
+                    crate::audio::types::Codec::Opus => "Opus",
+                    crate::audio::types::Codec::Flac => "FLAC",
+                    crate::audio::types::Codec::Mp3 => "MP3",
+                }
+            )
+        } else {
+            "No format".to_string()
+        };
+
+        let trend = &client.buffer_trend;
+        let buffer_str = trend.level_ms().map(|ms| match trend.slope_ms_per_sec() {
+            Some(slope) => format!("{}ms ({:+.0}ms/s)", ms, slope),
+            None => format!("{}ms", ms),
+        });
+
+        Self {
+            name: client.name.clone(),
+            client_id: client.client_id.clone(),
+            group_id: client.group_id.clone(),
+            roles: client.active_roles.join(", "),
+            format_str,
+            channel_map: ChannelMap::Stereo,
+            volume: client.volume,
+            muted: client.muted,
+            sync_error_micros: client.stats.as_ref().and_then(|s| s.sync_error_micros),
+            buffer_str,
+            buffer_health: trend.health(),
+            rtt: client.rtt_histogram.summary(),
+            transport: Some(client.transport.snapshot(Instant::now())),
+        }
+    }
+
+    fn volume_str(&self) -> String {
+        if self.muted {
+            format!("{}% (muted)", self.volume)
+        } else {
+            format!("{}%", self.volume)
+        }
+    }
+
+    fn sync_str(&self) -> String {
+        match self.sync_error_micros {
+            Some(micros) => format!("{:+.1}ms", micros as f64 / 1000.0),
+            None => "-".to_string(),
+        }
+    }
+
+    fn buffer_style(&self) -> (Color, &'static str) {
+        match self.buffer_health {
+            BufferHealth::Healthy => (Color::Green, ""),
+            BufferHealth::Draining => (Color::Yellow, "  DRAINING"),
+            BufferHealth::Low => (Color::Red, "  LOW"),
+        }
+    }
+
+    fn lines(&self, compact: bool) -> Vec<Line<'_>> {
+        let (color, alert) = self.buffer_style();
+        let label = Style::default().fg(Color::DarkGray);
+
+        if compact {
+            return vec![Line::from(vec![
+                Span::styled(
+                    format!("{:<20} ", self.name),
+                    Style::default().fg(Color::Magenta),
+                ),
+                Span::styled(
+                    format!("{:<12} ", self.group_id.as_deref().unwrap_or("-")),
+                    label,
+                ),
+                Span::raw(format!("{:>12} ", self.volume_str())),
+                Span::raw(format!("sync {:>9} ", self.sync_str())),
+                Span::styled(
+                    self.buffer_str.as_deref().unwrap_or("").to_string(),
+                    Style::default().fg(color),
+                ),
+                Span::styled(
+                    alert,
+                    Style::default().fg(color).add_modifier(Modifier::BOLD),
+                ),
+            ])];
+        }
+
+        let mut lines = vec![
+            Line::from(vec![
+                Span::styled("Name: ", Style::default().fg(Color::Magenta)),
+                Span::raw(self.name.as_str()),
+            ]),
+            Line::from(vec![
+                Span::styled("  ID: ", label),
+                Span::raw(self.client_id.as_str()),
+            ]),
+            Line::from(vec![
+                Span::styled("  Group: ", label),
+                Span::raw(self.group_id.as_deref().unwrap_or("-")),
+            ]),
+            Line::from(vec![
+                Span::styled("  Roles: ", label),
+                Span::raw(self.roles.as_str()),
+            ]),
+            Line::from(vec![
+                Span::styled("  Format: ", label),
+                Span::raw(self.format_str.as_str()),
+                Span::raw(match self.channel_map {
+                    ChannelMap::Stereo => String::new(),
+                    map => format!(" ({})", map.as_str()),
+                }),
+            ]),
+            Line::from(vec![
+                Span::styled("  Volume: ", label),
+                Span::raw(self.volume_str()),
+                Span::styled("  Sync: ", label),
+                Span::raw(self.sync_str()),
+            ]),
+        ];
+        if let Some(rtt) = self.rtt {
+            let ms = |micros: i64| format!("{:.1}ms", micros as f64 / 1000.0);
+            let color = if rtt.jitter_micros() > RTT_JITTER_WARN_MICROS {
+                Color::Yellow
+            } else {
+                Color::Reset
+            };
+            lines.push(Line::from(vec![
+                Span::styled("  RTT: ", label),
+                Span::styled(
+                    format!(
+                        "p50 {}  p90 {}  p99 {}  ({} samples)",
+                        ms(rtt.p50_micros),
+                        ms(rtt.p90_micros),
+                        ms(rtt.p99_micros),
+                        rtt.samples
+                    ),
+                    Style::default().fg(color),
+                ),
+            ]));
+        }
+        if let Some(transport) = self.transport {
+            let color = if transport.backlog_frames > BACKLOG_WARN_FRAMES {
+                Color::Yellow
+            } else {
+                Color::Reset
+            };
+            let ack = match transport.last_ack_age_ms {
+                Some(ms) => format!("  ack {:.1}s ago", ms as f64 / 1000.0),
+                None => String::new(),
+            };
+            lines.push(Line::from(vec![
+                Span::styled("  Network: ", label),
+                Span::styled(
+                    format!(
+                        "{:.0} frames/s  {:.1} KB/s  backlog {} ({:.1} KB){}",
+                        transport.frames_per_sec,
+                        transport.bytes_per_sec / 1024.0,
+                        transport.backlog_frames,
+                        transport.backlog_bytes as f64 / 1024.0,
+                        ack
+                    ),
+                    Style::default().fg(color),
+                ),
+            ]));
+        }
+        if let Some(ref buffer_str) = self.buffer_str {
+            lines.push(Line::from(vec![
+                Span::styled("  Buffer: ", label),
+                Span::styled(buffer_str.as_str(), Style::default().fg(color)),
+                Span::styled(
+                    alert,
+                    Style::default().fg(color).add_modifier(Modifier::BOLD),
+                ),
+            ]));
+        }
+        lines.push(Line::from(""));
+        lines
+    }
+}
+
+fn render_groups(f: &mut Frame, area: Rect, groups: &[crate::server::group_stats::GroupStats]) {
+    let text: Vec<Line> = groups
+        .iter()
+        .map(|g| {
+            let sync = match (g.min_sync_error_micros, g.max_sync_error_micros) {
+                (Some(min), Some(max)) => format!(
+                    "sync {:.1}..{:.1}ms (spread {:.1}ms)",
+                    min as f64 / 1000.0,
+                    max as f64 / 1000.0,
+                    (max - min) as f64 / 1000.0
+                ),
+                _ => "sync n/a".to_string(),
+            };
+            let mut spans = vec![
+                Span::styled(format!("{}: ", g.name), Style::default().fg(Color::Yellow)),
+                Span::raw(format!(
+                    "{} | {} clients | {:.1} KB/s | {} dropped | {}",
+                    g.playback_state,
+                    g.client_count,
+                    g.bytes_per_second / 1024.0,
+                    g.dropped,
+                    sync
+                )),
+            ];
+            if g.night_mode {
+                spans.push(Span::styled(" | night", Style::default().fg(Color::Blue)));
+            }
+            Line::from(spans)
+        })
+        .collect();
+
+    let paragraph = Paragraph::new(text).block(
+        Block::default()
+            .title("Groups")
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(Color::Cyan)),
+    );
+
+    f.render_widget(paragraph, area);
+}
+
 /// Setup TUI terminal
 pub fn setup_terminal() -> io::Result<Terminal<CrosstermBackend<io::Stdout>>> {
     enable_raw_mode()?;
@@ -341,9 +754,7 @@ pub fn setup_terminal() -> io::Result<Terminal<CrosstermBackend<io::Stdout>>> {
 }
 
 /// Restore terminal to normal mode
-pub fn restore_terminal(
-    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
-) -> io::Result<()> {
+pub fn restore_terminal(terminal: &mut Terminal<CrosstermBackend<io::Stdout>>) -> io::Result<()> {
     disable_raw_mode()?;
     execute!(
         terminal.backend_mut(),
@@ -353,3 +764,65 @@ pub fn restore_terminal(
     terminal.show_cursor()?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(name: &str, group: Option<&str>, volume: u8, sync: Option<i64>) -> ClientRow {
+        ClientRow {
+            name: name.to_string(),
+            client_id: name.to_lowercase(),
+            group_id: group.map(str::to_string),
+            roles: String::new(),
+            format_str: String::new(),
+            channel_map: ChannelMap::Stereo,
+            volume,
+            muted: false,
+            sync_error_micros: sync,
+            buffer_str: None,
+            buffer_health: BufferHealth::Healthy,
+            rtt: None,
+            transport: None,
+        }
+    }
+
+    fn names(sort: ClientSort, rows: &mut [ClientRow]) -> Vec<&str> {
+        sort.sort(rows);
+        rows.iter().map(|r| r.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_client_sort_orders() {
+        let mut rows = vec![
+            row("kitchen", Some("upstairs"), 40, Some(-900)),
+            row("Bedroom", None, 80, None),
+            row("attic", Some("downstairs"), 40, Some(200)),
+        ];
+        assert_eq!(
+            names(ClientSort::Name, &mut rows),
+            ["attic", "Bedroom", "kitchen"]
+        );
+        assert_eq!(
+            names(ClientSort::Group, &mut rows),
+            ["attic", "kitchen", "Bedroom"]
+        );
+        assert_eq!(
+            names(ClientSort::Volume, &mut rows),
+            ["Bedroom", "attic", "kitchen"]
+        );
+        assert_eq!(
+            names(ClientSort::SyncError, &mut rows),
+            ["kitchen", "attic", "Bedroom"]
+        );
+        assert_eq!(ClientSort::SyncError.next(), ClientSort::Name);
+    }
+
+    #[test]
+    fn test_compact_rows_are_one_line() {
+        let client = row("kitchen", None, 50, Some(1500));
+        assert_eq!(client.lines(true).len(), 1);
+        assert_eq!(client.lines(false).len(), 7);
+        assert_eq!(client.sync_str(), "+1.5ms");
+    }
+}
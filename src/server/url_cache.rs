@@ -0,0 +1,276 @@
+// ABOUTME: On-disk cache for HTTP audio sources
+// ABOUTME: Stores complete downloads keyed by URL and revalidates them with ETag/Last-Modified
+
+use crate::server::icy::ICY_METADATA_HEADER;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// What the server said about a cached download
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    url: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    content_type: Option<String>,
+}
+
+/// Result of fetching a URL through the cache
+pub(crate) enum Fetched {
+    /// A complete copy on disk
+    Cached {
+        /// Downloaded body
+        path: PathBuf,
+        /// Content type reported when it was downloaded
+        content_type: Option<String>,
+    },
+    /// A live or otherwise uncacheable response, to be streamed as-is
+    Live(Box<ureq::Response>),
+}
+
+/// Persistent cache of HTTP audio downloads
+///
+/// Only responses with a known length and no ICY (internet radio) headers are
+/// cached, so live streams keep streaming. A cached copy is revalidated with
+/// `If-None-Match` / `If-Modified-Since` before use, and served as-is when the
+/// server cannot be reached.
+#[derive(Debug, Clone)]
+pub struct UrlCache {
+    dir: PathBuf,
+    max_bytes: Option<u64>,
+}
+
+impl UrlCache {
+    /// Cache downloads in `dir`, which is created on first use
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            max_bytes: None,
+        }
+    }
+
+    /// Evict least recently used downloads beyond `max_bytes` in total
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Directory holding the cache
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn paths(&self, url: &str) -> (PathBuf, PathBuf) {
+        let key = format!("{:016x}", cache_key(url));
+        (
+            self.dir.join(format!("{}.data", key)),
+            self.dir.join(format!("{}.json", key)),
+        )
+    }
+
+    fn entry(&self, url: &str) -> Option<(PathBuf, CacheEntry)> {
+        let (data, meta) = self.paths(url);
+        let entry: CacheEntry = serde_json::from_slice(&fs::read(meta).ok()?).ok()?;
+        (entry.url == url && data.is_file()).then_some((data, entry))
+    }
+
+    /// Fetch `url`, serving or filling the cache when the response allows it
+    pub(crate) fn fetch(&self, url: &str) -> Result<Fetched, BoxError> {
+        let cached = self.entry(url);
+
+        let mut request = ureq::get(url).set(ICY_METADATA_HEADER, "1");
+        if let Some((_, entry)) = &cached {
+            if let Some(etag) = &entry.etag {
+                request = request.set("If-None-Match", etag);
+            }
+            if let Some(last_modified) = &entry.last_modified {
+                request = request.set("If-Modified-Since", last_modified);
+            }
+        }
+
+        let response = match (request.call(), cached) {
+            (Ok(response), Some((path, entry))) if response.status() == 304 => {
+                log::info!("Cache hit for {}", url);
+                return Ok(self.hit(path, entry));
+            }
+            (Ok(response), _) => response,
+            (Err(ureq::Error::Transport(e)), Some((path, entry))) => {
+                log::warn!("Cannot reach {} ({}); playing cached copy", url, e);
+                return Ok(self.hit(path, entry));
+            }
+            (Err(e), _) => return Err(format!("HTTP request failed: {}", e).into()),
+        };
+
+        if !is_cacheable(&response) {
+            return Ok(Fetched::Live(Box::new(response)));
+        }
+        self.store(url, response)
+    }
+
+    fn hit(&self, path: PathBuf, entry: CacheEntry) -> Fetched {
+        // Mark as recently used for eviction
+        if let Ok(file) = fs::File::options().write(true).open(&path) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+        Fetched::Cached {
+            path,
+            content_type: entry.content_type,
+        }
+    }
+
+    fn store(&self, url: &str, response: ureq::Response) -> Result<Fetched, BoxError> {
+        let (data, meta) = self.paths(url);
+        let entry = CacheEntry {
+            url: url.to_string(),
+            etag: response.header("etag").map(str::to_string),
+            last_modified: response.header("last-modified").map(str::to_string),
+            content_type: response.header("content-type").map(str::to_string),
+        };
+        let expected: Option<u64> = response
+            .header("content-length")
+            .and_then(|len| len.parse().ok());
+
+        fs::create_dir_all(&self.dir)?;
+        let partial = data.with_extension("part");
+        log::info!("Downloading {} into cache", url);
+        let written = io::copy(
+            &mut response.into_reader(),
+            &mut fs::File::create(&partial)?,
+        )?;
+        if expected.is_some_and(|len| len != written) {
+            let _ = fs::remove_file(&partial);
+            return Err(format!("download of {} ended early ({} bytes)", url, written).into());
+        }
+        fs::rename(&partial, &data)?;
+        fs::write(&meta, serde_json::to_vec(&entry)?)?;
+
+        if let Some(max_bytes) = self.max_bytes {
+            self.prune(max_bytes);
+        }
+        Ok(Fetched::Cached {
+            path: data,
+            content_type: entry.content_type,
+        })
+    }
+
+    /// Remove least recently used downloads until the total fits `max_bytes`
+    fn prune(&self, max_bytes: u64) {
+        let Ok(dir) = fs::read_dir(&self.dir) else {
+            return;
+        };
+        let mut files: Vec<(PathBuf, u64, SystemTime)> = dir
+            .flatten()
+            .filter(|e| e.path().extension().is_some_and(|ext| ext == "data"))
+            .filter_map(|e| {
+                let metadata = e.metadata().ok()?;
+                Some((e.path(), metadata.len(), metadata.modified().ok()?))
+            })
+            .collect();
+        files.sort_by_key(|&(_, _, modified)| std::cmp::Reverse(modified));
+
+        let mut total = 0;
+        for (path, len, _) in files {
+            total += len;
+            if total > max_bytes {
+                log::debug!("Evicting {} from URL cache", path.display());
+                let _ = fs::remove_file(&path);
+                let _ = fs::remove_file(path.with_extension("json"));
+            }
+        }
+    }
+}
+
+/// Whether a response is a finite download rather than a live stream
+fn is_cacheable(response: &ureq::Response) -> bool {
+    response.status() == 200
+        && response.header("content-length").is_some()
+        && !response
+            .headers_names()
+            .iter()
+            .any(|name| name.to_ascii_lowercase().starts_with("icy-"))
+}
+
+/// Stable 64-bit FNV-1a hash of a URL, used as the cache file name
+fn cache_key(url: &str) -> u64 {
+    url.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_cache(name: &str) -> UrlCache {
+        let dir = std::env::temp_dir().join(format!(
+            "sendspin-url-cache-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        UrlCache::new(dir)
+    }
+
+    fn seed(cache: &UrlCache, url: &str, body: &[u8]) -> PathBuf {
+        let (data, meta) = cache.paths(url);
+        fs::write(&data, body).unwrap();
+        let entry = CacheEntry {
+            url: url.to_string(),
+            etag: Some("\"v1\"".to_string()),
+            last_modified: None,
+            content_type: Some("audio/mpeg".to_string()),
+        };
+        fs::write(meta, serde_json::to_vec(&entry).unwrap()).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_cache_key_is_stable() {
+        assert_eq!(cache_key(""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(cache_key("a"), 0xaf63_dc4c_8601_ec8c);
+        assert_ne!(cache_key("http://a/1.mp3"), cache_key("http://a/2.mp3"));
+    }
+
+    #[test]
+    fn test_unreachable_server_serves_cached_copy() {
+        let cache = temp_cache("offline");
+        // Nothing listens on the discard port
+        let url = "http://127.0.0.1:9/episode.mp3";
+        let data = seed(&cache, url, b"audio");
+
+        match cache.fetch(url).unwrap() {
+            Fetched::Cached { path, content_type } => {
+                assert_eq!(path, data);
+                assert_eq!(content_type.as_deref(), Some("audio/mpeg"));
+            }
+            Fetched::Live(_) => panic!("expected cached copy"),
+        }
+        assert!(cache.fetch("http://127.0.0.1:9/other.mp3").is_err());
+        let _ = fs::remove_dir_all(cache.dir());
+    }
+
+    #[test]
+    fn test_prune_keeps_recent_downloads() {
+        let cache = temp_cache("prune");
+        let old = seed(&cache, "http://host/old.mp3", &[0; 600]);
+        let new = seed(&cache, "http://host/new.mp3", &[0; 600]);
+        let past = SystemTime::now() - std::time::Duration::from_secs(60);
+        fs::File::options()
+            .write(true)
+            .open(&old)
+            .unwrap()
+            .set_modified(past)
+            .unwrap();
+
+        cache.prune(1000);
+        assert!(!old.exists());
+        assert!(!old.with_extension("json").exists());
+        assert!(new.exists());
+        let _ = fs::remove_dir_all(cache.dir());
+    }
+}
@@ -0,0 +1,239 @@
+// ABOUTME: Per-chunk timing audit log for debugging end-to-end scheduling
+// ABOUTME: Compact binary records of when each chunk was generated, sent, received, and played
+
+use parking_lot::Mutex;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Bytes at the start of every audit log
+pub const AUDIT_MAGIC: &[u8; 8] = b"SSAUDIT1";
+
+/// Size of one encoded record: stage, play-at, and event time
+const RECORD_LEN: usize = 17;
+
+/// Point in a chunk's life that a record marks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum AuditStage {
+    /// Server read the chunk from its source
+    Generated,
+    /// Server queued the chunk to its clients
+    Sent,
+    /// Client received the chunk
+    Received,
+    /// Client handed the chunk to the audio device
+    PlayedOut,
+}
+
+impl AuditStage {
+    /// Every stage in pipeline order
+    pub const ALL: [AuditStage; 4] = [
+        AuditStage::Generated,
+        AuditStage::Sent,
+        AuditStage::Received,
+        AuditStage::PlayedOut,
+    ];
+
+    fn code(self) -> u8 {
+        match self {
+            AuditStage::Generated => 0,
+            AuditStage::Sent => 1,
+            AuditStage::Received => 2,
+            AuditStage::PlayedOut => 3,
+        }
+    }
+
+    fn from_code(code: u8) -> Option<Self> {
+        Self::ALL.get(code as usize).copied()
+    }
+
+    /// Lowercase name for reports
+    pub fn name(self) -> &'static str {
+        match self {
+            AuditStage::Generated => "generated",
+            AuditStage::Sent => "sent",
+            AuditStage::Received => "received",
+            AuditStage::PlayedOut => "played",
+        }
+    }
+}
+
+/// One timing record
+///
+/// Both times are on the server loop clock in microseconds; clients convert
+/// their local times with clock sync, so server and client logs line up on
+/// `play_at`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuditRecord {
+    /// Stage the record marks
+    pub stage: AuditStage,
+    /// The chunk's play-at timestamp, which identifies it
+    pub play_at: i64,
+    /// When the stage happened
+    pub at: i64,
+}
+
+impl AuditRecord {
+    fn encode(&self) -> [u8; RECORD_LEN] {
+        let mut bytes = [0u8; RECORD_LEN];
+        bytes[0] = self.stage.code();
+        bytes[1..9].copy_from_slice(&self.play_at.to_le_bytes());
+        bytes[9..].copy_from_slice(&self.at.to_le_bytes());
+        bytes
+    }
+
+    fn decode(bytes: &[u8; RECORD_LEN]) -> Option<Self> {
+        let (play_at, at) = bytes[1..].split_at(8);
+        Some(Self {
+            stage: AuditStage::from_code(bytes[0])?,
+            play_at: i64::from_le_bytes(play_at.try_into().ok()?),
+            at: i64::from_le_bytes(at.try_into().ok()?),
+        })
+    }
+}
+
+/// Append-only audit log file
+///
+/// Cheap to clone; clones write to the same file. Write errors are logged
+/// once and further records dropped, so auditing never disturbs playback.
+#[derive(Clone)]
+pub struct AuditLog {
+    inner: Arc<Mutex<AuditWriter>>,
+}
+
+struct AuditWriter {
+    out: Option<BufWriter<File>>,
+}
+
+impl AuditLog {
+    /// Create (or truncate) a log file
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(AUDIT_MAGIC)?;
+        Ok(Self {
+            inner: Arc::new(Mutex::new(AuditWriter { out: Some(out) })),
+        })
+    }
+
+    /// Record that a chunk reached `stage` at `at`
+    pub fn record(&self, stage: AuditStage, play_at: i64, at: i64) {
+        let mut writer = self.inner.lock();
+        let Some(out) = writer.out.as_mut() else {
+            return;
+        };
+        let record = AuditRecord { stage, play_at, at };
+        if let Err(e) = out.write_all(&record.encode()) {
+            log::warn!("Chunk audit log disabled: {}", e);
+            writer.out = None;
+        }
+    }
+
+    /// Write buffered records to disk
+    pub fn flush(&self) {
+        if let Some(out) = self.inner.lock().out.as_mut() {
+            let _ = out.flush();
+        }
+    }
+}
+
+/// Read every record from a log file
+pub fn read_audit_log(path: impl AsRef<Path>) -> io::Result<Vec<AuditRecord>> {
+    let mut input = BufReader::new(File::open(path)?);
+    let mut magic = [0u8; 8];
+    input.read_exact(&mut magic)?;
+    if &magic != AUDIT_MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a chunk audit log",
+        ));
+    }
+
+    let mut records = Vec::new();
+    let mut bytes = [0u8; RECORD_LEN];
+    loop {
+        match input.read_exact(&mut bytes) {
+            Ok(()) => records.extend(AuditRecord::decode(&bytes)),
+            // A partial trailing record is what a crash leaves behind
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(records),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Distribution of one timing interval in microseconds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IntervalStats {
+    /// Chunks measured
+    pub count: usize,
+    /// Smallest interval
+    pub min: i64,
+    /// Median interval
+    pub median: i64,
+    /// 99th percentile interval
+    pub p99: i64,
+    /// Largest interval
+    pub max: i64,
+}
+
+impl IntervalStats {
+    /// Summarize intervals, or None if there are none
+    pub fn from_values(mut values: Vec<i64>) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+        values.sort_unstable();
+        let at = |fraction: f64| values[((values.len() - 1) as f64 * fraction).round() as usize];
+        Some(Self {
+            count: values.len(),
+            min: values[0],
+            median: at(0.5),
+            p99: at(0.99),
+            max: values[values.len() - 1],
+        })
+    }
+}
+
+/// When one chunk reached each stage
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkTimeline {
+    /// The chunk's play-at timestamp
+    pub play_at: i64,
+    times: [Option<i64>; 4],
+}
+
+impl ChunkTimeline {
+    /// When the chunk reached `stage`, if it was recorded
+    pub fn at(&self, stage: AuditStage) -> Option<i64> {
+        self.times[stage.code() as usize]
+    }
+
+    /// Time from one stage to a later one
+    pub fn between(&self, from: AuditStage, to: AuditStage) -> Option<i64> {
+        Some(self.at(to)? - self.at(from)?)
+    }
+
+    /// How far ahead of its play-at time the chunk reached `stage`
+    ///
+    /// Positive values are headroom; negative ones mean the stage was late.
+    pub fn lead(&self, stage: AuditStage) -> Option<i64> {
+        Some(self.play_at - self.at(stage)?)
+    }
+}
+
+/// Join records into one timeline per chunk, ordered by play-at time
+///
+/// Records from several logs (a server's and its clients') can be merged;
+/// the first record of each stage for a chunk wins.
+pub fn chunk_timelines(records: &[AuditRecord]) -> Vec<ChunkTimeline> {
+    let mut chunks: BTreeMap<i64, [Option<i64>; 4]> = BTreeMap::new();
+    for record in records {
+        let times = chunks.entry(record.play_at).or_default();
+        times[record.stage.code() as usize].get_or_insert(record.at);
+    }
+    chunks
+        .into_iter()
+        .map(|(play_at, times)| ChunkTimeline { play_at, times })
+        .collect()
+}
@@ -1,10 +1,14 @@
 // ABOUTME: Clock synchronization implementation
-// ABOUTME: Calculates RTT and converts server loop time to local Instant
+// ABOUTME: Kalman-filters time exchanges into an offset and drift, and converts server loop time to local Instant
 
-use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use crate::sync::time::{Clock, SystemClock};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 /// Clock synchronization quality
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum SyncQuality {
     /// Good synchronization (RTT < 50ms)
     Good,
@@ -14,30 +18,138 @@ pub enum SyncQuality {
     Lost,
 }
 
+/// Samples with a higher RTT are discarded outright (network congestion)
+const MAX_RTT_MICROS: i64 = 100_000;
+
+/// Smallest measurement noise assumed for a sample, in microseconds
+const MIN_MEASUREMENT_STD_MICROS: f64 = 100.0;
+
+/// Samples needed before the outlier gate is trusted
+const SETTLING_SAMPLES: u32 = 4;
+
+/// Innovations beyond this many standard deviations are outliers
+const OUTLIER_SIGMAS: f64 = 3.0;
+
+/// Consecutive outliers that mean the clocks really moved (a step or a
+/// server restart), so the filter starts over
+const MAX_CONSECUTIVE_OUTLIERS: u32 = 5;
+
+/// Offset random walk, in µs² per second
+const OFFSET_PROCESS_NOISE: f64 = 10.0;
+
+/// Drift random walk, in ppm² per second
+const DRIFT_PROCESS_NOISE: f64 = 0.01;
+
+/// Initial drift uncertainty (crystals are typically within ±100 ppm)
+const INITIAL_DRIFT_STD_PPM: f64 = 100.0;
+
+/// Offset and drift estimate between the local Unix clock and the server loop clock
+///
+/// A two-state Kalman filter: the offset is the Unix time at which the
+/// server loop clock read zero, the drift how fast that moves (µs per second,
+/// i.e. ppm).
+#[derive(Debug, Clone, Copy)]
+struct OffsetFilter {
+    /// Offset in microseconds at `at_unix`
+    offset: f64,
+    /// Drift in ppm
+    drift: f64,
+    /// Covariance of (offset, drift)
+    p: [[f64; 2]; 2],
+    /// Local Unix time the estimate refers to
+    at_unix: i64,
+    /// Samples accepted since the filter (re)started
+    samples: u32,
+    /// Outliers rejected in a row
+    outliers: u32,
+}
+
+impl OffsetFilter {
+    fn new(offset: f64, variance: f64, at_unix: i64) -> Self {
+        Self {
+            offset,
+            drift: 0.0,
+            p: [[variance, 0.0], [0.0, INITIAL_DRIFT_STD_PPM.powi(2)]],
+            at_unix,
+            samples: 1,
+            outliers: 0,
+        }
+    }
+
+    /// Offset extrapolated to `unix`
+    fn offset_at(&self, unix: i64) -> f64 {
+        self.offset + self.drift * (unix - self.at_unix) as f64 / 1_000_000.0
+    }
+
+    /// Fold in a measured offset; returns false if it was rejected as an outlier
+    fn update(&mut self, measured: f64, variance: f64, at_unix: i64) -> bool {
+        // Predict forward to the measurement
+        let dt = ((at_unix - self.at_unix) as f64 / 1_000_000.0).max(0.0);
+        let [[p00, p01], [p10, p11]] = self.p;
+        let predicted = self.offset + self.drift * dt;
+        let p00 = p00 + dt * (p10 + p01) + dt * dt * p11 + OFFSET_PROCESS_NOISE * dt;
+        let p01 = p01 + dt * p11;
+        let p10 = p10 + dt * p11;
+        let p11 = p11 + DRIFT_PROCESS_NOISE * dt;
+
+        let innovation = measured - predicted;
+        let innovation_variance = p00 + variance;
+        if self.samples >= SETTLING_SAMPLES
+            && innovation.powi(2) > OUTLIER_SIGMAS.powi(2) * innovation_variance
+        {
+            self.outliers += 1;
+            return false;
+        }
+
+        let k0 = p00 / innovation_variance;
+        let k1 = p10 / innovation_variance;
+        self.offset = predicted + k0 * innovation;
+        self.drift += k1 * innovation;
+        self.p = [
+            [(1.0 - k0) * p00, (1.0 - k0) * p01],
+            [p10 - k1 * p00, p11 - k1 * p01],
+        ];
+        self.at_unix = at_unix;
+        self.samples += 1;
+        self.outliers = 0;
+        true
+    }
+}
+
 /// Clock synchronization state
+///
+/// Filters `client/time`–`server/time` exchanges into a smoothed offset and
+/// drift between the local Unix clock and the server loop clock. Each
+/// exchange is weighted by its round-trip time (a sample's error is bounded
+/// by half its RTT), and samples far from the prediction are rejected.
 #[derive(Debug)]
 pub struct ClockSync {
     /// Last known RTT in microseconds
     rtt_micros: Option<i64>,
 
-    /// When server loop started in Unix time (microseconds)
-    server_loop_start_unix: Option<i64>,
+    /// Offset and drift estimate, once a sample has been accepted
+    filter: Option<OffsetFilter>,
 
-    /// When we computed this (for staleness detection)
+    /// When we last accepted a sample (for staleness detection)
     last_update: Option<Instant>,
 
-    /// Whether we've successfully synced once
-    synced: bool,
+    /// Source of local time
+    clock: Arc<dyn Clock>,
 }
 
 impl ClockSync {
     /// Create a new clock synchronization instance
     pub fn new() -> Self {
+        Self::with_clock(SystemClock::shared())
+    }
+
+    /// Create a clock synchronization instance reading time from `clock`
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
         Self {
             rtt_micros: None,
-            server_loop_start_unix: None,
+            filter: None,
             last_update: None,
-            synced: false,
+            clock,
         }
     }
 
@@ -51,33 +163,38 @@ impl ClockSync {
         let rtt = (t4 - t1) - (t3 - t2);
         self.rtt_micros = Some(rtt);
 
-        // Discard samples with high RTT (network congestion)
-        if rtt > 100_000 {
-            // 100ms
-            eprintln!("Discarding sync sample: high RTT {}µs", rtt);
+        if !(0..=MAX_RTT_MICROS).contains(&rtt) {
+            log::debug!("Discarding sync sample: RTT {}µs", rtt);
             return;
         }
 
-        // On first successful sync, compute when the server loop started in Unix µs
-        // Per Go reference: ONLY calculate this once, never update it again!
-        // The server loop started at a specific moment in time - that never changes.
-        if !self.synced {
-            let now_unix = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_micros() as i64;
-
-            self.server_loop_start_unix = Some(now_unix - t2);
-            self.synced = true;
-
-            eprintln!(
-                "Clock sync established: t1={}, t2={}, t3={}, t4={}, rtt={}µs, now_unix={}, serverLoopStart={}",
-                t1, t2, t3, t4, rtt, now_unix,
-                self.server_loop_start_unix.unwrap()
-            );
+        // NTP offset: Unix time at which the server loop clock read zero
+        let measured = ((t1 - t2) + (t4 - t3)) as f64 / 2.0;
+        let variance = (rtt as f64 / 2.0).max(MIN_MEASUREMENT_STD_MICROS).powi(2);
+
+        match self.filter.as_mut() {
+            None => {
+                self.filter = Some(OffsetFilter::new(measured, variance, t4));
+                log::debug!(
+                    "Clock sync established: rtt={}µs, serverLoopStart={:.0}",
+                    rtt,
+                    measured
+                );
+            }
+            Some(filter) => {
+                if !filter.update(measured, variance, t4) {
+                    let residual = measured - filter.offset_at(t4);
+                    if filter.outliers < MAX_CONSECUTIVE_OUTLIERS {
+                        log::debug!("Rejecting sync sample {:.0}µs off the estimate", residual);
+                        return;
+                    }
+                    log::info!("Clock moved by {:.0}µs, resynchronizing", residual);
+                    *filter = OffsetFilter::new(measured, variance, t4);
+                }
+            }
         }
 
-        self.last_update = Some(Instant::now());
+        self.last_update = Some(self.clock.now());
     }
 
     /// Get current RTT in microseconds
@@ -85,20 +202,33 @@ impl ClockSync {
         self.rtt_micros
     }
 
+    /// Estimated offset in microseconds: the local Unix time at which the
+    /// server loop clock read zero
+    pub fn offset_micros(&self) -> Option<i64> {
+        let filter = self.filter.as_ref()?;
+        Some(filter.offset_at(self.clock.unix_micros()).round() as i64)
+    }
+
+    /// Estimated drift of the server clock against the local one, in ppm
+    pub fn drift_ppm(&self) -> Option<f64> {
+        self.filter.as_ref().map(|f| f.drift)
+    }
+
+    /// Standard deviation of the offset estimate in microseconds
+    pub fn offset_std_micros(&self) -> Option<f64> {
+        self.filter.as_ref().map(|f| f.p[0][0].max(0.0).sqrt())
+    }
+
     /// Convert server loop microseconds to local Instant
     pub fn server_to_local_instant(&self, server_micros: i64) -> Option<Instant> {
-        let server_start = self.server_loop_start_unix?;
+        let server_start = self.offset_micros()?;
 
         // Convert to Unix microseconds
         let unix_micros = server_start + server_micros;
 
         // Convert to Instant
-        let now_unix = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .ok()?
-            .as_micros() as i64;
-
-        let now_instant = Instant::now();
+        let now_unix = self.clock.unix_micros();
+        let now_instant = self.clock.now();
 
         let delta_micros = unix_micros - now_unix;
 
@@ -109,6 +239,12 @@ impl ClockSync {
         }
     }
 
+    /// Current time on the server loop clock in microseconds
+    pub fn server_now_micros(&self) -> Option<i64> {
+        let server_start = self.offset_micros()?;
+        Some(self.clock.unix_micros() - server_start)
+    }
+
     /// Get sync quality based on RTT
     pub fn quality(&self) -> SyncQuality {
         match self.rtt_micros {
@@ -121,7 +257,7 @@ impl ClockSync {
     /// Check if sync is stale (>5 seconds old)
     pub fn is_stale(&self) -> bool {
         match self.last_update {
-            Some(last) => last.elapsed() > Duration::from_secs(5),
+            Some(last) => self.clock.now().duration_since(last) > Duration::from_secs(5),
             None => true,
         }
     }
@@ -1,7 +1,13 @@
 // ABOUTME: Clock synchronization for Sendspin protocol
 // ABOUTME: NTP-style round-trip time calculation and server timestamp conversion
 
+/// Per-chunk timing audit log
+pub mod audit;
 /// Clock synchronization implementation
 pub mod clock;
+/// Time sources, including a controllable clock for tests
+pub mod time;
 
+pub use audit::{AuditLog, AuditRecord, AuditStage};
 pub use clock::{ClockSync, SyncQuality};
+pub use time::{Clock, ManualClock, SystemClock};
@@ -0,0 +1,109 @@
+// ABOUTME: Time source abstraction for timing-sensitive code
+// ABOUTME: System clock for production and a manually advanced clock for tests
+
+use parking_lot::Mutex;
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Source of monotonic and wall-clock time
+///
+/// `ServerClock`, `ClockSync` and `AudioScheduler` read time through this
+/// trait so tests can drive them with [`ManualClock`] instead of sleeping.
+pub trait Clock: Debug + Send + Sync {
+    /// Current monotonic instant
+    fn now(&self) -> Instant;
+
+    /// Current Unix time in microseconds
+    fn unix_micros(&self) -> i64;
+}
+
+/// The real system clock
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl SystemClock {
+    /// Shared handle to the system clock
+    pub fn shared() -> Arc<dyn Clock> {
+        Arc::new(SystemClock)
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn unix_micros(&self) -> i64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_micros() as i64)
+    }
+}
+
+/// A clock that only moves when told to
+///
+/// Both readings start from the moment the clock is created and advance
+/// together. Clones share the same time.
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    inner: Arc<Mutex<ManualTime>>,
+}
+
+#[derive(Debug)]
+struct ManualTime {
+    instant: Instant,
+    unix_micros: i64,
+}
+
+impl ManualClock {
+    /// Create a clock frozen at the current time
+    pub fn new() -> Self {
+        Self::at_unix_micros(SystemClock.unix_micros())
+    }
+
+    /// Create a clock frozen at the given Unix time
+    pub fn at_unix_micros(unix_micros: i64) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(ManualTime {
+                instant: Instant::now(),
+                unix_micros,
+            })),
+        }
+    }
+
+    /// Move time forward
+    pub fn advance(&self, by: Duration) {
+        let mut time = self.inner.lock();
+        time.instant += by;
+        time.unix_micros += by.as_micros() as i64;
+    }
+
+    /// Move the wall clock by `micros` without touching monotonic time
+    ///
+    /// Simulates an NTP step or a manual clock change on the host.
+    pub fn step_unix(&self, micros: i64) {
+        self.inner.lock().unix_micros += micros;
+    }
+
+    /// Shared handle for passing to the types that take a clock
+    pub fn shared(&self) -> Arc<dyn Clock> {
+        Arc::new(self.clone())
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.inner.lock().instant
+    }
+
+    fn unix_micros(&self) -> i64 {
+        self.inner.lock().unix_micros
+    }
+}
@@ -1,4 +1,5 @@
-use sendspin::audio::output::{AudioOutput, CpalOutput};
+use cpal::{SampleFormat, SampleRate, SupportedBufferSize, SupportedStreamConfigRange};
+use sendspin::audio::output::{negotiate_sample_format, AudioOutput, CpalOutput};
 use sendspin::audio::{AudioFormat, Codec, Sample};
 use std::sync::Arc;
 
@@ -51,3 +52,54 @@ fn test_audio_output_write() {
     }
     assert!(result.is_ok());
 }
+
+fn range(channels: u16, min: u32, max: u32, format: SampleFormat) -> SupportedStreamConfigRange {
+    SupportedStreamConfigRange::new(
+        channels,
+        SampleRate(min),
+        SampleRate(max),
+        SupportedBufferSize::Unknown,
+        format,
+    )
+}
+
+#[test]
+fn test_negotiate_sample_format() {
+    let format = AudioFormat {
+        codec: Codec::Pcm,
+        sample_rate: 48000,
+        channels: 2,
+        bit_depth: 24,
+        codec_header: None,
+    };
+
+    // A USB DAC offering only integer formats
+    let dac = [
+        range(2, 44100, 96000, SampleFormat::I16),
+        range(2, 44100, 96000, SampleFormat::I32),
+    ];
+    assert_eq!(
+        negotiate_sample_format(&dac, &format),
+        Some(SampleFormat::I32)
+    );
+
+    // Float is preferred only where it fits the stream
+    let mixed = [
+        range(8, 48000, 48000, SampleFormat::F32),
+        range(2, 48000, 48000, SampleFormat::U16),
+    ];
+    assert_eq!(
+        negotiate_sample_format(&mixed, &format),
+        Some(SampleFormat::U16)
+    );
+
+    // Nothing matches the stream: fall back to any convertible format
+    let mono = [range(1, 8000, 16000, SampleFormat::I16)];
+    assert_eq!(
+        negotiate_sample_format(&mono, &format),
+        Some(SampleFormat::I16)
+    );
+
+    let unsupported = [range(2, 48000, 48000, SampleFormat::U8)];
+    assert_eq!(negotiate_sample_format(&unsupported, &format), None);
+}
@@ -10,18 +10,37 @@ fn test_sample_from_i16() {
 fn test_sample_from_i24() {
     let bytes = [0x00, 0x10, 0x00]; // 4096 in 24-bit little-endian
     let sample = Sample::from_i24_le(bytes);
-    assert_eq!(sample.0, 4096);
+    assert_eq!(sample.to_i24(), 4096);
+    assert_eq!(sample, Sample::from_i16(16));
 }
 
 #[test]
 fn test_sample_clamp() {
-    let over_max = Sample(10_000_000);
+    let over_max = Sample(2.0);
     assert_eq!(over_max.clamp().0, Sample::MAX.0);
 
-    let under_min = Sample(-10_000_000);
+    let under_min = Sample(-2.0);
     assert_eq!(under_min.clamp().0, Sample::MIN.0);
 }
 
+#[test]
+fn test_sample_output_conversions() {
+    assert_eq!(Sample::ZERO.to_u16(), 32_768);
+    assert_eq!(Sample::MAX.to_u16(), u16::MAX);
+    assert_eq!(Sample::MIN.to_u16(), 0);
+
+    assert_eq!(Sample::MAX.to_i32(), i32::MAX);
+    assert_eq!(Sample::MIN.to_i32(), i32::MIN);
+    assert_eq!(Sample(2.0).to_i32(), i32::MAX);
+    assert_eq!(Sample(2.0).to_i24(), 0x7F_FFFF);
+    assert_eq!(Sample(-0.5).to_i16(), -16_384);
+    assert_eq!(Sample(0.5).quantize(8), 64);
+    assert_eq!(Sample::MAX.quantize(20), 0x7_FFFF);
+
+    assert_eq!(Sample::MAX.to_f32(), 1.0);
+    assert_eq!(Sample::ZERO.to_f32(), 0.0);
+}
+
 #[test]
 fn test_audio_format_creation() {
     let format = AudioFormat {
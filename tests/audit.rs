@@ -0,0 +1,83 @@
+// ABOUTME: Tests for the per-chunk timing audit log
+// ABOUTME: Covers the binary round trip, merging server and client logs, and interval statistics
+
+use sendspin::sync::audit::{chunk_timelines, read_audit_log, IntervalStats};
+use sendspin::sync::{AuditLog, AuditStage};
+
+fn temp_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("sendspin-{}-{}.audit", name, std::process::id()))
+}
+
+#[test]
+fn test_audit_log_roundtrip_and_merge() {
+    let server_path = temp_path("server");
+    let client_path = temp_path("client");
+
+    let server = AuditLog::create(&server_path).unwrap();
+    for i in 0..3 {
+        let play_at = 1_000_000 + i * 20_000;
+        server.record(AuditStage::Generated, play_at, play_at - 500_000);
+        server.record(AuditStage::Sent, play_at, play_at - 499_000);
+    }
+    server.flush();
+
+    let client = AuditLog::create(&client_path).unwrap();
+    client.record(AuditStage::Received, 1_000_000, 502_000);
+    client.record(AuditStage::PlayedOut, 1_000_000, 1_000_300);
+    client.flush();
+
+    let mut records = read_audit_log(&server_path).unwrap();
+    assert_eq!(records.len(), 6);
+    assert_eq!(records[1].stage, AuditStage::Sent);
+    assert_eq!(records[1].at, 501_000);
+    records.extend(read_audit_log(&client_path).unwrap());
+
+    let chunks = chunk_timelines(&records);
+    assert_eq!(chunks.len(), 3);
+    let first = &chunks[0];
+    assert_eq!(first.play_at, 1_000_000);
+    assert_eq!(
+        first.between(AuditStage::Generated, AuditStage::Sent),
+        Some(1_000)
+    );
+    assert_eq!(
+        first.between(AuditStage::Sent, AuditStage::Received),
+        Some(1_000)
+    );
+    assert_eq!(first.lead(AuditStage::Received), Some(498_000));
+    // Played 300µs late
+    assert_eq!(first.lead(AuditStage::PlayedOut), Some(-300));
+    assert_eq!(chunks[1].at(AuditStage::Received), None);
+
+    std::fs::remove_file(server_path).unwrap();
+    std::fs::remove_file(client_path).unwrap();
+}
+
+#[test]
+fn test_audit_log_tolerates_truncated_record() {
+    let path = temp_path("truncated");
+    let log = AuditLog::create(&path).unwrap();
+    log.record(AuditStage::Generated, 10, 5);
+    log.record(AuditStage::Sent, 10, 6);
+    log.flush();
+    drop(log);
+
+    let len = std::fs::metadata(&path).unwrap().len();
+    let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+    file.set_len(len - 4).unwrap();
+
+    let records = read_audit_log(&path).unwrap();
+    assert_eq!(records.len(), 1);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_interval_stats() {
+    assert_eq!(IntervalStats::from_values(Vec::new()), None);
+    let stats = IntervalStats::from_values((1..=100).rev().collect()).unwrap();
+    assert_eq!(stats.count, 100);
+    assert_eq!(stats.min, 1);
+    assert_eq!(stats.median, 51);
+    assert_eq!(stats.p99, 99);
+    assert_eq!(stats.max, 100);
+}
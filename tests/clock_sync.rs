@@ -1,5 +1,5 @@
-use sendspin::sync::ClockSync;
-use std::time::{Duration, Instant};
+use sendspin::sync::{Clock, ClockSync, ManualClock};
+use std::time::Duration;
 
 #[test]
 fn test_clock_sync_rtt_calculation() {
@@ -28,8 +28,8 @@ fn test_server_to_local_conversion() {
 
     sync.update(t1, t2, t3, t4);
 
-    // Server loop start = t4 - t3 = 1_000_050 - 500_010 = 500_040 Unix µs
-    // Converting server time 520_000 should give us ~520_040 Unix µs
+    // Server loop start = ((t1 - t2) + (t4 - t3)) / 2 = 500_020 Unix µs
+    assert_eq!(sync.offset_micros(), Some(500_020));
     let local = sync.server_to_local_instant(520_000);
     assert!(local.is_some());
 }
@@ -46,3 +46,124 @@ fn test_sync_quality() {
     sync.update(2_000_000, 600_000, 600_010, 2_075_010);
     assert_eq!(sync.quality(), sendspin::sync::SyncQuality::Degraded);
 }
+
+#[test]
+fn test_server_to_local_with_manual_clock() {
+    let clock = ManualClock::at_unix_micros(10_000_000);
+    let mut sync = ClockSync::with_clock(clock.shared());
+
+    // Server loop started 8s after the Unix epoch of the client, 10µs each way
+    sync.update(9_999_970, 1_999_980, 1_999_990, 10_000_000);
+    assert_eq!(sync.server_now_micros(), Some(2_000_000));
+
+    let local = sync.server_to_local_instant(2_050_000).unwrap();
+    assert_eq!(local - clock.now(), Duration::from_millis(50));
+
+    clock.advance(Duration::from_millis(30));
+    assert_eq!(sync.server_now_micros(), Some(2_030_000));
+    let local = sync.server_to_local_instant(2_050_000).unwrap();
+    assert_eq!(local - clock.now(), Duration::from_millis(20));
+}
+
+#[test]
+fn test_sync_goes_stale() {
+    let clock = ManualClock::new();
+    let mut sync = ClockSync::with_clock(clock.shared());
+    assert!(sync.is_stale());
+
+    sync.update(1_000_000, 500_000, 500_010, 1_000_040);
+    assert!(!sync.is_stale());
+
+    clock.advance(Duration::from_secs(5));
+    assert!(!sync.is_stale());
+    clock.advance(Duration::from_millis(1));
+    assert!(sync.is_stale());
+}
+
+/// One exchange with a server whose loop clock started at `offset` (Unix µs)
+/// and runs `drift_ppm` fast, with the given one-way delays, then a 2s pause
+fn exchange(
+    sync: &mut ClockSync,
+    clock: &ManualClock,
+    offset: i64,
+    drift_ppm: f64,
+    (up, down): (i64, i64),
+) {
+    let server = |unix: i64| {
+        let elapsed = unix - offset;
+        elapsed + (elapsed as f64 * drift_ppm / 1_000_000.0) as i64
+    };
+    let t1 = clock.unix_micros();
+    let t2 = server(t1 + up);
+    let t3 = t2 + 20;
+    let t4 = t1 + up + 20 + down;
+    clock.advance(Duration::from_micros((t4 - t1) as u64));
+    sync.update(t1, t2, t3, t4);
+    clock.advance(Duration::from_secs(2));
+}
+
+#[test]
+fn test_filter_smooths_jitter_and_rejects_outliers() {
+    let clock = ManualClock::at_unix_micros(2_000_000_000);
+    let mut sync = ClockSync::with_clock(clock.shared());
+    let offset = 1_000_000_000;
+    // Deterministic jitter in the one-way delays
+    let delays = [
+        (900, 1100),
+        (1500, 700),
+        (800, 800),
+        (1200, 1300),
+        (600, 1000),
+    ];
+    for i in 0..40 {
+        exchange(&mut sync, &clock, offset, 0.0, delays[i % delays.len()]);
+    }
+    let estimate = sync.offset_micros().unwrap();
+    assert!((estimate - offset).abs() < 200, "offset {}", estimate);
+
+    // A sample delayed 40ms on the way there only is rejected
+    exchange(&mut sync, &clock, offset, 0.0, (40_000, 500));
+    assert_eq!(sync.rtt_micros(), Some(40_500));
+    let estimate = sync.offset_micros().unwrap();
+    assert!((estimate - offset).abs() < 200, "offset {}", estimate);
+}
+
+#[test]
+fn test_filter_tracks_drift() {
+    let clock = ManualClock::at_unix_micros(5_000_000_000);
+    let mut sync = ClockSync::with_clock(clock.shared());
+    for _ in 0..60 {
+        exchange(&mut sync, &clock, 0, 50.0, (1_000, 1_000));
+    }
+    let drift = sync.drift_ppm().unwrap();
+    // The server clock runs fast, so the offset shrinks
+    assert!((drift + 50.0).abs() < 5.0, "drift {}", drift);
+    assert!(sync.offset_std_micros().unwrap() < 500.0);
+
+    // Conversions extrapolate the drift between exchanges
+    let expected = clock.unix_micros() + (clock.unix_micros() as f64 * 50e-6) as i64;
+    let now = sync.server_now_micros().unwrap();
+    assert!(
+        (now - expected).abs() < 500,
+        "server now {} vs {}",
+        now,
+        expected
+    );
+}
+
+#[test]
+fn test_filter_follows_clock_step() {
+    let clock = ManualClock::at_unix_micros(1_000_000_000);
+    let mut sync = ClockSync::with_clock(clock.shared());
+    for _ in 0..10 {
+        exchange(&mut sync, &clock, 0, 0.0, (1_000, 1_000));
+    }
+    assert_eq!(sync.offset_micros(), Some(0));
+
+    // The server restarts a second later; a few samples look like outliers first
+    for _ in 0..10 {
+        exchange(&mut sync, &clock, 1_000_000, 0.0, (1_000, 1_000));
+    }
+    let estimate = sync.offset_micros().unwrap();
+    assert!((estimate - 1_000_000).abs() < 100, "offset {}", estimate);
+}
@@ -0,0 +1,126 @@
+use sendspin::protocol::binary::BinaryFrame;
+use sendspin::protocol::messages::Message;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Files in a fixture directory with the given extension, sorted
+fn fixtures(dir: &str, extension: &str) -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/compat")
+        .join(dir);
+    let mut files: Vec<PathBuf> = fs::read_dir(&dir)
+        .unwrap_or_else(|e| panic!("cannot read {}: {}", dir.display(), e))
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == extension))
+        .collect();
+    files.sort();
+    assert!(!files.is_empty(), "no fixtures in {}", dir.display());
+    files
+}
+
+fn name(path: &Path) -> String {
+    path.file_name().unwrap().to_string_lossy().into_owned()
+}
+
+fn parse_message(path: &Path) -> (String, Message) {
+    let text = fs::read_to_string(path).unwrap();
+    let text = text.trim().to_string();
+    let message = serde_json::from_str(&text)
+        .unwrap_or_else(|e| panic!("{} does not parse: {}", name(path), e));
+    (text, message)
+}
+
+/// Bytes of a hex dump, ignoring whitespace
+fn read_hex(path: &Path) -> Vec<u8> {
+    let text = fs::read_to_string(path).unwrap();
+    let digits: Vec<char> = text.chars().filter(|c| !c.is_whitespace()).collect();
+    assert!(
+        digits.len().is_multiple_of(2),
+        "{} has an odd digit count",
+        name(path)
+    );
+    digits
+        .chunks(2)
+        .map(|pair| {
+            let byte: String = pair.iter().collect();
+            u8::from_str_radix(&byte, 16)
+                .unwrap_or_else(|e| panic!("{}: bad hex {:?}: {}", name(path), byte, e))
+        })
+        .collect()
+}
+
+#[test]
+fn test_exact_messages_round_trip() {
+    for path in fixtures("messages/exact", "json") {
+        let (text, message) = parse_message(&path);
+        let output = serde_json::to_string(&message).unwrap();
+        assert_eq!(output, text, "{} re-serialized differently", name(&path));
+    }
+}
+
+#[test]
+fn test_lenient_messages_parse() {
+    for path in fixtures("messages/lenient", "json") {
+        let (text, message) = parse_message(&path);
+
+        // What we send back keeps the message type and only known fields
+        let output: serde_json::Value = serde_json::to_value(&message).unwrap();
+        let input: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(output["type"], input["type"], "{}", name(&path));
+    }
+}
+
+#[test]
+fn test_exact_frames_round_trip() {
+    for path in fixtures("frames/exact", "hex") {
+        let bytes = read_hex(&path);
+        let frame = BinaryFrame::decode(&bytes)
+            .unwrap_or_else(|e| panic!("{} does not decode: {}", name(&path), e));
+        assert_eq!(
+            frame.encode(),
+            bytes,
+            "{} re-encoded differently",
+            name(&path)
+        );
+    }
+}
+
+#[test]
+fn test_rejected_frames_fail_to_decode() {
+    for path in fixtures("frames/rejected", "hex") {
+        let bytes = read_hex(&path);
+        assert!(
+            BinaryFrame::decode(&bytes).is_err(),
+            "{} decoded but should be rejected",
+            name(&path)
+        );
+    }
+}
+
+#[test]
+fn test_fixture_frames_carry_expected_fields() {
+    let dir = "frames/exact";
+    let find = |file: &str| {
+        let path = fixtures(dir, "hex")
+            .into_iter()
+            .find(|p| name(p) == file)
+            .unwrap();
+        read_hex(&path)
+    };
+
+    let audio = find("audio_chunk_pcm16.hex");
+    let BinaryFrame::AudioChunk { timestamp, payload } = BinaryFrame::decode(&audio).unwrap()
+    else {
+        panic!("expected an audio chunk");
+    };
+    assert_eq!(timestamp, 5_012_288);
+    assert_eq!(payload.len(), 8);
+
+    let clear = find("artwork_channel2_clear.hex");
+    let frame = BinaryFrame::decode(&clear).unwrap();
+    assert!(matches!(frame, BinaryFrame::Artwork { channel: 2, .. }));
+    assert!(frame.payload().is_empty());
+
+    let negative = find("audio_chunk_empty_negative_timestamp.hex");
+    assert_eq!(BinaryFrame::decode(&negative).unwrap().timestamp(), -1000);
+}
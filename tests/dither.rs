@@ -0,0 +1,102 @@
+use sendspin::audio::dither::{DitherMode, Ditherer};
+use sendspin::audio::Sample;
+
+const SAMPLE_RATE: u32 = 48_000;
+
+/// One 16-bit step
+const STEP: f32 = 1.0 / 32_768.0;
+
+/// Mono sine at `amplitude` steps of 16-bit
+fn quiet_tone(amplitude: f32, frames: usize) -> Vec<Sample> {
+    (0..frames)
+        .map(|i| {
+            let phase = 2.0 * std::f32::consts::PI * 1000.0 * i as f32 / SAMPLE_RATE as f32;
+            Sample(phase.sin() * amplitude * STEP)
+        })
+        .collect()
+}
+
+/// Quantization error of `output` against `input`, in steps
+fn errors(input: &[Sample], output: &[i32]) -> Vec<f32> {
+    input
+        .iter()
+        .zip(output)
+        .map(|(s, &q)| q as f32 - s.0 / STEP)
+        .collect()
+}
+
+fn power(values: impl Iterator<Item = f32>) -> f32 {
+    let (sum, count) = values.fold((0.0, 0), |(sum, n), v| (sum + v * v, n + 1));
+    sum / count as f32
+}
+
+#[test]
+fn test_off_rounds_to_the_nearest_step() {
+    let input = quiet_tone(100.5, 4800);
+    let mut ditherer = Ditherer::new(DitherMode::Off, 16, 1);
+    let expected: Vec<i32> = input.iter().map(|s| s.quantize(16)).collect();
+    assert_eq!(ditherer.quantize(&input), expected);
+}
+
+#[test]
+fn test_24_bit_is_not_dithered() {
+    let input: Vec<Sample> = (-50..50).map(Sample::from_i24).collect();
+    let mut ditherer = Ditherer::new(DitherMode::Shaped, 24, 2);
+    assert!(!ditherer.is_active());
+    assert_eq!(ditherer.quantize(&input), (-50..50).collect::<Vec<i32>>());
+}
+
+#[test]
+fn test_tpdf_keeps_detail_below_one_step() {
+    // A quarter step rounds away to nothing, but survives on average with dither
+    let input = vec![Sample(0.25 * STEP); 48_000];
+    assert!(Ditherer::new(DitherMode::Off, 16, 1)
+        .quantize(&input)
+        .iter()
+        .all(|&q| q == 0));
+
+    let output = Ditherer::new(DitherMode::Tpdf, 16, 1).quantize(&input);
+    let mean = output.iter().sum::<i32>() as f32 / output.len() as f32;
+    assert!((mean - 0.25).abs() < 0.02, "mean {}", mean);
+    assert!(output.iter().all(|q| (-1..=2).contains(q)));
+}
+
+#[test]
+fn test_tpdf_error_does_not_follow_the_signal() {
+    // Undithered, a low-level tone's error repeats with each cycle
+    let input = quiet_tone(3.3, 48_000);
+    let period = (SAMPLE_RATE / 1000) as usize;
+    let repetition = |mode| {
+        let output = Ditherer::new(mode, 16, 1).quantize(&input);
+        let error = errors(&input, &output);
+        let sum: f32 = error.iter().zip(&error[period..]).map(|(a, b)| a * b).sum();
+        sum / (error.len() - period) as f32
+    };
+    let plain = repetition(DitherMode::Off);
+    let dithered = repetition(DitherMode::Tpdf);
+    assert!(plain > 0.05, "{}", plain);
+    assert!(dithered.abs() < 0.01, "{}", dithered);
+}
+
+#[test]
+fn test_shaping_moves_noise_out_of_the_low_band() {
+    let input = quiet_tone(20.0, 48_000);
+    let low_band = |mode| {
+        let output = Ditherer::new(mode, 16, 1).quantize(&input);
+        // Averaging over 64 samples keeps only the bottom of the spectrum
+        let error = errors(&input, &output);
+        power(error.chunks(64).map(|c| c.iter().sum::<f32>() / 64.0))
+    };
+    let flat = low_band(DitherMode::Tpdf);
+    let shaped = low_band(DitherMode::Shaped);
+    assert!(shaped < flat / 5.0, "shaped {} flat {}", shaped, flat);
+}
+
+#[test]
+fn test_output_stays_in_range() {
+    let input = [Sample::MAX, Sample::MIN, Sample(2.0), Sample(-2.0)].repeat(100);
+    for mode in [DitherMode::Tpdf, DitherMode::Shaped] {
+        let output = Ditherer::new(mode, 16, 2).quantize(&input);
+        assert!(output.iter().all(|q| (-32_768..=32_767).contains(q)));
+    }
+}
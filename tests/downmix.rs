@@ -0,0 +1,167 @@
+use sendspin::audio::downmix::{
+    fold_to_mono, ChannelMap, Downmix, DownmixLevels, Speaker, MINUS_3DB,
+};
+use sendspin::audio::Sample;
+
+fn levels(center: f32, lfe: f32, surround: f32) -> DownmixLevels {
+    DownmixLevels {
+        center,
+        lfe,
+        surround,
+        mono: 0.5,
+        max_channels: 2,
+    }
+}
+
+#[test]
+fn test_five_one_keeps_center() {
+    let layout = Speaker::default_layout(6);
+    let downmix = Downmix::stereo(&layout, levels(0.5, 0.0, 0.25));
+
+    // FL FR FC LFE RL RR
+    let frame = [0.125, 0.25, 0.5, 1.0, 0.0625, 0.125];
+    let (left, right) = downmix.frame(&frame);
+    assert_eq!(left, Sample(0.125 + 0.25 + 0.015625));
+    assert_eq!(right, Sample(0.25 + 0.25 + 0.03125));
+}
+
+#[test]
+fn test_lfe_level() {
+    let layout = Speaker::default_layout(6);
+    let downmix = Downmix::stereo(&layout, levels(0.0, 0.5, 0.0));
+    let (left, right) = downmix.frame(&[0.0, 0.0, 0.0, 0.5, 0.0, 0.0]);
+    assert_eq!((left, right), (Sample(0.25), Sample(0.25)));
+}
+
+#[test]
+fn test_mono_and_stereo_pass_through() {
+    let mut output = Vec::new();
+    let mono = Downmix::stereo(&Speaker::default_layout(1), DownmixLevels::default());
+    assert_eq!(mono.extend(&[0.25, -0.5], 10, &mut output), 2);
+    assert_eq!(
+        output,
+        [Sample(0.25), Sample(0.25), Sample(-0.5), Sample(-0.5)]
+    );
+
+    output.clear();
+    let stereo = Downmix::stereo(&Speaker::default_layout(2), DownmixLevels::default());
+    // Limited to one frame
+    assert_eq!(stereo.extend(&[0.1, 0.2, 0.3, 0.4], 1, &mut output), 2);
+    assert_eq!(output, [Sample(0.1), Sample(0.2)]);
+}
+
+#[test]
+fn test_extend_leaves_partial_frame() {
+    let downmix = Downmix::stereo(&Speaker::default_layout(3), DownmixLevels::default());
+    let mut output = Vec::new();
+    // Two whole L R C frames and one stray sample
+    assert_eq!(
+        downmix.extend(&[0.0, 0.0, 0.5, 0.0, 0.0, 0.5, 0.1], 10, &mut output),
+        6
+    );
+    assert_eq!(output, vec![Sample(0.5 * MINUS_3DB); 4]);
+}
+
+#[test]
+fn test_mix_keeps_headroom() {
+    // Past full scale is left for the end of the chain to clamp
+    let downmix = Downmix::stereo(&Speaker::default_layout(3), levels(1.0, 0.0, 0.0));
+    let (left, _) = downmix.frame(&[1.0, 0.0, 1.0]);
+    assert_eq!(left, Sample(2.0));
+    assert_eq!(left.clamp(), Sample::MAX);
+}
+
+#[test]
+fn test_stereo_to_mono() {
+    let levels = DownmixLevels::default();
+    assert_eq!(levels.to_mono(Sample(0.25), Sample(0.75)), Sample(0.5));
+    assert!((DownmixLevels::db_to_gain(-6.0) - 0.501).abs() < 0.001);
+}
+
+#[test]
+fn test_fold_to_mono_sums_at_minus_3db() {
+    let stereo = [Sample(0.25), Sample(-0.25), Sample(0.5), Sample::ZERO];
+    let mono = fold_to_mono(&stereo);
+
+    assert_eq!(mono.len(), 4);
+    assert_eq!(mono[0], Sample::ZERO);
+    assert_eq!(mono[1], Sample::ZERO);
+    let expected = Sample(0.5 * MINUS_3DB);
+    assert_eq!(mono[2], expected);
+    assert_eq!(mono[3], expected);
+}
+
+#[test]
+fn test_fold_to_mono_clamps() {
+    let stereo = [Sample::MAX, Sample::MAX];
+    assert_eq!(fold_to_mono(&stereo), vec![Sample::MAX, Sample::MAX]);
+}
+
+#[test]
+fn test_channel_maps() {
+    let stereo = [Sample(0.125), Sample(-0.25), Sample(0.375), Sample(-0.5)];
+    let samples = |values: [f32; 4]| values.map(Sample).to_vec();
+
+    assert_eq!(ChannelMap::Stereo.apply(&stereo), stereo);
+    assert_eq!(ChannelMap::Mono.apply(&stereo), fold_to_mono(&stereo));
+    assert_eq!(
+        ChannelMap::Left.apply(&stereo),
+        samples([0.125, 0.125, 0.375, 0.375])
+    );
+    assert_eq!(
+        ChannelMap::Right.apply(&stereo),
+        samples([-0.25, -0.25, -0.5, -0.5])
+    );
+    assert_eq!(
+        ChannelMap::Swap.apply(&stereo),
+        samples([-0.25, 0.125, -0.5, 0.375])
+    );
+}
+
+#[test]
+fn test_surround_layouts_fold_to_smaller_ones() {
+    let levels = DownmixLevels::default();
+    let downmix = Downmix::between(8, 6, levels);
+    assert_eq!(downmix.output_channels(), 6);
+    // FL FR FC LFE RL RR SL SR: the sides join the rear pair
+    let frame = [0.5, 0.25, 0.125, 0.0625, 0.03125, 0.015625, 0.25, 0.5].map(Sample);
+    assert_eq!(
+        downmix.apply(&frame),
+        [0.5, 0.25, 0.125, 0.0625, 0.28125, 0.515625]
+            .map(Sample)
+            .to_vec()
+    );
+
+    // 6.1's rear center splits across 7.1's surrounds
+    let downmix = Downmix::between(7, 8, levels);
+    let frame = [0.0, 0.0, 0.0, 0.0, 0.5, 0.0, 0.0].map(Sample);
+    let split = 0.5 * MINUS_3DB;
+    assert_eq!(
+        downmix.apply(&frame),
+        [0.0, 0.0, 0.0, 0.0, split, split, 0.0, 0.0].map(Sample)
+    );
+}
+
+#[test]
+fn test_smaller_layouts_spread_over_the_front() {
+    let levels = DownmixLevels::default();
+    let stereo = Downmix::between(2, 6, levels).apply(&[Sample(0.5), Sample(-0.5)]);
+    assert_eq!(stereo, [0.5, -0.5, 0.0, 0.0, 0.0, 0.0].map(Sample));
+    let mono = Downmix::between(1, 6, levels).apply(&[Sample(0.5)]);
+    assert_eq!(mono, [0.5, 0.5, 0.0, 0.0, 0.0, 0.0].map(Sample));
+}
+
+#[test]
+fn test_sources_keep_channels_up_to_the_limit() {
+    let stereo = DownmixLevels::default();
+    let surround = DownmixLevels {
+        max_channels: 6,
+        ..DownmixLevels::default()
+    };
+    assert_eq!(stereo.source_channels(6), 2);
+    assert_eq!(surround.source_channels(1), 2);
+    assert_eq!(surround.source_channels(4), 4);
+    assert_eq!(surround.source_channels(8), 6);
+    let layout = Speaker::default_layout(8);
+    assert_eq!(Downmix::for_source(&layout, surround).output_channels(), 6);
+}
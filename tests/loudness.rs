@@ -0,0 +1,120 @@
+use sendspin::audio::loudness::{LoudnessMeter, LoudnessNormalization, LoudnessNormalizer};
+use sendspin::audio::Sample;
+
+const SAMPLE_RATE: u32 = 48_000;
+
+/// Interleaved stereo 1kHz sine at `amplitude` of full scale
+fn tone(amplitude: f64, frames: usize) -> Vec<Sample> {
+    (0..frames)
+        .flat_map(|i| {
+            let phase = 2.0 * std::f64::consts::PI * 1000.0 * i as f64 / SAMPLE_RATE as f64;
+            let value = Sample((phase.sin() * amplitude) as f32);
+            [value, value]
+        })
+        .collect()
+}
+
+fn measure(samples: &[Sample]) -> Option<f64> {
+    let mut meter = LoudnessMeter::new(SAMPLE_RATE, 2);
+    meter.add(samples);
+    meter.integrated()
+}
+
+/// Normalize `seconds` of a tone at `amplitude` in 20ms chunks, returning the last second
+fn normalize(settings: LoudnessNormalization, amplitude: f64, seconds: usize) -> Vec<Sample> {
+    let mut normalizer = LoudnessNormalizer::new(settings, SAMPLE_RATE);
+    let chunk = tone(amplitude, 960);
+    let output: Vec<Sample> = (0..seconds * 50)
+        .flat_map(|_| normalizer.process(&chunk))
+        .collect();
+    output[output.len() - 2 * SAMPLE_RATE as usize..].to_vec()
+}
+
+#[test]
+fn test_sine_measures_at_its_level() {
+    // A 1kHz sine on both channels reads its peak level in LUFS
+    let loudness = measure(&tone(0.1, 3 * SAMPLE_RATE as usize)).unwrap();
+    assert!((loudness - -20.0).abs() < 0.2, "{} LUFS", loudness);
+}
+
+#[test]
+fn test_silence_is_gated_out() {
+    assert_eq!(measure(&vec![Sample::ZERO; 6 * SAMPLE_RATE as usize]), None);
+
+    // Silence between passages does not pull the loudness down
+    let mut programme = tone(0.1, 2 * SAMPLE_RATE as usize);
+    programme.extend(vec![Sample::ZERO; 4 * SAMPLE_RATE as usize]);
+    programme.extend(tone(0.1, 2 * SAMPLE_RATE as usize));
+    let loudness = measure(&programme).unwrap();
+    assert!((loudness - -20.0).abs() < 0.5, "{} LUFS", loudness);
+}
+
+#[test]
+fn test_loud_and_quiet_sources_meet_the_target() {
+    let settings = LoudnessNormalization::default();
+    for amplitude in [0.5, 0.02] {
+        let output = normalize(settings, amplitude, 5);
+        let loudness = measure(&output).unwrap();
+        assert!(
+            (loudness - settings.target_lufs).abs() < 0.5,
+            "{} LUFS from amplitude {}",
+            loudness,
+            amplitude
+        );
+    }
+}
+
+#[test]
+fn test_gain_is_capped() {
+    // -40 LUFS would need 17dB to reach -23
+    let settings = LoudnessNormalization {
+        max_gain_db: 6.0,
+        ..LoudnessNormalization::default()
+    };
+    let loudness = measure(&normalize(settings, 0.01, 5)).unwrap();
+    assert!((loudness - -34.0).abs() < 0.5, "{} LUFS", loudness);
+
+    // Boosting never drives the loudest sample past full scale
+    let loud = LoudnessNormalization {
+        target_lufs: 0.0,
+        max_gain_db: 40.0,
+    };
+    let output = normalize(loud, 0.25, 5);
+    let peak = output.iter().fold(0.0, |peak, s| s.0.abs().max(peak));
+    assert!(peak < Sample::MAX.0, "peak {}", peak);
+    assert!(peak > 0.9, "peak {}", peak);
+}
+
+#[test]
+fn test_replay_gain_sources_are_not_measured() {
+    // A source leveled to -18 LUFS by its tags is moved 5dB to the target at once
+    let mut normalizer = LoudnessNormalizer::new(LoudnessNormalization::default(), SAMPLE_RATE);
+    normalizer.set_replay_gain(true);
+    let chunk = tone(0.01, 960);
+    let output: Vec<Sample> = (0..250).flat_map(|_| normalizer.process(&chunk)).collect();
+    assert_eq!(normalizer.loudness(), None);
+    assert!(
+        (normalizer.gain_db() - -5.0).abs() < 0.01,
+        "{} dB",
+        normalizer.gain_db()
+    );
+    let loudness = measure(&output[output.len() - 2 * SAMPLE_RATE as usize..]).unwrap();
+    assert!((loudness - -45.0).abs() < 0.5, "{} LUFS", loudness);
+}
+
+#[test]
+fn test_surround_weights_follow_bs1770() {
+    // The same tone on FL FR FC LFE RL RR: the LFE is not counted and the
+    // surrounds count 1.41 times, for 1 + 1 + 1 + 1.41 + 1.41 times one channel
+    let frames = 3 * SAMPLE_RATE as usize;
+    let surround: Vec<Sample> = tone(0.1, frames)
+        .chunks(2)
+        .flat_map(|pair| [pair[0]; 6])
+        .collect();
+    let mut meter = LoudnessMeter::new(SAMPLE_RATE, 6);
+    meter.add(&surround);
+    let stereo = measure(&tone(0.1, frames)).unwrap();
+    let expected = stereo + 10.0 * (5.82f64 / 2.0).log10();
+    let loudness = meter.integrated().unwrap();
+    assert!((loudness - expected).abs() < 0.05, "{} LUFS", loudness);
+}
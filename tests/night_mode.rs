@@ -0,0 +1,77 @@
+use sendspin::audio::drc::{Compressor, NightMode};
+use sendspin::audio::Sample;
+
+const SAMPLE_RATE: u32 = 48_000;
+
+/// Interleaved stereo sine at `amplitude` of full scale
+fn tone(frequency: f32, amplitude: f32, frames: usize) -> Vec<Sample> {
+    (0..frames)
+        .flat_map(|i| {
+            let phase = 2.0 * std::f32::consts::PI * frequency * i as f32 / SAMPLE_RATE as f32;
+            let value = Sample(phase.sin() * amplitude);
+            [value, value]
+        })
+        .collect()
+}
+
+fn peak(samples: &[Sample]) -> f32 {
+    samples.iter().fold(0.0, |peak, s| s.0.abs().max(peak))
+}
+
+#[test]
+fn test_loud_and_quiet_move_closer() {
+    let profile = NightMode {
+        bass_cut_db: None,
+        ..Default::default()
+    };
+    let one_second = SAMPLE_RATE as usize;
+
+    let mut compressor = Compressor::new(profile, SAMPLE_RATE);
+    let loud = compressor.process(&tone(1000.0, 0.9, one_second));
+    let mut compressor = Compressor::new(profile, SAMPLE_RATE);
+    let quiet = compressor.process(&tone(1000.0, 0.05, one_second));
+
+    // Measure after the envelope has settled
+    let tail = one_second; // last half second of interleaved samples
+    let loud_peak = peak(&loud[tail..]);
+    let quiet_peak = peak(&quiet[tail..]);
+
+    // 25 dB apart going in; much less coming out
+    assert!(loud_peak < 0.9);
+    assert!(quiet_peak > 0.05);
+    assert!(loud_peak / quiet_peak < 0.9 / 0.05 / 2.0);
+}
+
+#[test]
+fn test_bass_cut_attenuates_low_frequencies() {
+    let cut = NightMode {
+        threshold_db: 0.0,
+        makeup_db: 0.0,
+        ..Default::default()
+    };
+    let flat = NightMode {
+        bass_cut_db: None,
+        ..cut
+    };
+    let bass = tone(40.0, 0.2, SAMPLE_RATE as usize);
+
+    let with_cut = Compressor::new(cut, SAMPLE_RATE).process(&bass);
+    let without = Compressor::new(flat, SAMPLE_RATE).process(&bass);
+
+    let tail = SAMPLE_RATE as usize;
+    assert!((peak(&without[tail..]) - 0.2).abs() < 0.01);
+    assert!(peak(&with_cut[tail..]) < 0.2 * 0.7);
+}
+
+#[test]
+fn test_output_is_clamped() {
+    let profile = NightMode {
+        makeup_db: 40.0,
+        ..Default::default()
+    };
+    let output = Compressor::new(profile, SAMPLE_RATE).process(&tone(1000.0, 1.0, 4800));
+    assert_eq!(output.len(), 9600);
+    assert!(output
+        .iter()
+        .all(|s| s.0 <= Sample::MAX.0 && s.0 >= Sample::MIN.0));
+}
@@ -0,0 +1,34 @@
+use sendspin::audio::decode::Concealer;
+use sendspin::audio::Sample;
+
+#[test]
+fn test_conceal_without_history_is_silence() {
+    let mut plc = Concealer::new(48000, 2);
+    let samples = plc.conceal(10);
+    assert_eq!(samples.len(), 20);
+    assert!(samples.iter().all(|&s| s == Sample::ZERO));
+}
+
+#[test]
+fn test_conceal_repeats_recent_audio_and_fades() {
+    // 1kHz sample rate: 10 frames of history, 100 frame fade
+    let mut plc = Concealer::new(1000, 1);
+    let audio: Vec<Sample> = (1..=20).map(|i| Sample(i as f32 / 32.0)).collect();
+    plc.remember(&audio);
+
+    let samples = plc.conceal(30);
+    assert_eq!(samples.len(), 30);
+    // Starts at full level from the last 10 frames, then repeats them
+    assert_eq!(samples[0], Sample(11.0 / 32.0));
+    assert!((samples[9].0 - 20.0 / 32.0 * 0.91).abs() < 1e-6);
+    assert!((samples[10].0 - 11.0 / 32.0 * 0.9).abs() < 1e-6);
+
+    // The fade continues across calls until silent
+    let rest = plc.conceal(100);
+    assert!(rest[..70].iter().any(|&s| s != Sample::ZERO));
+    assert!(rest[70..].iter().all(|&s| s == Sample::ZERO));
+
+    // New audio restores full level
+    plc.remember(&audio);
+    assert_eq!(plc.conceal(1)[0], Sample(11.0 / 32.0));
+}
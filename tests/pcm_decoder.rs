@@ -34,6 +34,6 @@ fn test_decode_pcm_24bit() {
     let samples = decoder.decode(&data).unwrap();
 
     assert_eq!(samples.len(), 2);
-    assert_eq!(samples[0].0, 4096);
-    assert_eq!(samples[1].0, -1);
+    assert_eq!(samples[0].to_i24(), 4096);
+    assert_eq!(samples[1].to_i24(), -1);
 }
@@ -0,0 +1,81 @@
+use sendspin::protocol::binary::{BinaryFrame, AUDIO_CHUNK, HEADER_LEN, SOURCE_AUDIO, VISUALIZER};
+use sendspin::protocol::client::AudioChunk;
+
+#[test]
+fn test_audio_chunk_round_trip() {
+    let payload = [1u8, 2, 3, 4, 5, 6];
+    let frame = BinaryFrame::AudioChunk {
+        timestamp: 1_234_567_890,
+        payload: &payload,
+    };
+
+    let bytes = frame.encode();
+    assert_eq!(bytes.len(), HEADER_LEN + payload.len());
+    assert_eq!(bytes[0], AUDIO_CHUNK);
+    assert_eq!(BinaryFrame::decode(&bytes).unwrap(), frame);
+}
+
+#[test]
+fn test_artwork_and_visualizer_round_trip() {
+    for channel in 0..4 {
+        let frame = BinaryFrame::Artwork {
+            channel,
+            timestamp: -5,
+            payload: b"\x89PNG",
+        };
+        assert_eq!(BinaryFrame::decode(&frame.encode()).unwrap(), frame);
+    }
+
+    let frame = BinaryFrame::Visualizer {
+        timestamp: 42,
+        payload: &[],
+    };
+    let bytes = frame.encode();
+    assert_eq!(bytes[0], VISUALIZER);
+    assert_eq!(BinaryFrame::decode(&bytes).unwrap(), frame);
+}
+
+#[test]
+fn test_source_audio_uses_application_type() {
+    let frame = BinaryFrame::SourceAudio {
+        timestamp: 3,
+        payload: &[1, 2],
+    };
+    let bytes = frame.encode();
+    assert_eq!(bytes[0], SOURCE_AUDIO);
+    assert!((192..=255).contains(&bytes[0]));
+    assert_eq!(BinaryFrame::decode(&bytes).unwrap(), frame);
+    assert!(AudioChunk::from_bytes(&bytes).is_err());
+}
+
+#[test]
+fn test_rejects_short_and_unknown_frames() {
+    assert!(BinaryFrame::decode(&[]).is_err());
+    assert!(BinaryFrame::decode(&[AUDIO_CHUNK, 0, 0, 0, 0, 0, 0, 0]).is_err());
+    assert!(BinaryFrame::decode(&[99, 0, 0, 0, 0, 0, 0, 0, 0]).is_err());
+
+    // A header with no payload is a valid (empty) frame
+    let empty = BinaryFrame::decode(&[AUDIO_CHUNK, 0, 0, 0, 0, 0, 0, 0, 7]).unwrap();
+    assert_eq!(empty.timestamp(), 7);
+    assert!(empty.payload().is_empty());
+}
+
+#[test]
+fn test_audio_chunk_from_bytes_requires_player_frame() {
+    let audio = BinaryFrame::AudioChunk {
+        timestamp: 100,
+        payload: &[9, 9],
+    }
+    .encode();
+    let chunk = AudioChunk::from_bytes(&audio).unwrap();
+    assert_eq!(chunk.timestamp, 100);
+    assert_eq!(&chunk.data[..], &[9, 9]);
+
+    let artwork = BinaryFrame::Artwork {
+        channel: 0,
+        timestamp: 100,
+        payload: &[],
+    }
+    .encode();
+    assert!(AudioChunk::from_bytes(&artwork).is_err());
+}
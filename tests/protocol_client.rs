@@ -1,16 +1,21 @@
-// Note: These are integration tests that require a running server
-// For now, we'll create the structure and skip them
+// End-to-end tests against a running server are in server_streaming.rs
 
 #[test]
-#[ignore] // Requires running server
-fn test_client_receives_stream_start() {
-    // Test that client can receive stream/start message
-    // Will implement when we have full client
-}
+fn test_discovered_server_urls() {
+    use sendspin::protocol::DiscoveredServer;
 
-#[test]
-#[ignore] // Requires running server
-fn test_client_handles_audio_chunks() {
-    // Test that client can receive binary audio chunks
-    // Will implement when we have full client
+    let server = DiscoveredServer {
+        name: "Living Room".to_string(),
+        server_id: Some("abc".to_string()),
+        addresses: vec!["192.168.1.20".parse().unwrap(), "fe80::1".parse().unwrap()],
+        port: 8927,
+        path: "/sendspin".to_string(),
+    };
+    assert_eq!(
+        server.urls(),
+        vec![
+            "ws://192.168.1.20:8927/sendspin".to_string(),
+            "ws://[fe80::1]:8927/sendspin".to_string(),
+        ]
+    );
 }
@@ -0,0 +1,103 @@
+use sendspin::protocol::failover::{prefer_new_server, ServerList, ServerOrigin};
+use sendspin::protocol::messages::{ClientHello, DeviceInfo, ServerHandoff, ServerHello};
+use std::time::Duration;
+
+fn hello() -> ClientHello {
+    ClientHello {
+        client_id: "failover-test".to_string(),
+        name: "Failover Test".to_string(),
+        version: 1,
+        supported_roles: vec!["player@v1".to_string()],
+        device_info: DeviceInfo {
+            product_name: "Test".to_string(),
+            manufacturer: "Sendspin".to_string(),
+            software_version: "0.1.0".to_string(),
+        },
+        player_support: None,
+        metadata_support: None,
+        artwork_support: None,
+        source_format: None,
+    }
+}
+
+#[test]
+fn test_discovered_entries_rank_after_configured() {
+    let mut list = ServerList::new(["ws://a/sendspin", "ws://b/sendspin"]);
+    list.add_discovered(["ws://c/sendspin", "ws://a/sendspin"]);
+
+    let urls: Vec<&str> = list.entries().iter().map(|e| e.url.as_str()).collect();
+    assert_eq!(
+        urls,
+        ["ws://a/sendspin", "ws://b/sendspin", "ws://c/sendspin"]
+    );
+    assert_eq!(list.entries()[2].origin, ServerOrigin::Discovered);
+
+    list.clear_discovered();
+    assert_eq!(list.len(), 2);
+}
+
+#[test]
+fn test_failover_order_starts_at_top_without_connection() {
+    let list = ServerList::new(["ws://a", "ws://b", "ws://c"]);
+    assert!(list.current().is_none());
+    assert_eq!(list.failover_order(), ["ws://a", "ws://b", "ws://c"]);
+}
+
+#[tokio::test]
+async fn test_connect_fails_when_no_server_reachable() {
+    // Port 1 on localhost refuses connections immediately
+    let mut list = ServerList::new(["ws://127.0.0.1:1/sendspin", "ws://127.0.0.1:1/other"])
+        .connect_timeout(Duration::from_millis(500));
+
+    assert!(list.connect(&hello()).await.is_err());
+    assert!(list.current().is_none());
+}
+
+#[tokio::test]
+async fn test_empty_list_is_an_error() {
+    let mut list = ServerList::new(Vec::<String>::new());
+    assert!(list.is_empty());
+    assert!(list.failover(None, &hello()).await.is_err());
+}
+
+fn server_hello(server_id: &str, reason: &str) -> ServerHello {
+    ServerHello {
+        server_id: server_id.to_string(),
+        name: server_id.to_string(),
+        version: 1,
+        active_roles: vec!["player@v1".to_string()],
+        connection_reason: Some(reason.to_string()),
+    }
+}
+
+#[test]
+fn test_prefer_new_server_rules() {
+    let discovery_a = server_hello("a", "discovery");
+    let discovery_b = server_hello("b", "discovery");
+    let playback_a = server_hello("a", "playback");
+    let playback_b = server_hello("b", "playback");
+
+    // New playback connection always wins
+    assert!(prefer_new_server(&playback_a, &playback_b, None));
+    assert!(prefer_new_server(&discovery_a, &playback_b, Some("a")));
+    // Discovery never displaces playback
+    assert!(!prefer_new_server(&playback_a, &discovery_b, Some("b")));
+    // Both discovery: prefer last played, otherwise keep existing
+    assert!(prefer_new_server(&discovery_a, &discovery_b, Some("b")));
+    assert!(!prefer_new_server(&discovery_a, &discovery_b, Some("a")));
+    assert!(!prefer_new_server(&discovery_a, &discovery_b, None));
+}
+
+#[tokio::test]
+async fn test_failed_handoff_keeps_current_server() {
+    let mut list =
+        ServerList::new(["ws://127.0.0.1:1/primary"]).connect_timeout(Duration::from_millis(500));
+    let handoff = ServerHandoff {
+        url: "ws://127.0.0.1:1/backup".to_string(),
+        server_id: None,
+    };
+
+    assert!(list.follow_handoff(&handoff, None, &hello()).await.is_err());
+    assert!(list.current().is_none());
+    assert_eq!(list.entries()[1].origin, ServerOrigin::Handoff);
+}
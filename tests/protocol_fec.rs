@@ -0,0 +1,119 @@
+use sendspin::protocol::binary::BinaryFrame;
+use sendspin::protocol::client::AudioChunk;
+use sendspin::protocol::fec::{FecConfig, ParityDecoder, ParityEncoder, MAX_GROUP_SIZE};
+use std::sync::Arc;
+
+fn chunk(timestamp: i64, data: &[u8]) -> AudioChunk {
+    AudioChunk {
+        timestamp,
+        data: Arc::from(data),
+    }
+}
+
+fn parity_payload(frame: &[u8]) -> Vec<u8> {
+    match BinaryFrame::decode(frame).unwrap() {
+        BinaryFrame::Parity { payload, .. } => payload.to_vec(),
+        other => panic!("expected parity frame, got {:?}", other),
+    }
+}
+
+/// Send `chunks` through an encoder, returning the parity frame for the group
+fn encode_group(config: FecConfig, chunks: &[AudioChunk]) -> Vec<u8> {
+    let mut encoder = ParityEncoder::new(config);
+    let (last, rest) = chunks.split_last().unwrap();
+    for c in rest {
+        assert!(encoder.push(c.timestamp, &c.data).is_none());
+    }
+    encoder.push(last.timestamp, &last.data).unwrap()
+}
+
+#[test]
+fn test_encoder_emits_parity_every_group() {
+    let mut encoder = ParityEncoder::new(FecConfig { group_size: 3 });
+    let emitted: Vec<bool> = (0..6)
+        .map(|i| encoder.push(i * 1000, &[i as u8; 8]).is_some())
+        .collect();
+    assert_eq!(emitted, [false, false, true, false, false, true]);
+
+    let frame = encoder.push(6000, &[0; 8]);
+    assert!(frame.is_none());
+    encoder.reset();
+    assert!(encoder.push(7000, &[0; 8]).is_none());
+}
+
+#[test]
+fn test_parity_frame_timestamp_is_first_chunk() {
+    let config = FecConfig { group_size: 2 };
+    let frame = encode_group(config, &[chunk(500, &[1]), chunk(600, &[2])]);
+    let decoded = BinaryFrame::decode(&frame).unwrap();
+    assert_eq!(decoded.timestamp(), 500);
+    assert_eq!(BinaryFrame::decode(&decoded.encode()).unwrap(), decoded);
+}
+
+#[test]
+fn test_recovers_single_lost_chunk() {
+    let config = FecConfig { group_size: 3 };
+    let chunks = [
+        chunk(0, &[1, 2, 3, 4]),
+        chunk(1000, &[9, 8, 7, 6, 5, 4]),
+        chunk(2000, &[42]),
+    ];
+    let parity = parity_payload(&encode_group(config, &chunks));
+
+    for lost in 0..chunks.len() {
+        let mut decoder = ParityDecoder::new(config);
+        for (i, c) in chunks.iter().enumerate() {
+            if i != lost {
+                decoder.observe(c);
+            }
+        }
+        let recovered = decoder.recover(&parity).unwrap().unwrap();
+        assert_eq!(recovered.timestamp, chunks[lost].timestamp);
+        assert_eq!(recovered.data, chunks[lost].data);
+    }
+}
+
+#[test]
+fn test_nothing_to_recover() {
+    let config = FecConfig { group_size: 3 };
+    let chunks = [chunk(0, &[1]), chunk(1000, &[2]), chunk(2000, &[3])];
+    let parity = parity_payload(&encode_group(config, &chunks));
+
+    // Nothing lost
+    let mut decoder = ParityDecoder::new(config);
+    chunks.iter().for_each(|c| decoder.observe(c));
+    assert!(decoder.recover(&parity).unwrap().is_none());
+
+    // Two lost: beyond what XOR parity can rebuild
+    let mut decoder = ParityDecoder::new(config);
+    decoder.observe(&chunks[0]);
+    assert!(decoder.recover(&parity).unwrap().is_none());
+}
+
+#[test]
+fn test_rejects_truncated_parity() {
+    let mut decoder = ParityDecoder::new(FecConfig { group_size: 2 });
+    assert!(decoder.recover(&[]).is_err());
+    assert!(decoder.recover(&[2, 0, 0, 0]).is_err());
+}
+
+#[test]
+fn test_negotiate() {
+    let requested = Some(FecConfig { group_size: 16 });
+    assert_eq!(FecConfig::negotiate(requested, None), None);
+    assert_eq!(FecConfig::negotiate(None, Some(8)), None);
+    assert_eq!(
+        FecConfig::negotiate(requested, Some(8)),
+        Some(FecConfig { group_size: 8 })
+    );
+    assert_eq!(
+        FecConfig::negotiate(Some(FecConfig { group_size: 0 }), Some(8)),
+        Some(FecConfig { group_size: 2 })
+    );
+    assert_eq!(
+        FecConfig::negotiate(Some(FecConfig { group_size: 255 }), Some(255)),
+        Some(FecConfig {
+            group_size: MAX_GROUP_SIZE
+        })
+    );
+}
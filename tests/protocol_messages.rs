@@ -1,7 +1,6 @@
 use sendspin::protocol::messages::{
-    AudioFormatSpec, ClientHello, DeviceInfo, Message, PlayerSupport, ServerHello,
+    AudioFormatSpec, ClientHello, DeviceInfo, Message, PlayerSupport, ServerStatus, StatusRequest,
 };
-use serde_json;
 
 #[test]
 fn test_client_hello_serialization() {
@@ -16,11 +15,7 @@ fn test_client_hello_serialization() {
             software_version: "0.1.0".to_string(),
         },
         player_support: Some(PlayerSupport {
-            support_codecs: vec!["pcm".to_string()],
-            support_channels: vec![2],
-            support_sample_rates: vec![48000],
-            support_bit_depth: vec![16, 24],
-            support_formats: vec![AudioFormatSpec {
+            supported_formats: vec![AudioFormatSpec {
                 codec: "pcm".to_string(),
                 channels: 2,
                 sample_rate: 48000,
@@ -28,8 +23,11 @@ fn test_client_hello_serialization() {
             }],
             buffer_capacity: 100,
             supported_commands: vec!["play".to_string(), "pause".to_string()],
+            fec: None,
         }),
         metadata_support: None,
+        artwork_support: None,
+        source_format: None,
     };
 
     let message = Message::ClientHello(hello);
@@ -61,3 +59,133 @@ fn test_server_hello_deserialization() {
         _ => panic!("Expected ServerHello"),
     }
 }
+
+#[test]
+fn test_server_handoff_roundtrip() {
+    let json = r#"{
+        "type": "_server/handoff",
+        "payload": {
+            "url": "ws://backup.local:8927/sendspin",
+            "server_id": "backup"
+        }
+    }"#;
+
+    let message: Message = serde_json::from_str(json).unwrap();
+    match &message {
+        Message::ServerHandoff(handoff) => {
+            assert_eq!(handoff.url, "ws://backup.local:8927/sendspin");
+            assert_eq!(handoff.server_id.as_deref(), Some("backup"));
+        }
+        _ => panic!("Expected ServerHandoff"),
+    }
+
+    let serialized = serde_json::to_string(&message).unwrap();
+    assert!(serialized.contains("\"type\":\"_server/handoff\""));
+}
+
+#[test]
+fn test_server_goodbye_roundtrip() {
+    let json = r#"{
+        "type": "_server/goodbye",
+        "payload": {
+            "reason": "no_supported_format",
+            "message": "none of the audio formats [mp3 44100Hz] can be streamed"
+        }
+    }"#;
+
+    let message: Message = serde_json::from_str(json).unwrap();
+    match &message {
+        Message::ServerGoodbye(goodbye) => {
+            assert_eq!(goodbye.reason, "no_supported_format");
+            assert!(goodbye.message.contains("mp3"));
+        }
+        _ => panic!("Expected ServerGoodbye"),
+    }
+
+    let serialized = serde_json::to_string(&message).unwrap();
+    assert!(serialized.contains("\"type\":\"_server/goodbye\""));
+}
+
+#[test]
+fn test_client_command_deserialization() {
+    let json = r#"{
+        "type": "client/command",
+        "payload": {
+            "controller": {
+                "command": "volume",
+                "volume": 40
+            }
+        }
+    }"#;
+
+    let message: Message = serde_json::from_str(json).unwrap();
+    match message {
+        Message::ClientCommand(command) => {
+            let controller = command.controller.unwrap();
+            assert_eq!(controller.command, "volume");
+            assert_eq!(controller.volume, Some(40));
+            assert_eq!(controller.mute, None);
+        }
+        _ => panic!("Expected ClientCommand"),
+    }
+}
+
+#[test]
+fn test_diagnostics_roundtrip() {
+    let json = r#"{
+        "type": "_client/diagnostics",
+        "payload": {
+            "request_id": "7",
+            "stats": {
+                "chunks_received": 100,
+                "gaps": 1,
+                "overlaps": 0,
+                "max_gap_micros": 20000,
+                "max_overlap_micros": 0,
+                "buffered_ms": 480,
+                "underruns": 2
+            },
+            "sync_quality": "good"
+        }
+    }"#;
+
+    let message: Message = serde_json::from_str(json).unwrap();
+    match &message {
+        Message::ClientDiagnostics(diagnostics) => {
+            assert_eq!(diagnostics.request_id, "7");
+            assert_eq!(diagnostics.stats.buffered_ms, Some(480));
+            assert_eq!(diagnostics.stats.underruns, 2);
+            assert_eq!(
+                diagnostics.sync_quality,
+                Some(sendspin::sync::SyncQuality::Good)
+            );
+            assert!(diagnostics.device_info.is_none());
+        }
+        _ => panic!("Expected ClientDiagnostics"),
+    }
+
+    let serialized = serde_json::to_string(&message).unwrap();
+    assert!(serialized.contains("\"type\":\"_client/diagnostics\""));
+}
+
+#[test]
+fn test_server_status_diff_omits_unchanged_parts() {
+    let diff = ServerStatus {
+        revision: 4,
+        full: false,
+        groups: Vec::new(),
+        removed_groups: Vec::new(),
+        clients: Vec::new(),
+        removed_clients: vec!["kitchen".to_string()],
+        now_playing: None,
+    };
+    let json = serde_json::to_value(&diff).unwrap();
+    assert_eq!(
+        json,
+        serde_json::json!({"revision": 4, "full": false, "removed_clients": ["kitchen"]})
+    );
+    assert_eq!(serde_json::from_value::<ServerStatus>(json).unwrap(), diff);
+
+    let request: StatusRequest = serde_json::from_str("{}").unwrap();
+    assert!(!request.subscribe);
+}
@@ -0,0 +1,57 @@
+use sendspin::protocol::client::AudioChunk;
+use sendspin::protocol::reorder::ReorderBuffer;
+use sendspin::protocol::stats::ClientStats;
+use std::sync::Arc;
+
+fn chunk(timestamp: i64) -> AudioChunk {
+    AudioChunk {
+        timestamp,
+        data: Arc::from(vec![0u8; 16]),
+    }
+}
+
+fn timestamps(chunks: Vec<AudioChunk>) -> Vec<i64> {
+    chunks.iter().map(|c| c.timestamp).collect()
+}
+
+#[test]
+fn test_zero_window_passes_through() {
+    let mut buffer = ReorderBuffer::new(0);
+    let mut stats = ClientStats::default();
+
+    assert_eq!(timestamps(buffer.push(chunk(20), &mut stats)), [20]);
+    assert_eq!(timestamps(buffer.push(chunk(0), &mut stats)), [0]);
+    assert!(buffer.is_empty());
+    assert_eq!(stats.reordered, 1);
+}
+
+#[test]
+fn test_window_restores_order() {
+    let mut buffer = ReorderBuffer::new(2);
+    let mut stats = ClientStats::default();
+
+    let mut released = Vec::new();
+    for ts in [0, 40, 20, 60, 100, 80] {
+        released.extend(timestamps(buffer.push(chunk(ts), &mut stats)));
+    }
+    assert_eq!(buffer.len(), 2);
+    released.extend(timestamps(buffer.flush()));
+
+    assert_eq!(released, [0, 20, 40, 60, 80, 100]);
+    assert_eq!(stats.reordered, 2);
+}
+
+#[test]
+fn test_chunk_behind_window_passes_through() {
+    let mut buffer = ReorderBuffer::new(1);
+    let mut stats = ClientStats::default();
+
+    buffer.push(chunk(20), &mut stats);
+    assert_eq!(timestamps(buffer.push(chunk(40), &mut stats)), [20]);
+    // 10 arrives after 20 was released: too late to reorder, delivered as is
+    assert_eq!(timestamps(buffer.push(chunk(10), &mut stats)), [10]);
+
+    buffer.reset();
+    assert!(buffer.is_empty());
+    assert!(buffer.push(chunk(0), &mut stats).is_empty());
+}
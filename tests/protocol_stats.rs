@@ -0,0 +1,142 @@
+use sendspin::protocol::client::AudioChunk;
+use sendspin::protocol::messages::{ClientState, Message, StreamPlayerConfig};
+use sendspin::protocol::stats::{ChunkTracker, ClientStats, StaleChunkFilter};
+use std::sync::Arc;
+
+fn pcm_config() -> StreamPlayerConfig {
+    StreamPlayerConfig {
+        codec: "pcm".to_string(),
+        sample_rate: 48000,
+        channels: 2,
+        bit_depth: 24,
+        codec_header: None,
+        fec: None,
+        chunk_interval_ms: None,
+        buffer_ahead_ms: None,
+    }
+}
+
+/// 20ms of 48kHz stereo 24-bit PCM
+fn chunk(timestamp: i64) -> AudioChunk {
+    AudioChunk {
+        timestamp,
+        data: Arc::from(vec![0u8; 960 * 6]),
+    }
+}
+
+#[test]
+fn test_contiguous_chunks_have_no_discontinuities() {
+    let mut tracker = ChunkTracker::default();
+    tracker.set_format(&pcm_config());
+    let mut stats = ClientStats::default();
+
+    for i in 0..10 {
+        assert_eq!(tracker.observe(&chunk(i * 20_000), &mut stats), None);
+    }
+
+    assert_eq!(stats.chunks_received, 10);
+    assert_eq!(stats.gaps, 0);
+    assert_eq!(stats.overlaps, 0);
+}
+
+#[test]
+fn test_gap_and_overlap_detection() {
+    let mut tracker = ChunkTracker::default();
+    tracker.set_format(&pcm_config());
+    let mut stats = ClientStats::default();
+
+    tracker.observe(&chunk(0), &mut stats);
+    // One chunk missing: expected 20_000, got 40_000
+    assert_eq!(tracker.observe(&chunk(40_000), &mut stats), Some(20_000));
+    // Overlap: expected 60_000, got 50_000
+    assert_eq!(tracker.observe(&chunk(50_000), &mut stats), Some(-10_000));
+
+    assert_eq!(stats.gaps, 1);
+    assert_eq!(stats.overlaps, 1);
+    assert_eq!(stats.max_gap_micros, 20_000);
+    assert_eq!(stats.max_overlap_micros, 10_000);
+}
+
+#[test]
+fn test_reset_ignores_jump_after_clear() {
+    let mut tracker = ChunkTracker::default();
+    tracker.set_format(&pcm_config());
+    let mut stats = ClientStats::default();
+
+    tracker.observe(&chunk(0), &mut stats);
+    tracker.reset();
+    assert_eq!(tracker.observe(&chunk(5_000_000), &mut stats), None);
+    assert_eq!(stats.gaps, 0);
+}
+
+#[test]
+fn test_unknown_codec_uses_chunk_spacing() {
+    let mut tracker = ChunkTracker::default();
+    tracker.set_format(&StreamPlayerConfig {
+        codec: "opus".to_string(),
+        bit_depth: 16,
+        ..pcm_config()
+    });
+    let mut stats = ClientStats::default();
+
+    tracker.observe(&chunk(0), &mut stats);
+    tracker.observe(&chunk(20_000), &mut stats);
+    assert_eq!(tracker.observe(&chunk(40_000), &mut stats), None);
+    assert_eq!(tracker.observe(&chunk(80_000), &mut stats), Some(20_000));
+}
+
+#[test]
+fn test_stats_serialize_as_client_state_extension() {
+    let msg = Message::ClientState(ClientState {
+        player: None,
+        stats: Some(ClientStats {
+            chunks_received: 3,
+            gaps: 1,
+            ..Default::default()
+        }),
+        trace: None,
+    });
+
+    let json = serde_json::to_string(&msg).unwrap();
+    assert!(json.contains("\"_stats\""));
+
+    match serde_json::from_str::<Message>(&json).unwrap() {
+        Message::ClientState(state) => assert_eq!(state.stats.unwrap().gaps, 1),
+        _ => panic!("Expected ClientState"),
+    }
+}
+
+#[test]
+fn test_stale_chunks_are_dropped_and_counted() {
+    let mut filter = StaleChunkFilter::new(50_000);
+    let mut stats = ClientStats::default();
+
+    // No synchronized clock yet: everything is accepted
+    assert_eq!(filter.check(&chunk(0), None, &mut stats), None);
+
+    let now = 10_000_000;
+    assert_eq!(
+        filter.check(&chunk(now + 500_000), Some(now), &mut stats),
+        None
+    );
+    assert_eq!(
+        filter.check(&chunk(now - 40_000), Some(now), &mut stats),
+        None
+    );
+    assert!(!filter.is_dropping());
+
+    // A backlog from two seconds ago arrives after a stall
+    assert_eq!(
+        filter.check(&chunk(now - 2_000_000), Some(now), &mut stats),
+        Some(2_000_000)
+    );
+    assert!(filter.is_dropping());
+    filter.check(&chunk(now - 1_980_000), Some(now), &mut stats);
+
+    assert_eq!(
+        filter.check(&chunk(now + 100_000), Some(now), &mut stats),
+        None
+    );
+    assert!(!filter.is_dropping());
+    assert_eq!(stats.stale_dropped, 2);
+}
@@ -0,0 +1,147 @@
+use sendspin::protocol::binary::{BinaryFrame, TRANSFER_SEGMENT};
+use sendspin::protocol::transfer::{Reassembler, SegmentHeader, Segmenter, SEGMENT_HEADER_LEN};
+use std::time::{Duration, Instant};
+
+fn artwork(payload: &[u8]) -> BinaryFrame<'_> {
+    BinaryFrame::Artwork {
+        channel: 0,
+        timestamp: 1_000,
+        payload,
+    }
+}
+
+/// Timestamp and payload of a segment frame
+fn segment_parts(frame: &[u8]) -> (i64, &[u8]) {
+    match BinaryFrame::decode(frame).unwrap() {
+        BinaryFrame::Segment { timestamp, payload } => (timestamp, payload),
+        other => panic!("expected segment, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_small_frames_are_sent_whole() {
+    let mut segmenter = Segmenter::new(1024);
+    let frame = artwork(&[1, 2, 3]);
+    assert_eq!(segmenter.segment(&frame).unwrap(), vec![frame.encode()]);
+}
+
+#[test]
+fn test_segments_reassemble_in_any_order() {
+    let image: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+    let frame = artwork(&image);
+    let mut segmenter = Segmenter::new(128);
+    let mut segments = segmenter.segment(&frame).unwrap();
+    assert_eq!(segments.len(), 10);
+    assert!(segments
+        .iter()
+        .all(|s| s.len() <= 128 && s[0] == TRANSFER_SEGMENT));
+
+    segments.reverse();
+    let mut reassembler = Reassembler::default();
+    let now = Instant::now();
+    let (last, rest) = segments.split_last().unwrap();
+    for segment in rest {
+        let (timestamp, payload) = segment_parts(segment);
+        assert_eq!(reassembler.push(timestamp, payload, now).unwrap(), None);
+    }
+    let (timestamp, payload) = segment_parts(last);
+    let reassembled = reassembler.push(timestamp, payload, now).unwrap().unwrap();
+    assert_eq!(BinaryFrame::decode(&reassembled).unwrap(), frame);
+
+    // A duplicate of a delivered transfer is ignored
+    assert_eq!(reassembler.push(timestamp, payload, now).unwrap(), None);
+}
+
+#[test]
+fn test_newer_transfer_supersedes_unfinished_one() {
+    let mut segmenter = Segmenter::new(64);
+    let first = segmenter.segment(&artwork(&[1; 200])).unwrap();
+    let second = segmenter.segment(&artwork(&[2; 100])).unwrap();
+    let mut reassembler = Reassembler::default();
+    let now = Instant::now();
+
+    let (timestamp, payload) = segment_parts(&first[0]);
+    assert_eq!(reassembler.push(timestamp, payload, now).unwrap(), None);
+    let mut delivered = None;
+    for segment in &second {
+        let (timestamp, payload) = segment_parts(segment);
+        delivered = reassembler.push(timestamp, payload, now).unwrap();
+    }
+    let delivered = delivered.unwrap();
+    assert_eq!(
+        BinaryFrame::decode(&delivered).unwrap().payload(),
+        &[2; 100]
+    );
+
+    // Late segments of the older transfer are dropped
+    for segment in &first[1..] {
+        let (timestamp, payload) = segment_parts(segment);
+        assert_eq!(reassembler.push(timestamp, payload, now).unwrap(), None);
+    }
+}
+
+#[test]
+fn test_channels_are_sequenced_independently() {
+    let mut segmenter = Segmenter::new(64);
+    let cover = segmenter.segment(&artwork(&[1; 100])).unwrap();
+    let artist = segmenter
+        .segment(&BinaryFrame::Artwork {
+            channel: 1,
+            timestamp: 1_000,
+            payload: &[2; 100],
+        })
+        .unwrap();
+    let mut reassembler = Reassembler::default();
+    let now = Instant::now();
+
+    let mut delivered = Vec::new();
+    for (a, b) in cover.iter().zip(&artist) {
+        for segment in [a, b] {
+            let (timestamp, payload) = segment_parts(segment);
+            delivered.extend(reassembler.push(timestamp, payload, now).unwrap());
+        }
+    }
+    assert_eq!(delivered.len(), 2);
+}
+
+#[test]
+fn test_unfinished_transfers_time_out() {
+    let mut segmenter = Segmenter::new(64);
+    let segments = segmenter.segment(&artwork(&[1; 100])).unwrap();
+    let mut reassembler = Reassembler::new(Duration::from_secs(5), 1 << 20);
+    let start = Instant::now();
+
+    let (timestamp, payload) = segment_parts(&segments[0]);
+    reassembler.push(timestamp, payload, start).unwrap();
+    assert_eq!(reassembler.expire(start + Duration::from_secs(1)), 0);
+    assert_eq!(reassembler.expire(start + Duration::from_secs(6)), 1);
+
+    // The rest of the transfer alone is not enough
+    let later = start + Duration::from_secs(6);
+    for segment in &segments[1..] {
+        let (timestamp, payload) = segment_parts(segment);
+        assert_eq!(reassembler.push(timestamp, payload, later).unwrap(), None);
+    }
+}
+
+#[test]
+fn test_rejects_oversized_and_malformed_segments() {
+    let mut segmenter = Segmenter::new(64);
+    let segments = segmenter.segment(&artwork(&[1; 1000])).unwrap();
+    let mut reassembler = Reassembler::new(Duration::from_secs(5), 500);
+    let now = Instant::now();
+    let result = segments.iter().try_for_each(|segment| {
+        let (timestamp, payload) = segment_parts(segment);
+        reassembler.push(timestamp, payload, now).map(|_| ())
+    });
+    assert!(result.is_err());
+
+    assert!(SegmentHeader::decode(&[0; SEGMENT_HEADER_LEN - 1]).is_err());
+    let out_of_range = SegmentHeader {
+        message_type: 8,
+        transfer_id: 0,
+        index: 3,
+        count: 3,
+    };
+    assert!(SegmentHeader::decode(&out_of_range.encode()).is_err());
+}
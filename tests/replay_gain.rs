@@ -0,0 +1,85 @@
+use sendspin::audio::replay_gain::{GainTags, ReplayGain, ReplayGainMode};
+
+fn tags(pairs: &[(&str, &str)]) -> GainTags {
+    let mut tags = GainTags::default();
+    for (key, value) in pairs {
+        tags.add(key, value);
+    }
+    tags
+}
+
+fn gain_db(settings: ReplayGain, tags: &GainTags) -> Option<f64> {
+    settings.gain(tags).map(|gain| 20.0 * gain.log10())
+}
+
+#[test]
+fn test_tags_are_parsed() {
+    let tags = tags(&[
+        ("REPLAYGAIN_TRACK_GAIN", "-7.21 dB"),
+        ("TXXX:replaygain_track_peak", " 0.988 "),
+        ("----:com.apple.iTunes:REPLAYGAIN_ALBUM_GAIN", "+1.5 dB"),
+        ("REPLAYGAIN_ALBUM_PEAK", "not a number"),
+        ("TITLE", "-3 dB"),
+    ]);
+    assert_eq!(tags.track_gain_db, Some(-7.21));
+    assert_eq!(tags.track_peak, Some(0.988));
+    assert_eq!(tags.album_gain_db, Some(1.5));
+    assert_eq!(tags.album_peak, None);
+    assert!(GainTags::default().is_empty());
+}
+
+#[test]
+fn test_r128_gains_are_moved_to_the_replay_gain_reference() {
+    // -1280/256 = -5dB toward -23 LUFS is 0dB toward -18 LUFS
+    let tags = tags(&[("R128_TRACK_GAIN", "-1280"), ("R128_ALBUM_GAIN", "256")]);
+    assert_eq!(tags.track_gain_db, Some(0.0));
+    assert_eq!(tags.album_gain_db, Some(6.0));
+}
+
+#[test]
+fn test_mode_prefers_its_gain_and_falls_back() {
+    let both = tags(&[
+        ("REPLAYGAIN_TRACK_GAIN", "-3 dB"),
+        ("REPLAYGAIN_ALBUM_GAIN", "-5 dB"),
+    ]);
+    let track_only = tags(&[("REPLAYGAIN_TRACK_GAIN", "-3 dB")]);
+    let album = ReplayGain {
+        mode: ReplayGainMode::Album,
+        ..ReplayGain::default()
+    };
+    assert!((gain_db(ReplayGain::default(), &both).unwrap() - -3.0).abs() < 1e-9);
+    assert!((gain_db(album, &both).unwrap() - -5.0).abs() < 1e-9);
+    assert!((gain_db(album, &track_only).unwrap() - -3.0).abs() < 1e-9);
+    assert_eq!(gain_db(album, &GainTags::default()), None);
+}
+
+#[test]
+fn test_preamp_is_held_below_the_peak() {
+    let tags = tags(&[
+        ("REPLAYGAIN_TRACK_GAIN", "+2 dB"),
+        ("REPLAYGAIN_TRACK_PEAK", "0.5"),
+    ]);
+    let boosted = ReplayGain {
+        preamp_db: 3.0,
+        ..ReplayGain::default()
+    };
+    // A 0.5 peak leaves 6.02dB of headroom
+    assert!((gain_db(boosted, &tags).unwrap() - 5.0).abs() < 1e-9);
+    let louder = ReplayGain {
+        preamp_db: 6.0,
+        ..boosted
+    };
+    assert!((louder.gain(&tags).unwrap() - 2.0).abs() < 1e-9);
+    let clipping = ReplayGain {
+        prevent_clipping: false,
+        ..louder
+    };
+    assert!((gain_db(clipping, &tags).unwrap() - 8.0).abs() < 1e-9);
+
+    assert!(louder.validate().is_ok());
+    let extreme = ReplayGain {
+        preamp_db: 20.0,
+        ..ReplayGain::default()
+    };
+    assert!(extreme.validate().is_err());
+}
@@ -0,0 +1,132 @@
+use sendspin::audio::resample::Resampler;
+use sendspin::audio::Sample;
+use std::collections::HashSet;
+
+/// Interleaved stereo sine at `sample_rate`, starting at frame `start`
+fn tone(frequency: f64, sample_rate: u32, start: usize, frames: usize) -> Vec<Sample> {
+    (start..start + frames)
+        .flat_map(|i| {
+            let phase = 2.0 * std::f64::consts::PI * frequency * i as f64 / sample_rate as f64;
+            let value = (phase.sin() * 0.5) as f32;
+            [Sample(value), Sample(value)]
+        })
+        .collect()
+}
+
+/// Resample one second of a tone in 20ms chunks, returning the left channel
+fn convert(frequency: f64, from: u32, to: u32) -> Vec<f64> {
+    let mut resampler = Resampler::new(from, to, 2);
+    let chunk = from as usize / 50;
+    (0..50)
+        .flat_map(|i| resampler.process(&tone(frequency, from, i * chunk, chunk)))
+        .step_by(2)
+        .map(|s| s.0 as f64)
+        .collect()
+}
+
+fn peak(samples: &[f64]) -> f64 {
+    samples.iter().fold(0.0, |peak, s| s.abs().max(peak))
+}
+
+#[test]
+fn test_equal_rates_pass_through() {
+    let input = tone(1000.0, 48_000, 0, 960);
+    let mut resampler = Resampler::new(48_000, 48_000, 2);
+    assert_eq!(resampler.process(&input), input);
+}
+
+#[test]
+fn test_output_length_follows_the_ratio() {
+    let mut resampler = Resampler::new(44_100, 48_000, 2);
+    let lengths: Vec<usize> = (0..50)
+        .map(|i| resampler.process(&tone(440.0, 44_100, i * 882, 882)).len() / 2)
+        .collect();
+    // After the filter fills, each 20ms chunk comes out as 20ms at the new rate
+    assert!(
+        lengths[1..].iter().all(|&frames| frames == 960),
+        "{:?}",
+        lengths
+    );
+    // The filter's delay is all that is missing from the total
+    let delay = (48_000 - lengths.iter().sum::<usize>()) as f64;
+    let expected = resampler.delay_frames() as f64 * 48_000.0 / 44_100.0;
+    assert!((delay - expected).abs() < 1.0, "{} frames short", delay);
+}
+
+#[test]
+fn test_output_offset_places_each_chunk_by_its_first_frame() {
+    // 1000-frame chunks at 48kHz come to 918.75 frames at 44.1kHz
+    let mut resampler = Resampler::new(48_000, 44_100, 2);
+    let delay_micros = resampler.delay_frames() as f64 * 1e6 / 48_000.0;
+    let mut output_frames = 0;
+    let mut lengths = HashSet::new();
+    for i in 0..48 {
+        let offset = resampler.output_offset_micros() as f64;
+        // Output frame n carries the audio of n / 44100 seconds into the stream
+        let first_frame_micros = output_frames as f64 * 1e6 / 44_100.0;
+        let chunk_start_micros = i as f64 * 1e6 / 48.0;
+        assert!(
+            (chunk_start_micros + offset - first_frame_micros).abs() <= 1.0,
+            "chunk {}: offset {}µs, first frame at {}µs",
+            i,
+            offset,
+            first_frame_micros
+        );
+        assert!(
+            (-delay_micros - 1.0..=0.0).contains(&offset),
+            "offset {}µs",
+            offset
+        );
+
+        let frames = resampler
+            .process(&tone(440.0, 48_000, i * 1000, 1000))
+            .len()
+            / 2;
+        output_frames += frames;
+        lengths.insert(frames);
+    }
+    // Lengths vary a frame around the ratio but add up to it, less the filter's delay
+    assert!(
+        lengths.contains(&918) && lengths.contains(&919),
+        "{:?}",
+        lengths
+    );
+    let expected = 44_100 - (delay_micros * 44_100.0 / 1e6).round() as usize;
+    assert!(
+        output_frames.abs_diff(expected) <= 1,
+        "{} frames",
+        output_frames
+    );
+}
+
+#[test]
+fn test_tone_keeps_pitch_and_level() {
+    let output = convert(1000.0, 44_100, 48_000);
+    let steady = &output[4_800..43_200];
+    assert!((peak(steady) - 0.5).abs() < 0.01, "peak {}", peak(steady));
+
+    // 1kHz crosses zero twice per millisecond, whatever the sample rate
+    let crossings = steady
+        .windows(2)
+        .filter(|pair| (pair[0] < 0.0) != (pair[1] < 0.0))
+        .count();
+    assert!(
+        (1_598..=1_602).contains(&crossings),
+        "{} crossings",
+        crossings
+    );
+}
+
+#[test]
+fn test_downsampling_filters_what_no_longer_fits() {
+    // 12kHz is above the 8kHz Nyquist frequency of a 16kHz stream
+    let output = convert(12_000.0, 48_000, 16_000);
+    assert!(
+        peak(&output[1_600..]) < 0.01,
+        "peak {}",
+        peak(&output[1_600..])
+    );
+
+    let kept = convert(2_000.0, 48_000, 16_000);
+    assert!((peak(&kept[1_600..]) - 0.5).abs() < 0.01);
+}
@@ -1,5 +1,7 @@
+use sendspin::audio::output::{AudioOutput, MockOutput};
 use sendspin::audio::{AudioBuffer, AudioFormat, Codec, Sample};
 use sendspin::scheduler::AudioScheduler;
+use sendspin::sync::{Clock, ManualClock};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
@@ -40,3 +42,237 @@ fn test_scheduler_schedule_and_ready() {
     let ready = scheduler.next_ready();
     assert!(ready.is_some());
 }
+
+#[test]
+fn test_scheduler_tracks_buffered_duration() {
+    let scheduler = AudioScheduler::new();
+
+    let format = AudioFormat {
+        codec: Codec::Pcm,
+        sample_rate: 48000,
+        channels: 2,
+        bit_depth: 24,
+        codec_header: None,
+    };
+
+    // 960 stereo samples at 48kHz = 10ms each
+    for i in 0..3 {
+        scheduler.schedule(AudioBuffer {
+            timestamp: i * 10_000,
+            play_at: Instant::now(),
+            samples: Arc::from(vec![Sample::ZERO; 960].into_boxed_slice()),
+            format: format.clone(),
+        });
+    }
+    assert_eq!(scheduler.buffered(), Duration::from_millis(30));
+
+    assert!(scheduler.next_ready().is_some());
+    assert_eq!(scheduler.buffered(), Duration::from_millis(20));
+}
+
+#[test]
+fn test_scheduler_waits_for_play_time() {
+    let clock = ManualClock::new();
+    let scheduler = AudioScheduler::with_clock(clock.shared());
+
+    let format = AudioFormat {
+        codec: Codec::Pcm,
+        sample_rate: 48000,
+        channels: 2,
+        bit_depth: 24,
+        codec_header: None,
+    };
+    scheduler.schedule(AudioBuffer {
+        timestamp: 0,
+        play_at: clock.now() + Duration::from_millis(10),
+        samples: Arc::from(vec![Sample::ZERO; 960].into_boxed_slice()),
+        format,
+    });
+
+    // Not ready until within the 1ms early window
+    clock.advance(Duration::from_millis(8));
+    assert!(scheduler.next_ready().is_none());
+    clock.advance(Duration::from_millis(1));
+    assert!(scheduler.next_ready().is_some());
+}
+
+#[test]
+fn test_scheduler_clear() {
+    let scheduler = AudioScheduler::new();
+
+    let format = AudioFormat {
+        codec: Codec::Pcm,
+        sample_rate: 48000,
+        channels: 2,
+        bit_depth: 24,
+        codec_header: None,
+    };
+    for i in 0..2 {
+        scheduler.schedule(AudioBuffer {
+            timestamp: i * 10_000,
+            play_at: Instant::now(),
+            samples: Arc::from(vec![Sample::ZERO; 960].into_boxed_slice()),
+            format: format.clone(),
+        });
+    }
+    // One buffer sorted, one still incoming
+    assert!(scheduler.next_ready().is_some());
+    scheduler.schedule(AudioBuffer {
+        timestamp: 20_000,
+        play_at: Instant::now(),
+        samples: Arc::from(vec![Sample::ZERO; 960].into_boxed_slice()),
+        format,
+    });
+
+    scheduler.clear();
+    assert!(scheduler.is_empty());
+    assert_eq!(scheduler.buffered(), Duration::ZERO);
+    assert!(scheduler.next_ready().is_none());
+}
+
+/// The `index`th 10ms chunk of 48kHz stereo, played from `start`
+fn stereo_chunk(start: Instant, index: u64, value: f32) -> AudioBuffer {
+    AudioBuffer {
+        timestamp: index as i64 * 10_000,
+        play_at: start + Duration::from_millis(10 * index),
+        samples: Arc::from(vec![Sample(value); 960].into_boxed_slice()),
+        format: AudioFormat {
+            codec: Codec::Pcm,
+            sample_rate: 48000,
+            channels: 2,
+            bit_depth: 24,
+            codec_header: None,
+        },
+    }
+}
+
+#[test]
+fn test_late_joiner_starts_at_next_due_chunk() {
+    let clock = ManualClock::new();
+    let scheduler = AudioScheduler::with_clock(clock.shared());
+    let start = clock.now();
+    for i in 0..5u64 {
+        scheduler.schedule(stereo_chunk(start, i, 0.25));
+    }
+
+    // Joined 25ms in: the first two chunks are too late to start on, the
+    // third is within the start tolerance
+    clock.advance(Duration::from_millis(25));
+    let first = scheduler.next_ready().unwrap();
+    assert_eq!(first.play_at, start + Duration::from_millis(20));
+    assert_eq!(first.samples[0], Sample::ZERO);
+    assert_eq!(scheduler.buffered(), Duration::from_millis(20));
+
+    // Once playing, late chunks are still handed out
+    clock.advance(Duration::from_millis(50));
+    assert!(scheduler.next_ready().is_some());
+    assert!(scheduler.next_ready().is_some());
+}
+
+#[test]
+fn test_playback_fades_in_after_clear() {
+    let clock = ManualClock::new();
+    let scheduler = AudioScheduler::with_clock(clock.shared());
+    let level = 0.5;
+
+    let mut played = Vec::new();
+    for _ in 0..2 {
+        scheduler.clear();
+        played.clear();
+        let start = clock.now();
+        for i in 0..12u64 {
+            scheduler.schedule(stereo_chunk(start, i, level));
+        }
+        for _ in 0..12 {
+            played.extend_from_slice(&scheduler.next_ready().unwrap().samples);
+            clock.advance(Duration::from_millis(10));
+        }
+
+        // Ramps up over 100ms (4800 frames), both channels alike, then full level
+        assert_eq!(played[0], Sample::ZERO);
+        assert_eq!(played[2400], played[2401]);
+        assert!((played[4800].0 - level / 2.0).abs() < 1e-6);
+        assert!(played.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+        assert!(played[9600..].iter().all(|s| s.0 == level));
+    }
+}
+
+/// Drive `scheduler` into `output` for `duration`, polling every millisecond
+///
+/// Stands in for the player's playback thread, on simulated time.
+fn play_for(
+    scheduler: &AudioScheduler,
+    output: &mut MockOutput,
+    clock: &ManualClock,
+    duration: Duration,
+) {
+    let end = clock.now() + duration;
+    while clock.now() < end {
+        while let Some(buffer) = scheduler.next_ready() {
+            output.write(&buffer.samples).unwrap();
+        }
+        clock.advance(Duration::from_millis(1));
+    }
+}
+
+#[test]
+fn test_chunks_are_written_at_their_play_time() {
+    let clock = ManualClock::new();
+    let scheduler = AudioScheduler::with_clock(clock.shared());
+    let start = clock.now() + Duration::from_millis(50);
+    // Delivered out of order, as after a retransmit
+    for i in (0..20u64).rev() {
+        scheduler.schedule(stereo_chunk(start, i, 0.25));
+    }
+    let format = stereo_chunk(start, 0, 0.0).format;
+    let mut output =
+        MockOutput::new(format.clone(), clock.shared()).with_latency(Duration::from_millis(5));
+    let recording = output.recording();
+
+    play_for(&scheduler, &mut output, &clock, Duration::from_millis(300));
+
+    let writes = recording.writes();
+    assert_eq!(writes.len(), 20);
+    for (i, write) in writes.iter().enumerate() {
+        // Written within the 1ms early window, never late
+        let play_at = start + Duration::from_millis(10 * i as u64);
+        assert!(write.written_at + Duration::from_millis(1) >= play_at);
+        assert!(write.written_at <= play_at);
+        assert_eq!(write.starts_at, write.written_at + Duration::from_millis(5));
+    }
+    // The device plays the chunks back to back
+    for pair in writes.windows(2) {
+        assert!(pair[1].starts_at >= pair[0].ends_at(&format));
+    }
+    assert!(scheduler.is_empty());
+}
+
+#[test]
+fn test_clear_drops_queued_audio_before_output() {
+    let clock = ManualClock::new();
+    let scheduler = AudioScheduler::with_clock(clock.shared());
+    let start = clock.now();
+    for i in 0..20u64 {
+        scheduler.schedule(stereo_chunk(start, i, 0.25));
+    }
+    let mut output = MockOutput::new(stereo_chunk(start, 0, 0.0).format, clock.shared());
+    let recording = output.recording();
+
+    play_for(&scheduler, &mut output, &clock, Duration::from_millis(45));
+    assert_eq!(recording.writes().len(), 5);
+
+    // A seek: stale chunks never reach the output, new ones fade in on time
+    scheduler.clear();
+    recording.clear();
+    let resume = clock.now() + Duration::from_millis(20);
+    for i in 0..3u64 {
+        scheduler.schedule(stereo_chunk(resume, i, 0.5));
+    }
+    play_for(&scheduler, &mut output, &clock, Duration::from_millis(100));
+
+    let writes = recording.writes();
+    assert_eq!(writes.len(), 3);
+    assert!(writes[0].written_at >= resume - Duration::from_millis(1));
+    assert_eq!(writes[0].samples[0], Sample::ZERO);
+    assert!(recording.samples().iter().all(|s| s.0 <= 0.5));
+}
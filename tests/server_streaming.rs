@@ -0,0 +1,356 @@
+use sendspin::protocol::messages::{
+    AudioFormatSpec, ClientHello, ClientTime, DeviceInfo, Message, PlayerSupport,
+};
+use sendspin::server::RoleLimits;
+use sendspin::{ProtocolClient, SendspinServer, ServerConfig};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+
+/// How long to wait for anything the server should send promptly
+const WAIT: Duration = Duration::from_secs(5);
+
+/// A server running on a free local port
+struct TestServer {
+    url: String,
+    shutdown: oneshot::Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+impl TestServer {
+    async fn start() -> Self {
+        Self::start_with(ServerConfig::new("Test Server").mdns(false)).await
+    }
+
+    async fn start_with(config: ServerConfig) -> Self {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!(
+            "ws://{}{}",
+            listener.local_addr().unwrap(),
+            config.ws_route()
+        );
+        let (shutdown, stop) = oneshot::channel::<()>();
+        let server = SendspinServer::with_config(config);
+        let handle = tokio::spawn(async move {
+            server
+                .serve(listener, async {
+                    let _ = stop.await;
+                })
+                .await
+                .unwrap();
+        });
+        Self {
+            url,
+            shutdown,
+            handle,
+        }
+    }
+
+    async fn stop(self) {
+        let _ = self.shutdown.send(());
+        timeout(WAIT, self.handle).await.unwrap().unwrap();
+    }
+}
+
+fn player_hello(client_id: &str) -> ClientHello {
+    ClientHello {
+        client_id: client_id.to_string(),
+        name: client_id.to_string(),
+        version: 1,
+        supported_roles: vec!["player@v1".to_string()],
+        device_info: DeviceInfo {
+            product_name: "Integration Test".to_string(),
+            manufacturer: "Sendspin".to_string(),
+            software_version: "0.1.0".to_string(),
+        },
+        player_support: Some(PlayerSupport {
+            supported_formats: vec![AudioFormatSpec {
+                codec: "pcm".to_string(),
+                channels: 2,
+                sample_rate: 48000,
+                bit_depth: 24,
+            }],
+            buffer_capacity: 1_000_000,
+            supported_commands: vec!["volume".to_string(), "mute".to_string()],
+            fec: None,
+        }),
+        metadata_support: None,
+        artwork_support: None,
+        source_format: None,
+    }
+}
+
+fn unix_micros() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_micros() as i64
+}
+
+/// Wait for the first message matching `wanted`, skipping others
+async fn expect_message(
+    messages: &mut UnboundedReceiver<Message>,
+    wanted: impl Fn(&Message) -> bool,
+) -> Message {
+    timeout(WAIT, async {
+        loop {
+            let message = messages.recv().await.expect("connection closed");
+            if wanted(&message) {
+                return message;
+            }
+        }
+    })
+    .await
+    .expect("timed out waiting for message")
+}
+
+#[tokio::test]
+async fn test_handshake_and_stream_start() {
+    let server = TestServer::start().await;
+    let client = ProtocolClient::connect(&server.url, player_hello("kitchen"))
+        .await
+        .unwrap();
+
+    let hello = client.server_hello();
+    assert_eq!(hello.name, "Test Server");
+    assert_eq!(hello.version, 1);
+    assert!(hello.active_roles.iter().any(|r| r.starts_with("player")));
+
+    // The default group starts playing when its first player joins
+    let (mut messages, _audio, _sync, sender) = client.split();
+    sender
+        .send_player_state("synchronized", Some(100), Some(false))
+        .await
+        .unwrap();
+    let Message::StreamStart(start) =
+        expect_message(&mut messages, |m| matches!(m, Message::StreamStart(_))).await
+    else {
+        unreachable!();
+    };
+    assert_eq!(start.player.codec, "pcm");
+    assert_eq!(start.player.sample_rate, 48000);
+    assert_eq!(start.player.channels, 2);
+    assert_eq!(start.player.chunk_interval_ms, Some(20));
+    assert_eq!(start.player.buffer_ahead_ms, Some(500));
+
+    let Message::GroupUpdate(update) =
+        expect_message(&mut messages, |m| matches!(m, Message::GroupUpdate(_))).await
+    else {
+        unreachable!();
+    };
+    assert_eq!(update.group_id.as_deref(), Some("default"));
+    assert_eq!(update.playback_state.as_deref(), Some("playing"));
+
+    sender.send_goodbye("shutdown").await.unwrap();
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_player_without_encodable_format_is_refused() {
+    let server = TestServer::start().await;
+    let mut hello = player_hello("kitchen");
+    let support = hello.player_support.as_mut().unwrap();
+    for format in &mut support.supported_formats {
+        format.codec = "opus".to_string();
+    }
+
+    // The server has no Opus encoder and must not substitute PCM
+    let Err(e) = ProtocolClient::connect(&server.url, hello).await else {
+        panic!("an Opus-only player was accepted");
+    };
+    assert!(e.to_string().contains("no_supported_format"), "{}", e);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_time_sync_converges() {
+    let server = TestServer::start().await;
+    let client = ProtocolClient::connect(&server.url, player_hello("den"))
+        .await
+        .unwrap();
+    let (mut messages, _audio, clock_sync, sender) = client.split();
+
+    for _ in 0..8 {
+        let client_transmitted = unix_micros();
+        sender
+            .send_message(Message::ClientTime(ClientTime { client_transmitted }))
+            .await
+            .unwrap();
+        let Message::ServerTime(time) =
+            expect_message(&mut messages, |m| matches!(m, Message::ServerTime(_))).await
+        else {
+            unreachable!();
+        };
+        assert_eq!(time.client_transmitted, client_transmitted);
+        assert!(time.server_transmitted >= time.server_received);
+        clock_sync.lock().await.update(
+            time.client_transmitted,
+            time.server_received,
+            time.server_transmitted,
+            unix_micros(),
+        );
+    }
+
+    // Over loopback the round trip is short and the estimate steady
+    let sync = clock_sync.lock().await;
+    let rtt = sync.rtt_micros().unwrap();
+    assert!((0..50_000).contains(&rtt), "rtt {}µs", rtt);
+    assert!(sync.offset_micros().is_some());
+    assert!(sync.server_now_micros().unwrap() >= 0);
+    drop(sync);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_chunks_arrive_in_order() {
+    let server = TestServer::start().await;
+    let client = ProtocolClient::connect(&server.url, player_hello("garage"))
+        .await
+        .unwrap();
+    let (mut messages, mut audio, _sync, _sender) = client.split();
+    expect_message(&mut messages, |m| matches!(m, Message::StreamStart(_))).await;
+
+    let mut timestamps = Vec::new();
+    for _ in 0..25 {
+        let chunk = timeout(WAIT, audio.recv()).await.unwrap().unwrap();
+        // 20ms of 24-bit stereo PCM at 48kHz
+        assert_eq!(chunk.data.len(), 960 * 2 * 3);
+        timestamps.push(chunk.timestamp);
+    }
+
+    // Back-to-back chunks, each starting where the previous one ends
+    for pair in timestamps.windows(2) {
+        assert_eq!(pair[1] - pair[0], 20_000, "timestamps {:?}", timestamps);
+    }
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_metadata_client_gets_track() {
+    let server = TestServer::start().await;
+    let mut hello = player_hello("frame");
+    hello.supported_roles = vec!["metadata@v1".to_string()];
+    hello.player_support = None;
+    let client = ProtocolClient::connect(&server.url, hello).await.unwrap();
+    let (mut messages, _audio, _sync, _sender) = client.split();
+
+    // Sent right after the handshake, with whatever tags the source has
+    let Message::ServerState(state) =
+        expect_message(&mut messages, |m| matches!(m, Message::ServerState(_))).await
+    else {
+        unreachable!();
+    };
+    let metadata = state.metadata.unwrap();
+    assert!(metadata.timestamp >= 0);
+    assert!(state.controller.is_none());
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_source_client_feeds_players() {
+    let config = ServerConfig::new("Test Server")
+        .mdns(false)
+        .role_limits(RoleLimits {
+            allow_source: true,
+            ..RoleLimits::default()
+        });
+    let server = TestServer::start_with(config).await;
+    let player = ProtocolClient::connect(&server.url, player_hello("lounge"))
+        .await
+        .unwrap();
+    let (mut messages, mut audio, _sync, _sender) = player.split();
+    expect_message(&mut messages, |m| matches!(m, Message::StreamStart(_))).await;
+
+    // Without a declared format the role cannot be used
+    let mut hello = player_hello("laptop");
+    hello.supported_roles = vec!["_source@v1".to_string()];
+    hello.player_support = None;
+    let refused = ProtocolClient::connect(&server.url, hello.clone()).await;
+    assert!(refused.is_err());
+
+    hello.source_format = Some(AudioFormatSpec {
+        codec: "pcm".to_string(),
+        channels: 2,
+        sample_rate: 48000,
+        bit_depth: 16,
+    });
+    let source = ProtocolClient::connect(&server.url, hello).await.unwrap();
+    let (_messages, _audio, _sync, sender) = source.split();
+    // One second of 16-bit stereo in 10ms chunks, which is all the burst the
+    // inbound rate limit allows other clients, then 100ms more; source audio
+    // is held to its format's byte rate instead
+    let chunk = |value: i16| -> Vec<u8> {
+        std::iter::repeat_n(value.to_le_bytes(), 480 * 2)
+            .flatten()
+            .collect()
+    };
+    for _ in 0..100 {
+        sender.send_audio(0, &chunk(1000)).await.unwrap();
+    }
+    for _ in 0..10 {
+        sender.send_audio(0, &chunk(2000)).await.unwrap();
+    }
+
+    // The test tone gives way to the pushed audio, widened to 24 bits
+    let expected = (2000i32 << 8).to_le_bytes();
+    timeout(WAIT, async {
+        loop {
+            let chunk = audio.recv().await.expect("connection closed");
+            if chunk.data.chunks(3).all(|sample| sample == &expected[..3]) {
+                break;
+            }
+        }
+    })
+    .await
+    .expect("pushed audio never reached the player");
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_source_client_flooding_is_closed() {
+    let config = ServerConfig::new("Test Server")
+        .mdns(false)
+        .role_limits(RoleLimits {
+            allow_source: true,
+            ..RoleLimits::default()
+        });
+    let server = TestServer::start_with(config).await;
+    let mut hello = player_hello("laptop");
+    hello.supported_roles = vec!["_source@v1".to_string()];
+    hello.player_support = None;
+    hello.source_format = Some(AudioFormatSpec {
+        codec: "pcm".to_string(),
+        channels: 2,
+        sample_rate: 8000,
+        bit_depth: 16,
+    });
+    let source = ProtocolClient::connect(&server.url, hello).await.unwrap();
+    let (mut messages, _audio, _sync, sender) = source.split();
+
+    // Ten seconds of audio at once is far beyond the declared format's rate
+    let chunk = vec![0u8; 8000 * 4 / 10];
+    for _ in 0..100 {
+        if sender.send_audio(0, &chunk).await.is_err() {
+            break;
+        }
+    }
+    // The goodbye can be lost to the reset of a connection closed mid-flood
+    timeout(WAIT, async {
+        while let Some(message) = messages.recv().await {
+            if let Message::ServerGoodbye(goodbye) = message {
+                assert_eq!(goodbye.reason, "rate_limited");
+            }
+        }
+    })
+    .await
+    .expect("flooding source was not disconnected");
+
+    server.stop().await;
+}
@@ -0,0 +1,85 @@
+use sendspin::audio::spatial::{StereoWidth, Widener};
+use sendspin::audio::Sample;
+
+const SAMPLE_RATE: u32 = 48_000;
+
+/// Interleaved stereo sine with the right channel inverted, so it is all side signal
+fn side_tone(frequency: f32, amplitude: f32, frames: usize) -> Vec<Sample> {
+    (0..frames)
+        .flat_map(|i| {
+            let phase = 2.0 * std::f32::consts::PI * frequency * i as f32 / SAMPLE_RATE as f32;
+            let value = phase.sin() * amplitude;
+            [Sample(value), Sample(-value)]
+        })
+        .collect()
+}
+
+fn peak(samples: &[Sample]) -> f32 {
+    samples.iter().fold(0.0, |peak, s| s.0.abs().max(peak))
+}
+
+#[test]
+fn test_unity_width_is_transparent() {
+    let input = vec![Sample(0.5), Sample(-0.125), Sample(-0.01), Sample(0.15)];
+    let mut widener = Widener::new(StereoWidth::default(), SAMPLE_RATE);
+    assert!(StereoWidth::default().is_bypass());
+    assert_eq!(widener.process(&input), input);
+}
+
+#[test]
+fn test_zero_width_folds_to_mono() {
+    let input = vec![Sample(0.5), Sample(-0.25), Sample(0.25), Sample(0.5)];
+    let mut widener = Widener::new(StereoWidth::new(0.0), SAMPLE_RATE);
+    let output = widener.process(&input);
+    assert_eq!(
+        output,
+        vec![Sample(0.125), Sample(0.125), Sample(0.375), Sample(0.375)]
+    );
+}
+
+#[test]
+fn test_widening_scales_side_and_clamps() {
+    let input = side_tone(1000.0, 0.3, 4800);
+    let mut widener = Widener::new(StereoWidth::new(2.0), SAMPLE_RATE);
+    let wide = peak(&widener.process(&input));
+    assert!((wide - 0.6).abs() < 0.01, "peak {}", wide);
+
+    // Full-scale side content cannot exceed full scale
+    let loud = side_tone(1000.0, 0.9, 4800);
+    let mut widener = Widener::new(StereoWidth::new(2.0), SAMPLE_RATE);
+    let output = widener.process(&loud);
+    assert!(output
+        .iter()
+        .all(|s| (Sample::MIN.0..=Sample::MAX.0).contains(&s.0)));
+    assert!(peak(&output) > 0.99);
+}
+
+#[test]
+fn test_bass_stays_centered() {
+    let profile = StereoWidth {
+        width: 2.0,
+        bass_mono_hz: Some(150.0),
+    };
+    let one_second = SAMPLE_RATE as usize;
+
+    let mut widener = Widener::new(profile, SAMPLE_RATE);
+    let bass = widener.process(&side_tone(40.0, 0.3, one_second));
+    let mut widener = Widener::new(profile, SAMPLE_RATE);
+    let treble = widener.process(&side_tone(5000.0, 0.3, one_second));
+
+    // Side bass is mostly removed while side treble is still widened
+    assert!(peak(&bass[one_second..]) < 0.25);
+    assert!(peak(&treble[one_second..]) > 0.55);
+}
+
+#[test]
+fn test_validate() {
+    assert!(StereoWidth::new(1.5).validate().is_ok());
+    assert!(StereoWidth::new(2.5).validate().is_err());
+    assert!(StereoWidth::new(-0.1).validate().is_err());
+    let profile = StereoWidth {
+        width: 1.0,
+        bass_mono_hz: Some(5.0),
+    };
+    assert!(profile.validate().is_err());
+}